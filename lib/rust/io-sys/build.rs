@@ -0,0 +1,17 @@
+use std::env;
+
+fn main() {
+    println!("cargo::rerun-if-changed=build.rs");
+    println!("cargo::rerun-if-env-changed=IO_SYS_LIB_DIR");
+    println!("cargo::rustc-check-cfg=cfg(io_sys_native)");
+
+    // The native library is built separately and is not always around. Link
+    // it when its directory is given, and leave the bindings that need it
+    // unlinked otherwise so the rest of the crate can be built and tested
+    let Ok(dir) = env::var("IO_SYS_LIB_DIR") else {
+        return;
+    };
+    println!("cargo::rustc-link-search=native={dir}");
+    println!("cargo::rustc-link-lib=example");
+    println!("cargo::rustc-cfg=io_sys_native");
+}
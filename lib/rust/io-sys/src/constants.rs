@@ -1 +1,22 @@
-// Generated binding code for constants
\ No newline at end of file
+// Generated binding code for constants
+
+/// Flags of `open`, combined by [`OpenOptions`](crate::file::OpenOptions).
+#[cfg(unix)]
+pub use libc::{O_APPEND, O_CLOEXEC, O_CREAT, O_EXCL, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY};
+
+/// Domains, types and protocols of `socket`, for
+/// [`OwnedSocket::new`](crate::socket::OwnedSocket::new).
+#[cfg(unix)]
+pub use libc::{AF_INET, AF_INET6, IPPROTO_TCP, IPPROTO_UDP, SOCK_DGRAM, SOCK_STREAM};
+
+/// Levels and names of the options [`OwnedSocket`](crate::socket::OwnedSocket)
+/// sets.
+#[cfg(unix)]
+pub use libc::{SO_LINGER, SO_RCVTIMEO, SO_REUSEADDR, SO_SNDTIMEO, SOL_SOCKET, TCP_NODELAY};
+
+/// Queue size [`ContextBuilder`](crate::context::ContextBuilder) asks for
+/// unless told otherwise.
+pub const DEFAULT_CONCURRENCY: usize = 256;
+
+/// Largest queue size a context can be built with, the limit of io_uring.
+pub const MAX_CONCURRENCY: usize = 32768;
@@ -1,13 +1,177 @@
 //! Context related functions and types
+//!
+//! The library has a single context for the whole process, set up by [`init`]
+//! and torn down by [`shutdown`]. [`ContextBuilder`] builds a [`Context`] that
+//! owns it and shuts it down when dropped, so it cannot be used after.
 
 use libc::{size_t, c_void};
-use crate::types::{Context, Complete, Error};
+use crate::types::{self, Complete, Error};
 
 unsafe extern "C" {
-    pub fn current() -> *mut Context;
-    pub fn init(desired_concurrency: usize) -> *mut Context;
+    pub fn current() -> *mut types::Context;
+    pub fn init(desired_concurrency: usize) -> *mut types::Context;
     pub fn shutdown() -> ();
     pub fn submit() -> usize;
     pub fn poll(completions: *mut Complete, max_completions: usize) -> usize;
     pub fn last_error() -> *mut Error;
-}
\ No newline at end of file
+}
+
+pub use guard::{Context, ContextBuilder};
+
+mod guard {
+    use std::{
+        ptr::NonNull,
+        sync::atomic::{AtomicBool, Ordering::*},
+    };
+
+    use super::{init, last_error, poll, shutdown, submit};
+    use crate::{
+        constants::{DEFAULT_CONCURRENCY, MAX_CONCURRENCY},
+        types::{self, Complete, Error},
+    };
+
+    /// Whether a [`Context`] owns the context of the library.
+    static LIVE: AtomicBool = AtomicBool::new(false);
+
+    /// Options of the context, taken by [`init`].
+    #[derive(Debug, Clone)]
+    pub struct ContextBuilder {
+        concurrency: usize,
+    }
+
+    impl Default for ContextBuilder {
+        fn default() -> Self {
+            Self {
+                concurrency: DEFAULT_CONCURRENCY,
+            }
+        }
+    }
+
+    impl ContextBuilder {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// How many operations can be in flight at once, between 1 and
+        /// [`MAX_CONCURRENCY`]. The library rounds it up to a power of two.
+        pub fn concurrency(mut self, concurrency: usize) -> Self {
+            self.concurrency = concurrency;
+            self
+        }
+
+        /// Sets up the context. There is one per process, so this fails with
+        /// [`Error::ResourceUnavailable`] while another [`Context`] lives.
+        pub fn build(self) -> Result<Context, Error> {
+            if !(1..=MAX_CONCURRENCY).contains(&self.concurrency) {
+                return Err(Error::InvalidArgument);
+            }
+            if LIVE.swap(true, Acquire) {
+                return Err(Error::ResourceUnavailable);
+            }
+            match NonNull::new(unsafe { init(self.concurrency) }) {
+                Some(raw) => Ok(Context {
+                    raw,
+                    concurrency: self.concurrency,
+                }),
+                None => {
+                    let error = Context::error().unwrap_or(Error::Unknown);
+                    LIVE.store(false, Release);
+                    Err(error)
+                }
+            }
+        }
+    }
+
+    /// The context of the library, shut down when dropped.
+    #[derive(Debug)]
+    pub struct Context {
+        raw: NonNull<types::Context>,
+        concurrency: usize,
+    }
+
+    /// The Linux library keeps no per-thread state, so the context can move
+    /// between threads. It does not lock its queues, so it is not `Sync`.
+    #[cfg(target_os = "linux")]
+    unsafe impl Send for Context {}
+
+    impl Context {
+        pub fn builder() -> ContextBuilder {
+            ContextBuilder::new()
+        }
+
+        /// The concurrency the context was built with.
+        pub fn concurrency(&self) -> usize {
+            self.concurrency
+        }
+
+        /// Hands the queued operations to the kernel, returning how many.
+        /// On failure this is 0 and [`last_error`](Self::last_error) says why.
+        pub fn submit(&mut self) -> usize {
+            unsafe { submit() }
+        }
+
+        /// Fills `completions` with finished operations, returning how many.
+        /// On failure this is 0 and [`last_error`](Self::last_error) says why.
+        pub fn poll(&mut self, completions: &mut [Complete]) -> usize {
+            unsafe { poll(completions.as_mut_ptr(), completions.len()) }
+        }
+
+        /// Why the last call into the library failed, if one did.
+        pub fn last_error(&self) -> Option<Error> {
+            Self::error()
+        }
+
+        fn error() -> Option<Error> {
+            unsafe { last_error().as_ref().copied() }
+        }
+
+        /// For the functions of the library taking the context. It must not
+        /// be used once this is dropped.
+        pub fn as_ptr(&self) -> *mut types::Context {
+            self.raw.as_ptr()
+        }
+    }
+
+    impl Drop for Context {
+        fn drop(&mut self) {
+            unsafe { shutdown() };
+            LIVE.store(false, Release);
+        }
+    }
+
+    // Needs the native library, see build.rs
+    #[cfg(all(test, io_sys_native))]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_contexts() {
+            // The context is global, so the cases run one after the other
+            let context = ContextBuilder::new().build().unwrap();
+            assert_eq!(context.concurrency(), DEFAULT_CONCURRENCY);
+            assert_eq!(context.last_error(), None);
+            drop(context);
+
+            let mut context = Context::builder()
+                .concurrency(MAX_CONCURRENCY)
+                .build()
+                .unwrap();
+            assert_eq!(context.concurrency(), MAX_CONCURRENCY);
+            assert_eq!(context.submit(), 0);
+            assert_eq!(context.poll(&mut []), 0);
+            drop(context);
+
+            for concurrency in [0, MAX_CONCURRENCY + 1] {
+                let error = ContextBuilder::new().concurrency(concurrency).build();
+                assert_eq!(error.unwrap_err(), Error::InvalidArgument);
+            }
+
+            // Dropping shuts down once, after which another can be built
+            let context = ContextBuilder::new().concurrency(1).build().unwrap();
+            let error = ContextBuilder::new().build().unwrap_err();
+            assert_eq!(error, Error::ResourceUnavailable);
+            drop(context);
+            drop(ContextBuilder::new().build().unwrap());
+        }
+    }
+}
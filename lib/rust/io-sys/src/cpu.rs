@@ -1,4 +1,8 @@
 //! CPU buffer related functions and types
+//!
+//! Next to the buffers are queries of the processor the process runs on, for
+//! sizing worker pools and picking SIMD paths. They are answered once and
+//! cached, except for [`pin_current_thread`].
 
 use libc::{size_t, c_void,  c_uchar};
 use crate::types::Buffer;
@@ -7,4 +11,371 @@ unsafe extern "C" {
     pub fn create(cap: usize) -> *mut Buffer;
     pub fn wrap(data: *mut c_uchar, len: usize) -> *mut Buffer;
     pub fn release(buffer: *mut Buffer) -> bool;
-}
\ No newline at end of file
+}
+
+#[cfg(unix)]
+pub use topology::{
+    CpuFeatures, cache_line_size, features, logical_cores, physical_cores, pin_current_thread,
+};
+
+#[cfg(unix)]
+mod topology {
+    use std::{
+        io,
+        ops::{BitOr, BitOrAssign},
+        sync::OnceLock,
+    };
+
+    /// Instruction set extensions, as a set of bits.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+    pub struct CpuFeatures(pub u32);
+
+    impl CpuFeatures {
+        pub const SSE4_2: Self = Self(1 << 0);
+        pub const AVX2: Self = Self(1 << 1);
+        /// The AVX-512 foundation, the other AVX-512 extensions build on it.
+        pub const AVX512F: Self = Self(1 << 2);
+        pub const NEON: Self = Self(1 << 3);
+
+        pub const fn empty() -> Self {
+            Self(0)
+        }
+
+        /// Whether all of `other` is in this set.
+        pub const fn contains(self, other: Self) -> bool {
+            self.0 & other.0 == other.0
+        }
+    }
+
+    impl BitOr for CpuFeatures {
+        type Output = Self;
+
+        fn bitor(self, rhs: Self) -> Self {
+            Self(self.0 | rhs.0)
+        }
+    }
+
+    impl BitOrAssign for CpuFeatures {
+        fn bitor_assign(&mut self, rhs: Self) {
+            self.0 |= rhs.0;
+        }
+    }
+
+    struct Topology {
+        logical_cores: usize,
+        physical_cores: usize,
+        cache_line_size: usize,
+        features: CpuFeatures,
+    }
+
+    fn topology() -> &'static Topology {
+        static TOPOLOGY: OnceLock<Topology> = OnceLock::new();
+        TOPOLOGY.get_or_init(|| {
+            let logical_cores = detect_logical_cores();
+            Topology {
+                logical_cores,
+                // Some systems do not say, then every logical core counts
+                physical_cores: detect_physical_cores()
+                    .filter(|&cores| cores > 0)
+                    .map_or(logical_cores, |cores| cores.min(logical_cores)),
+                cache_line_size: detect_cache_line_size()
+                    .filter(|size| size.is_power_of_two())
+                    .unwrap_or(64),
+                features: detect_features(),
+            }
+        })
+    }
+
+    /// Cores online, counting each hardware thread. This ignores the
+    /// affinity of the calling thread, so pinning does not change it.
+    pub fn logical_cores() -> usize {
+        topology().logical_cores
+    }
+
+    /// Cores online, counting hardware threads sharing a core once.
+    pub fn physical_cores() -> usize {
+        topology().physical_cores
+    }
+
+    /// Size of a line of the first level data cache in bytes, 64 when the
+    /// system does not say.
+    pub fn cache_line_size() -> usize {
+        topology().cache_line_size
+    }
+
+    /// Extensions both the processor and the operating system support.
+    pub fn features() -> CpuFeatures {
+        topology().features
+    }
+
+    fn detect_logical_cores() -> usize {
+        let cores = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+        cores.max(1) as usize
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn detect_physical_cores() -> Option<usize> {
+        use std::{collections::HashSet, fs};
+
+        // Offline cores have no topology and are skipped
+        let read = |cpu: &str, name: &str| {
+            fs::read_to_string(format!("/sys/devices/system/cpu/{cpu}/topology/{name}")).ok()
+        };
+        let mut cores = HashSet::new();
+        for entry in fs::read_dir("/sys/devices/system/cpu").ok()? {
+            let name = entry.ok()?.file_name();
+            let Some(cpu) = name.to_str().filter(|name| {
+                name.strip_prefix("cpu")
+                    .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            }) else {
+                continue;
+            };
+            if let (Some(package), Some(core)) =
+                (read(cpu, "physical_package_id"), read(cpu, "core_id"))
+            {
+                cores.insert((package, core));
+            }
+        }
+        Some(cores.len())
+    }
+
+    #[cfg(target_vendor = "apple")]
+    fn detect_physical_cores() -> Option<usize> {
+        sysctl::<libc::c_int>(c"hw.physicalcpu").map(|cores| cores as usize)
+    }
+
+    #[cfg(target_os = "freebsd")]
+    fn detect_physical_cores() -> Option<usize> {
+        sysctl::<libc::c_int>(c"kern.smp.cores").map(|cores| cores as usize)
+    }
+
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_vendor = "apple",
+        target_os = "freebsd"
+    )))]
+    fn detect_physical_cores() -> Option<usize> {
+        None
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn detect_cache_line_size() -> Option<usize> {
+        // The CLFLUSH line size, in units of 8 bytes
+        let size = (cpuid::leaf(1)?.ebx >> 8 & 0xff) as usize * 8;
+        Some(size)
+    }
+
+    #[cfg(all(
+        not(any(target_arch = "x86", target_arch = "x86_64")),
+        any(target_os = "linux", target_os = "android")
+    ))]
+    fn detect_cache_line_size() -> Option<usize> {
+        std::fs::read_to_string("/sys/devices/system/cpu/cpu0/cache/index0/coherency_line_size")
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    #[cfg(all(
+        not(any(target_arch = "x86", target_arch = "x86_64")),
+        target_vendor = "apple"
+    ))]
+    fn detect_cache_line_size() -> Option<usize> {
+        sysctl::<i64>(c"hw.cachelinesize").map(|size| size as usize)
+    }
+
+    #[cfg(not(any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        target_os = "linux",
+        target_os = "android",
+        target_vendor = "apple"
+    )))]
+    fn detect_cache_line_size() -> Option<usize> {
+        None
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    fn detect_features() -> CpuFeatures {
+        let mut features = CpuFeatures::empty();
+        let Some(basic) = cpuid::leaf(1) else {
+            return features;
+        };
+        if basic.ecx & 1 << 20 != 0 {
+            features |= CpuFeatures::SSE4_2;
+        }
+
+        // The wide registers also need the operating system to save them,
+        // which it tells through XCR0 once it enabled XSAVE
+        let (avx, osxsave) = (basic.ecx & 1 << 28 != 0, basic.ecx & 1 << 27 != 0);
+        if !avx || !osxsave {
+            return features;
+        }
+        let xcr0 = unsafe { cpuid::xcr0() };
+        let Some(extended) = cpuid::leaf(7) else {
+            return features;
+        };
+        if xcr0 & 0b110 == 0b110 && extended.ebx & 1 << 5 != 0 {
+            features |= CpuFeatures::AVX2;
+        }
+        // Also the mask registers and both halves of the upper ZMM registers
+        if xcr0 & 0b1110_0110 == 0b1110_0110 && extended.ebx & 1 << 16 != 0 {
+            features |= CpuFeatures::AVX512F;
+        }
+        features
+    }
+
+    #[cfg(all(
+        target_arch = "aarch64",
+        any(target_os = "linux", target_os = "android")
+    ))]
+    fn detect_features() -> CpuFeatures {
+        // Advanced SIMD, which is what the kernel calls NEON on aarch64
+        const HWCAP_ASIMD: libc::c_ulong = 1 << 1;
+        match unsafe { libc::getauxval(libc::AT_HWCAP) } & HWCAP_ASIMD {
+            0 => CpuFeatures::empty(),
+            _ => CpuFeatures::NEON,
+        }
+    }
+
+    #[cfg(all(target_arch = "aarch64", target_vendor = "apple"))]
+    fn detect_features() -> CpuFeatures {
+        match sysctl::<libc::c_int>(c"hw.optional.neon") {
+            Some(1) => CpuFeatures::NEON,
+            _ => CpuFeatures::empty(),
+        }
+    }
+
+    #[cfg(not(any(
+        target_arch = "x86",
+        target_arch = "x86_64",
+        all(
+            target_arch = "aarch64",
+            any(target_os = "linux", target_os = "android", target_vendor = "apple")
+        )
+    )))]
+    fn detect_features() -> CpuFeatures {
+        CpuFeatures::empty()
+    }
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    mod cpuid {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86::{__cpuid, __cpuid_count, _xgetbv, CpuidResult};
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64::{__cpuid, __cpuid_count, _xgetbv, CpuidResult};
+
+        /// Subleaf 0 of `leaf`, if the processor has it.
+        pub fn leaf(leaf: u32) -> Option<CpuidResult> {
+            (leaf <= __cpuid(0).eax).then(|| __cpuid_count(leaf, 0))
+        }
+
+        /// # Safety
+        /// The operating system must have enabled XSAVE, which CPUID leaf 1
+        /// reports as OSXSAVE.
+        #[target_feature(enable = "xsave")]
+        pub unsafe fn xcr0() -> u64 {
+            unsafe { _xgetbv(0) }
+        }
+    }
+
+    #[cfg(any(target_vendor = "apple", target_os = "freebsd"))]
+    fn sysctl<T: Copy + Default>(name: &std::ffi::CStr) -> Option<T> {
+        let mut value = T::default();
+        let mut len = size_of::<T>();
+        let ret = unsafe {
+            libc::sysctlbyname(
+                name.as_ptr(),
+                (&raw mut value).cast(),
+                &mut len,
+                std::ptr::null_mut(),
+                0,
+            )
+        };
+        (ret == 0 && len == size_of::<T>()).then_some(value)
+    }
+
+    /// Keeps the calling thread on logical core `core`, numbered as the
+    /// kernel does. Cores past [`logical_cores`] or outside the affinity
+    /// of the process fail with [`Errno::INVAL`](crate::error::Errno::INVAL).
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn pin_current_thread(core: usize) -> io::Result<()> {
+        use crate::error::{Errno, cvt};
+
+        if core >= libc::CPU_SETSIZE as usize {
+            return Err(Errno::INVAL.into());
+        }
+        let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+        unsafe { libc::CPU_SET(core, &mut set) };
+        cvt(unsafe { libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) })?;
+        Ok(())
+    }
+
+    /// Other systems at best take affinity as a hint, so this always fails
+    /// with [`io::ErrorKind::Unsupported`].
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub fn pin_current_thread(_core: usize) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "threads cannot be pinned on this system",
+        ))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_core_counts() {
+            assert!(logical_cores() >= 1);
+            assert!((1..=logical_cores()).contains(&physical_cores()));
+            assert!(cache_line_size().is_power_of_two());
+        }
+
+        #[test]
+        fn test_features_are_stable() {
+            let features = features();
+            assert_eq!(features, super::features());
+            assert_eq!(features, detect_features());
+            assert!(features.contains(CpuFeatures::empty()));
+            assert!(!CpuFeatures::SSE4_2.contains(CpuFeatures::SSE4_2 | CpuFeatures::AVX2));
+
+            #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+            for (feature, detected) in [
+                (CpuFeatures::SSE4_2, is_x86_feature_detected!("sse4.2")),
+                (CpuFeatures::AVX2, is_x86_feature_detected!("avx2")),
+                (CpuFeatures::AVX512F, is_x86_feature_detected!("avx512f")),
+            ] {
+                assert_eq!(features.contains(feature), detected, "{feature:?}");
+            }
+        }
+
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        #[test]
+        fn test_pin_round_trips() {
+            fn affinity() -> Vec<usize> {
+                let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+                let size = size_of::<libc::cpu_set_t>();
+                assert_eq!(unsafe { libc::sched_getaffinity(0, size, &mut set) }, 0);
+                (0..libc::CPU_SETSIZE as usize)
+                    .filter(|&core| unsafe { libc::CPU_ISSET(core, &set) })
+                    .collect()
+            }
+
+            // On a thread of its own, the other tests keep their affinity
+            std::thread::spawn(|| {
+                let allowed = affinity();
+                let core = *allowed.last().unwrap();
+                pin_current_thread(core).unwrap();
+                assert_eq!(affinity(), [core]);
+
+                let error = pin_current_thread(libc::CPU_SETSIZE as usize).unwrap_err();
+                assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+            })
+            .join()
+            .unwrap();
+        }
+    }
+}
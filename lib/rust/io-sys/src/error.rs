@@ -1,3 +1,149 @@
 //! Error handling related functions and types
+//!
+//! The functions of the library report failures through
+//! [`context::last_error`](crate::context::last_error) and do not set errno.
+//! [`Errno`] is for the libc calls made next to them, which return -1 and
+//! leave the reason in errno.
 
-pub use crate::types::Error;
\ No newline at end of file
+use std::{fmt, io};
+
+use libc::{c_int, ssize_t};
+
+pub use crate::types::Error;
+
+/// An error number, as left in errno by a libc call that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Errno(pub c_int);
+
+impl Errno {
+    pub const PERM: Self = Self(libc::EPERM);
+    pub const NOENT: Self = Self(libc::ENOENT);
+    pub const INTR: Self = Self(libc::EINTR);
+    pub const IO: Self = Self(libc::EIO);
+    pub const BADF: Self = Self(libc::EBADF);
+    /// Also `EWOULDBLOCK`, which is the same number where it matters.
+    pub const AGAIN: Self = Self(libc::EAGAIN);
+    pub const NOMEM: Self = Self(libc::ENOMEM);
+    pub const ACCES: Self = Self(libc::EACCES);
+    pub const EXIST: Self = Self(libc::EEXIST);
+    pub const NOTDIR: Self = Self(libc::ENOTDIR);
+    pub const ISDIR: Self = Self(libc::EISDIR);
+    pub const INVAL: Self = Self(libc::EINVAL);
+    pub const MFILE: Self = Self(libc::EMFILE);
+    pub const NOSPC: Self = Self(libc::ENOSPC);
+    pub const PIPE: Self = Self(libc::EPIPE);
+    pub const INPROGRESS: Self = Self(libc::EINPROGRESS);
+    pub const ADDRINUSE: Self = Self(libc::EADDRINUSE);
+    pub const ADDRNOTAVAIL: Self = Self(libc::EADDRNOTAVAIL);
+    pub const CONNREFUSED: Self = Self(libc::ECONNREFUSED);
+    pub const CONNRESET: Self = Self(libc::ECONNRESET);
+    pub const NOTCONN: Self = Self(libc::ENOTCONN);
+    pub const TIMEDOUT: Self = Self(libc::ETIMEDOUT);
+
+    /// The errno of the calling thread. Only meaningful right after a call
+    /// that failed, successful calls may leave anything there.
+    pub fn last() -> Self {
+        Self(unsafe { *errno_location() })
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn errno_location() -> *mut c_int {
+    unsafe { libc::__errno_location() }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+))]
+unsafe fn errno_location() -> *mut c_int {
+    unsafe { libc::__error() }
+}
+
+#[cfg(any(target_os = "openbsd", target_os = "netbsd"))]
+unsafe fn errno_location() -> *mut c_int {
+    unsafe { libc::__errno() }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        io::Error::from(*self).fmt(f)
+    }
+}
+
+impl std::error::Error for Errno {}
+
+impl From<Errno> for io::Error {
+    fn from(errno: Errno) -> Self {
+        io::Error::from_raw_os_error(errno.0)
+    }
+}
+
+/// Turns the -1 of a failed libc call into the [`Errno`] it left.
+pub fn cvt(ret: c_int) -> Result<c_int, Errno> {
+    match ret {
+        ..0 => Err(Errno::last()),
+        ret => Ok(ret),
+    }
+}
+
+/// [`cvt`] for the calls returning a size, like `read` and `write`.
+pub fn cvt_size(ret: ssize_t) -> Result<usize, Errno> {
+    match ret {
+        ..0 => Err(Errno::last()),
+        ret => Ok(ret as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_missing_path() {
+        let ret = unsafe { libc::open(c"/nonexistent/io-sys".as_ptr(), libc::O_RDONLY) };
+        let errno = cvt(ret).unwrap_err();
+        assert_eq!(errno, Errno::NOENT);
+        assert_eq!(io::Error::from(errno).kind(), io::ErrorKind::NotFound);
+        assert_eq!(errno.to_string(), io::Error::from(errno).to_string());
+    }
+
+    #[test]
+    fn test_read_bad_descriptor() {
+        let mut buf = [0u8; 4];
+        let ret = unsafe { libc::read(-1, buf.as_mut_ptr().cast(), buf.len()) };
+        assert_eq!(cvt_size(ret), Err(Errno::BADF));
+
+        let ret = unsafe { libc::write(libc::STDERR_FILENO, buf.as_ptr().cast(), 0) };
+        assert_eq!(cvt_size(ret), Ok(0));
+    }
+
+    #[test]
+    fn test_bind_privileged_port() {
+        // Root may bind any port
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        let fd = cvt(unsafe { libc::socket(libc::AF_INET, libc::SOCK_STREAM, 0) }).unwrap();
+        let mut addr = unsafe { std::mem::zeroed::<libc::sockaddr_in>() };
+        addr.sin_family = libc::AF_INET as _;
+        addr.sin_port = 1u16.to_be();
+        addr.sin_addr.s_addr = u32::from(std::net::Ipv4Addr::LOCALHOST).to_be();
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                (&raw const addr).cast(),
+                size_of::<libc::sockaddr_in>() as _,
+            )
+        };
+        let errno = cvt(ret).unwrap_err();
+        unsafe { libc::close(fd) };
+        assert_eq!(errno, Errno::ACCES);
+        assert_eq!(
+            io::Error::from(errno).kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+}
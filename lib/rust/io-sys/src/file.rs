@@ -1,9 +1,14 @@
 //! File operations
+//!
+//! These return `false` on failure and leave the reason in
+//! [`context::last_error`](crate::context::last_error), errno is not set.
+//!
+//! [`OwnedFile`] is a safe descriptor for the files that need one, closed
+//! exactly once when dropped.
 
 use libc::{c_int, c_char, size_t, c_longlong, c_void};
 use crate::types::{File, Buffer, SeekOrigin, ModeFlags};
 
-#[link(name = "example")]
 unsafe extern "C" {
     pub fn create(user_data: *mut c_void) -> *mut File;
     pub fn open(file: *mut File, path: *const c_char, mode: c_int) -> bool;
@@ -14,4 +19,353 @@ unsafe extern "C" {
     pub fn close(file: *mut File) -> bool;
     pub fn release(file: *mut File) -> bool;
     pub fn size(file: *mut File, size_out: *mut u64) -> bool;
-}
\ No newline at end of file
+}
+
+#[cfg(unix)]
+pub use owned::{Metadata, OpenOptions, OwnedFile};
+
+#[cfg(unix)]
+mod owned {
+    use std::{
+        ffi::CString,
+        io::{self, SeekFrom},
+        mem::{self, ManuallyDrop},
+        os::{
+            fd::{AsRawFd, FromRawFd, RawFd},
+            unix::ffi::OsStrExt,
+        },
+        path::Path,
+    };
+
+    use libc::{c_int, mode_t, off_t};
+
+    use crate::{
+        constants::{O_APPEND, O_CLOEXEC, O_CREAT, O_EXCL, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY},
+        error::{Errno, cvt, cvt_size},
+    };
+
+    /// How [`OwnedFile::open`] opens a file, nothing is allowed by default.
+    #[derive(Debug, Clone, Copy)]
+    pub struct OpenOptions {
+        read: bool,
+        write: bool,
+        append: bool,
+        truncate: bool,
+        create: bool,
+        create_new: bool,
+        mode: mode_t,
+    }
+
+    impl Default for OpenOptions {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl OpenOptions {
+        pub fn new() -> Self {
+            Self {
+                read: false,
+                write: false,
+                append: false,
+                truncate: false,
+                create: false,
+                create_new: false,
+                mode: 0o666,
+            }
+        }
+
+        pub fn read(mut self, read: bool) -> Self {
+            self.read = read;
+            self
+        }
+
+        pub fn write(mut self, write: bool) -> Self {
+            self.write = write;
+            self
+        }
+
+        /// Writes go to the end of the file, wherever the offset is. Implies
+        /// [`write`](Self::write).
+        pub fn append(mut self, append: bool) -> Self {
+            self.append = append;
+            self
+        }
+
+        pub fn truncate(mut self, truncate: bool) -> Self {
+            self.truncate = truncate;
+            self
+        }
+
+        /// Creates the file if it does not exist.
+        pub fn create(mut self, create: bool) -> Self {
+            self.create = create;
+            self
+        }
+
+        /// Creates the file, failing if it exists already.
+        pub fn create_new(mut self, create_new: bool) -> Self {
+            self.create_new = create_new;
+            self
+        }
+
+        /// Permissions of a created file, before the umask.
+        pub fn mode(mut self, mode: u32) -> Self {
+            self.mode = mode as mode_t;
+            self
+        }
+
+        fn flags(&self) -> Result<c_int, Errno> {
+            let write = self.write || self.append;
+            let access = match (self.read, write) {
+                (true, false) => O_RDONLY,
+                (false, true) => O_WRONLY,
+                (true, true) => O_RDWR,
+                (false, false) => return Err(Errno::INVAL),
+            };
+            let mut flags = access | O_CLOEXEC;
+            if self.append {
+                flags |= O_APPEND;
+            }
+            if self.truncate {
+                flags |= O_TRUNC;
+            }
+            if self.create_new {
+                flags |= O_CREAT | O_EXCL;
+            } else if self.create {
+                flags |= O_CREAT;
+            }
+            Ok(flags)
+        }
+    }
+
+    /// An open file descriptor, closed when dropped.
+    #[derive(Debug)]
+    pub struct OwnedFile(RawFd);
+
+    impl OwnedFile {
+        pub fn open(path: impl AsRef<Path>, options: &OpenOptions) -> io::Result<Self> {
+            let path = CString::new(path.as_ref().as_os_str().as_bytes())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul"))?;
+            let flags = options.flags()?;
+            let fd =
+                cvt(unsafe { libc::open(path.as_ptr(), flags, options.mode as libc::c_uint) })?;
+            Ok(Self(fd))
+        }
+
+        /// Reads at the offset of the file, and moves it past what was read.
+        pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = unsafe { libc::read(self.0, buf.as_mut_ptr().cast(), buf.len()) };
+            Ok(cvt_size(read)?)
+        }
+
+        pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+            let written = unsafe { libc::write(self.0, buf.as_ptr().cast(), buf.len()) };
+            Ok(cvt_size(written)?)
+        }
+
+        /// Reads at `offset`, leaving the offset of the file alone.
+        pub fn pread(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            let offset = to_off(offset)?;
+            let read = unsafe { libc::pread(self.0, buf.as_mut_ptr().cast(), buf.len(), offset) };
+            Ok(cvt_size(read)?)
+        }
+
+        /// Writes at `offset`, leaving the offset of the file alone. Files
+        /// opened to append may write at the end regardless.
+        pub fn pwrite(&self, buf: &[u8], offset: u64) -> io::Result<usize> {
+            let offset = to_off(offset)?;
+            let written = unsafe { libc::pwrite(self.0, buf.as_ptr().cast(), buf.len(), offset) };
+            Ok(cvt_size(written)?)
+        }
+
+        /// Moves the offset of the file, returning it from the start.
+        pub fn seek(&self, pos: SeekFrom) -> io::Result<u64> {
+            let (offset, whence) = match pos {
+                SeekFrom::Start(offset) => (to_off(offset)?, libc::SEEK_SET),
+                SeekFrom::Current(offset) => (offset as off_t, libc::SEEK_CUR),
+                SeekFrom::End(offset) => (offset as off_t, libc::SEEK_END),
+            };
+            let offset = unsafe { libc::lseek(self.0, offset, whence) };
+            if offset < 0 {
+                return Err(Errno::last().into());
+            }
+            Ok(offset as u64)
+        }
+
+        pub fn metadata(&self) -> io::Result<Metadata> {
+            let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+            cvt(unsafe { libc::fstat(self.0, &mut stat) })?;
+            Ok(Metadata(stat))
+        }
+
+        /// Flushes the data and metadata of the file to the disk.
+        pub fn sync_all(&self) -> io::Result<()> {
+            cvt(unsafe { libc::fsync(self.0) })?;
+            Ok(())
+        }
+
+        /// Gives up the descriptor without closing it.
+        pub fn into_raw(self) -> RawFd {
+            ManuallyDrop::new(self).0
+        }
+
+        /// # Safety
+        /// `fd` must be an open descriptor that nothing else closes.
+        pub unsafe fn from_raw(fd: RawFd) -> Self {
+            Self(fd)
+        }
+    }
+
+    fn to_off(offset: u64) -> Result<off_t, Errno> {
+        off_t::try_from(offset).map_err(|_| Errno::INVAL)
+    }
+
+    impl Drop for OwnedFile {
+        fn drop(&mut self) {
+            // The descriptor is gone even if close fails, and retrying could
+            // close one opened meanwhile
+            unsafe { libc::close(self.0) };
+        }
+    }
+
+    impl AsRawFd for OwnedFile {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0
+        }
+    }
+
+    impl From<OwnedFile> for std::fs::File {
+        fn from(file: OwnedFile) -> Self {
+            unsafe { std::fs::File::from_raw_fd(file.into_raw()) }
+        }
+    }
+
+    /// What `fstat` tells about an [`OwnedFile`].
+    #[derive(Clone, Copy)]
+    pub struct Metadata(libc::stat);
+
+    // The types of the `stat` fields differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    impl Metadata {
+        pub fn len(&self) -> u64 {
+            self.0.st_size as u64
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.len() == 0
+        }
+
+        pub fn is_file(&self) -> bool {
+            self.0.st_mode & libc::S_IFMT == libc::S_IFREG
+        }
+
+        pub fn is_dir(&self) -> bool {
+            self.0.st_mode & libc::S_IFMT == libc::S_IFDIR
+        }
+
+        /// Permission bits, without the file type.
+        pub fn mode(&self) -> u32 {
+            (self.0.st_mode & !libc::S_IFMT) as u32
+        }
+
+        /// Device and inode, which tell files apart.
+        pub fn id(&self) -> (u64, u64) {
+            (self.0.st_dev as u64, self.0.st_ino as u64)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::{env, fs, path::PathBuf, process};
+
+        fn temp_path(name: &str) -> PathBuf {
+            env::temp_dir().join(format!("io-sys-{}-{name}", process::id()))
+        }
+
+        #[test]
+        fn test_write_read_round_trip() {
+            let path = temp_path("round-trip");
+            let options = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true);
+            let file = OwnedFile::open(&path, &options).unwrap();
+            assert_eq!(file.write(b"hello world").unwrap(), 11);
+
+            let mut buf = [0; 5];
+            assert_eq!(file.pread(&mut buf, 6).unwrap(), 5);
+            assert_eq!(&buf, b"world");
+            assert_eq!(file.seek(SeekFrom::Start(0)).unwrap(), 0);
+            assert_eq!(file.read(&mut buf).unwrap(), 5);
+            assert_eq!(&buf, b"hello");
+            assert_eq!(file.pwrite(b"HELLO", 0).unwrap(), 5);
+            assert_eq!(file.seek(SeekFrom::Current(0)).unwrap(), 5);
+            assert_eq!(file.seek(SeekFrom::End(-5)).unwrap(), 6);
+
+            let metadata = file.metadata().unwrap();
+            assert_eq!(metadata.len(), 11);
+            assert!(metadata.is_file() && !metadata.is_dir());
+            file.sync_all().unwrap();
+
+            let file = std::fs::File::from(file);
+            drop(file);
+            assert_eq!(fs::read(&path).unwrap(), b"HELLO world");
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn test_append() {
+            let path = temp_path("append");
+            let create = OpenOptions::new().write(true).create_new(true);
+            OwnedFile::open(&path, &create)
+                .unwrap()
+                .write(b"one")
+                .unwrap();
+            let error = OwnedFile::open(&path, &create).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+
+            let file = OwnedFile::open(&path, &OpenOptions::new().append(true)).unwrap();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.write(b"two").unwrap();
+            drop(file);
+            assert_eq!(fs::read(&path).unwrap(), b"onetwo");
+            fs::remove_file(&path).unwrap();
+        }
+
+        #[test]
+        fn test_open_errors() {
+            let error =
+                OwnedFile::open(temp_path("missing"), &OpenOptions::new().read(true)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::NotFound);
+            let error = OwnedFile::open(temp_path("neither"), &OpenOptions::new()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        #[test]
+        fn test_drop_closes() {
+            let path = temp_path("drop");
+            let options = OpenOptions::new().write(true).create(true).truncate(true);
+            let file = OwnedFile::open(&path, &options).unwrap();
+            let id = file.metadata().unwrap().id();
+            let fd = file.into_raw();
+            drop(unsafe { OwnedFile::from_raw(fd) });
+
+            // Other tests may have been given the same number since, but not
+            // for this file
+            let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+            match cvt(unsafe { libc::fstat(fd, &mut stat) }) {
+                Err(errno) => assert_eq!(errno, Errno::BADF),
+                Ok(_) => assert_ne!(Metadata(stat).id(), id),
+            }
+
+            let file = OwnedFile::open(&path, &options).unwrap();
+            assert_eq!(file.write(b"again").unwrap(), 5);
+            drop(file);
+            assert_eq!(fs::read(&path).unwrap(), b"again");
+            fs::remove_file(&path).unwrap();
+        }
+    }
+}
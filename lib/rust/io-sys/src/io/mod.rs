@@ -1,4 +1,7 @@
 //! IO module
+//!
+//! [`IoQueue`] is a safe queue of reads and writes, owning their buffers
+//! until they complete.
 
 use libc::{c_int, c_void};
 use crate::types::HandleType;
@@ -6,4 +9,10 @@ use crate::types::HandleType;
 unsafe extern "C" {
     pub fn handle_type(handle: *mut c_void) -> HandleType;
     pub fn last_operation_id() -> u64;
-}
\ No newline at end of file
+}
+
+#[cfg(target_os = "linux")]
+pub use queue::{Completion, IoQueue, OpId};
+
+#[cfg(target_os = "linux")]
+mod queue;
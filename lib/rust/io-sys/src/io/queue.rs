@@ -0,0 +1,565 @@
+//! A completion queue over io_uring that owns the buffers of its operations
+//!
+//! The kernel reads and writes the buffer of an operation until it completes,
+//! so [`IoQueue`] keeps each one until then and hands it back in the
+//! [`Completion`]. Files are borrowed for as long as the queue lives, and
+//! dropping the queue waits for the operations still in flight.
+
+use std::{
+    collections::HashMap,
+    io,
+    marker::PhantomData,
+    os::fd::{AsRawFd, RawFd},
+    ptr,
+    sync::atomic::{AtomicU32, Ordering::*},
+};
+
+use libc::{c_long, c_uint, c_void};
+
+use crate::error::{Errno, cvt};
+
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x8000000;
+const IORING_OFF_SQES: i64 = 0x10000000;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+const IORING_ENTER_GETEVENTS: c_uint = 1 << 0;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// Identifies an operation of an [`IoQueue`] in its [`Completion`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OpId(pub u64);
+
+/// A finished operation.
+#[derive(Debug)]
+pub struct Completion {
+    pub id: OpId,
+    /// Bytes read or written.
+    pub result: Result<usize, Errno>,
+    /// For a read the bytes read, for a write the bytes it was given.
+    pub buffer: Vec<u8>,
+}
+
+/// A memory mapping of the queue, unmapped when dropped.
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: i64) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Errno::last().into());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+
+    /// # Safety
+    /// `offset` must be within the mapping and aligned for `T`.
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        unsafe { self.ptr.add(offset as usize).cast() }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.cast(), self.len) };
+    }
+}
+
+struct InFlight {
+    buffer: Vec<u8>,
+    read: bool,
+}
+
+/// Reads and writes at offsets of files, completed in whatever order the
+/// kernel finishes them.
+///
+/// Operations are queued by the `submit_` functions and handed to the kernel
+/// by [`poll_completions`](Self::poll_completions) or
+/// [`wait_completions`](Self::wait_completions), or once the submission
+/// queue is full.
+pub struct IoQueue<'f> {
+    fd: RawFd,
+    sq_ring: Mapping,
+    // `None` when the completion queue shares the mapping of the submissions
+    cq_ring: Option<Mapping>,
+    sqes: Mapping,
+    params: Params,
+    // Queued but not yet handed to the kernel
+    unsubmitted: u32,
+    next_id: u64,
+    in_flight: HashMap<u64, InFlight>,
+    files: PhantomData<&'f ()>,
+}
+
+impl<'f> IoQueue<'f> {
+    /// Sets up a queue for `entries` operations at once, which the kernel
+    /// rounds up to a power of two.
+    pub fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        let fd =
+            unsafe { libc::syscall(libc::SYS_io_uring_setup, entries as c_long, &raw mut params) };
+        let fd = cvt(fd as i32)?;
+        // Closes the queue if a mapping fails
+        let guard = Fd(fd);
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+        let (sq_ring, cq_ring) = match params.features & IORING_FEAT_SINGLE_MMAP {
+            0 => (
+                Mapping::new(fd, sq_len, IORING_OFF_SQ_RING)?,
+                Some(Mapping::new(fd, cq_len, IORING_OFF_CQ_RING)?),
+            ),
+            _ => (
+                Mapping::new(fd, sq_len.max(cq_len), IORING_OFF_SQ_RING)?,
+                None,
+            ),
+        };
+        let sqes = Mapping::new(
+            fd,
+            params.sq_entries as usize * size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+        std::mem::forget(guard);
+
+        Ok(Self {
+            fd,
+            sq_ring,
+            cq_ring,
+            sqes,
+            params,
+            unsubmitted: 0,
+            next_id: 0,
+            in_flight: HashMap::new(),
+            files: PhantomData,
+        })
+    }
+
+    /// Operations submitted and not yet completed.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Reads up to `len` bytes of `file` at `offset`.
+    pub fn submit_read(
+        &mut self,
+        file: &'f impl AsRawFd,
+        offset: u64,
+        len: usize,
+    ) -> io::Result<OpId> {
+        self.push(file.as_raw_fd(), offset, Vec::with_capacity(len), len, true)
+    }
+
+    /// Writes `data` to `file` at `offset`. The data comes back in the
+    /// [`Completion`] to be reused.
+    pub fn submit_write(
+        &mut self,
+        file: &'f impl AsRawFd,
+        offset: u64,
+        data: Vec<u8>,
+    ) -> io::Result<OpId> {
+        let len = data.len();
+        self.push(file.as_raw_fd(), offset, data, len, false)
+    }
+
+    /// Submits what is queued and adds the operations that completed to
+    /// `out` without waiting, returning how many.
+    pub fn poll_completions(&mut self, out: &mut Vec<Completion>) -> io::Result<usize> {
+        self.enter(0)?;
+        Ok(self.reap(out))
+    }
+
+    /// Like [`poll_completions`](Self::poll_completions), but waits for at
+    /// least one completion if any operation is in flight.
+    pub fn wait_completions(&mut self, out: &mut Vec<Completion>) -> io::Result<usize> {
+        let wait = match self.cq_ready() {
+            0 => self.in_flight.len().min(1) as u32,
+            _ => 0,
+        };
+        self.enter(wait)?;
+        Ok(self.reap(out))
+    }
+
+    fn push(
+        &mut self,
+        fd: RawFd,
+        offset: u64,
+        mut buffer: Vec<u8>,
+        len: usize,
+        read: bool,
+    ) -> io::Result<OpId> {
+        let len = u32::try_from(len).map_err(|_| Errno::INVAL)?;
+        // Every operation needs room for its completion
+        if self.in_flight.len() >= self.params.cq_entries as usize {
+            return Err(Errno::AGAIN.into());
+        }
+
+        let sq = self.params.sq_off;
+        let (head, tail) = unsafe {
+            (
+                &*self.sq_ring.at::<AtomicU32>(sq.head),
+                &*self.sq_ring.at::<AtomicU32>(sq.tail),
+            )
+        };
+        if tail.load(Relaxed).wrapping_sub(head.load(Acquire)) == self.params.sq_entries {
+            self.enter(0)?;
+        }
+        let tail = tail.load(Relaxed);
+        let index = tail & unsafe { *self.sq_ring.at::<u32>(sq.ring_mask) };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let sqe = Sqe {
+            opcode: match read {
+                true => IORING_OP_READ,
+                false => IORING_OP_WRITE,
+            },
+            fd,
+            off: offset,
+            addr: buffer.as_mut_ptr() as u64,
+            len,
+            user_data: id,
+            ..Sqe::default()
+        };
+        unsafe {
+            self.sqes.at::<Sqe>(0).add(index as usize).write(sqe);
+            *self.sq_ring.at::<u32>(sq.array).add(index as usize) = index;
+            (*self.sq_ring.at::<AtomicU32>(sq.tail)).store(tail.wrapping_add(1), Release);
+        }
+        self.unsubmitted += 1;
+        // Moving the vector leaves its allocation where the kernel expects it
+        self.in_flight.insert(id, InFlight { buffer, read });
+        Ok(OpId(id))
+    }
+
+    /// Hands the queued operations to the kernel, waiting for `wait`
+    /// completions.
+    fn enter(&mut self, wait: u32) -> io::Result<()> {
+        if self.unsubmitted == 0 && wait == 0 {
+            return Ok(());
+        }
+        let flags = match wait {
+            0 => 0,
+            _ => IORING_ENTER_GETEVENTS,
+        };
+        loop {
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd as c_long,
+                    self.unsubmitted as c_long,
+                    wait as c_long,
+                    flags as c_long,
+                    ptr::null::<c_void>(),
+                    0 as c_long,
+                )
+            };
+            match cvt(ret as i32) {
+                Ok(submitted) => {
+                    self.unsubmitted -= submitted as u32;
+                    return Ok(());
+                }
+                Err(Errno::INTR) => continue,
+                Err(errno) => return Err(errno.into()),
+            }
+        }
+    }
+
+    fn cq_ring(&self) -> &Mapping {
+        self.cq_ring.as_ref().unwrap_or(&self.sq_ring)
+    }
+
+    fn cq_ready(&self) -> u32 {
+        let cq = self.params.cq_off;
+        let ring = self.cq_ring();
+        let (head, tail) = unsafe {
+            (
+                (*ring.at::<AtomicU32>(cq.head)).load(Relaxed),
+                (*ring.at::<AtomicU32>(cq.tail)).load(Acquire),
+            )
+        };
+        tail.wrapping_sub(head)
+    }
+
+    fn reap(&mut self, out: &mut Vec<Completion>) -> usize {
+        let cq = self.params.cq_off;
+        let ring = self.cq_ring.as_ref().unwrap_or(&self.sq_ring);
+        let (head, tail, mask, cqes) = unsafe {
+            (
+                &*ring.at::<AtomicU32>(cq.head),
+                (*ring.at::<AtomicU32>(cq.tail)).load(Acquire),
+                *ring.at::<u32>(cq.ring_mask),
+                ring.at::<Cqe>(cq.cqes),
+            )
+        };
+
+        let mut reaped = 0;
+        let mut current = head.load(Relaxed);
+        while current != tail {
+            let Cqe { user_data, res, .. } = unsafe { cqes.add((current & mask) as usize).read() };
+            current = current.wrapping_add(1);
+            let Some(InFlight { mut buffer, read }) = self.in_flight.remove(&user_data) else {
+                continue;
+            };
+            let result = match res {
+                ..0 => Err(Errno(-res)),
+                res => Ok(res as usize),
+            };
+            if let (true, Ok(len)) = (read, result) {
+                // The kernel filled this much of the capacity
+                unsafe { buffer.set_len(len) };
+            }
+            out.push(Completion {
+                id: OpId(user_data),
+                result,
+                buffer,
+            });
+            reaped += 1;
+        }
+        head.store(current, Release);
+        reaped
+    }
+}
+
+/// Waits for the operations in flight, which the kernel may still be
+/// reading or writing the buffers of. If waiting fails the buffers are
+/// leaked rather than freed under the kernel.
+impl Drop for IoQueue<'_> {
+    fn drop(&mut self) {
+        let mut completions = Vec::new();
+        while !self.in_flight.is_empty() {
+            if self.wait_completions(&mut completions).is_err() {
+                std::mem::forget(std::mem::take(&mut self.in_flight));
+                break;
+            }
+            completions.clear();
+        }
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Closes a queue that could not be set up.
+struct Fd(RawFd);
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::{OpenOptions, OwnedFile};
+    use std::{env, fs, path::PathBuf, process};
+
+    const BLOCK: usize = 4096;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("io-sys-{}-{name}", process::id()))
+    }
+
+    fn open(path: &PathBuf) -> OwnedFile {
+        let options = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true);
+        OwnedFile::open(path, &options).unwrap()
+    }
+
+    fn block(i: usize) -> Vec<u8> {
+        (0..BLOCK).map(|j| (i * 31 + j) as u8).collect()
+    }
+
+    fn wait_all(queue: &mut IoQueue<'_>) -> HashMap<OpId, Completion> {
+        let mut completions = Vec::new();
+        while queue.in_flight() > 0 {
+            queue.wait_completions(&mut completions).unwrap();
+        }
+        completions.into_iter().map(|c| (c.id, c)).collect()
+    }
+
+    #[test]
+    fn test_overlapping_reads_and_writes() {
+        let path = temp_path("queue");
+        let file = open(&path);
+        let mut queue = IoQueue::new(8).unwrap();
+
+        // More writes than entries, so some are submitted before others queue
+        let writes: Vec<_> = (0..16)
+            .map(|i| {
+                let id = queue
+                    .submit_write(&file, (i * BLOCK) as u64, block(i))
+                    .unwrap();
+                (id, i)
+            })
+            .collect();
+        let completions = wait_all(&mut queue);
+        for (id, i) in writes {
+            let completion = &completions[&id];
+            assert_eq!(completion.result, Ok(BLOCK));
+            assert_eq!(completion.buffer, block(i));
+        }
+
+        // Reads straddling the blocks, each overlapping two of them
+        let expected: Vec<u8> = (0..16).flat_map(block).collect();
+        let reads: Vec<_> = (0..15)
+            .map(|i| {
+                let offset = i * BLOCK + BLOCK / 2;
+                (
+                    queue.submit_read(&file, offset as u64, BLOCK).unwrap(),
+                    offset,
+                )
+            })
+            .collect();
+        let past_end = queue
+            .submit_read(&file, expected.len() as u64 - 10, BLOCK)
+            .unwrap();
+        let completions = wait_all(&mut queue);
+        for (id, offset) in reads {
+            let completion = &completions[&id];
+            assert_eq!(completion.result, Ok(BLOCK));
+            assert_eq!(completion.buffer, &expected[offset..offset + BLOCK]);
+        }
+        assert_eq!(completions[&past_end].result, Ok(10));
+        assert_eq!(
+            completions[&past_end].buffer,
+            &expected[expected.len() - 10..]
+        );
+
+        drop(queue);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_errors_carry_errno() {
+        let path = temp_path("queue-errors");
+        let file = open(&path);
+        let read_only = OwnedFile::open(&path, &OpenOptions::new().read(true)).unwrap();
+        let mut queue = IoQueue::new(1).unwrap();
+
+        let write = queue.submit_write(&read_only, 0, b"nope".to_vec()).unwrap();
+        let read = queue.submit_read(&file, 0, 16).unwrap();
+        let completions = wait_all(&mut queue);
+        assert_eq!(completions[&write].result, Err(Errno::BADF));
+        assert_eq!(completions[&write].buffer, b"nope");
+        assert_eq!(completions[&read].result, Ok(0));
+        assert!(completions[&read].buffer.is_empty());
+
+        // One completion entry more than submission entries, then it is full
+        let cq_entries = queue.params.cq_entries as usize;
+        for _ in 0..cq_entries {
+            queue.submit_read(&file, 0, 1).unwrap();
+        }
+        let error = queue.submit_read(&file, 0, 1).unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(Errno::AGAIN.0));
+        assert_eq!(wait_all(&mut queue).len(), cq_entries);
+
+        drop(queue);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_drop_waits_for_in_flight() {
+        let path = temp_path("queue-drop");
+        let file = open(&path);
+        // Room for as many completions as there are operations
+        let mut queue = IoQueue::new(16).unwrap();
+        for i in 0..31 {
+            queue
+                .submit_write(&file, (i * BLOCK) as u64, block(i))
+                .unwrap();
+        }
+        queue.submit_read(&file, 0, BLOCK).unwrap();
+        assert!(queue.in_flight() > 0);
+        drop(queue);
+
+        let expected: Vec<u8> = (0..31).flat_map(block).collect();
+        assert_eq!(fs::read(&path).unwrap(), expected);
+        fs::remove_file(&path).unwrap();
+    }
+}
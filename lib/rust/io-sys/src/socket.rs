@@ -1,9 +1,14 @@
 //! Socket operations
+//!
+//! These return `false` on failure and leave the reason in
+//! [`context::last_error`](crate::context::last_error), errno is not set.
+//!
+//! [`OwnedSocket`] is a safe descriptor over the socket calls of libc, whose
+//! failures come back as [`Errno`](crate::error::Errno).
 
 use libc::{c_int, c_char, size_t, c_void};
 use crate::types::{Socket, IpAddress, SockType, Option, Buffer};
 
-#[link(name = "example")]
 unsafe extern "C" {
     pub fn create(ipv6: bool, sock_type: SockType, user_data: *mut c_void) -> *mut Socket;
     pub fn bind(sock: *mut Socket, address: *const IpAddress, op_id: *mut u64) -> bool;
@@ -15,4 +20,416 @@ unsafe extern "C" {
     pub fn close(sock: *mut Socket) -> bool;
     pub fn release(sock: *mut Socket) -> bool;
     pub fn set_option(sock: *mut Socket, option: Option, value: *const c_void, len: u32) -> bool;
-}
\ No newline at end of file
+}
+
+#[cfg(unix)]
+pub use owned::OwnedSocket;
+
+#[cfg(unix)]
+mod owned {
+    use std::{
+        io,
+        mem::{self, ManuallyDrop},
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+        os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        time::Duration,
+    };
+
+    use libc::{c_int, c_void, socklen_t};
+
+    use crate::{
+        constants::{
+            AF_INET, AF_INET6, IPPROTO_TCP, SO_LINGER, SO_RCVTIMEO, SO_REUSEADDR, SO_SNDTIMEO,
+            SOL_SOCKET, TCP_NODELAY,
+        },
+        error::{Errno, cvt, cvt_size},
+    };
+
+    /// Keeps a peer that went away from raising `SIGPIPE` on send.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const SEND_FLAGS: c_int = libc::MSG_NOSIGNAL;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const SEND_FLAGS: c_int = 0;
+
+    /// An open socket descriptor, closed when dropped.
+    #[derive(Debug)]
+    pub struct OwnedSocket(RawFd);
+
+    impl OwnedSocket {
+        /// Opens a socket, like `socket(domain, type_, protocol)` with the
+        /// constants of the same names. It is closed on exec.
+        pub fn new(domain: c_int, type_: c_int, protocol: c_int) -> io::Result<Self> {
+            #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+            let socket = Self(cvt(unsafe {
+                libc::socket(domain, type_ | libc::SOCK_CLOEXEC, protocol)
+            })?);
+            #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+            let socket = {
+                let socket = Self(cvt(unsafe { libc::socket(domain, type_, protocol) })?);
+                cvt(unsafe { libc::fcntl(socket.0, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+                socket
+            };
+            #[cfg(target_vendor = "apple")]
+            socket.set_option(SOL_SOCKET, libc::SO_NOSIGPIPE, 1 as c_int)?;
+            Ok(socket)
+        }
+
+        pub fn bind(&self, address: &SocketAddr) -> io::Result<()> {
+            let (address, len) = to_sockaddr(address);
+            cvt(unsafe { libc::bind(self.0, (&raw const address).cast(), len) })?;
+            Ok(())
+        }
+
+        pub fn listen(&self, backlog: c_int) -> io::Result<()> {
+            cvt(unsafe { libc::listen(self.0, backlog) })?;
+            Ok(())
+        }
+
+        /// Takes the next connection, which is closed on exec and blocks
+        /// whatever this socket does.
+        pub fn accept(&self) -> io::Result<(OwnedSocket, SocketAddr)> {
+            let mut address = unsafe { mem::zeroed::<libc::sockaddr_storage>() };
+            let mut len = size_of::<libc::sockaddr_storage>() as socklen_t;
+            let address_ptr = (&raw mut address).cast();
+            #[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+            let socket = Self(cvt(unsafe {
+                libc::accept4(self.0, address_ptr, &mut len, libc::SOCK_CLOEXEC)
+            })?);
+            #[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+            let socket = {
+                let socket = Self(cvt(unsafe { libc::accept(self.0, address_ptr, &mut len) })?);
+                cvt(unsafe { libc::fcntl(socket.0, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+                socket
+            };
+            Ok((socket, from_sockaddr(&address)?))
+        }
+
+        /// Connects to `address`. A nonblocking socket fails with
+        /// [`Errno::INPROGRESS`] and is connected once writable.
+        pub fn connect(&self, address: &SocketAddr) -> io::Result<()> {
+            let (address, len) = to_sockaddr(address);
+            cvt(unsafe { libc::connect(self.0, (&raw const address).cast(), len) })?;
+            Ok(())
+        }
+
+        pub fn send(&self, buf: &[u8]) -> io::Result<usize> {
+            let sent = unsafe { libc::send(self.0, buf.as_ptr().cast(), buf.len(), SEND_FLAGS) };
+            Ok(cvt_size(sent)?)
+        }
+
+        /// Receives into `buf`, returning 0 once a stream peer shut down
+        /// writing.
+        pub fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+            let received = unsafe { libc::recv(self.0, buf.as_mut_ptr().cast(), buf.len(), 0) };
+            Ok(cvt_size(received)?)
+        }
+
+        pub fn send_to(&self, buf: &[u8], address: &SocketAddr) -> io::Result<usize> {
+            let (address, len) = to_sockaddr(address);
+            let sent = unsafe {
+                libc::sendto(
+                    self.0,
+                    buf.as_ptr().cast(),
+                    buf.len(),
+                    SEND_FLAGS,
+                    (&raw const address).cast(),
+                    len,
+                )
+            };
+            Ok(cvt_size(sent)?)
+        }
+
+        pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+            let mut address = unsafe { mem::zeroed::<libc::sockaddr_storage>() };
+            let mut len = size_of::<libc::sockaddr_storage>() as socklen_t;
+            let received = unsafe {
+                libc::recvfrom(
+                    self.0,
+                    buf.as_mut_ptr().cast(),
+                    buf.len(),
+                    0,
+                    (&raw mut address).cast(),
+                    &mut len,
+                )
+            };
+            Ok((cvt_size(received)?, from_sockaddr(&address)?))
+        }
+
+        pub fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.address(libc::getsockname)
+        }
+
+        pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+            self.address(libc::getpeername)
+        }
+
+        fn address(
+            &self,
+            get: unsafe extern "C" fn(c_int, *mut libc::sockaddr, *mut socklen_t) -> c_int,
+        ) -> io::Result<SocketAddr> {
+            let mut address = unsafe { mem::zeroed::<libc::sockaddr_storage>() };
+            let mut len = size_of::<libc::sockaddr_storage>() as socklen_t;
+            cvt(unsafe { get(self.0, (&raw mut address).cast(), &mut len) })?;
+            from_sockaddr(&address)
+        }
+
+        /// Calls that would block fail with [`Errno::AGAIN`] instead, which
+        /// maps to [`io::ErrorKind::WouldBlock`].
+        pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+            let flags = cvt(unsafe { libc::fcntl(self.0, libc::F_GETFL) })?;
+            let flags = match nonblocking {
+                true => flags | libc::O_NONBLOCK,
+                false => flags & !libc::O_NONBLOCK,
+            };
+            cvt(unsafe { libc::fcntl(self.0, libc::F_SETFL, flags) })?;
+            Ok(())
+        }
+
+        /// `SO_REUSEADDR`, to bind to an address still in `TIME_WAIT`.
+        pub fn set_reuseaddr(&self, reuse: bool) -> io::Result<()> {
+            self.set_option(SOL_SOCKET, SO_REUSEADDR, reuse as c_int)
+        }
+
+        /// `SO_RCVTIMEO`, after which a blocking receive fails with
+        /// [`Errno::AGAIN`]. `None` waits forever.
+        pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.set_option(SOL_SOCKET, SO_RCVTIMEO, timeval(timeout)?)
+        }
+
+        /// `SO_SNDTIMEO`, like [`set_read_timeout`](Self::set_read_timeout)
+        /// for sending.
+        pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.set_option(SOL_SOCKET, SO_SNDTIMEO, timeval(timeout)?)
+        }
+
+        /// `TCP_NODELAY`, sending small writes right away rather than
+        /// coalescing them.
+        pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+            self.set_option(IPPROTO_TCP, TCP_NODELAY, nodelay as c_int)
+        }
+
+        /// `SO_LINGER`, how long closing waits for unsent data. `None` closes
+        /// right away and sends the rest in the background.
+        pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+            let linger = libc::linger {
+                l_onoff: linger.is_some() as c_int,
+                l_linger: linger
+                    .map_or(0, |linger| linger.as_secs().min(c_int::MAX as u64) as c_int),
+            };
+            self.set_option(SOL_SOCKET, SO_LINGER, linger)
+        }
+
+        fn set_option<T>(&self, level: c_int, name: c_int, value: T) -> io::Result<()> {
+            cvt(unsafe {
+                libc::setsockopt(
+                    self.0,
+                    level,
+                    name,
+                    (&raw const value).cast::<c_void>(),
+                    size_of::<T>() as socklen_t,
+                )
+            })?;
+            Ok(())
+        }
+
+        /// Gives up the descriptor without closing it.
+        pub fn into_raw(self) -> RawFd {
+            ManuallyDrop::new(self).0
+        }
+
+        /// # Safety
+        /// `fd` must be an open socket that nothing else closes.
+        pub unsafe fn from_raw(fd: RawFd) -> Self {
+            Self(fd)
+        }
+    }
+
+    impl Drop for OwnedSocket {
+        fn drop(&mut self) {
+            unsafe { libc::close(self.0) };
+        }
+    }
+
+    impl AsRawFd for OwnedSocket {
+        fn as_raw_fd(&self) -> RawFd {
+            self.0
+        }
+    }
+
+    /// For the socket types of std, which all convert from `OwnedFd`.
+    impl From<OwnedSocket> for OwnedFd {
+        fn from(socket: OwnedSocket) -> Self {
+            unsafe { OwnedFd::from_raw_fd(socket.into_raw()) }
+        }
+    }
+
+    /// A zero timeout would mean none to the kernel, so it is refused.
+    fn timeval(timeout: Option<Duration>) -> Result<libc::timeval, Errno> {
+        let Some(timeout) = timeout else {
+            return Ok(libc::timeval {
+                tv_sec: 0,
+                tv_usec: 0,
+            });
+        };
+        if timeout.is_zero() {
+            return Err(Errno::INVAL);
+        }
+        // Rounds up, so a timeout of less than a microsecond is still one
+        let micros = timeout.subsec_nanos().div_ceil(1000);
+        let (secs, micros) = match micros {
+            1_000_000 => (timeout.as_secs().saturating_add(1), 0),
+            micros => (timeout.as_secs(), micros),
+        };
+        Ok(libc::timeval {
+            tv_sec: secs.min(libc::time_t::MAX as u64) as libc::time_t,
+            tv_usec: micros as libc::suseconds_t,
+        })
+    }
+
+    fn to_sockaddr(address: &SocketAddr) -> (libc::sockaddr_storage, socklen_t) {
+        let mut storage = unsafe { mem::zeroed::<libc::sockaddr_storage>() };
+        let len = match address {
+            SocketAddr::V4(address) => {
+                let sin = unsafe { &mut *(&raw mut storage).cast::<libc::sockaddr_in>() };
+                sin.sin_family = AF_INET as libc::sa_family_t;
+                sin.sin_port = address.port().to_be();
+                sin.sin_addr.s_addr = u32::from_ne_bytes(address.ip().octets());
+                size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(address) => {
+                let sin6 = unsafe { &mut *(&raw mut storage).cast::<libc::sockaddr_in6>() };
+                sin6.sin6_family = AF_INET6 as libc::sa_family_t;
+                sin6.sin6_port = address.port().to_be();
+                sin6.sin6_flowinfo = address.flowinfo();
+                sin6.sin6_addr.s6_addr = address.ip().octets();
+                sin6.sin6_scope_id = address.scope_id();
+                size_of::<libc::sockaddr_in6>()
+            }
+        };
+        (storage, len as socklen_t)
+    }
+
+    fn from_sockaddr(storage: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+        match storage.ss_family as c_int {
+            AF_INET => {
+                let sin = unsafe { &*(&raw const *storage).cast::<libc::sockaddr_in>() };
+                let ip = Ipv4Addr::from(sin.sin_addr.s_addr.to_ne_bytes());
+                Ok(SocketAddrV4::new(ip, u16::from_be(sin.sin_port)).into())
+            }
+            AF_INET6 => {
+                let sin6 = unsafe { &*(&raw const *storage).cast::<libc::sockaddr_in6>() };
+                Ok(SocketAddrV6::new(
+                    Ipv6Addr::from(sin6.sin6_addr.s6_addr),
+                    u16::from_be(sin6.sin6_port),
+                    sin6.sin6_flowinfo,
+                    sin6.sin6_scope_id,
+                )
+                .into())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not an IPv4 or IPv6 address",
+            )),
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{
+            constants::{IPPROTO_UDP, SOCK_DGRAM, SOCK_STREAM},
+            types::IpAddress,
+        };
+        use std::thread;
+
+        fn loopback() -> SocketAddr {
+            (Ipv4Addr::LOCALHOST, 0).into()
+        }
+
+        #[test]
+        fn test_tcp_echo() {
+            let listener = OwnedSocket::new(AF_INET, SOCK_STREAM, IPPROTO_TCP).unwrap();
+            listener.set_reuseaddr(true).unwrap();
+            listener.bind(&loopback()).unwrap();
+            listener.listen(8).unwrap();
+            let address = listener.local_addr().unwrap();
+
+            let client = thread::spawn(move || {
+                let socket = OwnedSocket::new(AF_INET, SOCK_STREAM, IPPROTO_TCP).unwrap();
+                socket.set_nodelay(true).unwrap();
+                socket.set_linger(Some(Duration::from_secs(1))).unwrap();
+                socket
+                    .set_read_timeout(Some(Duration::from_secs(10)))
+                    .unwrap();
+                socket
+                    .set_write_timeout(Some(Duration::from_secs(10)))
+                    .unwrap();
+                socket.connect(&address).unwrap();
+                assert_eq!(socket.peer_addr().unwrap(), address);
+                assert_eq!(socket.send(b"ping").unwrap(), 4);
+                let mut buf = [0; 4];
+                assert_eq!(socket.recv(&mut buf).unwrap(), 4);
+                buf
+            });
+
+            let (stream, peer) = listener.accept().unwrap();
+            assert_eq!(peer, stream.peer_addr().unwrap());
+            let mut buf = [0; 4];
+            assert_eq!(stream.recv(&mut buf).unwrap(), 4);
+            assert_eq!(stream.send(&buf).unwrap(), 4);
+            assert_eq!(&client.join().unwrap(), b"ping");
+            // The client closed its end
+            assert_eq!(stream.recv(&mut buf).unwrap(), 0);
+        }
+
+        #[test]
+        fn test_udp_round_trip() {
+            let a = OwnedSocket::new(AF_INET, SOCK_DGRAM, IPPROTO_UDP).unwrap();
+            let b = OwnedSocket::new(AF_INET, SOCK_DGRAM, IPPROTO_UDP).unwrap();
+            a.bind(&loopback()).unwrap();
+            b.bind(&loopback()).unwrap();
+            let (a_address, b_address) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+
+            assert_eq!(a.send_to(b"datagram", &b_address).unwrap(), 8);
+            let mut buf = [0; 16];
+            let (received, from) = b.recv_from(&mut buf).unwrap();
+            assert_eq!((&buf[..received], from), (&b"datagram"[..], a_address));
+        }
+
+        #[test]
+        fn test_nonblocking_without_data() {
+            let socket = OwnedSocket::new(AF_INET, SOCK_DGRAM, IPPROTO_UDP).unwrap();
+            socket.bind(&loopback()).unwrap();
+            socket.set_nonblocking(true).unwrap();
+            let error = socket.recv(&mut [0; 16]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+            assert_eq!(error.raw_os_error(), Some(Errno::AGAIN.0));
+
+            socket.set_nonblocking(false).unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_millis(10)))
+                .unwrap();
+            let error = socket.recv(&mut [0; 16]).unwrap_err();
+            assert_eq!(error.raw_os_error(), Some(Errno::AGAIN.0));
+            let error = socket.set_read_timeout(Some(Duration::ZERO)).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        #[test]
+        fn test_addresses_convert() {
+            let v4: SocketAddr = "192.0.2.1:8080".parse().unwrap();
+            let v6 = SocketAddr::V6(SocketAddrV6::new("2001:db8::1".parse().unwrap(), 443, 7, 3));
+            for address in [v4, v6] {
+                let (storage, _) = to_sockaddr(&address);
+                assert_eq!(from_sockaddr(&storage).unwrap(), address);
+            }
+
+            assert_eq!(SocketAddr::from(&IpAddress::from(v4)), v4);
+            // Flow info and scope id do not survive
+            assert_eq!(
+                SocketAddr::from(&IpAddress::from(v6)),
+                SocketAddr::from((v6.ip().to_owned(), 443))
+            );
+        }
+    }
+}
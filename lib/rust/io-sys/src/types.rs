@@ -1,6 +1,7 @@
 //! Type definitions for the example library
 
 use libc::{c_int, c_char, c_uchar, c_ushort, c_void, c_longlong, c_ulonglong, c_uint,  size_t};
+use std::net::{Ipv6Addr, SocketAddr};
 
 /// Opaque File handle
 #[repr(C)]
@@ -41,6 +42,52 @@ pub struct IpAddressIpv6 {
     pub port: u16,
 }
 
+/// The port stays in host byte order. IPv6 flow info and scope id have no
+/// place here and are dropped.
+impl From<SocketAddr> for IpAddress {
+    fn from(address: SocketAddr) -> Self {
+        let (is_ipv6, addr) = match address {
+            SocketAddr::V4(address) => (
+                false,
+                IpAddressAddrUnion {
+                    ipv4: IpAddressIpv4 {
+                        addr: address.ip().octets(),
+                        port: address.port(),
+                    },
+                },
+            ),
+            SocketAddr::V6(address) => (
+                true,
+                IpAddressAddrUnion {
+                    ipv6: IpAddressIpv6 {
+                        addr: address.ip().octets(),
+                        port: address.port(),
+                    },
+                },
+            ),
+        };
+        IpAddress { is_ipv6, addr }
+    }
+}
+
+impl From<&IpAddress> for SocketAddr {
+    fn from(address: &IpAddress) -> Self {
+        // `is_ipv6` tells which field was written
+        unsafe {
+            match address.is_ipv6 {
+                false => {
+                    let IpAddressIpv4 { addr, port } = address.addr.ipv4;
+                    SocketAddr::from((addr, port))
+                }
+                true => {
+                    let IpAddressIpv6 { addr, port } = address.addr.ipv6;
+                    SocketAddr::from((Ipv6Addr::from(addr), port))
+                }
+            }
+        }
+    }
+}
+
 /// Socket options
 #[repr(C)]
 pub enum Option {
@@ -131,6 +178,7 @@ pub struct Context {
 
 /// Error enum
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
     Ok = 0,
     OutOfMemory = 1,
@@ -152,4 +200,4 @@ pub enum Error {
     SubmissionQueueFull = 51,
     SystemLimitReached = 60,
     Unknown = 999,
-}
\ No newline at end of file
+}
\ No newline at end of file
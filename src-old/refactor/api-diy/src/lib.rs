@@ -0,0 +1,2 @@
+#![feature(ptr_metadata)]
+pub mod request;
@@ -1,6 +1,12 @@
+use ecs::schedule::Schedule;
+use ecs::world::World;
 use http::Router;
 
 #[base::main]
 async fn main() {
-    http::serve(Router {}).await;
+    let mut world = World::new();
+    world.insert_resource(Router::new());
+    if let Err(error) = http::serve("0.0.0.0:8080", world, Schedule::default()).await {
+        eprintln!("{error}");
+    }
 }
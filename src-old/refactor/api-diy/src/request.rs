@@ -0,0 +1,338 @@
+//! Requests answered over several schedule runs. Every run hands the
+//! [`Pending`] ones to the [`Handler`], retries those it could not answer
+//! while their budget lasts and settles the rest as [`Complete`] or
+//! [`Failure`], which are despawned a few runs later.
+
+use base::rt::join::UnorderedJoin;
+use ecs::command::{Commands, Reserved};
+use ecs::component::source::Source;
+use ecs::component::{Component, component};
+use ecs::query::Query;
+use ecs::resource::{Res, ResMut};
+use ecs::schedule::Schedule;
+use ecs::world::World;
+use http::{Request, Response};
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Marks a request waiting for an answer, with the number of attempts the
+/// handler has left at it. A budget of zero or below is exhausted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[component]
+pub struct Pending(pub isize);
+
+/// What the handler answered a request with.
+#[derive(Debug, Clone)]
+#[component]
+pub struct Complete(pub Success);
+
+/// Why a request was given up on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[component]
+pub struct Failure(pub Error);
+
+/// The id commands reach a request's entity by, as systems only see its
+/// components.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[component]
+pub struct Ticket(pub Reserved);
+
+/// Schedule runs since a request was settled, counted by [`reap`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[component]
+pub struct Settled(pub u32);
+
+#[derive(Debug, Clone)]
+pub struct Success(pub Response);
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    /// The handler could not answer this time. The request is tried again
+    /// next run while it has attempts left.
+    #[error("the handler is unavailable")]
+    Unavailable,
+    /// The handler refused the request, trying again would not help.
+    #[error("the request was rejected: {0}")]
+    Rejected(String),
+    /// The request ran out of attempts.
+    #[error("the request ran out of attempts")]
+    RequestTimeout,
+}
+
+type Answer = Pin<Box<dyn Future<Output = Result<Success, Error>> + Send>>;
+
+/// Answers requests, as a resource of the world. Its futures run on the
+/// worker pool, every pending request's next to the others'.
+#[derive(Clone)]
+pub struct Handler(Arc<dyn Fn(Request) -> Answer + Send + Sync>);
+
+impl Handler {
+    pub fn new<F, Fut>(handle: F) -> Self
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Success, Error>> + Send + 'static,
+    {
+        Self(Arc::new(move |request| Box::pin(handle(request))))
+    }
+}
+
+/// Schedule runs a settled request is kept for before [`reap`] despawns it,
+/// as a resource of the world.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention(pub u32);
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self(3)
+    }
+}
+
+/// Outcomes of the requests so far, as a resource of the world.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub completed: u64,
+    /// Failures other than timeouts.
+    pub failed: u64,
+    pub timed_out: u64,
+    /// Attempts the handler could not answer, with attempts left after them.
+    pub retried: u64,
+    pub reaped: u64,
+}
+
+/// Adds the resources the lifecycle needs to `world`, answering with
+/// `handler` and keeping settled requests for `retention`.
+pub fn install(world: &mut World, handler: Handler, retention: Retention) {
+    world.insert_resource(handler);
+    world.insert_resource(retention);
+    world.insert_resource(Metrics::default());
+}
+
+/// Schedules [`dispatch`] and [`reap`] in the current stage of `schedule`.
+pub fn schedule(schedule: Schedule) -> Schedule {
+    schedule.schedule(dispatch).schedule(reap)
+}
+
+/// Spawns `request` with `attempts` attempts, and returns the id its entity
+/// goes by across schedule runs, see [`World::spawned`].
+pub fn submit(world: &mut World, request: Request, attempts: isize) -> Reserved {
+    let entity = world.spawn_batch([(request, Pending(attempts))])[0];
+    let id = world.reserve(entity);
+    world.insert_component(entity, Ticket(id));
+    id
+}
+
+/// Claims every pending request, taking one attempt off its budget, and
+/// waits for the handler to answer them all. Those answered or refused are
+/// settled, those it could not answer stay pending unless that was their
+/// last attempt. Requests claimed with their budget exhausted time out
+/// without reaching the handler.
+pub async fn dispatch(
+    mut pending: Query<'_, (&'_ Ticket, &'_ Request, &'_ mut Pending)>,
+    handler: Res<Handler>,
+    mut metrics: ResMut<Metrics>,
+    commands: Commands,
+) {
+    let mut answers = UnorderedJoin::new();
+    for (ticket, request, pending) in &mut pending {
+        if pending.0 <= 0 {
+            settle(&commands, ticket.0, Failure(Error::RequestTimeout));
+            metrics.timed_out += 1;
+            continue;
+        }
+        pending.0 -= 1;
+        let (id, left, answer) = (ticket.0, pending.0, (handler.0)(request.clone()));
+        answers.push(async move { (id, left, answer.await) });
+    }
+
+    for (id, left, answer) in answers.await {
+        match answer {
+            Ok(success) => {
+                settle(&commands, id, Complete(success));
+                metrics.completed += 1;
+            }
+            Err(Error::Unavailable) if left > 0 => metrics.retried += 1,
+            Err(Error::Unavailable) => {
+                settle(&commands, id, Failure(Error::RequestTimeout));
+                metrics.timed_out += 1;
+            }
+            Err(error) => {
+                settle(&commands, id, Failure(error));
+                metrics.failed += 1;
+            }
+        }
+    }
+}
+
+/// Swaps the [`Pending`] of a request for its outcome.
+fn settle<C: Component + Source + Send>(commands: &Commands, id: Reserved, outcome: C) {
+    commands.remove::<Pending>(id);
+    commands.insert(id, outcome);
+    commands.insert(id, Settled(0));
+}
+
+/// Ages the settled requests by a schedule run, and despawns those kept for
+/// the [`Retention`].
+pub fn reap(
+    mut settled: Query<'_, (&'_ Ticket, &'_ mut Settled)>,
+    retention: Res<Retention>,
+    mut metrics: ResMut<Metrics>,
+    commands: Commands,
+) {
+    for (ticket, settled) in &mut settled {
+        settled.0 += 1;
+        if settled.0 >= retention.0 {
+            commands.despawn(ticket.0);
+            metrics.reaped += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base::prelude::Vector;
+    use base::rt::{Runtime, block_on, worker};
+    use http::Method;
+    use std::collections::HashMap;
+    use std::sync::{Mutex, Once};
+    use std::thread;
+
+    /// Starts the worker pool the schedules run on, once for every test.
+    fn runtime() {
+        static START: Once = Once::new();
+        START.call_once(|| {
+            let (tx, rx) = std::sync::mpsc::channel();
+            thread::spawn(move || {
+                block_on(async move {
+                    let start = Runtime::new(Vector::splat(7))
+                        .workers(3)
+                        .signals(false)
+                        .start()
+                        .await;
+                    tx.send(()).unwrap();
+                    worker::worker_start_barrier(start).await;
+                })
+            });
+            rx.recv().unwrap();
+        });
+    }
+
+    /// Answers by path: `/ok` and `/reject` at once, `/flaky` on the third
+    /// attempt and `/down` never. Counts the attempts at every path.
+    fn handler(attempts: Arc<Mutex<HashMap<String, u32>>>) -> Handler {
+        Handler::new(move |request: Request| {
+            let attempt = {
+                let mut attempts = attempts.lock().unwrap();
+                let attempt = attempts.entry(request.path.clone()).or_default();
+                *attempt += 1;
+                *attempt
+            };
+            async move {
+                match request.path.split('/').nth(1) {
+                    Some("ok") => Ok(Success(Response::new(200))),
+                    Some("reject") => Err(Error::Rejected(request.path)),
+                    Some("flaky") if attempt >= 3 => Ok(Success(Response::new(201))),
+                    _ => Err(Error::Unavailable),
+                }
+            }
+        })
+    }
+
+    /// The requests of `ids` still alive with a `C`.
+    fn count<C: Component>(world: &World, ids: &[Reserved]) -> usize {
+        ids.iter()
+            .filter_map(|&id| world.spawned(id))
+            .filter(|&entity| world.get::<C>(entity).is_some())
+            .count()
+    }
+
+    #[test]
+    fn test_lifecycle() {
+        runtime();
+        let attempts = Arc::new(Mutex::new(HashMap::new()));
+        let mut world = World::new();
+        install(&mut world, handler(attempts.clone()), Retention(2));
+        let mut schedule = schedule(Schedule::default());
+
+        let mut submit =
+            |path: &str, budget| submit(&mut world, Request::new(Method::Get, path), budget);
+        let ids = [
+            submit("/ok/1", 3),
+            submit("/ok/2", 3),
+            submit("/ok/3", 3),
+            submit("/reject/1", 3),
+            submit("/reject/2", 3),
+            // Answered on its last attempt
+            submit("/flaky/a", 3),
+            // Out of attempts before it would be answered
+            submit("/flaky/b", 2),
+            submit("/down", 4),
+            submit("/exhausted", 0),
+        ];
+        let counts = |world: &World| {
+            (
+                count::<Pending>(world, &ids),
+                count::<Complete>(world, &ids),
+                count::<Failure>(world, &ids),
+            )
+        };
+        let failure = |world: &World, id| world.get::<Failure>(world.spawned(id).unwrap()).cloned();
+
+        block_on(schedule.run(&mut world)).unwrap();
+        assert_eq!(counts(&world), (3, 3, 3));
+        assert_eq!(
+            failure(&world, ids[3]),
+            Some(Failure(Error::Rejected("/reject/1".into())))
+        );
+        assert_eq!(
+            failure(&world, ids[8]),
+            Some(Failure(Error::RequestTimeout))
+        );
+        assert_eq!(
+            world.get::<Pending>(world.spawned(ids[5]).unwrap()),
+            Some(&Pending(2))
+        );
+        assert!(!attempts.lock().unwrap().contains_key("/exhausted"));
+
+        block_on(schedule.run(&mut world)).unwrap();
+        assert_eq!(counts(&world), (2, 3, 4));
+        assert_eq!(
+            failure(&world, ids[6]),
+            Some(Failure(Error::RequestTimeout))
+        );
+
+        // The first settled are reaped as `/flaky/a` is answered
+        block_on(schedule.run(&mut world)).unwrap();
+        assert_eq!(counts(&world), (1, 1, 1));
+        assert_eq!(world.spawned(ids[0]), None);
+        let flaky = world
+            .get::<Complete>(world.spawned(ids[5]).unwrap())
+            .unwrap();
+        assert_eq!(flaky.0.0.status, 201);
+
+        for _ in 0..3 {
+            block_on(schedule.run(&mut world)).unwrap();
+        }
+        assert_eq!(counts(&world), (0, 0, 0));
+        assert_eq!(world.entity_count(), 0);
+        assert_eq!(
+            *world.resource::<Metrics>().unwrap(),
+            Metrics {
+                completed: 4,
+                failed: 2,
+                timed_out: 3,
+                retried: 6,
+                reaped: 9,
+            }
+        );
+        let attempts = attempts.lock().unwrap();
+        assert_eq!(
+            (
+                attempts["/flaky/a"],
+                attempts["/flaky/b"],
+                attempts["/down"]
+            ),
+            (3, 2, 4)
+        );
+    }
+}
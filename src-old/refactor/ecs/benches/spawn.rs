@@ -0,0 +1,51 @@
+#![feature(ptr_metadata)]
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use ecs::component::{component, registry::STACK};
+use ecs::world::World;
+
+const COUNT: usize = 1_000_000;
+
+#[derive(Clone, Copy)]
+#[component]
+struct Position(f32, f32);
+
+#[derive(Clone, Copy)]
+#[component]
+struct Velocity(f32, f32);
+
+fn bundle(i: usize) -> (Position, Velocity) {
+    (Position(i as f32, 0.0), Velocity(1.0, 1.0))
+}
+
+fn spawn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spawn 1M");
+    group.sample_size(10);
+    group.bench_function("extend", |b| {
+        b.iter_batched(
+            World::new,
+            |mut world| {
+                // `extend` returns at most `STACK` entities per call
+                for start in (0..COUNT).step_by(STACK) {
+                    world.extend((start..(start + STACK).min(COUNT)).map(bundle));
+                }
+                world
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function("spawn_batch", |b| {
+        b.iter_batched(
+            World::new,
+            |mut world| {
+                world.spawn_batch((0..COUNT).map(bundle));
+                world
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, spawn);
+criterion_main!(benches);
@@ -0,0 +1,249 @@
+//! Structural changes queued by systems while the schedule has the world
+//! lent out, applied at the sync point that ends their stage.
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicU32, Ordering},
+};
+
+use flume::{Receiver, Sender, unbounded};
+
+use crate::{
+    component::{Component, archetype::Archetype, source::Source, table::Table},
+    entity::Entity,
+    system::param::{Context, Param},
+    world::World,
+};
+
+/// An entity queued with [`Commands::spawn`]. It can be named by other
+/// commands right away, and turns into an [`Entity`] once the spawn is
+/// applied, see [`World::spawned`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Reserved(u32);
+
+/// The entity a command applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Entity(Entity),
+    Reserved(Reserved),
+}
+
+impl From<Entity> for Target {
+    fn from(entity: Entity) -> Self {
+        Self::Entity(entity)
+    }
+}
+
+impl From<Reserved> for Target {
+    fn from(reserved: Reserved) -> Self {
+        Self::Reserved(reserved)
+    }
+}
+
+pub(crate) enum Command {
+    Spawn(Reserved, Box<dyn FnOnce(&mut World) -> Entity + Send>),
+    Despawn(Target),
+    DespawnRecursive(Target),
+    /// Moves the entity to another archetype and returns its new handle.
+    Migrate(Target, Box<dyn FnOnce(&mut World, Entity) -> Entity + Send>),
+    /// Gives the child the parent, or takes it from the one it has.
+    SetParent(Target, Option<Target>),
+}
+
+impl Command {
+    /// The entities the command names.
+    pub(crate) fn targets(&mut self) -> impl Iterator<Item = &mut Target> {
+        let (first, second) = match self {
+            Self::Spawn(..) => (None, None),
+            Self::Despawn(target) | Self::DespawnRecursive(target) | Self::Migrate(target, _) => {
+                (Some(target), None)
+            }
+            Self::SetParent(child, parent) => (Some(child), parent.as_mut()),
+        };
+        first.into_iter().chain(second)
+    }
+}
+
+//SAFETY: entities are only dereferenced by the world, once it applies the command
+unsafe impl Send for Command {}
+
+/// The commands of a world, shared by every system running in it.
+#[derive(Clone)]
+pub(crate) struct Queue {
+    tx: Sender<Command>,
+    rx: Receiver<Command>,
+    reserved: Arc<AtomicU32>,
+}
+
+impl Default for Queue {
+    fn default() -> Self {
+        let (tx, rx) = unbounded();
+        Self {
+            tx,
+            rx,
+            reserved: Default::default(),
+        }
+    }
+}
+
+impl Queue {
+    fn push(&self, command: Command) {
+        // The queue holds its own receiver, so it cannot be disconnected
+        self.tx.send(command).unwrap();
+    }
+
+    pub(crate) fn reserve(&self) -> Reserved {
+        Reserved(self.reserved.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Takes the commands queued so far, oldest first.
+    pub(crate) fn drain(&self) -> Vec<Command> {
+        self.rx.try_iter().collect()
+    }
+}
+
+/// Queues spawns, despawns and component changes from inside a system. They
+/// are applied in the order they were queued once the system's stage is
+/// done, so queries running alongside never see them half way.
+pub struct Commands(Queue);
+
+impl Commands {
+    /// Queues an entity made of `bundle` and reserves its id.
+    pub fn spawn<B: Source + Send>(&self, bundle: B) -> Reserved {
+        let reserved = self.0.reserve();
+        self.0.push(Command::Spawn(
+            reserved,
+            Box::new(move |world| world.extend([bundle])[0]),
+        ));
+        reserved
+    }
+
+    /// Queues despawning `entity`. Despawning it again is a no-op.
+    pub fn despawn(&self, entity: impl Into<Target>) {
+        self.0.push(Command::Despawn(entity.into()));
+    }
+
+    /// Queues despawning `entity` and all its descendants, whatever the
+    /// [`OrphanPolicy`](crate::hierarchy::OrphanPolicy).
+    pub fn despawn_recursive(&self, entity: impl Into<Target>) {
+        self.0.push(Command::DespawnRecursive(entity.into()));
+    }
+
+    /// Queues making `parent` the parent of `child`, taking it from the one
+    /// it has. A `parent` that is `child` or descends from it is rejected:
+    /// the command is dropped and the [`HierarchyError`] sent to
+    /// `Events<HierarchyError>`, if the world added them.
+    ///
+    /// [`HierarchyError`]: crate::hierarchy::HierarchyError
+    pub fn set_parent(&self, child: impl Into<Target>, parent: impl Into<Target>) {
+        self.0
+            .push(Command::SetParent(child.into(), Some(parent.into())));
+    }
+
+    /// Queues taking `child` from its parent, making it a root.
+    pub fn remove_parent(&self, child: impl Into<Target>) {
+        self.0.push(Command::SetParent(child.into(), None));
+    }
+
+    /// Queues adding `component` to `entity`, replacing the one it has.
+    pub fn insert<C: Component + Source + Send>(&self, entity: impl Into<Target>, component: C) {
+        self.0.push(Command::Migrate(
+            entity.into(),
+            Box::new(move |world, entity| world.insert_component(entity, component)),
+        ));
+    }
+
+    /// Queues taking the `C` off `entity`.
+    pub fn remove<C: Component>(&self, entity: impl Into<Target>) {
+        self.0.push(Command::Migrate(
+            entity.into(),
+            Box::new(|world, entity| world.remove_component::<C>(entity)),
+        ));
+    }
+}
+
+impl<'a> Param<'a> for Commands {
+    fn inject(_: &mut Vec<Archetype>) {}
+
+    fn create(_: &'a [Archetype], _: &'a mut [&'a mut Table], context: &'a Context) -> Self
+    where
+        Self: Sized + 'a,
+    {
+        Commands(context.commands.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{params, query::Query};
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Velocity(f32, f32);
+
+    component!(Position, Velocity);
+
+    fn count_positions(world: &mut World) -> usize {
+        let (positions,) = params::<(Query<&'static Position>,)>(world);
+        (&positions).into_iter().count()
+    }
+
+    #[test]
+    fn test_spawns_show_up_in_the_next_stage() {
+        let mut world = World::new();
+        let (commands,) = params::<(Commands,)>(&mut world);
+        let spawned = (0..10)
+            .map(|i| {
+                commands.spawn(Position {
+                    x: i as f32,
+                    y: 0.0,
+                })
+            })
+            .collect::<Vec<_>>();
+        commands.insert(spawned[3], Velocity(1.0, 0.0));
+        commands.insert(spawned[9], Velocity(0.0, 1.0));
+        commands.remove::<Position>(spawned[9]);
+        assert_eq!(world.spawned(spawned[0]), None);
+        assert_eq!(count_positions(&mut world), 0);
+
+        world.sync();
+        assert_eq!(count_positions(&mut world), 9);
+        let entity = world.spawned(spawned[3]).unwrap();
+        assert_eq!(
+            world.get::<Position>(entity),
+            Some(&Position { x: 3.0, y: 0.0 })
+        );
+        assert_eq!(world.get::<Velocity>(entity), Some(&Velocity(1.0, 0.0)));
+        let entity = world.spawned(spawned[9]).unwrap();
+        assert_eq!(world.get::<Position>(entity), None);
+        assert_eq!(world.get::<Velocity>(entity), Some(&Velocity(0.0, 1.0)));
+    }
+
+    #[test]
+    fn test_despawn_waits_for_the_iteration() {
+        let mut world = World::new();
+        let entities = world.extend((0..4).map(|i| Position {
+            x: i as f32,
+            y: 0.0,
+        }));
+        let (commands, positions) = params::<(Commands, Query<&'static Position>)>(&mut world);
+        let mut seen = vec![];
+        for (position,) in &positions {
+            commands.despawn(entities[seen.len()]);
+            seen.push(position.x);
+        }
+        assert_eq!(seen, [0.0, 1.0, 2.0, 3.0]);
+
+        // The same entity despawned twice in a frame goes once
+        commands.despawn(entities[0]);
+        world.sync();
+        assert_eq!(count_positions(&mut world), 0);
+        assert!((0..entities.len()).all(|i| !world.contains(entities[i])));
+    }
+}
@@ -1,8 +1,39 @@
-use super::{Component, Handle, Meta};
-use std::any::Any;
-use std::ptr::DynMetadata;
+use super::{Component, Handle, Id, Meta};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::marker::Unsize;
+use std::mem;
+use std::ptr::{self, DynMetadata, Pointee};
 
-pub trait Access: ?Sized {
+pub trait Access {
     fn access<'a>(ptr: *mut u8, vtable: DynMetadata<dyn Component>) -> &'a mut Self;
     fn meta() -> Vec<Meta>;
+    /// The id of `Self` if it is a trait object. Queries read components as
+    /// one through the vtable registered for them in [`Traits`].
+    fn trait_id() -> Option<TypeId> {
+        None
+    }
+}
+
+/// The vtables of the traits components are registered to implement, by
+/// trait and component. See
+/// [`World::register_trait_impl`](crate::world::World::register_trait_impl).
+#[derive(Clone, Default)]
+pub struct Traits(HashMap<(TypeId, Id), DynMetadata<dyn Component>>);
+
+impl Traits {
+    pub(crate) fn insert<T, C>(&mut self)
+    where
+        T: ?Sized + Pointee<Metadata = DynMetadata<T>> + 'static,
+        C: Component + Unsize<T>,
+    {
+        let vtable = ptr::metadata(ptr::null::<C>() as *const T);
+        //SAFETY: only `T`'s `Access::access` reads the vtable, as a `T` again
+        let vtable = unsafe { mem::transmute_copy::<_, DynMetadata<dyn Component>>(&vtable) };
+        self.0.insert((TypeId::of::<T>(), C::meta().id), vtable);
+    }
+
+    pub fn get(&self, trait_id: TypeId, component: Id) -> Option<DynMetadata<dyn Component>> {
+        self.0.get(&(trait_id, component)).copied()
+    }
 }
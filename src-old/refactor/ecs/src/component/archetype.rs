@@ -50,18 +50,30 @@ impl From<Meta> for Archetype {
 
 impl Archetype {
     pub const MAX: usize = 256;
+    /// The size of a row, a multiple of [`Archetype::align`] so every row
+    /// of a page is aligned like the first.
     pub fn size(&self) -> usize {
-        (self.offset_of(self.count()))
+        self.offset_of(self.count()).next_multiple_of(self.align())
+    }
+    /// The alignment of a row, that of its most aligned component.
+    pub fn align(&self) -> usize {
+        self.iter().map(|meta| meta.align).max().unwrap_or(1)
     }
     pub fn count(&self) -> usize {
         self.len()
     }
+    /// Where the component at `index` starts in a row, padded to its
+    /// alignment, or where the last one ends for `count`.
     pub(crate) fn offset_of(&self, index: usize) -> usize {
-        self.iter()
-            .copied()
-            .map(|x| x.size)
-            .take(index)
-            .sum::<usize>()
+        let mut offset = 0usize;
+        for (i, meta) in self.iter().enumerate() {
+            offset = offset.next_multiple_of(meta.align);
+            if i == index {
+                return offset;
+            }
+            offset += meta.size;
+        }
+        offset
     }
     pub(crate) fn merge(&mut self, archetype: Archetype) {
         for meta in archetype {
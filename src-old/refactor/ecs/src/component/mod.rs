@@ -1,3 +1,4 @@
+use crate::entity::Entity;
 use derive_more::derive::{Deref, DerefMut};
 use std::fmt::Formatter;
 use std::ptr::DynMetadata;
@@ -18,6 +19,7 @@ pub struct Id(pub TypeId);
 pub struct Meta {
     pub id: Id,
     pub size: usize,
+    pub align: usize,
 }
 
 impl Meta {
@@ -25,6 +27,7 @@ impl Meta {
         Self {
             id: Id(typeid::of::<T>()),
             size: mem::size_of::<T>(),
+            align: mem::align_of::<T>(),
         }
     }
 }
@@ -33,17 +36,27 @@ pub trait Component: 'static {
     fn meta() -> Meta
     where
         Self: Sized;
+    /// Runs in the row once the component is added to `entity`, when it
+    /// spawns or by [`World::insert_component`]. Moves to another archetype
+    /// keep the component and do not run it.
+    ///
+    /// [`World::insert_component`]: crate::world::World::insert_component
+    fn on_insert(&mut self, entity: Entity) {}
+    /// Runs before the component is taken off `entity`, when it despawns, is
+    /// removed or replaced, or leaves for another world. It is dropped right
+    /// after, as are the components of a dropped world, which are not told.
+    fn on_remove(&mut self, entity: Entity) {}
 }
 
 pub struct Handle(pub Box<dyn Component>, pub DynMetadata<dyn Component>);
 impl Handle {
-    fn as_mut_ptr(&mut self) -> *mut dyn Component {
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut dyn Component {
         //SAFETY: Arc only has one strong reference to the component
         //Well here technically two but were just hacking it to get the raw pointer
         let ptr = &mut *self.0 as *mut _;
         ptr
     }
-    fn vtable(&self) -> DynMetadata<dyn Component> {
+    pub(crate) fn vtable(&self) -> DynMetadata<dyn Component> {
         self.1
     }
 }
@@ -4,8 +4,15 @@ use std::ops::AddAssign;
 use std::{collections::HashMap, iter};
 
 use crate::entity::Entity;
+use crate::world::WorldId;
+use base::collections::array::Array;
 
-use super::{archetype::Archetype, source::Source, table::Table};
+use super::{
+    Handle,
+    archetype::Archetype,
+    source::Source,
+    table::{Components, Moved, Table, Tick},
+};
 
 pub const STACK: usize = 1024;
 pub type Entities = ArrayVec<Entity, STACK>;
@@ -42,7 +49,12 @@ impl AddAssign<Shard> for Shard {
             ) => {
                 let tables = unsafe { tables.get().as_mut().unwrap() };
                 let other_tables = unsafe { other_tables.get().as_mut().unwrap() };
-                tables.extend(other_tables.drain(..));
+                // Parameters of one system may match the same table
+                for (archetype, table) in other_tables.drain(..) {
+                    if !tables.iter().any(|(other, _)| *other == archetype) {
+                        tables.push((archetype, table));
+                    }
+                }
             }
             (
                 Shard::Linear { tables },
@@ -121,71 +133,148 @@ impl Default for Shard {
     }
 }
 
-#[derive(Default)]
-pub struct Registry(Shard);
+/// The tables of a world and the tick it is at.
+pub struct Registry(Shard, WorldId, Tick);
+
+impl Drop for Registry {
+    fn drop(&mut self) {
+        // Shards only alias the tables, the registry owns them
+        let tables = self
+            .0
+            .table_map_mut()
+            .expect("main shard should be a table map");
+        for (_, table) in tables.drain() {
+            drop(unsafe { Box::from_raw(table as *mut Table) });
+        }
+    }
+}
 
 impl Registry {
+    pub fn new(world: WorldId) -> Self {
+        Self(Shard::default(), world, 0)
+    }
+    pub fn world(&self) -> WorldId {
+        self.1
+    }
+    pub fn tick(&self) -> Tick {
+        self.2
+    }
+    /// Starts the next tick, once per schedule run.
+    pub fn advance(&mut self) -> Tick {
+        self.2 += 1;
+        self.2
+    }
     pub fn extend<Src: Source + 'static>(
         &mut self,
         src: impl IntoIterator<Item = Src>,
     ) -> Entities {
-        let Self(shard) = self;
         let mut src = src.into_iter();
         let Some(first) = src.next() else {
             return Entities::new();
         };
         let archetype = unsafe { first.archetype() };
         let components = unsafe { first.erase_component_data() };
+        let table = self.table(archetype);
+        let src =
+            iter::once(components).chain(src.map(|src| unsafe { src.erase_component_data() }));
+        table.extend(src).collect::<Entities>()
+    }
+    /// Like [`Registry::extend`] for batches of any size: the table makes
+    /// room for the whole batch before the rows are written.
+    pub fn spawn_batch<Src: Source + 'static>(
+        &mut self,
+        src: impl IntoIterator<Item = Src>,
+    ) -> Vec<Entity> {
+        let mut src = src.into_iter();
+        let Some(first) = src.next() else {
+            return vec![];
+        };
+        let archetype = unsafe { first.archetype() };
+        let table = self.table(archetype);
+        let additional = src.size_hint().0 + 1;
+        table.reserve(additional);
+        let mut entities = Vec::with_capacity(additional);
+        let src = iter::once(first).chain(src).map(|src| unsafe { src.erase_component_data() });
+        entities.extend(table.extend(src));
+        entities
+    }
+    /// Inserts one row of already erased components into the table of
+    /// `archetype`, e.g. an entity moved over from another world.
+    pub(crate) fn insert(&mut self, archetype: Archetype, components: Components<'static>) -> Entity {
+        self.table(archetype)
+            .extend(iter::once(components))
+            .next()
+            .expect("table should make room for one more row")
+    }
+    /// Frees the row of `entity` and returns its component handles instead
+    /// of dropping them, along with the entity moved into the row.
+    pub(crate) fn take(
+        &mut self,
+        entity: Entity,
+    ) -> Option<(Array<Handle, { Archetype::MAX }>, Option<Moved>)> {
+        self.0
+            .table_map_mut()
+            .expect("main shard should be a table map")
+            .get_mut(entity.archetype())?
+            .take(entity)
+    }
+    /// Every table of the world, in no particular order.
+    pub(crate) fn tables(&self) -> impl Iterator<Item = &Table> {
+        self.0
+            .table_map()
+            .expect("main shard should be a table map")
+            .values()
+            .map(|table| &**table)
+    }
+    /// Whether `entity` still has its row.
+    pub(crate) fn contains(&self, entity: Entity) -> bool {
+        self.table_of(entity)
+            .is_some_and(|table| table.contains(entity))
+    }
+    /// The table of the archetype `entity` was spawned or moved with.
+    pub(crate) fn table_of(&self, entity: Entity) -> Option<&Table> {
+        self.0
+            .table_map()
+            .expect("main shard should be a table map")
+            .get(entity.archetype())
+            .map(|table| &**table)
+    }
+    fn table(&mut self, archetype: Archetype) -> &mut Table {
+        let Self(shard, world, tick) = self;
         let table = shard
             .table_map_mut()
             .expect("main shard should be a table map")
             .entry(archetype.clone())
             .or_insert_with(|| unsafe {
-                Box::into_raw(Box::new(Table::with_archetype(archetype)))
+                Box::into_raw(Box::new(Table::with_archetype(archetype, *world)))
                     .as_mut()
                     .unwrap()
             });
-        let src =
-            iter::once(components).chain(src.map(|src| unsafe { src.erase_component_data() }));
-        table.extend(src).collect::<Entities>()
+        table.tick = *tick;
+        table
     }
-    pub fn drop(&mut self, entity: impl IntoIterator<Item = Entity>) {
-        let Self(shard) = self;
-        let mut buckets = HashMap::<Archetype, Vec<Entity>>::default();
-
-        let entities = entity.into_iter().collect::<Vec<Entity>>();
-        entities
-            .into_iter()
-            .for_each(|x| buckets.entry(x.archetype().clone()).or_default().push(x));
-
-        for (archetype, buckets) in buckets {
-            let table = shard
-                .table_map_mut()
-                .expect("main shard should be a table map")
-                .get_mut(&archetype)
-                .unwrap();
-            table.free(buckets);
-        }
-    }
-    pub(crate) fn shard(&mut self, archetype: Archetype) -> Shard {
-        let mut shard = Shard::Linear {
+    /// Hands out the tables matching `archetype` that `admits` lets through.
+    /// They stay in the registry, the schedule keeps systems borrowing the
+    /// same component mutably from sharing them at the same time.
+    pub(crate) fn shard(&mut self, archetype: Archetype, admits: fn(&Archetype) -> bool) -> Shard {
+        let Self(main, _, tick) = self;
+        let shard = Shard::Linear {
             tables: vec![].into(),
         };
 
-        let mut table_take = vec![];
-        if let Some(tables) = self.0.table_map() {
-            for (table_arch, table) in tables {
-                if table_arch >= &archetype {
-                    table_take.push(table_arch.clone());
-                }
+        let tables = main
+            .table_map_mut()
+            .expect("main shard should be a table map");
+        for (table_arch, table) in tables {
+            // `a >= b` holds when `a` has no component `b` lacks, so this
+            // hands out the tables holding at least the query's components
+            if &archetype >= table_arch && admits(table_arch) {
+                table.tick = *tick;
+                let table = unsafe { (&raw mut **table).as_mut().unwrap() };
+                shard.table_vec().unwrap().push((table_arch.clone(), table));
             }
         }
 
-        for table_arch in table_take {
-            let table = dbg!(self.0.table_map_mut().unwrap().remove(&table_arch).unwrap());
-            shard.table_vec().unwrap().push((table_arch, table));
-        }
-
         shard
     }
 }
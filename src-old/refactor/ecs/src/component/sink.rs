@@ -1,18 +1,24 @@
-use std::ptr;
+use std::any::TypeId;
+use std::ptr::{self, DynMetadata};
 
 use crate::entity::Entity;
 
-use super::{Component, Handle, Meta, access::Access, table::Data};
+use super::{Component, Meta, access::Access, table::Data};
 
-pub trait Sink: ?Sized {
+pub trait Sink {
     type Ref;
     type Mut;
+    /// Whether a mutable fetch writes the component, which counts as a
+    /// change.
+    const MUTABLE: bool = false;
+    /// Whether rows lacking the component still match, see `Option<S>`.
+    const OPTIONAL: bool = false;
 
     unsafe fn coerce_component_data(
         entity: Entity,
         offset: usize,
         meta: Meta,
-        handle: &Handle,
+        vtable: DynMetadata<dyn Component>,
     ) -> Self::Ref
     where
         Self: Sized,
@@ -22,11 +28,11 @@ pub trait Sink: ?Sized {
                 ptr: entity.data().add(offset) as _,
                 meta,
             },
-            handle,
+            vtable,
         )
     }
 
-    unsafe fn interpret_component_data(data: Data, handle: &Handle) -> Self::Ref
+    unsafe fn interpret_component_data(data: Data, vtable: DynMetadata<dyn Component>) -> Self::Ref
     where
         Self: Sized;
 
@@ -34,38 +40,115 @@ pub trait Sink: ?Sized {
         entity: Entity,
         offset: usize,
         meta: Meta,
-        handle: &Handle,
+        vtable: DynMetadata<dyn Component>,
     ) -> Self::Mut
     where
         Self: Sized,
     {
         Self::interpret_component_data_mut(
             Data {
-                ptr: entity.head().add(offset),
+                ptr: entity.data().add(offset),
                 meta,
             },
-            handle,
+            vtable,
         )
     }
 
-    unsafe fn interpret_component_data_mut(data: Data, handle: &Handle) -> Self::Mut
+    unsafe fn interpret_component_data_mut(data: Data, vtable: DynMetadata<dyn Component>) -> Self::Mut
     where
         Self: Sized;
 
+    /// Like [`Sink::coerce_component_data`] for a table that may lack the
+    /// component, `column` holding its offset and the vtable to read it
+    /// through if it does not. Only optional sinks yield something for a
+    /// missing component.
+    unsafe fn try_coerce_component_data(
+        entity: Entity,
+        column: Option<(usize, DynMetadata<dyn Component>)>,
+        meta: Meta,
+    ) -> Option<Self::Ref>
+    where
+        Self: Sized,
+    {
+        let (offset, vtable) = column?;
+        Some(unsafe { Self::coerce_component_data(entity, offset, meta, vtable) })
+    }
+
+    unsafe fn try_coerce_component_data_mut(
+        entity: Entity,
+        column: Option<(usize, DynMetadata<dyn Component>)>,
+        meta: Meta,
+    ) -> Option<Self::Mut>
+    where
+        Self: Sized,
+    {
+        let (offset, vtable) = column?;
+        Some(unsafe { Self::coerce_component_data_mut(entity, offset, meta, vtable) })
+    }
+
     fn meta() -> Vec<Meta>
     where
         Self: Sized;
+
+    /// The trait object the sink reads components as, see
+    /// [`Access::trait_id`].
+    fn trait_id() -> Option<TypeId>
+    where
+        Self: Sized,
+    {
+        None
+    }
+}
+
+/// The component if the entity has it. Tables lacking it still match.
+impl<S: Sink> Sink for Option<S> {
+    type Ref = Option<S::Ref>;
+    type Mut = Option<S::Mut>;
+    const MUTABLE: bool = S::MUTABLE;
+    const OPTIONAL: bool = true;
+
+    unsafe fn interpret_component_data(data: Data, vtable: DynMetadata<dyn Component>) -> Self::Ref {
+        Some(unsafe { S::interpret_component_data(data, vtable) })
+    }
+    unsafe fn interpret_component_data_mut(data: Data, vtable: DynMetadata<dyn Component>) -> Self::Mut {
+        Some(unsafe { S::interpret_component_data_mut(data, vtable) })
+    }
+    unsafe fn try_coerce_component_data(
+        entity: Entity,
+        column: Option<(usize, DynMetadata<dyn Component>)>,
+        meta: Meta,
+    ) -> Option<Self::Ref> {
+        Some(column.map(|(offset, vtable)| unsafe {
+            S::coerce_component_data(entity, offset, meta, vtable)
+        }))
+    }
+    unsafe fn try_coerce_component_data_mut(
+        entity: Entity,
+        column: Option<(usize, DynMetadata<dyn Component>)>,
+        meta: Meta,
+    ) -> Option<Self::Mut> {
+        Some(column.map(|(offset, vtable)| unsafe {
+            S::coerce_component_data_mut(entity, offset, meta, vtable)
+        }))
+    }
+    fn meta() -> Vec<Meta> {
+        S::meta()
+    }
+    fn trait_id() -> Option<TypeId> {
+        S::trait_id()
+    }
 }
 
 impl<'a, T: Access + ?Sized + 'a> Sink for &'a mut T {
     type Mut = &'a mut T;
     type Ref = &'a T;
+    const MUTABLE: bool = true;
 
-    unsafe fn interpret_component_data(data: Data, handle: &Handle) -> Self::Ref {
-        T::access(data.ptr, handle.vtable())
+    unsafe fn interpret_component_data(data: Data, vtable: DynMetadata<dyn Component>) -> Self::Ref {
+        T::access(data.ptr, vtable)
     }
-    unsafe fn interpret_component_data_mut(data: Data, handle: &Handle) -> Self::Mut {
-        T::access(data.ptr, handle.vtable())
+    unsafe fn interpret_component_data_mut(data: Data, vtable: DynMetadata<dyn Component>) -> Self::Mut {
+        T::access(data.ptr, vtable)
     }
     fn meta() -> Vec<Meta>
     where
@@ -73,17 +156,20 @@ impl<'a, T: Access + ?Sized + 'a> Sink for &'a mut T {
     {
         T::meta()
     }
+    fn trait_id() -> Option<TypeId> {
+        T::trait_id()
+    }
 }
 
 impl<'a, T: Access + ?Sized + 'a> Sink for &'a T {
     type Mut = &'a T;
     type Ref = &'a T;
 
-    unsafe fn interpret_component_data(data: Data, handle: &Handle) -> Self::Ref {
-        T::access(data.ptr, handle.vtable())
+    unsafe fn interpret_component_data(data: Data, vtable: DynMetadata<dyn Component>) -> Self::Ref {
+        T::access(data.ptr, vtable)
     }
-    unsafe fn interpret_component_data_mut(data: Data, handle: &Handle) -> Self::Mut {
-        T::access(data.ptr, handle.vtable())
+    unsafe fn interpret_component_data_mut(data: Data, vtable: DynMetadata<dyn Component>) -> Self::Mut {
+        T::access(data.ptr, vtable)
     }
     fn meta() -> Vec<Meta>
     where
@@ -91,4 +177,7 @@ impl<'a, T: Access + ?Sized + 'a> Sink for &'a T {
     {
         T::meta()
     }
+    fn trait_id() -> Option<TypeId> {
+        T::trait_id()
+    }
 }
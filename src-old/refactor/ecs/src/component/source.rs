@@ -7,7 +7,6 @@ use super::{
 };
 use base::array;
 use base::collections::array::Array;
-use base::prelude::W;
 use base::rng::transform::DistributionTransform;
 
 pub trait Source: 'static {
@@ -1,6 +1,7 @@
-use super::{Component, Handle, Meta, archetype::Archetype};
+use super::{Component, Handle, Id, Meta, archetype::Archetype};
 use crate::component::source::Source;
 use crate::entity::Entity;
+use crate::world::WorldId;
 use base::array;
 use base::collections::{array::Array, arrayvec::ArrayVec};
 use base::rng::transform::DistributionTransform;
@@ -9,7 +10,6 @@ use std::fmt::{Formatter, format};
 use std::{
     alloc,
     cell::UnsafeCell,
-    collections::HashMap,
     iter,
     mem::{self, transmute},
     ptr, slice,
@@ -54,8 +54,23 @@ impl<C: Component> Erase for C {
 
 pub type Components<'a> = Vec<(Handle, Data)>;
 
+/// Counts schedule runs. Every column of a row remembers the tick it was last
+/// written at, see [`crate::query::filter::Changed`].
+pub type Tick = u64;
+
+/// An entity moved to another row of its page to keep the page packed. The
+/// handle it had goes stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Moved {
+    pub from: Entity,
+    pub to: Entity,
+}
+
 pub struct Table {
     archetype: Archetype,
+    world: WorldId,
+    /// The tick of the run the table was last handed out for.
+    pub(crate) tick: Tick,
     pub(crate) pages: UnsafeCell<Vec<Page>>,
 }
 
@@ -75,20 +90,70 @@ impl fmt::Debug for Table {
 
 pub struct Page {
     head: *mut u8,
+    world: WorldId,
     capacity: usize,
     state: UnsafeCell<State>,
 }
 
+/// Live rows are packed at the front of the page.
 #[derive(Debug)]
 pub struct State {
+    len: usize,
     erased: Vec<Option<Array<Handle, { Archetype::MAX }>>>,
-    freed: Vec<Entity>,
+    /// Bumped whenever a row changes hands, so handles to what was there
+    /// before no longer match.
+    generations: Vec<usize>,
+    /// Last written tick of every row, by column.
+    ticks: Vec<Vec<Tick>>,
 }
 
 impl Table {
-    pub fn with_archetype(archetype: Archetype) -> Self {
+    pub fn with_archetype(archetype: Archetype, world: WorldId) -> Self {
         let pages = UnsafeCell::new(vec![]);
-        Self { archetype, pages }
+        Self {
+            archetype,
+            world,
+            tick: 0,
+            pages,
+        }
+    }
+
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+
+    pub fn archetype(&self) -> &Archetype {
+        &self.archetype
+    }
+
+    /// The page holding row `idx` and the row's index within it.
+    fn page_of(&self, mut idx: usize) -> Option<(&Page, usize)> {
+        let page = self.pages().find(|page| {
+            let count = page.count();
+            let chosen = idx < count;
+            if !chosen {
+                idx -= count;
+            }
+            chosen
+        })?;
+        Some((page, idx))
+    }
+
+    /// Marks component `id` of row `idx` as written in the current tick.
+    pub fn touch(&self, idx: usize, id: Id) {
+        let Some(column) = self.archetype.iter().position(|meta| meta.id == id) else {
+            return;
+        };
+        if let Some((page, idx)) = self.page_of(idx) {
+            page.state().ticks[column][idx] = self.tick;
+        }
+    }
+
+    /// The tick component `id` of row `idx` was last written at.
+    pub fn changed(&self, idx: usize, id: Id) -> Option<Tick> {
+        let column = self.archetype.iter().position(|meta| meta.id == id)?;
+        let (page, idx) = self.page_of(idx)?;
+        Some(page.state().ticks[column][idx])
     }
 
     fn pages(&self) -> impl Iterator<Item = &Page> {
@@ -155,16 +220,32 @@ impl Table {
             let Some(components) = data.next() else {
                 break;
             };
-            let entity = next_page.insert(components).unwrap();
+            let entity = next_page.insert(components, self.tick).unwrap();
             entities.push(entity);
         }
         entities.into_iter()
     }
 
+    /// Adds pages until `additional` more rows fit without allocating.
+    pub fn reserve(&self, additional: usize) {
+        let pages = unsafe { self.pages.get().as_mut().unwrap() };
+        let free = pages
+            .iter()
+            .map(|page| page.capacity - page.count())
+            .sum::<usize>();
+        let missing = additional
+            .saturating_sub(free)
+            .div_ceil(Page::capacity(&self.archetype));
+        pages.reserve(missing);
+        pages.extend(
+            iter::repeat_with(|| Page::new(self.archetype.clone(), self.world)).take(missing),
+        );
+    }
+
     pub fn next_page_index(&self) -> usize {
         let pages = unsafe { self.pages.get().as_mut().unwrap() };
         if pages.is_empty() || pages.last().unwrap().is_full() {
-            pages.push(Page::new(self.archetype.clone()));
+            pages.push(Page::new(self.archetype.clone(), self.world));
             return pages.len() - 1;
         }
         for (i, page) in pages.iter().enumerate() {
@@ -175,32 +256,51 @@ impl Table {
         unreachable!("No available pages?");
     }
 
-    pub fn free(&self, entities: Vec<Entity>) {
-        type Head = *mut u8;
-        let mut page_head = HashMap::<Head, Vec<Entity>>::default();
-
-        entities
-            .into_iter()
-            .for_each(|entity| page_head.entry(entity.head()).or_default().push(entity));
+    /// Whether `entity` still has its row, i.e. was not taken and its handle
+    /// is not stale.
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.pages()
+            .find(|page| page.head == entity.head())
+            .is_some_and(|page| page.contains(entity))
+    }
 
-        let mut pages = unsafe { self.pages.get().as_mut().unwrap() };
+    /// Releases the row of `entity` without dropping its components, see
+    /// [`Page::take`].
+    pub fn take(
+        &self,
+        entity: Entity,
+    ) -> Option<(Array<Handle, { Archetype::MAX }>, Option<Moved>)> {
+        self.pages()
+            .find(|page| page.head == entity.head())?
+            .take(entity)
+    }
 
-        for (page_head, entities) in page_head {
-            let page = pages
-                .iter_mut()
-                .find(|page| page.head == page_head)
-                .unwrap();
+    pub fn count(&self) -> usize {
+        self.pages().map(Page::count).sum()
+    }
 
-            page.free(entities);
+    /// Component `column` of `entity`, in its row.
+    pub(crate) fn component(&self, entity: Entity, column: usize) -> Option<&mut dyn Component> {
+        let page = self.pages().find(|page| page.head == entity.head())?;
+        if !page.contains(entity) {
+            return None;
         }
+        let vtable = page.handle(entity.index(), column).vtable();
+        let data = page.row_column(&entity, column);
+        Some(unsafe { &mut *ptr::from_raw_parts_mut::<dyn Component>(data, vtable) })
     }
-    pub fn count(&self) -> usize {
-        let pages = unsafe { self.pages.get().as_mut().unwrap() };
-        match pages.len() {
-            0 => 0,
-            1 => dbg!(pages.first().unwrap().count()),
-            x => (x - 1) * Page::capacity(&self.archetype) + pages.last().as_ref().unwrap().count(),
+
+    /// The row of `entity` counted over every page, as queries count them,
+    /// if it lives in the table.
+    pub fn row(&self, entity: Entity) -> Option<usize> {
+        let mut row = 0;
+        for page in self.pages() {
+            if page.head == entity.head() {
+                return page.contains(entity).then(|| row + entity.index());
+            }
+            row += page.count();
         }
+        None
     }
 }
 
@@ -218,18 +318,28 @@ impl fmt::Debug for Page {
             )
             .field("components", &self.count())
             .field("capacity", &Self::capacity(&self.archetype()))
-            .field("freed", &self.state().freed.len())
+            .field("free", &(Self::capacity(self.archetype()) - self.count()))
             .finish()
     }
 }
 
 impl Page {
     pub const SIZE: usize = 2usize.pow(14);
-    pub const AVAIL: usize = Page::SIZE - mem::size_of::<Archetype>();
-
-    pub fn new(archetype: Archetype) -> Self {
+    /// Most a component may be aligned to.
+    pub const ALIGN: usize = 64;
+    /// Where the rows start, after the archetype and aligned for any
+    /// component.
+    pub const HEADER: usize = mem::size_of::<Archetype>().next_multiple_of(Page::ALIGN);
+    pub const AVAIL: usize = Page::SIZE - Page::HEADER;
+
+    pub fn new(archetype: Archetype, world: WorldId) -> Self {
+        assert!(
+            archetype.align() <= Page::ALIGN,
+            "components may be aligned to at most {} bytes",
+            Page::ALIGN
+        );
         let capacity = Self::capacity(&archetype);
-        let row_size = archetype.size().max(1);
+        let columns = archetype.count();
         let layout = alloc::Layout::from_size_align(Page::SIZE, Page::SIZE).unwrap();
         let mut head = unsafe { alloc::alloc(layout) };
         unsafe { head.cast::<Archetype>().write(archetype) };
@@ -237,11 +347,8 @@ impl Page {
             Self {
                 capacity,
                 head,
-                state: UnsafeCell::new(State::init(
-                    head.add(mem::size_of::<Archetype>()),
-                    capacity,
-                    row_size,
-                )),
+                world,
+                state: UnsafeCell::new(State::init(capacity, columns)),
             }
         }
     }
@@ -252,7 +359,7 @@ impl Page {
     }
 
     pub fn count(&self) -> usize {
-        (Self::capacity(self.archetype())) - (self.state().freed.len())
+        self.state().len
     }
 
     pub fn state(&self) -> &mut State {
@@ -260,23 +367,43 @@ impl Page {
     }
 
     pub fn is_full(&self) -> bool {
-        self.state().freed.is_empty() && self.count() == self.capacity
+        self.count() == self.capacity
+    }
+
+    fn row_size(&self) -> usize {
+        self.archetype().size().max(1)
     }
 
     pub fn entity(&self, index: usize) -> Entity {
-        let row_size = self.archetype().size();
-        let offset = index * row_size;
+        let offset = index * self.row_size();
         let ptr = unsafe { self.entity_head().add(offset) };
-        Entity::new(ptr)
+        Entity {
+            generation: self.state().generations[index],
+            ..Entity::new(ptr, self.world)
+        }
+    }
+
+    /// Whether `entity` is the one living in its row.
+    pub fn contains(&self, entity: Entity) -> bool {
+        let row = entity.index();
+        row < self.count() && self.state().generations[row] == entity.generation
     }
 
     pub fn handle(&self, index: usize, component: usize) -> &Handle {
         &self.state().erased[(index)].as_ref().unwrap()[component]
     }
 
-    pub fn insert(&self, components: Components<'static>) -> Option<Entity> {
-        let entity = self.state().freed.pop()?;
-        self.state().erased[entity.index()] = Some(Array::new());
+    pub fn insert(&self, components: Components<'static>, tick: Tick) -> Option<Entity> {
+        if self.is_full() {
+            return None;
+        }
+        let row = self.count();
+        self.state().len += 1;
+        let entity = self.entity(row);
+        self.state().erased[row] = Some(Array::new());
+        for ticks in &mut self.state().ticks {
+            ticks[row] = tick;
+        }
         let archetype = self.archetype();
         for (i, ((_handle, mut erased), meta)) in
             components.into_iter().zip(archetype.iter()).enumerate()
@@ -291,30 +418,45 @@ impl Page {
         Some(entity)
     }
 
-    pub fn free(&self, entities: impl IntoIterator<Item = Entity>) {
-        for entity in entities {
-            self.coalese_row(&entity);
-            self.state().erased[entity.index()] = None;
-            self.state().freed.push(entity.incr_gen());
+    /// Frees the row of `entity` and hands its component handles to the
+    /// caller, holding the values last written to the row. The last row of
+    /// the page moves into the gap, which is returned unless it was the row
+    /// itself.
+    pub fn take(
+        &self,
+        entity: Entity,
+    ) -> Option<(Array<Handle, { Archetype::MAX }>, Option<Moved>)> {
+        if !self.contains(entity) {
+            return None;
+        }
+        let (row, last) = (entity.index(), self.count() - 1);
+        let state = self.state();
+        let mut handles = state.erased[row].take()?;
+        // Queries write to the row, so its values go back into the handles
+        for (column, (handle, meta)) in handles.iter_mut().zip(self.archetype().iter()).enumerate()
+        {
+            let src = self.row_column(&entity, column);
+            unsafe { ptr::copy_nonoverlapping(src, handle.as_mut_ptr() as *mut u8, meta.size) };
+        }
+        state.generations[row] += 1;
+        state.len = last;
+        if row == last {
+            return Some((handles, None));
         }
-    }
 
-    pub fn entity_head(&self) -> *mut u8 {
-        unsafe { self.head.add(mem::size_of::<Archetype>()) }
+        let from = self.entity(last);
+        unsafe { ptr::copy_nonoverlapping(from.data(), entity.data(), self.row_size()) };
+        state.erased[row] = state.erased[last].take();
+        for ticks in &mut state.ticks {
+            ticks[row] = ticks[last];
+        }
+        state.generations[last] += 1;
+        let to = self.entity(row);
+        Some((handles, Some(Moved { from, to })))
     }
 
-    pub fn coalese_row(&self, entity: &Entity) {
-        let idx = entity.index();
-        for (i, meta) in self.archetype().iter().enumerate() {
-            let Some(erased) = &mut self.state().erased[idx] else {
-                unreachable!();
-            };
-            let mut data = Data {
-                ptr: self.row_column(entity, i),
-                meta: *meta,
-            };
-            data.copy_from(todo!(), meta);
-        }
+    pub fn entity_head(&self) -> *mut u8 {
+        unsafe { self.head.add(Page::HEADER) }
     }
 
     pub fn row_column(&self, entity: &Entity, index: usize) -> *mut u8 {
@@ -326,18 +468,42 @@ impl Page {
     }
 
     fn can_insert(&self) -> bool {
-        self.state().freed.len() > 0 && self.count() + 1 <= self.capacity
+        !self.is_full()
+    }
+}
+
+impl Drop for Page {
+    fn drop(&mut self) {
+        // Queries write to the rows, so their values go back into the handles
+        // that drop them
+        for row in 0..self.count() {
+            let entity = self.entity(row);
+            let Some(handles) = self.state().erased[row].as_mut() else {
+                continue;
+            };
+            for (column, (handle, meta)) in handles.iter_mut().zip(self.archetype().iter()).enumerate()
+            {
+                let src = self.row_column(&entity, column);
+                unsafe { ptr::copy_nonoverlapping(src, handle.as_mut_ptr() as *mut u8, meta.size) };
+            }
+        }
+        let layout = alloc::Layout::from_size_align(Page::SIZE, Page::SIZE).unwrap();
+        unsafe {
+            ptr::drop_in_place(self.head.cast::<Archetype>());
+            alloc::dealloc(self.head, layout);
+        }
     }
 }
 
 impl State {
-    fn init(entity_head: *mut u8, capacity: usize, row_size: usize) -> Self {
-        let freed = (0..capacity)
-            .map(|i| unsafe { entity_head.add(i * row_size) })
-            .map(Entity::new)
-            .rev()
-            .collect::<Vec<_>>();
+    fn init(capacity: usize, columns: usize) -> Self {
         let erased = iter::repeat_with(|| None).take(capacity).collect();
-        Self { freed, erased }
+        let ticks = vec![vec![0; capacity]; columns];
+        Self {
+            len: 0,
+            erased,
+            generations: vec![0; capacity],
+            ticks,
+        }
     }
 }
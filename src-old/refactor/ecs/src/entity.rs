@@ -1,21 +1,28 @@
 use crate::component::source::Source;
 use crate::component::{archetype::Archetype, table::Page};
-use std::mem;
+use crate::world::WorldId;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Entity {
     pub(crate) data: *mut u8,
     pub(crate) generation: usize,
+    pub(crate) world: WorldId,
 }
 
 impl Entity {
-    pub(crate) fn new(data: *mut u8) -> Self {
+    pub(crate) fn new(data: *mut u8, world: WorldId) -> Self {
         Self {
             data,
             generation: 0,
+            world,
         }
     }
 
+    /// The world this entity was spawned in.
+    pub fn world(&self) -> WorldId {
+        self.world
+    }
+
     pub(crate) fn archetype(&self) -> &Archetype {
         unsafe { self.head().cast::<Archetype>().as_ref().unwrap() }
     }
@@ -33,13 +40,6 @@ impl Entity {
     pub(crate) fn index(&self) -> usize {
         let data = self.data as usize;
         let head = self.head() as usize;
-        (data - (head + mem::size_of::<Archetype>())) / self.archetype().size().max(1)
-    }
-
-    pub(crate) fn incr_gen(self) -> Self {
-        Self {
-            generation: self.generation + 1,
-            data: self.data,
-        }
+        (data - (head + Page::HEADER)) / self.archetype().size().max(1)
     }
 }
@@ -0,0 +1,257 @@
+//! Messages passed between systems through the [`Events`] resource, written
+//! with [`EventWriter`] and read with [`EventReader`].
+
+use std::{cmp::max, collections::HashMap, mem, sync::Mutex};
+
+use crate::{
+    component::{archetype::Archetype, table::Table},
+    resource::Res,
+    system::{
+        func::Id,
+        param::{Borrow, Context, Param},
+    },
+};
+
+/// The events of type `T`, added with [`World::add_event`](crate::world::World::add_event).
+///
+/// Events sent during a frame become readable once it ends, and stay so for
+/// two frames before they are dropped. Every reader keeps its own cursor, so
+/// each sees every event once, starting with those sent after its first read.
+pub struct Events<T> {
+    /// Readable events of the frame before last, then of the last frame.
+    previous: Vec<T>,
+    current: Vec<T>,
+    /// The id of the first event in `previous`, ids count every event ever
+    /// made readable.
+    start: usize,
+    /// Events sent this frame.
+    sent: Mutex<Vec<T>>,
+    /// The id of the next event each reader reads, by system.
+    cursors: Mutex<HashMap<Id, usize>>,
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Self {
+        Self {
+            previous: vec![],
+            current: vec![],
+            start: 0,
+            sent: Default::default(),
+            cursors: Default::default(),
+        }
+    }
+}
+
+impl<T> Events<T> {
+    /// Queues `event` for readers, from the next frame on.
+    pub fn send(&self, event: T) {
+        self.sent.lock().unwrap().push(event);
+    }
+
+    /// Ends a frame: drops the events of the frame before last and makes the
+    /// ones sent since the last update readable.
+    pub fn update(&mut self) {
+        self.start += self.previous.len();
+        let sent = mem::take(self.sent.get_mut().unwrap());
+        self.previous = mem::replace(&mut self.current, sent);
+    }
+
+    /// The number of readable events.
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The events `reader` has not read yet, moving its cursor past them.
+    fn read(&self, reader: Id) -> impl Iterator<Item = &T> {
+        let end = self.start + self.len();
+        let mut cursors = self.cursors.lock().unwrap();
+        let cursor = cursors.entry(reader).or_insert(end);
+        let from = max(*cursor, self.start) - self.start;
+        *cursor = end;
+        self.previous.iter().chain(&self.current).skip(from)
+    }
+}
+
+/// Sends events of type `T` from a system. Writers only share the
+/// [`Events<T>`] resource, so they run alongside each other and readers.
+pub struct EventWriter<T: Send + Sync + 'static>(Res<Events<T>>);
+
+impl<T: Send + Sync + 'static> EventWriter<T> {
+    pub fn send(&self, event: T) {
+        self.0.send(event);
+    }
+}
+
+/// Reads the events of type `T` sent before the current frame, each once.
+pub struct EventReader<T: Send + Sync + 'static> {
+    events: Res<Events<T>>,
+    reader: Id,
+}
+
+impl<T: Send + Sync + 'static> EventReader<T> {
+    /// The events this system has not read yet.
+    pub fn read(&mut self) -> impl Iterator<Item = &T> {
+        self.events.read(self.reader)
+    }
+}
+
+impl<'a, T: Send + Sync + 'static> Param<'a> for EventWriter<T> {
+    fn inject(_: &mut Vec<Archetype>) {}
+
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        Res::<Events<T>>::borrows(borrows);
+    }
+
+    fn create(
+        archetypes: &'a [Archetype],
+        tables: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
+    where
+        Self: Sized + 'a,
+    {
+        EventWriter(Res::create(archetypes, tables, context))
+    }
+}
+
+impl<'a, T: Send + Sync + 'static> Param<'a> for EventReader<T> {
+    fn inject(_: &mut Vec<Archetype>) {}
+
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        Res::<Events<T>>::borrows(borrows);
+    }
+
+    fn create(
+        archetypes: &'a [Archetype],
+        tables: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
+    where
+        Self: Sized + 'a,
+    {
+        EventReader {
+            events: Res::create(archetypes, tables, context),
+            reader: context.system,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        params, resource::ResMut, schedule::Schedule, system::param::Params, world::World,
+    };
+    use base::rt::block_on;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Hit(u32);
+
+    struct Seen(Vec<u32>);
+
+    fn read<P: Params<'static>>(
+        world: &mut World,
+        reader: impl FnOnce(P) -> Vec<u32>,
+    ) -> Vec<u32> {
+        reader(params::<P>(world))
+    }
+
+    fn first((mut hits,): (EventReader<Hit>,)) -> Vec<u32> {
+        hits.read().map(|hit| hit.0).collect()
+    }
+
+    fn second((mut hits, _): (EventReader<Hit>, EventWriter<Hit>)) -> Vec<u32> {
+        hits.read().map(|hit| hit.0).collect()
+    }
+
+    fn send(world: &mut World, hits: impl IntoIterator<Item = u32>) {
+        let (writer,) = params::<(EventWriter<Hit>,)>(world);
+        hits.into_iter().for_each(|hit| writer.send(Hit(hit)));
+    }
+
+    fn update(world: &mut World) {
+        world.resource_mut::<Events<Hit>>().unwrap().update();
+    }
+
+    #[test]
+    fn test_every_reader_sees_every_event_once() {
+        let mut world = World::new();
+        world.add_event::<Hit>();
+        assert!(read(&mut world, first).is_empty());
+        assert!(read(&mut world, second).is_empty());
+
+        send(&mut world, [1, 2]);
+        assert!(read(&mut world, first).is_empty());
+        update(&mut world);
+        send(&mut world, [3]);
+        assert_eq!(read(&mut world, first), [1, 2]);
+        assert!(read(&mut world, first).is_empty());
+
+        update(&mut world);
+        assert_eq!(read(&mut world, first), [3]);
+        assert_eq!(read(&mut world, second), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_late_reader_sees_only_new_events() {
+        let mut world = World::new();
+        world.add_event::<Hit>();
+        send(&mut world, [1]);
+        update(&mut world);
+        assert!(read(&mut world, first).is_empty());
+
+        send(&mut world, [2]);
+        update(&mut world);
+        assert_eq!(read(&mut world, first), [2]);
+    }
+
+    #[test]
+    fn test_events_drop_after_two_updates() {
+        let mut world = World::new();
+        world.add_event::<Hit>();
+        assert!(read(&mut world, first).is_empty());
+        send(&mut world, [1, 2]);
+        update(&mut world);
+        update(&mut world);
+        assert_eq!(world.resource::<Events<Hit>>().unwrap().len(), 2);
+
+        update(&mut world);
+        assert!(world.resource::<Events<Hit>>().unwrap().is_empty());
+        assert!(read(&mut world, first).is_empty());
+    }
+
+    fn shoot(hits: EventWriter<Hit>) {
+        (0..100).for_each(|hit| hits.send(Hit(hit)));
+    }
+
+    fn shoot_more(hits: EventWriter<Hit>) {
+        (100..200).for_each(|hit| hits.send(Hit(hit)));
+    }
+
+    fn count(mut hits: EventReader<Hit>, mut seen: ResMut<Seen>) {
+        seen.0.extend(hits.read().map(|hit| hit.0));
+    }
+
+    #[test]
+    fn test_concurrent_writers_lose_no_events() {
+        crate::runtime();
+        let mut world = World::new();
+        world.add_event::<Hit>();
+        world.insert_resource(Seen(vec![]));
+        let mut schedule = Schedule::default()
+            .schedule(shoot)
+            .schedule(shoot_more)
+            .schedule(count);
+        block_on(schedule.run(&mut world)).unwrap();
+        assert!(world.resource::<Seen>().unwrap().0.is_empty());
+
+        block_on(schedule.run(&mut world)).unwrap();
+        let mut seen = world.resource::<Seen>().unwrap().0.clone();
+        seen.sort();
+        assert_eq!(seen, (0..200).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,485 @@
+//! Parent and child relationships between entities, made with
+//! [`Commands::set_parent`] or [`World::set_parent`], and [`propagate`] to
+//! pass values down the trees they form, e.g. transforms.
+//!
+//! The world keeps the [`Parent`] of a child and the [`Children`] of its
+//! parent in line, and points both at the rows entities move to. Taking
+//! either off by hand, e.g. with [`Commands::remove`], leaves the hierarchy
+//! as it was.
+//!
+//! [`Commands::set_parent`]: crate::command::Commands::set_parent
+//! [`Commands::remove`]: crate::command::Commands::remove
+//! [`World::set_parent`]: crate::world::World::set_parent
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error, fmt,
+    ops::Deref,
+};
+
+use smallvec::SmallVec;
+
+use crate::{
+    component::{Component, access::Access, component},
+    entity::Entity,
+    query::Query,
+};
+
+/// The entity this one is a child of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[component(crate = "crate")]
+pub struct Parent(pub(crate) Entity);
+
+impl Parent {
+    pub fn get(&self) -> Entity {
+        self.0
+    }
+}
+
+/// The children of an entity, in the order they were given to it. Taken
+/// off once the last one leaves. The first few are kept in the row.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[component(crate = "crate")]
+pub struct Children(pub(crate) SmallVec<[Entity; 4]>);
+
+impl Deref for Children {
+    type Target = [Entity];
+
+    fn deref(&self) -> &[Entity] {
+        &self.0
+    }
+}
+
+/// What happens to the children of an entity when it despawns, set with
+/// [`World::set_orphan_policy`](crate::world::World::set_orphan_policy).
+/// [`World::despawn_recursive`](crate::world::World::despawn_recursive)
+/// takes the descendants along either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OrphanPolicy {
+    /// The children lose their [`Parent`] and become roots, keeping their
+    /// own children.
+    #[default]
+    Detach,
+    /// The children despawn with their parent, and theirs with them.
+    Despawn,
+}
+
+/// Why a parent could not be set.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HierarchyError {
+    /// `parent` is `child` or descends from it.
+    Cycle { child: Entity, parent: Entity },
+}
+
+//SAFETY: entities are only dereferenced by the world, which checks they are alive
+unsafe impl Send for HierarchyError {}
+unsafe impl Sync for HierarchyError {}
+
+impl fmt::Display for HierarchyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cycle { child, parent } => {
+                write!(
+                    f,
+                    "making {parent:?} the parent of {child:?} would make a cycle"
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for HierarchyError {}
+
+/// A value passed down from parents to children by [`propagate`].
+pub trait Propagate: Access + Clone {
+    /// Combines the value of an entity with that of its parent, which is
+    /// already propagated, or with nothing for a root. A transform would
+    /// set its global part to the parent's global part times its own local
+    /// one.
+    fn propagate(&mut self, parent: Option<&Self>);
+}
+
+/// Propagates every `T`, roots first and then level by level down to the
+/// leaves, so each parent is done before its children. Entities without a
+/// `T` end the walk: their descendants are left as they are. Each entity is
+/// propagated to once, and roots only as roots, even if [`Children`] copied
+/// onto other entities list one twice or lead back up the tree.
+pub fn propagate<T: Component + Propagate>(
+    mut values: Query<'_, (&'_ mut T, Option<&'_ Children>, Option<&'_ Parent>)>,
+) {
+    let mut queue = VecDeque::new();
+    let mut visited = HashSet::new();
+    for (value, children, parent) in &mut values {
+        if parent.is_some() {
+            continue;
+        }
+        value.propagate(None);
+        for &child in children.into_iter().flat_map(|children| children.iter()) {
+            queue.push_back((child, value.clone()));
+        }
+    }
+    while let Some((entity, parent)) = queue.pop_front() {
+        if !visited.insert(entity) {
+            continue;
+        }
+        let Some((value, children, Some(_))) = values.get_mut(entity) else {
+            continue;
+        };
+        value.propagate(Some(&parent));
+        for &child in children.into_iter().flat_map(|children| children.iter()) {
+            queue.push_back((child, value.clone()));
+        }
+    }
+}
+
+/// The parents and children of the entities of a world, which its
+/// [`Parent`] and [`Children`] components are written from.
+#[derive(Default)]
+pub(crate) struct Hierarchy {
+    parents: HashMap<Entity, Entity>,
+    children: HashMap<Entity, Vec<Entity>>,
+    pub(crate) orphans: OrphanPolicy,
+}
+
+impl Hierarchy {
+    pub(crate) fn parent(&self, child: Entity) -> Option<Entity> {
+        self.parents.get(&child).copied()
+    }
+
+    pub(crate) fn children(&self, parent: Entity) -> &[Entity] {
+        self.children.get(&parent).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether `entity` is `ancestor` or descends from it.
+    pub(crate) fn descends(&self, mut entity: Entity, ancestor: Entity) -> bool {
+        loop {
+            if entity == ancestor {
+                return true;
+            }
+            match self.parent(entity) {
+                Some(parent) => entity = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Makes `parent` the parent of `child` and returns the one it had.
+    pub(crate) fn attach(&mut self, child: Entity, parent: Entity) -> Option<Entity> {
+        let previous = self.detach(child);
+        self.parents.insert(child, parent);
+        self.children.entry(parent).or_default().push(child);
+        previous
+    }
+
+    /// Takes `child` from its parent and returns it.
+    pub(crate) fn detach(&mut self, child: Entity) -> Option<Entity> {
+        let parent = self.parents.remove(&child)?;
+        if let Some(children) = self.children.get_mut(&parent) {
+            children.retain(|&sibling| sibling != child);
+            if children.is_empty() {
+                self.children.remove(&parent);
+            }
+        }
+        Some(parent)
+    }
+
+    /// `entities` followed by every descendant of theirs not among them,
+    /// each once and parents before children.
+    pub(crate) fn with_descendants(&self, entities: Vec<Entity>) -> Vec<Entity> {
+        let mut seen = entities.iter().copied().collect::<HashSet<_>>();
+        let mut all = entities;
+        let mut next = 0;
+        while next < all.len() {
+            for &child in self.children(all[next]) {
+                if seen.insert(child) {
+                    all.push(child);
+                }
+            }
+            next += 1;
+        }
+        all
+    }
+
+    /// Forgets the `dead`. Returns the living entities that lost their
+    /// parent or a child to them, whose components need to catch up.
+    pub(crate) fn despawn(&mut self, dead: &[Entity]) -> Vec<Entity> {
+        let set = dead.iter().copied().collect::<HashSet<_>>();
+        let mut survivors = vec![];
+        for &entity in dead {
+            if let Some(parent) = self.detach(entity) {
+                survivors.push(parent);
+            }
+            for child in self.children.remove(&entity).unwrap_or_default() {
+                self.parents.remove(&child);
+                survivors.push(child);
+            }
+        }
+        let mut seen = HashSet::new();
+        survivors.retain(|entity| !set.contains(entity) && seen.insert(*entity));
+        survivors
+    }
+
+    /// Points everything known about `from` at `to`, after the entity's row
+    /// was relocated.
+    pub(crate) fn relocate(&mut self, from: Entity, to: Entity) {
+        if let Some(parent) = self.parents.remove(&from) {
+            self.parents.insert(to, parent);
+            let siblings = self.children.get_mut(&parent).into_iter().flatten();
+            for sibling in siblings.filter(|sibling| **sibling == from) {
+                *sibling = to;
+            }
+        }
+        if let Some(children) = self.children.remove(&from) {
+            for child in &children {
+                self.parents.insert(*child, to);
+            }
+            self.children.insert(to, children);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command::{Commands, Reserved},
+        event::Events,
+        params,
+        schedule::Schedule,
+        world::World,
+    };
+    use base::rt::block_on;
+    use std::sync::Mutex;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Name(u32);
+
+    /// Sums the locals on the way down from the root, and records the name
+    /// of every entity it is propagated to in `VISITS`.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Offset {
+        name: u32,
+        local: f32,
+        global: f32,
+    }
+
+    static VISITS: Mutex<Vec<u32>> = Mutex::new(vec![]);
+
+    impl Propagate for Offset {
+        fn propagate(&mut self, parent: Option<&Self>) {
+            VISITS.lock().unwrap().push(self.name);
+            self.global = parent.map_or(0.0, |parent| parent.global) + self.local;
+        }
+    }
+
+    /// How far below its root an entity is.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Depth(u32);
+
+    impl Propagate for Depth {
+        fn propagate(&mut self, parent: Option<&Self>) {
+            self.0 = parent.map_or(0, |parent| parent.0 + 1);
+        }
+    }
+
+    component!(Name, Offset, Depth);
+
+    /// Spawns a root with two children, each with two of their own, and
+    /// returns the ids of all seven by name: 0 is the root, 1 and 2 its
+    /// children, 3 and 4 those of 1, 5 and 6 those of 2.
+    fn tree(world: &mut World) -> Vec<Reserved> {
+        // Leaves first, so no table holds a parent before its children
+        let entities = world.extend((0..7).rev().map(Name));
+        let ids = (0..7)
+            .rev()
+            .map(|i| world.reserve(entities[i]))
+            .collect::<Vec<_>>();
+        let (commands,) = params::<(Commands,)>(world);
+        for child in 1..7 {
+            commands.set_parent(ids[child], ids[(child - 1) / 2]);
+        }
+        world.sync();
+        ids
+    }
+
+    fn name(world: &World, entity: Entity) -> u32 {
+        world.get::<Name>(entity).unwrap().0
+    }
+
+    fn parent(world: &World, id: Reserved) -> Option<u32> {
+        let entity = world.spawned(id).unwrap();
+        world
+            .get::<Parent>(entity)
+            .map(|parent| name(world, parent.get()))
+    }
+
+    fn children(world: &World, id: Reserved) -> Vec<u32> {
+        let entity = world.spawned(id).unwrap();
+        world.get::<Children>(entity).map_or(vec![], |children| {
+            children.iter().map(|&child| name(world, child)).collect()
+        })
+    }
+
+    #[test]
+    fn test_three_levels() {
+        let mut world = World::new();
+        let ids = tree(&mut world);
+        assert_eq!(parent(&world, ids[0]), None);
+        assert_eq!(children(&world, ids[0]), [1, 2]);
+        assert_eq!(children(&world, ids[1]), [3, 4]);
+        assert_eq!(children(&world, ids[2]), [5, 6]);
+        for child in 1..7 {
+            assert_eq!(parent(&world, ids[child]), Some((child as u32 - 1) / 2));
+        }
+        assert!(children(&world, ids[6]).is_empty());
+
+        // Neither a descendant nor the child itself can be its parent
+        world.add_event::<HierarchyError>();
+        let (commands,) = params::<(Commands,)>(&mut world);
+        commands.set_parent(ids[0], ids[5]);
+        commands.set_parent(ids[1], ids[1]);
+        world.sync();
+        world.update_events();
+        assert_eq!(world.resource::<Events<HierarchyError>>().unwrap().len(), 2);
+        assert_eq!(parent(&world, ids[0]), None);
+        assert_eq!(parent(&world, ids[1]), Some(0));
+
+        let (root, leaf) = (
+            world.spawned(ids[0]).unwrap(),
+            world.spawned(ids[5]).unwrap(),
+        );
+        assert_eq!(
+            world.set_parent(root, leaf),
+            Err(HierarchyError::Cycle {
+                child: root,
+                parent: leaf
+            })
+        );
+    }
+
+    #[test]
+    fn test_reparent_subtree() {
+        let mut world = World::new();
+        let ids = tree(&mut world);
+        let (commands,) = params::<(Commands,)>(&mut world);
+        commands.set_parent(ids[1], ids[2]);
+        world.sync();
+        assert_eq!(children(&world, ids[0]), [2]);
+        assert_eq!(children(&world, ids[2]), [5, 6, 1]);
+        assert_eq!(parent(&world, ids[1]), Some(2));
+        // The subtree comes along
+        assert_eq!(children(&world, ids[1]), [3, 4]);
+        assert_eq!(parent(&world, ids[3]), Some(1));
+
+        // The root loses its Children with the last one
+        let (commands,) = params::<(Commands,)>(&mut world);
+        commands.remove_parent(ids[2]);
+        world.sync();
+        assert_eq!(parent(&world, ids[2]), None);
+        assert!(children(&world, ids[0]).is_empty());
+        assert_eq!(children(&world, ids[2]), [5, 6, 1]);
+    }
+
+    #[test]
+    fn test_despawn_recursive_counts() {
+        let mut world = World::new();
+        let ids = tree(&mut world);
+        world.extend([Name(7)]);
+        let middle = world.spawned(ids[1]).unwrap();
+        assert_eq!(world.despawn_recursive(middle), 3);
+        assert_eq!(world.entity_count(), 5);
+        assert!([1, 3, 4].iter().all(|&i| world.spawned(ids[i]).is_none()));
+        assert_eq!(children(&world, ids[0]), [2]);
+
+        // By default the children of a despawned entity become roots
+        world.despawn([world.spawned(ids[0]).unwrap()]);
+        assert_eq!(world.entity_count(), 4);
+        assert_eq!(parent(&world, ids[2]), None);
+        assert_eq!(children(&world, ids[2]), [5, 6]);
+
+        // Or they go along, with their own
+        world.set_orphan_policy(OrphanPolicy::Despawn);
+        let (commands,) = params::<(Commands,)>(&mut world);
+        commands.despawn(ids[2]);
+        world.sync();
+        assert_eq!(world.entity_count(), 1);
+
+        world.set_orphan_policy(OrphanPolicy::Detach);
+        let ids = tree(&mut world);
+        let (commands,) = params::<(Commands,)>(&mut world);
+        commands.despawn_recursive(ids[2]);
+        world.sync();
+        assert_eq!(world.entity_count(), 5);
+        assert_eq!(children(&world, ids[0]), [1]);
+        assert_eq!(world.despawn_filtered::<()>(), 5);
+    }
+
+    #[test]
+    fn test_propagation_order() {
+        crate::runtime();
+        let mut world = World::new();
+        let ids = tree(&mut world);
+        // Adding offsets moves every entity of the tree
+        for (name, &id) in ids.iter().enumerate() {
+            let entity = world.spawned(id).unwrap();
+            world.insert_component(
+                entity,
+                Offset {
+                    name: name as u32,
+                    local: 10f32.powi(name as i32),
+                    global: f32::NAN,
+                },
+            );
+        }
+        world.extend([Offset {
+            name: 7,
+            local: 1.0,
+            global: f32::NAN,
+        }]);
+
+        let mut schedule = Schedule::default().schedule(propagate::<Offset>);
+        block_on(schedule.run(&mut world)).unwrap();
+        let global = |name: usize| {
+            let entity = world.spawned(ids[name]).unwrap();
+            world.get::<Offset>(entity).unwrap().global
+        };
+        assert_eq!(global(0), 1.0);
+        assert_eq!(global(2), 101.0);
+        assert_eq!(global(4), 10_011.0);
+        assert_eq!(global(6), 1_000_101.0);
+
+        // The roots first, then their children, then theirs
+        let visits = VISITS.lock().unwrap().clone();
+        assert_eq!(visits.len(), 8);
+        let level = |name: &u32| match name {
+            0 | 7 => 0,
+            1 | 2 => 1,
+            _ => 2,
+        };
+        assert!(visits.is_sorted_by_key(level), "{visits:?}");
+    }
+
+    #[test]
+    fn test_propagation_visits_once() {
+        crate::runtime();
+        let mut world = World::new();
+        let ids = tree(&mut world);
+        let entity = |world: &World, name: usize| world.spawned(ids[name]).unwrap();
+        for name in 0..7 {
+            world.insert_component(entity(&world, name), Depth(u32::MAX));
+        }
+        // Copied by hand, so nothing checked them: 3 leads back to the root,
+        // and 4 to 2, which the root lists as well
+        let root = entity(&world, 0);
+        let two = entity(&world, 2);
+        world.insert_component(entity(&world, 3), Children(SmallVec::from_slice(&[root])));
+        world.insert_component(entity(&world, 4), Children(SmallVec::from_slice(&[two])));
+
+        let mut schedule = Schedule::default().schedule(propagate::<Depth>);
+        block_on(schedule.run(&mut world)).unwrap();
+        let depths = (0..7)
+            .map(|name| world.get::<Depth>(entity(&world, name)).unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(depths, [0, 1, 1, 2, 2, 2, 2]);
+    }
+}
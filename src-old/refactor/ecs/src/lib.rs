@@ -7,11 +7,100 @@
     more_maybe_bounds,
     set_ptr_value
 )]
-#![feature(ptr_metadata)]
+#![feature(ptr_metadata, unsize)]
 
+/// Makes plain structs components in tests, without the attribute macro and
+/// the paths it expands to. `@data` leaves out the `Component` impl, for
+/// components with hooks.
+#[cfg(test)]
+macro_rules! component {
+    ($($name:ident),*) => {$(
+        impl $crate::component::Component for $name {
+            fn meta() -> $crate::component::Meta {
+                $crate::component::Meta::of::<Self>()
+            }
+        }
+
+        component!(@data $name);
+    )*};
+    (@data $($name:ident),*) => {$(
+        impl $crate::component::source::Source for $name {
+            type Table = dyn $crate::component::Component;
+            unsafe fn erase_component_data<'a>(self) -> $crate::component::table::Components<'a>
+            where
+                Self: 'a,
+            {
+                use $crate::component::{Component, Handle, table::Erase};
+                let (original, data) = Box::new(self).erase();
+                let original: Box<dyn Component> = original;
+                let vtable = std::ptr::metadata(&*original as *const dyn Component);
+                vec![(Handle(original, vtable), data)]
+            }
+            unsafe fn archetype(&self) -> $crate::component::archetype::Archetype {
+                $crate::component::archetype::Archetype::from($crate::component::Meta::of::<Self>())
+            }
+        }
+
+        impl $crate::component::access::Access for $name {
+            fn access<'a>(
+                ptr: *mut u8,
+                _: std::ptr::DynMetadata<dyn $crate::component::Component>,
+            ) -> &'a mut Self {
+                unsafe { &mut *(ptr as *mut Self) }
+            }
+            fn meta() -> Vec<$crate::component::Meta> {
+                vec![$crate::component::Meta::of::<Self>()]
+            }
+        }
+    )*};
+}
+
+pub mod command;
 pub mod component;
 pub mod entity;
+pub mod event;
+pub mod hierarchy;
+pub mod link;
 pub mod query;
+pub mod resource;
 pub mod schedule;
+pub mod snapshot;
 pub mod system;
 pub mod world;
+
+/// Hands `P` the tables it binds, as a schedule run would. Every `P` counts
+/// as a system of its own.
+#[cfg(test)]
+fn params<P: system::param::Params<'static>>(world: &mut world::World) -> P {
+    let shard = P::bind(&mut world.registry);
+    let (archetypes, tables): (Vec<_>, Vec<_>) = shard.table_vec().unwrap().drain(..).unzip();
+    P::create(
+        archetypes.leak(),
+        tables.leak(),
+        Box::leak(Box::new(world.context(system::func::Id(
+            std::any::TypeId::of::<P>(),
+        )))),
+    )
+}
+
+/// Starts one pool for every test, on a thread that joins it as a worker.
+/// Schedules spawn their systems on it.
+#[cfg(test)]
+fn runtime() {
+    static START: std::sync::Once = std::sync::Once::new();
+    START.call_once(|| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            base::rt::block_on(async move {
+                let start = base::rt::Runtime::new(base::prelude::Vector::splat(7))
+                    .workers(3)
+                    .signals(false)
+                    .start()
+                    .await;
+                tx.send(()).unwrap();
+                base::rt::worker::worker_start_barrier(start).await;
+            })
+        });
+        rx.recv().unwrap();
+    });
+}
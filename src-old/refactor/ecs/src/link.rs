@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::component::Id;
+use crate::entity::Entity;
+
+/// A component field pointing at another entity. Unlike a bare [`Entity`] it
+/// is tracked by the world, so it never silently dangles into a recycled slot
+/// once its target despawns.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Link(Option<Entity>);
+
+impl Link {
+    pub fn to(target: Entity) -> Self {
+        Self(Some(target))
+    }
+
+    pub fn get(&self) -> Option<Entity> {
+        self.0
+    }
+}
+
+/// Entities are addresses only meaningful to the running world, so a link is
+/// written as nothing. World snapshots record the targets of registered link
+/// fields themselves, see [`crate::snapshot`]; read back, a link is empty.
+impl Serialize for Link {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_unit()
+    }
+}
+
+impl<'de> Deserialize<'de> for Link {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <()>::deserialize(deserializer).map(|()| Self::default())
+    }
+}
+
+/// What happens to a link when its target despawns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LinkPolicy {
+    /// The link is reset to empty.
+    Clear,
+    /// The link is left alone and the owner gets a [`BrokenLink`] marker.
+    Mark,
+}
+
+/// Left on an owner whose [`LinkPolicy::Mark`] link outlived its target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BrokenLink {
+    pub component: Id,
+    pub offset: usize,
+    pub target: Entity,
+}
+
+/// Where a link lives: its owner, the component holding it and the byte
+/// offset of the field within that component.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LinkSite {
+    pub owner: Entity,
+    pub component: Id,
+    pub offset: usize,
+}
+
+/// Reverse index from link targets to the sites pointing at them.
+#[derive(Default)]
+pub struct EntityRefs {
+    fields: HashMap<Id, Vec<(usize, LinkPolicy)>>,
+    sites: HashMap<LinkSite, Entity>,
+    targets: HashMap<Entity, HashSet<LinkSite>>,
+    owners: HashMap<Entity, HashSet<LinkSite>>,
+    broken: HashMap<Entity, Vec<BrokenLink>>,
+}
+
+impl EntityRefs {
+    pub fn register(&mut self, component: Id, offset: usize, policy: LinkPolicy) {
+        let fields = self.fields.entry(component).or_default();
+        fields.retain(|(existing, _)| *existing != offset);
+        fields.push((offset, policy));
+    }
+
+    /// Link fields registered for `component` and their policies.
+    pub fn fields(&self, component: Id) -> &[(usize, LinkPolicy)] {
+        self.fields.get(&component).map_or(&[], Vec::as_slice)
+    }
+
+    /// Records what the link at `site` currently points at.
+    pub fn track(&mut self, site: LinkSite, link: Link) {
+        self.untrack(site);
+
+        let Some(target) = link.get() else {
+            self.unmark(site);
+            return;
+        };
+        // A marked link still holding its dead target stays marked and unindexed
+        if self.broken(site.owner).iter().any(|broken| {
+            (broken.component, broken.offset, broken.target) == (site.component, site.offset, target)
+        }) {
+            return;
+        }
+        self.unmark(site);
+
+        self.sites.insert(site, target);
+        self.targets.entry(target).or_default().insert(site);
+        self.owners.entry(site.owner).or_default().insert(site);
+    }
+
+    /// Drops every site owned by `owner`, e.g. before re-reading its links.
+    pub fn forget_owner(&mut self, owner: Entity) {
+        for site in self.owners.remove(&owner).unwrap_or_default() {
+            self.untrack(site);
+        }
+    }
+
+    /// Resolves links to entities that despawned. Returns the sites whose
+    /// link must be cleared; marked owners can be read back with [`broken`].
+    ///
+    /// [`broken`]: EntityRefs::broken
+    pub fn despawn(&mut self, dead: &[Entity]) -> Vec<LinkSite> {
+        // Links held by the dead themselves go away with them
+        for &entity in dead {
+            self.forget_owner(entity);
+            self.broken.remove(&entity);
+        }
+
+        let mut clear = vec![];
+        for &target in dead {
+            for site in self.targets.remove(&target).unwrap_or_default() {
+                self.untrack(site);
+                match self.policy(site) {
+                    LinkPolicy::Clear => clear.push(site),
+                    LinkPolicy::Mark => self.broken.entry(site.owner).or_default().push(BrokenLink {
+                        component: site.component,
+                        offset: site.offset,
+                        target,
+                    }),
+                }
+            }
+        }
+        clear
+    }
+
+    /// Moves everything known about `from` to `to` after the entity's row was
+    /// relocated, e.g. when it migrated to another archetype. Returns the sites
+    /// whose stored link still names `from` and must be rewritten to `to`.
+    pub fn relocate(&mut self, from: Entity, to: Entity) -> Vec<LinkSite> {
+        for site in self.owners.remove(&from).unwrap_or_default() {
+            let target = self.sites[&site];
+            self.untrack(site);
+            self.track(LinkSite { owner: to, ..site }, Link::to(target));
+        }
+        if let Some(broken) = self.broken.remove(&from) {
+            self.broken.insert(to, broken);
+        }
+
+        // Links pointing at the moved entity follow it too
+        let rewrite = self.targets.remove(&from).unwrap_or_default();
+        for &site in &rewrite {
+            self.sites.insert(site, to);
+            self.targets.entry(to).or_default().insert(site);
+        }
+        rewrite.into_iter().collect()
+    }
+
+    pub fn links_to(&self, target: Entity) -> impl Iterator<Item = LinkSite> + '_ {
+        self.targets.get(&target).into_iter().flatten().copied()
+    }
+
+    pub fn broken(&self, owner: Entity) -> &[BrokenLink] {
+        self.broken.get(&owner).map_or(&[], Vec::as_slice)
+    }
+
+    fn policy(&self, site: LinkSite) -> LinkPolicy {
+        self.fields(site.component)
+            .iter()
+            .find(|(offset, _)| *offset == site.offset)
+            .map_or(LinkPolicy::Clear, |(_, policy)| *policy)
+    }
+
+    fn untrack(&mut self, site: LinkSite) {
+        let Some(target) = self.sites.remove(&site) else {
+            return;
+        };
+        if let Some(sites) = self.targets.get_mut(&target) {
+            sites.remove(&site);
+            if sites.is_empty() {
+                self.targets.remove(&target);
+            }
+        }
+        if let Some(sites) = self.owners.get_mut(&site.owner) {
+            sites.remove(&site);
+            if sites.is_empty() {
+                self.owners.remove(&site.owner);
+            }
+        }
+    }
+
+    fn unmark(&mut self, site: LinkSite) {
+        if let Some(broken) = self.broken.get_mut(&site.owner) {
+            broken.retain(|broken| (broken.component, broken.offset) != (site.component, site.offset));
+            if broken.is_empty() {
+                self.broken.remove(&site.owner);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::WorldId;
+
+    struct Target;
+    struct Parent;
+
+    fn entity(addr: usize) -> Entity {
+        Entity {
+            data: addr as *mut u8,
+            generation: 0,
+            world: WorldId(0),
+        }
+    }
+
+    fn id<T: 'static>() -> Id {
+        Id(std::any::TypeId::of::<T>())
+    }
+
+    fn site<T: 'static>(owner: Entity) -> LinkSite {
+        LinkSite {
+            owner,
+            component: id::<T>(),
+            offset: 0,
+        }
+    }
+
+    fn refs() -> EntityRefs {
+        let mut refs = EntityRefs::default();
+        refs.register(id::<Target>(), 0, LinkPolicy::Clear);
+        refs.register(id::<Parent>(), 0, LinkPolicy::Mark);
+        refs
+    }
+
+    #[test]
+    fn test_despawn_clears_links() {
+        let (a, b, dead) = (entity(0x100), entity(0x200), entity(0x300));
+        let mut refs = refs();
+        refs.track(site::<Target>(a), Link::to(dead));
+        refs.track(site::<Target>(b), Link::to(dead));
+        assert_eq!(refs.links_to(dead).count(), 2);
+
+        let mut cleared = refs.despawn(&[dead]);
+        cleared.sort_by_key(|site| site.owner.data as usize);
+        assert_eq!(cleared, [site::<Target>(a), site::<Target>(b)]);
+        assert_eq!(refs.links_to(dead).count(), 0);
+        assert!(refs.broken(a).is_empty());
+    }
+
+    #[test]
+    fn test_mark_policy() {
+        let (child, parent) = (entity(0x100), entity(0x200));
+        let mut refs = refs();
+        refs.track(site::<Parent>(child), Link::to(parent));
+
+        assert!(refs.despawn(&[parent]).is_empty());
+        assert_eq!(
+            refs.broken(child),
+            [BrokenLink {
+                component: id::<Parent>(),
+                offset: 0,
+                target: parent,
+            }]
+        );
+
+        // Re-reading the untouched field keeps the marker, retargeting drops it
+        refs.track(site::<Parent>(child), Link::to(parent));
+        assert_eq!(refs.broken(child).len(), 1);
+        assert_eq!(refs.links_to(parent).count(), 0);
+        let adopted = entity(0x300);
+        refs.track(site::<Parent>(child), Link::to(adopted));
+        assert!(refs.broken(child).is_empty());
+        assert_eq!(refs.links_to(adopted).collect::<Vec<_>>(), [site::<Parent>(child)]);
+    }
+
+    #[test]
+    fn test_retarget_and_owner_despawn() {
+        let (owner, first, second) = (entity(0x100), entity(0x200), entity(0x300));
+        let mut refs = refs();
+        refs.track(site::<Target>(owner), Link::to(first));
+        refs.track(site::<Target>(owner), Link::to(second));
+        assert_eq!(refs.links_to(first).count(), 0);
+        assert_eq!(refs.links_to(second).count(), 1);
+
+        // Owner and target dying together leaves nothing to clear
+        assert!(refs.despawn(&[owner, second]).is_empty());
+        assert_eq!(refs.links_to(second).count(), 0);
+    }
+
+    #[test]
+    fn test_relocate_owner_and_target() {
+        let (owner, moved_owner) = (entity(0x100), entity(0x4100));
+        let (target, moved_target) = (entity(0x200), entity(0x4200));
+        let other = entity(0x300);
+        let mut refs = refs();
+        refs.track(site::<Target>(owner), Link::to(target));
+        refs.track(site::<Target>(other), Link::to(owner));
+
+        assert_eq!(refs.relocate(owner, moved_owner), [site::<Target>(other)]);
+        assert_eq!(refs.links_to(target).collect::<Vec<_>>(), [site::<Target>(moved_owner)]);
+        assert_eq!(refs.links_to(moved_owner).collect::<Vec<_>>(), [site::<Target>(other)]);
+        assert_eq!(refs.links_to(owner).count(), 0);
+
+        assert_eq!(refs.relocate(target, moved_target), [site::<Target>(moved_owner)]);
+        assert_eq!(refs.despawn(&[moved_target]), [site::<Target>(moved_owner)]);
+        assert_eq!(refs.links_to(moved_owner).count(), 1);
+    }
+}
@@ -1,36 +1,53 @@
-use crate::component::{sink::Sink, table::Table};
+use crate::component::{Component, Meta, sink::Sink, table::Table};
 use crate::{
-    component::{archetype::Archetype, registry::Shard},
+    component::{access::Traits, archetype::Archetype, registry::Shard},
+    entity::Entity,
+    system::param::Borrow,
     world::World,
 };
 use base::{
-    Simd,
     collections::{array::Array, arrayvec::ArrayVec},
-    prelude::{Pattern, Vector, X, Y, Z},
+    prelude::{Simd, Vector},
 };
+use std::any::TypeId;
 use std::cmp::max;
+use std::ptr::DynMetadata;
 use std::{marker::PhantomData, ops::AddAssign};
 
-pub trait Query: ?Sized {
+use super::filter::Filter;
+
+pub trait Query {
     type Ref;
     type Mut;
 
+    /// The components a table needs for the query to match it, optional
+    /// ones left out.
     fn archetype(index: usize) -> Option<Archetype>;
-    fn offsets(index: usize) -> Option<Array<usize, { Archetype::MAX }>>;
-    fn deduce(state: &mut State, fetcher: &Fetch<Self>) -> Option<Self::Ref>;
-    fn deduce_mut(state: &mut State, fetcher: &mut Fetch<Self>) -> Option<Self::Mut>;
+    /// The component of every element of the query, in order.
+    fn metas(index: usize) -> Option<Array<Meta, { Archetype::MAX }>>;
+    /// Whether every element is optional, and the trait object it reads
+    /// components as if any.
+    fn elements() -> Array<(bool, Option<TypeId>), { Archetype::MAX }>;
+    fn deduce<F: Filter>(state: &mut State, fetcher: &Fetch<Self, F>) -> Option<Self::Ref>;
+    /// Like [`Query::deduce`], marking every component borrowed mutably as
+    /// changed.
+    fn deduce_mut<F: Filter>(state: &mut State, fetcher: &mut Fetch<Self, F>) -> Option<Self::Mut>;
+    /// Records the components the query reads, and writes if mutable.
+    fn borrows(borrows: &mut Vec<Borrow>);
 }
 
 use paste::paste;
 ecs_macro::query!();
 
-pub struct Fetch<'a, Q: Query + ?Sized> {
+pub struct Fetch<'a, Q: Query + ?Sized, F: Filter = ()> {
     pub(crate) supertypes: &'a [Archetype],
     pub(crate) tables: &'a mut [&'a mut Table],
+    pub(crate) traits: &'a Traits,
     pub(crate) marker: PhantomData<Q>,
+    pub(crate) filter: PhantomData<F>,
 }
 
-unsafe impl<Q: Query> Send for Fetch<'_, Q> {}
+unsafe impl<Q: Query, F: Filter> Send for Fetch<'_, Q, F> {}
 
 #[derive(Default, Debug, Clone, Copy)]
 pub struct Cursor {
@@ -42,7 +59,10 @@ impl Cursor {
     fn init(tables: &[&mut Table]) -> Cursor {
         Self {
             route: Default::default(),
-            max: Vector(Simd([tables[0].count(), tables.len()])),
+            max: Vector(Simd([
+                tables.first().map_or(0, |table| table.count()),
+                tables.len(),
+            ])),
         }
     }
 
@@ -67,19 +87,28 @@ impl AddAssign<usize> for Cursor {
 }
 
 impl Cursor {
-    fn row_finished(&self) -> bool {
-        self.route[0] == self.max[0]
-    }
     fn table_finished(&self) -> bool {
-        self.route[1] == self.max[1]
+        self.route[1] >= self.max[1]
+    }
+    /// Starts on a table of `rows` rows.
+    fn enter(&mut self, rows: usize) {
+        self.max[0] = rows;
+    }
+    fn skip_table(&mut self) {
+        self.route[0] = 0;
+        self.route[1] += 1;
     }
 }
 
 #[derive(Clone)]
 pub struct State {
     index: usize,
-    offsets: Array<usize, 256>,
+    metas: Array<Meta, { Archetype::MAX }>,
     supertype: Archetype,
+    /// The column of every element in the current table, and the vtable to
+    /// read trait objects through. `None` where the table lacks an optional
+    /// element.
+    columns: Array<Option<(usize, Option<DynMetadata<dyn Component>>)>, { Archetype::MAX }>,
     cursor: Cursor,
 }
 
@@ -88,65 +117,146 @@ impl State {
         let index = old.map(|state| state.index + 1).unwrap_or(0);
         Some(Self {
             index,
-            offsets: Q::offsets(index)?,
+            metas: Q::metas(index)?,
             supertype: Q::archetype(index)?,
+            columns: Array::new(),
             cursor: Cursor::init(shard),
         })
     }
-    fn check<Q: Query>(&mut self, fetcher: &Fetch<Q>) -> bool {
-        if self.cursor.row_finished() {
-            if let Some(state) = State::init::<Q>(fetcher.tables, Some(self.clone())) {
-                *self = state;
+    /// A state at the row of `entity`, if it is alive in one of the tables
+    /// and matches the query.
+    pub(crate) fn locate<Q: Query, F: Filter>(
+        fetcher: &Fetch<Q, F>,
+        entity: Entity,
+    ) -> Option<State> {
+        let (index, row) = fetcher
+            .tables
+            .iter()
+            .enumerate()
+            .find_map(|(index, table)| Some((index, table.row(entity)?)))?;
+        let table = &fetcher.tables[index];
+        // Trait objects may match it through any combination of implementors
+        let mut state = State::init::<Q>(fetcher.tables, None);
+        while let Some(mut current) = state {
+            current.cursor.route = Vector(Simd([row, index]));
+            if current.enter::<Q, F>(table, fetcher.traits) && F::matches(table, row) {
+                return Some(current);
             }
+            state = State::init::<Q>(fetcher.tables, Some(current));
         }
-        if self.cursor.table_finished() {
-            return true;
+        None
+    }
+    /// Moves on to the next row passing `F`, true once there is none left.
+    fn check<Q: Query, F: Filter>(&mut self, fetcher: &Fetch<Q, F>) -> bool {
+        loop {
+            if self.cursor.table_finished() {
+                // Trait objects match one combination of implementors at a
+                // time, each over every table
+                match State::init::<Q>(fetcher.tables, Some(self.clone())) {
+                    Some(state) => {
+                        *self = state;
+                        continue;
+                    }
+                    None => return true,
+                }
+            }
+            let table = &fetcher.tables[self.cursor.table()];
+            if self.cursor.row() == 0 && !self.enter::<Q, F>(table, fetcher.traits) {
+                self.cursor.skip_table();
+                continue;
+            }
+            if self.cursor.row() >= table.count() {
+                self.cursor.skip_table();
+                continue;
+            }
+            if F::matches(table, self.cursor.row()) {
+                return false;
+            }
+            self.cursor += 1;
+        }
+    }
+    /// Looks up the columns of the query in `table`, false if it cannot hold
+    /// matching rows. Parameters of a system share their tables, so not all
+    /// of them are for this query.
+    fn enter<Q: Query, F: Filter>(&mut self, table: &Table, traits: &Traits) -> bool {
+        let archetype = table.archetype();
+        if !(self.supertype >= *archetype && F::admits(archetype)) {
+            return false;
         }
-        false
+        let columns = self
+            .metas
+            .iter()
+            .zip(Q::elements())
+            .map(|(meta, (optional, trait_id))| {
+                let Some(column) = archetype.iter().position(|column| column.id == meta.id)
+                else {
+                    return optional.then_some(None);
+                };
+                let Some(trait_id) = trait_id else {
+                    return Some(Some((column, None)));
+                };
+                // Components never registered for the trait are as good as
+                // missing
+                match traits.get(trait_id, meta.id) {
+                    Some(vtable) => Some(Some((column, Some(vtable)))),
+                    None if optional => Some(None),
+                    None => None,
+                }
+            })
+            .collect::<Option<_>>();
+        let Some(columns) = columns else {
+            return false;
+        };
+        self.cursor.enter(table.count());
+        self.columns = columns;
+        true
     }
 }
 
 pub struct Scan<F> {
     fetcher: F,
-    state: State,
+    /// `None` for a trait object query no component implements.
+    state: Option<State>,
 }
 
-impl<'a, 'b, Q: Query> Scan<&'b Fetch<'a, Q>> {
-    pub fn new(fetcher: &'a Fetch<'a, Q>) -> Self {
+impl<'a, 'b, Q: Query, F: Filter> Scan<&'b Fetch<'a, Q, F>> {
+    pub fn new(fetcher: &'a Fetch<'a, Q, F>) -> Self {
         Scan {
-            state: State::init::<Q>(fetcher.tables, None).unwrap(),
+            state: State::init::<Q>(fetcher.tables, None),
             fetcher,
         }
     }
 }
-impl<'a, 'b, Q: Query> Scan<&'b mut Fetch<'a, Q>> {
-    pub fn new_mut(fetcher: &'a mut Fetch<'a, Q>) -> Self {
+impl<'a, 'b, Q: Query, F: Filter> Scan<&'b mut Fetch<'a, Q, F>> {
+    pub fn new_mut(fetcher: &'a mut Fetch<'a, Q, F>) -> Self {
         Scan {
-            state: State::init::<Q>(fetcher.tables, None).unwrap(),
+            state: State::init::<Q>(fetcher.tables, None),
             fetcher,
         }
     }
 }
 
-impl<'a, Q: Query> Iterator for Scan<&'a Fetch<'a, Q>> {
+impl<'a, Q: Query, F: Filter> Iterator for Scan<&'a Fetch<'a, Q, F>> {
     type Item = Q::Ref;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let ret = Q::deduce(&mut self.state, self.fetcher);
+        let state = self.state.as_mut()?;
+        let ret = Q::deduce(state, self.fetcher);
 
-        self.state.cursor += 1;
+        state.cursor += 1;
 
         ret
     }
 }
 
-impl<'a, Q: Query> Iterator for Scan<&'a mut Fetch<'a, Q>> {
+impl<'a, Q: Query, F: Filter> Iterator for Scan<&'a mut Fetch<'a, Q, F>> {
     type Item = Q::Mut;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let ret = Q::deduce_mut(&mut self.state, self.fetcher);
+        let state = self.state.as_mut()?;
+        let ret = Q::deduce_mut(state, self.fetcher);
 
-        self.state.cursor += 1;
+        state.cursor += 1;
 
         ret
     }
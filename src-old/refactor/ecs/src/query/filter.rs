@@ -0,0 +1,86 @@
+use std::marker::PhantomData;
+
+use crate::component::{access::Access, archetype::Archetype, table::Table};
+
+/// Narrows the entities a [`Query`](super::Query) yields without borrowing
+/// any of their components. Filters in a tuple must all pass.
+pub trait Filter: 'static {
+    /// Whether the tables of `archetype` can hold matching entities at all.
+    fn admits(archetype: &Archetype) -> bool;
+    /// Whether the entity at `row` of an admitted `table` matches.
+    fn matches(table: &Table, row: usize) -> bool;
+}
+
+/// Entities that have a `T`.
+pub struct With<T: ?Sized>(PhantomData<T>);
+
+/// Entities that have no `T`.
+pub struct Without<T: ?Sized>(PhantomData<T>);
+
+/// Entities whose `T` was spawned or borrowed mutably since the previous
+/// run of the schedule began.
+pub struct Changed<T: ?Sized>(PhantomData<T>);
+
+fn contains<T: Access + ?Sized>(archetype: &Archetype) -> bool {
+    T::meta()
+        .iter()
+        .any(|meta| archetype.iter().any(|column| column.id == meta.id))
+}
+
+impl Filter for () {
+    fn admits(_: &Archetype) -> bool {
+        true
+    }
+    fn matches(_: &Table, _: usize) -> bool {
+        true
+    }
+}
+
+impl<T: Access + ?Sized + 'static> Filter for With<T> {
+    fn admits(archetype: &Archetype) -> bool {
+        contains::<T>(archetype)
+    }
+    fn matches(_: &Table, _: usize) -> bool {
+        true
+    }
+}
+
+impl<T: Access + ?Sized + 'static> Filter for Without<T> {
+    fn admits(archetype: &Archetype) -> bool {
+        !contains::<T>(archetype)
+    }
+    fn matches(_: &Table, _: usize) -> bool {
+        true
+    }
+}
+
+impl<T: Access + ?Sized + 'static> Filter for Changed<T> {
+    fn admits(archetype: &Archetype) -> bool {
+        contains::<T>(archetype)
+    }
+    fn matches(table: &Table, row: usize) -> bool {
+        T::meta().iter().any(|meta| {
+            table
+                .changed(row, meta.id)
+                .is_some_and(|tick| tick + 1 >= table.tick())
+        })
+    }
+}
+
+macro_rules! filter_tuple {
+    ($($filter:ident),*) => {
+        impl<$($filter: Filter),*> Filter for ($($filter,)*) {
+            fn admits(archetype: &Archetype) -> bool {
+                $($filter::admits(archetype))&&*
+            }
+            fn matches(table: &Table, row: usize) -> bool {
+                $($filter::matches(table, row))&&*
+            }
+        }
+    };
+}
+
+filter_tuple!(F0);
+filter_tuple!(F0, F1);
+filter_tuple!(F0, F1, F2);
+filter_tuple!(F0, F1, F2, F3);
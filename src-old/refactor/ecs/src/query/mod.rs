@@ -1,36 +1,91 @@
 use crate::{
     component::{archetype::Archetype, registry::Registry, sink::Sink},
-    system::param::Param,
+    entity::Entity,
+    system::param::{Borrow, Context, Param},
 };
 use base::rt::UnsafeLocal;
-use fetch::{Fetch, Scan};
-use std::{iter, marker::PhantomData, mem};
+use fetch::{Fetch, Scan, State};
+use filter::Filter;
+use std::{any::type_name, error, fmt, iter, marker::PhantomData, mem};
 
 use crate::world::World;
 
 pub mod fetch;
+pub mod filter;
 
+/// Borrows the components `Q` of every entity passing the filter `F`, e.g.
+/// `Query<(&Request,), With<Pending>>`. `Option<&T>` elements yield `None`
+/// for entities without a `T` instead of leaving them out.
 //SAFETY: Query will only be used by one thread at a time, so its inner RefCell is safe.
-pub struct Query<'a, Q: fetch::Query + 'static + ?Sized>(UnsafeLocal<Fetch<'a, Q>>);
+pub struct Query<'a, Q: fetch::Query + 'static + ?Sized, F: Filter = ()>(
+    UnsafeLocal<Fetch<'a, Q, F>>,
+);
 
-impl<'a, 'b: 'a, Q: fetch::Query> IntoIterator for &'a Query<'b, Q> {
+/// Why [`Query::single`] found no single match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryError {
+    NoMatch { query: &'static str },
+    MultipleMatches { query: &'static str },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoMatch { query } => write!(f, "no entity matches {query}"),
+            Self::MultipleMatches { query } => {
+                write!(f, "more than one entity matches {query}")
+            }
+        }
+    }
+}
+
+impl error::Error for QueryError {}
+
+impl<'a, Q: fetch::Query, F: Filter> Query<'a, Q, F> {
+    /// The components of `entity`, if it is alive and matches the query.
+    pub fn get(&self, entity: Entity) -> Option<Q::Ref> {
+        let mut state = State::locate(&self.0, entity)?;
+        Q::deduce(&mut state, &self.0)
+    }
+
+    /// Like [`Query::get`], marking the components borrowed mutably as
+    /// changed.
+    pub fn get_mut(&mut self, entity: Entity) -> Option<Q::Mut> {
+        let mut state = State::locate(&self.0, entity)?;
+        Q::deduce_mut(&mut state, &mut self.0)
+    }
+
+    /// The components of the one entity matching the query, e.g. the camera
+    /// or the focused widget.
+    pub fn single(&self) -> Result<Q::Ref, QueryError> {
+        let query = type_name::<Self>();
+        let mut matches = self.into_iter();
+        let single = matches.next().ok_or(QueryError::NoMatch { query })?;
+        match matches.next() {
+            Some(_) => Err(QueryError::MultipleMatches { query }),
+            None => Ok(single),
+        }
+    }
+}
+
+impl<'a, 'b: 'a, Q: fetch::Query, F: Filter> IntoIterator for &'a Query<'b, Q, F> {
     type Item = Q::Ref;
-    type IntoIter = Scan<&'b Fetch<'b, Q>>;
+    type IntoIter = Scan<&'b Fetch<'b, Q, F>>;
 
     fn into_iter(self) -> Self::IntoIter {
         Self::IntoIter::new(unsafe { mem::transmute(&self.0) })
     }
 }
-impl<'a, 'b: 'a, Q: fetch::Query> IntoIterator for &'a mut Query<'b, Q> {
+impl<'a, 'b: 'a, Q: fetch::Query, F: Filter> IntoIterator for &'a mut Query<'b, Q, F> {
     type Item = Q::Mut;
-    type IntoIter = Scan<&'b mut Fetch<'b, Q>>;
+    type IntoIter = Scan<&'b mut Fetch<'b, Q, F>>;
 
     fn into_iter(mut self) -> Self::IntoIter {
         Self::IntoIter::new_mut(unsafe { mem::transmute(&mut self.0) })
     }
 }
 
-impl<'a, Q: fetch::Query> Param<'a> for Query<'a, Q> {
+impl<'a, Q: fetch::Query, F: Filter> Param<'a> for Query<'a, Q, F> {
     fn inject(archetypes: &mut Vec<Archetype>) {
         let mut index = 0;
         let mut iter = iter::repeat_with(|| {
@@ -44,9 +99,18 @@ impl<'a, Q: fetch::Query> Param<'a> for Query<'a, Q> {
         }
     }
 
+    fn admits(archetype: &Archetype) -> bool {
+        F::admits(archetype)
+    }
+
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        Q::borrows(borrows);
+    }
+
     fn create(
         archetypes: &'a [Archetype],
         tables: &'a mut [&'a mut crate::component::table::Table],
+        context: &'a Context,
     ) -> Self
     where
         Self: Sized + 'a,
@@ -54,7 +118,249 @@ impl<'a, Q: fetch::Query> Param<'a> for Query<'a, Q> {
         Query(UnsafeLocal(Fetch {
             supertypes: archetypes,
             tables,
+            traits: &context.traits,
             marker: PhantomData,
+            filter: PhantomData,
         }))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::{Component, Meta, access::Access, table::Table};
+    use crate::params;
+    use filter::{Changed, With, Without};
+    use std::{
+        any::TypeId,
+        mem,
+        ptr::{self, DynMetadata},
+    };
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Pending(bool);
+
+    trait Code: Component {
+        fn code(&self) -> u16;
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Found(u16);
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Missing(u16);
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Teapot(u16);
+
+    component!(Position, Pending, Found, Missing, Teapot);
+
+    impl Code for Found {
+        fn code(&self) -> u16 {
+            self.0
+        }
+    }
+
+    impl Code for Missing {
+        fn code(&self) -> u16 {
+            self.0
+        }
+    }
+
+    impl Code for Teapot {
+        fn code(&self) -> u16 {
+            self.0
+        }
+    }
+
+    impl Access for dyn Code {
+        fn access<'a>(ptr: *mut u8, vtable: DynMetadata<dyn Component>) -> &'a mut Self {
+            let vtable = unsafe { mem::transmute::<_, DynMetadata<dyn Code>>(vtable) };
+            unsafe { &mut *ptr::from_raw_parts_mut::<dyn Code>(ptr, vtable) }
+        }
+        fn meta() -> Vec<Meta> {
+            vec![Meta::of::<Found>(), Meta::of::<Missing>(), Meta::of::<Teapot>()]
+        }
+        fn trait_id() -> Option<TypeId> {
+            Some(TypeId::of::<dyn Code>())
+        }
+    }
+
+    /// Three pending entities and two others.
+    fn world() -> World {
+        let mut world = World::new();
+        world.extend((0..3).map(|i| {
+            (
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                Pending(true),
+            )
+        }));
+        world.extend((0..2).map(|i| Position {
+            x: i as f32,
+            y: 1.0,
+        }));
+        world
+    }
+
+    #[test]
+    fn test_filtered_iteration() {
+        let mut world = world();
+        let (all,) = params::<(Query<&'static Position>,)>(&mut world);
+        assert_eq!((&all).into_iter().count(), 5);
+
+        let (pending,) = params::<(Query<&'static Position, With<Pending>>,)>(&mut world);
+        let pending = (&pending)
+            .into_iter()
+            .map(|(position,)| *position)
+            .collect::<Vec<_>>();
+        assert_eq!(pending.len(), 3);
+        assert!(pending.iter().all(|position| position.y == 0.0));
+
+        let (done,) = params::<(Query<&'static Position, Without<Pending>>,)>(&mut world);
+        assert_eq!((&done).into_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_optional_components() {
+        let mut world = world();
+        let (mut positions,) =
+            params::<(Query<(&'static Position, Option<&'static mut Pending>)>,)>(&mut world);
+        let (mut some, mut none) = (0, 0);
+        for (_, pending) in &mut positions {
+            match pending {
+                Some(pending) => {
+                    pending.0 = false;
+                    some += 1;
+                }
+                None => none += 1,
+            }
+        }
+        assert_eq!((some, none), (3, 2));
+
+        let (pending,) = params::<(Query<Option<&'static Pending>>,)>(&mut world);
+        let pending = (&pending)
+            .into_iter()
+            .map(|(pending,)| pending.copied())
+            .collect::<Vec<_>>();
+        assert_eq!(pending.len(), 5);
+        assert_eq!(pending.iter().filter(|pending| pending.is_none()).count(), 2);
+        assert!(
+            pending
+                .iter()
+                .flatten()
+                .all(|pending| *pending == Pending(false))
+        );
+    }
+
+    #[test]
+    fn test_trait_objects_need_registered_impls() {
+        let mut world = World::new();
+        world.extend((0..3).map(|_| Found(200)));
+        world.extend((0..2).map(|i| {
+            (
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                Missing(404),
+            )
+        }));
+        let teapot = world.extend([Teapot(418)])[0];
+        let sum = |world: &mut World| {
+            let (codes,) = params::<(Query<&'static dyn Code>,)>(world);
+            (&codes)
+                .into_iter()
+                .map(|(code,)| code.code() as u32)
+                .sum::<u32>()
+        };
+        assert_eq!(sum(&mut world), 0);
+
+        world.register_trait_impl::<dyn Code, Found>();
+        world.register_trait_impl::<dyn Code, Missing>();
+        assert_eq!(sum(&mut world), 3 * 200 + 2 * 404);
+
+        assert!(world.get_dyn::<dyn Code>(teapot).is_none());
+        world.register_trait_impl::<dyn Code, Teapot>();
+        assert_eq!(world.get_dyn::<dyn Code>(teapot).map(|code| code.code()), Some(418));
+    }
+
+    #[test]
+    fn test_get_and_single() {
+        let mut world = world();
+        let pending = world.extend([(Position { x: 7.0, y: 0.0 }, Pending(true))])[0];
+        let done = world.extend([Position { x: 8.0, y: 1.0 }])[0];
+
+        let (mut query,) = params::<(Query<&'static mut Position, With<Pending>>,)>(&mut world);
+        assert!(query.get(done).is_none());
+        query.get_mut(pending).unwrap().0.x = 9.0;
+        assert_eq!(query.get(pending).map(|(position,)| position.x), Some(9.0));
+        assert_eq!(
+            query.single().err(),
+            Some(QueryError::MultipleMatches {
+                query: type_name::<Query<&'static mut Position, With<Pending>>>(),
+            })
+        );
+
+        let (all,) = params::<(Query<(&'static Position, Option<&'static Pending>)>,)>(&mut world);
+        let (position, pending) = all.get(done).unwrap();
+        assert_eq!((position.x, pending), (8.0, None));
+
+        // Despawning moves the last entity of the page into the freed row
+        world.despawn([done]);
+        let (all,) = params::<(Query<&'static Position>,)>(&mut world);
+        assert!(all.get(done).is_none());
+
+        let (single,) = params::<(Query<&'static Pending, Without<Position>>,)>(&mut world);
+        assert!(matches!(single.single(), Err(QueryError::NoMatch { .. })));
+        let lone = world.extend([Pending(false)])[0];
+        let (single,) = params::<(Query<&'static Pending, Without<Position>>,)>(&mut world);
+        assert_eq!(
+            single.single().map(|(pending,)| *pending),
+            Ok(Pending(false))
+        );
+        assert_eq!(
+            single.get(lone).map(|(pending,)| *pending),
+            Some(Pending(false))
+        );
+    }
+
+    #[test]
+    fn test_changed_since_last_run() {
+        let mut world = World::new();
+        world.extend((0..2).map(|i| Position {
+            x: i as f32,
+            y: 0.0,
+        }));
+        let id = Meta::of::<Position>().id;
+        let shard = world.registry.shard(
+            Archetype::from(Meta::of::<Position>()),
+            <Changed<Position> as Filter>::admits,
+        );
+        let table = &mut shard.table_vec().unwrap()[0].1;
+        let changed = |table: &Table| {
+            (0..table.count())
+                .filter(|&row| <Changed<Position> as Filter>::matches(table, row))
+                .count()
+        };
+
+        // Spawning counts as a change in the first run
+        table.tick = 1;
+        assert_eq!(changed(table), 2);
+        // The second run sees what the first one wrote, and only that
+        table.touch(1, id);
+        table.tick = 2;
+        assert_eq!(changed(table), 1);
+        assert_eq!(table.changed(1, id), Some(1));
+        table.tick = 3;
+        assert_eq!(changed(table), 0);
+    }
+}
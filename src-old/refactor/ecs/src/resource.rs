@@ -0,0 +1,169 @@
+//! Singleton data of a world, kept apart from the tables and borrowed by
+//! systems through [`Res`] and [`ResMut`].
+
+use std::{
+    any::{Any, TypeId},
+    cell::UnsafeCell,
+    collections::HashMap,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use crate::{
+    component::{archetype::Archetype, table::Table},
+    system::param::{Borrow, Context, Param},
+};
+
+pub(crate) struct Slot(UnsafeCell<Box<dyn Any + Send + Sync>>);
+
+//SAFETY: the schedule never runs a system borrowing a slot mutably next to
+//another one borrowing it at all
+unsafe impl Sync for Slot {}
+
+impl Slot {
+    unsafe fn get<T: 'static>(&self) -> &mut T {
+        unsafe { self.0.get().as_mut().unwrap() }
+            .downcast_mut()
+            .expect("slot should hold the type it is keyed by")
+    }
+}
+
+/// The resources of a world by type. Cloning shares them.
+#[derive(Clone, Default)]
+pub(crate) struct Resources(HashMap<TypeId, Arc<Slot>>);
+
+impl Resources {
+    pub(crate) fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        let slot = Slot(UnsafeCell::new(Box::new(value)));
+        self.0.insert(TypeId::of::<T>(), Arc::new(slot));
+    }
+
+    pub(crate) fn get<T: 'static>(&self) -> Option<&T> {
+        Some(unsafe { self.0.get(&TypeId::of::<T>())?.get() })
+    }
+
+    pub(crate) fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        Some(unsafe { self.0.get(&TypeId::of::<T>())?.get() })
+    }
+
+    pub(crate) fn contains(&self, id: TypeId) -> bool {
+        self.0.contains_key(&id)
+    }
+
+    fn slot<T: 'static>(&self) -> Arc<Slot> {
+        self.0
+            .get(&TypeId::of::<T>())
+            .expect("the schedule should check resources before running systems")
+            .clone()
+    }
+}
+
+/// Shared access to the resource `T` from a system. Systems holding one may
+/// run alongside each other, but not alongside a [`ResMut<T>`].
+pub struct Res<T: 'static>(Arc<Slot>, PhantomData<T>);
+
+/// Exclusive access to the resource `T` from a system.
+pub struct ResMut<T: 'static>(Arc<Slot>, PhantomData<T>);
+
+impl<T: 'static> Deref for Res<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.0.get() }
+    }
+}
+
+impl<T: 'static> Deref for ResMut<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.0.get() }
+    }
+}
+
+impl<T: 'static> DerefMut for ResMut<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.0.get() }
+    }
+}
+
+impl<'a, T: Send + Sync + 'static> Param<'a> for Res<T> {
+    fn inject(_: &mut Vec<Archetype>) {}
+
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        borrows.push(Borrow::resource::<T>(false));
+    }
+
+    fn create(_: &'a [Archetype], _: &'a mut [&'a mut Table], context: &'a Context) -> Self
+    where
+        Self: Sized + 'a,
+    {
+        Res(context.resources.slot::<T>(), PhantomData)
+    }
+}
+
+impl<'a, T: Send + Sync + 'static> Param<'a> for ResMut<T> {
+    fn inject(_: &mut Vec<Archetype>) {}
+
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        borrows.push(Borrow::resource::<T>(true));
+    }
+
+    fn create(_: &'a [Archetype], _: &'a mut [&'a mut Table], context: &'a Context) -> Self
+    where
+        Self: Sized + 'a,
+    {
+        ResMut(context.resources.slot::<T>(), PhantomData)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{params, system::param::Params, world::World};
+
+    #[derive(Debug, PartialEq)]
+    struct Frame(u64);
+
+    struct Assets(Vec<&'static str>);
+
+    fn borrows<P: Params<'static>>() -> Vec<Borrow> {
+        let mut borrows = vec![];
+        P::borrows(&mut borrows);
+        borrows
+    }
+
+    #[test]
+    fn test_insert_and_borrow() {
+        let mut world = World::new();
+        assert_eq!(world.resource::<Frame>(), None);
+        world.insert_resource(Frame(0));
+        world.insert_resource(Assets(vec!["grass.png"]));
+
+        let (mut frame, assets) = params::<(ResMut<Frame>, Res<Assets>)>(&mut world);
+        (*frame).0 += 1;
+        assert_eq!((*assets).0, ["grass.png"]);
+        let (frame,) = params::<(Res<Frame>,)>(&mut world);
+        assert_eq!(*frame, Frame(1));
+
+        world.resource_mut::<Frame>().unwrap().0 += 1;
+        assert_eq!(world.resource::<Frame>(), Some(&Frame(2)));
+        world.insert_resource(Frame(7));
+        assert_eq!(world.resource::<Frame>(), Some(&Frame(7)));
+    }
+
+    #[test]
+    fn test_borrow_conflicts() {
+        let read = borrows::<(Res<Frame>, Res<Assets>)>();
+        let write = borrows::<(ResMut<Frame>,)>();
+        let conflicts =
+            |a: &[Borrow], b: &[Borrow]| a.iter().any(|a| b.iter().any(|b| a.conflicts(b)));
+
+        assert_eq!(read.len(), 2);
+        assert!(!conflicts(&read, &read));
+        assert!(conflicts(&read, &write));
+        assert!(conflicts(&write, &write));
+        assert!(!conflicts(&write, &borrows::<(Res<Assets>,)>()));
+    }
+}
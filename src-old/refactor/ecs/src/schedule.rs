@@ -1,5 +1,6 @@
-use crate::system::func::{Provider, Wrap};
-use crate::system::graph::Graph;
+use crate::system::func::{Id, Provider, Wrap};
+use crate::system::graph::{Graph, Node, Require};
+use crate::system::param::Kind;
 use crate::system::sequence::Sequence;
 use crate::world::World;
 use base::collections::array::Array;
@@ -7,67 +8,201 @@ use base::rt::join::UnorderedJoin;
 use base::{collections::queue::Queue, rt::spawn};
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::env::args;
-use std::iter;
+use std::{error, fmt, iter};
 
-#[derive(Default)]
-pub struct Schedule {
+/// Why a schedule refused to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// A system borrows a resource the world does not have.
+    MissingResource {
+        system: String,
+        resource: &'static str,
+    },
+    /// The ordering constraints of a stage loop back on themselves. The
+    /// systems on the loop are named by their labels, or their names when
+    /// they have none.
+    Cycle { stage: String, labels: Vec<String> },
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingResource { system, resource } => {
+                write!(f, "system {system} needs the missing resource {resource}")
+            }
+            Self::Cycle { stage, labels } => {
+                write!(f, "stage {stage} orders {} in a cycle", labels.join(", "))
+            }
+        }
+    }
+}
+
+impl error::Error for ScheduleError {}
+
+/// Labels a system and orders it against the labeled systems of its stage,
+/// e.g. `SystemConfig::new().label("input").before("physics")`. Labels of
+/// other stages are ignored, stages already run one after the other.
+#[derive(Debug, Clone, Default)]
+pub struct SystemConfig {
+    labels: Vec<String>,
+    before: Vec<String>,
+    after: Vec<String>,
+}
+
+impl SystemConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn label(mut self, label: impl Into<String>) -> Self {
+        self.labels.push(label.into());
+        self
+    }
+
+    /// Runs the system before every one labeled `label`.
+    pub fn before(mut self, label: impl Into<String>) -> Self {
+        self.before.push(label.into());
+        self
+    }
+
+    /// Runs the system after every one labeled `label`.
+    pub fn after(mut self, label: impl Into<String>) -> Self {
+        self.after.push(label.into());
+        self
+    }
+}
+
+struct Stage {
+    name: String,
     graph: Graph,
+    configs: Vec<(Id, SystemConfig)>,
 }
-impl Schedule {
-    pub async fn run(&mut self, world: &mut World) {
+
+impl Stage {
+    fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            graph: Graph::default(),
+            configs: vec![],
+        }
+    }
+
+    fn labeled<'a>(&'a self, label: &'a str) -> impl Iterator<Item = Id> + 'a {
+        self.configs
+            .iter()
+            .filter(move |(_, config)| config.labels.iter().any(|other| other == label))
+            .map(|(id, _)| *id)
+    }
+
+    /// Turns the label constraints into dependencies, and fails if they
+    /// cannot all hold.
+    fn order(&mut self) -> Result<(), ScheduleError> {
+        let mut requires = vec![];
+        for (id, config) in &self.configs {
+            for label in &config.before {
+                requires.extend(self.labeled(label).map(|other| Require::Depend {
+                    dependent: other,
+                    dependency: *id,
+                }));
+            }
+            for label in &config.after {
+                requires.extend(self.labeled(label).map(|other| Require::Depend {
+                    dependent: *id,
+                    dependency: other,
+                }));
+            }
+        }
+        for require in requires {
+            self.graph.require(require);
+        }
+
+        let cycle = self.graph.cycle();
+        if cycle.is_empty() {
+            return Ok(());
+        }
+        let mut labels = cycle
+            .iter()
+            .flat_map(|id| {
+                let labels = self
+                    .configs
+                    .iter()
+                    .filter(|(other, _)| other == id)
+                    .flat_map(|(_, config)| config.labels.clone())
+                    .collect::<Vec<_>>();
+                if labels.is_empty() {
+                    vec![self.graph.nodes[id].name.clone()]
+                } else {
+                    labels
+                }
+            })
+            .collect::<Vec<_>>();
+        labels.sort();
+        labels.dedup();
+        Err(ScheduleError::Cycle {
+            stage: self.name.clone(),
+            labels,
+        })
+    }
+
+    /// Runs the systems in waves. Every wave takes the systems whose
+    /// dependencies are done and whose borrows do not conflict, in the order
+    /// they were scheduled, hands them all to the worker pool and waits for
+    /// the last one before the next wave starts.
+    async fn run(&mut self, world: &mut World) {
+        // Conflicting systems without an order between them take turns in
+        // the order they were scheduled, not by chance
+        let rank = self
+            .configs
+            .iter()
+            .enumerate()
+            .map(|(rank, (id, _))| (*id, rank))
+            .collect::<HashMap<_, _>>();
         let mut nodes_ready = VecDeque::default();
         let mut nodes_pending = HashMap::new();
         let mut nodes_completed = HashSet::new();
 
         // Initialize dependency tracking
-        for (id, _) in &self.graph.nodes {
+        for id in self.graph.nodes.keys() {
             let deps = self.graph.dependencies(id);
             if deps.is_empty() {
-                nodes_ready.push_front(id.clone());
+                nodes_ready.push_back(*id);
             } else {
-                nodes_pending.insert(id.clone(), deps.len());
+                nodes_pending.insert(*id, deps.len());
             }
         }
 
         while !nodes_ready.is_empty() {
-            // Collect batch of ready nodes
-            let mut batch = Vec::<_>::new();
-            while let Some(node_id) = nodes_ready.pop_back() {
-                if let Some(node) = self.graph.nodes.remove(&node_id) {
-                    batch.push((node_id, node));
-                }
-            }
+            nodes_ready
+                .make_contiguous()
+                .sort_by_key(|id| rank.get(id).copied().unwrap_or(usize::MAX));
 
-            // Prepare and launch tasks
-            let mut join = UnorderedJoin::<_>::new();
-
-            for (node_id, mut node) in batch {
-                // Prepare node input data
-                let table_count = node.put.prepare(&mut world.registry);
+            // Collect batch of ready nodes, leaving those whose borrows
+            // conflict with the batch's for the next one
+            let batch = self.graph.batch(&mut nodes_ready);
 
-                // Create system task
+            // Every system of the wave is on the pool before any is awaited
+            let mut join = UnorderedJoin::<_>::new();
+            for (node_id, node) in batch {
+                node.put.prepare(world);
+                let handle = (node.system)(node.rx.clone());
                 join.push(async move {
-                    // Execute system
-                    dbg!("stock");
-                    for _ in 0..table_count {
-                        (node.system)(node.rx.clone())
-                            .await
-                            .map_err(|_| ())
-                            .expect("YO");
-                    }
-                    dbg!("poop");
+                    handle
+                        .await
+                        .expect("system should not panic")
+                        .map_err(|_| ())
+                        .expect("system should not fail");
                     (node_id, node)
                 });
             }
 
-            // Wait for all tasks to complete in any order
+            // Join barrier: the next wave may depend on any of this one
             let completed = join.await;
 
             // Process completed tasks
             for (completed_id, node) in completed {
                 // Restore node and mark completed
-                self.graph.nodes.insert(completed_id.clone(), node);
-                nodes_completed.insert(completed_id.clone());
+                self.graph.nodes.insert(completed_id, node);
+                nodes_completed.insert(completed_id);
 
                 // update dependent nodes
                 for dependent in self.graph.dependents(&completed_id) {
@@ -75,7 +210,7 @@ impl Schedule {
                         *pending_count -= 1;
                         if *pending_count == 0 {
                             nodes_pending.remove(&dependent);
-                            nodes_ready.push_front(dependent);
+                            nodes_ready.push_back(dependent);
                         }
                     }
                 }
@@ -87,8 +222,372 @@ impl Schedule {
             "Cycle detected in system dependencies"
         );
     }
-    pub fn schedule<Ty: Provider>(mut self, sequence: impl Sequence<Ty>) -> Self {
-        sequence.transform(&mut self.graph);
+}
+
+/// Runs systems stage by stage. Within a stage, systems run as soon as the
+/// ones ordered before them are done, next to any others whose borrows they
+/// do not conflict with. Commands apply once a stage is done.
+pub struct Schedule {
+    stages: Vec<Stage>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self {
+            stages: vec![Stage::new("default")],
+        }
+    }
+}
+
+impl Schedule {
+    pub async fn run(&mut self, world: &mut World) -> Result<(), ScheduleError> {
+        self.order()?;
+
+        // Nothing runs unless every system can get its resources
+        for node in self.stages.iter().flat_map(|stage| stage.graph.nodes.values()) {
+            let missing = node.put.borrows.iter().find(|borrow| {
+                borrow.kind == Kind::Resource && !world.resources.contains(borrow.id)
+            });
+            if let Some(missing) = missing {
+                return Err(ScheduleError::MissingResource {
+                    system: node.name.clone(),
+                    resource: missing.name,
+                });
+            }
+        }
+
+        world.registry.advance();
+        for stage in &mut self.stages {
+            stage.run(world).await;
+            // Sync point: the commands of a stage apply before the next one
+            world.sync();
+        }
+        world.update_events();
+        Ok(())
+    }
+
+    /// Checks the ordering constraints of every stage can hold, which
+    /// [`Schedule::run`] does as well.
+    pub fn build(mut self) -> Result<Self, ScheduleError> {
+        self.order()?;
+        Ok(self)
+    }
+
+    fn order(&mut self) -> Result<(), ScheduleError> {
+        self.stages.iter_mut().try_for_each(Stage::order)
+    }
+
+    /// Starts the stage `name`. Systems scheduled from now on run once the
+    /// ones scheduled before are done and their commands applied.
+    pub fn stage(mut self, name: impl Into<String>) -> Self {
+        let stage = self.stages.last_mut().unwrap();
+        if stage.graph.nodes.is_empty() {
+            stage.name = name.into();
+        } else {
+            self.stages.push(Stage::new(name));
+        }
+        self
+    }
+
+    pub fn schedule<Ty: Provider>(self, sequence: impl Sequence<Ty>) -> Self {
+        self.schedule_with(sequence, SystemConfig::new())
+    }
+
+    /// Schedules `sequence` in the current stage, labeling and ordering each
+    /// of its systems by `config`.
+    pub fn schedule_with<Ty: Provider>(
+        mut self,
+        sequence: impl Sequence<Ty>,
+        config: SystemConfig,
+    ) -> Self {
+        let stage = self.stages.last_mut().unwrap();
+        stage
+            .configs
+            .extend(sequence.iter().map(|id| (id, config.clone())));
+        sequence.transform(&mut stage.graph);
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        command::Commands,
+        query::Query,
+        resource::{Res, ResMut},
+    };
+    use base::rt::block_on;
+    use std::{
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    struct Frame(u64);
+
+    struct Log(Vec<&'static str>);
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Position(f32, f32);
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Velocity(f32, f32);
+
+    /// Written by two systems, `busy` is set while one of them holds it.
+    struct Guard {
+        busy: AtomicBool,
+        writes: u32,
+    }
+
+    static COLLISIONS: AtomicUsize = AtomicUsize::new(0);
+
+    component!(Position, Velocity, Guard);
+
+    fn input(mut log: ResMut<Log>) {
+        log.0.push("input");
+    }
+
+    fn physics(mut log: ResMut<Log>) {
+        log.0.push("physics");
+    }
+
+    fn render(mut log: ResMut<Log>) {
+        log.0.push("render");
+    }
+
+    fn spawn(commands: Commands) {
+        commands.spawn(Position(0.0, 0.0));
+    }
+
+    fn look(positions: Query<'_, &'_ Position>, mut log: ResMut<Log>) {
+        let seen = (&positions).into_iter().count();
+        log.0.push(if seen == 1 { "spawned" } else { "empty" });
+    }
+
+    fn push(mut positions: Query<'_, &'_ mut Position>) {
+        for (position,) in &mut positions {
+            position.0 += 1.0;
+        }
+    }
+
+    fn pull(mut positions: Query<'_, &'_ mut Position>) {
+        for (position,) in &mut positions {
+            position.0 -= 1.0;
+        }
+    }
+
+    fn drift(velocities: Query<'_, &'_ Velocity>) {
+        assert!((&velocities).into_iter().count() < 100);
+    }
+
+    fn guarded(guards: &mut Query<'_, &'_ mut Guard>) {
+        for (guard,) in guards {
+            if guard.busy.swap(true, Ordering::AcqRel) {
+                COLLISIONS.fetch_add(1, Ordering::Relaxed);
+            }
+            // Long enough for an interleaved writer to run into the flag
+            thread::sleep(Duration::from_micros(50));
+            guard.writes += 1;
+            guard.busy.store(false, Ordering::Release);
+        }
+    }
+
+    fn guard(mut guards: Query<'_, &'_ mut Guard>) {
+        guarded(&mut guards);
+    }
+
+    fn reguard(mut guards: Query<'_, &'_ mut Guard>) {
+        guarded(&mut guards);
+    }
+
+    static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+    static MET: AtomicUsize = AtomicUsize::new(0);
+
+    /// Waits for the other reader, which only comes while this one runs if
+    /// the two run at once.
+    fn meet<const N: usize>(positions: Query<'_, &'_ Position>) {
+        assert_eq!((&positions).into_iter().count(), 100);
+        ARRIVED.fetch_add(1, Ordering::AcqRel);
+        // Turns readers run one after the other into a failure, not a hang
+        let give_up = Instant::now() + Duration::from_secs(10);
+        while ARRIVED.load(Ordering::Acquire) < 2 && Instant::now() < give_up {
+            thread::yield_now();
+        }
+        if ARRIVED.load(Ordering::Acquire) == 2 {
+            MET.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    fn count(mut frame: ResMut<Frame>) {
+        frame.0 += 1;
+    }
+
+    fn recount(mut frame: ResMut<Frame>) {
+        frame.0 += 10;
+    }
+
+    fn read(frame: Res<Frame>) {
+        assert!(frame.0 < 100);
+    }
+
+    #[test]
+    fn test_writers_take_turns() {
+        crate::runtime();
+        let mut world = World::new();
+        world.insert_resource(Frame(0));
+        let mut schedule = Schedule::default()
+            .schedule(count)
+            .schedule(recount)
+            .schedule(read);
+        block_on(schedule.run(&mut world)).unwrap();
+        block_on(schedule.run(&mut world)).unwrap();
+        assert_eq!(world.resource::<Frame>().unwrap().0, 22);
+    }
+
+    #[test]
+    fn test_missing_resource() {
+        crate::runtime();
+        let mut world = World::new();
+        let mut schedule = Schedule::default().schedule(read);
+        let error = block_on(schedule.run(&mut world)).unwrap_err();
+        assert!(matches!(
+            error,
+            ScheduleError::MissingResource { resource, .. } if resource.ends_with("Frame")
+        ));
+    }
+
+    #[test]
+    fn test_labels_order_systems() {
+        crate::runtime();
+        let mut world = World::new();
+        world.insert_resource(Log(vec![]));
+        let mut schedule = Schedule::default()
+            .schedule_with(render, SystemConfig::new().label("render").after("physics"))
+            .schedule_with(physics, SystemConfig::new().label("physics"))
+            .schedule_with(input, SystemConfig::new().label("input").before("physics"))
+            .build()
+            .unwrap_or_else(|error| panic!("{error}"));
+        block_on(schedule.run(&mut world)).unwrap();
+        assert_eq!(
+            world.resource::<Log>().unwrap().0,
+            ["input", "physics", "render"]
+        );
+    }
+
+    #[test]
+    fn test_stages_apply_commands_in_between() {
+        crate::runtime();
+        let mut world = World::new();
+        world.insert_resource(Log(vec![]));
+        let mut schedule = Schedule::default()
+            .stage("update")
+            .schedule(spawn)
+            .schedule(look)
+            .stage("late")
+            .schedule(render);
+        block_on(schedule.run(&mut world)).unwrap();
+        assert_eq!(world.resource::<Log>().unwrap().0, ["empty", "render"]);
+
+        let mut schedule = Schedule::default().stage("late").schedule(look);
+        block_on(schedule.run(&mut world)).unwrap();
+        assert_eq!(
+            world.resource::<Log>().unwrap().0,
+            ["empty", "render", "spawned"]
+        );
+    }
+
+    #[test]
+    fn test_conflicting_writers_take_turns() {
+        crate::runtime();
+        let mut world = World::new();
+        world.extend([(Position(0.0, 0.0), Velocity(1.0, 0.0))]);
+        let mut schedule = Schedule::default()
+            .schedule(push)
+            .schedule(pull)
+            .schedule(drift);
+
+        let graph = &mut schedule.stages[0].graph;
+        let mut ready = graph.nodes.keys().copied().collect::<VecDeque<_>>();
+        let batch = graph.batch(&mut ready);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(ready.len(), 1);
+        assert!(batch.iter().any(|(_, node)| node.name.ends_with("drift")));
+        graph.nodes.extend(batch);
+
+        block_on(schedule.run(&mut world)).unwrap();
+        let (positions,) = crate::params::<(Query<&'static Position>,)>(&mut world);
+        let positions = (&positions)
+            .into_iter()
+            .map(|(position,)| *position)
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [Position(0.0, 0.0)]);
+    }
+
+    #[test]
+    fn test_conflicting_systems_run_in_schedule_order() {
+        crate::runtime();
+        let mut world = World::new();
+        world.insert_resource(Log(vec![]));
+        let mut schedule = Schedule::default()
+            .schedule(render)
+            .schedule(input)
+            .schedule(physics);
+        block_on(schedule.run(&mut world)).unwrap();
+        block_on(schedule.run(&mut world)).unwrap();
+        assert_eq!(
+            world.resource::<Log>().unwrap().0,
+            ["render", "input", "physics", "render", "input", "physics"]
+        );
+    }
+
+    #[test]
+    fn test_writers_never_interleave() {
+        crate::runtime();
+        let mut world = World::new();
+        world.extend((0..64).map(|_| Guard {
+            busy: AtomicBool::new(false),
+            writes: 0,
+        }));
+        let mut schedule = Schedule::default()
+            .schedule(guard)
+            .schedule(reguard)
+            .schedule(drift);
+        for _ in 0..10 {
+            block_on(schedule.run(&mut world)).unwrap();
+        }
+        assert_eq!(COLLISIONS.load(Ordering::Relaxed), 0);
+        let (guards,) = crate::params::<(Query<&'static Guard>,)>(&mut world);
+        assert!((&guards).into_iter().all(|(guard,)| guard.writes == 20));
+    }
+
+    #[test]
+    fn test_readers_run_in_parallel() {
+        crate::runtime();
+        let mut world = World::new();
+        world.spawn_batch((0..100).map(|i| Position(i as f32, 1.0)));
+        let mut schedule = Schedule::default()
+            .schedule(meet::<0>)
+            .schedule(meet::<1>);
+        block_on(schedule.run(&mut world)).unwrap();
+        // Each saw the other arrive before it returned
+        assert_eq!(MET.load(Ordering::Acquire), 2);
+    }
+
+    #[test]
+    fn test_cycle_rejected() {
+        let error = Schedule::default()
+            .schedule_with(input, SystemConfig::new().label("input").after("render"))
+            .schedule_with(render, SystemConfig::new().label("render").after("input"))
+            .schedule_with(physics, SystemConfig::new().after("input"))
+            .build()
+            .err();
+        assert_eq!(
+            error,
+            Some(ScheduleError::Cycle {
+                stage: "default".to_string(),
+                labels: vec!["input".to_string(), "render".to_string()],
+            })
+        );
+    }
+}
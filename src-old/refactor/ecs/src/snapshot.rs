@@ -0,0 +1,413 @@
+//! Saving a [`World`] to bytes and loading it back, with components written
+//! under the names given to their types in a [`TypeRegistry`].
+
+use std::{collections::HashMap, error, fmt, ptr};
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    component::{
+        Component, Handle, Id, Meta,
+        table::{Data, Erase},
+    },
+    entity::Entity,
+    link::{Link, LinkPolicy, LinkSite},
+    world::World,
+};
+
+/// Why a snapshot could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The bytes are no snapshot, or a component in it does not read back as
+    /// the type registered under its name.
+    Malformed(String),
+    /// A component is saved under a name no type is registered under, and
+    /// the registry does not skip those.
+    UnknownComponent(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed(error) => write!(f, "malformed snapshot: {error}"),
+            Self::UnknownComponent(name) => write!(f, "unknown component {name} in snapshot"),
+        }
+    }
+}
+
+impl error::Error for SnapshotError {}
+
+/// How the values of a registered component type are written and read back.
+struct Type {
+    name: &'static str,
+    meta: Meta,
+    save: unsafe fn(*const u8) -> bincode::Result<Vec<u8>>,
+    load: fn(&[u8]) -> bincode::Result<(Handle, Data)>,
+}
+
+/// The component types snapshots hold, each under a name that stays the same
+/// across builds, unlike its `TypeId`.
+#[derive(Default)]
+pub struct TypeRegistry {
+    types: HashMap<&'static str, Type>,
+    names: HashMap<Id, &'static str>,
+    skip_unknown: bool,
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Saves and loads `T` under `name`, replacing what was registered under
+    /// either before.
+    pub fn register<T>(&mut self, name: &'static str) -> &mut Self
+    where
+        T: Component + Serialize + DeserializeOwned,
+    {
+        let meta = T::meta();
+        if let Some(old) = self.names.insert(meta.id, name) {
+            self.types.remove(old);
+        }
+        let ty = Type {
+            name,
+            meta,
+            save: save::<T>,
+            load: load::<T>,
+        };
+        if let Some(old) = self.types.insert(name, ty)
+            && old.meta.id != meta.id
+        {
+            self.names.remove(&old.meta.id);
+        }
+        self
+    }
+
+    /// Whether loading leaves out components saved under unregistered names,
+    /// listing them in [`Loaded::skipped`], instead of failing. Off at first.
+    pub fn skip_unknown(&mut self, skip: bool) -> &mut Self {
+        self.skip_unknown = skip;
+        self
+    }
+
+    fn of(&self, id: Id) -> Option<&Type> {
+        self.types.get(self.names.get(&id)?)
+    }
+}
+
+unsafe fn save<T: Serialize>(data: *const u8) -> bincode::Result<Vec<u8>> {
+    bincode::serialize(unsafe { &*data.cast::<T>() })
+}
+
+fn load<T: Component + DeserializeOwned>(bytes: &[u8]) -> bincode::Result<(Handle, Data)> {
+    let value = bincode::deserialize::<T>(bytes)?;
+    let (original, data) = Box::new(value).erase();
+    let original: Box<dyn Component> = original;
+    let vtable = ptr::metadata(&*original as *const dyn Component);
+    Ok((Handle(original, vtable), data))
+}
+
+/// What a world is saved as. Entities are numbered by their place in
+/// `entities`, and links name their targets by that number.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    /// The link fields registered for saved components, by component name.
+    links: Vec<(String, usize, LinkPolicy)>,
+    entities: Vec<Vec<Saved>>,
+}
+
+/// A component of a saved entity, with the targets of its link fields by
+/// their offset.
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    name: String,
+    data: Vec<u8>,
+    links: Vec<(usize, u64)>,
+}
+
+/// A world read back from a snapshot.
+pub struct Loaded {
+    pub world: World,
+    /// The loaded entities in the order they were saved in.
+    pub entities: Vec<Entity>,
+    /// The names of the unregistered components left out, see
+    /// [`TypeRegistry::skip_unknown`].
+    pub skipped: Vec<String>,
+}
+
+impl World {
+    /// Writes every entity, with its components of types registered in
+    /// `types`, for [`World::load`] to read back. Links between entities are
+    /// kept; other components, resources and queued commands are not saved.
+    pub fn save(&self, types: &TypeRegistry) -> Vec<u8> {
+        // Tables are visited in a fixed order, so equal worlds save the same
+        let mut tables = self.registry.tables().collect::<Vec<_>>();
+        tables.sort_by(|a, b| a.archetype().cmp(b.archetype()));
+        let entities = tables
+            .iter()
+            .flat_map(|table| (0..table.count()).filter_map(|row| table.entity(row)))
+            .collect::<Vec<_>>();
+        let ids = entities
+            .iter()
+            .enumerate()
+            .map(|(id, &entity)| (entity, id as u64))
+            .collect::<HashMap<_, _>>();
+
+        let mut links = types
+            .types
+            .values()
+            .flat_map(|ty| {
+                self.links
+                    .fields(ty.meta.id)
+                    .iter()
+                    .map(|&(offset, policy)| (ty.name.to_string(), offset, policy))
+            })
+            .collect::<Vec<_>>();
+        links.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+
+        let entities = entities
+            .iter()
+            .map(|&entity| {
+                let archetype = entity.archetype();
+                archetype
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(column, meta)| {
+                        let ty = types.of(meta.id)?;
+                        let value = unsafe { entity.data().add(archetype.offset_of(column)) };
+                        let data = unsafe { (ty.save)(value) }.expect("component should serialize");
+                        let links = self
+                            .links
+                            .fields(meta.id)
+                            .iter()
+                            .filter_map(|&(offset, _)| {
+                                let site = LinkSite {
+                                    owner: entity,
+                                    component: meta.id,
+                                    offset,
+                                };
+                                let target = self.read_link(site)?.get()?;
+                                Some((offset, *ids.get(&target)?))
+                            })
+                            .collect();
+                        Some(Saved {
+                            name: ty.name.to_string(),
+                            data,
+                            links,
+                        })
+                    })
+                    .collect()
+            })
+            .collect();
+
+        bincode::serialize(&Snapshot { links, entities }).expect("snapshot should serialize")
+    }
+
+    /// Reads a world written by [`World::save`] into a new one, looking its
+    /// components up by name in `types`. Links point at the loaded entities
+    /// and are tracked like those of spawned ones.
+    pub fn load(bytes: &[u8], types: &TypeRegistry) -> Result<Loaded, SnapshotError> {
+        let snapshot = bincode::deserialize::<Snapshot>(bytes)
+            .map_err(|error| SnapshotError::Malformed(error.to_string()))?;
+        let mut world = World::new();
+        for (name, offset, policy) in snapshot.links {
+            if let Some(ty) = types.types.get(name.as_str()) {
+                world.links.register(ty.meta.id, offset, policy);
+            }
+        }
+
+        let mut entities = Vec::with_capacity(snapshot.entities.len());
+        let mut targets = Vec::with_capacity(snapshot.entities.len());
+        let mut skipped = vec![];
+        for saved in snapshot.entities {
+            let mut components = vec![];
+            let mut linked = vec![];
+            for Saved { name, data, links } in saved {
+                let Some(ty) = types.types.get(name.as_str()) else {
+                    if !types.skip_unknown {
+                        return Err(SnapshotError::UnknownComponent(name));
+                    }
+                    if !skipped.contains(&name) {
+                        skipped.push(name);
+                    }
+                    continue;
+                };
+                let component = (ty.load)(&data)
+                    .map_err(|error| SnapshotError::Malformed(format!("{name}: {error}")))?;
+                components.push(component);
+                linked.extend(
+                    links
+                        .into_iter()
+                        .map(|(offset, target)| (ty.meta.id, offset, target)),
+                );
+            }
+            // Like the archetypes of spawned bundles, by component id
+            components.sort_by_key(|(_, data)| data.meta.id);
+            let archetype = components.iter().map(|(_, data)| data.meta).collect();
+            entities.push(world.registry.insert(archetype, components));
+            targets.push(linked);
+        }
+
+        // Every entity is spawned by now, so links can name their targets and
+        // hooks see them set
+        for (&owner, linked) in entities.iter().zip(targets) {
+            for (component, offset, target) in linked {
+                if let Some(&target) = entities.get(target as usize) {
+                    let site = LinkSite {
+                        owner,
+                        component,
+                        offset,
+                    };
+                    world.write_link(site, Link::to(target));
+                }
+            }
+            world.inserted(owner, |_| true);
+            world.index_links(owner);
+        }
+
+        Ok(Loaded {
+            world,
+            entities,
+            skipped,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{params, query::Query};
+    use std::mem;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    struct Position(f32, f32);
+
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    struct Velocity(f32, f32);
+
+    #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+    struct Follow {
+        target: Link,
+    }
+
+    component!(Position, Velocity, Follow);
+
+    fn types() -> TypeRegistry {
+        let mut types = TypeRegistry::new();
+        types
+            .register::<Position>("position")
+            .register::<Velocity>("velocity")
+            .register::<Follow>("follow");
+        types
+    }
+
+    fn positions(world: &mut World) -> Vec<(f32, Option<Velocity>)> {
+        let (query,) = params::<(Query<(&'static Position, Option<&'static Velocity>)>,)>(world);
+        let mut rows = (&query)
+            .into_iter()
+            .map(|(position, velocity)| (position.0, velocity.copied()))
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+        rows
+    }
+
+    /// Followers by their position, with the position of what they follow.
+    fn follows(world: &mut World) -> Vec<(f32, f32)> {
+        let (query,) = params::<(Query<(&'static Position, &'static Follow)>,)>(world);
+        let follows = (&query)
+            .into_iter()
+            .map(|(position, follow)| (position.0, follow.target.get().unwrap()))
+            .collect::<Vec<_>>();
+        let mut follows = follows
+            .into_iter()
+            .map(|(x, target)| (x, world.get::<Position>(target).unwrap().0))
+            .collect::<Vec<_>>();
+        follows.sort_by(|a, b| a.0.total_cmp(&b.0));
+        follows
+    }
+
+    #[test]
+    fn test_round_trip_keeps_values_and_links() {
+        let mut world = World::new();
+        world.register_link::<Follow>(mem::offset_of!(Follow, target), LinkPolicy::Clear);
+        let moving =
+            world.extend((0..3).map(|i| (Position(i as f32, 0.0), Velocity(1.0, i as f32))));
+        let still = world.extend((3..5).map(|i| Position(i as f32, 0.0)));
+        world.extend([
+            (
+                Position(5.0, 0.0),
+                Follow {
+                    target: Link::to(moving[1]),
+                },
+            ),
+            (
+                Position(6.0, 0.0),
+                Follow {
+                    target: Link::to(still[0]),
+                },
+            ),
+        ]);
+
+        let types = types();
+        let bytes = world.save(&types);
+        let Loaded {
+            world: mut loaded,
+            entities,
+            skipped,
+        } = World::load(&bytes, &types).unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(entities.len(), 7);
+        assert!(entities.iter().all(|&entity| loaded.contains(entity)));
+        assert_eq!(positions(&mut loaded), positions(&mut world));
+        assert_eq!(follows(&mut loaded), [(5.0, 1.0), (6.0, 3.0)]);
+
+        // Entities keep their numbers, so the loaded world saves the same
+        assert_eq!(loaded.save(&types), bytes);
+
+        // Loaded links are tracked, and cleared once their target despawns
+        let follower = entities
+            .iter()
+            .copied()
+            .find(|&entity| loaded.get::<Position>(entity) == Some(&Position(5.0, 0.0)))
+            .unwrap();
+        let target = loaded
+            .get::<Follow>(follower)
+            .unwrap()
+            .target
+            .get()
+            .unwrap();
+        assert_eq!(loaded.links_to(target).count(), 1);
+        loaded.despawn([target]);
+        assert_eq!(loaded.get::<Follow>(follower).unwrap().target.get(), None);
+    }
+
+    #[test]
+    fn test_unknown_components() {
+        let mut world = World::new();
+        world.extend([(Position(0.0, 0.0), Velocity(1.0, 1.0))]);
+        world.extend([Position(1.0, 0.0)]);
+        let bytes = world.save(&types());
+
+        let mut types = TypeRegistry::new();
+        types.register::<Position>("position");
+        assert_eq!(
+            World::load(&bytes, &types).err(),
+            Some(SnapshotError::UnknownComponent("velocity".into()))
+        );
+
+        types.skip_unknown(true);
+        let Loaded {
+            world: mut loaded,
+            entities,
+            skipped,
+        } = World::load(&bytes, &types).unwrap();
+        assert_eq!(skipped, ["velocity"]);
+        assert_eq!(entities.len(), 2);
+        assert_eq!(positions(&mut loaded), [(0.0, None), (1.0, None)]);
+
+        assert!(matches!(
+            World::load(&bytes[..bytes.len() - 1], &types),
+            Err(SnapshotError::Malformed(_))
+        ));
+    }
+}
@@ -6,10 +6,7 @@ use crate::{
     },
     world::World,
 };
-use base::{
-    rt::{UnsafeLocal, block_on, spawn, spawn_blocking, worker::Register, yield_now},
-    sync::{barrier::Barrier, oneshot::Oneshot},
-};
+use base::rt::{block_on, spawn, spawn_blocking};
 use derive_more::derive::{Deref, DerefMut};
 use ecs_macro::func;
 use flume::{Receiver, Sender, bounded, unbounded};
@@ -23,7 +20,10 @@ use std::{
     sync::Arc,
 };
 
-use super::{System, param::Params};
+use super::{
+    System,
+    param::{Borrow, Context, Params},
+};
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Deref, DerefMut)]
 pub struct Id(pub TypeId);
@@ -94,6 +94,7 @@ type Erased = u8;
 
 pub enum Cmd {
     Execute(Archetype, &'static mut Table),
+    Context(Context),
     Complete,
 }
 
@@ -116,11 +117,21 @@ impl<T: Params<'static>> AsyncFnOnce<()> for Get<T> {
             fut: Box::pin(async move {
                 let mut supertypes = Box::leak(Box::new(vec![]));
                 let mut tables = Box::leak(Box::new(vec![]));
-                while let Ok(Cmd::Execute(supertype, table)) = self.get.try_recv() {
-                    tables.push(table);
-                    supertypes.push(supertype);
+                let mut context = None;
+                while let Ok(cmd) = self.get.try_recv() {
+                    match cmd {
+                        Cmd::Execute(supertype, table) => {
+                            tables.push(table);
+                            supertypes.push(supertype);
+                        }
+                        Cmd::Context(given) => context = Some(given),
+                        Cmd::Complete => break,
+                    }
                 }
-                return Ok(T::create(supertypes, tables));
+                let context = Box::leak(Box::new(
+                    context.expect("the schedule should hand out a context first"),
+                ));
+                return Ok(T::create(supertypes, tables, context));
             }),
         }
     }
@@ -145,11 +156,33 @@ impl<T: Params<'static>> Future for Getter<T> {
 
 pub struct Put {
     binding: Arc<dyn Fn(&mut Registry) -> Shard>,
+    pub(crate) borrows: Vec<Borrow>,
+    system: Id,
     put: Sender<Cmd>,
 }
 impl Put {
-    pub(crate) fn prepare(&self, registry: &mut Registry) -> usize {
-        let mut binding = (self.binding)(registry);
+    fn new<Input: Params<'static>>(system: Id, put: Sender<Cmd>) -> Self {
+        let mut borrows = vec![];
+        Input::borrows(&mut borrows);
+        Self {
+            binding: Arc::new(Input::bind),
+            borrows,
+            system,
+            put,
+        }
+    }
+    /// Whether the systems the two feed may not run at the same time.
+    pub(crate) fn conflicts(&self, other: &Put) -> bool {
+        self.borrows
+            .iter()
+            .any(|borrow| other.borrows.iter().any(|other| borrow.conflicts(other)))
+    }
+    pub(crate) fn prepare(&self, world: &mut World) -> usize {
+        self.put
+            .clone()
+            .try_send(Cmd::Context(world.context(self.system)))
+            .unwrap();
+        let mut binding = (self.binding)(&mut world.registry);
         let mut count = 0;
         for (supertype, table) in binding.table_vec().unwrap().drain(..) {
             self.put
@@ -170,11 +203,11 @@ impl<Input: Params<'static>, Output: Outcome, F: Func<Input, Blocking<Output>> +
     Wrap<Input, Blocking<Output>> for F
 {
     fn wrap(mut self) -> (System, Receiver<Cmd>, Put) {
+        let id = self.id();
         let (tx, rx) = unbounded();
-        let binding = Arc::new(Input::bind);
         let system = Box::pin(move |get: Receiver<Cmd>| {
             let this = self.clone();
-            let system = block_on(spawn_blocking(move || {
+            let system = spawn_blocking(move || {
                 block_on(async move {
                     Ok(this
                         .derive()
@@ -189,11 +222,11 @@ impl<Input: Params<'static>, Output: Outcome, F: Func<Input, Blocking<Output>> +
                         .await
                         .to_return())
                 })
-            }));
+            });
             system
         });
         dbg!(rx.is_disconnected());
-        (system, rx, Put { binding, put: tx })
+        (system, rx, Put::new::<Input>(id, tx))
     }
 }
 
@@ -203,12 +236,12 @@ where
     F: Func<Input, Concurrent<Fut>> + Clone,
 {
     fn wrap(mut self) -> (System, Receiver<Cmd>, Put) {
+        let id = self.id();
         let (tx, rx) = unbounded();
-        let binding = Arc::new(Input::bind);
 
         let system = Box::pin(move |get: Receiver<Cmd>| {
             let this = self.clone();
-            let system = block_on(spawn(async move {
+            let system = spawn(async move {
                 Ok(this
                     .derive()
                     .execute(
@@ -220,10 +253,10 @@ where
                     )
                     .await
                     .to_return())
-            }));
+            });
             system
         });
-        (system, rx, Put { binding, put: tx })
+        (system, rx, Put::new::<Input>(id, tx))
     }
 }
 ecs_macro::func!();
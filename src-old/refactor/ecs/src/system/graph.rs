@@ -12,7 +12,7 @@ use std::{
 pub struct Node {
     pub(crate) system: System,
     pub(crate) put: Put,
-    name: String,
+    pub(crate) name: String,
     id: Id,
     pub(crate) rx: Receiver<Cmd>,
 }
@@ -82,6 +82,52 @@ impl Graph {
             .collect()
     }
 
+    /// Takes the nodes of `ready` that may run together, front first. Those
+    /// whose borrows conflict with one taken before them are left in `ready`,
+    /// in the order they were in.
+    pub(crate) fn batch(&mut self, ready: &mut VecDeque<Id>) -> Vec<(Id, Node)> {
+        let mut batch = Vec::<(Id, Node)>::new();
+        let mut deferred = vec![];
+        while let Some(id) = ready.pop_front() {
+            let Some(node) = self.nodes.remove(&id) else {
+                continue;
+            };
+            if batch.iter().any(|(_, other)| node.put.conflicts(&other.put)) {
+                self.nodes.insert(id, node);
+                deferred.push(id);
+            } else {
+                batch.push((id, node));
+            }
+        }
+        ready.extend(deferred);
+        batch
+    }
+
+    /// The nodes caught on a dependency cycle, none if there is no cycle.
+    pub(crate) fn cycle(&self) -> Vec<Id> {
+        let mut left = self.nodes.keys().copied().collect::<HashSet<_>>();
+        // Peel off nodes depending on none of the rest, and nodes none of the
+        // rest depend on, until only the cycles remain
+        loop {
+            let peeled = left
+                .iter()
+                .copied()
+                .filter(|id| {
+                    let free = self.dependencies(id).iter().all(|id| !left.contains(id));
+                    let last = self.dependents(id).iter().all(|id| !left.contains(id));
+                    free || last
+                })
+                .collect::<Vec<_>>();
+            if peeled.is_empty() {
+                break;
+            }
+            for id in peeled {
+                left.remove(&id);
+            }
+        }
+        left.into_iter().collect()
+    }
+
     pub(crate) fn search(&self, mut action: impl FnMut(&Node)) {
         let mut nodes_all = self.nodes.keys().copied().collect::<HashSet<_>>();
         let mut nodes_visited = HashSet::new();
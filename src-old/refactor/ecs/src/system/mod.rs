@@ -1,5 +1,5 @@
 use crate::system::func::Cmd;
-use base::rt::Handle;
+use base::rt::JoinHandle;
 use flume::Receiver;
 use func::{Finished, Return};
 use std::pin::Pin;
@@ -9,4 +9,4 @@ pub mod graph;
 pub mod param;
 pub mod sequence;
 
-pub type System = Pin<Box<dyn Fn(Receiver<Cmd>) -> Handle<Result<Return, Finished>>>>;
+pub type System = Pin<Box<dyn Fn(Receiver<Cmd>) -> JoinHandle<Result<Return, Finished>>>>;
@@ -1,22 +1,103 @@
-use crate::component::{
-    archetype::Archetype,
-    registry::{Registry, Shard},
-    table::Table,
+use crate::{
+    command::Queue,
+    resource::Resources,
+    component::{
+        Meta,
+        access::Traits,
+        archetype::Archetype,
+        registry::{Registry, Shard},
+        table::Table,
+    },
 };
 
-use super::func::Outcome;
-use std::cell::UnsafeCell;
+use super::func::{Id, Outcome};
+use std::{
+    any::{TypeId, type_name},
+    cell::UnsafeCell,
+    sync::Arc,
+};
+
+/// What parameters reach of the world besides the tables bound to them. One
+/// is handed to every system a schedule runs.
+#[derive(Clone)]
+pub struct Context {
+    pub(crate) commands: Queue,
+    pub(crate) resources: Resources,
+    pub(crate) traits: Arc<Traits>,
+    /// The system the parameters are created for, which parameters keeping
+    /// state across runs key it by.
+    pub(crate) system: Id,
+}
+
+/// What a [`Borrow`] is of. Ids of different kinds never conflict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Resource,
+    Component,
+}
+
+/// A resource or component a parameter borrows. The schedule checks the world
+/// has the resources, and keeps a mutable borrow from running next to any
+/// other of the same resource or component.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Borrow {
+    pub kind: Kind,
+    pub id: TypeId,
+    pub name: &'static str,
+    pub mutable: bool,
+}
+
+impl Borrow {
+    pub fn resource<T: 'static>(mutable: bool) -> Self {
+        Self {
+            kind: Kind::Resource,
+            id: TypeId::of::<T>(),
+            name: type_name::<T>(),
+            mutable,
+        }
+    }
+
+    pub fn component(meta: Meta, name: &'static str, mutable: bool) -> Self {
+        Self {
+            kind: Kind::Component,
+            id: meta.id.0,
+            name,
+            mutable,
+        }
+    }
+
+    /// Whether the two may not be held at the same time.
+    pub fn conflicts(&self, other: &Borrow) -> bool {
+        self.kind == other.kind && self.id == other.id && (self.mutable || other.mutable)
+    }
+}
 
 pub trait Param<'a>: Send {
     fn inject(archetype: &mut Vec<Archetype>);
-    fn create(archetype: &'a [Archetype], table: &'a mut [&'a mut Table]) -> Self
+    /// Whether tables of `archetype` are handed to the parameter, on top of
+    /// matching one it injected.
+    fn admits(archetype: &Archetype) -> bool {
+        true
+    }
+    /// Records the resources and components the parameter borrows.
+    fn borrows(borrows: &mut Vec<Borrow>) {}
+    fn create(
+        archetype: &'a [Archetype],
+        table: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
     where
         Self: Sized + 'a;
 }
 
 pub trait Params<'a>: Send + 'static {
     fn bind(registry: &mut Registry) -> Shard;
-    fn create(archetype: &'a [Archetype], table: &'a mut [&'a mut Table]) -> Self
+    fn borrows(borrows: &mut Vec<Borrow>);
+    fn create(
+        archetype: &'a [Archetype],
+        table: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
     where
         Self: Sized + 'a;
 }
@@ -28,7 +109,11 @@ impl<'a> Param<'a> for () {
         todo!()
     }
 
-    fn create(archetype: &[Archetype], table: &'a mut [&'a mut Table]) -> Self
+    fn create(
+        archetype: &[Archetype],
+        table: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
     where
         Self: Sized,
     {
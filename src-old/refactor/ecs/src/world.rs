@@ -1,29 +1,1135 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::marker::Unsize;
+use std::ptr::{self, DynMetadata, Pointee};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use smallvec::SmallVec;
+
 use crate::{
+    command::{self, Command, Reserved, Target},
     component::{
+        Component, Handle, Meta,
+        access::{Access, Traits},
         archetype::{self, Archetype},
-        registry::Registry,
+        registry::{Entities, Registry},
         source::Source,
+        table::{Components, Data, Moved, Table},
     },
+    entity::Entity,
+    event::Events,
+    hierarchy::{Children, Hierarchy, HierarchyError, OrphanPolicy, Parent},
+    link::{BrokenLink, EntityRefs, Link, LinkPolicy, LinkSite},
+    query::filter::Filter,
+    resource::Resources,
     system::{
-        func::{Provider, Wrap},
+        func::{Id, Provider, Wrap},
         graph::Graph,
+        param::Context,
         sequence::Sequence,
     },
 };
 
-#[derive(Default)]
+/// Identifies a [`World`]. Every entity remembers the world it was spawned
+/// in, so handing it to another one is caught in debug builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WorldId(pub(crate) u32);
+
+impl WorldId {
+    fn next() -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 pub struct World {
+    pub(crate) id: WorldId,
     pub(crate) registry: Registry,
+    pub(crate) links: EntityRefs,
+    pub(crate) hierarchy: Hierarchy,
+    pub(crate) commands: command::Queue,
+    pub(crate) resources: Resources,
+    /// Vtables of registered trait impls, shared with running systems.
+    pub(crate) traits: Arc<Traits>,
+    /// Swaps the buffers of every event type added, once per schedule run.
+    updates: Vec<fn(&mut Resources)>,
+    /// Entities spawned by command, by the id reserved for them and back.
+    spawned: HashMap<Reserved, Entity>,
+    reserved: HashMap<Entity, Reserved>,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl World {
     pub fn new() -> Self {
+        let id = WorldId::next();
         Self {
-            registry: Registry::default(),
+            id,
+            registry: Registry::new(id),
+            links: EntityRefs::default(),
+            hierarchy: Hierarchy::default(),
+            commands: command::Queue::default(),
+            resources: Resources::default(),
+            traits: Arc::default(),
+            updates: vec![],
+            spawned: HashMap::default(),
+            reserved: HashMap::default(),
+        }
+    }
+
+    pub fn id(&self) -> WorldId {
+        self.id
+    }
+
+    pub fn extend(&mut self, src: impl IntoIterator<Item = impl Source>) -> Entities {
+        let entities = self.registry.extend(src);
+        for i in 0..entities.len() {
+            self.inserted(entities[i], |_| true);
+            self.index_links(entities[i]);
+        }
+        entities
+    }
+
+    /// Spawns every bundle of `src`, which share one archetype, and returns
+    /// the entities in order. Unlike [`World::extend`], room for the batch is
+    /// made up front and its size is not capped.
+    pub fn spawn_batch<S: Source + 'static>(
+        &mut self,
+        src: impl IntoIterator<Item = S>,
+    ) -> Vec<Entity> {
+        let entities = self.registry.spawn_batch(src);
+        let Some(first) = entities.first() else {
+            return entities;
+        };
+        for &entity in &entities {
+            self.inserted(entity, |_| true);
+        }
+        // Only archetypes with declared links need their rows scanned
+        let linked = first
+            .archetype()
+            .iter()
+            .any(|meta| !self.links.fields(meta.id).is_empty());
+        if linked {
+            for &entity in &entities {
+                self.index_links(entity);
+            }
+        }
+        entities
+    }
+
+    /// Lets queries of the trait object `T` read `C` as one. `T` still picks
+    /// the components it matches, see
+    /// [`Access::meta`](crate::component::access::Access::meta), and queries
+    /// leave out those never registered.
+    pub fn register_trait_impl<T, C>(&mut self)
+    where
+        T: ?Sized + Pointee<Metadata = DynMetadata<T>> + 'static,
+        C: Component + Unsize<T>,
+    {
+        Arc::make_mut(&mut self.traits).insert::<T, C>();
+    }
+
+    /// Declares the [`Link`] at byte `offset` of `C` (see `mem::offset_of!`)
+    /// and what happens to it when its target despawns. Register before
+    /// spawning owners; existing entities are not re-scanned.
+    pub fn register_link<C: Component>(&mut self, offset: usize, policy: LinkPolicy) {
+        self.links.register(C::meta().id, offset, policy);
+    }
+
+    /// Component `C` of `entity`, if it is alive and has one. Handles left
+    /// stale by a move, see [`World::despawn`], find nothing.
+    pub fn get<C: Component>(&self, entity: Entity) -> Option<&C> {
+        // Like the components handed to queries, it lives in the row
+        self.component_ptr::<C>(entity).map(|data| unsafe { &*data })
+    }
+
+    /// Like [`World::get`], marking the component as changed.
+    pub fn get_mut<C: Component>(&mut self, entity: Entity) -> Option<&mut C> {
+        let data = self.component_ptr::<C>(entity)?;
+        let table = self.registry.table_of(entity)?;
+        table.touch(table.row(entity)?, C::meta().id);
+        Some(unsafe { &mut *data })
+    }
+
+    /// The component of `entity` read as the trait object `T`, e.g. its
+    /// `dyn Code`, if it has one registered with
+    /// [`World::register_trait_impl`].
+    pub fn get_dyn<T: Access + ?Sized + 'static>(&self, entity: Entity) -> Option<&T> {
+        self.debug_assert_owns(entity);
+        if !self.registry.contains(entity) {
+            return None;
+        }
+        let trait_id = T::trait_id()?;
+        let archetype = entity.archetype();
+        let (column, vtable) = archetype
+            .iter()
+            .enumerate()
+            .find_map(|(column, meta)| Some((column, self.traits.get(trait_id, meta.id)?)))?;
+        let data = unsafe { entity.data().add(archetype.offset_of(column)) };
+        Some(T::access(data, vtable))
+    }
+
+    /// Where `entity` keeps its `C`, if it is alive and has one.
+    fn component_ptr<C: Component>(&self, entity: Entity) -> Option<*mut C> {
+        self.debug_assert_owns(entity);
+        if !self.registry.contains(entity) {
+            return None;
+        }
+        let archetype = entity.archetype();
+        let column = archetype.iter().position(|meta| meta.id == C::meta().id)?;
+        Some(unsafe { entity.data().add(archetype.offset_of(column)).cast::<C>() })
+    }
+
+    /// Whether `entity` is alive in this world.
+    pub fn contains(&self, entity: Entity) -> bool {
+        entity.world == self.id && self.registry.contains(entity)
+    }
+
+    /// The entity spawned for `reserved`, once the command was applied and
+    /// as long as the entity lives.
+    pub fn spawned(&self, reserved: Reserved) -> Option<Entity> {
+        self.spawned.get(&reserved).copied()
+    }
+
+    /// Names `entity` by an id that follows it through moves, like those of
+    /// entities spawned by command, for code holding on to it across
+    /// schedule runs. See [`World::spawned`].
+    pub fn reserve(&mut self, entity: Entity) -> Reserved {
+        self.debug_assert_owns(entity);
+        if let Some(&reserved) = self.reserved.get(&entity) {
+            return reserved;
+        }
+        let reserved = self.commands.reserve();
+        self.spawned.insert(reserved, entity);
+        self.reserved.insert(entity, reserved);
+        reserved
+    }
+
+    /// Adds `component` to `entity`, replacing the one it has, and returns
+    /// the entity's new handle.
+    pub fn insert_component<C: Component + Source>(
+        &mut self,
+        entity: Entity,
+        component: C,
+    ) -> Entity {
+        self.migrate(entity, C::meta(), unsafe {
+            component.erase_component_data()
+        })
+    }
+
+    /// Takes the `C` off `entity`, if it has one, and returns the entity's new
+    /// handle.
+    pub fn remove_component<C: Component>(&mut self, entity: Entity) -> Entity {
+        let id = C::meta().id;
+        if !entity.archetype().iter().any(|meta| meta.id == id) {
+            return entity;
+        }
+        self.migrate(entity, C::meta(), vec![])
+    }
+
+    /// Stores `value` as the resource of its type, replacing the one there.
+    pub fn insert_resource<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.resources.insert(value);
+    }
+
+    pub fn resource<T: 'static>(&self) -> Option<&T> {
+        self.resources.get()
+    }
+
+    pub fn resource_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.resources.get_mut()
+    }
+
+    /// Adds the [`Events<T>`] resource, updated at the end of every schedule
+    /// run. Adding it again keeps the events already there.
+    pub fn add_event<T: Send + Sync + 'static>(&mut self) {
+        if self.resources.contains(TypeId::of::<Events<T>>()) {
+            return;
+        }
+        self.resources.insert(Events::<T>::default());
+        self.updates.push(|resources| {
+            if let Some(events) = resources.get_mut::<Events<T>>() {
+                events.update();
+            }
+        });
+    }
+
+    /// Ends a frame: the events sent during it become readable, and those
+    /// sent the frame before last are dropped.
+    pub(crate) fn update_events(&mut self) {
+        for update in &self.updates {
+            update(&mut self.resources);
+        }
+    }
+
+    /// Re-reads the links of `owner` after they were changed in place.
+    pub fn relink(&mut self, owner: Entity) {
+        self.debug_assert_owns(owner);
+        self.links.forget_owner(owner);
+        self.index_links(owner);
+    }
+
+    /// Makes `parent` the parent of `child`, taking it from the one it had,
+    /// and returns the child's new handle. Both may move to another
+    /// archetype for their [`Parent`] and [`Children`], so other handles to
+    /// them can go stale, see [`World::reserve`]. A `parent` that is `child`
+    /// or descends from it is rejected.
+    pub fn set_parent(&mut self, child: Entity, parent: Entity) -> Result<Entity, HierarchyError> {
+        self.debug_assert_owns(child);
+        self.debug_assert_owns(parent);
+        assert!(
+            self.contains(child) && self.contains(parent),
+            "entities should be alive in their world"
+        );
+        if self.hierarchy.descends(parent, child) {
+            return Err(HierarchyError::Cycle { child, parent });
+        }
+        if self.hierarchy.parent(child) == Some(parent) {
+            return Ok(child);
+        }
+        let previous = self.hierarchy.attach(child, parent);
+        Ok(self.rehome_all([child, parent].into_iter().chain(previous)))
+    }
+
+    /// Takes `child` from its parent, if it has one, making it a root, and
+    /// returns its new handle.
+    pub fn remove_parent(&mut self, child: Entity) -> Entity {
+        self.debug_assert_owns(child);
+        match self.hierarchy.detach(child) {
+            Some(parent) => self.rehome_all([child, parent]),
+            None => child,
+        }
+    }
+
+    /// Sets what happens to the children of entities despawned from now on.
+    pub fn set_orphan_policy(&mut self, policy: OrphanPolicy) {
+        self.hierarchy.orphans = policy;
+    }
+
+    /// Despawns `entities` and drops their components. The last entity of
+    /// each page moves into the row freed there: its handle goes stale, but
+    /// its links and reserved id follow it. Entities already gone are skipped.
+    /// Their children are detached or despawned along, as the
+    /// [`OrphanPolicy`] says.
+    pub fn despawn(&mut self, entities: impl IntoIterator<Item = Entity>) {
+        self.despawn_all(entities.into_iter().collect(), false);
+    }
+
+    /// Despawns `entity` and all its descendants, whatever the
+    /// [`OrphanPolicy`], and returns how many there were.
+    pub fn despawn_recursive(&mut self, entity: Entity) -> usize {
+        self.despawn_all(vec![entity], true)
+    }
+
+    /// Despawns `entities`, with their descendants if `recursive`, and
+    /// returns how many of them were alive.
+    fn despawn_all(&mut self, mut entities: Vec<Entity>, recursive: bool) -> usize {
+        entities.iter().for_each(|&entity| self.debug_assert_owns(entity));
+        if recursive || self.hierarchy.orphans == OrphanPolicy::Despawn {
+            entities = self.hierarchy.with_descendants(entities);
+        }
+        self.unlink(&entities);
+        // Those losing their parent or a child catch up once the dead are gone
+        let mut pinned = vec![];
+        let mut survivors = vec![];
+        for entity in self.hierarchy.despawn(&entities) {
+            if self.contains(entity) {
+                survivors.push(self.pin(entity, &mut pinned));
+            }
+        }
+
+        // Later entities may be moved by despawning the earlier ones
+        let mut moved = HashMap::new();
+        let mut count = 0;
+        for mut entity in entities {
+            while let Some(&to) = moved.get(&entity) {
+                entity = to;
+            }
+            self.forget_reserved(entity);
+            let Some((mut handles, relocated)) = self.registry.take(entity) else {
+                continue;
+            };
+            count += 1;
+            Self::removing(entity, handles.iter_mut());
+            drop(handles);
+            if let Some(Moved { from, to }) = relocated {
+                self.follow(from, to);
+                moved.insert(from, to);
+            }
+        }
+
+        for reserved in survivors {
+            if let Some(entity) = self.spawned(reserved) {
+                self.rehome(entity);
+            }
+        }
+        self.unpin(pinned);
+        count
+    }
+
+    /// Despawns every entity, running the hooks of their components, but
+    /// keeps the tables, resources, links and trait impls registered, so
+    /// spawning again sets nothing up anew. For wiping a world between
+    /// benchmark iterations or tests.
+    pub fn clear(&mut self) {
+        self.despawn_filtered::<()>();
+    }
+
+    /// Despawns every entity `F` lets through, e.g. those `With<Level>` when
+    /// the level unloads, and returns how many there were, children despawned
+    /// along by the [`OrphanPolicy`] included.
+    pub fn despawn_filtered<F: Filter>(&mut self) -> usize {
+        // From the last row back, so no despawn moves a row into a gap
+        let entities = self
+            .registry
+            .tables()
+            .filter(|table| F::admits(table.archetype()))
+            .flat_map(|table| {
+                (0..table.count())
+                    .rev()
+                    .filter(move |&row| F::matches(table, row))
+                    .filter_map(move |row| table.entity(row))
+            })
+            .collect::<Vec<_>>();
+        self.despawn_all(entities, false)
+    }
+
+    /// How many entities are alive in the world.
+    pub fn entity_count(&self) -> usize {
+        self.registry.tables().map(Table::count).sum()
+    }
+
+    /// Every archetype the world has a table for, with how many entities it
+    /// holds. Tables stay when their last entity despawns.
+    pub fn archetype_counts(&self) -> impl Iterator<Item = (&Archetype, usize)> {
+        self.registry
+            .tables()
+            .map(|table| (table.archetype(), table.count()))
+    }
+
+    pub(crate) fn context(&self, system: Id) -> Context {
+        Context {
+            commands: self.commands.clone(),
+            resources: self.resources.clone(),
+            traits: self.traits.clone(),
+            system,
+        }
+    }
+
+    /// Ends a stage: applies the commands its systems queued.
+    pub(crate) fn sync(&mut self) {
+        self.apply_commands();
+    }
+
+    /// Applies the queued commands in order. Those naming an entity that is
+    /// gone by then, e.g. despawned by an earlier one, are dropped.
+    fn apply_commands(&mut self) {
+        let mut commands = self.commands.drain();
+        // Entities move as others despawn or migrate, so for the pass every
+        // one is named by a reserved id, which follows it
+        let mut pinned = vec![];
+        for command in &mut commands {
+            for target in command.targets() {
+                if let Target::Entity(entity) = *target
+                    && self.contains(entity)
+                {
+                    *target = Target::Reserved(self.pin(entity, &mut pinned));
+                }
+            }
+        }
+
+        for command in commands {
+            match command {
+                Command::Spawn(reserved, spawn) => {
+                    let entity = spawn(self);
+                    self.spawned.insert(reserved, entity);
+                    self.reserved.insert(entity, reserved);
+                }
+                Command::Despawn(target) => {
+                    if let Some(entity) = self.resolve(target) {
+                        self.despawn([entity]);
+                    }
+                }
+                Command::DespawnRecursive(target) => {
+                    if let Some(entity) = self.resolve(target) {
+                        self.despawn_recursive(entity);
+                    }
+                }
+                Command::Migrate(target, migrate) => {
+                    if let Some(entity) = self.resolve(target) {
+                        migrate(self, entity);
+                    }
+                }
+                Command::SetParent(child, Some(parent)) => {
+                    if let (Some(child), Some(parent)) = (self.resolve(child), self.resolve(parent))
+                        && let Err(error) = self.set_parent(child, parent)
+                        && let Some(events) = self.resources.get::<Events<HierarchyError>>()
+                    {
+                        events.send(error);
+                    }
+                }
+                Command::SetParent(child, None) => {
+                    if let Some(child) = self.resolve(child) {
+                        self.remove_parent(child);
+                    }
+                }
+            }
+        }
+        self.unpin(pinned);
+    }
+
+    /// Names `entity` by a reserved id, which follows it through moves, for
+    /// the length of an operation. Ids made for the purpose are added to
+    /// `pinned`, for [`World::unpin`] to drop once it is done.
+    fn pin(&mut self, entity: Entity, pinned: &mut Vec<Reserved>) -> Reserved {
+        if let Some(&reserved) = self.reserved.get(&entity) {
+            return reserved;
+        }
+        let reserved = self.reserve(entity);
+        pinned.push(reserved);
+        reserved
+    }
+
+    fn unpin(&mut self, pinned: Vec<Reserved>) {
+        for reserved in pinned {
+            if let Some(entity) = self.spawned.remove(&reserved) {
+                self.reserved.remove(&entity);
+            }
+        }
+    }
+
+    fn resolve(&self, target: Target) -> Option<Entity> {
+        let entity = match target {
+            Target::Entity(entity) => entity,
+            Target::Reserved(reserved) => self.spawned(reserved)?,
+        };
+        self.contains(entity).then_some(entity)
+    }
+
+    fn forget_reserved(&mut self, entity: Entity) {
+        if let Some(reserved) = self.reserved.remove(&entity) {
+            self.spawned.remove(&reserved);
+        }
+    }
+
+    /// Frees the row of `entity` and returns its components, pointing into
+    /// their handles.
+    fn take(&mut self, entity: Entity) -> Components<'static> {
+        let archetype = entity.archetype().clone();
+        let (handles, moved) = self
+            .registry
+            .take(entity)
+            .expect("entity should be alive in its world");
+        if let Some(Moved { from, to }) = moved {
+            self.follow(from, to);
+        }
+        // The row is reused right away, but the handles hold its values
+        handles
+            .into_iter()
+            .zip(archetype.iter())
+            .map(|(mut handle, meta)| {
+                let ptr = handle.as_mut_ptr() as *mut u8;
+                (handle, Data { ptr, meta: *meta })
+            })
+            .collect()
+    }
+
+    /// Points what the world tracks about an entity at the row it moved to.
+    fn follow(&mut self, from: Entity, to: Entity) {
+        for site in self.links.relocate(from, to) {
+            self.write_link(site, Link::to(to));
+        }
+        if let Some(reserved) = self.reserved.remove(&from) {
+            self.spawned.insert(reserved, to);
+            self.reserved.insert(to, reserved);
+        }
+
+        self.hierarchy.relocate(from, to);
+        let parent = self.hierarchy.parent(to);
+        let children = self.hierarchy.children(to);
+        if parent.is_some() || !children.is_empty() {
+            // The entity and its relatives name each other by their new handles
+            let relatives = parent.into_iter().chain(children.iter().copied());
+            for entity in [to].into_iter().chain(relatives) {
+                self.write_relatives(entity);
+            }
+        }
+    }
+
+    /// Rewrites the [`Parent`] and [`Children`] `entity` has from the
+    /// hierarchy, in place, once it or one of its relatives moved.
+    fn write_relatives(&self, entity: Entity) {
+        if let Some(parent) = self.hierarchy.parent(entity)
+            && let Some(data) = self.component_ptr::<Parent>(entity)
+        {
+            unsafe { *data = Parent(parent) };
+        }
+        if let Some(data) = self.component_ptr::<Children>(entity) {
+            unsafe { (*data).0 = SmallVec::from_slice(self.hierarchy.children(entity)) };
+        }
+    }
+
+    /// Gives `entity` the [`Parent`] and [`Children`] the hierarchy says it
+    /// has, adding or taking them off, and returns its new handle.
+    fn rehome(&mut self, mut entity: Entity) -> Entity {
+        match self.hierarchy.parent(entity) {
+            Some(parent) => match self.get_mut::<Parent>(entity) {
+                Some(data) => *data = Parent(parent),
+                None => entity = self.insert_component(entity, Parent(parent)),
+            },
+            None => entity = self.remove_component::<Parent>(entity),
+        }
+        let children = SmallVec::from_slice(self.hierarchy.children(entity));
+        if children.is_empty() {
+            entity = self.remove_component::<Children>(entity);
+        } else if let Some(data) = self.get_mut::<Children>(entity) {
+            data.0 = children;
+        } else {
+            entity = self.insert_component(entity, Children(children));
+        }
+        entity
+    }
+
+    /// Like [`World::rehome`] for each of `entities` in turn, following the
+    /// moves of one to the next, and returns the new handle of the first.
+    fn rehome_all(&mut self, entities: impl IntoIterator<Item = Entity>) -> Entity {
+        let mut pinned = vec![];
+        let reserved = entities
+            .into_iter()
+            .map(|entity| self.pin(entity, &mut pinned))
+            .collect::<Vec<_>>();
+        for &reserved in &reserved {
+            let entity = self.spawned[&reserved];
+            self.rehome(entity);
+        }
+        let first = self.spawned[&reserved[0]];
+        self.unpin(pinned);
+        first
+    }
+
+    /// Moves `entity` to the table of its components without `remove` and
+    /// with `add`, which replace those of the same type, and returns its new
+    /// handle. Links keep following it.
+    fn migrate(&mut self, entity: Entity, remove: Meta, add: Components<'static>) -> Entity {
+        self.debug_assert_owns(entity);
+        let mut components = self.take(entity);
+        let added = add.iter().map(|(_, data)| data.meta).collect::<Vec<_>>();
+        let removed = components.extract_if(.., |(_, data)| {
+            data.meta.id == remove.id || added.iter().any(|meta| meta.id == data.meta.id)
+        });
+        for (mut handle, _) in removed {
+            Self::removing(entity, [&mut handle]);
+        }
+        components.extend(add);
+        // Like the archetypes of spawned bundles, by component id
+        components.sort_by_key(|(_, data)| data.meta.id);
+        let archetype = components.iter().map(|(_, data)| data.meta).collect();
+        let moved = self.registry.insert(archetype, components);
+
+        self.inserted(moved, |meta| added.contains(meta));
+        self.links.forget_owner(entity);
+        self.follow(entity, moved);
+        self.index_links(moved);
+        moved
+    }
+
+    /// Moves `entity` with all its components into `dst` and returns its
+    /// handle there. To this world it is despawned; its links into this world
+    /// cannot follow it and are cleared. Its place in a hierarchy stays
+    /// behind too: its children here become roots and it arrives as one,
+    /// without any.
+    pub fn transfer(&mut self, dst: &mut World, entity: Entity) -> Entity {
+        self.debug_assert_owns(entity);
+        assert_ne!(self.id, dst.id, "cannot transfer an entity into its own world");
+        self.unlink(&[entity]);
+        self.forget_reserved(entity);
+        let mut pinned = vec![];
+        let mut relatives = vec![];
+        for relative in self.hierarchy.despawn(&[entity]) {
+            relatives.push(self.pin(relative, &mut pinned));
+        }
+
+        let archetype = entity.archetype().clone();
+        let mut components = self.take(entity);
+        Self::removing(entity, components.iter_mut().map(|(handle, _)| handle));
+        let moved = dst.registry.insert(archetype, components);
+        dst.inserted(moved, |_| true);
+
+        for meta in moved.archetype().iter() {
+            for &(offset, _) in self.links.fields(meta.id) {
+                let site = LinkSite {
+                    owner: moved,
+                    component: meta.id,
+                    offset,
+                };
+                dst.write_link(site, Link::default());
+            }
+        }
+        dst.index_links(moved);
+
+        for reserved in relatives {
+            let relative = self.spawned[&reserved];
+            self.rehome(relative);
+        }
+        self.unpin(pinned);
+        let moved = dst.remove_component::<Parent>(moved);
+        dst.remove_component::<Children>(moved)
+    }
+
+    /// Runs [`Component::on_insert`] for the components of `entity` that
+    /// `added` lets through.
+    pub(crate) fn inserted(&self, entity: Entity, added: impl Fn(&Meta) -> bool) {
+        let Some(table) = self.registry.table_of(entity) else {
+            return;
+        };
+        for (column, meta) in entity.archetype().iter().enumerate() {
+            if added(meta)
+                && let Some(component) = table.component(entity, column)
+            {
+                component.on_insert(entity);
+            }
+        }
+    }
+
+    /// Runs [`Component::on_remove`] for components taken off `entity`.
+    fn removing<'a>(entity: Entity, handles: impl IntoIterator<Item = &'a mut Handle>) {
+        for handle in handles {
+            unsafe { &mut *handle.as_mut_ptr() }.on_remove(entity);
+        }
+    }
+
+    /// Every link currently pointing at `target`.
+    pub fn links_to(&self, target: Entity) -> impl Iterator<Item = LinkSite> + '_ {
+        self.links.links_to(target)
+    }
+
+    pub fn broken_links(&self, owner: Entity) -> &[BrokenLink] {
+        self.links.broken(owner)
+    }
+
+    fn unlink(&mut self, dead: &[Entity]) {
+        for site in self.links.despawn(dead) {
+            self.write_link(site, Link::default());
+        }
+    }
+
+    fn debug_assert_owns(&self, entity: Entity) {
+        debug_assert_eq!(
+            entity.world, self.id,
+            "{entity:?} belongs to another world than {:?}",
+            self.id
+        );
+    }
+
+    pub(crate) fn index_links(&mut self, owner: Entity) {
+        let archetype = owner.archetype().clone();
+        for meta in archetype.iter() {
+            for &(offset, _) in self.links.fields(meta.id).to_vec().iter() {
+                let site = LinkSite {
+                    owner,
+                    component: meta.id,
+                    offset,
+                };
+                if let Some(link) = self.read_link(site) {
+                    self.links.track(site, link);
+                }
+            }
+        }
+    }
+
+    fn link_ptr(&self, site: LinkSite) -> Option<*mut Link> {
+        let archetype = site.owner.archetype();
+        let column = archetype.iter().position(|meta| meta.id == site.component)?;
+        let offset = archetype.offset_of(column) + site.offset;
+        Some(unsafe { site.owner.data().add(offset).cast::<Link>() })
+    }
+
+    pub(crate) fn read_link(&self, site: LinkSite) -> Option<Link> {
+        self.link_ptr(site).map(|link| unsafe { link.read() })
+    }
+
+    pub(crate) fn write_link(&self, site: LinkSite, link: Link) {
+        if let Some(ptr) = self.link_ptr(site) {
+            unsafe { ptr.write(link) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        params,
+        query::{
+            Query,
+            filter::{With, Without},
+        },
+    };
+    use std::{
+        mem,
+        sync::{
+            Mutex,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Velocity(f32, f32);
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Follow {
+        target: Link,
+    }
+
+    /// Records its value when dropped.
+    #[derive(Debug)]
+    struct Tracked(u32);
+
+    static DROPPED: Mutex<Vec<u32>> = Mutex::new(vec![]);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            DROPPED.lock().unwrap().push(self.0);
+        }
+    }
+
+    /// Counts the entities it is part of in `live`, as its hooks hear of it;
+    /// the count of `live` itself tells how many values are left.
+    struct Counted {
+        live: Arc<AtomicUsize>,
+    }
+
+    impl Component for Counted {
+        fn meta() -> Meta {
+            Meta::of::<Self>()
+        }
+
+        fn on_insert(&mut self, _: Entity) {
+            self.live.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_remove(&mut self, _: Entity) {
+            self.live.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    component!(Position, Velocity, Follow, Tracked);
+    component!(@data Counted);
+
+    #[test]
+    fn test_worlds_are_independent() {
+        let (mut a, mut b) = (World::new(), World::new());
+        assert_ne!(a.id(), b.id());
+
+        let pa = a.extend([Position { x: 1.0, y: 2.0 }])[0];
+        let vb = b.extend([Velocity(3.0, 4.0)])[0];
+        let pb = b.extend([Position { x: 5.0, y: 6.0 }])[0];
+
+        assert_eq!(a.get::<Position>(pa), Some(&Position { x: 1.0, y: 2.0 }));
+        assert_eq!(a.get::<Velocity>(pa), None);
+        assert_eq!(b.get::<Velocity>(vb), Some(&Velocity(3.0, 4.0)));
+        assert_eq!(b.get::<Position>(pb), Some(&Position { x: 5.0, y: 6.0 }));
+
+        assert_eq!((pa.world(), pb.world()), (a.id(), b.id()));
+        assert!(format!("{pa:?}").contains(&format!("{:?}", a.id())));
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "belongs to another world")]
+    fn test_cross_world_misuse_panics() {
+        let (mut a, b) = (World::new(), World::new());
+        let entity = a.extend([Position { x: 1.0, y: 2.0 }])[0];
+        b.get::<Position>(entity);
+    }
+
+    #[test]
+    fn test_transfer_preserves_values() {
+        let (mut a, mut b) = (World::new(), World::new());
+        a.register_link::<Follow>(mem::offset_of!(Follow, target), LinkPolicy::Clear);
+        b.extend([Velocity(0.0, 0.0)]);
+
+        let entity = a.extend([Position { x: 1.0, y: 2.0 }])[0];
+        let follower = a.extend([Follow {
+            target: Link::to(entity),
+        }])[0];
+        let leader = a.extend([Velocity(7.0, 8.0)])[0];
+        let follows = a.extend([Follow {
+            target: Link::to(leader),
+        }])[0];
+
+        let moved = a.transfer(&mut b, entity);
+        assert_eq!(moved.world(), b.id());
+        assert_eq!(b.get::<Position>(moved), Some(&Position { x: 1.0, y: 2.0 }));
+        assert_eq!(a.get::<Follow>(follower).unwrap().target.get(), None);
+
+        // Links held by the moved entity point into the world it left
+        let moved = a.transfer(&mut b, follows);
+        assert_eq!(b.get::<Follow>(moved).unwrap().target.get(), None);
+        assert_eq!(a.links_to(leader).count(), 0);
+    }
+
+    #[test]
+    fn test_despawn_keeps_pages_packed() {
+        let mut world = World::new();
+        world.register_link::<Follow>(mem::offset_of!(Follow, target), LinkPolicy::Clear);
+        let entities = world.extend((0..4).map(|i| Position {
+            x: i as f32,
+            y: 0.0,
+        }));
+        let follower = world.extend([Follow {
+            target: Link::to(entities[3]),
+        }])[0];
+
+        world.despawn([entities[0], entities[1]]);
+        let (positions,) = params::<(Query<&'static Position>,)>(&mut world);
+        let mut left = (&positions)
+            .into_iter()
+            .map(|(position,)| position.x)
+            .collect::<Vec<_>>();
+        left.sort_by(f32::total_cmp);
+        assert_eq!(left, [2.0, 3.0]);
+
+        // The last entity took the first row, its old handle is stale
+        assert!(!world.contains(entities[3]));
+        assert_eq!(world.get::<Position>(entities[3]), None);
+        let moved = world.get::<Follow>(follower).unwrap().target.get().unwrap();
+        assert_ne!(moved, entities[3]);
+        assert_eq!(
+            world.get::<Position>(moved),
+            Some(&Position { x: 3.0, y: 0.0 })
+        );
+        assert_eq!(world.links_to(moved).count(), 1);
+    }
+
+    #[test]
+    fn test_components_drop_once_with_their_last_value() {
+        let mut world = World::new();
+        let entities = world.extend((0..4).map(Tracked));
+        let both = world.extend([(Position { x: 0.0, y: 0.0 }, Tracked(20))])[0];
+        let (mut tracked,) = params::<(Query<&'static mut Tracked>,)>(&mut world);
+        for (tracked,) in &mut tracked {
+            tracked.0 += 10;
         }
+
+        world.despawn([entities[0], entities[2], entities[0]]);
+        let position = world.remove_component::<Tracked>(both);
+        assert_eq!(
+            world.get::<Position>(position),
+            Some(&Position { x: 0.0, y: 0.0 })
+        );
+        let replaced = world.insert_component(position, Velocity(1.0, 1.0));
+        assert_eq!(
+            world.get::<Position>(replaced),
+            Some(&Position { x: 0.0, y: 0.0 })
+        );
+        assert_eq!(*DROPPED.lock().unwrap(), [10, 12, 30]);
+    }
+
+    #[test]
+    fn test_get_follows_moves() {
+        let mut world = World::new();
+        let entity = world.extend([Position { x: 1.0, y: 2.0 }])[0];
+        let other = world.extend([Position { x: 3.0, y: 4.0 }])[0];
+        world.get_mut::<Position>(entity).unwrap().x = 5.0;
+
+        let moved = world.insert_component(entity, Velocity(1.0, 0.0));
+        assert_eq!(world.get::<Position>(moved), Some(&Position { x: 5.0, y: 2.0 }));
+        assert_eq!(world.get::<Velocity>(moved), Some(&Velocity(1.0, 0.0)));
+        // Both the moved entity and the one that took its row have new handles
+        assert_eq!(world.get::<Position>(entity), None);
+        assert_eq!(world.get::<Position>(other), None);
+        assert!(world.get_mut::<Position>(other).is_none());
+
+        world.despawn([moved]);
+        assert_eq!(world.get::<Position>(moved), None);
+        assert!(world.get_mut::<Velocity>(moved).is_none());
     }
 
-    pub fn extend(&mut self, src: impl IntoIterator<Item = impl Source>) {
-        self.registry.extend(src);
+    #[test]
+    fn test_reserved_ids_follow_moves() {
+        let mut world = World::new();
+        let entities = world.extend((0..3).map(|i| Position {
+            x: i as f32,
+            y: 0.0,
+        }));
+        let last = world.reserve(entities[2]);
+        assert_eq!(world.reserve(entities[2]), last);
+
+        world.despawn([entities[0]]);
+        let moved = world.spawned(last).unwrap();
+        assert_eq!(world.get::<Position>(moved), Some(&Position { x: 2.0, y: 0.0 }));
+        let moved = world.insert_component(moved, Velocity(0.0, 0.0));
+        assert_eq!(world.spawned(last), Some(moved));
+
+        world.despawn([moved]);
+        assert_eq!(world.spawned(last), None);
+    }
+
+    #[test]
+    fn test_hooks_and_drops() {
+        let live = Arc::new(AtomicUsize::new(0));
+        let counted = || Counted { live: live.clone() };
+        let counts =
+            |live: &Arc<AtomicUsize>| (live.load(Ordering::Relaxed), Arc::strong_count(live));
+
+        let mut world = World::new();
+        let entities = world.extend((0..3).map(|_| counted()));
+        world.spawn_batch((0..2).map(|i| {
+            (
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                counted(),
+            )
+        }));
+        assert_eq!(counts(&live), (5, 6));
+
+        // Moving keeps the component, replacing it drops the old one
+        let moved = world.insert_component(entities[0], Velocity(0.0, 0.0));
+        assert_eq!(counts(&live), (5, 6));
+        let moved = world.insert_component(moved, counted());
+        assert_eq!(counts(&live), (5, 6));
+        world.remove_component::<Counted>(moved);
+        assert_eq!(counts(&live), (4, 5));
+
+        // The first move left the second entity in its row
+        world.despawn([entities[1]]);
+        assert_eq!(counts(&live), (3, 4));
+
+        drop(world);
+        assert_eq!(counts(&live), (3, 1));
+    }
+
+    #[test]
+    fn test_spawn_batch_matches_extend() {
+        let bundle = |i: usize| {
+            (
+                Position {
+                    x: i as f32,
+                    y: -(i as f32),
+                },
+                Velocity(1.0, i as f32),
+            )
+        };
+        let mut extended = World::new();
+        for chunk in (0..5000).collect::<Vec<_>>().chunks(1000) {
+            extended.extend(chunk.iter().copied().map(bundle));
+        }
+        let mut batched = World::new();
+        let entities = batched.spawn_batch((0..5000).map(bundle));
+        assert_eq!(entities.len(), 5000);
+        assert_eq!(batched.get::<Velocity>(entities[4321]), Some(&Velocity(1.0, 4321.0)));
+
+        let rows = |world: &mut World| {
+            let (query,) = params::<(Query<(&'static Position, &'static Velocity)>,)>(world);
+            let mut rows = (&query)
+                .into_iter()
+                .map(|(position, velocity)| (position.x, position.y, velocity.0, velocity.1))
+                .collect::<Vec<_>>();
+            rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+            rows
+        };
+        assert_eq!(rows(&mut extended), rows(&mut batched));
+        assert_eq!(rows(&mut batched).len(), 5000);
+    }
+
+    #[test]
+    fn test_clear_keeps_tables() {
+        let live = Arc::new(AtomicUsize::new(0));
+        let counted = || Counted { live: live.clone() };
+        let counts =
+            |live: &Arc<AtomicUsize>| (live.load(Ordering::Relaxed), Arc::strong_count(live));
+        let fill = |world: &mut World| {
+            world.extend((0..3).map(|_| counted()));
+            world.spawn_batch((0..2000).map(|i| {
+                (
+                    Position {
+                        x: i as f32,
+                        y: 0.0,
+                    },
+                    counted(),
+                )
+            }));
+            world.extend([Velocity(1.0, 1.0)]);
+        };
+
+        let mut world = World::new();
+        world.insert_resource(7u32);
+        fill(&mut world);
+        assert_eq!(world.entity_count(), 2004);
+        assert_eq!(counts(&live), (2003, 2004));
+        let mut archetypes = world
+            .archetype_counts()
+            .map(|(archetype, count)| (archetype.clone(), count))
+            .collect::<Vec<_>>();
+        archetypes.sort();
+        assert_eq!(
+            archetypes.iter().map(|(_, count)| *count).sum::<usize>(),
+            2004
+        );
+
+        world.clear();
+        assert_eq!(world.entity_count(), 0);
+        assert!(world.archetype_counts().all(|(_, count)| count == 0));
+        assert_eq!(world.archetype_counts().count(), archetypes.len());
+        assert_eq!(counts(&live), (0, 1));
+        assert_eq!(world.resource::<u32>(), Some(&7));
+
+        // Spawning again fills the same tables
+        fill(&mut world);
+        let mut refilled = world
+            .archetype_counts()
+            .map(|(archetype, count)| (archetype.clone(), count))
+            .collect::<Vec<_>>();
+        refilled.sort();
+        assert_eq!(refilled, archetypes);
+        assert_eq!(counts(&live), (2003, 2004));
+    }
+
+    #[test]
+    fn test_despawn_filtered() {
+        let mut world = World::new();
+        world.register_link::<Follow>(mem::offset_of!(Follow, target), LinkPolicy::Clear);
+        let moving = world.extend((0..5).map(|i| {
+            (
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+                Velocity(1.0, 0.0),
+            )
+        }));
+        let still = world.extend((0..3).map(|i| Position {
+            x: i as f32,
+            y: 1.0,
+        }));
+        let follower = world.extend([Follow {
+            target: Link::to(moving[2]),
+        }])[0];
+
+        assert_eq!(world.despawn_filtered::<With<Velocity>>(), 5);
+        assert_eq!(world.entity_count(), 4);
+        assert!((0..moving.len()).all(|i| !world.contains(moving[i])));
+        // Nothing that stays was moved
+        assert!((0..still.len()).all(|i| world.contains(still[i])));
+        assert_eq!(world.get::<Follow>(follower).unwrap().target.get(), None);
+
+        assert_eq!(world.despawn_filtered::<(With<Position>, Without<Velocity>)>(), 3);
+        assert_eq!(world.despawn_filtered::<With<Position>>(), 0);
+        assert_eq!(world.entity_count(), 1);
+        assert!(world.contains(follower));
     }
 }
@@ -0,0 +1,217 @@
+use crate::message::{Method, Request, Response};
+use crate::middleware::Middleware;
+
+/// Headers a 304 keeps from the full response (RFC 9110 §15.4.5).
+const NOT_MODIFIED_HEADERS: [&str; 6] = [
+    "cache-control",
+    "content-location",
+    "date",
+    "etag",
+    "expires",
+    "vary",
+];
+
+/// Strong entity tag of a response body.
+pub fn etag(body: &[u8]) -> String {
+    format!("\"{:016x}\"", hash::xxhash64(body, 0))
+}
+
+/// Adds a strong ETag to successful GET/HEAD responses and turns them into
+/// body-less 304s when `If-None-Match` already names it. Handlers may set
+/// their own ETag, which is then used as-is.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheValidation;
+
+impl Middleware for CacheValidation {
+    fn respond(&self, req: &Request, mut res: Response) -> Response {
+        if !matches!(req.method, Method::Get | Method::Head) || res.status != 200 {
+            return res;
+        }
+
+        if !res.headers.contains("etag") {
+            res.headers.insert("ETag", etag(&res.body));
+        }
+        let current = res.headers.get("etag").unwrap_or_default().to_string();
+
+        if if_none_match(req).is_some_and(|tags| matches_any(&tags, &current, false)) {
+            return not_modified(res);
+        }
+
+        if req.method == Method::Head {
+            res.headers.insert("Content-Length", res.body.len().to_string());
+            res.body = Default::default();
+        }
+        res
+    }
+}
+
+/// Evaluates `If-Match` and `If-None-Match` against the resource's current
+/// ETag before a handler does expensive work. Returns the response to send
+/// instead (304 or 412), or `None` if the request should proceed.
+pub fn precondition(req: &Request, current_etag: &str) -> Option<Response> {
+    if let Some(tags) = if_match(req)
+        && !matches_any(&tags, current_etag, true)
+    {
+        return Some(Response::new(412));
+    }
+
+    if let Some(tags) = if_none_match(req)
+        && matches_any(&tags, current_etag, false)
+    {
+        return Some(if req.method.is_safe() {
+            Response::new(304).header("ETag", current_etag)
+        } else {
+            Response::new(412)
+        });
+    }
+
+    None
+}
+
+fn not_modified(res: Response) -> Response {
+    let mut headers = res.headers;
+    headers.retain(|name, _| {
+        NOT_MODIFIED_HEADERS
+            .iter()
+            .any(|keep| keep.eq_ignore_ascii_case(name))
+    });
+    Response {
+        status: 304,
+        headers,
+        body: Default::default(),
+    }
+}
+
+fn if_match(req: &Request) -> Option<Vec<&str>> {
+    tag_list(req, "if-match")
+}
+
+fn if_none_match(req: &Request) -> Option<Vec<&str>> {
+    tag_list(req, "if-none-match")
+}
+
+/// Collects the comma separated entity tags of every instance of a header.
+fn tag_list<'a>(req: &'a Request, name: &'a str) -> Option<Vec<&'a str>> {
+    let mut values = req.headers.get_all(name).peekable();
+    values.peek()?;
+    Some(
+        values
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .collect(),
+    )
+}
+
+/// Compares a tag list against the current tag. `*` matches any existing
+/// resource; strong comparison rejects weak validators on either side.
+fn matches_any(tags: &[&str], current: &str, strong: bool) -> bool {
+    let (current_weak, current) = split_weak(current);
+    tags.iter().any(|tag| {
+        if *tag == "*" {
+            return true;
+        }
+        let (weak, opaque) = split_weak(tag);
+        opaque == current && !(strong && (weak || current_weak))
+    })
+}
+
+fn split_weak(tag: &str) -> (bool, &str) {
+    match tag.strip_prefix("W/") {
+        Some(opaque) => (true, opaque),
+        None => (false, tag),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = r#"{"players":3}"#;
+
+    fn ok() -> Response {
+        Response::new(200)
+            .header("Content-Type", "application/json")
+            .header("Cache-Control", "max-age=60")
+            .body(BODY)
+    }
+
+    #[test]
+    fn test_not_modified_round_trip() {
+        let first = CacheValidation.respond(&Request::new(Method::Get, "/state"), ok());
+        assert_eq!(first.status, 200);
+        let tag = first.headers.get("etag").unwrap().to_string();
+        assert_eq!(tag, etag(BODY.as_bytes()));
+
+        let req = Request::new(Method::Get, "/state").header("If-None-Match", tag.clone());
+        let second = CacheValidation.respond(&req, ok());
+        assert_eq!(second.status, 304);
+        assert!(second.body.is_empty());
+        assert_eq!(second.headers.get("etag"), Some(tag.as_str()));
+        assert_eq!(second.headers.get("cache-control"), Some("max-age=60"));
+        assert!(!second.headers.contains("content-type"));
+    }
+
+    #[test]
+    fn test_if_none_match_lists() {
+        let tag = etag(BODY.as_bytes());
+        let list = format!("\"stale\", W/{tag}");
+        let req = Request::new(Method::Get, "/state").header("If-None-Match", list);
+        assert_eq!(CacheValidation.respond(&req, ok()).status, 304);
+
+        let req = Request::new(Method::Get, "/state")
+            .header("If-None-Match", "\"a\"")
+            .header("If-None-Match", tag);
+        assert_eq!(CacheValidation.respond(&req, ok()).status, 304);
+
+        let req = Request::new(Method::Get, "/state").header("If-None-Match", "*");
+        assert_eq!(CacheValidation.respond(&req, ok()).status, 304);
+
+        let req = Request::new(Method::Get, "/state").header("If-None-Match", "\"a\", \"b\"");
+        assert_eq!(CacheValidation.respond(&req, ok()).status, 200);
+    }
+
+    #[test]
+    fn test_if_match_precondition_failed() {
+        let current = "\"v2\"";
+        let req = Request::new(Method::Put, "/state").header("If-Match", "\"v1\"");
+        assert_eq!(precondition(&req, current).unwrap().status, 412);
+
+        let req = Request::new(Method::Put, "/state").header("If-Match", "W/\"v2\"");
+        assert_eq!(precondition(&req, current).unwrap().status, 412);
+
+        let req = Request::new(Method::Put, "/state").header("If-Match", "\"v1\", \"v2\"");
+        assert!(precondition(&req, current).is_none());
+
+        let req = Request::new(Method::Delete, "/state").header("If-Match", "*");
+        assert!(precondition(&req, current).is_none());
+
+        let req = Request::new(Method::Put, "/state").header("If-None-Match", "*");
+        assert_eq!(precondition(&req, current).unwrap().status, 412);
+
+        let req = Request::new(Method::Get, "/state").header("If-None-Match", current);
+        let res = precondition(&req, current).unwrap();
+        assert_eq!(res.status, 304);
+        assert_eq!(res.headers.get("etag"), Some(current));
+    }
+
+    #[test]
+    fn test_head_has_headers_without_body() {
+        let res = CacheValidation.respond(&Request::new(Method::Head, "/state"), ok());
+        assert_eq!(res.status, 200);
+        assert!(res.body.is_empty());
+        assert_eq!(res.headers.get("etag"), Some(etag(BODY.as_bytes()).as_str()));
+        assert_eq!(res.headers.get("content-length"), Some("13"));
+        assert_eq!(res.headers.get("cache-control"), Some("max-age=60"));
+    }
+
+    #[test]
+    fn test_handler_etag_and_non_cacheable() {
+        let res = ok().header("ETag", "\"custom\"");
+        let req = Request::new(Method::Get, "/state").header("If-None-Match", "\"custom\"");
+        assert_eq!(CacheValidation.respond(&req, res).status, 304);
+
+        let req = Request::new(Method::Post, "/state");
+        assert!(!CacheValidation.respond(&req, ok()).headers.contains("etag"));
+    }
+}
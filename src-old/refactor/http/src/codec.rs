@@ -0,0 +1,295 @@
+//! HTTP/1.1 on the wire: request heads and bodies read off a connection,
+//! responses written back.
+
+use std::io::{self, BufRead, Read, Write};
+
+use crate::message::{Headers, Request, Response};
+use crate::server::status;
+
+/// Longest request or header line read, in bytes.
+const MAX_LINE: usize = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    /// The client sent something that is not HTTP/1.x, answered with a 400.
+    #[error("malformed request: {0}")]
+    Malformed(String),
+    /// The body is longer than the limit, answered with a 413 unread.
+    #[error("body of {0} bytes is too large")]
+    TooLarge(u64),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// The longest request body the server reads, 1 MiB unless a `BodyLimit`
+/// resource of the world passed to [`serve`](crate::server::serve) says
+/// otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimit(pub usize);
+
+impl Default for BodyLimit {
+    fn default() -> Self {
+        Self(1024 * 1024)
+    }
+}
+
+/// A request and whether its connection stays open once it is answered.
+#[derive(Debug)]
+pub struct Incoming {
+    pub request: Request,
+    pub keep_alive: bool,
+}
+
+fn malformed<T>(reason: impl Into<String>) -> Result<T, ParseError> {
+    Err(ParseError::Malformed(reason.into()))
+}
+
+/// Reads the next request off `reader`, or `None` if the connection closed
+/// in between requests. Bodies are read by `Content-Length`, up to `limit`
+/// bytes; chunked bodies are not supported and count as malformed.
+pub fn read_request(
+    reader: &mut impl BufRead,
+    limit: BodyLimit,
+) -> Result<Option<Incoming>, ParseError> {
+    // Clients may send an empty line after the body of the previous request
+    let line = loop {
+        match read_line(reader)? {
+            None => return Ok(None),
+            Some(line) if line.is_empty() => continue,
+            Some(line) => break line,
+        }
+    };
+
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return malformed(format!("request line {line:?}"));
+    };
+    let method = method.parse().or_else(malformed)?;
+    if !target.starts_with('/') && target != "*" {
+        return malformed(format!("request target {target:?}"));
+    }
+    let http10 = match version {
+        "HTTP/1.1" => false,
+        "HTTP/1.0" => true,
+        _ => return malformed(format!("version {version:?}")),
+    };
+
+    let mut request = Request::new(method, target);
+    request.headers = read_headers(reader)?;
+    if request.headers.contains("Transfer-Encoding") {
+        return malformed("chunked bodies are not supported");
+    }
+    let length = {
+        let mut lengths = request.headers.get_all("Content-Length");
+        match (lengths.next(), lengths.next()) {
+            (None, _) => 0,
+            (Some(length), None) => match length.parse::<u64>() {
+                Ok(length) => length,
+                Err(_) => return malformed(format!("Content-Length {length:?}")),
+            },
+            (Some(_), Some(_)) => return malformed("more than one Content-Length"),
+        }
+    };
+    if length > limit.0 as u64 {
+        return Err(ParseError::TooLarge(length));
+    }
+    let mut body = vec![];
+    reader.by_ref().take(length).read_to_end(&mut body)?;
+    if body.len() as u64 != length {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    request.body = body.into();
+
+    let connection = request.headers.get("Connection").unwrap_or("");
+    let has = |token: &str| {
+        connection
+            .split(',')
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+    let keep_alive = if http10 {
+        has("keep-alive")
+    } else {
+        !has("close")
+    };
+    Ok(Some(Incoming {
+        request,
+        keep_alive,
+    }))
+}
+
+fn read_headers(reader: &mut impl BufRead) -> Result<Headers, ParseError> {
+    let mut headers = Headers::default();
+    for _ in 0..=MAX_HEADERS {
+        let Some(line) = read_line(reader)? else {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        };
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return malformed(format!("header {line:?}"));
+        };
+        // Also rules out obsolete line folding, which starts with whitespace
+        if name.is_empty() || name.contains(|c: char| c.is_ascii_whitespace()) {
+            return malformed(format!("header name {name:?}"));
+        }
+        headers.append(name, value.trim());
+    }
+    malformed(format!("more than {MAX_HEADERS} headers"))
+}
+
+/// The next line without its line ending, or `None` at the end of the stream.
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>, ParseError> {
+    let mut line = vec![];
+    reader
+        .by_ref()
+        .take(MAX_LINE as u64)
+        .read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        return match line.len() + 1 {
+            MAX_LINE => malformed(format!("line longer than {MAX_LINE} bytes")),
+            _ => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        };
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    match String::from_utf8(line) {
+        Ok(line) => Ok(Some(line)),
+        Err(_) => malformed("line is not UTF-8"),
+    }
+}
+
+/// Writes `response` with its body length, and asks the client to close the
+/// connection unless `keep_alive`.
+pub fn write_response(
+    writer: &mut impl Write,
+    response: &Response,
+    keep_alive: bool,
+) -> io::Result<()> {
+    let reason = status::of(response.status).map_or("", |code| code.reason());
+    let mut head = format!("HTTP/1.1 {} {reason}\r\n", response.status);
+    for (name, value) in response.headers.iter() {
+        if !name.eq_ignore_ascii_case("Content-Length") && !name.eq_ignore_ascii_case("Connection")
+        {
+            head += &format!("{name}: {value}\r\n");
+        }
+    }
+    head += &format!("Content-Length: {}\r\n", response.body.len());
+    if !keep_alive {
+        head += "Connection: close\r\n";
+    }
+    head += "\r\n";
+    writer.write_all(head.as_bytes())?;
+    writer.write_all(&response.body)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Method;
+
+    fn read(bytes: &[u8]) -> Result<Option<Incoming>, ParseError> {
+        read_request(&mut &bytes[..], BodyLimit::default())
+    }
+
+    fn is_malformed(bytes: &[u8]) -> bool {
+        matches!(read(bytes), Err(ParseError::Malformed(_)))
+    }
+
+    #[test]
+    fn test_request_head_and_body() {
+        let incoming = read(b"POST /items?sort=name HTTP/1.1\r\nHost: example.com\r\nContent-Length: 4\r\nX-Tag: a\r\nx-tag:  b \r\n\r\nbodyGET")
+            .unwrap()
+            .unwrap();
+        let request = incoming.request;
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.path, "/items?sort=name");
+        assert_eq!(request.headers.get("host"), Some("example.com"));
+        assert_eq!(
+            request.headers.get_all("X-Tag").collect::<Vec<_>>(),
+            ["a", "b"]
+        );
+        assert_eq!(&request.body[..], b"body");
+        assert!(incoming.keep_alive);
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        let keep_alive = |bytes: &[u8]| read(bytes).unwrap().unwrap().keep_alive;
+        assert!(keep_alive(b"GET / HTTP/1.1\n\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.1\r\nConnection: Close\r\n\r\n"));
+        assert!(!keep_alive(b"GET / HTTP/1.0\r\n\r\n"));
+        assert!(keep_alive(
+            b"GET / HTTP/1.0\r\nConnection: upgrade, keep-alive\r\n\r\n"
+        ));
+    }
+
+    #[test]
+    fn test_malformed_requests() {
+        assert!(is_malformed(b"GET /\r\n\r\n"));
+        assert!(is_malformed(b"GET / HTTP/2.0\r\n\r\n"));
+        assert!(is_malformed(b"BREW /pot HTTP/1.1\r\n\r\n"));
+        assert!(is_malformed(b"GET  / HTTP/1.1\r\n\r\n"));
+        assert!(is_malformed(b"GET / HTTP/1.1\r\nHost example.com\r\n\r\n"));
+        assert!(is_malformed(
+            b"GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n"
+        ));
+        assert!(is_malformed(
+            b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"
+        ));
+        assert!(is_malformed(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"
+        ));
+        assert!(is_malformed(
+            &[b"GET /".as_slice(), &[b'a'; MAX_LINE]].concat()
+        ));
+
+        // A client hanging up is not answered
+        assert!(matches!(read(b""), Ok(None)));
+        assert!(matches!(
+            read(b"GET / HTTP/1.1\r\nHost"),
+            Err(ParseError::Io(_))
+        ));
+        assert!(matches!(
+            read(b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nshort"),
+            Err(ParseError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_body_limit() {
+        let request = b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello";
+        let read = |limit| read_request(&mut &request[..], BodyLimit(limit));
+        assert_eq!(&read(5).unwrap().unwrap().request.body[..], b"hello");
+        assert!(matches!(read(4), Err(ParseError::TooLarge(5))));
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut written = vec![];
+        let response = Response::new(404)
+            .header("Content-Type", "text/plain")
+            .header("Content-Length", "99")
+            .body("gone");
+        write_response(&mut written, &response, false).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "HTTP/1.1 404 NotFound\r\nContent-Type: text/plain\r\nContent-Length: 4\r\nConnection: close\r\n\r\ngone"
+        );
+
+        let mut written = vec![];
+        write_response(&mut written, &Response::new(299), true).unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            "HTTP/1.1 299 \r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use ecs::component::component;
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+
+/// Where a request came from, captured when its connection is accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[component]
+pub struct ConnectionInfo {
+    pub peer_addr: SocketAddr,
+    pub local_addr: SocketAddr,
+    /// Present for connections that went through the TLS handshake.
+    pub tls: Option<TlsInfo>,
+}
+
+/// What the TLS handshake negotiated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    pub alpn: Option<String>,
+    pub sni: Option<String>,
+    /// Leaf certificate the client presented, if client auth is enabled.
+    pub peer_cert_der: Option<Vec<u8>>,
+    /// Distinguished name of `peer_cert_der`, e.g. `CN=alice, O=Example`.
+    pub peer_cert_subject: Option<String>,
+}
+
+impl ConnectionInfo {
+    pub fn plaintext(stream: &TcpStream) -> io::Result<Self> {
+        Ok(Self {
+            peer_addr: stream.peer_addr()?,
+            local_addr: stream.local_addr()?,
+            tls: None,
+        })
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+}
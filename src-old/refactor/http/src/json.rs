@@ -0,0 +1,186 @@
+//! JSON request bodies and responses.
+
+use ecs::component::archetype::Archetype;
+use ecs::component::table::Table;
+use ecs::query::{Query, filter::With};
+use ecs::system::param::{Borrow, Context, Param};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::marker::PhantomData;
+
+use crate::message::{IntoResponse, Request, Response};
+use crate::route::RouteMatch;
+use crate::server::Pending;
+use crate::server::status::{Code, Ok as Success};
+
+const CONTENT_TYPE: &str = "application/json";
+
+/// A value read from or written as a JSON body. Answering with `Json(value)`
+/// responds 200, with `(status::Created, Json(value))` the status given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Json<T>(pub T);
+
+impl<T: DeserializeOwned> Json<T> {
+    /// Deserializes the body of `req`. Requests without a JSON content type
+    /// are rejected with a 415, bodies that do not deserialize with a 422.
+    pub fn from_request(req: &Request) -> Result<Self, Response> {
+        let content_type = req
+            .headers
+            .get("Content-Type")
+            .and_then(|content_type| content_type.split(';').next())
+            .map(str::trim);
+        if !content_type.is_some_and(|content_type| content_type.eq_ignore_ascii_case(CONTENT_TYPE))
+        {
+            return Err(error(415, format!("expected an {CONTENT_TYPE} body")));
+        }
+        serde_json::from_slice(&req.body)
+            .map(Json)
+            .map_err(|err| error(422, err.to_string()))
+    }
+}
+
+fn error(status: u16, message: String) -> Response {
+    let mut res = Json(serde_json::json!({ "error": message })).into_response();
+    res.status = status;
+    res
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        (Success, self).into_response()
+    }
+}
+
+impl<C: Code, T: Serialize> IntoResponse for (C, Json<T>) {
+    fn into_response(self) -> Response {
+        let (code, Json(value)) = self;
+        match serde_json::to_vec(&value) {
+            Ok(body) => Response::new(code.code())
+                .header("Content-Type", CONTENT_TYPE)
+                .body(body),
+            Err(err) => Response::new(500).body(err.to_string()),
+        }
+    }
+}
+
+type Bodies<'a> =
+    Query<'a, (&'static Request, &'static RouteMatch, &'static mut Response), With<Pending>>;
+
+/// Hands a system the pending requests routed to a handler, with their JSON
+/// bodies deserialized into `T`, see [`Json::from_request`].
+pub struct JsonBody<'a, T> {
+    requests: Bodies<'a>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> JsonBody<'_, T> {
+    /// Calls `respond` for every request routed to `handler`. Those whose
+    /// bodies are rejected are answered with the rejection instead.
+    pub fn each(&mut self, handler: &str, mut respond: impl FnMut(Json<T>, &mut Response)) {
+        for (request, route, response) in &mut self.requests {
+            if route.handler != handler {
+                continue;
+            }
+            match Json::from_request(request) {
+                Ok(body) => respond(body, response),
+                Err(rejection) => *response = rejection,
+            }
+        }
+    }
+}
+
+impl<'a, T: DeserializeOwned + 'static> Param<'a> for JsonBody<'a, T> {
+    fn inject(archetypes: &mut Vec<Archetype>) {
+        Bodies::inject(archetypes);
+    }
+
+    fn admits(archetype: &Archetype) -> bool {
+        Bodies::admits(archetype)
+    }
+
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        Bodies::borrows(borrows);
+    }
+
+    fn create(
+        archetypes: &'a [Archetype],
+        tables: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
+    where
+        Self: Sized + 'a,
+    {
+        JsonBody {
+            requests: Query::create(archetypes, tables, context),
+            marker: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Method;
+    use crate::server::status::Created;
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Item {
+        name: String,
+        count: u32,
+    }
+
+    fn post(content_type: &str, body: &str) -> Request {
+        let mut req = Request::new(Method::Post, "/items").header("Content-Type", content_type);
+        req.body = body.to_owned().into();
+        req
+    }
+
+    fn rejection(req: &Request) -> (u16, String) {
+        let res = Json::<Item>::from_request(req).unwrap_err();
+        assert_eq!(res.headers.get("Content-Type"), Some(CONTENT_TYPE));
+        (res.status, String::from_utf8(res.body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_happy_path() {
+        let req = post(
+            "application/json; charset=utf-8",
+            r#"{"name":"bolt","count":3}"#,
+        );
+        let Json(item) = Json::<Item>::from_request(&req).unwrap();
+        assert_eq!(
+            item,
+            Item {
+                name: "bolt".into(),
+                count: 3
+            }
+        );
+
+        let res = (Created, Json(item)).into_response();
+        assert_eq!(res.status, 201);
+        assert_eq!(res.headers.get("Content-Type"), Some(CONTENT_TYPE));
+        assert_eq!(&res.body[..], br#"{"name":"bolt","count":3}"#);
+        assert_eq!(Json(1).into_response().status, 200);
+    }
+
+    #[test]
+    fn test_wrong_content_type() {
+        let (status, body) = rejection(&post("text/plain", r#"{"name":"bolt","count":3}"#));
+        assert_eq!(status, 415);
+        assert!(body.contains("expected an application/json body"));
+        let (status, _) = rejection(&Request::new(Method::Post, "/items"));
+        assert_eq!(status, 415);
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        let (status, body) = rejection(&post(CONTENT_TYPE, r#"{"name":"bolt""#));
+        assert_eq!(status, 422);
+        assert!(body.starts_with(r#"{"error":"#));
+
+        let (status, body) = rejection(&post(CONTENT_TYPE, r#"{"name":"bolt","count":-1}"#));
+        assert_eq!(status, 422);
+        assert!(body.contains("expected u32"));
+    }
+}
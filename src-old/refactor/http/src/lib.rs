@@ -1,3 +1,19 @@
 #![feature(set_ptr_value, trait_upcasting, ptr_metadata)]
+pub mod cache;
+pub mod codec;
+pub mod conn;
+pub mod json;
+pub mod message;
+pub mod middleware;
+pub mod route;
 pub mod server;
-pub use server::{Router, serve};
+pub mod tls;
+pub use cache::{CacheValidation, precondition};
+pub use codec::BodyLimit;
+pub use conn::{ConnectionInfo, TlsInfo};
+pub use json::{Json, JsonBody};
+pub use message::{Headers, IntoResponse, Method, Request, Response};
+pub use middleware::{AccessLog, Middleware};
+pub use route::{Path, RouteMatch, Router};
+pub use server::{Pending, listen, serve};
+pub use tls::{CaBundle, ClientAuth, TlsConfig, TlsError};
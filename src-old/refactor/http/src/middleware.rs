@@ -0,0 +1,75 @@
+use std::sync::Arc;
+
+use crate::message::{Request, Response};
+
+/// Post-processes a handler's response before it is written out.
+pub trait Middleware: Send + Sync {
+    fn respond(&self, req: &Request, res: Response) -> Response;
+}
+
+/// Writes one line per response: peer address, request line, status and
+/// body size. The peer is `-` when the request has no connection info.
+#[derive(Clone)]
+pub struct AccessLog {
+    sink: Arc<dyn Fn(&str) + Send + Sync>,
+}
+
+impl AccessLog {
+    pub fn new(sink: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        Self {
+            sink: Arc::new(sink),
+        }
+    }
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self::new(|line| eprintln!("{line}"))
+    }
+}
+
+impl Middleware for AccessLog {
+    fn respond(&self, req: &Request, res: Response) -> Response {
+        let peer = req
+            .connection
+            .as_ref()
+            .map_or("-".to_string(), |conn| conn.peer_addr.to_string());
+        (self.sink)(&format!(
+            "{peer} \"{} {}\" {} {}",
+            req.method,
+            req.path,
+            res.status,
+            res.body.len()
+        ));
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conn::ConnectionInfo;
+    use crate::message::Method;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_access_log_peer() {
+        let lines = Arc::new(Mutex::new(vec![]));
+        let log = AccessLog::new({
+            let lines = lines.clone();
+            move |line| lines.lock().unwrap().push(line.to_owned())
+        });
+        let conn = ConnectionInfo {
+            peer_addr: "203.0.113.7:51234".parse().unwrap(),
+            local_addr: "10.0.0.1:443".parse().unwrap(),
+            tls: None,
+        };
+
+        log.respond(&Request::new(Method::Get, "/state").connection(conn), Response::new(200).body("ok"));
+        log.respond(&Request::new(Method::Post, "/state"), Response::new(404));
+        assert_eq!(
+            *lines.lock().unwrap(),
+            ["203.0.113.7:51234 \"GET /state\" 200 2", "- \"POST /state\" 404 0"]
+        );
+    }
+}
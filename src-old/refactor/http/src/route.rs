@@ -0,0 +1,482 @@
+//! Picks the handler of each request by method and path, see [`Router`].
+
+use ecs::component::archetype::Archetype;
+use ecs::component::component;
+use ecs::component::table::Table;
+use ecs::query::{Query, filter::With};
+use ecs::system::param::{Borrow, Context, Param};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::forward_to_deserialize_any;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::message::{Method, Request, Response};
+use crate::middleware::Middleware;
+use crate::server::Pending;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Static(String),
+    /// `:name`, one segment.
+    Param(String),
+    /// `*name`, the rest of the path, possibly empty.
+    Wildcard(String),
+}
+
+impl Segment {
+    /// Segments of lower rank take precedence.
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Static(_) => 0,
+            Segment::Param(_) => 1,
+            Segment::Wildcard(_) => 2,
+        }
+    }
+}
+
+struct Route {
+    method: Method,
+    segments: Vec<Segment>,
+    handler: String,
+}
+
+impl Route {
+    fn new(method: Method, pattern: &str, handler: String) -> Self {
+        let segments = segments(pattern)
+            .map(|segment| {
+                if let Some(name) = segment.strip_prefix(':') {
+                    Segment::Param(name.to_owned())
+                } else if let Some(name) = segment.strip_prefix('*') {
+                    Segment::Wildcard(name.to_owned())
+                } else {
+                    Segment::Static(segment.to_owned())
+                }
+            })
+            .collect::<Vec<_>>();
+        let wildcards = segments
+            .iter()
+            .position(|segment| matches!(segment, Segment::Wildcard(_)));
+        assert!(
+            wildcards.is_none_or(|at| at == segments.len() - 1),
+            "only the last segment of {pattern:?} may be a wildcard"
+        );
+        Self {
+            method,
+            segments,
+            handler,
+        }
+    }
+
+    /// The parameters `path` binds, if it matches.
+    fn bind(&self, path: &[&str]) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        for (at, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Static(name) => {
+                    if path.get(at) != Some(&name.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.insert(name.clone(), path.get(at)?.to_string());
+                }
+                Segment::Wildcard(name) => {
+                    params.insert(name.clone(), path[at..].join("/"));
+                    return Some(params);
+                }
+            }
+        }
+        (path.len() == self.segments.len()).then_some(params)
+    }
+}
+
+/// The non-empty segments of a path, without its query.
+fn segments(path: &str) -> impl Iterator<Item = &str> {
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+/// Routes requests to handlers by label, e.g.
+/// `Router::new().get("/users/:id", "user").post("/items", "items")`, and
+/// runs middleware over their responses. Insert it as a resource of the
+/// world passed to [`serve`](crate::server::serve).
+///
+/// Patterns are made of static segments, `:name` parameters matching one
+/// segment and a trailing `*name` wildcard matching the rest. Where several
+/// routes match, static segments take precedence over parameters and those
+/// over wildcards, left to right; the first route added wins ties.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route(mut self, method: Method, pattern: &str, handler: impl Into<String>) -> Self {
+        self.routes
+            .push(Route::new(method, pattern, handler.into()));
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Into<String>) -> Self {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Into<String>) -> Self {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Into<String>) -> Self {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn patch(self, pattern: &str, handler: impl Into<String>) -> Self {
+        self.route(Method::Patch, pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Into<String>) -> Self {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Appends a middleware, layers run in the order they were added.
+    pub fn layer(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// The route `req` takes, if any matches.
+    pub fn resolve(&self, req: &Request) -> Option<RouteMatch> {
+        let path = segments(&req.path).collect::<Vec<_>>();
+        self.routes
+            .iter()
+            .filter(|route| route.method == req.method)
+            .filter_map(|route| Some((route, route.bind(&path)?)))
+            .min_by_key(|(route, _)| route.segments.iter().map(Segment::rank).collect::<Vec<_>>())
+            .map(|(route, params)| RouteMatch {
+                handler: route.handler.clone(),
+                params,
+            })
+    }
+
+    pub fn respond(&self, req: &Request, res: Response) -> Response {
+        self.middleware
+            .iter()
+            .fold(res, |res, middleware| middleware.respond(req, res))
+    }
+}
+
+/// The route a request entity took, attached before the schedule runs.
+/// Requests no route matches get [`NotFound`](crate::server::status::NotFound)
+/// instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[component]
+pub struct RouteMatch {
+    pub handler: String,
+    pub params: HashMap<String, String>,
+}
+
+impl RouteMatch {
+    /// Deserializes the captured parameters: a struct or map by name, or the
+    /// only one as a value. Values parse from their text, e.g. `:id` into a
+    /// `u64`.
+    pub fn extract<T: DeserializeOwned>(&self) -> Result<T, PathError> {
+        T::deserialize(Params(&self.params))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("path parameters: {0}")]
+pub struct PathError(String);
+
+impl de::Error for PathError {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        PathError(msg.to_string())
+    }
+}
+
+type Routed<'a> = Query<'a, (&'static RouteMatch, &'static mut Response), With<Pending>>;
+
+/// Hands a system the pending requests routed to a handler, with their path
+/// parameters deserialized into `T`, see [`RouteMatch::extract`].
+pub struct Path<'a, T> {
+    requests: Routed<'a>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> Path<'_, T> {
+    /// Calls `respond` for every request routed to `handler`. Those whose
+    /// parameters do not deserialize are answered with a 400 instead.
+    pub fn each(&mut self, handler: &str, mut respond: impl FnMut(T, &mut Response)) {
+        for (route, response) in &mut self.requests {
+            if route.handler != handler {
+                continue;
+            }
+            match route.extract() {
+                Ok(params) => respond(params, response),
+                Err(error) => *response = Response::new(400).body(error.to_string()),
+            }
+        }
+    }
+}
+
+impl<'a, T: DeserializeOwned + 'static> Param<'a> for Path<'a, T> {
+    fn inject(archetypes: &mut Vec<Archetype>) {
+        Routed::inject(archetypes);
+    }
+
+    fn admits(archetype: &Archetype) -> bool {
+        Routed::admits(archetype)
+    }
+
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        Routed::borrows(borrows);
+    }
+
+    fn create(
+        archetypes: &'a [Archetype],
+        tables: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
+    where
+        Self: Sized + 'a,
+    {
+        Path {
+            requests: Query::create(archetypes, tables, context),
+            marker: PhantomData,
+        }
+    }
+}
+
+/// Deserializes captured parameters by name, or the only one as a value.
+struct Params<'a>(&'a HashMap<String, String>);
+
+impl<'a> Params<'a> {
+    fn single(&self) -> Result<Value<'a>, PathError> {
+        match self.0.values().collect::<Vec<_>>()[..] {
+            [value] => Ok(Value(value)),
+            _ => Err(PathError(format!(
+                "expected one parameter, found {}",
+                self.0.len()
+            ))),
+        }
+    }
+}
+
+macro_rules! single {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+            self.single()?.$method(visitor)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Params<'_> {
+    type Error = PathError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        let params = self
+            .0
+            .iter()
+            .map(|(name, value)| (name.as_str(), Value(value)));
+        visitor.visit_map(de::value::MapDeserializer::new(params))
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_option
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct enum identifier
+        ignored_any
+    }
+}
+
+/// One parameter's text.
+struct Value<'a>(&'a str);
+
+impl<'de> IntoDeserializer<'de, PathError> for Value<'_> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! parse {
+    ($($method:ident => $visit:ident($ty:ty))*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+            match self.0.parse::<$ty>() {
+                Ok(value) => visitor.$visit(value),
+                Err(_) => Err(PathError(format!(
+                    "{:?} is not a valid {}",
+                    self.0,
+                    stringify!($ty)
+                ))),
+            }
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for Value<'_> {
+    type Error = PathError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        visitor.visit_str(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    parse! {
+        deserialize_bool => visit_bool(bool)
+        deserialize_i8 => visit_i8(i8)
+        deserialize_i16 => visit_i16(i16)
+        deserialize_i32 => visit_i32(i32)
+        deserialize_i64 => visit_i64(i64)
+        deserialize_u8 => visit_u8(u8)
+        deserialize_u16 => visit_u16(u16)
+        deserialize_u32 => visit_u32(u32)
+        deserialize_u64 => visit_u64(u64)
+        deserialize_f32 => visit_f32(f32)
+        deserialize_f64 => visit_f64(f64)
+        deserialize_char => visit_char(char)
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    fn router() -> Router {
+        Router::new()
+            .get("/users/:id", "user")
+            .get("/users/me", "me")
+            .get("/users/:id/posts/:post", "post")
+            .get("/files/*path", "file")
+            .get("/files/readme", "readme")
+            .post("/users", "create")
+    }
+
+    fn resolve(method: Method, path: &str) -> Option<RouteMatch> {
+        router().resolve(&Request::new(method, path))
+    }
+
+    fn handler(method: Method, path: &str) -> Option<String> {
+        resolve(method, path).map(|route| route.handler)
+    }
+
+    fn param(path: &str, name: &str) -> String {
+        resolve(Method::Get, path).unwrap().params[name].clone()
+    }
+
+    #[test]
+    fn test_matcher_table() {
+        let table = [
+            (Method::Get, "/users/42", Some("user")),
+            (Method::Get, "/users/me", Some("me")),
+            (Method::Get, "/users/me/", Some("me")),
+            (Method::Get, "/users/7?fields=name", Some("user")),
+            (Method::Get, "/users/7/posts/9", Some("post")),
+            (Method::Get, "/users", None),
+            (Method::Post, "/users", Some("create")),
+            (Method::Delete, "/users/42", None),
+            (Method::Get, "/files", Some("file")),
+            (Method::Get, "/files/readme", Some("readme")),
+            (Method::Get, "/files/docs/readme", Some("file")),
+            (Method::Get, "/", None),
+        ];
+        for (method, path, expected) in table {
+            assert_eq!(
+                handler(method, path).as_deref(),
+                expected,
+                "{method} {path}"
+            );
+        }
+
+        assert_eq!(param("/users/42", "id"), "42");
+        assert_eq!(param("/users/7/posts/9", "post"), "9");
+        assert_eq!(param("/files/docs/a.txt", "path"), "docs/a.txt");
+        assert_eq!(param("/files", "path"), "");
+    }
+
+    #[test]
+    #[should_panic(expected = "only the last segment")]
+    fn test_wildcard_must_be_last() {
+        Router::new().get("/files/*path/raw", "raw");
+    }
+
+    #[test]
+    fn test_extract() {
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Post {
+            id: u64,
+            post: String,
+        }
+
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct UserId(u32);
+
+        let post = resolve(Method::Get, "/users/7/posts/hello").unwrap();
+        assert_eq!(
+            post.extract::<Post>(),
+            Ok(Post {
+                id: 7,
+                post: "hello".into()
+            })
+        );
+        assert_eq!(
+            post.extract::<HashMap<String, String>>().unwrap()["id"],
+            "7"
+        );
+        assert!(post.extract::<u64>().is_err());
+
+        let user = resolve(Method::Get, "/users/42").unwrap();
+        assert_eq!(user.extract::<u64>(), Ok(42));
+        assert_eq!(user.extract::<UserId>(), Ok(UserId(42)));
+        assert_eq!(user.extract::<Option<u8>>(), Ok(Some(42)));
+        let invalid = resolve(Method::Get, "/users/alice").unwrap();
+        assert_eq!(
+            invalid.extract::<u64>(),
+            Err(PathError("\"alice\" is not a valid u64".into()))
+        );
+    }
+}
@@ -1,15 +1,23 @@
-use base::rng::Random;
-use base::sync::mutex::Queue;
+use base::io::net::{TcpListener, TcpStream};
+use base::rt::spawn;
+use base::sync::mpsc::{self, Receiver, Sender};
+use base::sync::oneshot;
 use ecs::component::component;
-use ecs::query::Query;
 use ecs::schedule::Schedule;
-use ecs::system::func::{Blocking, Func, Wrap};
-use ecs::{component::Component, world::World};
+use ecs::world::World;
 use status::Code;
-use std::future::pending;
+use std::io::{self, ErrorKind};
+use std::iter;
+use std::net::ToSocketAddrs;
 
-mod status {
-    use ecs::component::{Component, access::Access, component};
+use crate::codec::{self, BodyLimit, Incoming, ParseError};
+use crate::conn::ConnectionInfo;
+use crate::message::{Request, Response};
+use crate::route::Router;
+
+pub mod status {
+    use ecs::component::{Component, component};
+    use ecs::world::World;
 
     macro_rules! codes {
             (
@@ -25,6 +33,19 @@ mod status {
                     fn reason(&self) -> &'static str;
                 }
 
+                /// Lets `Query<&dyn Code>` read every status of `world`.
+                pub fn register(world: &mut World) {
+                    $(world.register_trait_impl::<dyn Code, $variant_name>();)*
+                }
+
+                /// The status numbered `code`, if it is a standard one.
+                pub fn of(code: u16) -> Option<&'static dyn Code> {
+                    $(if code == $code_value {
+                        return Some(&$variant_name);
+                    })*
+                    None
+                }
+
                 // Define the code structs and implement the trait.
                 $(
                     $(#[$attr])*
@@ -121,29 +142,235 @@ mod status {
     }
 }
 
-pub fn sysa(query: Query<'_, &'_ dyn Code>) {
-    let mut count = 0;
-    let mut deez = 0;
-    for code in &query {
-        if code.0.reason().starts_with("Ok") {
-            count += 1;
-        } else if code.0.reason().starts_with("NotFound") {
-            deez += 1;
+/// Tags request entities until their response is written.
+#[derive(Debug)]
+#[component]
+pub struct Pending;
+
+/// Requests queued for the world before connections wait to hand theirs over.
+const BACKLOG: usize = 1024;
+/// Bytes read off a connection at once.
+const CHUNK: usize = 8 * 1024;
+
+/// A request handed to the world, and where its response goes.
+struct Exchange {
+    request: Request,
+    reply: oneshot::Sender<Response>,
+}
+
+/// Answers HTTP/1.1 requests on `addr` in `world`, see [`listen`].
+pub async fn serve(addr: impl ToSocketAddrs, world: World, schedule: Schedule) -> io::Result<()> {
+    listen(TcpListener::bind(addr).await?, world, schedule).await;
+    Ok(())
+}
+
+/// Answers requests accepted by `listener` in `world`, forever.
+///
+/// Every request becomes an entity of its [`Request`], a [`Response`] set to
+/// 404 and [`Pending`]. If `world` has a [`Router`] resource, the entity also
+/// gets the [`RouteMatch`](crate::route::RouteMatch) of its request, or
+/// [`NotFound`](status::NotFound) if none matches. Requests that arrive
+/// together are spawned together and `schedule` runs once for them; each is
+/// then answered with its entity's response, status taken from its [`Code`]
+/// if it has one and passed through the router's middleware, and despawned.
+/// Malformed requests are answered with a 400 and bodies longer than the
+/// world's [`BodyLimit`] with a 413; neither reaches the world.
+///
+/// Connections are accepted and read by tasks of their own on the runtime,
+/// so this has to be awaited while its workers run.
+pub async fn listen(listener: TcpListener, mut world: World, mut schedule: Schedule) {
+    let (tx, mut rx) = mpsc::channel(BACKLOG);
+    let limit = world.resource::<BodyLimit>().copied().unwrap_or_default();
+    spawn(async move {
+        loop {
+            // Failed accepts only concern the client that was being accepted
+            if let Ok((stream, _)) = listener.accept().await {
+                spawn(converse(stream, tx.clone(), limit));
+            }
         }
+    });
+
+    status::register(&mut world);
+    while let Some(exchanges) = next_batch(&mut rx).await {
+        answer(&mut world, &mut schedule, exchanges).await;
     }
-    println!("Ok: {}, NotFound: {}", count, deez);
 }
 
-#[component]
-pub struct Request;
+/// The requests waiting for the world, once there is one.
+async fn next_batch(rx: &mut Receiver<Exchange>) -> Option<Vec<Exchange>> {
+    let first = rx.recv().await?;
+    Some(
+        iter::once(first)
+            .chain(iter::from_fn(|| rx.try_recv().ok()))
+            .collect(),
+    )
+}
 
-#[derive(Debug)]
-#[component]
-pub struct Pending;
+async fn answer(world: &mut World, schedule: &mut Schedule, exchanges: Vec<Exchange>) {
+    let (requests, replies): (Vec<_>, Vec<_>) = exchanges
+        .into_iter()
+        .map(|exchange| (exchange.request, exchange.reply))
+        .unzip();
+    let routes = world.resource::<Router>().map(|router| {
+        requests
+            .iter()
+            .map(|request| router.resolve(request))
+            .collect::<Vec<_>>()
+    });
+    // Systems may move the entities, their reserved ids follow them
+    let reserved = world
+        .spawn_batch(
+            requests
+                .into_iter()
+                .map(|request| (request, Response::new(404), Pending)),
+        )
+        .into_iter()
+        .map(|entity| world.reserve(entity))
+        .collect::<Vec<_>>();
+    for (&reserved, route) in reserved.iter().zip(routes.into_iter().flatten()) {
+        let entity = world.spawned(reserved).unwrap();
+        match route {
+            Some(route) => world.insert_component(entity, route),
+            None => world.insert_component(entity, status::NotFound),
+        };
+    }
+
+    let failed = match schedule.run(world).await {
+        Ok(()) => false,
+        Err(error) => {
+            eprintln!("{error}");
+            true
+        }
+    };
 
-pub struct Router {}
-pub async fn serve(router: Router) {
-    let mut world = World::default();
-    let mut schedule = Schedule::default().schedule(sysa);
-    schedule.run(&mut world).await;
+    let mut answered = vec![];
+    for (reserved, reply) in reserved.into_iter().zip(replies) {
+        let entity = world.spawned(reserved);
+        let mut response = match entity.and_then(|entity| world.get::<Response>(entity)) {
+            Some(response) if !failed => {
+                let mut response = response.clone();
+                if let Some(code) = entity.and_then(|entity| world.get_dyn::<dyn Code>(entity)) {
+                    response.status = code.code();
+                }
+                response
+            }
+            _ => Response::new(500),
+        };
+        let request = entity.and_then(|entity| world.get::<Request>(entity));
+        if let (Some(router), Some(request)) = (world.resource::<Router>(), request) {
+            response = router.respond(request, response);
+        }
+        // The connection may have closed in the meantime
+        let _ = reply.send(response);
+        answered.extend(entity);
+    }
+    world.despawn(answered);
+}
+
+/// Reads requests off `stream` and writes their responses, one at a time, so
+/// pipelined requests are answered in order.
+async fn converse(stream: TcpStream, world: Sender<Exchange>, limit: BodyLimit) -> io::Result<()> {
+    let info = ConnectionInfo {
+        peer_addr: stream.peer_addr()?,
+        local_addr: stream.local_addr()?,
+        tls: None,
+    };
+    let mut inbound = Inbound::new(&stream);
+    loop {
+        let incoming = match inbound.request(limit).await {
+            Ok(Some(incoming)) => incoming,
+            Ok(None) => return Ok(()),
+            Err(ParseError::Malformed(reason)) => {
+                let response = Response::new(400)
+                    .header("Content-Type", "text/plain")
+                    .body(reason);
+                return respond(&stream, &response, false).await;
+            }
+            Err(ParseError::TooLarge(length)) => {
+                respond(&stream, &Response::new(413), false).await?;
+                // Closing with the body unread would reset the connection,
+                // possibly before the client read the response
+                return inbound.skip(length).await;
+            }
+            Err(ParseError::Io(error)) => return Err(error),
+        };
+
+        let (reply, response) = oneshot::channel();
+        let request = incoming.request.connection(info.clone());
+        if world.send(Exchange { request, reply }).await.is_err() {
+            return Ok(());
+        }
+        let Some(response) = response.await else {
+            return Ok(());
+        };
+        respond(&stream, &response, incoming.keep_alive).await?;
+        if !incoming.keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+async fn respond(stream: &TcpStream, response: &Response, keep_alive: bool) -> io::Result<()> {
+    let mut written = vec![];
+    codec::write_response(&mut written, response, keep_alive)?;
+    stream.write_all(&written).await
+}
+
+/// What was read off a connection past the requests parsed from it.
+struct Inbound<'a> {
+    stream: &'a TcpStream,
+    buffer: Vec<u8>,
+    /// Whether the client is done writing.
+    closed: bool,
+}
+
+impl<'a> Inbound<'a> {
+    fn new(stream: &'a TcpStream) -> Self {
+        Self {
+            stream,
+            buffer: vec![],
+            closed: false,
+        }
+    }
+
+    /// The next request, read until [`codec::read_request`] has a whole one
+    /// or the client stops writing.
+    async fn request(&mut self, limit: BodyLimit) -> Result<Option<Incoming>, ParseError> {
+        loop {
+            let mut unread = &self.buffer[..];
+            match codec::read_request(&mut unread, limit) {
+                Ok(None) if !self.closed => {}
+                Err(ParseError::Io(error))
+                    if error.kind() == ErrorKind::UnexpectedEof && !self.closed => {}
+                result => {
+                    let parsed = self.buffer.len() - unread.len();
+                    self.buffer.drain(..parsed);
+                    return result;
+                }
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Reads and drops the next `length` bytes, or up to where the client
+    /// stops writing.
+    async fn skip(&mut self, mut length: u64) -> io::Result<()> {
+        loop {
+            let skipped = self.buffer.len().min(length as usize);
+            self.buffer.drain(..skipped);
+            length -= skipped as u64;
+            if length == 0 || self.closed {
+                return Ok(());
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn fill(&mut self) -> io::Result<()> {
+        let mut chunk = [0; CHUNK];
+        let read = self.stream.read(&mut chunk).await?;
+        self.closed = read == 0;
+        self.buffer.extend_from_slice(&chunk[..read]);
+        Ok(())
+    }
 }
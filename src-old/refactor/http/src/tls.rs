@@ -0,0 +1,304 @@
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+use std::io;
+use std::net::TcpStream;
+use std::sync::Arc;
+
+use crate::conn::{ConnectionInfo, TlsInfo};
+
+#[derive(Debug, thiserror::Error)]
+pub enum TlsError {
+    #[error("invalid PEM: {0}")]
+    Pem(#[from] rustls::pki_types::pem::Error),
+    #[error("client certificate verifier: {0}")]
+    Verifier(#[from] rustls::server::VerifierBuilderError),
+    #[error(transparent)]
+    Rustls(#[from] rustls::Error),
+    /// The handshake failed, e.g. a required client certificate was missing.
+    #[error("TLS handshake: {0}")]
+    Handshake(#[from] io::Error),
+}
+
+/// Trust anchors client certificates are verified against.
+#[derive(Debug, Clone)]
+pub struct CaBundle(Vec<CertificateDer<'static>>);
+
+impl CaBundle {
+    pub fn from_pem(pem: &[u8]) -> Result<Self, TlsError> {
+        Ok(Self(CertificateDer::pem_slice_iter(pem).collect::<Result<_, _>>()?))
+    }
+}
+
+/// Whether clients authenticate with a certificate during the handshake.
+#[derive(Debug, Clone, Default)]
+pub enum ClientAuth {
+    #[default]
+    Off,
+    /// Certificates are requested and verified when sent, but not required.
+    Optional(CaBundle),
+    /// Handshakes without a valid certificate are rejected with a TLS alert.
+    Required(CaBundle),
+}
+
+pub struct TlsConfig {
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    alpn: Vec<Vec<u8>>,
+    client_auth: ClientAuth,
+}
+
+impl TlsConfig {
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> Result<Self, TlsError> {
+        Ok(Self {
+            cert_chain: CertificateDer::pem_slice_iter(cert_chain).collect::<Result<_, _>>()?,
+            key: PrivateKeyDer::from_pem_slice(key)?,
+            alpn: vec![],
+            client_auth: ClientAuth::Off,
+        })
+    }
+
+    /// Protocols offered through ALPN, most preferred first.
+    pub fn alpn(mut self, protocols: &[&str]) -> Self {
+        self.alpn = protocols.iter().map(|p| p.as_bytes().to_vec()).collect();
+        self
+    }
+
+    pub fn client_auth(mut self, client_auth: ClientAuth) -> Self {
+        self.client_auth = client_auth;
+        self
+    }
+
+    pub fn build(&self) -> Result<Arc<ServerConfig>, TlsError> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+
+        let builder = match &self.client_auth {
+            ClientAuth::Off => builder.with_no_client_auth(),
+            ClientAuth::Optional(bundle) => builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(roots(bundle)?, provider)
+                    .allow_unauthenticated()
+                    .build()?,
+            ),
+            ClientAuth::Required(bundle) => builder.with_client_cert_verifier(
+                WebPkiClientVerifier::builder_with_provider(roots(bundle)?, provider).build()?,
+            ),
+        };
+
+        let mut config = builder.with_single_cert(self.cert_chain.clone(), self.key.clone_key())?;
+        config.alpn_protocols = self.alpn.clone();
+        Ok(Arc::new(config))
+    }
+}
+
+fn roots(bundle: &CaBundle) -> Result<Arc<RootCertStore>, TlsError> {
+    let mut roots = RootCertStore::empty();
+    for cert in &bundle.0 {
+        roots.add(cert.clone())?;
+    }
+    Ok(Arc::new(roots))
+}
+
+pub type TlsStream = StreamOwned<ServerConnection, TcpStream>;
+
+/// Completes the handshake on an accepted connection and records what was
+/// negotiated. On failure the alert has already been sent to the client.
+pub fn accept(
+    mut tcp: TcpStream,
+    config: Arc<ServerConfig>,
+) -> Result<(TlsStream, ConnectionInfo), TlsError> {
+    let mut info = ConnectionInfo::plaintext(&tcp)?;
+    let mut conn = ServerConnection::new(config)?;
+    while conn.is_handshaking() {
+        conn.complete_io(&mut tcp)?;
+    }
+
+    let peer_cert_der = conn
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .map(|cert| cert.to_vec());
+    info.tls = Some(TlsInfo {
+        alpn: conn
+            .alpn_protocol()
+            .map(|alpn| String::from_utf8_lossy(alpn).into_owned()),
+        sni: conn.server_name().map(str::to_owned),
+        peer_cert_subject: peer_cert_der.as_deref().and_then(subject),
+        peer_cert_der,
+    });
+    Ok((StreamOwned::new(conn, tcp), info))
+}
+
+fn subject(der: &[u8]) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    Some(cert.subject().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::ServerName;
+    use rustls::{AlertDescription, ClientConfig, ClientConnection};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const CA: &[u8] = include_bytes!("../tests/fixtures/test-ca.pem");
+    const SERVER: &[u8] = include_bytes!("../tests/fixtures/server.pem");
+    const SERVER_KEY: &[u8] = include_bytes!("../tests/fixtures/server.key");
+    const CLIENT: &[u8] = include_bytes!("../tests/fixtures/client.pem");
+    const CLIENT_KEY: &[u8] = include_bytes!("../tests/fixtures/client.key");
+    const ROGUE: &[u8] = include_bytes!("../tests/fixtures/rogue-client.pem");
+    const ROGUE_KEY: &[u8] = include_bytes!("../tests/fixtures/rogue-client.key");
+
+    fn server(client_auth: ClientAuth) -> Arc<ServerConfig> {
+        TlsConfig::from_pem(SERVER, SERVER_KEY)
+            .unwrap()
+            .alpn(&["h2", "http/1.1"])
+            .client_auth(client_auth)
+            .build()
+            .unwrap()
+    }
+
+    fn client(identity: Option<(&[u8], &[u8])>) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(CA) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let mut config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(
+                    CertificateDer::pem_slice_iter(cert).collect::<Result<_, _>>().unwrap(),
+                    PrivateKeyDer::from_pem_slice(key).unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Arc::new(config)
+    }
+
+    /// Connects a client to a fresh listener and returns both sides of the
+    /// handshake: what the server accepted and what the client read back.
+    fn handshake(
+        server: Arc<ServerConfig>,
+        client: Arc<ClientConfig>,
+        sni: &'static str,
+    ) -> (Result<ConnectionInfo, TlsError>, io::Result<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let tcp = TcpStream::connect(addr).unwrap();
+            let conn = ClientConnection::new(client, ServerName::try_from(sni).unwrap()).unwrap();
+            let mut stream = StreamOwned::new(conn, tcp);
+            stream.write_all(b"ping")?;
+            let mut reply = vec![];
+            stream.read_to_end(&mut reply).map(|_| reply)
+        });
+
+        let (tcp, _) = listener.accept().unwrap();
+        let accepted = accept(tcp, server).map(|(mut stream, info)| {
+            let mut ping = [0; 4];
+            stream.read_exact(&mut ping).unwrap();
+            stream.write_all(b"pong").unwrap();
+            stream.conn.send_close_notify();
+            stream.flush().unwrap();
+            info
+        });
+        (accepted, client.join().unwrap())
+    }
+
+    #[test]
+    fn test_plaintext_info() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (tcp, _) = listener.accept().unwrap();
+
+        let info = ConnectionInfo::plaintext(&tcp).unwrap();
+        assert_eq!(info.peer_addr, client.local_addr().unwrap());
+        assert_eq!(info.local_addr, listener.local_addr().unwrap());
+        assert!(!info.is_tls());
+    }
+
+    #[test]
+    fn test_tls_info() {
+        let (info, reply) = handshake(server(ClientAuth::Off), client(None), "localhost");
+        let info = info.unwrap();
+        assert_eq!(reply.unwrap(), b"pong");
+
+        let tls = info.tls.unwrap();
+        assert_eq!(tls.alpn.as_deref(), Some("http/1.1"));
+        assert_eq!(tls.sni.as_deref(), Some("localhost"));
+        assert_eq!(tls.peer_cert_der, None);
+        assert_eq!(info.local_addr.ip(), info.peer_addr.ip());
+    }
+
+    #[test]
+    fn test_sni_capture() {
+        let (info, _) = handshake(server(ClientAuth::Off), client(None), "api.localhost");
+        assert_eq!(info.unwrap().tls.unwrap().sni.as_deref(), Some("api.localhost"));
+    }
+
+    #[test]
+    fn test_client_cert_matrix() {
+        let bundle = || CaBundle::from_pem(CA).unwrap();
+        let trusted = Some((CLIENT, CLIENT_KEY));
+        let rogue = Some((ROGUE, ROGUE_KEY));
+
+        for (auth, identity, accepted) in [
+            (ClientAuth::Optional(bundle()), None, true),
+            (ClientAuth::Optional(bundle()), trusted, true),
+            (ClientAuth::Optional(bundle()), rogue, false),
+            (ClientAuth::Required(bundle()), None, false),
+            (ClientAuth::Required(bundle()), trusted, true),
+            (ClientAuth::Required(bundle()), rogue, false),
+        ] {
+            let label = format!("{auth:?} with {}", identity.map_or("no cert", |_| "cert"));
+            let (info, reply) = handshake(server(auth), client(identity), "localhost");
+            if !accepted {
+                assert!(info.is_err(), "{label}");
+                assert!(reply.is_err(), "{label}");
+                continue;
+            }
+
+            let tls = info.unwrap().tls.unwrap();
+            assert_eq!(reply.unwrap(), b"pong", "{label}");
+            match identity {
+                Some(_) => {
+                    let der = CertificateDer::from_pem_slice(CLIENT).unwrap();
+                    assert_eq!(tls.peer_cert_der.as_deref(), Some(&der[..]), "{label}");
+                    assert_eq!(
+                        tls.peer_cert_subject.as_deref(),
+                        Some("CN=test-client, O=Angelite Test")
+                    );
+                }
+                None => assert_eq!(tls.peer_cert_subject, None, "{label}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_required_sends_alert() {
+        let (info, reply) = handshake(
+            server(ClientAuth::Required(CaBundle::from_pem(CA).unwrap())),
+            client(None),
+            "localhost",
+        );
+        assert!(matches!(info, Err(TlsError::Handshake(_))));
+        let err = reply.unwrap_err();
+        let alert = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<rustls::Error>())
+            .cloned();
+        assert_eq!(
+            alert,
+            Some(rustls::Error::AlertReceived(AlertDescription::CertificateRequired))
+        );
+    }
+}
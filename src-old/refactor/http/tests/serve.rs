@@ -0,0 +1,174 @@
+use base::io::net::TcpListener;
+use base::prelude::Vector;
+use base::rt::{Runtime, block_on, worker};
+use ecs::query::{Query, filter::With};
+use ecs::schedule::Schedule;
+use ecs::world::World;
+use http::server::status::Created;
+use http::{
+    BodyLimit, IntoResponse, Json, JsonBody, Path, Pending, Request, Response, Router, listen,
+};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Once;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+static SEEN: AtomicUsize = AtomicUsize::new(0);
+
+fn echo(mut requests: Query<'_, (&'_ Request, &'_ mut Response), With<Pending>>) {
+    for (request, response) in &mut requests {
+        *response = Response::new(200).body(format!("{} {}", request.method, request.path));
+    }
+}
+
+fn count(requests: Query<'_, &'_ Request, With<Pending>>) {
+    SEEN.fetch_add((&requests).into_iter().count(), Ordering::Relaxed);
+}
+
+fn user(mut users: Path<'_, u64>) {
+    users.each("user", |id, response| {
+        *response = Response::new(200).body(format!("user {id}"));
+    });
+}
+
+fn file(mut files: Path<'_, String>) {
+    files.each("file", |path, response| {
+        *response = Response::new(200).body(format!("file {path}"));
+    });
+}
+
+fn double(mut numbers: JsonBody<'_, Vec<u32>>) {
+    numbers.each("double", |Json(numbers), response| {
+        let doubled = numbers.iter().map(|n| n * 2).collect::<Vec<_>>();
+        *response = (Created, Json(doubled)).into_response();
+    });
+}
+
+/// Starts the worker pool the schedules run on, once for every test.
+fn runtime() {
+    static START: Once = Once::new();
+    START.call_once(|| {
+        let (tx, rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            block_on(async move {
+                let start = Runtime::new(Vector::splat(7))
+                    .workers(3)
+                    .signals(false)
+                    .start()
+                    .await;
+                tx.send(()).unwrap();
+                worker::worker_start_barrier(start).await;
+            })
+        });
+        rx.recv().unwrap();
+    });
+}
+
+/// Starts a server on an ephemeral port, answering in the world and with the
+/// schedule `setup` makes. Worlds stay on the thread they are made on.
+fn start(setup: impl FnOnce() -> (World, Schedule) + Send + 'static) -> SocketAddr {
+    runtime();
+    let listener = block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (world, schedule) = setup();
+        block_on(listen(listener, world, schedule))
+    });
+    addr
+}
+
+fn exchange(addr: SocketAddr, requests: &[u8]) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(requests).unwrap();
+    let mut responses = String::new();
+    stream.read_to_string(&mut responses).unwrap();
+    responses
+}
+
+#[test]
+fn test_pipelined_requests() {
+    let responses = exchange(
+        start(|| (World::new(), Schedule::default().schedule(echo))),
+        b"GET /a HTTP/1.1\r\nHost: test\r\n\r\n\
+          POST /b HTTP/1.1\r\nHost: test\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+    );
+    assert_eq!(
+        responses,
+        "HTTP/1.1 200 Ok\r\nContent-Length: 6\r\n\r\nGET /a\
+         HTTP/1.1 200 Ok\r\nContent-Length: 7\r\nConnection: close\r\n\r\nPOST /b"
+    );
+}
+
+#[test]
+fn test_request_split_across_writes() {
+    let addr = start(|| (World::new(), Schedule::default().schedule(echo)));
+    let mut stream = TcpStream::connect(addr).unwrap();
+    for part in ["PUT /split HTTP/1.1\r\nCont", "ent-Length: 3\r\n\r\nab", "c"] {
+        stream.write_all(part.as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(20));
+    }
+    stream.write_all(b"GET /next HTTP/1.0\r\n\r\n").unwrap();
+    let mut responses = String::new();
+    stream.read_to_string(&mut responses).unwrap();
+    assert_eq!(
+        responses,
+        "HTTP/1.1 200 Ok\r\nContent-Length: 10\r\n\r\nPUT /split\
+         HTTP/1.1 200 Ok\r\nContent-Length: 9\r\nConnection: close\r\n\r\nGET /next"
+    );
+}
+
+#[test]
+fn test_malformed_request_skips_the_world() {
+    let addr = start(|| (World::new(), Schedule::default().schedule(count)));
+    let responses = exchange(addr, b"GET /a HTTP/9\r\n\r\nGET /b HTTP/1.1\r\n\r\n");
+    assert!(responses.starts_with("HTTP/1.1 400 BadRequest\r\n"));
+    assert!(responses.contains("Connection: close\r\n"));
+    assert_eq!(SEEN.load(Ordering::Relaxed), 0);
+
+    // Requests no system answered are not found
+    let responses = exchange(addr, b"GET /b HTTP/1.0\r\n\r\n");
+    assert!(responses.starts_with("HTTP/1.1 404 NotFound\r\n"));
+    assert_eq!(SEEN.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn test_routes() {
+    let addr = start(|| {
+        let mut world = World::new();
+        world.insert_resource(
+            Router::new()
+                .get("/users/:id", "user")
+                .get("/files/*path", "file"),
+        );
+        (world, Schedule::default().schedule(user).schedule(file))
+    });
+
+    let get = |path: &str| exchange(addr, format!("GET {path} HTTP/1.0\r\n\r\n").as_bytes());
+    assert!(get("/users/7").ends_with("\r\n\r\nuser 7"));
+    assert!(get("/files/docs/a.txt").ends_with("\r\n\r\nfile docs/a.txt"));
+    assert!(get("/users/alice").starts_with("HTTP/1.1 400 BadRequest\r\n"));
+    assert!(get("/posts/1").starts_with("HTTP/1.1 404 NotFound\r\n"));
+}
+
+#[test]
+fn test_json_bodies() {
+    let addr = start(|| {
+        let mut world = World::new();
+        world.insert_resource(Router::new().post("/double", "double"));
+        world.insert_resource(BodyLimit(16));
+        (world, Schedule::default().schedule(double))
+    });
+
+    let post = |body: &str| {
+        let head = "POST /double HTTP/1.0\r\nContent-Type: application/json\r\n";
+        let request = format!("{head}Content-Length: {}\r\n\r\n{body}", body.len());
+        exchange(addr, request.as_bytes())
+    };
+    let doubled = post("[1, 2, 3]");
+    assert!(doubled.starts_with("HTTP/1.1 201 Created\r\n"));
+    assert!(doubled.ends_with("\r\n\r\n[2,4,6]"));
+    assert!(post("[1, 2,").starts_with("HTTP/1.1 422 UnprocessableEntity\r\n"));
+    assert!(post("[1, 2, 3, 4, 5, 6, 7]").starts_with("HTTP/1.1 413 PayloadTooLarge\r\n"));
+}
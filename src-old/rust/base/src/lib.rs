@@ -0,0 +1,41 @@
+//! The runtime, collections, math, rng, sync, time and io modules as one
+//! crate. They name each other through `crate::`, so they are built here
+//! from their own directories rather than each on its own.
+#![feature(
+    async_fn_traits,
+    async_iterator,
+    coroutines,
+    core_intrinsics,
+    gen_blocks,
+    negative_impls,
+    repr_simd,
+    thread_id_value,
+    trait_alias,
+    unboxed_closures
+)]
+#![allow(internal_features)]
+
+// Lets the derives name this crate as `::base` from inside it too
+extern crate self as base;
+
+#[path = "../../collections/src/lib.rs"]
+pub mod collections;
+#[path = "../../io/src/mod.rs"]
+pub mod io;
+#[path = "../../math/src/lib.rs"]
+pub mod math;
+#[path = "../../rng/src/lib.rs"]
+pub mod rng;
+#[path = "../../rt/src/lib.rs"]
+pub mod rt;
+#[path = "../../sync/src/mod.rs"]
+pub mod sync;
+#[path = "../../chrono/src/lib.rs"]
+pub mod time;
+
+pub use base_macro::main;
+pub use rt::run;
+
+pub mod prelude {
+    pub use crate::math::{Mask, Matrix, Quaternion, Simd, Vector};
+}
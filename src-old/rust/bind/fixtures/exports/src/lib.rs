@@ -0,0 +1,63 @@
+//! Fixture for the ABI extraction of Rust sources.
+use std::ffi::c_void;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Point {
+    /// Horizontal position
+    pub x: f32,
+    pub y: f32,
+}
+
+#[repr(C)]
+pub enum Shape {
+    Circle { center: Point, radius: f32 },
+    Line { from: Point, to: Point },
+}
+
+pub type Visit = extern "C" fn(shape: *const Shape, data: *mut c_void);
+
+struct Scratch {
+    shapes: Vec<Shape>,
+}
+
+impl Point {
+    fn len_squared(&self) -> f32 {
+        self.x * self.x + self.y * self.y
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn point_new(x: f32, y: f32) -> Point {
+    Point { x, y }
+}
+
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn point_length(point: *const Point) -> f32 {
+    unsafe { (*point).len_squared().sqrt() }
+}
+
+#[unsafe(export_name = "shapes_visit")]
+pub extern "C" fn visit_shapes(visit: Visit, data: *mut c_void) -> usize {
+    let scratch = Scratch { shapes: vec![] };
+    for shape in &scratch.shapes {
+        visit(shape, data);
+    }
+    scratch.shapes.len()
+}
+
+pub mod area {
+    use super::*;
+
+    #[unsafe(no_mangle)]
+    pub extern "C" fn shape_area(shape: Shape) -> f32 {
+        match shape {
+            Shape::Circle { radius, .. } => std::f32::consts::PI * radius * radius,
+            Shape::Line { .. } => 0.0,
+        }
+    }
+}
+
+pub extern "C" fn internal(point: Point) -> f32 {
+    point.x
+}
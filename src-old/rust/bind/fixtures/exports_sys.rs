@@ -0,0 +1,24 @@
+//! Bindings to fixtures/exports, as a model would write them.
+use std::ffi::c_void;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[repr(C)]
+pub enum Shape {
+    Circle { center: Point, radius: f32 },
+    Line { from: Point, to: Point },
+}
+
+pub type Visit = extern "C" fn(shape: *const Shape, data: *mut c_void);
+
+unsafe extern "C" {
+    pub fn point_new(x: f32, y: f32) -> Point;
+    pub fn point_length(point: *const Point) -> f32;
+    pub fn shapes_visit(visit: Visit, data: *mut c_void) -> usize;
+    pub fn shape_area(shape: Shape) -> f32;
+}
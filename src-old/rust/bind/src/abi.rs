@@ -0,0 +1,380 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use syn::{
+    Attribute, Expr, File, FnArg, ForeignItem, ForeignItemFn, Ident, Item, ItemFn, ItemForeignMod,
+    LitInt, Meta, Stmt, Visibility, parse_quote,
+};
+
+use crate::Executor;
+
+/// Marks a type size measured by [`probe_sizes`] in a summary.
+const SIZE_PREFIX: &str = "// size_of::<";
+
+/// Start of every [`smoke_test`], kept as text since macros do not format.
+const SMOKE_PRELUDE: &str = r#"//! Checks the bindings against the C ABI they were generated from.
+#![allow(unused_imports)]
+
+fn assert_size<T>(name: &str, size: usize) {
+    assert_eq!(std::mem::size_of::<T>(), size, "size of {name}");
+}
+
+"#;
+
+/// Condenses Rust sources to the C ABI they export: `#[repr(C)]` types and
+/// `extern "C"` functions that keep their symbol, declared without bodies.
+/// Files that export nothing are dropped.
+pub(crate) fn extract(files: &[(PathBuf, String)]) -> Result<Vec<(PathBuf, String)>, String> {
+    let mut summaries = vec![];
+    for (path, source) in files {
+        let file = syn::parse_file(source).map_err(|e| format!("{}: {e}", path.display()))?;
+        if let Some(summary) = summarize(&file) {
+            summaries.push((path.clone(), summary));
+        }
+    }
+    Ok(summaries)
+}
+
+fn summarize(file: &File) -> Option<String> {
+    let mut types = vec![];
+    let mut functions = vec![];
+    collect(&file.items, &mut types, &mut functions);
+    if types.is_empty() && functions.is_empty() {
+        return None;
+    }
+
+    if !functions.is_empty() {
+        let mut block: ItemForeignMod = parse_quote!(extern "C" {});
+        block.items = functions;
+        types.push(Item::ForeignMod(block));
+    }
+    Some(prettyplease::unparse(&File {
+        shebang: None,
+        attrs: vec![],
+        items: types,
+    }))
+}
+
+fn collect(items: &[Item], types: &mut Vec<Item>, functions: &mut Vec<ForeignItem>) {
+    for item in items {
+        match item {
+            Item::Fn(function) => {
+                if let Some(function) = export(function) {
+                    functions.push(ForeignItem::Fn(function));
+                }
+            }
+            Item::Struct(item) if repr_c(&item.attrs) => {
+                let mut item = item.clone();
+                item.attrs.retain(is_repr);
+                item.fields.iter_mut().for_each(|field| field.attrs.clear());
+                types.push(Item::Struct(item));
+            }
+            Item::Union(item) if repr_c(&item.attrs) => {
+                let mut item = item.clone();
+                item.attrs.retain(is_repr);
+                item.fields.named.iter_mut().for_each(|field| field.attrs.clear());
+                types.push(Item::Union(item));
+            }
+            Item::Enum(item) if repr_c(&item.attrs) => {
+                let mut item = item.clone();
+                item.attrs.retain(is_repr);
+                for variant in &mut item.variants {
+                    variant.attrs.clear();
+                    variant.fields.iter_mut().for_each(|field| field.attrs.clear());
+                }
+                types.push(Item::Enum(item));
+            }
+            // Aliases are how exported signatures usually name callbacks
+            Item::Type(item) if matches!(item.vis, Visibility::Public(_)) => {
+                let mut item = item.clone();
+                item.attrs.clear();
+                types.push(Item::Type(item));
+            }
+            Item::Mod(item) => {
+                if let Some((_, items)) = &item.content {
+                    collect(items, types, functions);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The declaration a C caller links against, if `function` is exported.
+fn export(function: &ItemFn) -> Option<ForeignItemFn> {
+    let abi = function.sig.abi.as_ref()?;
+    if !abi.name.as_ref().is_none_or(|name| matches!(&*name.value(), "C" | "C-unwind")) {
+        return None;
+    }
+    let mut symbol = None;
+    let mut exported = false;
+    for attr in &function.attrs {
+        let Some(meta) = unwrap_unsafe(attr) else {
+            continue;
+        };
+        if meta.path().is_ident("no_mangle") {
+            exported = true;
+        } else if meta.path().is_ident("export_name")
+            && let Meta::NameValue(pair) = &meta
+            && let syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Str(name), .. }) = &pair.value
+        {
+            exported = true;
+            symbol = Some(name.clone());
+        }
+    }
+    if !exported {
+        return None;
+    }
+
+    let mut sig = function.sig.clone();
+    sig.abi = None;
+    sig.unsafety = None;
+    if let Some(name) = symbol.and_then(|name| name.parse().ok()) {
+        sig.ident = name;
+    }
+    Some(ForeignItemFn {
+        attrs: vec![],
+        vis: function.vis.clone(),
+        sig,
+        semi_token: Default::default(),
+    })
+}
+
+/// The size of every type declared in `summaries`, measured by compiling and
+/// running a probe with `executor`.
+pub(crate) fn probe_sizes(
+    executor: &dyn Executor,
+    summaries: &[(PathBuf, String)],
+) -> Result<Vec<(String, usize)>, String> {
+    let mut items = vec![];
+    let mut prints: Vec<Stmt> = vec![];
+    for (path, summary) in summaries {
+        let file = syn::parse_file(summary).map_err(|e| format!("{}: {e}", path.display()))?;
+        for item in file.items {
+            let name = match &item {
+                Item::Struct(item) => item.ident.clone(),
+                Item::Union(item) => item.ident.clone(),
+                Item::Enum(item) => item.ident.clone(),
+                Item::Type(_) => {
+                    items.push(item);
+                    continue;
+                }
+                _ => continue,
+            };
+            let label = name.to_string();
+            prints.push(parse_quote!(println!("{} {}", #label, std::mem::size_of::<#name>());));
+            items.push(item);
+        }
+    }
+    if prints.is_empty() {
+        return Ok(vec![]);
+    }
+    let mut probe: File = parse_quote! {
+        #![allow(dead_code)]
+        use std::ffi::*;
+        fn main() {
+            #(#prints)*
+        }
+    };
+    probe.items.extend(items);
+
+    let dir = executor.exec(&["mktemp", "-d"])?;
+    let dir = Path::new(dir.stdout.trim());
+    let host = env::temp_dir().join(format!("bind-abi-probe-{}.rs", std::process::id()));
+    fs::write(&host, prettyplease::unparse(&probe)).map_err(|e| e.to_string())?;
+    let copied = executor.copy_to(&host, &dir.join("probe.rs"));
+    let _ = fs::remove_file(&host);
+    copied?;
+
+    let run = format!(
+        "cd '{}' && rustc --edition 2021 -o probe probe.rs && ./probe",
+        dir.display()
+    );
+    let result = executor.exec(&["bash", "-lc", &run])?;
+    if !result.success {
+        return Err(result.stderr);
+    }
+    Ok(result
+        .stdout
+        .lines()
+        .filter_map(|line| {
+            let (name, size) = line.split_once(' ')?;
+            Some((name.to_owned(), size.parse().ok()?))
+        })
+        .collect())
+}
+
+/// Notes each size under the summary that declares the type, where both the
+/// model and [`smoke_test`] find it.
+pub(crate) fn annotate(summaries: &mut [(PathBuf, String)], sizes: &[(String, usize)]) {
+    for (_, summary) in summaries {
+        let Ok(file) = syn::parse_file(summary) else {
+            continue;
+        };
+        for item in &file.items {
+            let name = match item {
+                Item::Struct(item) => &item.ident,
+                Item::Union(item) => &item.ident,
+                Item::Enum(item) => &item.ident,
+                _ => continue,
+            };
+            if let Some((_, size)) = sizes.iter().find(|(sized, _)| name == sized) {
+                summary.push_str(&format!("{SIZE_PREFIX}{name}>() == {size}\n"));
+            }
+        }
+    }
+}
+
+/// `tests/abi_smoke.rs` for the `-sys` crate `crate_name`: every function in
+/// `summaries` has to be declared with the same signature, and every type
+/// measured by [`probe_sizes`] has to have the same size. `None` when the
+/// summaries give nothing to check.
+pub(crate) fn smoke_test(crate_name: &str, summaries: &[(PathBuf, String)]) -> Option<String> {
+    let mut signatures: Vec<Stmt> = vec![];
+    let mut sizes: Vec<Stmt> = vec![];
+    for (path, summary) in summaries {
+        if path.extension().is_none_or(|ext| ext != "rs") {
+            continue;
+        }
+        let Ok(file) = syn::parse_file(summary) else {
+            continue;
+        };
+        for item in &file.items {
+            let Item::ForeignMod(block) = item else {
+                continue;
+            };
+            for item in &block.items {
+                let ForeignItem::Fn(function) = item else {
+                    continue;
+                };
+                let sig = &function.sig;
+                if sig.variadic.is_some() {
+                    continue;
+                }
+                let inputs = sig.inputs.iter().filter_map(|arg| match arg {
+                    FnArg::Typed(arg) => Some(&arg.ty),
+                    FnArg::Receiver(_) => None,
+                });
+                let (name, output) = (&sig.ident, &sig.output);
+                signatures.push(parse_quote!(let _: unsafe extern "C" fn(#(#inputs),*) #output = #name;));
+            }
+        }
+        for line in summary.lines() {
+            let Some((name, size)) = line
+                .strip_prefix(SIZE_PREFIX)
+                .and_then(|rest| rest.split_once(">() == "))
+            else {
+                continue;
+            };
+            let (Ok(name), Ok(size)) = (syn::parse_str::<Ident>(name), syn::parse_str::<LitInt>(size.trim()))
+            else {
+                continue;
+            };
+            let label = name.to_string();
+            sizes.push(parse_quote!(assert_size::<#name>(#label, #size);));
+        }
+    }
+    if signatures.is_empty() && sizes.is_empty() {
+        return None;
+    }
+
+    let krate: Ident = syn::parse_str(&crate_name.replace('-', "_")).ok()?;
+    let check: Expr = parse_quote!(|| { #(#signatures)* });
+    let file: File = parse_quote! {
+        use std::ffi::*;
+        use #krate::*;
+
+        const _: fn() = #check;
+
+        #[test]
+        fn sizes() {
+            #(#sizes)*
+        }
+    };
+    Some(format!("{SMOKE_PRELUDE}{}", prettyplease::unparse(&file)))
+}
+
+/// `#[unsafe(no_mangle)]` and friends as the attribute they wrap. `None` when
+/// the wrapped attribute does not parse.
+fn unwrap_unsafe(attr: &Attribute) -> Option<Meta> {
+    match attr.path().is_ident("unsafe") {
+        true => attr.parse_args().ok(),
+        false => Some(attr.meta.clone()),
+    }
+}
+
+fn is_repr(attr: &Attribute) -> bool {
+    attr.path().is_ident("repr")
+}
+
+fn repr_c(attrs: &[Attribute]) -> bool {
+    attrs.iter().filter(|attr| is_repr(attr)).any(|attr| {
+        let mut c = false;
+        let _ = attr.parse_nested_meta(|meta| {
+            c |= meta.path.is_ident("C");
+            Ok(())
+        });
+        c
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    fn fixture() -> Vec<(PathBuf, String)> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/exports/src/lib.rs");
+        let source = fs::read_to_string(&path).unwrap();
+        vec![(path, source)]
+    }
+
+    #[test]
+    fn test_extract_exports() {
+        let summaries = extract(&fixture()).unwrap();
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0].1;
+
+        assert!(summary.contains("#[repr(C)]\npub struct Point {"));
+        assert!(summary.contains("#[repr(C)]\npub enum Shape {"));
+        assert!(summary.contains("pub type Visit = "));
+        assert!(summary.contains("pub fn point_new(x: f32, y: f32) -> Point;"));
+        assert!(summary.contains("pub fn point_length(point: *const Point) -> f32;"));
+        assert!(summary.contains("pub fn shapes_visit(visit: Visit, data: *mut c_void) -> usize;"));
+        assert!(summary.contains("pub fn shape_area(shape: Shape) -> f32;"));
+
+        // Private items, bodies and Rust-only functions stay out of the prompt
+        assert!(!summary.contains("Scratch"));
+        assert!(!summary.contains("internal"));
+        assert!(!summary.contains("sqrt"));
+        assert!(!summary.contains("len_squared"));
+    }
+
+    #[test]
+    fn test_smoke_test() {
+        let mut summaries = extract(&fixture()).unwrap();
+        annotate(&mut summaries, &[("Point".to_owned(), 8), ("Unknown".to_owned(), 4)]);
+        assert!(summaries[0].1.ends_with("// size_of::<Point>() == 8\n"));
+        assert!(!summaries[0].1.contains("Unknown"));
+
+        let harness = smoke_test("exports-sys", &summaries).unwrap();
+        assert!(harness.starts_with(SMOKE_PRELUDE));
+        assert!(harness.contains("use exports_sys::*;"));
+        assert!(harness.contains("let _: unsafe extern \"C\" fn(f32, f32) -> Point = point_new;"));
+        assert!(harness.contains("let _: unsafe extern \"C\" fn(Visit, *mut c_void) -> usize = shapes_visit;"));
+        assert!(harness.contains("assert_size::<Point>(\"Point\", 8);"));
+
+        // Zig sources give nothing to check
+        let zig = vec![(PathBuf::from("src/root.zig"), "export fn f() void {}".to_owned())];
+        assert_eq!(smoke_test("root-sys", &zig), None);
+    }
+
+    #[test]
+    fn test_extract_drops_files_without_exports() {
+        let files = vec![(PathBuf::from("src/util.rs"), "pub fn add(a: u8, b: u8) -> u8 { a + b }".to_owned())];
+        assert!(extract(&files).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,93 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use hash::Xxh64;
+
+use crate::Language;
+
+/// Generated bindings keyed by everything that went into the prompt, so an
+/// unchanged source tree does not cost another model round trip.
+pub(crate) struct ResponseCache {
+    dir: PathBuf,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(target: &Path) -> Self {
+        Self {
+            dir: target.join(".bind-cache"),
+        }
+    }
+
+    pub(crate) fn key(
+        src_files: &[(PathBuf, String)],
+        injection: &str,
+        guidelines: &str,
+        input_lang: Language,
+        output_lang: Language,
+    ) -> u64 {
+        let mut hasher = Xxh64::default();
+        let mut field = |bytes: &[u8]| {
+            // Length prefix so adjacent fields cannot run into each other
+            hasher.update(&(bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        };
+        for (path, contents) in src_files {
+            field(path.as_os_str().as_encoded_bytes());
+            field(contents.as_bytes());
+        }
+        field(injection.as_bytes());
+        field(guidelines.as_bytes());
+        field(format!("{input_lang:?}->{output_lang:?}").as_bytes());
+        hasher.finalize()
+    }
+
+    pub(crate) fn load(&self, key: u64) -> Option<String> {
+        fs::read_to_string(self.path(key)).ok()
+    }
+
+    pub(crate) fn store(&self, key: u64, bindings: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Write then rename so an interrupted build never leaves half an entry
+        let tmp = self.path(key).with_extension("tmp");
+        fs::write(&tmp, bindings)?;
+        fs::rename(tmp, self.path(key))
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{key:016x}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(contents: &str) -> Vec<(PathBuf, String)> {
+        vec![(PathBuf::from("include/io.h"), contents.to_owned())]
+    }
+
+    #[test]
+    fn test_key_tracks_inputs() {
+        let key = |src: &str, injection: &str, output| {
+            ResponseCache::key(&sources(src), injection, "", Language::Zig, output)
+        };
+        let base = key("void f();", "", Language::Rust);
+        assert_eq!(base, key("void f();", "", Language::Rust));
+        assert_ne!(base, key("void g();", "", Language::Rust));
+        assert_ne!(base, key("void f();", "fix it", Language::Rust));
+        assert_ne!(base, key("void f();", "", Language::Swift));
+        assert_ne!(key("ab", "c", Language::Rust), key("a", "bc", Language::Rust));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let target = std::env::temp_dir().join(format!("bind-cache-{}", std::process::id()));
+        let cache = ResponseCache::new(&target);
+        assert_eq!(cache.load(7), None);
+        cache.store(7, "pub fn f() {}").unwrap();
+        assert_eq!(cache.load(7).as_deref(), Some("pub fn f() {}"));
+        fs::remove_dir_all(target).unwrap();
+    }
+}
@@ -0,0 +1,283 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::{Event, GeneratedFile, ProgressSink};
+
+/// A fenced code block from model output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    /// First word of the info string, lowercased. Empty for a bare fence.
+    pub lang: String,
+    /// Path named on the first line of the block or after the language.
+    pub declared_path: Option<PathBuf>,
+    /// The code, without the path line and with `\n` line endings.
+    pub body: String,
+}
+
+struct Open {
+    fence: usize,
+    lang: String,
+    info_path: Option<PathBuf>,
+    lines: Vec<String>,
+    /// Fences with an info string opened inside this block and not yet closed.
+    depth: usize,
+}
+
+impl Open {
+    fn finish(mut self) -> CodeBlock {
+        let path_line = self.lines.first().and_then(|line| path_comment(line));
+        if path_line.is_some() {
+            self.lines.remove(0);
+        }
+        CodeBlock {
+            lang: self.lang,
+            declared_path: path_line.or(self.info_path),
+            body: self.lines.join("\n"),
+        }
+    }
+}
+
+/// Every fenced block in `output`, in order. Text between blocks is ignored
+/// and a block left open at the end runs to the end of the output.
+///
+/// A fence closes its block only when it has no info string and at least as
+/// many backticks. Fences with an info string inside a block open a nested
+/// block, whose closing fence then belongs to the body as well.
+pub fn parse_blocks(output: &str) -> Vec<CodeBlock> {
+    let mut blocks = vec![];
+    let mut open: Option<Open> = None;
+    for line in output.lines() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let fence = fence(line);
+        match (&mut open, fence) {
+            (None, Some((ticks, info))) => {
+                let mut words = info.split_whitespace();
+                open = Some(Open {
+                    fence: ticks,
+                    lang: words.next().unwrap_or_default().to_lowercase(),
+                    info_path: words.find_map(|word| looks_like_path(word).then(|| PathBuf::from(word))),
+                    lines: vec![],
+                    depth: 0,
+                });
+            }
+            (None, None) => {}
+            (Some(block), Some((ticks, ""))) if block.depth == 0 && ticks >= block.fence => {
+                blocks.push(open.take().unwrap().finish());
+            }
+            (Some(block), fence) => {
+                match fence {
+                    Some((_, "")) => block.depth = block.depth.saturating_sub(1),
+                    Some(_) => block.depth += 1,
+                    None => {}
+                }
+                block.lines.push(line.to_owned());
+            }
+        }
+    }
+    blocks.extend(open.map(Open::finish));
+    blocks
+}
+
+/// The backtick count and trimmed info string of a fence line.
+fn fence(line: &str) -> Option<(usize, &str)> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    if indent > 3 {
+        return None;
+    }
+    let rest = &line[indent..];
+    let ticks = rest.len() - rest.trim_start_matches('`').len();
+    if ticks < 3 {
+        return None;
+    }
+    let info = rest[ticks..].trim();
+    // Inline code like ```a``` is not a fence
+    (!info.contains('`')).then_some((ticks, info))
+}
+
+/// The path a first line declares: `// path: src/lib.rs`, `# path: x.py`,
+/// `// src/lib.rs` or a bare `src/lib.rs`.
+fn path_comment(line: &str) -> Option<PathBuf> {
+    let line = line.trim();
+    let comment = ["//", "#"].iter().find_map(|marker| line.strip_prefix(marker));
+    let text = comment.unwrap_or(line).trim();
+    if let Some(explicit) = text.strip_prefix("path:") {
+        let explicit = explicit.trim();
+        return (!explicit.is_empty()).then(|| PathBuf::from(explicit));
+    }
+    looks_like_path(text).then(|| PathBuf::from(text))
+}
+
+/// Whether `text` reads as a file path rather than code or prose: a single
+/// word of path characters with a directory or an extension.
+fn looks_like_path(text: &str) -> bool {
+    !text.is_empty()
+        && !text.chars().any(|c| c.is_whitespace() || "(){}[]<>;,=:\"'`*!?&|".contains(c))
+        && Path::new(text)
+            .file_name()
+            .is_some_and(|name| text.contains('/') || name.to_string_lossy().contains('.'))
+        && !text.ends_with('.')
+}
+
+/// `path` relative to `root`, if it stays inside it. Absolute paths are only
+/// accepted below `root`; `..` and empty paths are rejected.
+pub fn sanitize(path: &Path, root: &Path) -> Option<PathBuf> {
+    let relative = match path.is_absolute() {
+        true => path.strip_prefix(root).ok()?,
+        false => path,
+    };
+    let mut clean = PathBuf::new();
+    for component in relative.components() {
+        match component {
+            Component::Normal(part) => clean.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!clean.as_os_str().is_empty()).then_some(clean)
+}
+
+/// Files from the blocks in one of `langs` that declare a path inside `root`.
+/// Other blocks are reported to `progress` and skipped.
+pub(crate) fn files(
+    output: &str,
+    langs: &[&str],
+    root: &Path,
+    progress: &dyn ProgressSink,
+) -> Vec<GeneratedFile> {
+    let mut files: Vec<GeneratedFile> = vec![];
+    for block in parse_blocks(output) {
+        if !langs.contains(&&*block.lang) {
+            continue;
+        }
+        let Some(declared) = &block.declared_path else {
+            progress.event(&Event::warning(format!(
+                "Code block without path information: {}",
+                block.body.lines().next().unwrap_or_default()
+            )));
+            continue;
+        };
+        let Some(file) = sanitize(declared, root).and_then(|path| GeneratedFile::new(path, block.body))
+        else {
+            progress.event(&Event::warning(format!(
+                "Skipping code block escaping the crate: {}",
+                declared.display()
+            )));
+            continue;
+        };
+        // A later block for the same path replaces the earlier one
+        files.retain(|existing| existing.path != file.path);
+        files.push(file);
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(lang: &str, path: Option<&str>, body: &str) -> CodeBlock {
+        CodeBlock {
+            lang: lang.to_owned(),
+            declared_path: path.map(PathBuf::from),
+            body: body.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_parse_blocks() {
+        let cases: &[(&str, &str, Vec<CodeBlock>)] = &[
+            (
+                "comment path",
+                "```rust\n// ./src/lib.rs\npub fn f() {}\n```\n",
+                vec![block("rust", Some("./src/lib.rs"), "pub fn f() {}")],
+            ),
+            (
+                "explicit slash path",
+                "```rust\n// path: src/ffi.rs\nextern \"C\" {}\n```",
+                vec![block("rust", Some("src/ffi.rs"), "extern \"C\" {}")],
+            ),
+            (
+                "explicit hash path",
+                "```toml\n# path: Cargo.toml\n[package]\n```",
+                vec![block("toml", Some("Cargo.toml"), "[package]")],
+            ),
+            (
+                "bare first line path",
+                "```zig\nsrc/root.zig\npub const a = 1;\n```",
+                vec![block("zig", Some("src/root.zig"), "pub const a = 1;")],
+            ),
+            (
+                "path after the language",
+                "```rust src/lib.rs\npub fn f() {}\n```",
+                vec![block("rust", Some("src/lib.rs"), "pub fn f() {}")],
+            ),
+            (
+                "prose comment is not a path",
+                "```rust\n// Generated bindings for io.h\nmod io;\n```",
+                vec![block("rust", None, "// Generated bindings for io.h\nmod io;")],
+            ),
+            (
+                "text around and between blocks",
+                "Here you go:\n```rust\n// a.rs\nA\n```\nand\n```Zig\n// b.zig\nB\n```\nDone.",
+                vec![block("rust", Some("a.rs"), "A"), block("zig", Some("b.zig"), "B")],
+            ),
+            (
+                "crlf line endings",
+                "```rust\r\n// src/lib.rs\r\nfn a() {}\r\nfn b() {}\r\n```\r\n",
+                vec![block("rust", Some("src/lib.rs"), "fn a() {}\nfn b() {}")],
+            ),
+            (
+                "no trailing fence",
+                "```rust\n// src/lib.rs\nfn a() {}\n",
+                vec![block("rust", Some("src/lib.rs"), "fn a() {}")],
+            ),
+            (
+                "nested fence with info string",
+                "```rust\n// src/lib.rs\nconst DOC: &str = r#\"\n```text\nhi\n```\n\"#;\n```",
+                vec![block("rust", Some("src/lib.rs"), "const DOC: &str = r#\"\n```text\nhi\n```\n\"#;")],
+            ),
+            (
+                "longer outer fence",
+                "````markdown\n// README.md\n```\ncode\n```\n````",
+                vec![block("markdown", Some("README.md"), "```\ncode\n```")],
+            ),
+            (
+                "backticks inside a string",
+                "```rust\n// src/lib.rs\nlet s = \"```\";\n```",
+                vec![block("rust", Some("src/lib.rs"), "let s = \"```\";")],
+            ),
+            (
+                "unicode",
+                "```rust\n// src/größe.rs\n/// Größe in µm\npub fn größe() {}\n```",
+                vec![block("rust", Some("src/größe.rs"), "/// Größe in µm\npub fn größe() {}")],
+            ),
+            (
+                "indented fence",
+                "  ```rust\n  // src/lib.rs\n  fn a() {}\n  ```",
+                vec![block("rust", Some("src/lib.rs"), "  fn a() {}")],
+            ),
+            ("no blocks", "I cannot help with that.", vec![]),
+        ];
+        for (name, output, expected) in cases {
+            assert_eq!(&parse_blocks(output), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn test_sanitize() {
+        let root = Path::new("/out/io-sys");
+        assert_eq!(sanitize(Path::new("./src/lib.rs"), root), Some("src/lib.rs".into()));
+        assert_eq!(sanitize(Path::new("/out/io-sys/src/lib.rs"), root), Some("src/lib.rs".into()));
+        assert_eq!(sanitize(Path::new("/etc/passwd"), root), None);
+        assert_eq!(sanitize(Path::new("../other/src/lib.rs"), root), None);
+        assert_eq!(sanitize(Path::new("src/../../lib.rs"), root), None);
+        assert_eq!(sanitize(Path::new("/out/io-sys"), root), None);
+    }
+
+    #[test]
+    fn test_files() {
+        let output = "```rust\n// src/lib.rs\nold\n```\n```zig\n// src/root.zig\nzig\n```\n\
+                      ```rust\n// /etc/passwd\nroot\n```\n```rust\n// src/lib.rs\nnew\n```";
+        let files = files(output, &["rust", "rs"], Path::new("/out/io-sys"), &crate::NullSink);
+        assert_eq!(files, [GeneratedFile::new("src/lib.rs", "new".to_owned()).unwrap()]);
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use gemini::RetryPolicy;
+use serde::Deserialize;
+
+use crate::{BindError, ModelConfig};
+
+/// Settings of a binding run, read from `bind.toml`. Every key is optional
+/// and unknown keys are rejected, so a typo does not silently fall back to
+/// a default.
+///
+/// ```toml
+/// source = "zig/src"
+/// target = "bindings"
+///
+/// [model]
+/// provider = "openai-compatible"
+/// name = "qwen2.5-coder"
+/// base_url = "http://localhost:8080/v1"
+///
+/// [critique]
+/// threshold = 90
+/// ```
+///
+/// `templates` names a directory of `.prompt` files that replace the built-in
+/// prompts, see [`crate::PromptTemplate`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BindConfig {
+    pub source: PathBuf,
+    pub target: PathBuf,
+    /// Where `generation_zig.prompt`, `critique.prompt` and the like are
+    /// looked up before falling back to the built-in prompts.
+    pub templates: Option<PathBuf>,
+    pub model: ModelSettings,
+    pub critique: CritiqueSettings,
+    pub container: ContainerSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModelProvider {
+    Gemini,
+    #[serde(rename = "openai-compatible")]
+    OpenAiCompatible,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelSettings {
+    pub provider: ModelProvider,
+    pub name: String,
+    /// API root of an OpenAI-compatible server, required for that provider.
+    pub base_url: Option<String>,
+    /// Variable holding the API key. Gemini defaults to `GEMINI_API_KEY`.
+    pub api_key_env: Option<String>,
+    pub temperature: f32,
+    /// Attempts per request, the first one included.
+    pub retries: u32,
+    /// Tokens of source a prompt may hold. Taken from the input limit the
+    /// model reports when not set.
+    pub context_tokens: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CritiqueSettings {
+    /// Score out of 100 the evaluation has to reach for bindings to pass.
+    pub threshold: u32,
+    /// Critique rounds before the last bindings are taken as they are.
+    pub max_rounds: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ContainerSettings {
+    /// Base image as `name:tag`.
+    pub image: String,
+    /// How long a build container is reused before the next run removes it
+    /// and starts over, in seconds, see [`crate::cleanup`].
+    pub keep_alive: u64,
+    pub execution: Execution,
+}
+
+/// Where toolchains run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Execution {
+    /// In a container if Docker is available, on the host otherwise.
+    #[default]
+    Auto,
+    Docker,
+    /// On the host, with the toolchains already installed.
+    Local,
+}
+
+/// What applying bindings does to the files already in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteMode {
+    /// Write everything, after backing up the previous tree to
+    /// `.bind-backup/<timestamp>` in the output directory.
+    #[default]
+    Overwrite,
+    /// Report a diff and a summary of what would change, and write nothing.
+    DryRun,
+    /// Show the diff of every file and ask before writing it.
+    Interactive,
+}
+
+impl std::str::FromStr for Execution {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "docker" => Ok(Self::Docker),
+            "local" => Ok(Self::Local),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Default for BindConfig {
+    fn default() -> Self {
+        Self {
+            source: PathBuf::from("src"),
+            target: PathBuf::from("bindings"),
+            templates: None,
+            model: ModelSettings::default(),
+            critique: CritiqueSettings::default(),
+            container: ContainerSettings::default(),
+        }
+    }
+}
+
+impl Default for ModelSettings {
+    fn default() -> Self {
+        Self {
+            provider: ModelProvider::Gemini,
+            name: crate::GEMINI_MODEL.to_owned(),
+            base_url: None,
+            api_key_env: None,
+            temperature: 0.5,
+            retries: RetryPolicy::default().max_attempts,
+            context_tokens: None,
+        }
+    }
+}
+
+impl Default for CritiqueSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 85,
+            max_rounds: 10,
+        }
+    }
+}
+
+impl Default for ContainerSettings {
+    fn default() -> Self {
+        Self {
+            image: "ubuntu:latest".to_owned(),
+            keep_alive: 86400,
+            execution: Execution::Auto,
+        }
+    }
+}
+
+impl ContainerSettings {
+    /// The image name and tag, `latest` if none is given.
+    pub fn image(&self) -> (&str, &str) {
+        self.image.rsplit_once(':').unwrap_or((&self.image, "latest"))
+    }
+
+    pub fn keep_alive(&self) -> Duration {
+        Duration::from_secs(self.keep_alive)
+    }
+}
+
+impl BindConfig {
+    /// Reads `path`, applies `BIND_*` environment overrides and validates
+    /// the result.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BindError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| BindError::Config {
+            path: path.to_owned(),
+            message: e.to_string(),
+        })?;
+        Self::parse(&text, |var| env::var(var).ok()).map_err(|message| BindError::Config {
+            path: path.to_owned(),
+            message,
+        })
+    }
+
+    /// Like [`BindConfig::load`], with the file contents and environment given.
+    pub fn parse(text: &str, var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut config: Self = toml::from_str(text).map_err(|e| e.to_string())?;
+        config.override_from(var)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn override_from(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        fn parsed<T: std::str::FromStr>(name: &str, value: String) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("{name}={value:?} is not a valid value"))
+        }
+
+        if let Some(value) = var("BIND_SOURCE") {
+            self.source = value.into();
+        }
+        if let Some(value) = var("BIND_TARGET") {
+            self.target = value.into();
+        }
+        if let Some(value) = var("BIND_TEMPLATES") {
+            self.templates = Some(value.into());
+        }
+        if let Some(value) = var("BIND_MODEL") {
+            self.model.name = value;
+        }
+        if let Some(value) = var("BIND_BASE_URL") {
+            self.model.base_url = Some(value);
+        }
+        if let Some(value) = var("BIND_API_KEY_ENV") {
+            self.model.api_key_env = Some(value);
+        }
+        if let Some(value) = var("BIND_TEMPERATURE") {
+            self.model.temperature = parsed("BIND_TEMPERATURE", value)?;
+        }
+        if let Some(value) = var("BIND_CONTEXT_TOKENS") {
+            self.model.context_tokens = Some(parsed("BIND_CONTEXT_TOKENS", value)?);
+        }
+        if let Some(value) = var("BIND_THRESHOLD") {
+            self.critique.threshold = parsed("BIND_THRESHOLD", value)?;
+        }
+        if let Some(value) = var("BIND_MAX_ROUNDS") {
+            self.critique.max_rounds = parsed("BIND_MAX_ROUNDS", value)?;
+        }
+        if let Some(value) = var("BIND_IMAGE") {
+            self.container.image = value;
+        }
+        if let Some(value) = var("BIND_KEEP_ALIVE") {
+            self.container.keep_alive = parsed("BIND_KEEP_ALIVE", value)?;
+        }
+        if let Some(value) = var("BIND_EXECUTION") {
+            self.container.execution = parsed("BIND_EXECUTION", value)?;
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.critique.threshold > 100 {
+            return Err(format!(
+                "critique.threshold is a score out of 100, got {}",
+                self.critique.threshold
+            ));
+        }
+        if self.critique.max_rounds == 0 {
+            return Err("critique.max_rounds must be at least 1".to_owned());
+        }
+        if !(0.0..=2.0).contains(&self.model.temperature) {
+            return Err(format!(
+                "model.temperature must be between 0.0 and 2.0, got {}",
+                self.model.temperature
+            ));
+        }
+        if self.model.retries == 0 {
+            return Err("model.retries counts the first attempt and must be at least 1".to_owned());
+        }
+        if self.model.context_tokens == Some(0) {
+            return Err("model.context_tokens must be at least 1".to_owned());
+        }
+        if self.model.provider == ModelProvider::OpenAiCompatible && self.model.base_url.is_none() {
+            return Err("model.base_url is required for the openai-compatible provider".to_owned());
+        }
+        if self.container.keep_alive == 0 {
+            return Err("container.keep_alive must be at least 1 second".to_owned());
+        }
+        Ok(())
+    }
+
+    /// The model these settings describe.
+    pub fn model_config(&self) -> ModelConfig {
+        let model = &self.model;
+        match model.provider {
+            ModelProvider::Gemini => ModelConfig::Gemini {
+                model: model.name.clone(),
+                api_key_env: model
+                    .api_key_env
+                    .clone()
+                    .unwrap_or(crate::GEMINI_API_KEY_ENV.to_owned()),
+                retries: model.retries,
+            },
+            ModelProvider::OpenAiCompatible => ModelConfig::OpenAiCompatible {
+                base_url: model.base_url.clone().unwrap_or_default(),
+                model: model.name.clone(),
+                api_key_env: model.api_key_env.clone(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> String {
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/bind.toml")).unwrap()
+    }
+
+    #[test]
+    fn test_parse_fixture() {
+        let config = BindConfig::parse(&fixture(), |_| None).unwrap();
+        assert_eq!(config.source, Path::new("zig/src"));
+        assert_eq!(config.target, Path::new("bindings/zig"));
+        assert_eq!(config.templates.as_deref(), Some(Path::new("prompts/zig")));
+        assert_eq!(config.model.provider, ModelProvider::OpenAiCompatible);
+        assert_eq!(config.model.name, "qwen2.5-coder");
+        assert_eq!(config.model.temperature, 0.3);
+        assert_eq!(config.model.context_tokens, Some(32000));
+        assert_eq!(config.critique.threshold, 90);
+        assert_eq!(config.critique.max_rounds, 4);
+        assert_eq!(config.container.image(), ("debian", "bookworm"));
+        assert_eq!(config.container.keep_alive(), Duration::from_secs(3600));
+        assert_eq!(config.container.execution, Execution::Docker);
+        assert_eq!(
+            config.model_config(),
+            ModelConfig::OpenAiCompatible {
+                base_url: "http://localhost:8080/v1".to_owned(),
+                model: "qwen2.5-coder".to_owned(),
+                api_key_env: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = BindConfig::parse("", |_| None).unwrap();
+        assert_eq!(config, BindConfig::default());
+        assert_eq!(config.critique.threshold, 85);
+        assert_eq!(config.templates, None);
+        assert_eq!(config.container.image(), ("ubuntu", "latest"));
+        assert_eq!(
+            config.model_config(),
+            ModelConfig::Gemini {
+                model: "gemini-2.0-flash-thinking-exp".to_owned(),
+                api_key_env: "GEMINI_API_KEY".to_owned(),
+                retries: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let config = BindConfig::parse(&fixture(), |var| match var {
+            "BIND_THRESHOLD" => Some("70".to_owned()),
+            "BIND_MODEL" => Some("llama".to_owned()),
+            "BIND_EXECUTION" => Some("local".to_owned()),
+            "BIND_TEMPLATES" => Some("/etc/bind/prompts".to_owned()),
+            "BIND_CONTEXT_TOKENS" => Some("8000".to_owned()),
+            _ => None,
+        })
+        .unwrap();
+        assert_eq!(config.critique.threshold, 70);
+        assert_eq!(config.templates.as_deref(), Some(Path::new("/etc/bind/prompts")));
+        assert_eq!(config.model.context_tokens, Some(8000));
+        assert_eq!(config.model.name, "llama");
+        assert_eq!(config.container.execution, Execution::Local);
+
+        let err = BindConfig::parse("", |var| (var == "BIND_MAX_ROUNDS").then(|| "many".to_owned()));
+        assert_eq!(err.unwrap_err(), "BIND_MAX_ROUNDS=\"many\" is not a valid value");
+    }
+
+    #[test]
+    fn test_rejects_invalid() {
+        let err = BindConfig::parse("[critique]\nthreshold = 101\n", |_| None).unwrap_err();
+        assert!(err.contains("critique.threshold"), "{err}");
+
+        let err = BindConfig::parse("[model]\ncontext_tokens = 0\n", |_| None).unwrap_err();
+        assert!(err.contains("model.context_tokens"), "{err}");
+
+        let err = BindConfig::parse("[model]\ntemprature = 0.5\n", |_| None).unwrap_err();
+        assert!(err.contains("unknown field `temprature`"), "{err}");
+
+        let err = BindConfig::parse("[model]\nprovider = \"openai-compatible\"\n", |_| None).unwrap_err();
+        assert!(err.contains("model.base_url"), "{err}");
+    }
+}
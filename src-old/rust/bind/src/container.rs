@@ -1 +1,142 @@
-pub use docker::*;
+//! The build containers bind keeps around between runs, and their cleanup.
+
+use std::{
+    env,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use docker::{ContainerConfigBuilder, Docker, DockerError, container_config};
+
+use crate::Language;
+
+/// Carried by every build container, which is how [`cleanup`] finds them.
+pub(crate) const LABEL: &str = "angelite.bind";
+const SOURCE_LABEL: &str = "angelite.bind.source";
+const TARGET_LABEL: &str = "angelite.bind.target";
+
+/// The build container for a pair of languages, shared by every run binding
+/// between them.
+pub(crate) fn name(src_lang: Language, dst_lang: Language) -> String {
+    format!("Build_BindAI_{src_lang:?}_{dst_lang:?}")
+}
+
+/// How a build container is created. It runs until it is shut down or
+/// cleaned up, so later runs find their toolchains already installed.
+pub(crate) fn config(src_lang: Language, dst_lang: Language) -> ContainerConfigBuilder {
+    let language = |lang: Language| format!("{lang:?}").to_lowercase();
+    container_config()
+        .working_dir(env::current_dir().unwrap().to_str().unwrap())
+        .cmd(vec!["sleep", "infinity"])
+        .label(LABEL, "1")
+        .label(SOURCE_LABEL, language(src_lang))
+        .label(TARGET_LABEL, language(dst_lang))
+}
+
+/// Removes the build containers created more than `older_than` ago, running
+/// or not, and returns their names.
+pub fn cleanup(older_than: Duration) -> Result<Vec<String>, DockerError> {
+    let mut containers = vec![];
+    for container in Docker::list_containers_with_label(format!("{LABEL}=1"))? {
+        let created = Docker::inspect_container(container.name())?.created;
+        containers.push((container.name().to_owned(), created));
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let stale = stale(&containers, now, older_than);
+    for name in &stale {
+        Docker::force_remove_container(name)?;
+    }
+    Ok(stale)
+}
+
+/// The names among `containers` created more than `older_than` before `now`,
+/// both since the Unix epoch. Containers of unknown age are kept.
+fn stale(
+    containers: &[(String, Option<String>)],
+    now: Duration,
+    older_than: Duration,
+) -> Vec<String> {
+    containers
+        .iter()
+        .filter(|(_, created)| {
+            created
+                .as_deref()
+                .and_then(parse_created)
+                .is_some_and(|created| now.saturating_sub(created) > older_than)
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+/// Time since the Unix epoch of a UTC timestamp as Docker writes it, like
+/// `2024-05-01T12:30:00.123456789Z`. Fractions of a second are dropped.
+fn parse_created(created: &str) -> Option<Duration> {
+    let (date, time) = created.strip_suffix('Z')?.split_once('T')?;
+    let time = time.split('.').next()?;
+    let fields = |text: &str, sep| -> Option<Vec<i64>> {
+        text.split(sep).map(|field| field.parse().ok()).collect()
+    };
+    let (date, time) = (fields(date, '-')?, fields(time, ':')?);
+    let ([year, month, day], [hour, minute, second]) = (
+        <[i64; 3]>::try_from(date).ok()?,
+        <[i64; 3]>::try_from(time).ok()?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days from the epoch to the civil date, after Howard Hinnant's
+    // `days_from_civil`
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels() {
+        let config = config(Language::Zig, Language::Rust).build();
+        assert_eq!(config.cmd.unwrap(), ["sleep", "infinity"]);
+        assert_eq!(config.labels[LABEL], "1");
+        assert_eq!(config.labels[SOURCE_LABEL], "zig");
+        assert_eq!(config.labels[TARGET_LABEL], "rust");
+        assert_eq!(name(Language::Zig, Language::Rust), "Build_BindAI_Zig_Rust");
+    }
+
+    #[test]
+    fn test_parse_created() {
+        assert_eq!(parse_created("1970-01-01T00:00:00Z"), Some(Duration::ZERO));
+        assert_eq!(
+            parse_created("2024-03-01T12:30:15.123456789Z"),
+            Some(Duration::from_secs(1709296215))
+        );
+        assert_eq!(parse_created("2024-03-01T12:30:15+02:00"), None);
+        assert_eq!(parse_created("yesterday"), None);
+    }
+
+    #[test]
+    fn test_stale() {
+        let containers = [
+            ("old".to_owned(), Some("2024-03-01T00:00:00Z".to_owned())),
+            ("new".to_owned(), Some("2024-03-01T23:00:00.5Z".to_owned())),
+            ("unknown".to_owned(), None),
+            ("garbled".to_owned(), Some("March".to_owned())),
+        ];
+        let now = parse_created("2024-03-02T00:00:00Z").unwrap();
+        assert_eq!(
+            stale(&containers, now, Duration::from_secs(2 * 3600)),
+            ["old"]
+        );
+        assert!(stale(&containers, now, Duration::from_secs(86400)).is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+
+/// The critique loop ran out of rounds before any bindings reached the
+/// threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindFailure {
+    /// The best scoring bindings, the earliest of equals.
+    pub best: String,
+    pub best_score: u32,
+    pub threshold: u32,
+    /// The score of every round, in order.
+    pub scores: Vec<u32>,
+    /// The critique of every round that got one, in order.
+    pub critiques: Vec<String>,
+}
+
+impl fmt::Display for BindFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "no bindings scored {} within {} critique rounds, the best scored {}",
+            self.threshold,
+            self.scores.len(),
+            self.best_score
+        )
+    }
+}
+
+impl std::error::Error for BindFailure {}
+
+/// The first integer in an evaluation, clamped to a score out of 100. Takes
+/// `85`, `85%`, `Score: 85/100` and `-3`.
+pub(crate) fn parse_score(reply: &str) -> Option<u32> {
+    let (start, digits) = first_number(reply, false)?;
+    let negative = reply[..start].ends_with('-');
+    let score = match negative {
+        true => 0,
+        false => digits.parse::<u64>().map_or(100, |score| score.min(100) as u32),
+    };
+    Some(score)
+}
+
+/// The first number in a temperature reply, clamped to `0.0..=1.0`.
+pub(crate) fn parse_temperature(reply: &str) -> Option<f32> {
+    let (start, digits) = first_number(reply, true)?;
+    let temperature = digits.parse::<f32>().ok()?;
+    let negative = reply[..start].ends_with('-');
+    Some(if negative { 0.0 } else { temperature.clamp(0.0, 1.0) })
+}
+
+/// Where the first run of digits in `text` starts and the run itself, with a
+/// fractional part if `decimal` is set.
+fn first_number(text: &str, decimal: bool) -> Option<(usize, &str)> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let rest = &text[start..];
+    let mut end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+    if decimal
+        && rest[end..].starts_with('.')
+        && rest[end + 1..].starts_with(|c: char| c.is_ascii_digit())
+    {
+        let fraction = &rest[end + 1..];
+        end += 1 + fraction.find(|c: char| !c.is_ascii_digit()).unwrap_or(fraction.len());
+    }
+    Some((start, &rest[..end]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_score() {
+        let cases = [
+            ("85", Some(85)),
+            (" 92\n", Some(92)),
+            ("85%", Some(85)),
+            ("Score: 70/100", Some(70)),
+            ("I'd say 64.5, mostly idiomatic", Some(64)),
+            ("150", Some(100)),
+            ("99999999999999999999999", Some(100)),
+            ("-3", Some(0)),
+            ("Looks great!", None),
+            ("", None),
+        ];
+        for (reply, expected) in cases {
+            assert_eq!(parse_score(reply), expected, "{reply:?}");
+        }
+    }
+
+    #[test]
+    fn test_parse_temperature() {
+        let cases = [
+            ("0.3", Some(0.3)),
+            ("Let's try 0.75 instead.", Some(0.75)),
+            ("1", Some(1.0)),
+            ("1.5", Some(1.0)),
+            ("-0.2", Some(0.0)),
+            ("0.", Some(0.0)),
+            ("cooler", None),
+        ];
+        for (reply, expected) in cases {
+            assert_eq!(parse_temperature(reply), expected, "{reply:?}");
+        }
+    }
+}
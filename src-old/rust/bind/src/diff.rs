@@ -0,0 +1,212 @@
+//! Line diffs between the files on disk and the ones about to replace them.
+
+use std::{fmt, path::Path};
+
+/// Lines of unchanged context around every change.
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Created,
+    Modified,
+    Deleted,
+}
+
+impl Change {
+    /// How `old` becomes `new`, `None` when it stays the same.
+    pub fn between(old: Option<&str>, new: Option<&str>) -> Option<Self> {
+        match (old, new) {
+            (None, Some(_)) => Some(Self::Created),
+            (Some(_), None) => Some(Self::Deleted),
+            (Some(old), Some(new)) if old != new => Some(Self::Modified),
+            _ => None,
+        }
+    }
+}
+
+/// How many files a set of changes creates, modifies and deletes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Summary {
+    pub created: usize,
+    pub modified: usize,
+    pub deleted: usize,
+}
+
+impl Summary {
+    pub fn add(&mut self, change: Change) {
+        match change {
+            Change::Created => self.created += 1,
+            Change::Modified => self.modified += 1,
+            Change::Deleted => self.deleted += 1,
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} created, {} modified, {} deleted",
+            self.created, self.modified, self.deleted
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// `old` turned into `new` as a unified diff of `path`, where a missing side
+/// is a created or deleted file. Empty when nothing changed.
+pub fn unified(path: &Path, old: Option<&str>, new: Option<&str>) -> String {
+    if Change::between(old, new).is_none() {
+        return String::new();
+    }
+    let old_lines: Vec<&str> = old.map(|old| old.lines().collect()).unwrap_or_default();
+    let new_lines: Vec<&str> = new.map(|new| new.lines().collect()).unwrap_or_default();
+    let ops = ops(&old_lines, &new_lines);
+
+    let mut out = format!(
+        "--- {}\n+++ {}\n",
+        old.map_or("/dev/null".to_owned(), |_| format!("a/{}", path.display())),
+        new.map_or("/dev/null".to_owned(), |_| format!("b/{}", path.display())),
+    );
+    // Position in both files before every op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut a, mut b) = (0, 0);
+    for op in &ops {
+        positions.push((a, b));
+        match op {
+            Op::Equal => (a, b) = (a + 1, b + 1),
+            Op::Delete => a += 1,
+            Op::Insert => b += 1,
+        }
+    }
+    positions.push((a, b));
+
+    let changed: Vec<usize> = (0..ops.len()).filter(|&i| ops[i] != Op::Equal).collect();
+    let mut i = 0;
+    while i < changed.len() {
+        let start = changed[i].saturating_sub(CONTEXT);
+        let mut end = changed[i] + 1;
+        // Changes with little enough between them share a hunk
+        while i + 1 < changed.len() && changed[i + 1] <= end + 2 * CONTEXT {
+            i += 1;
+            end = changed[i] + 1;
+        }
+        let end = (end + CONTEXT).min(ops.len());
+        i += 1;
+
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        let range = |start: usize, len: usize| match len {
+            0 => format!("{start},0"),
+            _ => format!("{},{len}", start + 1),
+        };
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_end - old_start),
+            range(new_start, new_end - new_start)
+        ));
+        for (op, &(a, b)) in ops[start..end].iter().zip(&positions[start..end]) {
+            let (sign, line) = match op {
+                Op::Equal => (' ', old_lines[a]),
+                Op::Delete => ('-', old_lines[a]),
+                Op::Insert => ('+', new_lines[b]),
+            };
+            out.push(sign);
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// The edit script of a longest common subsequence, with deletions before
+/// insertions where both are possible. Quadratic in the lines between the
+/// common prefix and suffix, which is plenty for generated bindings.
+fn ops(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (a, b) = (
+        &old[prefix..old.len() - suffix],
+        &new[prefix..new.len() - suffix],
+    );
+
+    // lcs[i][j] is the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = match a[i] == b[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut ops = vec![Op::Equal; prefix];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push(Op::Equal);
+            (i, j) = (i + 1, j + 1);
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete);
+            i += 1;
+        } else {
+            ops.push(Op::Insert);
+            j += 1;
+        }
+    }
+    ops.extend(std::iter::repeat_n(Op::Equal, suffix));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modified_file() {
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+        let new = "a\nB\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\nn\n";
+        assert_eq!(
+            unified(Path::new("src/lib.rs"), Some(old), Some(new)),
+            "--- a/src/lib.rs\n\
+             +++ b/src/lib.rs\n\
+             @@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n\
+             @@ -11,3 +11,4 @@\n k\n l\n m\n+n\n"
+        );
+        assert_eq!(unified(Path::new("src/lib.rs"), Some(old), Some(old)), "");
+    }
+
+    #[test]
+    fn test_new_and_deleted_files() {
+        assert_eq!(
+            unified(
+                Path::new("build.zig"),
+                None,
+                Some("const std = @import(\"std\");\n")
+            ),
+            "--- /dev/null\n+++ b/build.zig\n@@ -0,0 +1,1 @@\n+const std = @import(\"std\");\n"
+        );
+        assert_eq!(
+            unified(Path::new("old.zig"), Some("x\ny\n"), None),
+            "--- a/old.zig\n+++ /dev/null\n@@ -1,2 +0,0 @@\n-x\n-y\n"
+        );
+
+        let mut summary = Summary::default();
+        for change in [Change::Created, Change::Modified, Change::Created] {
+            summary.add(change);
+        }
+        assert_eq!(summary.to_string(), "2 created, 1 modified, 0 deleted");
+    }
+}
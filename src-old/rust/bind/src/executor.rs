@@ -0,0 +1,195 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use docker::{CommandResult, Container, Docker};
+
+use crate::Script;
+
+/// Where the toolchains run: a build container, or the host for machines
+/// that already have them installed.
+pub trait Executor {
+    fn exec(&self, cmd: &[&str]) -> Result<CommandResult, String>;
+
+    /// Copies the host file or directory at `host` to `dest`.
+    fn copy_to(&self, host: &Path, dest: &Path) -> Result<(), String>;
+
+    /// Every file ending in `.{ext}` under `path`.
+    fn find_files(&self, path: &Path, ext: &str) -> Result<Vec<PathBuf>, String> {
+        let path_str = path.display().to_string();
+        let glob = format!("*.{ext}");
+        let result = self.exec(&["find", &path_str, "-name", &glob, "-type", "f"])?;
+        if !result.success {
+            return Err(result.stderr);
+        }
+        Ok(result
+            .stdout
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(PathBuf::from)
+            .collect())
+    }
+
+    /// Whether commands run on the host, where toolchains are checked for
+    /// rather than installed.
+    fn is_local(&self) -> bool {
+        false
+    }
+
+    /// Stops whatever the commands run in, and removes it too if `remove` is
+    /// set. Nothing to do on the host.
+    fn shutdown(&self, _remove: bool) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl dyn Executor + '_ {
+    /// Places `script` in a fresh temporary directory, ready to run.
+    pub fn inject(&self, script: impl AsRef<str>) -> Script<'_> {
+        let temp = self
+            .exec(&["mktemp", "-d"])
+            .expect("Failed to create temporary directory");
+        let script_path = PathBuf::from(temp.stdout.trim()).join("script.sh");
+
+        let host = env::temp_dir().join(format!("bind-script-{}.sh", std::process::id()));
+        fs::write(&host, script.as_ref()).expect("Failed to write script");
+        self.copy_to(&host, &script_path).expect("Failed to copy script");
+        let _ = fs::remove_file(host);
+
+        Script {
+            executor: self,
+            script_path,
+        }
+    }
+}
+
+impl Executor for Container {
+    fn exec(&self, cmd: &[&str]) -> Result<CommandResult, String> {
+        Container::exec(self, cmd).map_err(|e| e.to_string())
+    }
+
+    fn copy_to(&self, host: &Path, dest: &Path) -> Result<(), String> {
+        Container::copy_to(self, host, dest.to_string_lossy()).map_err(|e| e.to_string())
+    }
+
+    fn shutdown(&self, remove: bool) -> Result<(), String> {
+        let result = match remove {
+            true => Docker::force_remove_container(self.name()),
+            false => Docker::stop_container(self.name()),
+        };
+        result.map_err(|e| e.to_string())
+    }
+}
+
+/// Runs commands on the host, from a workspace directory standing in for the
+/// container's working directory.
+pub struct LocalExecutor {
+    workspace: PathBuf,
+    /// Created by [`LocalExecutor::temp`] and removed with it.
+    owned: bool,
+}
+
+impl LocalExecutor {
+    pub fn new(workspace: impl Into<PathBuf>) -> Self {
+        Self {
+            workspace: workspace.into(),
+            owned: false,
+        }
+    }
+
+    /// A fresh workspace under the system temporary directory.
+    pub fn temp() -> io::Result<Self> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let workspace = env::temp_dir().join(format!("bind-local-{}-{nanos}", std::process::id()));
+        fs::create_dir_all(&workspace)?;
+        Ok(Self {
+            workspace,
+            owned: true,
+        })
+    }
+
+    pub fn workspace(&self) -> &Path {
+        &self.workspace
+    }
+}
+
+impl Executor for LocalExecutor {
+    fn exec(&self, cmd: &[&str]) -> Result<CommandResult, String> {
+        let (program, args) = cmd.split_first().ok_or("empty command")?;
+        let out = Command::new(program)
+            .args(args)
+            .current_dir(&self.workspace)
+            .output()
+            .map_err(|e| format!("could not run {program}: {e}"))?;
+        Ok(CommandResult {
+            success: out.status.success(),
+            stdout: String::from_utf8_lossy(&out.stdout).to_string(),
+            stderr: String::from_utf8_lossy(&out.stderr).to_string(),
+            exit_code: out.status.code().unwrap_or(-1),
+        })
+    }
+
+    fn copy_to(&self, host: &Path, dest: &Path) -> Result<(), String> {
+        let dest = self.workspace.join(dest);
+        if host == dest {
+            return Ok(());
+        }
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let (host, dest) = (host.to_string_lossy(), dest.to_string_lossy());
+        let result = self.exec(&["cp", "-r", &host, &dest])?;
+        match result.success {
+            true => Ok(()),
+            false => Err(result.stderr),
+        }
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+impl Drop for LocalExecutor {
+    fn drop(&mut self) {
+        if self.owned {
+            let _ = fs::remove_dir_all(&self.workspace);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_exec() {
+        let local = LocalExecutor::temp().unwrap();
+        let workspace = local.workspace().to_owned();
+        let host = workspace.join("host.txt");
+        fs::write(&host, "copied").unwrap();
+
+        local.copy_to(&host, Path::new("nested/copy.txt")).unwrap();
+        let result = local.exec(&["cat", "nested/copy.txt"]).unwrap();
+        assert!(result.success);
+        assert_eq!(result.stdout, "copied");
+
+        assert_eq!(
+            local.find_files(&workspace, "txt").unwrap().len(),
+            2,
+            "{:?}",
+            local.find_files(&workspace, "txt")
+        );
+        let failed = local.exec(&["cat", "missing.txt"]).unwrap();
+        assert!(!failed.success);
+        assert_ne!(failed.exit_code, 0);
+        assert!(local.exec(&["no-such-program-here"]).is_err());
+
+        drop(local);
+        assert!(!workspace.exists());
+    }
+}
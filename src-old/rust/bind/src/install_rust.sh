@@ -0,0 +1,26 @@
+#!/bin/bash
+set -e
+
+# Script for installing a Rust toolchain on Ubuntu Docker container
+
+# Update package repository
+apt-get update
+
+# Install dependencies
+apt-get install -y \
+    curl \
+    build-essential
+
+# Install rustup with the stable toolchain
+echo "Installing Rust via rustup..."
+curl --proto '=https' --tlsv1.2 -sSf https://sh.rustup.rs | sh -s -- -y --profile minimal
+
+# Add cargo to PATH
+echo 'export PATH="$HOME/.cargo/bin:$PATH"' >> /etc/profile.d/rust.sh
+echo 'export PATH="$HOME/.cargo/bin:$PATH"' >> ~/.bashrc
+export PATH="$HOME/.cargo/bin:$PATH"
+
+# Verify installation
+cargo --version
+
+echo "Rust has been successfully installed on Ubuntu!"
@@ -5,47 +5,55 @@
     stmt_expr_attributes,
     coroutine_trait
 )]
-use docker::{CommandResult, Container, Docker, Image, container_config};
-use gemini::{GeminiClient, GeminiError};
+use docker::{CommandResult, Container, Docker, Image};
+use cache::ResponseCache;
+use state::Manifest;
+use ratelimit::RateLimited;
+use pack::PackedSources;
+use session::SessionLog;
+use template::Prompts;
+use write::Plan;
+use gemini::{
+    GeminiClient, GeminiError, ModelUsage, PooledTransport, RetryEvent, RetryPolicy, Transport, UsageTracker,
+};
 use serde::Deserialize;
 use std::{
-    cell::{OnceCell, RefCell, UnsafeCell}, collections::HashMap, env, fs, ops::{ControlFlow, Coroutine, CoroutineState}, os::unix::process::ExitStatusExt, path::{Path, PathBuf}, pin::{pin, Pin}, process::Command, rc::Rc, sync::{Arc, OnceLock}, thread::{self, current}, time::{Duration, SystemTime}
+    cell::{OnceCell, RefCell, UnsafeCell}, env, fs, ops::{ControlFlow, Coroutine, CoroutineState}, os::unix::process::ExitStatusExt, path::{Path, PathBuf}, pin::{pin, Pin}, process::Command, rc::Rc, sync::{Arc, Mutex, OnceLock}, thread::{self, current}, time::{Duration, SystemTime}
 };
 
+mod abi;
+mod cache;
+pub mod codeblocks;
+mod config;
 mod container;
-
-pub trait ContainerExt {
-    fn inject(&self, script: impl AsRef<str>) -> Script;
-}
-
-impl ContainerExt for Container {
-    fn inject(&self, script: impl AsRef<str>) -> Script {
-        let script_content = script.as_ref();
-
-        // Ask the container for a temporary file path
-        let temp_path_output = self
-            .exec(&["mktemp", "-d"])
-            .expect("Failed to create temporary file in container");
-
-        // Parse the output to get the path (mktemp outputs the created path)
-        let temp_path_str = temp_path_output.stdout.trim();
-        let mut script_path = PathBuf::from(temp_path_str);
-
-        // Write the script content to the file
-        let hosttemp = env::temp_dir().join("script.sh");
-        std::fs::write(&hosttemp, script_content).expect("Failed to write script to container");
-        self.copy_to(hosttemp.to_str().unwrap(), script_path.to_str().unwrap())
-            .unwrap();
-
-        // Create a temporary file for the script
-        script_path.push("script.sh"); // Or another appropriate name
-
-        Script {
-            container: self,
-            script_path,
-        }
-    }
-}
+mod critique;
+pub mod diff;
+mod executor;
+mod openai;
+mod pack;
+mod parallel;
+pub mod post;
+pub mod progress;
+mod ratelimit;
+mod session;
+mod state;
+mod template;
+pub mod watch;
+mod write;
+
+pub use critique::BindFailure;
+pub use container::cleanup;
+pub use config::{
+    BindConfig, ContainerSettings, CritiqueSettings, Execution, ModelProvider, ModelSettings, WriteMode,
+};
+pub use executor::{Executor, LocalExecutor};
+pub use openai::OpenAiCompatible;
+pub use post::{GeneratedFile, HeaderBanner, PostContext, PostError, PostProcessor, Rustfmt};
+pub use progress::{AttemptResult, Event, JsonLinesSink, NullSink, ProgressSink, StdoutSink};
+pub use ratelimit::TokenBucket;
+pub use session::{Round, Session};
+pub use template::{PromptTemplate, TemplateError, Vars};
+pub use watch::{WatchOptions, watch};
 
 #[derive(Deserialize, Debug)]
 pub enum Invalid {
@@ -58,9 +66,59 @@ pub enum Invalid {
 pub enum Error {
     Missing { path: PathBuf },
     Invalid { src: Invalid, msg: String },
+    MissingDependency { package: String },
+}
+
+/// Ways a binding run can fail, short of the bindings not compiling.
+#[derive(Debug)]
+pub enum BindError {
+    Io(std::io::Error),
+    /// `bind.toml` could not be read or holds invalid settings.
+    Config { path: PathBuf, message: String },
+    /// The critique loop gave up on the bindings.
+    Failure(BindFailure),
+    /// Two prompt units generated the same file, see [`Config::concurrency`].
+    Conflict { path: PathBuf, units: [Vec<PathBuf>; 2] },
+    /// A session could not be resumed.
+    Session { id: String, message: String },
+    /// A tool failed on the sources in a way bind cannot work around, with
+    /// what it printed.
+    Tool { output: String, explanation: String },
+}
+
+impl std::fmt::Display for BindError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindError::Io(e) => write!(f, "I/O error: {e}"),
+            BindError::Config { path, message } => write!(f, "{}: {message}", path.display()),
+            BindError::Failure(failure) => failure.fmt(f),
+            BindError::Conflict { path, units } => {
+                let [a, b] = units.each_ref().map(|unit| {
+                    unit.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(", ")
+                });
+                write!(f, "{} was generated both from {a} and from {b}", path.display())
+            }
+            BindError::Session { id, message } => write!(f, "cannot resume session {id}: {message}"),
+            BindError::Tool { output, explanation } => write!(f, "{explanation}\n{}", output.trim_end()),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl std::error::Error for BindError {}
+
+impl From<std::io::Error> for BindError {
+    fn from(e: std::io::Error) -> Self {
+        BindError::Io(e)
+    }
+}
+
+impl From<BindFailure> for BindError {
+    fn from(failure: BindFailure) -> Self {
+        BindError::Failure(failure)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Language {
     Rust,
     Zig,
@@ -72,378 +130,513 @@ pub struct Output {
     pub crate_name: String,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Config {
     pub source: PathBuf,
     pub target: PathBuf,
     pub external_prompt: Option<String>,
+    /// Run in order on every generated file before it is written.
+    pub post_processors: Vec<Arc<dyn PostProcessor>>,
+    /// Model, critique and container settings.
+    pub settings: BindConfig,
+    /// Bind every source file again instead of only those changed since the
+    /// last successful run, and start the output over.
+    pub full_rebuild: bool,
+    /// Prompt units generated at once, each on its own thread with a model
+    /// built from `settings`. At most one when 0 or 1.
+    pub concurrency: usize,
+    /// Ties sources into prompt units, with a line like `shape.zig: point.zig`
+    /// for every source that needs others alongside, relative to `source`.
+    /// Every source is a unit of its own without one.
+    pub dependency_hints: Option<PathBuf>,
+    /// Paces every model request of the run, across all units.
+    pub rate_limit: Option<Arc<TokenBucket>>,
+    /// Sessions kept in `target`, the oldest removed first as new ones start.
+    pub keep_sessions: usize,
+    /// Whether applying bindings writes, previews or asks first.
+    pub write_mode: WriteMode,
+}
+
+impl Config {
+    /// Reads the settings from a `bind.toml`, see [`BindConfig::load`].
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, BindError> {
+        BindConfig::load(path).map(Self::from)
+    }
+}
+
+impl From<BindConfig> for Config {
+    fn from(settings: BindConfig) -> Self {
+        Self {
+            source: settings.source.clone(),
+            target: settings.target.clone(),
+            external_prompt: None,
+            post_processors: vec![],
+            settings,
+            full_rebuild: false,
+            concurrency: 1,
+            dependency_hints: None,
+            rate_limit: None,
+            keep_sessions: 10,
+            write_mode: WriteMode::Overwrite,
+        }
+    }
 }
 
 static CTX: OnceLock<Context> = OnceLock::new();
 
+const GEMINI_MODEL: &str = "gemini-2.0-flash-thinking-exp";
+const GEMINI_API_KEY_ENV: &str = "GEMINI_API_KEY";
+
+/// Which model the binding loop talks to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelConfig {
+    Gemini {
+        model: String,
+        api_key_env: String,
+        /// Attempts per request, the first one included.
+        retries: u32,
+    },
+    /// Any server speaking the OpenAI chat completions API, e.g. llama.cpp.
+    OpenAiCompatible {
+        base_url: String,
+        model: String,
+        api_key_env: Option<String>,
+    },
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self::Gemini {
+            model: GEMINI_MODEL.to_owned(),
+            api_key_env: GEMINI_API_KEY_ENV.to_owned(),
+            retries: RetryPolicy::default().max_attempts,
+        }
+    }
+}
+
+impl ModelConfig {
+    pub fn build(&self, system: String, temperature: f32) -> Rc<dyn Model> {
+        match self {
+            Self::Gemini {
+                model,
+                api_key_env,
+                retries,
+            } => Rc::new(
+                Gemini::connect(model, api_key_env, system, temperature).with_retries(*retries),
+            ),
+            Self::OpenAiCompatible {
+                base_url,
+                model,
+                api_key_env,
+            } => Rc::new(OpenAiCompatible::connect(
+                base_url,
+                model,
+                api_key_env.as_deref(),
+                system,
+                temperature,
+            )),
+        }
+    }
+}
+
 pub struct Gemini {
     client: RefCell<GeminiClient>,
+    model: String,
+    api_key: String,
+    retry: RetryPolicy,
     temperature: f32,
     system: String,
+    /// Outlives the clients replaced on temperature changes.
+    usage: UsageTracker,
+    /// Retries made since they were last taken, for the caller to report.
+    retries: Arc<Mutex<Vec<RetryEvent>>>,
+    /// Shared by the clients, so the requests of the critique rounds go over
+    /// connections kept open since the first.
+    transport: Arc<dyn Transport>,
 }
 impl Gemini {
-    fn client(temperature: f32) -> GeminiClient {
-        GeminiClient::new("gemini-2.0-flash-thinking-exp")
-            .with_temperature(temperature)
-            .with_api_key(&*env::var("GEMINI_API_KEY").unwrap())
+    pub fn new(system: String, temperature: f32) -> Self {
+        Self::connect(GEMINI_MODEL, GEMINI_API_KEY_ENV, system, temperature)
     }
-}
 
-pub trait ResponseCoroutine =
-    std::ops::Coroutine<(), Yield = Result<String, GeminiError>, Return = Result<(), GeminiError>>;
-impl Model for Gemini {
-    fn new(system: String, temperature: f32) -> Self {
+    pub fn connect(model: &str, api_key_env: &str, system: String, temperature: f32) -> Self {
+        let usage = UsageTracker::new();
+        let api_key = env::var(api_key_env).unwrap_or_else(|_| panic!("{api_key_env} is not set"));
+        let retry = RetryPolicy::default();
+        let retries = Arc::default();
+        let transport: Arc<dyn Transport> = Arc::new(PooledTransport::new());
         Self {
-            client: Self::client(temperature).into(),
+            client: Self::client(model, &api_key, retry, temperature, &usage, &retries, &transport).into(),
+            model: model.to_owned(),
+            api_key,
+            retry,
             temperature,
             system,
+            usage,
+            retries,
+            transport,
         }
     }
 
+    /// Attempts per request, the first one included.
+    pub fn with_retries(mut self, attempts: u32) -> Self {
+        self.retry.max_attempts = attempts;
+        self.change(self.temperature);
+        self
+    }
+
+    fn client(
+        model: &str,
+        api_key: &str,
+        retry: RetryPolicy,
+        temperature: f32,
+        usage: &UsageTracker,
+        retries: &Arc<Mutex<Vec<RetryEvent>>>,
+        transport: &Arc<dyn Transport>,
+    ) -> GeminiClient {
+        let retries = retries.clone();
+        GeminiClient::new(model)
+            .with_transport(transport.clone())
+            .with_temperature(temperature)
+            .with_usage_tracker(usage.clone())
+            .with_api_key(api_key)
+            .with_retry(retry)
+            .with_retry_callback(move |event| retries.lock().unwrap().push(event.clone()))
+    }
+}
+
+pub trait ResponseCoroutine =
+    std::ops::Coroutine<(), Yield = Result<String, GeminiError>, Return = Result<(), GeminiError>>;
+impl Model for Gemini {
     fn temp(&self) -> f32 {
         self.temperature
     }
     fn respond(&self, prompt: String) -> Pin<Box<dyn ResponseCoroutine + '_>> {
-        // Clone what we need for the coroutine
-        let prompt_clone = prompt;
-
-        // Keep reference to client
         let client = &self.client;
 
+        // Retries happen inside the client, which also drops text a restarted
+        // stream already delivered, so the stream can be forwarded as is.
         Box::pin(
             #[coroutine]
             static move || {
-                let max_retries = 3;
-                let mut retries = 0;
-
+                let mut stream = Box::into_pin(client.borrow().generate_content_streaming(&prompt));
                 loop {
-                    // Get a streaming coroutine for this attempt
-                    let mut stream_coroutine = client
-                        .borrow_mut()
-                        .generate_content_streaming(&prompt_clone);
-                    let mut pinned = unsafe { Pin::new_unchecked(&mut *stream_coroutine) };
-
-                    // Track any errors during streaming
-                    let mut had_error = false;
-
-                    // Process all yields from the streaming coroutine
-                    loop {
-                        match pinned.as_mut().resume(()) {
-                            std::ops::CoroutineState::Yielded(result) => {
-                                // Check for errors
-                                if let Err(_) = &result {
-                                    had_error = true;
-                                }
-
-                                // Forward the result to our caller
-                                yield result;
-                            }
-                            std::ops::CoroutineState::Complete(final_result) => {
-                                match final_result {
-                                    Ok(()) => {
-                                        // Stream completed successfully without errors
-                                        if !had_error {
-                                            return Ok(());
-                                        }
-
-                                        // Had errors during streaming - check if we should retry
-                                        break;
-                                    }
-                                    Err(e) => {
-                                        // Check if this is a network error (curl exit code 56)
-                                        let is_network_error = match &e {
-                                            GeminiError::HttpError(msg) => {
-                                                msg.contains("exit code: 56")
-                                            }
-                                            _ => false,
-                                        };
-
-                                        if is_network_error && retries < max_retries {
-                                            // Break out to retry
-                                            break;
-                                        }
-
-                                        // Non-retryable error or max retries exceeded
-                                        return Err(e);
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    // If we get here, we either need to retry or had errors
-                    if retries < max_retries {
-                        // Wait before retrying
-                        std::thread::sleep(std::time::Duration::from_secs(2));
-                        retries += 1;
-
-                        // Inform the user we're retrying
-                        yield Ok(format!(
-                            "Network error, retrying ({}/{})",
-                            retries, max_retries
-                        ));
-                        continue;
+                    let state = stream.as_mut().resume(());
+                    match state {
+                        CoroutineState::Yielded(result) => yield result,
+                        CoroutineState::Complete(result) => return result,
                     }
-
-                    // If we get here, we've exhausted our retries
-                    return Ok(());
                 }
             },
         )
     }
+    fn respond_json(&self, prompt: String) -> Result<serde_json::Value, GeminiError> {
+        self.client.borrow().generate_json(&prompt)
+    }
     fn change(&self, temp: f32) {
-        *self.client.borrow_mut() = Self::client(temp as f32);
+        *self.client.borrow_mut() = Self::client(
+            &self.model,
+            &self.api_key,
+            self.retry,
+            temp,
+            &self.usage,
+            &self.retries,
+            &self.transport,
+        );
+    }
+    fn usage(&self) -> Option<ModelUsage> {
+        Some(self.usage.total())
+    }
+    fn take_retries(&self) -> Vec<RetryEvent> {
+        std::mem::take(&mut self.retries.lock().unwrap())
+    }
+    fn input_limit(&self) -> Option<usize> {
+        let info = self.client.borrow().model_info().ok()?;
+        info.input_token_limit.map(|limit| limit as usize)
     }
 }
 ///Provides AI responses
 pub trait Model {
-    fn new(system: String, temperature: f32) -> Self
-    where
-        Self: Sized;
     fn respond(&self, prompt: String) -> Pin<Box<dyn ResponseCoroutine + '_>>;
+    /// Asks for a reply in JSON mode and returns it parsed.
+    fn respond_json(&self, prompt: String) -> Result<serde_json::Value, GeminiError>;
     fn change(&self, temp: f32);
     fn temp(&self) -> f32;
+    /// Tokens spent so far, if the model keeps count.
+    fn usage(&self) -> Option<ModelUsage> {
+        None
+    }
+    /// Most tokens a prompt may take, if the model reports a limit.
+    fn input_limit(&self) -> Option<usize> {
+        None
+    }
+    /// Failed requests the model retried since this was last called.
+    fn take_retries(&self) -> Vec<RetryEvent> {
+        vec![]
+    }
 }
 
-pub struct Prompter<M: Model> {
+/// Raises a warning for each request `model` had to retry.
+fn report_retries<M: Model + ?Sized>(model: &M, progress: &dyn ProgressSink) {
+    for retry in model.take_retries() {
+        progress.event(&Event::warning(format!(
+            "Attempt {} failed ({}), retrying in {:.1}s",
+            retry.attempt,
+            retry.error,
+            retry.delay.as_secs_f32()
+        )));
+    }
+}
+
+const BINDING_GUIDELINES: &str = include_str!("generate_bindings.prompt");
+
+pub struct Prompter<M: Model + ?Sized> {
     model: Rc<M>,
+    progress: Rc<dyn ProgressSink>,
+    /// Where rounds are recorded and resumed from.
+    session: Option<Rc<SessionLog>>,
+    prompts: Arc<Prompts>,
 }
-impl<M: Model> Prompter<M> {
-    fn from_model(model: Rc<M>) -> Self {
-        Self { model }
+impl<M: Model + ?Sized> Prompter<M> {
+    fn from_model(model: Rc<M>, progress: Rc<dyn ProgressSink>) -> Self {
+        Self {
+            model,
+            progress,
+            session: None,
+            prompts: Arc::default(),
+        }
+    }
+
+    fn with_session(self, session: Option<Rc<SessionLog>>) -> Self {
+        Self { session, ..self }
+    }
+
+    fn with_prompts(self, prompts: Arc<Prompts>) -> Self {
+        Self { prompts, ..self }
+    }
+
+    fn render(&self, template: &PromptTemplate, vars: &Vars) -> String {
+        template
+            .render(vars)
+            .expect("templates are checked against their variables when loaded")
+    }
+
+    fn record(&self, round: Round) {
+        if let Some(session) = &self.session {
+            session.round(round);
+        }
+    }
+
+    /// Sends `prompt` and collects the whole reply.
+    fn ask(&self, prompt: String) -> String {
+        self.progress.event(&Event::PromptSent {
+            tokens: progress::estimate_tokens(&prompt),
+        });
+        let mut coroutine = pin!(self.model.respond(prompt));
+        let mut reply = String::new();
+        while let CoroutineState::Yielded(yielded) = coroutine.as_mut().resume(()) {
+            report_retries(&*self.model, &*self.progress);
+            reply += &yielded.unwrap();
+        }
+        report_retries(&*self.model, &*self.progress);
+        reply
     }
 
+    /// Generates bindings and has the model score them, critique them and
+    /// pick a new temperature until they reach the threshold. Gives up after
+    /// `critique.max_rounds` rounds.
     fn generate_bindings(
         &self,
-        c_abi: &[(PathBuf, String)],
+        c_abi: &PackedSources,
         injection: &str,
         target_guidelines: &str,
         input_lang: &Language,
         output_lang: &Language,
-    ) -> String {
-        const BINDING_GUIDELINES: &str = include_str!("generate_bindings.prompt");
-        const YES: &str = "YES";
-        const NO: &str = "NO";
-
-        let mut tries = 0;
+        critique: &CritiqueSettings,
+    ) -> Result<String, BindFailure> {
+        let threshold = critique.threshold;
         let mut buffer = String::new();
-
         let mut buffer_critique = String::new();
-        'outer: loop {
-            tries += 1;
-            let mut buffer_main = String::new();
-            let mut buffer_main_cursor = 0;
-            let mut critique_requested = false;
-            // Run the first part of generation
-            let mut prompt = String::new();
-
-            let mut temp = self.model.temp();
-
-            let mut prompt = String::new();
-            prompt.push_str(BINDING_GUIDELINES);
-            prompt.push_str("\n\n# Binding target guidelines\n\n");
-            prompt.push_str(target_guidelines);
-            prompt.push_str("\n\n# Generation parameters\n");
-            prompt.push_str(&format!("Current temperature: {temp}\n"));
-            prompt.push_str(&format!("Input Language: {input_lang:?}\nOutput Language: {output_lang:?}\n\n"));
-            prompt.push_str(&format!("# C-abi input\n\n{c_abi:?}\n\n"));
-            
-            if !injection.is_empty() {
-                prompt.push_str(&format!(
-                    "# Compiler output:\n```\n{injection}\n```\n\n"
-                ));
+        let mut best: Option<(u32, String)> = None;
+        let mut scores = vec![];
+        let mut critiques = vec![];
+        let resumed = self.session.as_ref().map(|session| session.rounds()).unwrap_or_default();
+        for round in &resumed {
+            scores.push(round.score);
+            if best.as_ref().is_none_or(|(best, _)| round.score > *best) {
+                best = Some((round.score, round.bindings.clone()));
             }
-            else {
-                prompt.push_str("# No compiler output provided\n");
+            if let Some(critique) = &round.critique {
+                buffer_critique += critique;
+                critiques.push(critique.clone());
             }
-
-            if !buffer_critique.is_empty() {
-                prompt.push_str(&format!(
-                    "# AI feedback on previous output:\n```\n{buffer_critique}\n```\n\n"
-                ));
-            }
-
-            if !buffer.is_empty() {
-                prompt.push_str(&format!(
-                    "# IMPORTANT: This is the code the critique is about: \n\n{buffer}\n"
-                ));
+        }
+        if let Some(last) = resumed.last() {
+            self.progress.event(&Event::note(format!("Resuming after round {}", resumed.len())));
+            buffer = last.bindings.clone();
+            if last.score >= threshold {
+                return Ok(buffer);
             }
-
-            println!("{prompt}");
+            self.model.change(last.next_temperature);
+        }
+        if !c_abi.is_complete() {
+            self.progress.event(&Event::warning(format!(
+                "The sources do not fit in the prompt:\n{}",
+                c_abi.omissions().trim_end()
+            )));
+        }
+        let mut vars = Vars::new();
+        vars.set("guidelines", BINDING_GUIDELINES)
+            .set("target_guidelines", target_guidelines)
+            .set("input_language", format!("{input_lang:?}"))
+            .set("output_language", format!("{output_lang:?}"))
+            .set("c_abi", format!("{:?}", c_abi.sources()))
+            .set("omitted", c_abi.omissions())
+            .set("compiler_output", injection);
+        for round in resumed.len() + 1..=critique.max_rounds {
+            let temp = self.model.temp();
+
+            vars.set("temperature", temp)
+                .set("critique", &buffer_critique)
+                .set("bindings", &buffer);
+            let prompt = self.render(&self.prompts.generation, &vars);
+
+            self.progress.event(&Event::PromptSent {
+                tokens: progress::estimate_tokens(&prompt),
+            });
             let mut main_coroutine = pin!(self.model.respond(prompt));
-            let mut yes = true;
-            let mut ready = true;
-            println!("going into loop");
-            while yes {
-                println!("top");
-                while ready {
-                    //}&& buffer_main.len() - buffer_main_cursor < 4096 {
-                    match main_coroutine.as_mut().resume(()) {
-                        CoroutineState::Complete(complete) => {
-                            println!("yo123123");
-                            complete.unwrap();
-                            ready = false;
-                        }
-                        CoroutineState::Yielded(yielded) => {
-                            let yielded = yielded.unwrap();
-                            println!("cargo::warning={yielded}");
-                            buffer_main += &yielded;
-                        }
+            buffer = String::new();
+            loop {
+                let state = main_coroutine.as_mut().resume(());
+                report_retries(&*self.model, &*self.progress);
+                match state {
+                    CoroutineState::Complete(complete) => {
+                        complete.unwrap();
+                        break;
                     }
-                }
-                println!("out");
-
-                buffer = buffer_main.clone();
-
-                let prompt = format!(
-                    "You are a specialized code binding evaluator. Your task is to assess if generated code bindings match the provided style guide with extreme precision.
-When evaluating the code:
-
-IMPORTANT: Output a number, and only a number, one number with no other symbols, including code (THERE SHOULD BE NO CODE OR WORDS OR ANYTHING).
-Just output a number between 0 and 100 that represents how closely the code follows the binding guidelines (a percentage, but without the %)
-
-Everything below this line is the bind guidelines you were asked to use:
-````
-{BINDING_GUIDELINES}
-{target_guidelines}
-````
-
-Here is compiler output:
-{injection}
-
-Everything below this line is the code you were asked to evaluate:
-
-{buffer_main}"
-                );
-
-                let mut eval_coroutine = pin!(self.model.respond(prompt));
-
-                let mut buffer_eval = String::new();
-
-                while let CoroutineState::Yielded(yielded) = eval_coroutine.as_mut().resume(()) {
-                    buffer_eval += &yielded.unwrap();
-                }
-
-                dbg!(&buffer_eval, &buffer_critique);
-
-                println!("cargo::warning=\n\n\n\n EVAL \n\n\n\n");
-                let critical = 85;
-                println!(
-                    "cargo::warning=\n\nVALUE: {}\nCRITICAL THRESHOLD: {critical}\n",
-                    buffer_eval
-                );
-                let val = buffer_eval.trim().parse::<usize>().unwrap();
-
-                yes = val >= critical;
-
-                if !yes {
-                    println!("\n\n\n\n CRITIQUE \n\n\n\n");
-
-                    let prompt = format!(
-                    "You are a specialized code binding evaluator. Your task is to assess if generated code bindings match the provided style guide with extreme precision.
-When evaluating the code:
-
-Categorize each guideline as either \"critical\" or \"non-critical\" based on importance
-Consider a binding successful only if 100% of critical guidelines and at least 95% of non-critical guidelines are met
-Focus on style conformance, not functionality
-Be aware the code may be incomplete as it's being generated in real-time
-IMPORTANT: Do not output code! You are being asked to create a categorized list of critiques. Only output your list of critiques, and nothing else. Do not output anything else.
-
-Everything below this line is the bind guidelines you were asked to use:
-{BINDING_GUIDELINES}
-{target_guidelines}
-
-Here is compiler output:
-{injection}
-
-Everything below this line is the code you were asked to evaluate:
-
-{buffer_main}"
-                );
-
-                    let mut critique_coroutine = pin!(self.model.respond(prompt));
-
-                    while let CoroutineState::Yielded(yielded) =
-                        critique_coroutine.as_mut().resume(())
-                    {
+                    CoroutineState::Yielded(yielded) => {
                         let yielded = yielded.unwrap();
-                        println!("cargo::warning={yielded}");
-                        buffer_critique += &yielded;
+                        self.progress.event(&Event::Delta {
+                            text: yielded.clone(),
+                        });
+                        buffer += &yielded;
                     }
+                }
+            }
 
-                    let prompt = format!(
-                    "You are a specialized bind generator. You failed to provide code that met the critical threshold of {critical}, instead, your code scored {val}. You have currently been set to temperature {temp} and are being asked to provide a new temperature to try. Only output a temperature between 0.0 - 1.0 where 0.0 is very strict and 1.0 is very creative. Do not output anything else.
-
-Here are the binding guidelines you were asked to use:
-{BINDING_GUIDELINES}
-{target_guidelines}
-
-Here is compiler output:
-{injection}
-
-Here is the critique about your code:
-{buffer_critique}
-
-Here is the current code:
-
-{buffer_main}"
-                );
-
-                    let mut temp_coroutine = pin!(self.model.respond(prompt));
+            vars.set("bindings", &buffer);
+            let prompt = self.render(&self.prompts.evaluation, &vars);
+
+            let score = self.ask_number(prompt, "a number between 0 and 100", critique::parse_score);
+            let score = score.unwrap_or_else(|| {
+                self.progress.event(&Event::warning("The evaluation held no score, taking it as 0"));
+                0
+            });
+            self.progress.event(&Event::EvaluationScore { score, threshold });
+            scores.push(score);
+            if best.as_ref().is_none_or(|(best, _)| score > *best) {
+                best = Some((score, buffer.clone()));
+            }
 
-                    let mut buffer_temp = String::new();
+            let finished = Round {
+                attempt: 0,
+                temperature: temp,
+                bindings: buffer.clone(),
+                score,
+                critique: None,
+                next_temperature: temp,
+            };
+            if score >= threshold {
+                self.record(finished);
+                return Ok(buffer);
+            }
+            if round == critique.max_rounds {
+                self.record(finished);
+                break;
+            }
 
-                    while let CoroutineState::Yielded(yielded) = temp_coroutine.as_mut().resume(())
-                    {
-                        buffer_temp += &yielded.unwrap();
-                    }
+            let prompt = self.render(&self.prompts.critique, &vars);
 
-                    temp = buffer_temp.trim().parse::<f32>().unwrap();
-                    self.model.change(temp);
-                    println!("cargo::warning=Changed temperature to {}", temp);
+            let round_critique = self.ask(prompt);
+            self.progress.event(&Event::Critique {
+                text: round_critique.clone(),
+            });
+            buffer_critique += &round_critique;
+            critiques.push(round_critique.clone());
 
-                    continue;
-                }
+            vars.set("critique", &buffer_critique)
+                .set("threshold", threshold)
+                .set("score", score);
+            let prompt = self.render(&self.prompts.temperature, &vars);
 
-                if !ready {
-                    break 'outer buffer;
+            match self.ask_number(prompt, "a temperature between 0.0 and 1.0", critique::parse_temperature) {
+                Some(to) => {
+                    self.model.change(to);
+                    self.progress.event(&Event::TemperatureChanged { from: temp, to });
                 }
+                None => self.progress.event(&Event::warning(format!(
+                    "The model suggested no temperature, staying at {temp}"
+                ))),
+            }
+            self.record(Round {
+                critique: Some(round_critique),
+                next_temperature: self.model.temp(),
+                ..finished
+            });
+            if let Some(usage) = self.model.usage() {
+                self.progress.event(&Event::note(format!("Usage so far: {usage}")));
             }
         }
+
+        let (best_score, best) = best.unwrap_or_default();
+        Err(BindFailure {
+            best,
+            best_score,
+            threshold,
+            scores,
+            critiques,
+        })
+    }
+
+    /// Asks `prompt` and parses the reply, asking once more for only `what`
+    /// if the first reply holds nothing `parse` accepts.
+    fn ask_number<T>(&self, prompt: String, what: &str, parse: fn(&str) -> Option<T>) -> Option<T> {
+        let reply = self.ask(prompt.clone());
+        parse(&reply).or_else(|| {
+            let retry = format!(
+                "{prompt}\n\nYour previous reply was:\n{reply}\n\nIt held no number. Output {what} and nothing else."
+            );
+            parse(&self.ask(retry))
+        })
     }
 }
 
 ///Interprets AI responses
-pub struct Interpreter<M: Model> {
+pub struct Interpreter<M: Model + ?Sized> {
     model: Rc<M>,
+    progress: Rc<dyn ProgressSink>,
 }
-impl<M: Model> Interpreter<M> {
-    fn from_model(model: Rc<M>) -> Self {
-        Self { model }
+impl<M: Model + ?Sized> Interpreter<M> {
+    fn from_model(model: Rc<M>, progress: Rc<dyn ProgressSink>) -> Self {
+        Self { model, progress }
     }
 
     fn error_interpret(&self, err: String) -> Option<Vec<Error>> {
-        let mut ret = String::new();
-        let mut coroutine = pin!(
-            self.model
-                .respond(format!("{}\n{err}", include_str!("error_interpret.prompt")),)
-        );
-
-        let mut ret = String::new();
-
-        while let CoroutineState::Yielded(text) = coroutine.as_mut().resume(()) {
-            ret += &text.unwrap();
+        let prompt = format!("{}\n{err}", include_str!("error_interpret.prompt"));
+        self.progress.event(&Event::PromptSent {
+            tokens: progress::estimate_tokens(&prompt),
+        });
+        let reply = self.model.respond_json(prompt);
+        report_retries(&*self.model, &*self.progress);
+        match reply.and_then(|value| {
+            serde_json::from_value(value).map_err(|e| GeminiError::JsonParseError(e.to_string()))
+        }) {
+            Ok(errors) => Some(errors),
+            Err(e) => {
+                self.progress.event(&Event::warning(format!("Could not interpret errors: {e}")));
+                None
+            }
         }
-
-        dbg!(serde_json::from_str(dbg!(
-            ret.trim_matches('`').trim_start_matches("json").trim()
-        )))
-        .ok()
     }
 }
 
@@ -452,7 +645,39 @@ pub trait Stage {
     fn priority(&self) -> u64 {
         500
     }
-    fn installation<'a>(&self, container: &'a Container) -> Script<'a>;
+    fn installation<'a>(&self, executor: &'a dyn Executor) -> Script<'a>;
+    /// A command that succeeds when the stage's toolchain is installed.
+    fn probe(&self) -> Vec<&str>;
+}
+
+/// A system package a tool turned out to need, installed with `apt-get`.
+pub struct PackageInstall {
+    package: String,
+}
+
+impl PackageInstall {
+    /// `None` unless `package` is a valid Debian package name, since it ends
+    /// up in a shell script.
+    pub fn new(package: impl Into<String>) -> Option<Self> {
+        let package = package.into();
+        let valid = package.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && package
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c));
+        valid.then_some(Self { package })
+    }
+}
+
+impl Stage for PackageInstall {
+    fn installation<'a>(&self, executor: &'a dyn Executor) -> Script<'a> {
+        executor.inject(format!(
+            "#!/bin/bash\nset -e\nexport DEBIAN_FRONTEND=noninteractive\napt-get update\napt-get install -y {}\n",
+            self.package
+        ))
+    }
+    fn probe(&self) -> Vec<&str> {
+        vec!["dpkg", "-s", &self.package]
+    }
 }
 
 pub trait Provider {
@@ -460,27 +685,18 @@ pub trait Provider {
     fn derive() -> Self where Self: Sized;
     fn setup(&self) -> Vec<Arc<dyn Stage>>;
     fn file_ext(&self) -> &'static str;
-    fn find_files(&self, container: &Container, path: &Path) -> Result<Vec<PathBuf>, String> {
-        let path_str = path.display().to_string();
-        let glob = format!("*.{}", self.file_ext());
-        let find_args = vec!["find", &path_str, "-name", &glob, "-type", "f"];
-        println!("Executing find command: {:?}", find_args);
-        let result = dbg!(container.exec(&find_args).unwrap());
-
-        if !result.success {
-            return Err(result.stderr);
-        }
-
-        // Parse the output to get all swift file paths
-        let swift_file_paths: Vec<String> = result
-            .stdout
-            .lines()
-            .map(|line| line.trim().to_string())
-            .filter(|line| !line.is_empty())
-            .collect();
-
-        // Add all found swift files to args
-        Ok(swift_file_paths.iter().map(|s| s.into()).collect())
+    fn find_files(&self, executor: &dyn Executor, path: &Path) -> Result<Vec<PathBuf>, String> {
+        executor.find_files(path, self.file_ext())
+    }
+    /// What the model is shown of the sources found under `path`. By default
+    /// that is the files themselves.
+    fn extract(
+        &self,
+        _executor: &dyn Executor,
+        _path: &Path,
+        files: Vec<(PathBuf, String)>,
+    ) -> Result<Vec<(PathBuf, String)>, String> {
+        Ok(files)
     }
 }
 
@@ -490,15 +706,39 @@ pub trait Compiler: Provider {
 }
 
 pub trait Applicator: Compiler {
-    fn apply(&self, output: &Output, bindings: String);
+    /// Writes the files in `bindings`, generated from `sources`, and returns
+    /// their paths.
+    fn apply(
+        &self,
+        output: &Output,
+        bindings: String,
+        sources: &[(PathBuf, String)],
+        cfg: &Config,
+        ctx: &PostContext,
+    ) -> Result<Vec<PathBuf>, PostError>;
+}
+
+/// Zig as a source, or as a target built with `binary`.
+pub struct Zig {
+    pub binary: PathBuf,
+}
+
+impl Default for Zig {
+    fn default() -> Self {
+        Self {
+            binary: "zig".into(),
+        }
+    }
 }
 
-pub struct Zig;
 pub struct ZigInstall;
 
 impl Stage for ZigInstall {
-    fn installation<'a>(&self, container: &'a Container) -> Script<'a> {
-        container.inject(include_str!("install_zig.sh").to_owned())
+    fn installation<'a>(&self, executor: &'a dyn Executor) -> Script<'a> {
+        executor.inject(include_str!("install_zig.sh"))
+    }
+    fn probe(&self) -> Vec<&str> {
+        vec!["zig", "version"]
     }
 }
 
@@ -515,17 +755,77 @@ impl Provider for Zig {
     }
     
     fn derive() -> Self where Self: Sized {
-    Zig
+    Zig::default()
     }
 }
 
+impl Compiler for Zig {
+    fn compile(&self, pkg: &str, path: &Path) -> Result<String, String> {
+        let dir = path.join(pkg);
+        let args: &[&str] = if dir.join("build.zig").exists() {
+            &["build"]
+        } else {
+            &["build-lib", "src/root.zig"]
+        };
+        match Command::new(&self.binary).args(args).current_dir(&dir).output() {
+            Ok(out) if out.status.success() => Ok(String::from_utf8_lossy(&out.stdout).to_string()),
+            Ok(out) => Err(String::from_utf8_lossy(&out.stderr).to_string()),
+            Err(err) => Err(format!("could not run {}: {err}", self.binary.display())),
+        }
+    }
+
+    fn guidelines(&self) -> &'static str {
+        include_str!("generate_bindings_zig.prompt")
+    }
+}
+
+impl Applicator for Zig {
+    fn apply(
+        &self,
+        output: &Output,
+        bindings: String,
+        _sources: &[(PathBuf, String)],
+        cfg: &Config,
+        ctx: &PostContext,
+    ) -> Result<Vec<PathBuf>, PostError> {
+        let package = output.lib_path.join(&output.crate_name);
+        let mut files = codeblocks::files(&bindings, &["zig"], &package, ctx.progress);
+        post::run(&cfg.post_processors, &mut files, ctx)?;
+
+        let mut plan = Plan::new(package, files);
+        // The guidelines only ask for a build script when the defaults do not do
+        plan.default_file("build.zig", ZIG_BUILD.replace("{name}", &output.crate_name), cfg);
+        plan.write(output, cfg, ctx.progress)
+    }
+}
+
+/// Builds `src/root.zig` into a static library linked against libc.
+const ZIG_BUILD: &str = r#"const std = @import("std");
+
+pub fn build(b: *std.Build) void {
+    const target = b.standardTargetOptions(.{});
+    const optimize = b.standardOptimizeOption(.{});
+    const lib = b.addStaticLibrary(.{
+        .name = "{name}",
+        .root_source_file = b.path("src/root.zig"),
+        .target = target,
+        .optimize = optimize,
+    });
+    lib.linkLibC();
+    b.installArtifact(lib);
+}
+"#;
+
 pub struct Rust;
 
 pub struct RustInstall;
 
 impl Stage for RustInstall {
-    fn installation<'a>(&self, container: &'a Container) -> Script<'a> {
-        container.inject(include_str!("install_rust.sh").to_owned())
+    fn installation<'a>(&self, executor: &'a dyn Executor) -> Script<'a> {
+        executor.inject(include_str!("install_rust.sh"))
+    }
+    fn probe(&self) -> Vec<&str> {
+        vec!["cargo", "--version"]
     }
 }
 impl Provider for Rust {
@@ -543,14 +843,40 @@ impl Provider for Rust {
     fn derive() -> Self where Self: Sized {
     Rust
     }
+
+    /// Only the exported C ABI is worth binding, so the crate has to build
+    /// and the prompt gets its `extern "C"` surface instead of every file,
+    /// with the size of every type when it can be measured.
+    fn extract(
+        &self,
+        executor: &dyn Executor,
+        path: &Path,
+        files: Vec<(PathBuf, String)>,
+    ) -> Result<Vec<(PathBuf, String)>, String> {
+        let build = format!("cargo build --manifest-path '{}'", path.join("Cargo.toml").display());
+        let result = executor.exec(&["bash", "-lc", &build])?;
+        if !result.success {
+            return Err(result.stderr);
+        }
+        let mut summaries = abi::extract(&files)?;
+        // Without sizes the smoke test only checks signatures
+        let sizes = abi::probe_sizes(executor, &summaries).unwrap_or_default();
+        abi::annotate(&mut summaries, &sizes);
+        Ok(summaries)
+    }
 }
 impl Compiler for Rust {
     fn compile(&self, pkg: &str, path: &Path) -> Result<String, String> {
-        match Command::new("cargo").args(&["build", "--release", "--package", &pkg]).current_dir(path).output() {
+        // Applying bindings creates `{pkg}-sys`, which need not be a workspace member
+        let manifest = path.join(format!("{pkg}-sys")).join("Cargo.toml");
+        match Command::new("cargo").arg("test").arg("--release").arg("--manifest-path").arg(&manifest).output() {
             Ok(out) => if out.status.success() {
 Ok(String::from_utf8_lossy(&out.stdout).to_string())
             }  else {
-Err(String::from_utf8_lossy(&out.stderr).to_string())
+                // Failed assertions are reported on stdout, build errors on stderr
+                let mut errors = String::from_utf8_lossy(&out.stdout).to_string();
+                errors.push_str(&String::from_utf8_lossy(&out.stderr));
+                Err(errors)
             },
             Err(err) => panic!("{err:?}"),
         }
@@ -562,86 +888,65 @@ Err(String::from_utf8_lossy(&out.stderr).to_string())
 }
 
 impl Applicator for Rust {
-    fn apply(&self, output: &Output, bindings: String) {
+    fn apply(
+        &self,
+        output: &Output,
+        bindings: String,
+        sources: &[(PathBuf, String)],
+        cfg: &Config,
+        ctx: &PostContext,
+    ) -> Result<Vec<PathBuf>, PostError> {
     let sys_name = format!("{}-sys", output.crate_name);
-    let _ = Command::new("rm")
-        .args(["-rf", &sys_name])
-        .current_dir(&output.lib_path)
-        .output();
-    let _ = Command::new("cargo")
-        .args(["new", "--lib", &sys_name])
-        .current_dir(&output.lib_path)
-        .output();
-    println!("cargo::warning={:?}", &bindings);
-    // Split the bindings by ```rust markers
-    let code_blocks: Vec<&str> = bindings.split("```rust").collect();
-    println!("cargo::warning={:?}", &code_blocks);
-    // Create a map to store path -> code mappings
-    let mut path_code_map: HashMap<PathBuf, String> = HashMap::new();
-    
-    // Process each code block - skip the first element as it's likely empty or contains non-code text
-    for block in code_blocks.iter().skip(1) {
-        // Find the end of the code block
-        if let Some(end_index) = block.find("```") {
-            let full_block = &block[..end_index].trim();
-            let mut lines = full_block.lines();
-            
-            // The first line might be a path or a comment containing a path
-            if let Some(first_line) = lines.next() {
-                let path_str = if first_line.trim().starts_with("//") {
-                    // Extract path from comment
-                    first_line.trim().trim_start_matches("//").trim()
-                } else {
-                    // Might be a direct path
-                    first_line.trim()
-                };
-                
-                // Check if this looks like a valid path
-                if path_str.contains("/") || path_str.contains(".") {
-                    // This is likely a path
-                    let rel_path = PathBuf::from(path_str);
-                    
-                    // The rest of the lines are the code
-                    let code = lines.collect::<Vec<&str>>().join("\n");
-                    
-                    // Store in our map
-                    path_code_map.insert(rel_path.clone(), code.clone());
-                    
-                } else {
-                    // No path found, but we still have code
-                    println!("cargo::warning=Code block without path information: {}", first_line);
-                }
-            }
-        }
-    }
-    
-    // Now write each code block to its respective file
-    for (rel_path, code) in path_code_map.iter() {
-        // Construct the full path
-        let full_path = output.lib_path.join(&sys_name).join(rel_path);
-        
-        // Create parent directories if needed
-        if let Some(parent) = full_path.parent() {
-            fs::create_dir_all(parent).expect("Failed to create directory structure");
-        }
-        
-        // Write the code to the file
-        fs::write(&full_path, code).expect("Failed to write to file");
-        println!("cargo::warning=Written code to {}", rel_path.display());
-    
+    let crate_root = output.lib_path.join(&sys_name);
+    let mut files = codeblocks::files(&bindings, &["rust", "rs"], &crate_root, ctx.progress);
+    post::run(&cfg.post_processors, &mut files, ctx)?;
+
+    // Outputs of sources that did not change are kept unless starting over
+    let mut plan = Plan::new(crate_root, files);
+    plan.default_file("Cargo.toml", RUST_MANIFEST.replace("{name}", &sys_name), cfg);
+    // Compiling proves little about link names and layouts, the smoke test
+    // checks them against the ABI the bindings were generated from
+    if let Some(harness) = abi::smoke_test(&sys_name, sources) {
+        plan.file("tests/abi_smoke.rs", harness);
     }
+    plan.write(output, cfg, ctx.progress)
 }
 
 }
-pub struct Swift;
+/// What `cargo new --lib` would write, for bindings that bring no manifest.
+const RUST_MANIFEST: &str = r#"[package]
+name = "{name}"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+"#;
+
+/// Swift as a source, or as a target built as a package with `binary`.
+pub struct Swift {
+    pub binary: PathBuf,
+}
+
+
+impl Default for Swift {
+    fn default() -> Self {
+        Self {
+            binary: "swift".into(),
+        }
+    }
+}
+
 pub struct SwiftInstall;
 
 impl Stage for SwiftInstall {
     fn priority(&self) -> u64 {
         10
     }
-    fn installation<'a>(&self, container: &'a Container) -> Script<'a> {
-        container.inject(include_str!("install_swift.sh").to_owned())
+    fn installation<'a>(&self, executor: &'a dyn Executor) -> Script<'a> {
+        executor.inject(include_str!("install_swift.sh"))
+    }
+    fn probe(&self) -> Vec<&str> {
+        vec!["swift", "--version"]
     }
 }
 
@@ -658,38 +963,21 @@ impl Provider for Swift {
     }
     
     fn derive() -> Self where Self: Sized {
-    Swift
+    Swift::default()
     }
 }
 
 impl Compiler for Swift {
     fn compile(&self, pkg: &str, path: &Path) -> Result<String, String> {
-        let mut args = vec!["swiftc".to_owned()];
-        /*let files = self
-            .find_files(container, path)
-            .unwrap()
-            .into_iter()
-            .map(|s| s.to_str().map(ToOwned::to_owned).unwrap())
-            .collect::<Vec<_>>();
-        args.extend(files);
-        args.push("-parse-as-library".to_owned());
-        args.push("-o Hello".to_owned());
-        let ret = dbg!(container.exec(&args).unwrap());*/
-        let files = todo!();
-        let ret = todo!();
-        match ret {
-            CommandResult {
-                success: true,
-                stdout,
-                stderr,
-                exit_code,
-            } => Ok(stdout),
-            CommandResult {
-                success: false,
-                stdout,
-                stderr,
-                exit_code,
-            } => Err(stderr),
+        match Command::new(&self.binary).arg("build").current_dir(path.join(pkg)).output() {
+            Ok(out) if out.status.success() => Ok(String::from_utf8_lossy(&out.stdout).to_string()),
+            Ok(out) => {
+                // The package manager reports diagnostics on stdout
+                let mut errors = String::from_utf8_lossy(&out.stdout).to_string();
+                errors.push_str(&String::from_utf8_lossy(&out.stderr));
+                Err(errors)
+            }
+            Err(err) => Err(format!("could not run {}: {err}", self.binary.display())),
         }
     }
 
@@ -698,26 +986,86 @@ impl Compiler for Swift {
     }
 }
 
+impl Applicator for Swift {
+    fn apply(
+        &self,
+        output: &Output,
+        bindings: String,
+        sources: &[(PathBuf, String)],
+        cfg: &Config,
+        ctx: &PostContext,
+    ) -> Result<Vec<PathBuf>, PostError> {
+        let name = &output.crate_name;
+        let package = output.lib_path.join(name);
+        let target = Path::new("Sources").join(name);
+        let mut files = codeblocks::files(&bindings, &["swift"], &package, ctx.progress);
+        post::run(&cfg.post_processors, &mut files, ctx)?;
+        for file in &mut files {
+            // Paths may or may not spell out the target directory
+            if !file.path.starts_with(&target) {
+                file.path = target.join(&file.path);
+            }
+        }
+
+        let mut plan = Plan::new(package, files);
+        plan.default_file("Package.swift", SWIFT_PACKAGE.replace("{name}", name), cfg);
+        // The C library the bindings call into, as a system library target
+        let system = Path::new("Sources").join(format!("C{name}"));
+        let headers: String = sources
+            .iter()
+            .filter(|(path, _)| path.extension().is_some_and(|ext| ext == "h"))
+            .map(|(path, _)| format!("#include \"{}\"\n", path.display()))
+            .collect();
+        plan.file(system.join("shim.h"), headers);
+        plan.default_file(system.join("module.modulemap"), SWIFT_MODULEMAP.replace("{name}", name), cfg);
+        plan.write(output, cfg, ctx.progress)
+    }
+}
+
+/// Builds the bindings in `Sources/{name}` on top of the C library, which is
+/// linked by name through `Sources/C{name}`.
+const SWIFT_PACKAGE: &str = r#"// swift-tools-version:5.9
+import PackageDescription
+
+let package = Package(
+    name: "{name}",
+    products: [
+        .library(name: "{name}", targets: ["{name}"]),
+    ],
+    targets: [
+        .systemLibrary(name: "C{name}", path: "Sources/C{name}"),
+        .target(name: "{name}", dependencies: ["C{name}"]),
+    ]
+)
+"#;
+
+const SWIFT_MODULEMAP: &str = r#"module C{name} [system] {
+    umbrella header "shim.h"
+    link "{name}"
+    export *
+}
+"#;
+
 pub struct Context {
     temp: PathBuf,
 }
 
 pub struct Build {
-    container: Container,
+    executor: Box<dyn Executor>,
     source: Arc<dyn Provider>,
     target: Arc<dyn Compiler>,
 }
 
 pub struct Script<'a> {
-    container: &'a Container,
+    executor: &'a dyn Executor,
     script_path: PathBuf,
 }
 
 impl Script<'_> {
     fn run(&self) -> CommandResult {
         let path = self.script_path.to_str().unwrap();
-        self.container.exec(&["chmod", "+x", path]).unwrap();
-        self.container
+        self.executor.exec(&["chmod", "+x", path]).unwrap();
+        self.executor
             .exec(&["/bin/bash", "-c", &*format!("{}", path)])
             .unwrap()
     }
@@ -725,43 +1073,40 @@ impl Script<'_> {
 
 impl Build {
     fn source_files(&self, path: impl AsRef<Path>) -> Result<Vec<PathBuf>, String> {
-        dbg!(self.source.find_files(&self.container, path.as_ref()))
-    }
-
-    fn create(src_lang: Language, dst_lang: Language) -> Build {
-        let existed;
-        let container = {
-            let name = format!("Build_BindAI_{:?}_{:?}", src_lang, dst_lang);
-            let mut container = if Docker::container_exists(&name) {
-                existed = true;
-                Docker::container(&name)
-            } else {
-                existed = false;
-                let container_config = container_config()
-                    .working_dir(env::current_dir().unwrap().to_str().unwrap())
-                    .cmd(vec!["sleep", "300"]);
-                let mut image = Image::new("ubuntu", "latest");
-                image.pull().unwrap();
-                image
-                    .create_container(&name, &container_config.build())
-                    .unwrap()
-            };
-            container.refresh().unwrap();
-            if !container.running() {
-                dbg!(container.start().unwrap());
+        self.source.find_files(&*self.executor, path.as_ref())
+    }
+
+    fn create(
+        src_lang: Language,
+        dst_lang: Language,
+        settings: &ContainerSettings,
+        progress: &dyn ProgressSink,
+    ) -> Build {
+        let local = match settings.execution {
+            Execution::Local => true,
+            Execution::Docker => false,
+            Execution::Auto if Docker::is_available() => false,
+            Execution::Auto => {
+                progress.event(&Event::warning("Docker is not available, building on the host"));
+                true
             }
-            container
+        };
+        let executor: Box<dyn Executor> = if local {
+            Box::new(LocalExecutor::temp().expect("Failed to create local workspace"))
+        } else {
+            Box::new(Self::container(src_lang, dst_lang, settings, progress))
         };
 
-        let source = Arc::new(match src_lang {
-            Language::Zig => Zig,
-            _ => todo!(),
-        }) as Arc<dyn Provider>;
+        let source = match src_lang {
+            Language::Zig => Arc::new(Zig::default()) as Arc<dyn Provider>,
+            Language::Rust => Arc::new(Rust) as Arc<dyn Provider>,
+            Language::Swift => Arc::new(Swift::default()) as Arc<dyn Provider>,
+        };
 
         let target = match dst_lang {
-            Language::Swift => Arc::new(Swift) as Arc<dyn Compiler>,
+            Language::Swift => Arc::new(Swift::default()) as Arc<dyn Compiler>,
             Language::Rust => Arc::new(Rust) as Arc<dyn Compiler>,
-            _ => todo!(),
+            Language::Zig => Arc::new(Zig::default()) as Arc<dyn Compiler>,
         };
 
         let mut stages = vec![];
@@ -771,77 +1116,235 @@ impl Build {
 
         stages.sort_by_key(|x| x.priority());
 
-        if !existed {
-            for stage in stages {
-                dbg!("Installing stage...");
-                let CommandResult {
-                    success,
-                    mut stdout,
-                    stderr,
-                    exit_code,
-                } = stage.installation(&container).run();
-                {
-                    let out = if success {
-                        stdout
-                    } else {
-                        stdout.push_str(&stderr);
-                        stdout
-                    };
-                    println!("Exited with code {exit_code}: {out}");
-                }
-            }
-        }
+        install(&*executor, stages, progress);
 
         Self {
-            container,
+            executor,
             source,
             target,
         }
     }
 
+    /// The build container for these languages, started if need be. Containers
+    /// older than `settings.keep_alive` are cleaned up first.
+    fn container(
+        src_lang: Language,
+        dst_lang: Language,
+        settings: &ContainerSettings,
+        progress: &dyn ProgressSink,
+    ) -> Container {
+        match container::cleanup(settings.keep_alive()) {
+            Ok(removed) if !removed.is_empty() => progress.event(&Event::note(format!(
+                "Removed stale build containers: {}",
+                removed.join(", ")
+            ))),
+            Ok(_) => {}
+            Err(e) => progress.event(&Event::warning(format!(
+                "Could not clean up build containers: {e}"
+            ))),
+        }
+        let name = container::name(src_lang, dst_lang);
+        let mut container = if Docker::container_exists(&name) {
+            Docker::container(&name)
+        } else {
+            let (image_name, tag) = settings.image();
+            let mut image = Image::new(image_name, tag);
+            image.pull().unwrap();
+            image
+                .create_container(&name, &container::config(src_lang, dst_lang).build())
+                .unwrap()
+        };
+        container.refresh().unwrap();
+        if !container.running() {
+            container.start().unwrap();
+        }
+        container
+    }
+
+    /// Stops the build container, removing it as well if `remove` is set, so
+    /// the next run starts from a fresh one. Host builds have nothing to stop.
+    pub fn shutdown(&self, remove: bool) -> Result<(), String> {
+        self.executor.shutdown(remove)
+    }
+
+    /// Deals with what `interpreter` makes of `output`, printed by a tool that
+    /// failed on the sources. Missing files are copied in and missing packages
+    /// installed, after which the tool can simply run again. Mistakes the model
+    /// made are returned to prompt the next generation with. Errors in the
+    /// sources themselves end the run, as does a package missing on the host.
+    fn recover<M: Model + ?Sized>(
+        &self,
+        interpreter: &Interpreter<M>,
+        output: String,
+        progress: &dyn ProgressSink,
+    ) -> Result<Option<String>, BindError> {
+        let Some(errors) = interpreter.error_interpret(output.clone()) else {
+            progress.event(&Event::warning("Found no error to act on"));
+            return Ok(None);
+        };
+        let abort = |explanation: String| BindError::Tool {
+            output: output.clone(),
+            explanation,
+        };
+        let (mut user, mut agent) = (vec![], vec![]);
+        for error in errors {
+            match error {
+                Error::Missing { path } => self.include(path),
+                Error::Invalid {
+                    src: Invalid::User,
+                    msg,
+                } => user.push(msg),
+                Error::Invalid {
+                    src: Invalid::Agent,
+                    msg,
+                } => agent.push(msg),
+                Error::MissingDependency { package } => {
+                    let Some(stage) = PackageInstall::new(&package) else {
+                        progress.event(&Event::warning(format!(
+                            "Not installing `{package}`, which is no package name"
+                        )));
+                        continue;
+                    };
+                    if self.executor.is_local() {
+                        return Err(abort(format!(
+                            "The package {package} is missing, install it and try again"
+                        )));
+                    }
+                    install(&*self.executor, vec![Arc::new(stage)], progress);
+                }
+            }
+        }
+        if !user.is_empty() {
+            return Err(abort(format!(
+                "The sources have errors bind cannot fix:\n{}",
+                user.join("\n")
+            )));
+        }
+        Ok((!agent.is_empty()).then(|| agent.join("\n")))
+    }
+
     fn include(&self, host_path: impl AsRef<Path>) {
-        let path_str = host_path.as_ref().to_str().unwrap();
         let parent_path_str = host_path.as_ref().parent().unwrap().to_str().unwrap();
-        self.container
+        self.executor
             .exec(&["mkdir", "-p", parent_path_str])
             .expect("failed to create host directory in container");
 
-        self.container
-            .copy_to(path_str, path_str)
+        self.executor
+            .copy_to(host_path.as_ref(), host_path.as_ref())
             .expect("failed to mount and copy host data");
     }
 
 }
 
-pub fn bind<Source: Provider, Target: Compiler>(cfg: &Config) -> String {
+/// Installs the stages whose probe fails, in order. A reused container may
+/// have lost a toolchain, or never finished installing it, so the probe
+/// decides rather than whether the container is new. The host's toolchains
+/// are used as they are and only checked.
+fn install(executor: &dyn Executor, stages: Vec<Arc<dyn Stage>>, progress: &dyn ProgressSink) {
+    for stage in stages {
+        let probe = stage.probe();
+        if executor.exec(&probe).is_ok_and(|result| result.success) {
+            continue;
+        }
+        if executor.is_local() {
+            progress.event(&Event::warning(format!(
+                "`{}` failed, is the toolchain installed?",
+                probe.join(" ")
+            )));
+            continue;
+        }
+        let CommandResult {
+            success,
+            mut stdout,
+            stderr,
+            exit_code,
+        } = stage.installation(executor).run();
+        match success {
+            true => progress.event(&Event::note(format!("Installed stage: {}", stdout.trim_end()))),
+            false => {
+                stdout.push_str(&stderr);
+                progress.event(&Event::warning(format!(
+                    "Stage installation exited with code {exit_code}: {stdout}"
+                )));
+            }
+        }
+    }
+}
+
+pub fn bind<Source: Provider, Target: Compiler>(
+    cfg: &Config,
+    model: Rc<dyn Model>,
+    progress: Rc<dyn ProgressSink>,
+) -> Result<String, BindError> {
+    bind_sources::<Source, Target>(cfg, model, progress, None).map(|(bindings, ..)| bindings)
+}
+
+/// How often a tool may fail on the sources before the run is given up.
+const MAX_RECOVERIES: usize = 3;
+
+/// Bindings for the sources changed since the last successful run, or all
+/// of them on a full rebuild, along with those sources and, if the bindings
+/// are new, the key to cache them under once they compile.
+fn bind_sources<Source: Provider, Target: Compiler>(
+    cfg: &Config,
+    model: Rc<dyn Model>,
+    progress: Rc<dyn ProgressSink>,
+    session: Option<&Rc<SessionLog>>,
+) -> Result<(String, Vec<(PathBuf, String)>, Option<u64>), BindError> {
     let Config {
         source: src_dir,
         target: bind_dir,
     ..
     } = cfg;
-
-    let build = Build::create(Source::language(), Target::language());
-    let model = Rc::new(Gemini::new("".to_owned(), 0.5));
-    let interpreter = Interpreter::from_model(model.clone());
-    let prompter = Prompter::from_model(model.clone());
-    let error_act = |err| match interpreter.error_interpret(err) {
-        Some(errs) => {
-            for err in errs {
-                match err {
-                    Error::Missing { path } => {
-                        build.include(path.to_str().unwrap());
-                    }
-                    _ => todo!(),
-                }
+    // A local workspace is not where bind runs, so paths have to be absolute
+    let src_dir = &std::path::absolute(src_dir).unwrap_or_else(|_| src_dir.clone());
+
+    let build = Build::create(
+        Source::language(),
+        Target::language(),
+        &cfg.settings.container,
+        &*progress,
+    );
+    let model = match &cfg.rate_limit {
+        Some(bucket) => RateLimited::wrap(model, bucket.clone()),
+        None => model,
+    };
+    let prompts = Arc::new(Prompts::load(cfg.settings.templates.as_deref(), Target::language())?);
+    let interpreter = Interpreter::from_model(model.clone(), progress.clone());
+    let prompter = Prompter::from_model(model.clone(), progress.clone())
+        .with_session(session.cloned())
+        .with_prompts(prompts.clone());
+    let cache = ResponseCache::new(bind_dir);
+    // Sources are packed to fit the model unless a budget is configured
+    let context_tokens = cfg.settings.model.context_tokens;
+    let input_limit = context_tokens.is_none().then(|| model.input_limit()).flatten();
+    // Tool failures are retried a few times, with what the model got wrong
+    // added to the external prompt
+    let mut external_prompt = cfg.external_prompt.clone();
+    let mut recoveries = 0;
+    let mut recover = |output: String, external_prompt: &mut Option<String>| {
+        recoveries += 1;
+        if recoveries > MAX_RECOVERIES {
+            return Err(BindError::Tool {
+                output,
+                explanation: format!("Gave up after {MAX_RECOVERIES} attempts to recover"),
+            });
+        }
+        if let Some(message) = build.recover(&interpreter, output, &*progress)? {
+            progress.event(&Event::note(format!("Regenerating because: {message}")));
+            let prompt = external_prompt.get_or_insert_default();
+            if !prompt.is_empty() {
+                prompt.push('\n');
             }
+            prompt.push_str(&message);
         }
-        None => println!("no error found?"),
+        Ok(())
     };
     loop {
         let src_file_paths = match build.source_files(&src_dir) {
-            Ok(x) => dbg!(x),
+            Ok(x) => x,
             Err(e) => {
-                (error_act)(e);
+                recover(e, &mut external_prompt)?;
                 continue;
             }
         };
@@ -850,43 +1353,231 @@ pub fn bind<Source: Provider, Target: Compiler>(cfg: &Config) -> String {
         for path in src_file_paths {
             src_files.push((path.to_owned(), fs::read_to_string(&path).unwrap()));
         }
+        let src_files = match build.source.extract(&*build.executor, src_dir, src_files) {
+            Ok(x) => x,
+            Err(e) => {
+                recover(e, &mut external_prompt)?;
+                continue;
+            }
+        };
+        let src_files = match cfg.full_rebuild {
+            true => src_files,
+            false => Manifest::load(bind_dir, &*progress).stale(src_files),
+        };
+        if src_files.is_empty() {
+            progress.event(&Event::note("No source changed since the last run"));
+            break Ok((String::new(), src_files, None));
+        }
 
-        //temporarily disable compile/looping unction
-        break prompter.generate_bindings(
+        // Overridden templates change the bindings as much as the guidelines do
+        let guidelines = [build.target.guidelines(), prompts.overrides()].concat();
+        if let Some(session) = session {
+            session.check_inputs(ResponseCache::key(
+                &src_files,
+                "",
+                &guidelines,
+                Source::language(),
+                Target::language(),
+            ))?;
+        }
+        let injection = external_prompt.clone().unwrap_or_default();
+        let key = ResponseCache::key(
             &src_files,
-          &  cfg.external_prompt.clone().unwrap_or_default(),
-            build.target.guidelines(),
-            &Source::language(),
-            &Target::language(),
+            &injection,
+            &guidelines,
+            Source::language(),
+            Target::language(),
         );
+        if let Some(bindings) = cache.load(key) {
+            progress.event(&Event::note(format!("Reusing cached bindings {key:016x}")));
+            break Ok((bindings, src_files, None));
+        }
+
+        let hints = match &cfg.dependency_hints {
+            Some(path) => parallel::read_hints(path, src_dir)?,
+            None => vec![],
+        };
+        let units = parallel::units(src_files.clone(), &hints);
+        // Captures only what workers can share
+        let (guidelines, critique, rate_limit) =
+            (build.target.guidelines(), &cfg.settings.critique, &cfg.rate_limit);
+        let budget = context_tokens.or_else(|| {
+            let fixed = [BINDING_GUIDELINES, guidelines, &injection].concat();
+            input_limit.map(|limit| pack::budget(limit, &fixed))
+        });
+        let generate = |prompter: &Prompter<dyn Model>, sources: &[(PathBuf, String)]| {
+            prompter.generate_bindings(
+                &PackedSources::pack(sources.to_vec(), budget, Source::language()),
+                &injection,
+                guidelines,
+                &Source::language(),
+                &Target::language(),
+                critique,
+            )
+        };
+        let bindings = match cfg.concurrency > 1 && units.len() > 1 {
+            true => {
+                progress.event(&Event::note(format!(
+                    "Generating {} units on up to {} workers",
+                    units.len(),
+                    cfg.concurrency
+                )));
+                let model_config = cfg.settings.model_config();
+                let temperature = cfg.settings.model.temperature;
+                let worker_model = || {
+                    let model = model_config.build(String::new(), temperature);
+                    match rate_limit {
+                        Some(bucket) => RateLimited::wrap(model, bucket.clone()),
+                        None => model,
+                    }
+                };
+                parallel::generate(
+                    &units,
+                    cfg.concurrency,
+                    &worker_model,
+                    &prompts,
+                    &*progress,
+                    generate,
+                )?
+            }
+            false => generate(&prompter, &src_files)?,
+        };
+        break Ok((bindings, src_files, Some(key)));
 
 
-        //match build.compile(&bind_dir) {
-        //    Ok(out) => todo!(),
-        //    Err(err) => {
-        //        error_act(err);
-        //        continue;
-        //    }
-        //}
     }
 }
 
-pub fn bind_and_verify<Source: Provider, Target: Applicator>(cfg: &Config, output: &Output) {
-    let mut buffer = None;
-    loop {
-        let bindings = bind::<Source, Target>(&Config {
-            external_prompt: buffer.clone(),
-            ..cfg.clone()
+/// Binds and compiles until the bindings compile, recording a [`Session`]
+/// that [`resume`] can pick up if the run is interrupted.
+pub fn bind_and_verify<Source: Provider, Target: Applicator>(
+    cfg: &Config,
+    output: &Output,
+    model: Rc<dyn Model>,
+    progress: Rc<dyn ProgressSink>,
+) -> Result<(), BindError> {
+    let session = SessionLog::start(&cfg.target, cfg.keep_sessions, progress.clone());
+    verify_session::<Source, Target>(cfg, output, model, progress, Rc::new(session))
+}
+
+/// Continues an interrupted [`bind_and_verify`] from the last round it
+/// recorded. Refuses sessions started for other sources or guidelines.
+pub fn resume<Source: Provider, Target: Applicator>(
+    cfg: &Config,
+    output: &Output,
+    model: Rc<dyn Model>,
+    progress: Rc<dyn ProgressSink>,
+    session_id: &str,
+) -> Result<(), BindError> {
+    let session = SessionLog::resume(&cfg.target, session_id, progress.clone())?;
+    verify_session::<Source, Target>(cfg, output, model, progress, Rc::new(session))
+}
+
+fn verify_session<Source: Provider, Target: Applicator>(
+    cfg: &Config,
+    output: &Output,
+    model: Rc<dyn Model>,
+    progress: Rc<dyn ProgressSink>,
+    session: Rc<SessionLog>,
+) -> Result<(), BindError> {
+    verify(&Target::derive(), cfg, output, &*progress, Some(&session), |external_prompt| {
+        bind_sources::<Source, Target>(
+            &Config {
+                external_prompt,
+                ..cfg.clone()
+            },
+            model.clone(),
+            progress.clone(),
+            Some(&session),
+        )
+    })
+}
+
+/// Applies and compiles what `generate` produces until it compiles. Each
+/// retry hands `generate` the errors of the previous attempt to prompt with.
+/// `generate` also returns the sources it bound, which are recorded in the
+/// manifest once the bindings compile, and the key of the response cache
+/// entry for the bindings, if any. The bindings that compile are cached
+/// under the keys of every attempt, so the same inputs skip straight to
+/// them. Stops at the first failure to generate.
+/// Attempts are recorded in `session`, and counted on from the ones already
+/// there.
+fn verify<Target: Applicator>(
+    target: &Target,
+    cfg: &Config,
+    output: &Output,
+    progress: &dyn ProgressSink,
+    session: Option<&SessionLog>,
+    mut generate: impl FnMut(Option<String>) -> Result<(String, Vec<(PathBuf, String)>, Option<u64>), BindError>,
+) -> Result<(), BindError> {
+    let (mut attempt, mut buffer) = session.map(SessionLog::attempts).unwrap_or_default();
+    let started = SystemTime::now();
+    let mut keys = vec![];
+    let record = |attempt, result: AttemptResult, feedback: &Option<String>| {
+        progress.event(&Event::CompileAttempt {
+            attempt,
+            result: result.clone(),
         });
-        let target = Target::derive();
-        target.apply(&output, bindings.clone());
+        if let Some(session) = session {
+            session.attempt(result, feedback.clone());
+        }
+    };
+    loop {
+        attempt += 1;
+        let (bindings, sources, key) = generate(buffer.clone())?;
+        keys.extend(key);
+        let ctx = PostContext {
+            language: Target::language(),
+            output_root: &output.lib_path,
+            attempt,
+            started,
+            progress,
+        };
+        let written = match target.apply(output, bindings.clone(), &sources, cfg, &ctx) {
+            Ok(written) => written,
+            Err(err) => {
+                buffer = Some(format!("These bindings\n```{bindings}```\n could not be post processed:\n```{err}```\nPlease fix the bindings as provided"));
+                let error = err.to_string();
+                record(attempt, AttemptResult::PostProcessFailed { error }, &buffer);
+                continue;
+            }
+        };
+        for path in &written {
+            progress.event(&Event::FileWritten { path: path.clone() });
+        }
+        if cfg.write_mode == WriteMode::DryRun {
+            // Nothing was written, so there is nothing new to compile
+            if let Some(session) = session {
+                session.finish();
+            }
+            return Ok(());
+        }
         match target.compile( &output.crate_name, &output.lib_path) {
-            Ok(out) => {
-                break;
+            Ok(_) => {
+                record(attempt, AttemptResult::Compiled, &None);
+                let mut manifest = match cfg.full_rebuild {
+                    true => Manifest::default(),
+                    false => Manifest::load(&cfg.target, progress),
+                };
+                manifest.record(&sources, &written);
+                if let Err(e) = manifest.save(&cfg.target) {
+                    progress.event(&Event::warning(format!("Could not save the bind manifest: {e}")));
+                }
+                let cache = ResponseCache::new(&cfg.target);
+                for key in keys {
+                    if let Err(e) = cache.store(key, &bindings) {
+                        progress.event(&Event::warning(format!("Could not cache bindings: {e}")));
+                    }
+                }
+                if let Some(session) = session {
+                    session.finish();
+                }
+                progress.event(&Event::Completed { attempts: attempt });
+                return Ok(());
             }
             Err(err) => {
-                println!("\n\n\n{:?}\n\n\n", err);
-                buffer = Some(format!("These bindings\n```{bindings}```\n were deemed acceptable by the guidelines, but generated these compiler errors:\n```{err}```\nPlease fix the bindings as provided and improve upon them based on compiler feedback"));
+                buffer = Some(format!("These bindings\n```{bindings}```\n were deemed acceptable by the guidelines, but generated these compiler or test errors:\n```{err}```\nPlease fix the bindings as provided and improve upon them based on compiler feedback"));
+                record(attempt, AttemptResult::CompileFailed { errors: err }, &buffer);
             }
         }
     }
@@ -924,4 +1615,893 @@ pub fn unescape_unicode(text: &str) -> String {
         .replace("\\\\", "\\");
 
     result
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::Cell, collections::VecDeque, os::unix::fs::PermissionsExt};
+
+    /// Replies with `bindings` to generation prompts and a passing score to
+    /// evaluations, keeping every prompt it was sent.
+    struct Canned {
+        bindings: String,
+        prompts: RefCell<Vec<String>>,
+    }
+
+    impl Model for Canned {
+        fn respond(&self, prompt: String) -> Pin<Box<dyn ResponseCoroutine + '_>> {
+            let reply = match prompt.contains("Output a number") {
+                true => "95".to_owned(),
+                false => self.bindings.clone(),
+            };
+            self.prompts.borrow_mut().push(prompt);
+            Box::pin(
+                #[coroutine]
+                static move || {
+                    yield Ok(reply);
+                    Ok(())
+                },
+            )
+        }
+        fn respond_json(&self, _: String) -> Result<serde_json::Value, GeminiError> {
+            Err(GeminiError::HttpError("Canned replies are not JSON".to_owned()))
+        }
+        fn change(&self, _: f32) {}
+        fn temp(&self) -> f32 {
+            0.5
+        }
+    }
+
+    /// Runs the prompter loop over `model` with nothing to bind.
+    fn generate<M: Model + ?Sized>(model: Rc<M>) -> String {
+        Prompter::from_model(model, Rc::new(NullSink)).generate_bindings(
+            &PackedSources::default(),
+            "",
+            "",
+            &Language::Rust,
+            &Language::Zig,
+            &CritiqueSettings::default(),
+        )
+        .unwrap()
+    }
+
+    /// Replies with `replies` in order, one per prompt, keeping the prompts.
+    /// Reports `retries` once, as if made on the way.
+    struct Scripted {
+        replies: RefCell<VecDeque<String>>,
+        prompts: RefCell<Vec<String>>,
+        temperature: Cell<f32>,
+        retries: RefCell<Vec<RetryEvent>>,
+    }
+
+    impl Scripted {
+        fn new(replies: &[&str]) -> Rc<Self> {
+            Rc::new(Self {
+                replies: RefCell::new(replies.iter().map(|reply| reply.to_string()).collect()),
+                prompts: RefCell::default(),
+                temperature: Cell::new(0.5),
+                retries: RefCell::default(),
+            })
+        }
+    }
+
+    impl Model for Scripted {
+        fn respond(&self, prompt: String) -> Pin<Box<dyn ResponseCoroutine + '_>> {
+            let reply = self.replies.borrow_mut().pop_front().expect("script ran out");
+            self.prompts.borrow_mut().push(prompt);
+            Box::pin(
+                #[coroutine]
+                static move || {
+                    yield Ok(reply);
+                    Ok(())
+                },
+            )
+        }
+        fn respond_json(&self, prompt: String) -> Result<serde_json::Value, GeminiError> {
+            let reply = self.replies.borrow_mut().pop_front().expect("script ran out");
+            self.prompts.borrow_mut().push(prompt);
+            serde_json::from_str(&reply).map_err(|e| GeminiError::JsonParseError(e.to_string()))
+        }
+        fn change(&self, temp: f32) {
+            self.temperature.set(temp);
+        }
+        fn temp(&self) -> f32 {
+            self.temperature.get()
+        }
+        fn take_retries(&self) -> Vec<RetryEvent> {
+            self.retries.take()
+        }
+    }
+
+    fn critique_rounds(model: Rc<Scripted>, max_rounds: usize) -> (Result<String, BindFailure>, Vec<Event>) {
+        let recorder = Rc::new(Recorder::default());
+        let result = Prompter::from_model(model, recorder.clone()).generate_bindings(
+            &PackedSources::default(),
+            "",
+            "",
+            &Language::Rust,
+            &Language::Zig,
+            &CritiqueSettings {
+                threshold: 85,
+                max_rounds,
+            },
+        );
+        let events = recorder.0.take();
+        (result, events)
+    }
+
+    #[test]
+    fn test_junk_scores() {
+        let model = Scripted::new(&[
+            "first",
+            // Re-asked once, then taken as 0
+            "Looks great!",
+            "ninety",
+            "too loose",
+            "Let's try 0.2 instead.",
+            "second",
+            "Score: 150%",
+        ]);
+        let (result, events) = critique_rounds(model.clone(), 5);
+
+        assert_eq!(result.unwrap(), "second");
+        assert!(model.replies.borrow().is_empty());
+        assert_eq!(model.temp(), 0.2);
+        let scores: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::EvaluationScore { score, .. } => Some(*score),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(scores, [0, 100]);
+        assert!(events.contains(&Event::TemperatureChanged { from: 0.5, to: 0.2 }));
+        assert!(events.contains(&Event::Critique {
+            text: "too loose".to_owned()
+        }));
+    }
+
+    #[test]
+    fn test_critique_cap() {
+        let model = Scripted::new(&[
+            "first",
+            "40",
+            "too loose",
+            // Re-asked once, then the temperature stays
+            "hotter",
+            "much hotter",
+            "second",
+            "60/100",
+            "still loose",
+            "0.9",
+            "third",
+            "55",
+        ]);
+        let (result, events) = critique_rounds(model.clone(), 3);
+
+        assert!(model.replies.borrow().is_empty());
+        assert_eq!(
+            result.unwrap_err(),
+            BindFailure {
+                best: "second".to_owned(),
+                best_score: 60,
+                threshold: 85,
+                scores: vec![40, 60, 55],
+                critiques: vec!["too loose".to_owned(), "still loose".to_owned()],
+            }
+        );
+        assert!(events.contains(&Event::TemperatureChanged { from: 0.5, to: 0.9 }));
+        assert!(!events.iter().any(|event| matches!(event, Event::TemperatureChanged { from, .. } if *from != 0.5)));
+    }
+
+    #[test]
+    fn test_retries_reported() {
+        let model = Scripted::new(&["first", "90"]);
+        model.retries.borrow_mut().push(RetryEvent {
+            attempt: 1,
+            delay: Duration::from_millis(1500),
+            error: GeminiError::HttpError("503".to_owned()),
+        });
+        let (result, events) = critique_rounds(model, 1);
+
+        assert_eq!(result.unwrap(), "first");
+        let warnings: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Warning { message } => Some(message.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("Attempt 1 failed ("), "{}", warnings[0]);
+        assert!(warnings[0].ends_with("retrying in 1.5s"), "{}", warnings[0]);
+    }
+
+    #[test]
+    fn test_prompter_over_openai_compatible() {
+        let bindings = "```zig\n// ./src/root.zig\npub extern fn f() void;\n```\n";
+        let canned = Rc::new(Canned {
+            bindings: bindings.to_owned(),
+            prompts: RefCell::default(),
+        });
+        let expected = generate(canned.clone());
+
+        // The same replies, streamed in pieces over SSE
+        let (url, requests) = openai::tests::serve(move |body| {
+            let prompt = body["messages"][0]["content"].as_str().unwrap();
+            match prompt.contains("Output a number") {
+                true => vec!["9".to_owned(), "5".to_owned()],
+                false => bindings.split_inclusive('\n').map(str::to_owned).collect(),
+            }
+        });
+        let model = ModelConfig::OpenAiCompatible {
+            base_url: url,
+            model: "local".to_owned(),
+            api_key_env: None,
+        }
+        .build(String::new(), 0.5);
+
+        assert_eq!(generate(model), expected);
+        let prompts: Vec<_> = requests
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|body| body["messages"][0]["content"].as_str().unwrap().to_owned())
+            .collect();
+        assert_eq!(prompts, *canned.prompts.borrow());
+    }
+
+    /// Keeps every event it receives.
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<Event>>);
+
+    impl ProgressSink for Recorder {
+        fn event(&self, event: &Event) {
+            self.0.borrow_mut().push(event.clone());
+        }
+    }
+
+    /// A `zig` that rejects the first build of a package and accepts the next.
+    fn stub_zig(dir: &Path) -> PathBuf {
+        let path = dir.join("zig");
+        fs::write(
+            &path,
+            "#!/bin/sh\n\
+             [ -f .attempted ] && exit 0\n\
+             touch .attempted\n\
+             echo \"src/root.zig:3:1: error: expected ';' after declaration\" >&2\n\
+             exit 1\n",
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_zig_target() {
+        let root = env::temp_dir().join(format!("bind-zig-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let zig = Zig {
+            binary: stub_zig(&root),
+        };
+        let model = Rc::new(Canned {
+            bindings: "```zig\n// ./src/root.zig\npub const types = @import(\"types.zig\");\n```\n\n\
+                       ```zig\n// ./src/types.zig\npub const Point = extern struct { x: f32, y: f32 };\n```\n"
+                .to_owned(),
+            prompts: RefCell::default(),
+        });
+        let recorder = Rc::new(Recorder::default());
+        let prompter = Prompter::from_model(model.clone(), recorder.clone());
+        let cfg = Config {
+            source: root.join("source"),
+            target: root.clone(),
+            ..Default::default()
+        };
+        let output = Output {
+            lib_path: root.clone(),
+            crate_name: "point".to_owned(),
+        };
+
+        let mut feedback = vec![];
+        verify(&zig, &cfg, &output, &*recorder, None, |external_prompt| {
+            feedback.push(external_prompt.clone());
+            let bindings = prompter.generate_bindings(
+                &PackedSources::default(),
+                &external_prompt.unwrap_or_default(),
+                zig.guidelines(),
+                &Language::Rust,
+                &Language::Zig,
+                &CritiqueSettings::default(),
+            )?;
+            Ok((bindings, vec![], None))
+        })
+        .unwrap();
+
+        let package = root.join("point");
+        assert_eq!(
+            fs::read_to_string(package.join("src/root.zig")).unwrap(),
+            "pub const types = @import(\"types.zig\");"
+        );
+        assert_eq!(
+            fs::read_to_string(package.join("src/types.zig")).unwrap(),
+            "pub const Point = extern struct { x: f32, y: f32 };"
+        );
+        assert!(fs::read_to_string(package.join("build.zig")).unwrap().contains(".name = \"point\""));
+
+        // The stub's compile error reaches the prompt of the second attempt
+        assert_eq!(feedback.len(), 2);
+        assert_eq!(feedback[0], None);
+        let generations: Vec<_> = model
+            .prompts
+            .borrow()
+            .iter()
+            .filter(|prompt| !prompt.contains("Output a number"))
+            .cloned()
+            .collect();
+        assert_eq!(generations.len(), 2);
+        assert!(!generations[0].contains("expected ';'"));
+        assert!(generations[1].contains("# Compiler output:"));
+        assert!(generations[1].contains("error: expected ';' after declaration"));
+
+        // Each attempt is generated, scored once and compiled, in that order
+        let events = recorder.0.take();
+        let kinds: Vec<_> = events
+            .iter()
+            .filter(|event| !matches!(event, Event::Delta { .. } | Event::FileWritten { .. } | Event::Note { .. }))
+            .map(|event| match event {
+                Event::PromptSent { .. } => "prompt",
+                Event::EvaluationScore { .. } => "score",
+                Event::CompileAttempt { .. } => "compile",
+                Event::Completed { .. } => "completed",
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            ["prompt", "prompt", "score", "compile", "prompt", "prompt", "score", "compile", "completed"]
+        );
+        assert!(events.contains(&Event::Delta {
+            text: model.bindings.clone()
+        }));
+        assert!(events.contains(&Event::EvaluationScore {
+            score: 95,
+            threshold: 85
+        }));
+        assert!(matches!(
+            &events[events.len() - 2],
+            Event::CompileAttempt {
+                attempt: 2,
+                result: AttemptResult::Compiled
+            }
+        ));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            Event::CompileAttempt {
+                attempt: 1,
+                result: AttemptResult::CompileFailed { errors }
+            } if errors.contains("expected ';'")
+        )));
+        assert_eq!(events.last(), Some(&Event::Completed { attempts: 2 }));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_cache_only_compiled() {
+        let root = env::temp_dir().join(format!("bind-cache-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let zig = Zig {
+            binary: stub_zig(&root),
+        };
+        let cfg = Config {
+            source: root.join("source"),
+            target: root.clone(),
+            ..Default::default()
+        };
+        let output = Output {
+            lib_path: root.clone(),
+            crate_name: "point".to_owned(),
+        };
+        let cache = ResponseCache::new(&root);
+        let attempts = ["pub const x = 1", "pub const x = 2;"]
+            .map(|code| format!("```zig\n// ./src/root.zig\n{code}\n```\n"));
+
+        let mut attempt = 0;
+        verify(&zig, &cfg, &output, &NullSink, None, |_| {
+            // The attempt that failed to compile was not cached
+            assert_eq!(cache.load(1), None);
+            attempt += 1;
+            Ok((attempts[attempt - 1].clone(), vec![], Some(attempt as u64)))
+        })
+        .unwrap();
+
+        // The bindings that compiled stand in for the first attempt too
+        assert_eq!(cache.load(1).as_ref(), Some(&attempts[1]));
+        assert_eq!(cache.load(2).as_ref(), Some(&attempts[1]));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_swift_target() {
+        let root = env::temp_dir().join(format!("bind-swift-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        // Fails the first build the way the package manager reports errors
+        let binary = root.join("swift");
+        fs::write(
+            &binary,
+            "#!/bin/sh\n\
+             [ \"$1\" = build ] || exit 2\n\
+             [ -f .attempted ] && exit 0\n\
+             touch .attempted\n\
+             echo \"Sources/point/Point.swift:2:29: error: cannot find type 'CFloat2' in scope\"\n\
+             exit 1\n",
+        )
+        .unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+        let swift = Swift { binary };
+        let model = Rc::new(Canned {
+            bindings: "```swift\n// Point.swift\n@_silgen_name(\"point_len\")\npublic func pointLen(_ p: Point) -> Float\n```\n\n\
+                       ```swift\n// Sources/point/Shape.swift\npublic struct Shape {}\n```\n"
+                .to_owned(),
+            prompts: RefCell::default(),
+        });
+        let prompter = Prompter::from_model(model.clone(), Rc::new(NullSink));
+        let cfg = Config {
+            target: root.clone(),
+            ..Default::default()
+        };
+        let output = Output {
+            lib_path: root.clone(),
+            crate_name: "point".to_owned(),
+        };
+        let header = root.join("point.h");
+
+        let mut feedback = vec![];
+        verify(&swift, &cfg, &output, &NullSink, None, |external_prompt| {
+            feedback.push(external_prompt.clone());
+            let bindings = prompter.generate_bindings(
+                &PackedSources::default(),
+                &external_prompt.unwrap_or_default(),
+                swift.guidelines(),
+                &Language::Zig,
+                &Language::Swift,
+                &CritiqueSettings::default(),
+            )?;
+            Ok((bindings, vec![(header.clone(), "float point_len(Point p);".to_owned())], None))
+        })
+        .unwrap();
+
+        let package = root.join("point");
+        assert_eq!(
+            fs::read_to_string(package.join("Sources/point/Point.swift")).unwrap(),
+            "@_silgen_name(\"point_len\")\npublic func pointLen(_ p: Point) -> Float"
+        );
+        assert!(package.join("Sources/point/Shape.swift").exists());
+        let manifest = fs::read_to_string(package.join("Package.swift")).unwrap();
+        assert!(manifest.contains(".systemLibrary(name: \"Cpoint\", path: \"Sources/Cpoint\")"));
+        assert!(manifest.contains(".target(name: \"point\", dependencies: [\"Cpoint\"])"));
+        assert!(fs::read_to_string(package.join("Sources/Cpoint/module.modulemap")).unwrap().contains("link \"point\""));
+        assert_eq!(
+            fs::read_to_string(package.join("Sources/Cpoint/shim.h")).unwrap(),
+            format!("#include \"{}\"\n", header.display())
+        );
+
+        // The build error reaches the prompt of the second attempt
+        assert_eq!(feedback.len(), 2);
+        assert_eq!(feedback[0], None);
+        assert!(feedback[1].as_ref().unwrap().contains("cannot find type 'CFloat2' in scope"));
+        let generations: Vec<_> = model
+            .prompts
+            .borrow()
+            .iter()
+            .filter(|prompt| !prompt.contains("Output a number"))
+            .cloned()
+            .collect();
+        assert!(generations[1].contains("Sources/point/Point.swift:2:29: error"));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_resume_session() {
+        const SCRIPT: [&str; 10] = [
+            "```zig\n// ./src/root.zig\npub const a = 1;\n```",
+            "40",
+            "Too terse.",
+            "0.7",
+            "```zig\n// ./src/root.zig\npub const b = 2;\n```",
+            "60",
+            "Still terse.",
+            "0.9",
+            "```zig\n// ./src/root.zig\npub const c = 3;\n```",
+            "90",
+        ];
+        let base = env::temp_dir().join(format!("bind-session-{}", std::process::id()));
+        let run = |name: &str, model: Rc<Scripted>, session: Option<&str>, inputs: u64| {
+            let root = base.join(name);
+            fs::create_dir_all(&root).unwrap();
+            let binary = root.join("zig");
+            fs::write(&binary, "#!/bin/sh\nexit 0\n").unwrap();
+            fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+            let cfg = Config {
+                target: root.clone(),
+                ..Default::default()
+            };
+            let output = Output {
+                lib_path: root.clone(),
+                crate_name: "point".to_owned(),
+            };
+            let session = Rc::new(match session {
+                Some(id) => SessionLog::resume(&root, id, Rc::new(NullSink)).unwrap(),
+                None => SessionLog::start(&root, 1, Rc::new(NullSink)),
+            });
+            let prompter = Prompter::from_model(model, Rc::new(NullSink)).with_session(Some(session.clone()));
+            let result = verify(&Zig { binary }, &cfg, &output, &NullSink, Some(&session), |_| {
+                session.check_inputs(inputs)?;
+                let bindings = prompter.generate_bindings(
+                    &PackedSources::default(),
+                    "",
+                    "",
+                    &Language::Rust,
+                    &Language::Zig,
+                    &CritiqueSettings::default(),
+                )?;
+                Ok((bindings, vec![], None))
+            });
+            (result, root)
+        };
+
+        let whole = Scripted::new(&SCRIPT);
+        let (result, root) = run("whole", whole.clone(), None, 1);
+        result.unwrap();
+        let expected = fs::read_to_string(root.join("point/src/root.zig")).unwrap();
+        assert_eq!(expected, "pub const c = 3;");
+
+        // The script runs out in the third round, like a dropped connection
+        let crashed = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            run("crashed", Scripted::new(&SCRIPT[..8]), None, 1)
+        }));
+        assert!(crashed.is_err());
+        let root = base.join("crashed");
+        let saved: Vec<_> = fs::read_dir(root.join(".bind-session")).unwrap().collect();
+        assert_eq!(saved.len(), 1);
+        let id = saved[0].as_ref().unwrap().path().file_stem().unwrap().to_str().unwrap().to_owned();
+        let session = Session::load(&root, &id).unwrap();
+        assert_eq!(session.rounds.len(), 2);
+        assert_eq!(session.rounds[1].next_temperature, 0.9);
+        assert!(session.attempts.is_empty());
+
+        let (result, _) = run("crashed", Scripted::new(&[]), Some(&id), 2);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            format!(
+                "cannot resume session {id}: the sources or guidelines changed since it was started, \
+                 start a new session instead"
+            )
+        );
+
+        let resumed = Scripted::new(&SCRIPT[8..]);
+        run("crashed", resumed.clone(), Some(&id), 1).0.unwrap();
+        assert_eq!(fs::read_to_string(root.join("point/src/root.zig")).unwrap(), expected);
+        // The third round is prompted exactly as it was without the crash
+        assert_eq!(resumed.prompts.borrow()[0], whole.prompts.borrow()[8]);
+        assert!(!Session::path(&root, &id).exists());
+        fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn test_incremental_rebind() {
+        let root = env::temp_dir().join(format!("bind-incremental-{}", std::process::id()));
+        let source = root.join("source");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("a.h"), "void a();").unwrap();
+        fs::write(source.join("b.h"), "void b();").unwrap();
+        // Accepts every build
+        let zig = Zig {
+            binary: "true".into(),
+        };
+        let cfg = Config {
+            source: source.clone(),
+            target: root.clone(),
+            ..Default::default()
+        };
+        let output = Output {
+            lib_path: root.clone(),
+            crate_name: "ab".to_owned(),
+        };
+
+        // One output per stale source, standing in for bind_sources
+        let run = || {
+            let mut prompted = vec![];
+            verify(&zig, &cfg, &output, &NullSink, None, |_| {
+                let sources: Vec<_> = ["a.h", "b.h"]
+                    .into_iter()
+                    .map(|name| (source.join(name), fs::read_to_string(source.join(name)).unwrap()))
+                    .collect();
+                let stale = Manifest::load(&cfg.target, &NullSink).stale(sources);
+                let bindings = stale
+                    .iter()
+                    .map(|(path, contents)| {
+                        let stem = path.file_stem().unwrap().to_str().unwrap();
+                        format!("```zig\n// src/{stem}.zig\n// {contents}\n```\n")
+                    })
+                    .collect();
+                prompted.extend(stale.iter().map(|(path, _)| path.clone()));
+                Ok((bindings, stale, None))
+            })
+            .unwrap();
+            prompted
+        };
+
+        assert_eq!(run(), [source.join("a.h"), source.join("b.h")]);
+        let package = root.join("ab");
+        fs::write(package.join("src/a.zig"), "// edited by hand").unwrap();
+        let state = fs::read_to_string(root.join(".bind-state.json")).unwrap();
+
+        fs::write(source.join("b.h"), "void b(int);").unwrap();
+        assert_eq!(run(), [source.join("b.h")]);
+        assert_eq!(fs::read_to_string(package.join("src/a.zig")).unwrap(), "// edited by hand");
+        assert_eq!(fs::read_to_string(package.join("src/b.zig")).unwrap(), "// void b(int);");
+
+        let manifest = Manifest::load(&root, &NullSink);
+        assert!(manifest.stale(vec![(source.join("b.h"), "void b(int);".to_owned())]).is_empty());
+        assert!(!manifest.stale(vec![(source.join("b.h"), "void b();".to_owned())]).is_empty());
+        assert_ne!(fs::read_to_string(root.join(".bind-state.json")).unwrap(), state);
+        assert!(run().is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    /// A host workspace passed off as a container, so stages get installed.
+    struct Pretend(LocalExecutor);
+
+    impl Executor for Pretend {
+        fn exec(&self, cmd: &[&str]) -> Result<CommandResult, String> {
+            self.0.exec(cmd)
+        }
+        fn copy_to(&self, host: &Path, dest: &Path) -> Result<(), String> {
+            self.0.copy_to(host, dest)
+        }
+    }
+
+    /// Installs by echoing its name, and counts as installed if `probe`
+    /// succeeds.
+    struct Echo {
+        name: &'static str,
+        probe: &'static [&'static str],
+    }
+
+    impl Stage for Echo {
+        fn installation<'a>(&self, executor: &'a dyn Executor) -> Script<'a> {
+            executor.inject(format!("echo {}", self.name))
+        }
+        fn probe(&self) -> Vec<&str> {
+            self.probe.to_vec()
+        }
+    }
+
+    #[test]
+    fn test_install_probes_stages() {
+        let stages = || -> Vec<Arc<dyn Stage>> {
+            vec![
+                Arc::new(Echo {
+                    name: "present",
+                    probe: &["true"],
+                }),
+                Arc::new(Echo {
+                    name: "missing",
+                    probe: &["false"],
+                }),
+            ]
+        };
+        let notes = |executor: &dyn Executor| {
+            let sink = Recorder::default();
+            install(executor, stages(), &sink);
+            sink.0
+                .take()
+                .into_iter()
+                .map(|event| match event {
+                    Event::Note { message } | Event::Warning { message } => message,
+                    event => panic!("unexpected {event:?}"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // A reused container only gets what it lacks
+        let container = Pretend(LocalExecutor::temp().unwrap());
+        assert_eq!(notes(&container), ["Installed stage: missing"]);
+        // The host is never installed to
+        let host = LocalExecutor::temp().unwrap();
+        assert_eq!(
+            notes(&host),
+            ["`false` failed, is the toolchain installed?"]
+        );
+    }
+
+    /// Stands in for a container, keeping every command and script instead
+    /// of running them. Only `dpkg` fails, as if no package were installed.
+    #[derive(Default)]
+    struct Recording(Rc<RefCell<Vec<String>>>);
+
+    impl Executor for Recording {
+        fn exec(&self, cmd: &[&str]) -> Result<CommandResult, String> {
+            self.0.borrow_mut().push(cmd.join(" "));
+            Ok(CommandResult {
+                success: cmd[0] != "dpkg",
+                stdout: "/tmp/recorded\n".to_owned(),
+                stderr: String::new(),
+                exit_code: 0,
+            })
+        }
+        fn copy_to(&self, host: &Path, _: &Path) -> Result<(), String> {
+            self.0.borrow_mut().push(fs::read_to_string(host).unwrap());
+            Ok(())
+        }
+    }
+
+    fn zig_build(executor: impl Executor + 'static) -> Build {
+        Build {
+            executor: Box::new(executor),
+            source: Arc::new(Zig::default()),
+            target: Arc::new(Zig::default()),
+        }
+    }
+
+    #[test]
+    fn test_recover_from_interpreted_errors() {
+        let model = Scripted::new(&[
+            r#"[{"type": "Invalid", "src": "Agent", "msg": "Use c_int for int"}]"#,
+            r#"[{"type": "Invalid", "src": "User", "msg": "point.h:3: unknown type vec2"}]"#,
+            r#"[{"type": "MissingDependency", "package": "libssl-dev"}]"#,
+            r#"[{"type": "MissingDependency", "package": "libssl-dev; rm -rf /"}]"#,
+            r#"[{"type": "MissingDependency", "package": "libssl-dev"}]"#,
+            "I cannot tell",
+        ]);
+        let interpreter = Interpreter::from_model(model.clone(), Rc::new(NullSink));
+        let recording = Recording::default();
+        let commands = recording.0.clone();
+        let container = zig_build(recording);
+        let recover = |build: &Build, output: &str| build.recover(&interpreter, output.to_owned(), &NullSink);
+
+        assert_eq!(
+            recover(&container, "expected c_int").unwrap().as_deref(),
+            Some("Use c_int for int")
+        );
+        assert!(model.prompts.borrow()[0].ends_with("\nexpected c_int"));
+
+        let err = recover(&container, "point.h:3:5: error: unknown type name 'vec2'").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The sources have errors bind cannot fix:\npoint.h:3: unknown type vec2\n\
+             point.h:3:5: error: unknown type name 'vec2'"
+        );
+
+        // Installed in the container, then the tool runs again as it was
+        assert_eq!(recover(&container, "openssl/ssl.h not found").unwrap(), None);
+        let installed = commands.take();
+        assert_eq!(installed[0], "dpkg -s libssl-dev");
+        assert!(installed.iter().any(|command| command.contains("apt-get install -y libssl-dev\n")));
+        assert_eq!(installed.last().unwrap(), "/bin/bash -c /tmp/recorded/script.sh");
+        // Nothing that is not a package name gets near a shell
+        assert_eq!(recover(&container, "ssl.h not found").unwrap(), None);
+        assert!(commands.take().is_empty());
+
+        // The host's packages are the user's to install
+        let host = zig_build(LocalExecutor::temp().unwrap());
+        let err = recover(&host, "openssl/ssl.h not found").unwrap_err();
+        assert!(matches!(&err, BindError::Tool { explanation, .. }
+            if explanation == "The package libssl-dev is missing, install it and try again"));
+
+        assert_eq!(recover(&host, "segfault").unwrap(), None);
+    }
+
+    #[test]
+    fn test_recovery_is_bounded() {
+        let agent = r#"[{"type": "Invalid", "src": "Agent", "msg": "Look elsewhere"}]"#;
+        let model = Scripted::new(&[agent; MAX_RECOVERIES]);
+        let mut cfg = Config {
+            source: env::temp_dir().join(format!("bind-missing-{}", std::process::id())),
+            ..Default::default()
+        };
+        cfg.settings.container.execution = Execution::Local;
+        let progress = Rc::new(Recorder::default());
+
+        // The sources cannot be found, however often the model is asked
+        let err = bind_sources::<Zig, Zig>(&cfg, model.clone(), progress.clone(), None).unwrap_err();
+        assert!(matches!(&err, BindError::Tool { explanation, output }
+            if explanation == "Gave up after 3 attempts to recover" && output.contains("bind-missing")));
+        assert_eq!(model.prompts.borrow().len(), MAX_RECOVERIES);
+        let regenerations = progress
+            .0
+            .borrow()
+            .iter()
+            .filter(|event| matches!(event, Event::Note { message } if message == "Regenerating because: Look elsewhere"))
+            .count();
+        assert_eq!(regenerations, MAX_RECOVERIES);
+    }
+
+    /// Where cargo puts what it builds of the crate at `dir`, which
+    /// `CARGO_TARGET_DIR` moves out of it.
+    fn target_dir(dir: &Path) -> PathBuf {
+        let output = Command::new("cargo")
+            .args(["metadata", "--format-version", "1", "--no-deps"])
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let metadata: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        PathBuf::from(metadata["target_directory"].as_str().unwrap())
+    }
+
+    #[test]
+    fn test_local_rust_build() {
+        let settings = ContainerSettings {
+            execution: Execution::Local,
+            ..Default::default()
+        };
+        let build = Build::create(Language::Rust, Language::Rust, &settings, &NullSink);
+        assert!(build.executor.is_local());
+
+        // Built from a copy, so the fixture gets no target directory
+        let root = env::temp_dir().join(format!("bind-local-rust-{}", std::process::id()));
+        let crate_dir = root.join("exports");
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/exports");
+        build.executor.copy_to(&fixture, &crate_dir).unwrap();
+
+        let paths = build.source_files(&crate_dir).unwrap();
+        assert_eq!(paths, [crate_dir.join("src/lib.rs")]);
+        let files = paths
+            .into_iter()
+            .map(|path| {
+                let contents = fs::read_to_string(&path).unwrap();
+                (path, contents)
+            })
+            .collect();
+        let abi = build.source.extract(&*build.executor, &crate_dir, files).unwrap();
+        assert!(abi[0].1.contains("pub fn point_new"));
+        assert!(abi[0].1.contains("// size_of::<Point>() == 8\n"));
+        assert!(target_dir(&crate_dir).join("debug").exists());
+
+        let cfg = Config {
+            source: crate_dir.clone(),
+            target: root.clone(),
+            ..Default::default()
+        };
+        let output = Output {
+            lib_path: root.clone(),
+            crate_name: "exports".to_owned(),
+        };
+        // Compiles, but with a layout the smoke test rejects
+        let fixed = fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/exports_sys.rs")).unwrap();
+        let wrong = fixed.replace("pub x: f32", "pub x: f64");
+        let block = |code: &str| format!("```rust\n// src/lib.rs\n{code}```\n");
+        let model = Scripted::new(&[&block(&wrong), "95", &block(&fixed), "95"]);
+        let prompter = Prompter::from_model(model.clone(), Rc::new(NullSink));
+        verify(&Rust, &cfg, &output, &NullSink, None, |external_prompt| {
+            let bindings = prompter.generate_bindings(
+                &PackedSources::pack(abi.clone(), None, Language::Rust),
+                &external_prompt.unwrap_or_default(),
+                Rust.guidelines(),
+                &Language::Rust,
+                &Language::Rust,
+                &CritiqueSettings::default(),
+            )?;
+            Ok((bindings, abi.clone(), None))
+        })
+        .unwrap();
+
+        let harness = fs::read_to_string(root.join("exports-sys/tests/abi_smoke.rs")).unwrap();
+        assert!(harness.contains("let _: unsafe extern \"C\" fn(f32, f32) -> Point = point_new;"));
+        assert!(harness.contains("assert_size::<Point>(\"Point\", 8);"));
+        // The failed size check reached the prompt of the second attempt
+        let prompts = model.prompts.borrow();
+        assert_eq!(prompts.len(), 4);
+        assert!(!prompts[0].contains("size of Point"));
+        assert!(prompts[2].contains("size of Point"));
+        assert!(target_dir(&root.join("exports-sys")).join("release").exists());
+        assert!(Manifest::load(&root, &NullSink).stale(abi.clone()).is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
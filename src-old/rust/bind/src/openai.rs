@@ -0,0 +1,336 @@
+use std::{
+    cell::Cell,
+    env,
+    io::{BufRead, BufReader, Read, Write},
+    pin::Pin,
+    process::{Child, Command, Stdio},
+};
+
+use gemini::GeminiError;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{Model, ResponseCoroutine};
+
+/// curl's exit code for an HTTP error status under `--fail-with-body`.
+const HTTP_FAILURE: i32 = 22;
+
+/// A model behind the OpenAI chat completions API, as served by llama.cpp,
+/// vLLM, Ollama and OpenAI itself.
+pub struct OpenAiCompatible {
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+    system: String,
+    temperature: Cell<f32>,
+}
+
+impl OpenAiCompatible {
+    /// Configured from `OPENAI_BASE_URL`, `OPENAI_MODEL` and, if set,
+    /// `OPENAI_API_KEY`, defaulting to a llama.cpp server on localhost.
+    pub fn new(system: String, temperature: f32) -> Self {
+        let base_url = env::var("OPENAI_BASE_URL").unwrap_or("http://localhost:8080/v1".to_owned());
+        let model = env::var("OPENAI_MODEL").unwrap_or("default".to_owned());
+        let key = env::var("OPENAI_API_KEY").is_ok().then_some("OPENAI_API_KEY");
+        Self::connect(&base_url, &model, key, system, temperature)
+    }
+
+    /// `base_url` is the API root, e.g. `http://localhost:8080/v1`. The key is
+    /// read from `api_key_env` if given; local servers usually need none.
+    pub fn connect(
+        base_url: &str,
+        model: &str,
+        api_key_env: Option<&str>,
+        system: String,
+        temperature: f32,
+    ) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            model: model.to_owned(),
+            api_key: api_key_env.map(|var| {
+                env::var(var).unwrap_or_else(|_| panic!("{var} is not set"))
+            }),
+            system,
+            temperature: Cell::new(temperature),
+        }
+    }
+
+    fn body(&self, prompt: &str, stream: bool) -> serde_json::Value {
+        let mut messages = vec![];
+        if !self.system.is_empty() {
+            messages.push(json!({ "role": "system", "content": self.system }));
+        }
+        messages.push(json!({ "role": "user", "content": prompt }));
+        json!({
+            "model": self.model,
+            "messages": messages,
+            "temperature": self.temperature.get(),
+            "stream": stream,
+        })
+    }
+
+    /// Starts a request with `body` written to curl's stdin, which keeps long
+    /// prompts out of the argument list.
+    fn send(&self, body: &serde_json::Value) -> Result<Child, GeminiError> {
+        let mut curl = Command::new("curl");
+        curl.args(["-sS", "-N", "--fail-with-body", "-X", "POST"])
+            .args(["-H", "Content-Type: application/json; charset=utf-8"])
+            .args(["--data-binary", "@-"]);
+        if let Some(key) = &self.api_key {
+            curl.arg("-H").arg(format!("Authorization: Bearer {key}"));
+        }
+        let mut child = curl
+            .arg(format!("{}/chat/completions", self.base_url))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| GeminiError::CurlError(e.to_string()))?;
+
+        let written = child.stdin.take().unwrap().write_all(body.to_string().as_bytes());
+        if let Err(e) = written {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(GeminiError::IoError(e.to_string()));
+        }
+        Ok(child)
+    }
+}
+
+/// Waits for curl, turning a failed exit into an error that carries `body`,
+/// the text that was not part of a successful reply.
+fn finish(mut child: Child, body: String) -> Result<(), GeminiError> {
+    let status = child
+        .wait()
+        .map_err(|e| GeminiError::CurlError(format!("Error waiting for curl process: {e}")))?;
+    if status.success() {
+        return Ok(());
+    }
+
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+        let _ = pipe.read_to_string(&mut stderr);
+    }
+    Err(match status.code() {
+        Some(HTTP_FAILURE) => GeminiError::HttpError(body),
+        code => GeminiError::Transport {
+            exit_code: code.unwrap_or(-1),
+            stderr,
+        },
+    })
+}
+
+#[derive(Deserialize)]
+struct Chunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    delta: Delta,
+}
+
+#[derive(Deserialize)]
+struct Delta {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Completion {
+    choices: Vec<CompletionChoice>,
+}
+
+#[derive(Deserialize)]
+struct CompletionChoice {
+    message: Message,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    content: String,
+}
+
+impl Model for OpenAiCompatible {
+    fn respond(&self, prompt: String) -> Pin<Box<dyn ResponseCoroutine + '_>> {
+        let body = self.body(&prompt, true);
+        Box::pin(
+            #[coroutine]
+            static move || {
+                let mut child = self.send(&body)?;
+                let stdout = child.stdout.take().unwrap();
+                // Whatever is not an event is an error body, kept for the error
+                let mut rest = String::new();
+                for line in BufReader::new(stdout).lines() {
+                    let line = line.map_err(|e| GeminiError::IoError(e.to_string()))?;
+                    let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                        if !line.starts_with(':') {
+                            rest.push_str(&line);
+                            rest.push('\n');
+                        }
+                        continue;
+                    };
+                    if data == "[DONE]" {
+                        break;
+                    }
+                    let chunk: Chunk = serde_json::from_str(data).map_err(|e| {
+                        GeminiError::JsonParseError(format!("Failed to parse stream chunk: {e}. Chunk: {data}"))
+                    })?;
+                    if let Some(error) = chunk.error {
+                        return Err(GeminiError::StreamError(error.to_string()));
+                    }
+                    for choice in chunk.choices {
+                        if let Some(text) = choice.delta.content.filter(|text| !text.is_empty()) {
+                            yield Ok(text);
+                        }
+                    }
+                }
+                finish(child, rest)
+            },
+        )
+    }
+
+    fn respond_json(&self, prompt: String) -> Result<serde_json::Value, GeminiError> {
+        let mut body = self.body(&prompt, false);
+        body["response_format"] = json!({ "type": "json_object" });
+        let mut child = self.send(&body)?;
+        let mut reply = String::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut reply)
+            .map_err(|e| GeminiError::IoError(e.to_string()))?;
+        finish(child, reply.clone())?;
+
+        let completion: Completion = serde_json::from_str(&reply)
+            .map_err(|e| GeminiError::JsonParseError(format!("{e}: {reply}")))?;
+        let content = completion
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| GeminiError::StreamError("Completion without choices".to_owned()))?
+            .message
+            .content;
+        serde_json::from_str(&content).map_err(|e| GeminiError::JsonParseError(format!("{e}: {content}")))
+    }
+
+    fn change(&self, temp: f32) {
+        self.temperature.set(temp);
+    }
+
+    fn temp(&self) -> f32 {
+        self.temperature.get()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        net::{TcpListener, TcpStream},
+        ops::CoroutineState,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    /// The request bodies a [`serve`] server received, in order.
+    pub(crate) type Requests = Arc<Mutex<Vec<serde_json::Value>>>;
+
+    /// Serves chat completions on localhost until the test ends, streaming the
+    /// chunks `reply` picks for each request body as SSE events.
+    pub(crate) fn serve(
+        reply: impl Fn(&serde_json::Value) -> Vec<String> + Send + 'static,
+    ) -> (String, Requests) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let requests = Requests::default();
+        let seen = requests.clone();
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                let mut socket = socket.unwrap();
+                let body = read_request(&mut socket);
+                let chunks = reply(&body);
+                seen.lock().unwrap().push(body);
+
+                let mut response = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nConnection: close\r\n\r\n".to_owned();
+                for chunk in chunks {
+                    let event = json!({ "choices": [{ "index": 0, "delta": { "content": chunk } }] });
+                    response.push_str(&format!("data: {event}\n\n"));
+                }
+                response.push_str("data: [DONE]\n\n");
+                socket.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn read_request(socket: &mut TcpStream) -> serde_json::Value {
+        let mut reader = BufReader::new(socket);
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn collect(model: &OpenAiCompatible, prompt: &str) -> (Vec<String>, Result<(), GeminiError>) {
+        let mut stream = model.respond(prompt.to_owned());
+        let mut texts = vec![];
+        loop {
+            match stream.as_mut().resume(()) {
+                CoroutineState::Yielded(text) => texts.push(text.unwrap()),
+                CoroutineState::Complete(result) => return (texts, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_streams_deltas() {
+        let (url, requests) = serve(|_| vec!["Hello".to_owned(), "".to_owned(), ", world".to_owned()]);
+        let model = OpenAiCompatible::connect(&url, "local", None, "Be brief.".to_owned(), 0.25);
+
+        let (texts, result) = collect(&model, "Say hello");
+        result.unwrap();
+        assert_eq!(texts, ["Hello", ", world"]);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0]["model"], "local");
+        assert_eq!(requests[0]["stream"], true);
+        assert_eq!(requests[0]["temperature"], 0.25);
+        assert_eq!(requests[0]["messages"][0]["content"], "Be brief.");
+        assert_eq!(requests[0]["messages"][1], json!({ "role": "user", "content": "Say hello" }));
+    }
+
+    #[test]
+    fn test_http_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            read_request(&mut socket);
+            let body = r#"{"error":{"message":"model not loaded"}}"#;
+            let head = format!("HTTP/1.1 503 Service Unavailable\r\nContent-Length: {}\r\n\r\n", body.len());
+            socket.write_all(head.as_bytes()).unwrap();
+            socket.write_all(body.as_bytes()).unwrap();
+        });
+        let model = OpenAiCompatible::connect(&url, "local", None, String::new(), 0.5);
+
+        let (texts, result) = collect(&model, "Say hello");
+        assert!(texts.is_empty());
+        assert!(matches!(result, Err(GeminiError::HttpError(body)) if body.contains("model not loaded")));
+    }
+}
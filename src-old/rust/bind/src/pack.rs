@@ -0,0 +1,362 @@
+use std::path::{Path, PathBuf};
+
+use crate::{Language, progress::estimate_tokens};
+
+/// How much of a source made it into the prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Provenance {
+    Whole,
+    /// Function bodies were cut, leaving the declarations.
+    Signatures,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PackedFile {
+    pub(crate) path: PathBuf,
+    pub(crate) contents: String,
+    pub(crate) provenance: Provenance,
+}
+
+/// The sources of one prompt, cut down to a token budget. Files that
+/// declare exports come first and are never dropped, then headers, then
+/// the rest. What does not fit whole is cut down to its signatures, and
+/// what does not fit that way is left out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct PackedSources {
+    /// In priority order.
+    pub(crate) files: Vec<PackedFile>,
+    /// Left out entirely, in priority order.
+    pub(crate) omitted: Vec<PathBuf>,
+}
+
+impl PackedSources {
+    /// Packs `files` into `budget` tokens, or takes them all whole without
+    /// one.
+    pub(crate) fn pack(mut files: Vec<(PathBuf, String)>, budget: Option<usize>, language: Language) -> Self {
+        files.sort_by_key(|(path, contents)| priority(path, contents));
+        let mut packed = Self {
+            files: vec![],
+            omitted: vec![],
+        };
+        let mut left = budget.unwrap_or(usize::MAX);
+        for (path, contents) in files {
+            let exports = priority(&path, &contents) == Priority::Exports;
+            let whole = estimate_tokens(&contents);
+            let (contents, provenance) = match whole <= left {
+                true => (contents, Provenance::Whole),
+                false => {
+                    let cut = signatures(&contents, &path, language);
+                    if estimate_tokens(&cut) > left && !exports {
+                        packed.omitted.push(path);
+                        continue;
+                    }
+                    (cut, Provenance::Signatures)
+                }
+            };
+            left = left.saturating_sub(estimate_tokens(&contents));
+            packed.files.push(PackedFile {
+                path,
+                contents,
+                provenance,
+            });
+        }
+        packed
+    }
+
+    /// Whether every source is in the prompt as it is.
+    pub(crate) fn is_complete(&self) -> bool {
+        self.omitted.is_empty() && self.files.iter().all(|file| file.provenance == Provenance::Whole)
+    }
+
+    /// The sources as shown to the model.
+    pub(crate) fn sources(&self) -> Vec<(&Path, &str)> {
+        self.files
+            .iter()
+            .map(|file| (file.path.as_path(), file.contents.as_str()))
+            .collect()
+    }
+
+    /// A line for every source that is not in the prompt as it is, empty if
+    /// all of them are.
+    pub(crate) fn omissions(&self) -> String {
+        let cut = self
+            .files
+            .iter()
+            .filter(|file| file.provenance == Provenance::Signatures);
+        let cut = cut.map(|file| format!("- {}: signatures only, bodies left out\n", file.path.display()));
+        let omitted = self
+            .omitted
+            .iter()
+            .map(|path| format!("- {}: left out\n", path.display()));
+        cut.chain(omitted).collect()
+    }
+}
+
+/// Tokens of source a prompt may hold when the model takes `input_limit`
+/// tokens and the rest of the prompt needs `fixed`. Later rounds add the
+/// previous bindings and their critique, which take about as much again as
+/// the sources, so those get half of what is left.
+pub(crate) fn budget(input_limit: usize, fixed: &str) -> usize {
+    input_limit.saturating_sub(estimate_tokens(fixed)) / 2
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Priority {
+    /// Declares what other languages link against.
+    Exports,
+    Header,
+    Implementation,
+}
+
+fn priority(path: &Path, contents: &str) -> Priority {
+    const EXPORTS: [&str; 5] = [
+        "export ",
+        "pub extern ",
+        "#[no_mangle]",
+        "#[unsafe(no_mangle)]",
+        "@_cdecl",
+    ];
+    if EXPORTS.iter().any(|marker| contents.contains(marker)) {
+        Priority::Exports
+    } else if is_header(path) {
+        Priority::Header
+    } else {
+        Priority::Implementation
+    }
+}
+
+fn is_header(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("h" | "hh" | "hpp" | "hxx")
+    )
+}
+
+/// `contents` with the body of every function replaced by `{ ... }`, so
+/// types and declarations survive. Braces in strings and comments are
+/// skipped, anything more exotic is taken at face value.
+fn signatures(contents: &str, path: &Path, language: Language) -> String {
+    let keyword = match language {
+        Language::Swift => "func",
+        Language::Rust | Language::Zig => "fn",
+    };
+    // A C declaration ends its parameter list right before the body
+    let is_function = |header: &str| {
+        let header = header.trim_end();
+        header
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .any(|word| word == keyword)
+            || (is_header(path) && header.ends_with(')'))
+    };
+
+    let mut out = String::with_capacity(contents.len());
+    // Start of the declaration the next `{` belongs to
+    let mut header = 0;
+    let mut chars = contents.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '/' if chars.peek().is_some_and(|&(_, next)| next == '/') => {
+                let end = contents[i..].find('\n').map_or(contents.len(), |n| i + n);
+                out.push_str(&contents[i..end]);
+                while chars.next_if(|&(j, _)| j < end).is_some() {}
+                header = end;
+                continue;
+            }
+            '"' => {
+                let end = string_end(contents, i);
+                out.push_str(&contents[i..end]);
+                while chars.next_if(|&(j, _)| j < end).is_some() {}
+                continue;
+            }
+            '{' if is_function(&contents[header..i]) => {
+                let end = block_end(contents, i);
+                out.push_str("{ ... }");
+                while chars.next_if(|&(j, _)| j < end).is_some() {}
+                header = end;
+                continue;
+            }
+            '{' | '}' | ';' => header = i + 1,
+            _ => {}
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Index just past the string literal opening at `start`.
+fn string_end(text: &str, start: usize) -> usize {
+    let mut escaped = false;
+    for (i, c) in text[start + 1..].char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return start + 1 + i + 1,
+            '\n' => return start + 1 + i,
+            _ => escaped = false,
+        }
+    }
+    text.len()
+}
+
+/// Index just past the `}` closing the block opened at `start`, or the end
+/// of `text` if it is never closed.
+fn block_end(text: &str, start: usize) -> usize {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                i += bytes[i..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .unwrap_or(bytes.len() - i);
+                continue;
+            }
+            b'"' => {
+                i = string_end(text, i);
+                continue;
+            }
+            b'{' => depth += 1,
+            b'}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    text.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 100 sources: every tenth exports a function, every fifth after that
+    /// is a header and the rest are implementation. All are the same size,
+    /// most of it the function body.
+    fn tree() -> Vec<(PathBuf, String)> {
+        let body = format!("{{\n    return {}0;\n}}\n", "a + ".repeat(36));
+        (0..100)
+            .rev()
+            .map(|i| {
+                let (path, decl) = match i {
+                    i if i % 10 == 0 => (
+                        format!("src/api{i:02}.zig"),
+                        format!("export fn sum{i:02}(a: i32) i32"),
+                    ),
+                    i if i % 5 == 0 => (
+                        format!("include/api{i:02}.h"),
+                        format!("static int sum{i:02}(int a)"),
+                    ),
+                    _ => (
+                        format!("src/impl{i:02}.zig"),
+                        format!("pub fn sum{i:02}(a: i32) i32"),
+                    ),
+                };
+                (
+                    PathBuf::from(path),
+                    format!("// source {i:02}\n{decl:<32} {body}"),
+                )
+            })
+            .collect()
+    }
+
+    fn paths(packed: &PackedSources, provenance: Provenance) -> Vec<String> {
+        let files = packed.files.iter().filter(|file| file.provenance == provenance);
+        files.map(|file| file.path.display().to_string()).collect()
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let packed = PackedSources::pack(tree(), None, Language::Zig);
+        assert!(packed.is_complete());
+        assert_eq!(packed.omissions(), "");
+        assert_eq!(packed.files.len(), 100);
+
+        // Exports, then headers, then implementation, each in the order given
+        let order = packed
+            .sources()
+            .iter()
+            .map(|(path, _)| path.display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(order[..3], ["src/api90.zig", "src/api80.zig", "src/api70.zig"]);
+        assert_eq!(order[10..12], ["include/api95.h", "include/api85.h"]);
+        assert_eq!(order[20..22], ["src/impl99.zig", "src/impl98.zig"]);
+    }
+
+    #[test]
+    fn test_budgets() {
+        let tokens = |packed: &PackedSources| {
+            packed
+                .files
+                .iter()
+                .map(|file| estimate_tokens(&file.contents))
+                .sum::<usize>()
+        };
+        let whole = estimate_tokens(&tree()[0].1);
+
+        // Room for the exports and headers whole, the rest is cut or dropped
+        let packed = PackedSources::pack(tree(), Some(whole * 25 + whole / 2), Language::Zig);
+        assert!(tokens(&packed) <= whole * 25 + whole / 2);
+        let kept = paths(&packed, Provenance::Whole);
+        assert_eq!(kept.len(), 25);
+        assert!(kept[..20].iter().all(|path| !path.contains("impl")), "{kept:?}");
+        let cut = paths(&packed, Provenance::Signatures);
+        assert!(!cut.is_empty() && !packed.omitted.is_empty());
+        assert!(cut.iter().all(|path| path.contains("impl")));
+        assert_eq!(packed.files.len() + packed.omitted.len(), 100);
+        let omissions = packed.omissions();
+        assert!(
+            omissions.contains(&format!("- {}: signatures only", cut[0])),
+            "{omissions}"
+        );
+        assert!(omissions.ends_with(&format!(
+            "- {}: left out\n",
+            packed.omitted.last().unwrap().display()
+        )));
+
+        // Tighter budgets drop more, and never a file that exports
+        let mut previous = 0;
+        for budget in [whole * 10, whole * 5, whole, 0] {
+            let packed = PackedSources::pack(tree(), Some(budget), Language::Zig);
+            assert!(packed.omitted.len() >= previous);
+            previous = packed.omitted.len();
+            let kept = packed.sources();
+            for i in (0..100).step_by(10) {
+                assert!(
+                    kept.iter()
+                        .any(|(path, _)| path.ends_with(format!("api{i:02}.zig")))
+                );
+            }
+        }
+        assert_eq!(previous, 90);
+    }
+
+    #[test]
+    fn test_signatures() {
+        let zig = "const S = struct {\n    x: i32,\n    pub fn get(self: S) i32 {\n        return self.x;\n    }\n};\n\
+                   // fn in a comment {\n\
+                   pub fn name() []const u8 {\n    return \"}\";\n}\n";
+        assert_eq!(
+            signatures(zig, Path::new("s.zig"), Language::Zig),
+            "const S = struct {\n    x: i32,\n    pub fn get(self: S) i32 { ... }\n};\n\
+             // fn in a comment {\n\
+             pub fn name() []const u8 { ... }\n"
+        );
+
+        let swift = "struct P {\n    var x: Int\n    func twice() -> Int { x * 2 }\n}\n";
+        assert_eq!(
+            signatures(swift, Path::new("p.swift"), Language::Swift),
+            "struct P {\n    var x: Int\n    func twice() -> Int { ... }\n}\n"
+        );
+
+        let header = "typedef struct { int x; } point;\nstatic inline int get(point p) { return p.x; }\n";
+        assert_eq!(
+            signatures(header, Path::new("point.h"), Language::Zig),
+            "typedef struct { int x; } point;\nstatic inline int get(point p) { ... }\n"
+        );
+    }
+}
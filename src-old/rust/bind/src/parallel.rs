@@ -0,0 +1,354 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Sender},
+    },
+    thread,
+};
+
+use crate::{
+    BindError, BindFailure, Model, Prompter,
+    codeblocks::{self, CodeBlock},
+    progress::{Event, ProgressSink},
+    template::Prompts,
+};
+
+type Source = (PathBuf, String);
+
+/// Reads a dependency hint file. Each line names a source and, after a colon,
+/// the sources it needs in the same prompt, relative to `root`:
+///
+/// ```text
+/// # Shapes are built from points
+/// shape.zig: point.zig
+/// ```
+pub(crate) fn read_hints(path: &Path, root: &Path) -> io::Result<Vec<(PathBuf, Vec<PathBuf>)>> {
+    let text = fs::read_to_string(path)?;
+    let hints = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (source, deps) = line.split_once(':').unwrap_or((line, ""));
+            let deps = deps.split_whitespace().map(|dep| root.join(dep)).collect();
+            (root.join(source.trim()), deps)
+        })
+        .collect();
+    Ok(hints)
+}
+
+/// Splits `sources` into prompt units, every source on its own except those
+/// tied together by `hints`. Hints naming sources not in `sources` are
+/// ignored. Units keep the order of their first source.
+pub(crate) fn units(sources: Vec<Source>, hints: &[(PathBuf, Vec<PathBuf>)]) -> Vec<Vec<Source>> {
+    let index: BTreeMap<&Path, usize> = sources
+        .iter()
+        .enumerate()
+        .map(|(i, (path, _))| (path.as_path(), i))
+        .collect();
+    let mut parent: Vec<usize> = (0..sources.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for (source, deps) in hints {
+        let Some(&a) = index.get(source.as_path()) else {
+            continue;
+        };
+        for dep in deps {
+            if let Some(&b) = index.get(dep.as_path()) {
+                let (a, b) = (root(&mut parent, a), root(&mut parent, b));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut units: Vec<Vec<Source>> = vec![];
+    let mut slots = BTreeMap::new();
+    for (i, source) in sources.into_iter().enumerate() {
+        let slot = *slots.entry(root(&mut parent, i)).or_insert_with(|| {
+            units.push(vec![]);
+            units.len() - 1
+        });
+        units[slot].push(source);
+    }
+    units
+}
+
+/// Forwards the events of a worker to the thread that started it.
+struct ChannelSink(Sender<Event>);
+
+impl ProgressSink for ChannelSink {
+    fn event(&self, event: &Event) {
+        let _ = self.0.send(event.clone());
+    }
+}
+
+/// Runs `generate` over every unit on up to `concurrency` threads and merges
+/// the bindings. Models hold no state that can cross threads, so every worker
+/// builds its own with `model` and prompts with `prompts`. Events reach
+/// `progress` in the order they happen, interleaved between workers.
+///
+/// Fails with the first unit that did not pass the critique loop, or when two
+/// units wrote the same path.
+pub(crate) fn generate(
+    units: &[Vec<Source>],
+    concurrency: usize,
+    model: &(dyn Fn() -> Rc<dyn Model> + Sync),
+    prompts: &Arc<Prompts>,
+    progress: &dyn ProgressSink,
+    generate: impl Fn(&Prompter<dyn Model>, &[Source]) -> Result<String, BindFailure> + Sync,
+) -> Result<String, BindError> {
+    let next = AtomicUsize::new(0);
+    let (sender, events) = mpsc::channel();
+    let mut results: Vec<(usize, Result<String, BindFailure>)> = thread::scope(|scope| {
+        let (next, generate) = (&next, &generate);
+        let workers: Vec<_> = (0..concurrency.clamp(1, units.len().max(1)))
+            .map(|_| {
+                let sender = sender.clone();
+                scope.spawn(move || {
+                    let prompter = Prompter::from_model(model(), Rc::new(ChannelSink(sender)))
+                        .with_prompts(prompts.clone());
+                    let mut done = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(unit) = units.get(i) else {
+                            break done;
+                        };
+                        done.push((i, generate(&prompter, unit)));
+                    }
+                })
+            })
+            .collect();
+        drop(sender);
+        for event in events {
+            progress.event(&event);
+        }
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+    results.sort_by_key(|(i, _)| *i);
+
+    let mut owners: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    let mut merged = String::new();
+    for (i, bindings) in results {
+        for block in codeblocks::parse_blocks(&bindings?) {
+            if let Some(path) = &block.declared_path {
+                let key =
+                    codeblocks::sanitize(path, Path::new("/")).unwrap_or_else(|| path.clone());
+                match owners.get(&key) {
+                    Some(&other) if other != i => {
+                        return Err(BindError::Conflict {
+                            path: key,
+                            units: [other, i].map(|unit| {
+                                units[unit].iter().map(|(path, _)| path.clone()).collect()
+                            }),
+                        });
+                    }
+                    _ => owners.insert(key, i),
+                };
+            }
+            merged.push_str(&render(&block));
+        }
+    }
+    Ok(merged)
+}
+
+/// Writes `block` back out as markdown, with its path in the info string.
+fn render(block: &CodeBlock) -> String {
+    let longest = block
+        .body
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    let path = block
+        .declared_path
+        .as_ref()
+        .map(|path| format!(" {}", path.display()))
+        .unwrap_or_default();
+    format!(
+        "{fence}{}{path}\n{}\n{fence}\n\n",
+        block.lang,
+        block.body.trim_end_matches('\n')
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::Pin,
+        sync::Mutex,
+        time::{Duration, Instant},
+    };
+
+    use gemini::GeminiError;
+
+    use super::*;
+    use crate::{CritiqueSettings, Language, NullSink, ResponseCoroutine, pack::PackedSources};
+
+    /// Binds every `.zig` source named in a prompt to `src/` under the name
+    /// `rename` gives it, taking a while and logging when.
+    struct Slow {
+        rename: fn(&str) -> String,
+        log: Arc<Mutex<Vec<(Instant, Instant)>>>,
+    }
+
+    impl Model for Slow {
+        fn respond(&self, prompt: String) -> Pin<Box<dyn ResponseCoroutine + '_>> {
+            let reply = match prompt.contains("Output a number") {
+                true => "95".to_owned(),
+                false => {
+                    let started = Instant::now();
+                    thread::sleep(Duration::from_millis(200));
+                    self.log.lock().unwrap().push((started, Instant::now()));
+                    let abi = prompt.split("# C-abi input").nth(1).unwrap_or_default();
+                    ["a.zig", "b.zig", "c.zig"]
+                        .into_iter()
+                        .filter(|name| abi.contains(name))
+                        .map(|name| {
+                            format!(
+                                "```zig\n// src/{}\npub const x = 1;\n```\n",
+                                (self.rename)(name)
+                            )
+                        })
+                        .collect()
+                }
+            };
+            Box::pin(
+                #[coroutine]
+                static move || {
+                    yield Ok(reply);
+                    Ok(())
+                },
+            )
+        }
+        fn respond_json(&self, _: String) -> Result<serde_json::Value, GeminiError> {
+            Err(GeminiError::HttpError("Slow replies are not JSON".to_owned()))
+        }
+        fn change(&self, _: f32) {}
+        fn temp(&self) -> f32 {
+            0.5
+        }
+    }
+
+    fn sources(names: &[&str]) -> Vec<Source> {
+        names
+            .iter()
+            .map(|name| (PathBuf::from("/zig").join(name), String::new()))
+            .collect()
+    }
+
+    fn run(
+        units: &[Vec<Source>],
+        rename: fn(&str) -> String,
+    ) -> (Result<String, BindError>, Vec<(Instant, Instant)>) {
+        let log = Arc::new(Mutex::new(vec![]));
+        let model = || -> Rc<dyn Model> {
+            Rc::new(Slow {
+                rename,
+                log: log.clone(),
+            })
+        };
+        let result = generate(units, 3, &model, &Arc::default(), &NullSink, |prompter, sources| {
+            prompter.generate_bindings(
+                &PackedSources::pack(sources.to_vec(), None, Language::Zig),
+                "",
+                "",
+                &Language::Zig,
+                &Language::Zig,
+                &CritiqueSettings::default(),
+            )
+        });
+        let log = log.lock().unwrap().clone();
+        (result, log)
+    }
+
+    #[test]
+    fn test_units() {
+        let root = Path::new("/zig");
+        let hint_file = std::env::temp_dir().join(format!("bind-deps-{}", std::process::id()));
+        fs::write(
+            &hint_file,
+            "# shapes are made of points\nshape.zig: point.zig\n\nmissing.zig: math.zig\n",
+        )
+        .unwrap();
+        let hints_read = read_hints(&hint_file, root).unwrap();
+        fs::remove_file(&hint_file).unwrap();
+        assert_eq!(
+            hints_read,
+            [
+                (root.join("shape.zig"), vec![root.join("point.zig")]),
+                (root.join("missing.zig"), vec![root.join("math.zig")]),
+            ]
+        );
+
+        let units = units(
+            sources(&["point.zig", "math.zig", "shape.zig"]),
+            &hints_read,
+        );
+        let names: Vec<Vec<_>> = units
+            .iter()
+            .map(|unit| {
+                unit.iter()
+                    .map(|(path, _)| path.file_name().unwrap().to_str().unwrap())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(names, [vec!["point.zig", "shape.zig"], vec!["math.zig"]]);
+    }
+
+    #[test]
+    fn test_concurrent_units() {
+        let units = [
+            sources(&["a.zig"]),
+            sources(&["b.zig"]),
+            sources(&["c.zig"]),
+        ];
+        let (result, log) = run(&units, str::to_owned);
+
+        let merged = result.unwrap();
+        for name in ["a.zig", "b.zig", "c.zig"] {
+            assert!(
+                merged.contains(&format!("```zig src/{name}\npub const x = 1;\n```")),
+                "{merged}"
+            );
+        }
+        // Every unit started before any finished
+        assert_eq!(log.len(), 3);
+        let last_start = log.iter().map(|(start, _)| *start).max().unwrap();
+        let first_end = log.iter().map(|(_, end)| *end).min().unwrap();
+        assert!(last_start < first_end, "{log:?}");
+    }
+
+    #[test]
+    fn test_conflicting_units() {
+        let units = [sources(&["a.zig"]), sources(&["b.zig"])];
+        let (result, _) = run(&units, |_| "root.zig".to_owned());
+
+        let err = result.unwrap_err();
+        assert!(
+            matches!(&err, BindError::Conflict { path, .. } if path == Path::new("src/root.zig")),
+            "{err:?}"
+        );
+        assert_eq!(
+            err.to_string(),
+            "src/root.zig was generated both from /zig/a.zig and from /zig/b.zig"
+        );
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    fmt,
+    io::{ErrorKind, Write},
+    path::{Component, Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use crate::{Language, ProgressSink};
+
+/// A file produced from the model output, relative to the output crate root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeneratedFile {
+    pub path: PathBuf,
+    pub contents: String,
+}
+
+impl GeneratedFile {
+    /// Accepts `path` only if it stays inside the output root: no absolute
+    /// paths, no `..`, and `./` prefixes dropped.
+    pub fn new(path: impl AsRef<Path>, contents: String) -> Option<Self> {
+        let mut clean = PathBuf::new();
+        for component in path.as_ref().components() {
+            match component {
+                Component::Normal(part) => clean.push(part),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+        (!clean.as_os_str().is_empty()).then_some(Self {
+            path: clean,
+            contents,
+        })
+    }
+}
+
+/// What a post processor knows about the run it is part of.
+pub struct PostContext<'a> {
+    pub language: Language,
+    pub output_root: &'a Path,
+    pub attempt: usize,
+    pub started: SystemTime,
+    pub progress: &'a dyn ProgressSink,
+}
+
+#[derive(Debug, Clone)]
+pub struct PostError {
+    pub file: PathBuf,
+    pub message: String,
+}
+
+impl PostError {
+    pub fn new(file: &Path, message: impl Into<String>) -> Self {
+        Self {
+            file: file.to_owned(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for PostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "post processing {} failed: {}", self.file.display(), self.message)
+    }
+}
+
+impl std::error::Error for PostError {}
+
+/// Final touch on generated files, run after parsing and before writing.
+pub trait PostProcessor {
+    fn process(&self, file: &mut GeneratedFile, ctx: &PostContext) -> Result<(), PostError>;
+}
+
+/// Runs every processor over every file in order, stopping at the first failure.
+pub fn run(
+    processors: &[Arc<dyn PostProcessor>],
+    files: &mut [GeneratedFile],
+    ctx: &PostContext,
+) -> Result<(), PostError> {
+    for processor in processors {
+        for file in files.iter_mut() {
+            processor.process(file, ctx)?;
+        }
+    }
+    Ok(())
+}
+
+/// Formats Rust files through `rustfmt` on stdin/stdout. Skipped when the
+/// binary is not installed.
+pub struct Rustfmt {
+    pub binary: PathBuf,
+}
+
+impl Default for Rustfmt {
+    fn default() -> Self {
+        Self {
+            binary: "rustfmt".into(),
+        }
+    }
+}
+
+impl PostProcessor for Rustfmt {
+    fn process(&self, file: &mut GeneratedFile, ctx: &PostContext) -> Result<(), PostError> {
+        if !matches!(ctx.language, Language::Rust)
+            || file.path.extension().is_none_or(|ext| ext != "rs")
+        {
+            return Ok(());
+        }
+
+        let child = Command::new(&self.binary)
+            .args(["--edition", "2024", "--emit", "stdout"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(PostError::new(&file.path, e.to_string())),
+        };
+
+        let written = child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(file.contents.as_bytes());
+        let out = child
+            .wait_with_output()
+            .map_err(|e| PostError::new(&file.path, e.to_string()))?;
+        written.map_err(|e| PostError::new(&file.path, e.to_string()))?;
+
+        if !out.status.success() {
+            return Err(PostError::new(
+                &file.path,
+                String::from_utf8_lossy(&out.stderr).to_string(),
+            ));
+        }
+        file.contents = String::from_utf8_lossy(&out.stdout).to_string();
+        Ok(())
+    }
+}
+
+/// Prepends `text` as a line comment block, once.
+pub struct HeaderBanner {
+    pub text: String,
+}
+
+impl HeaderBanner {
+    fn banner(&self) -> String {
+        self.text
+            .lines()
+            .map(|line| format!("// {line}").trim_end().to_owned() + "\n")
+            .collect()
+    }
+}
+
+impl PostProcessor for HeaderBanner {
+    fn process(&self, file: &mut GeneratedFile, _: &PostContext) -> Result<(), PostError> {
+        let banner = self.banner();
+        if !file.contents.starts_with(&banner) {
+            file.contents.insert_str(0, &banner);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(root: &Path) -> PostContext<'_> {
+        PostContext {
+            language: Language::Rust,
+            output_root: root,
+            attempt: 1,
+            started: SystemTime::now(),
+            progress: &crate::NullSink,
+        }
+    }
+
+    fn file(contents: &str) -> GeneratedFile {
+        GeneratedFile::new("src/lib.rs", contents.to_owned()).unwrap()
+    }
+
+    struct Append(&'static str);
+
+    impl PostProcessor for Append {
+        fn process(&self, file: &mut GeneratedFile, _: &PostContext) -> Result<(), PostError> {
+            file.contents.push_str(self.0);
+            Ok(())
+        }
+    }
+
+    struct Fail;
+
+    impl PostProcessor for Fail {
+        fn process(&self, file: &mut GeneratedFile, _: &PostContext) -> Result<(), PostError> {
+            Err(PostError::new(&file.path, "rejected"))
+        }
+    }
+
+    #[test]
+    fn test_sanitized_paths() {
+        assert_eq!(
+            GeneratedFile::new("./src/lib.rs", String::new()).unwrap().path,
+            PathBuf::from("src/lib.rs")
+        );
+        assert!(GeneratedFile::new("/etc/passwd", String::new()).is_none());
+        assert!(GeneratedFile::new("src/../../escape.rs", String::new()).is_none());
+        assert!(GeneratedFile::new("", String::new()).is_none());
+    }
+
+    #[test]
+    fn test_processor_order() {
+        let root = PathBuf::from("out");
+        let processors: Vec<Arc<dyn PostProcessor>> = vec![Arc::new(Append("a")), Arc::new(Append("b"))];
+        let mut files = vec![file(""), file("x")];
+        run(&processors, &mut files, &ctx(&root)).unwrap();
+        assert_eq!(files[0].contents, "ab");
+        assert_eq!(files[1].contents, "xab");
+    }
+
+    #[test]
+    fn test_rustfmt_missing() {
+        let root = PathBuf::from("out");
+        let rustfmt = Rustfmt {
+            binary: "rustfmt-that-is-not-installed".into(),
+        };
+        let mut generated = file("fn  main( ){}");
+        rustfmt.process(&mut generated, &ctx(&root)).unwrap();
+        assert_eq!(generated.contents, "fn  main( ){}");
+    }
+
+    #[test]
+    fn test_banner_idempotent() {
+        let root = PathBuf::from("out");
+        let banner = HeaderBanner {
+            text: "Generated by bind.\nDo not edit.".to_owned(),
+        };
+        let mut generated = file("pub fn f() {}\n");
+        banner.process(&mut generated, &ctx(&root)).unwrap();
+        banner.process(&mut generated, &ctx(&root)).unwrap();
+        assert_eq!(
+            generated.contents,
+            "// Generated by bind.\n// Do not edit.\npub fn f() {}\n"
+        );
+    }
+
+    #[test]
+    fn test_error_names_file() {
+        let root = PathBuf::from("out");
+        let processors: Vec<Arc<dyn PostProcessor>> = vec![Arc::new(Fail), Arc::new(Append("never"))];
+        let mut files = vec![file("")];
+        let err = run(&processors, &mut files, &ctx(&root)).unwrap_err();
+        assert_eq!(err.file, PathBuf::from("src/lib.rs"));
+        assert!(err.to_string().contains("src/lib.rs"));
+        assert_eq!(files[0].contents, "");
+    }
+}
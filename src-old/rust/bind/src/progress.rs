@@ -0,0 +1,165 @@
+use std::{
+    cell::{Cell, RefCell},
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// What a binding run is doing, for a log or a UI to follow.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A prompt went to the model, its size estimated by [`estimate_tokens`].
+    PromptSent { tokens: usize },
+    /// Generated text as it streams in.
+    Delta { text: String },
+    EvaluationScore { score: u32, threshold: u32 },
+    Critique { text: String },
+    TemperatureChanged { from: f32, to: f32 },
+    CompileAttempt { attempt: usize, result: AttemptResult },
+    FileWritten { path: PathBuf },
+    Note { message: String },
+    Warning { message: String },
+    /// The bindings compiled and the run is over.
+    Completed { attempts: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum AttemptResult {
+    Compiled,
+    PostProcessFailed { error: String },
+    CompileFailed { errors: String },
+}
+
+impl Event {
+    pub fn note(message: impl Into<String>) -> Self {
+        Self::Note {
+            message: message.into(),
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::Warning {
+            message: message.into(),
+        }
+    }
+}
+
+/// Receives the events of a binding run.
+pub trait ProgressSink {
+    fn event(&self, event: &Event);
+}
+
+/// Discards every event.
+pub struct NullSink;
+
+impl ProgressSink for NullSink {
+    fn event(&self, _: &Event) {}
+}
+
+/// A rough token count for `text`, at four bytes per token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.len().div_ceil(4)
+}
+
+/// Streams generated text as it arrives and gives every other event a line of
+/// its own. Warnings are raised as cargo warnings, so they still show up when
+/// binding from a build script.
+#[derive(Default)]
+pub struct StdoutSink {
+    mid_line: Cell<bool>,
+}
+
+impl ProgressSink for StdoutSink {
+    fn event(&self, event: &Event) {
+        if let Event::Delta { text } = event {
+            print!("{text}");
+            let _ = io::stdout().flush();
+            self.mid_line.set(!text.ends_with('\n'));
+            return;
+        }
+        if self.mid_line.replace(false) {
+            println!();
+        }
+        match event {
+            Event::PromptSent { tokens } => println!("Sent a prompt of ~{tokens} tokens"),
+            Event::Delta { .. } => unreachable!(),
+            Event::EvaluationScore { score, threshold } => {
+                println!("Scored {score}/100, {threshold} needed")
+            }
+            Event::Critique { text } => println!("Critique:\n{}", text.trim_end()),
+            Event::TemperatureChanged { from, to } => println!("Temperature {from} -> {to}"),
+            Event::CompileAttempt { attempt, result } => match result {
+                AttemptResult::Compiled => println!("Attempt {attempt} compiled"),
+                AttemptResult::PostProcessFailed { error } => {
+                    println!("Attempt {attempt} failed post processing: {error}")
+                }
+                AttemptResult::CompileFailed { errors } => {
+                    println!("Attempt {attempt} failed to compile:\n{}", errors.trim_end())
+                }
+            },
+            Event::FileWritten { path } => println!("Wrote {}", path.display()),
+            Event::Note { message } => println!("{message}"),
+            Event::Warning { message } => println!("cargo::warning={message}"),
+            Event::Completed { attempts } => println!("Bindings done after {attempts} attempt(s)"),
+        }
+    }
+}
+
+/// Writes every event as a line of JSON, e.g.
+/// `{"event":"evaluation_score","score":92,"threshold":85}`.
+pub struct JsonLinesSink {
+    file: RefCell<File>,
+}
+
+impl JsonLinesSink {
+    /// Truncates `path` if it exists.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self {
+            file: RefCell::new(File::create(path)?),
+        })
+    }
+}
+
+impl ProgressSink for JsonLinesSink {
+    fn event(&self, event: &Event) {
+        let mut line = serde_json::to_string(event).expect("events serialize");
+        line.push('\n');
+        // Progress is best effort and never fails the run
+        let _ = self.file.borrow_mut().write_all(line.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_lines() {
+        let path = std::env::temp_dir().join(format!("bind-progress-{}.jsonl", std::process::id()));
+        let sink = JsonLinesSink::create(&path).unwrap();
+        sink.event(&Event::EvaluationScore {
+            score: 92,
+            threshold: 85,
+        });
+        sink.event(&Event::CompileAttempt {
+            attempt: 1,
+            result: AttemptResult::CompileFailed {
+                errors: "error: expected ';'".to_owned(),
+            },
+        });
+        sink.event(&Event::Completed { attempts: 2 });
+        drop(sink);
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\"event\":\"evaluation_score\",\"score\":92,\"threshold\":85}\n\
+             {\"event\":\"compile_attempt\",\"attempt\":1,\"result\":{\"status\":\"compile_failed\",\"errors\":\"error: expected ';'\"}}\n\
+             {\"event\":\"completed\",\"attempts\":2}\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    pin::Pin,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use gemini::{GeminiError, ModelUsage, RetryEvent};
+
+use crate::{Model, ResponseCoroutine};
+
+/// Requests shared by every generation loop of a run: up to `capacity` in a
+/// burst, then as many as the bucket refills.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    /// Tokens left and when they were counted.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: u32, per_second: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        Self {
+            capacity,
+            per_second,
+            state: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// A quota of `requests` a minute.
+    pub fn per_minute(requests: u32) -> Self {
+        Self::new(requests, requests as f64 / 60.0)
+    }
+
+    /// Blocks until a request may be sent.
+    pub fn acquire(&self) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let (tokens, counted) = &mut *state;
+                let now = Instant::now();
+                *tokens = (*tokens + now.duration_since(*counted).as_secs_f64() * self.per_second)
+                    .min(self.capacity);
+                *counted = now;
+                if *tokens >= 1.0 {
+                    *tokens -= 1.0;
+                    return;
+                }
+                Duration::from_secs_f64((1.0 - *tokens) / self.per_second)
+            };
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Takes a token from `bucket` before every request to `model`.
+pub(crate) struct RateLimited {
+    model: Rc<dyn Model>,
+    bucket: Arc<TokenBucket>,
+}
+
+impl RateLimited {
+    pub(crate) fn wrap(model: Rc<dyn Model>, bucket: Arc<TokenBucket>) -> Rc<dyn Model> {
+        Rc::new(Self { model, bucket })
+    }
+}
+
+impl Model for RateLimited {
+    fn respond(&self, prompt: String) -> Pin<Box<dyn ResponseCoroutine + '_>> {
+        self.bucket.acquire();
+        self.model.respond(prompt)
+    }
+
+    fn respond_json(&self, prompt: String) -> Result<serde_json::Value, GeminiError> {
+        self.bucket.acquire();
+        self.model.respond_json(prompt)
+    }
+
+    fn change(&self, temp: f32) {
+        self.model.change(temp)
+    }
+
+    fn temp(&self) -> f32 {
+        self.model.temp()
+    }
+
+    fn usage(&self) -> Option<ModelUsage> {
+        self.model.usage()
+    }
+
+    fn input_limit(&self) -> Option<usize> {
+        self.model.input_limit()
+    }
+
+    fn take_retries(&self) -> Vec<RetryEvent> {
+        self.model.take_retries()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = Arc::new(TokenBucket::new(2, 20.0));
+        let started = Instant::now();
+        // Two in the burst, then one every 50ms, shared between threads
+        thread::scope(|scope| {
+            for _ in 0..2 {
+                let bucket = bucket.clone();
+                scope.spawn(move || {
+                    for _ in 0..2 {
+                        bucket.acquire();
+                    }
+                });
+            }
+        });
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(90), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+    }
+}
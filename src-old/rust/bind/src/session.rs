@@ -0,0 +1,257 @@
+use std::{
+    cell::RefCell,
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{AttemptResult, BindError, Event, ProgressSink};
+
+/// Everything a binding run has produced so far, saved after every model
+/// round and compile attempt so an interrupted run can pick up where it
+/// stopped, see [`crate::resume`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    /// Hash of the sources and guidelines the session binds, once known.
+    pub inputs: Option<String>,
+    /// Every critique round so far, across compile attempts.
+    pub rounds: Vec<Round>,
+    /// Every finished compile attempt, in order.
+    pub attempts: Vec<AttemptResult>,
+    /// What the next attempt is prompted with, from the last failed one.
+    pub feedback: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Round {
+    /// The compile attempt the round generated for, from 1.
+    pub attempt: usize,
+    pub temperature: f32,
+    pub bindings: String,
+    pub score: u32,
+    /// Missing when the round passed or was the last one.
+    pub critique: Option<String>,
+    /// Temperature the next round runs at.
+    pub next_temperature: f32,
+}
+
+impl Session {
+    const DIR: &str = ".bind-session";
+
+    pub fn path(target: &Path, id: &str) -> PathBuf {
+        target.join(Self::DIR).join(format!("{id}.json"))
+    }
+
+    pub fn load(target: &Path, id: &str) -> Result<Self, BindError> {
+        let failed = |message: String| BindError::Session {
+            id: id.to_owned(),
+            message,
+        };
+        let text = fs::read_to_string(Self::path(target, id)).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => failed("there is no such session".to_owned()),
+            _ => failed(e.to_string()),
+        })?;
+        serde_json::from_str(&text).map_err(|e| failed(format!("the session is corrupt: {e}")))
+    }
+
+    /// Replaces the saved session at once, so an interrupted write leaves the
+    /// previous one intact.
+    fn save(&self, target: &Path) -> io::Result<()> {
+        let path = Self::path(target, &self.id);
+        fs::create_dir_all(path.parent().unwrap())?;
+        let partial = path.with_extension("json.tmp");
+        fs::write(&partial, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&partial, &path)
+    }
+
+    /// Removes all but the `keep` newest sessions in `target`.
+    fn collect_garbage(target: &Path, keep: usize) -> io::Result<()> {
+        let dir = target.join(Self::DIR);
+        let mut sessions = match fs::read_dir(&dir) {
+            Ok(entries) => entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .filter(|path| {
+                    path.as_ref()
+                        .is_ok_and(|path| path.extension().is_some_and(|ext| ext == "json"))
+                })
+                .collect::<io::Result<Vec<_>>>()?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        // Ids start with the time they were made at, so names sort by age
+        sessions.sort();
+        let stale = sessions.len().saturating_sub(keep);
+        sessions.drain(..stale).try_for_each(fs::remove_file)
+    }
+}
+
+/// The session of a run in progress, saved on every change.
+pub(crate) struct SessionLog {
+    target: PathBuf,
+    session: RefCell<Session>,
+    progress: Rc<dyn ProgressSink>,
+}
+
+impl SessionLog {
+    /// Starts a session in `target`, making room for it among the `keep`
+    /// kept there.
+    pub(crate) fn start(target: &Path, keep: usize, progress: Rc<dyn ProgressSink>) -> Self {
+        if let Err(e) = Session::collect_garbage(target, keep.saturating_sub(1)) {
+            progress.event(&Event::warning(format!(
+                "Could not remove old sessions: {e}"
+            )));
+        }
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let id = format!("{millis:013}-{}", process::id());
+        progress.event(&Event::note(format!("Recording session {id}")));
+        let log = Self {
+            target: target.to_owned(),
+            session: RefCell::new(Session {
+                id,
+                ..Default::default()
+            }),
+            progress,
+        };
+        log.save();
+        log
+    }
+
+    pub(crate) fn resume(
+        target: &Path,
+        id: &str,
+        progress: Rc<dyn ProgressSink>,
+    ) -> Result<Self, BindError> {
+        let session = Session::load(target, id)?;
+        progress.event(&Event::note(format!(
+            "Resuming session {id} after {} round(s) and {} attempt(s)",
+            session.rounds.len(),
+            session.attempts.len()
+        )));
+        Ok(Self {
+            target: target.to_owned(),
+            session: RefCell::new(session),
+            progress,
+        })
+    }
+
+    /// Records what the session binds, or refuses if it was started for
+    /// something else.
+    pub(crate) fn check_inputs(&self, hash: u64) -> Result<(), BindError> {
+        let hash = format!("{hash:016x}");
+        let mut session = self.session.borrow_mut();
+        match &session.inputs {
+            Some(inputs) if *inputs != hash => Err(BindError::Session {
+                id: session.id.clone(),
+                message: "the sources or guidelines changed since it was started, start a new session instead"
+                    .to_owned(),
+            }),
+            Some(_) => Ok(()),
+            None => {
+                session.inputs = Some(hash);
+                drop(session);
+                self.save();
+                Ok(())
+            }
+        }
+    }
+
+    /// Attempts finished so far and the feedback for the next one.
+    pub(crate) fn attempts(&self) -> (usize, Option<String>) {
+        let session = self.session.borrow();
+        (session.attempts.len(), session.feedback.clone())
+    }
+
+    /// Rounds generated for the attempt in progress.
+    pub(crate) fn rounds(&self) -> Vec<Round> {
+        let session = self.session.borrow();
+        let attempt = session.attempts.len() + 1;
+        session
+            .rounds
+            .iter()
+            .filter(|round| round.attempt == attempt)
+            .cloned()
+            .collect()
+    }
+
+    /// `round` is numbered with the attempt in progress.
+    pub(crate) fn round(&self, round: Round) {
+        let mut session = self.session.borrow_mut();
+        let attempt = session.attempts.len() + 1;
+        session.rounds.push(Round { attempt, ..round });
+        drop(session);
+        self.save();
+    }
+
+    pub(crate) fn attempt(&self, result: AttemptResult, feedback: Option<String>) {
+        let mut session = self.session.borrow_mut();
+        session.attempts.push(result);
+        session.feedback = feedback;
+        drop(session);
+        self.save();
+    }
+
+    /// Drops the session once the bindings compiled, there is nothing left to
+    /// resume.
+    pub(crate) fn finish(&self) {
+        let path = Session::path(&self.target, &self.session.borrow().id);
+        if let Err(e) = fs::remove_file(path) {
+            self.progress.event(&Event::warning(format!(
+                "Could not remove the session: {e}"
+            )));
+        }
+    }
+
+    fn save(&self) {
+        if let Err(e) = self.session.borrow().save(&self.target) {
+            self.progress
+                .event(&Event::warning(format!("Could not save the session: {e}")));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NullSink;
+
+    #[test]
+    fn test_collect_garbage() {
+        let target = std::env::temp_dir().join(format!("bind-sessions-{}", process::id()));
+        let _ = fs::remove_dir_all(&target);
+        for id in ["0000000000001-1", "0000000000002-1", "0000000000003-1"] {
+            Session {
+                id: id.to_owned(),
+                ..Default::default()
+            }
+            .save(&target)
+            .unwrap();
+        }
+
+        let log = SessionLog::start(&target, 2, Rc::new(NullSink));
+        let id = log.session.borrow().id.clone();
+        let mut left: Vec<_> = fs::read_dir(target.join(Session::DIR))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        assert_eq!(
+            left,
+            ["0000000000003-1.json".to_owned(), format!("{id}.json")]
+        );
+
+        log.finish();
+        assert!(matches!(
+            Session::load(&target, &id),
+            Err(BindError::Session { message, .. }) if message == "there is no such session"
+        ));
+        fs::remove_dir_all(target).unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use hash::xxhash64;
+use serde::{Deserialize, Serialize};
+
+use crate::{Event, ProgressSink};
+
+/// What the last successful run bound: the hash of every source file it saw
+/// and the files generated from it. Sources that still match are left out of
+/// the next prompt and their outputs are kept.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    sources: BTreeMap<PathBuf, SourceState>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SourceState {
+    hash: String,
+    outputs: Vec<PathBuf>,
+}
+
+impl Manifest {
+    const FILE: &str = ".bind-state.json";
+
+    /// The manifest in `target`, empty if there is none or it is unreadable.
+    pub(crate) fn load(target: &Path, progress: &dyn ProgressSink) -> Self {
+        let path = target.join(Self::FILE);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(e) => {
+                progress.event(&Event::warning(format!(
+                    "Rebinding everything, {} is unreadable: {e}",
+                    path.display()
+                )));
+                return Self::default();
+            }
+        };
+        serde_json::from_str(&text).unwrap_or_else(|e| {
+            progress.event(&Event::warning(format!(
+                "Rebinding everything, {} is corrupt: {e}",
+                path.display()
+            )));
+            Self::default()
+        })
+    }
+
+    /// Replaces the manifest in `target` at once, so an interrupted write
+    /// leaves the previous one intact.
+    pub(crate) fn save(&self, target: &Path) -> io::Result<()> {
+        fs::create_dir_all(target)?;
+        let path = target.join(Self::FILE);
+        let partial = path.with_extension("json.tmp");
+        fs::write(&partial, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&partial, &path)
+    }
+
+    /// The files changed since they were recorded, new ones, and those with
+    /// an output gone missing.
+    pub(crate) fn stale(&self, files: Vec<(PathBuf, String)>) -> Vec<(PathBuf, String)> {
+        files
+            .into_iter()
+            .filter(|(path, contents)| match self.sources.get(path) {
+                Some(state) => {
+                    state.hash != hash(contents) || state.outputs.iter().any(|output| !output.exists())
+                }
+                None => true,
+            })
+            .collect()
+    }
+
+    /// Records that `outputs` were generated from `sources` together.
+    pub(crate) fn record(&mut self, sources: &[(PathBuf, String)], outputs: &[PathBuf]) {
+        for (path, contents) in sources {
+            self.sources.insert(
+                path.clone(),
+                SourceState {
+                    hash: hash(contents),
+                    outputs: outputs.to_vec(),
+                },
+            );
+        }
+    }
+}
+
+fn hash(contents: &str) -> String {
+    format!("{:016x}", xxhash64(contents.as_bytes(), 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stale() {
+        let target = std::env::temp_dir().join(format!("bind-state-{}", std::process::id()));
+        let output = target.join("out.zig");
+        fs::create_dir_all(&target).unwrap();
+        fs::write(&output, "").unwrap();
+        let a = (PathBuf::from("a.h"), "void a();".to_owned());
+        let b = (PathBuf::from("b.h"), "void b();".to_owned());
+
+        let mut manifest = Manifest::default();
+        assert_eq!(manifest.stale(vec![a.clone()]).len(), 1);
+        manifest.record(&[a.clone(), b.clone()], std::slice::from_ref(&output));
+        manifest.save(&target).unwrap();
+
+        let manifest = Manifest::load(&target, &crate::NullSink);
+        let changed = (PathBuf::from("b.h"), "void b(int);".to_owned());
+        assert_eq!(manifest.stale(vec![a.clone(), changed.clone()]), [changed]);
+        fs::remove_file(&output).unwrap();
+        assert_eq!(manifest.stale(vec![a.clone()]), [a]);
+
+        fs::remove_dir_all(target).unwrap();
+    }
+}
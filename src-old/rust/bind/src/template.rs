@@ -0,0 +1,506 @@
+use std::{
+    collections::HashMap,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{BindError, Language};
+
+/// A prompt with `{{name}}` placeholders and sections.
+///
+/// `{{#name}}...{{/name}}` is kept when `name` is set as a section or, if
+/// not, when the variable `name` is not empty. `{{^name}}...{{/name}}` is
+/// kept in the opposite case. A tag alone on its line takes the line with
+/// it, so sections do not leave blank lines behind.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptTemplate {
+    name: String,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Text(String),
+    Variable(String),
+    Section {
+        name: String,
+        inverted: bool,
+        nodes: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A tag is malformed or its sections do not nest.
+    Syntax {
+        template: String,
+        line: usize,
+        message: String,
+    },
+    /// A variable or section the template is not given, most likely a typo.
+    Unknown { template: String, name: String },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Syntax {
+                template,
+                line,
+                message,
+            } => write!(f, "template {template}, line {line}: {message}"),
+            TemplateError::Unknown { template, name } => {
+                write!(f, "template {template} uses unknown variable `{name}`")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// What a template is rendered with.
+#[derive(Debug, Clone, Default)]
+pub struct Vars {
+    values: HashMap<String, String>,
+    sections: HashMap<String, bool>,
+}
+
+impl Vars {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, name: &str, value: impl ToString) -> &mut Self {
+        self.values.insert(name.to_owned(), value.to_string());
+        self
+    }
+
+    /// Shows or hides the sections called `name`, whatever the variable of
+    /// that name holds.
+    pub fn section(&mut self, name: &str, shown: bool) -> &mut Self {
+        self.sections.insert(name.to_owned(), shown);
+        self
+    }
+
+    fn shown(&self, name: &str) -> Option<bool> {
+        self.sections
+            .get(name)
+            .copied()
+            .or_else(|| self.values.get(name).map(|value| !value.is_empty()))
+    }
+}
+
+enum Tag<'a> {
+    Variable(&'a str),
+    Open(&'a str, bool),
+    Close(&'a str),
+}
+
+impl PromptTemplate {
+    pub fn parse(name: &str, text: &str) -> Result<Self, TemplateError> {
+        let syntax = |line: usize, message: String| TemplateError::Syntax {
+            template: name.to_owned(),
+            line,
+            message,
+        };
+        // Sections being filled, the innermost last, with the line they opened on
+        let mut open: Vec<(String, bool, usize, Vec<Node>)> = vec![];
+        let mut root = vec![];
+
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            let number = i + 1;
+            let mut rest = line;
+            let mut pieces = vec![];
+            while let Some(start) = rest.find("{{") {
+                let Some(len) = rest[start..].find("}}") else {
+                    return Err(syntax(number, "`{{` is never closed".to_owned()));
+                };
+                pieces.push((&rest[..start], Some(tag(&rest[start + 2..start + len]))));
+                rest = &rest[start + len + 2..];
+            }
+            pieces.push((rest, None));
+
+            // A section tag alone on its line is dropped with the line
+            let standalone = match pieces.as_slice() {
+                [(before, Some(Some(Tag::Open(..) | Tag::Close(_)))), (after, None)] => {
+                    before.trim().is_empty() && after.trim().is_empty()
+                }
+                _ => false,
+            };
+            for (text, tag) in pieces {
+                let nodes = open.last_mut().map_or(&mut root, |(.., nodes)| nodes);
+                if !standalone && !text.is_empty() {
+                    nodes.push(Node::Text(text.to_owned()));
+                }
+                match tag {
+                    None => {}
+                    Some(None) => return Err(syntax(number, "tags need a name".to_owned())),
+                    Some(Some(Tag::Variable(name))) => nodes.push(Node::Variable(name.to_owned())),
+                    Some(Some(Tag::Open(name, inverted))) => {
+                        open.push((name.to_owned(), inverted, number, vec![]))
+                    }
+                    Some(Some(Tag::Close(name))) => match open.pop() {
+                        Some((opened, inverted, _, section)) if opened == name => {
+                            let parent = open.last_mut().map_or(&mut root, |(.., nodes)| nodes);
+                            parent.push(Node::Section {
+                                name: opened,
+                                inverted,
+                                nodes: section,
+                            });
+                        }
+                        Some((opened, ..)) => {
+                            return Err(syntax(
+                                number,
+                                format!("`{{{{/{name}}}}}` closes section `{opened}`"),
+                            ));
+                        }
+                        None => {
+                            return Err(syntax(number, format!("`{{{{/{name}}}}}` closes no section")));
+                        }
+                    },
+                }
+            }
+        }
+        if let Some((name, _, line, _)) = open.pop() {
+            return Err(syntax(line, format!("section `{name}` is never closed")));
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            nodes: root,
+        })
+    }
+
+    /// Fails on the first variable or section not in `known`.
+    pub fn check(&self, known: &[&str]) -> Result<(), TemplateError> {
+        fn walk<'a>(nodes: &'a [Node], known: &[&str]) -> Option<&'a str> {
+            nodes.iter().find_map(|node| match node {
+                Node::Text(_) => None,
+                Node::Variable(name) => (!known.contains(&name.as_str())).then_some(name.as_str()),
+                Node::Section { name, nodes, .. } => match known.contains(&name.as_str()) {
+                    true => walk(nodes, known),
+                    false => Some(name),
+                },
+            })
+        }
+        match walk(&self.nodes, known) {
+            Some(name) => Err(self.unknown(name)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn render(&self, vars: &Vars) -> Result<String, TemplateError> {
+        let mut out = String::new();
+        self.render_into(&self.nodes, vars, &mut out)?;
+        Ok(out)
+    }
+
+    fn render_into(&self, nodes: &[Node], vars: &Vars, out: &mut String) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => out.push_str(text),
+                Node::Variable(name) => match vars.values.get(name) {
+                    Some(value) => out.push_str(value),
+                    None => return Err(self.unknown(name)),
+                },
+                Node::Section {
+                    name,
+                    inverted,
+                    nodes,
+                } => match vars.shown(name) {
+                    Some(shown) if shown != *inverted => self.render_into(nodes, vars, out)?,
+                    Some(_) => {}
+                    None => return Err(self.unknown(name)),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn unknown(&self, name: &str) -> TemplateError {
+        TemplateError::Unknown {
+            template: self.name.clone(),
+            name: name.to_owned(),
+        }
+    }
+}
+
+/// The inside of a `{{...}}`, or `None` if it has no name.
+fn tag(inside: &str) -> Option<Tag<'_>> {
+    let inside = inside.trim();
+    let tag = match inside.as_bytes().first()? {
+        b'#' => Tag::Open(inside[1..].trim(), false),
+        b'^' => Tag::Open(inside[1..].trim(), true),
+        b'/' => Tag::Close(inside[1..].trim()),
+        _ => Tag::Variable(inside),
+    };
+    match tag {
+        Tag::Open("", _) | Tag::Close("") => None,
+        tag => Some(tag),
+    }
+}
+
+/// The templates of every prompt of the critique loop, with the variables
+/// each of them is rendered with.
+const TEMPLATES: [(&str, &str, &[&str]); 4] = [
+    (
+        "generation",
+        include_str!("templates/generation.prompt"),
+        &[
+            "guidelines",
+            "target_guidelines",
+            "temperature",
+            "input_language",
+            "output_language",
+            "c_abi",
+            "omitted",
+            "compiler_output",
+            "critique",
+            "bindings",
+        ],
+    ),
+    (
+        "evaluation",
+        include_str!("templates/evaluation.prompt"),
+        &["guidelines", "target_guidelines", "compiler_output", "bindings"],
+    ),
+    (
+        "critique",
+        include_str!("templates/critique.prompt"),
+        &["guidelines", "target_guidelines", "compiler_output", "bindings"],
+    ),
+    (
+        "temperature",
+        include_str!("templates/temperature.prompt"),
+        &[
+            "guidelines",
+            "target_guidelines",
+            "compiler_output",
+            "critique",
+            "bindings",
+            "threshold",
+            "score",
+            "temperature",
+        ],
+    ),
+];
+
+/// Templates for every prompt [`crate::Prompter`] sends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Prompts {
+    pub(crate) generation: PromptTemplate,
+    pub(crate) evaluation: PromptTemplate,
+    pub(crate) critique: PromptTemplate,
+    pub(crate) temperature: PromptTemplate,
+    /// Text of the templates read from an override directory, which has to
+    /// be part of what cached bindings are keyed by.
+    overrides: String,
+}
+
+impl Prompts {
+    /// The built-in templates, with those found in `dir` taking their place.
+    /// `generation_zig.prompt` overrides the generation prompt when binding
+    /// to Zig, `generation.prompt` for every target.
+    pub(crate) fn load(dir: Option<&Path>, target: Language) -> Result<Self, BindError> {
+        let language = format!("{target:?}").to_lowercase();
+        let mut overrides = String::new();
+        let mut template = |(name, builtin, known): (&str, &str, &[&str])| {
+            let found = match dir {
+                Some(dir) => override_file(dir, name, &language)?,
+                None => None,
+            };
+            let (path, text) = match &found {
+                Some((path, text)) => {
+                    overrides.push_str(text);
+                    (path.clone(), text.as_str())
+                }
+                None => (PathBuf::from(format!("<built-in {name}.prompt>")), builtin),
+            };
+            PromptTemplate::parse(name, text)
+                .and_then(|template| template.check(known).map(|()| template))
+                .map_err(|message| BindError::Config {
+                    path,
+                    message: message.to_string(),
+                })
+        };
+        let [generation, evaluation, critique, temperature] = TEMPLATES;
+        let (generation, evaluation, critique, temperature) = (
+            template(generation)?,
+            template(evaluation)?,
+            template(critique)?,
+            template(temperature)?,
+        );
+        Ok(Self {
+            generation,
+            evaluation,
+            critique,
+            temperature,
+            overrides,
+        })
+    }
+
+    pub(crate) fn overrides(&self) -> &str {
+        &self.overrides
+    }
+}
+
+impl Default for Prompts {
+    fn default() -> Self {
+        Self::load(None, Language::Rust).expect("built-in templates are valid")
+    }
+}
+
+/// `{name}_{language}.prompt` or else `{name}.prompt` in `dir`, if either
+/// exists.
+fn override_file(dir: &Path, name: &str, language: &str) -> Result<Option<(PathBuf, String)>, BindError> {
+    for file in [format!("{name}_{language}.prompt"), format!("{name}.prompt")] {
+        let path = dir.join(file);
+        match fs::read_to_string(&path) {
+            Ok(text) => return Ok(Some((path, text))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(BindError::Config {
+                    path,
+                    message: e.to_string(),
+                });
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(text: &str, vars: &Vars) -> Result<String, TemplateError> {
+        PromptTemplate::parse("test", text)?.render(vars)
+    }
+
+    #[test]
+    fn test_substitution() {
+        let mut vars = Vars::new();
+        vars.set("language", "Zig").set("temperature", 0.5);
+        assert_eq!(
+            render("Output {{ language }} at {{temperature}}.\n", &vars).unwrap(),
+            "Output Zig at 0.5.\n"
+        );
+        assert_eq!(render("no tags {} }}\n", &vars).unwrap(), "no tags {} }}\n");
+    }
+
+    #[test]
+    fn test_sections() {
+        let text = "# Input\n\
+            {{#critique}}\n# Feedback:\n{{critique}}\n{{/critique}}\n\
+            {{^critique}}\nFirst round.\n{{/critique}}\n\
+            End: {{#critique}}more{{/critique}}.\n";
+        let mut vars = Vars::new();
+        vars.set("critique", "");
+        assert_eq!(render(text, &vars).unwrap(), "# Input\nFirst round.\nEnd: .\n");
+
+        vars.set("critique", "too long");
+        assert_eq!(
+            render(text, &vars).unwrap(),
+            "# Input\n# Feedback:\ntoo long\nEnd: more.\n"
+        );
+
+        // An explicit toggle wins over the variable
+        vars.section("critique", false);
+        assert_eq!(render(text, &vars).unwrap(), "# Input\nFirst round.\nEnd: .\n");
+
+        // Sections work with CRLF and with nothing but a toggle
+        let mut vars = Vars::new();
+        vars.section("verbose", true);
+        assert_eq!(
+            render("a\r\n  {{#verbose}}  \r\nb\r\n{{/verbose}}\r\n", &vars).unwrap(),
+            "a\r\nb\r\n"
+        );
+    }
+
+    #[test]
+    fn test_unknown_variables() {
+        let template =
+            PromptTemplate::parse("generation", "{{c_abi}}\n{{#critque}}{{critique}}{{/critque}}\n").unwrap();
+        assert_eq!(
+            template.check(&["c_abi", "critique"]),
+            Err(TemplateError::Unknown {
+                template: "generation".to_owned(),
+                name: "critque".to_owned(),
+            })
+        );
+        assert_eq!(template.check(&["c_abi", "critique", "critque"]), Ok(()));
+
+        let err = render("{{c_abi}} {{guidlines}}", Vars::new().set("c_abi", "")).unwrap_err();
+        assert_eq!(err.to_string(), "template test uses unknown variable `guidlines`");
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let line = |text| match render(text, &Vars::new()) {
+            Err(TemplateError::Syntax { line, message, .. }) => (line, message),
+            other => panic!("{other:?}"),
+        };
+        assert_eq!(line("a\n{{b\n").0, 2);
+        assert_eq!(line("{{}}"), (1, "tags need a name".to_owned()));
+        assert_eq!(
+            line("{{#a}}\n\n{{/b}}\n"),
+            (3, "`{{/b}}` closes section `a`".to_owned())
+        );
+        assert_eq!(line("{{/a}}"), (1, "`{{/a}}` closes no section".to_owned()));
+        assert_eq!(line("\n{{^a}}\n"), (2, "section `a` is never closed".to_owned()));
+    }
+
+    #[test]
+    fn test_builtin_templates() {
+        let prompts = Prompts::default();
+        assert_eq!(prompts.overrides(), "");
+
+        let mut vars = Vars::new();
+        for name in TEMPLATES[0].2 {
+            vars.set(name, "");
+        }
+        vars.set("c_abi", "[\"int f();\"]");
+        let first = prompts.generation.render(&vars).unwrap();
+        assert!(first.contains("int f();"));
+        assert!(first.contains("# No compiler output provided"));
+        assert!(!first.contains("feedback"), "{first}");
+
+        vars.set("critique", "Use snake_case")
+            .set("compiler_output", "error: unused");
+        let second = prompts.generation.render(&vars).unwrap();
+        assert!(second.contains("# AI feedback on previous output:"), "{second}");
+        assert!(second.contains("Use snake_case"));
+        assert!(!second.contains("No compiler output"));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let dir = std::env::temp_dir().join(format!("bind-templates-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("critique.prompt"), "Critique {{bindings}}\n").unwrap();
+        fs::write(
+            dir.join("critique_zig.prompt"),
+            "Critique the Zig in {{bindings}}\n",
+        )
+        .unwrap();
+
+        let zig = Prompts::load(Some(&dir), Language::Zig).unwrap();
+        let rust = Prompts::load(Some(&dir), Language::Rust).unwrap();
+        let vars = Vars::new().set("bindings", "lib.rs").clone();
+        assert_eq!(
+            zig.critique.render(&vars).unwrap(),
+            "Critique the Zig in lib.rs\n"
+        );
+        assert_eq!(rust.critique.render(&vars).unwrap(), "Critique lib.rs\n");
+        assert_eq!(rust.overrides(), "Critique {{bindings}}\n");
+        assert_eq!(rust.generation, Prompts::default().generation);
+
+        fs::write(dir.join("evaluation.prompt"), "Score {{bindngs}}\n").unwrap();
+        let err = Prompts::load(Some(&dir), Language::Rust).unwrap_err();
+        assert!(
+            matches!(&err, BindError::Config { path, message }
+                if path.ends_with("evaluation.prompt") && message.contains("`bindngs`")),
+            "{err}"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    Config, Event, Output, PostError, ProgressSink, WriteMode,
+    diff::{self, Change, Summary},
+    post::GeneratedFile,
+};
+
+/// Build output, which is neither backed up nor removed on a full rebuild.
+const BUILD_DIRS: &[&str] = &["target", ".build", "zig-out", ".zig-cache", "zig-cache"];
+
+/// The files an applicator puts under `root`, relative to it.
+pub(crate) struct Plan {
+    root: PathBuf,
+    files: Vec<(PathBuf, String)>,
+}
+
+impl Plan {
+    pub(crate) fn new(root: PathBuf, files: Vec<GeneratedFile>) -> Self {
+        let files = files
+            .into_iter()
+            .map(|file| (file.path, file.contents))
+            .collect();
+        Self { root, files }
+    }
+
+    pub(crate) fn file(&mut self, path: impl Into<PathBuf>, contents: String) {
+        self.files.push((path.into(), contents));
+    }
+
+    /// Adds a file the package cannot do without, unless the bindings brought
+    /// their own or one is already there to keep.
+    pub(crate) fn default_file(
+        &mut self,
+        path: impl Into<PathBuf>,
+        contents: String,
+        cfg: &Config,
+    ) {
+        let path = path.into();
+        let generated = self.files.iter().any(|(file, _)| *file == path);
+        if !generated && (cfg.full_rebuild || !self.root.join(&path).exists()) {
+            self.files.push((path, contents));
+        }
+    }
+
+    /// Writes the files as `cfg.write_mode` says, asking on the terminal in
+    /// interactive mode, and returns the paths holding them. Backups go to
+    /// `.bind-backup` in the output directory.
+    pub(crate) fn write(
+        self,
+        output: &Output,
+        cfg: &Config,
+        progress: &dyn ProgressSink,
+    ) -> Result<Vec<PathBuf>, PostError> {
+        let backups = output.lib_path.join(".bind-backup");
+        self.write_with(&backups, cfg, progress, &mut ask)
+    }
+
+    /// Like [`Plan::write`], with `confirm` deciding on every change in
+    /// interactive mode. A full rebuild also removes the files under `root`
+    /// that are not part of the plan.
+    pub(crate) fn write_with(
+        self,
+        backups: &Path,
+        cfg: &Config,
+        progress: &dyn ProgressSink,
+        confirm: &mut dyn FnMut(&Path, Change) -> bool,
+    ) -> Result<Vec<PathBuf>, PostError> {
+        let failed = |path: &Path, e: io::Error| PostError::new(path, e.to_string());
+        let root = &self.root;
+        let planned: BTreeSet<PathBuf> = self.files.iter().map(|(path, _)| path.clone()).collect();
+        let mut entries = vec![];
+        for (path, contents) in self.files {
+            let old = fs::read_to_string(root.join(&path)).ok();
+            entries.push((path, old, Some(contents)));
+        }
+        if cfg.full_rebuild {
+            let mut existing = vec![];
+            list(root, Path::new(""), &mut existing).map_err(|e| failed(root, e))?;
+            existing.sort();
+            for path in existing.into_iter().filter(|path| !planned.contains(path)) {
+                let old = fs::read_to_string(root.join(&path)).ok();
+                entries.push((path, Some(old.unwrap_or_default()), None));
+            }
+        }
+
+        if cfg.write_mode == WriteMode::DryRun {
+            let mut summary = Summary::default();
+            for (path, old, new) in &entries {
+                if let Some(change) = Change::between(old.as_deref(), new.as_deref()) {
+                    progress.event(&Event::note(diff::unified(
+                        path,
+                        old.as_deref(),
+                        new.as_deref(),
+                    )));
+                    summary.add(change);
+                }
+            }
+            progress.event(&Event::note(format!(
+                "Dry run in {}: {summary}",
+                root.display()
+            )));
+            return Ok(vec![]);
+        }
+        let changes = entries
+            .iter()
+            .any(|(_, old, new)| Change::between(old.as_deref(), new.as_deref()).is_some());
+        if cfg.write_mode == WriteMode::Overwrite && changes {
+            backup(root, backups, progress).map_err(|e| failed(root, e))?;
+        }
+
+        let mut written = vec![];
+        for (path, old, new) in entries {
+            let full_path = root.join(&path);
+            let Some(change) = Change::between(old.as_deref(), new.as_deref()) else {
+                written.push(full_path);
+                continue;
+            };
+            if cfg.write_mode == WriteMode::Interactive {
+                progress.event(&Event::note(diff::unified(
+                    &path,
+                    old.as_deref(),
+                    new.as_deref(),
+                )));
+                if !confirm(&path, change) {
+                    continue;
+                }
+            }
+            match new {
+                Some(contents) => {
+                    if let Some(parent) = full_path.parent() {
+                        fs::create_dir_all(parent).map_err(|e| failed(parent, e))?;
+                    }
+                    fs::write(&full_path, contents).map_err(|e| failed(&full_path, e))?;
+                    written.push(full_path);
+                }
+                None => fs::remove_file(&full_path).map_err(|e| failed(&full_path, e))?,
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// Asks on the terminal whether to go ahead with `change` to `path`.
+fn ask(path: &Path, change: Change) -> bool {
+    let verb = match change {
+        Change::Created => "Create",
+        Change::Modified => "Overwrite",
+        Change::Deleted => "Delete",
+    };
+    print!("{verb} {}? [y/N] ", path.display());
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).is_ok() && matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Copies `root` to a new `<timestamp>` directory in `backups`, if there is
+/// anything to copy.
+fn backup(root: &Path, backups: &Path, progress: &dyn ProgressSink) -> io::Result<()> {
+    let mut files = vec![];
+    list(root, Path::new(""), &mut files)?;
+    if files.is_empty() {
+        return Ok(());
+    }
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let dest = backups
+        .join(format!("{millis:013}"))
+        .join(root.file_name().unwrap_or_default());
+    for file in files {
+        let to = dest.join(&file);
+        fs::create_dir_all(to.parent().unwrap())?;
+        fs::copy(root.join(&file), to)?;
+    }
+    progress.event(&Event::note(format!(
+        "Backed up {} to {}",
+        root.display(),
+        dest.display()
+    )));
+    Ok(())
+}
+
+/// Every file under `root.join(dir)`, relative to `root`, build output aside.
+fn list(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    let entries = match fs::read_dir(root.join(dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            if !BUILD_DIRS.iter().any(|build| entry.file_name() == *build) {
+                list(root, &path, files)?;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Notes(RefCell<Vec<String>>);
+
+    impl ProgressSink for Notes {
+        fn event(&self, event: &Event) {
+            if let Event::Note { message } = event {
+                self.0.borrow_mut().push(message.clone());
+            }
+        }
+    }
+
+    fn setup(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("bind-write-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let root = dir.join("point-sys");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("src/lib.rs"), "pub struct Point;\n").unwrap();
+        fs::write(root.join("src/old.rs"), "// gone\n").unwrap();
+        fs::write(root.join("target/big"), "").unwrap();
+        (dir, root)
+    }
+
+    fn plan(root: &Path) -> Plan {
+        let mut plan = Plan::new(root.to_owned(), vec![]);
+        plan.file("src/lib.rs", "pub struct Point(f32);\n".to_owned());
+        plan.file("src/shape.rs", "pub struct Shape;\n".to_owned());
+        plan
+    }
+
+    #[test]
+    fn test_overwrite_backs_up() {
+        let (dir, root) = setup("overwrite");
+        let cfg = Config {
+            full_rebuild: true,
+            ..Default::default()
+        };
+        let written = plan(&root)
+            .write_with(
+                &dir.join(".bind-backup"),
+                &cfg,
+                &Notes::default(),
+                &mut |_, _| unreachable!(),
+            )
+            .unwrap();
+
+        assert_eq!(
+            written,
+            [root.join("src/lib.rs"), root.join("src/shape.rs")]
+        );
+        assert_eq!(
+            fs::read_to_string(root.join("src/lib.rs")).unwrap(),
+            "pub struct Point(f32);\n"
+        );
+        assert!(!root.join("src/old.rs").exists());
+        assert!(root.join("target/big").exists());
+
+        let backups: Vec<_> = fs::read_dir(dir.join(".bind-backup")).unwrap().collect();
+        assert_eq!(backups.len(), 1);
+        let backup = backups[0].as_ref().unwrap().path().join("point-sys");
+        assert_eq!(
+            fs::read_to_string(backup.join("src/lib.rs")).unwrap(),
+            "pub struct Point;\n"
+        );
+        assert_eq!(
+            fs::read_to_string(backup.join("src/old.rs")).unwrap(),
+            "// gone\n"
+        );
+        assert!(!backup.join("target").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let (dir, root) = setup("dry-run");
+        let cfg = Config {
+            full_rebuild: true,
+            write_mode: WriteMode::DryRun,
+            ..Default::default()
+        };
+        let notes = Notes::default();
+        let written = plan(&root)
+            .write_with(
+                &dir.join(".bind-backup"),
+                &cfg,
+                &notes,
+                &mut |_, _| unreachable!(),
+            )
+            .unwrap();
+
+        assert!(written.is_empty());
+        assert_eq!(
+            fs::read_to_string(root.join("src/lib.rs")).unwrap(),
+            "pub struct Point;\n"
+        );
+        assert!(root.join("src/old.rs").exists());
+        assert!(!root.join("src/shape.rs").exists());
+        assert!(!dir.join(".bind-backup").exists());
+
+        let notes = notes.0.take();
+        assert_eq!(notes.len(), 4);
+        assert!(notes[0].contains("-pub struct Point;\n+pub struct Point(f32);\n"));
+        assert!(notes[1].starts_with("--- /dev/null\n+++ b/src/shape.rs\n"));
+        assert!(notes[2].starts_with("--- a/src/old.rs\n+++ /dev/null\n"));
+        assert_eq!(
+            notes[3],
+            format!(
+                "Dry run in {}: 1 created, 1 modified, 1 deleted",
+                root.display()
+            )
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_interactive() {
+        let (dir, root) = setup("interactive");
+        let cfg = Config {
+            write_mode: WriteMode::Interactive,
+            ..Default::default()
+        };
+        let mut asked = vec![];
+        let written = plan(&root)
+            .write_with(
+                &dir.join(".bind-backup"),
+                &cfg,
+                &Notes::default(),
+                &mut |path, change| {
+                    asked.push((path.to_owned(), change));
+                    change == Change::Created
+                },
+            )
+            .unwrap();
+
+        assert_eq!(
+            asked,
+            [
+                (PathBuf::from("src/lib.rs"), Change::Modified),
+                (PathBuf::from("src/shape.rs"), Change::Created)
+            ]
+        );
+        assert_eq!(written, [root.join("src/shape.rs")]);
+        assert_eq!(
+            fs::read_to_string(root.join("src/lib.rs")).unwrap(),
+            "pub struct Point;\n"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
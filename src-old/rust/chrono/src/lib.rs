@@ -1,42 +1,46 @@
-use num_traits::{AsPrimitive, Num};
-use std::iter;
+use std::fmt;
+use std::iter::{self, Sum};
 use std::marker::PhantomData;
+use std::mem;
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
-use std::sync::Arc;
-use std::sync::atomic::{
-    AtomicU64,
-    Ordering::{self, *},
-};
+use std::sync::{LazyLock, Mutex};
 use std::time::SystemTime;
 
-use crate::collections::skip::{List, Map};
+use crate::collections::skip::List;
 use crate::rt::block_on;
 use crate::rt::worker::current_worker;
 use crate::sync::backoff::Backoff;
-use crate::sync::r#yield::yield_now;
 
 // Base trait for all time units
 pub trait TimeUnit: Copy + Sized + std::fmt::Debug {
+    /// Nanoseconds in one tick of the unit.
+    const NANOS: u128;
+
     fn from_nanos(nanos: u128) -> Self;
     fn from_inner(inner: u128) -> Self;
     fn into_nanos(self) -> u128;
+    fn ticks(self) -> u128;
+
+    fn checked_nanos(self) -> Option<u128> {
+        self.ticks().checked_mul(Self::NANOS)
+    }
 }
 
 // Individual unit structs
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Nanos(u128);
+pub struct Nanos(pub u128);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Micros(u128);
+pub struct Micros(pub u128);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Millis(u128);
+pub struct Millis(pub u128);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Seconds(u128);
+pub struct Seconds(pub u128);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Minutes(u128);
+pub struct Minutes(pub u128);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Hours(u128);
+pub struct Hours(pub u128);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Days(u128);
+pub struct Days(pub u128);
 // Constants for conversion
 pub const NANOS_PER_MICRO: u128 = 1_000;
 pub const NANOS_PER_MILLI: u128 = 1_000_000;
@@ -54,6 +58,8 @@ macro_rules! impl_time_unit {
             }
         }
         impl TimeUnit for $type {
+            const NANOS: u128 = $multiplier;
+
             fn from_nanos(nanos: u128) -> Self {
                 Self(nanos / $multiplier)
             }
@@ -65,6 +71,10 @@ macro_rules! impl_time_unit {
             fn into_nanos(self) -> u128 {
                 self.0 * $multiplier
             }
+
+            fn ticks(self) -> u128 {
+                self.0
+            }
         }
     };
 }
@@ -77,17 +87,28 @@ impl_time_unit!(Minutes, NANOS_PER_SECOND * SECONDS_PER_MINUTE);
 impl_time_unit!(Hours, NANOS_PER_SECOND * SECONDS_PER_HOUR);
 impl_time_unit!(Days, NANOS_PER_SECOND * SECONDS_PER_DAY);
 
+/// Orders two durations without going through nanoseconds, which `u128`
+/// ticks of a coarse unit can overflow. Each unit is a whole multiple of
+/// every finer one, so the finer side is divided down instead.
+fn compare<T: TimeUnit, U: TimeUnit>(lhs: T, rhs: U) -> std::cmp::Ordering {
+    if T::NANOS <= U::NANOS {
+        let ratio = U::NANOS / T::NANOS;
+        let (whole, rest) = (lhs.ticks() / ratio, lhs.ticks() % ratio);
+        whole.cmp(&rhs.ticks()).then(rest.cmp(&0))
+    } else {
+        compare(rhs, lhs).reverse()
+    }
+}
+
 impl<T: TimeUnit, U: TimeUnit> PartialEq<Duration<U>> for Duration<T> {
     fn eq(&self, other: &Duration<U>) -> bool {
-        self.0.into_nanos() == other.0.into_nanos()
+        compare(self.0, other.0).is_eq()
     }
 }
 
 impl<T: TimeUnit, U: TimeUnit> PartialOrd<Duration<U>> for Duration<T> {
     fn partial_cmp(&self, other: &Duration<U>) -> Option<std::cmp::Ordering> {
-        let self_nanos = self.0.into_nanos();
-        let other_nanos = other.0.into_nanos();
-        self_nanos.partial_cmp(&other_nanos)
+        Some(compare(self.0, other.0))
     }
 }
 
@@ -96,24 +117,49 @@ impl<T: TimeUnit> Eq for Duration<T> {}
 
 impl<T: TimeUnit> Ord for Duration<T> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.0.into_nanos().cmp(&other.0.into_nanos())
+        self.0.ticks().cmp(&other.0.ticks())
+    }
+}
+
+/// Why a duration could not be converted without loss.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertError {
+    /// The duration does not fit the target.
+    Overflow,
+    /// The duration is not a whole number of the target unit.
+    Inexact,
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Overflow => write!(f, "duration overflows the target"),
+            ConvertError::Inexact => write!(f, "duration is not a whole number of the target unit"),
+        }
     }
 }
 
+impl std::error::Error for ConvertError {}
+
 // Conversion trait
 pub trait Convert<T: TimeUnit> {
     fn convert(self) -> T;
 }
 
-// Implement conversion between all units
+// Implement conversion between all units, rounding towards zero
 impl<From: TimeUnit, To: TimeUnit> Convert<To> for From {
     fn convert(self) -> To {
-        To::from_nanos(self.into_nanos())
+        if To::NANOS >= From::NANOS {
+            To::from_inner(self.ticks() / (To::NANOS / From::NANOS))
+        } else {
+            let ticks = self.ticks().checked_mul(From::NANOS / To::NANOS);
+            To::from_inner(ticks.expect("overflow when converting duration"))
+        }
     }
 }
 
 // Optional wrapper type for fluent API
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy)]
 pub struct Duration<T: TimeUnit>(T);
 
 impl<T: TimeUnit> Duration<T> {
@@ -121,10 +167,28 @@ impl<T: TimeUnit> Duration<T> {
         Self(value)
     }
 
+    /// The duration in `U`, rounded towards zero.
     pub fn into<U: TimeUnit>(self) -> Duration<U> {
         Duration(self.0.convert())
     }
 
+    /// The duration in `U`, unless it does not fit or is not a whole number
+    /// of `U`.
+    pub fn try_into<U: TimeUnit>(self) -> Result<Duration<U>, ConvertError> {
+        let ticks = self.0.ticks();
+        let ticks = if U::NANOS >= T::NANOS {
+            let ratio = U::NANOS / T::NANOS;
+            if !ticks.is_multiple_of(ratio) {
+                return Err(ConvertError::Inexact);
+            }
+            ticks / ratio
+        } else {
+            let ratio = T::NANOS / U::NANOS;
+            ticks.checked_mul(ratio).ok_or(ConvertError::Overflow)?
+        };
+        Ok(Duration::from(ticks))
+    }
+
     pub fn from(inner: u128) -> Self {
         Self(T::from_inner(inner))
     }
@@ -132,103 +196,333 @@ impl<T: TimeUnit> Duration<T> {
     pub fn get(self) -> T {
         self.0
     }
+
+    pub fn is_zero(self) -> bool {
+        self.0.ticks() == 0
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.ticks().checked_add(other.0.ticks()).map(Self::from)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.ticks().checked_sub(other.0.ticks()).map(Self::from)
+    }
+
+    pub fn checked_mul<S: Scalar>(self, scalar: S) -> Option<Self> {
+        scalar.checked_mul(self.0.ticks()).map(Self::from)
+    }
+
+    pub fn checked_div<S: Scalar>(self, scalar: S) -> Option<Self> {
+        scalar.checked_div(self.0.ticks()).map(Self::from)
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        Self::from(self.0.ticks().saturating_add(other.0.ticks()))
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        Self::from(self.0.ticks().saturating_sub(other.0.ticks()))
+    }
+
+    /// Multiplies by `scalar`, clamping to the longest duration on overflow
+    /// and to zero for negative scalars.
+    pub fn saturating_mul<S: Scalar>(self, scalar: S) -> Self {
+        match self.checked_mul(scalar) {
+            Some(duration) => duration,
+            None if self.is_zero() || scalar.is_negative() => Self::from(0),
+            None => Self::from(u128::MAX),
+        }
+    }
 }
 
 impl Duration<Nanos> {
     pub const INSTANT: Duration<Nanos> = Duration::<Nanos>(Nanos(0));
 }
 
+impl From<std::time::Duration> for Duration<Nanos> {
+    fn from(duration: std::time::Duration) -> Self {
+        Duration(Nanos(duration.as_nanos()))
+    }
+}
+
+impl<T: TimeUnit> TryFrom<Duration<T>> for std::time::Duration {
+    type Error = ConvertError;
+
+    fn try_from(duration: Duration<T>) -> Result<Self, ConvertError> {
+        let nanos = duration.0.checked_nanos().ok_or(ConvertError::Overflow)?;
+        let secs = u64::try_from(nanos / NANOS_PER_SECOND).map_err(|_| ConvertError::Overflow)?;
+        Ok(std::time::Duration::new(
+            secs,
+            (nanos % NANOS_PER_SECOND) as u32,
+        ))
+    }
+}
+
+macro_rules! impl_try_from {
+    ($from:ty => $($to:ty),*) => {
+        $(
+            impl TryFrom<Duration<$from>> for Duration<$to> {
+                type Error = ConvertError;
+
+                fn try_from(duration: Duration<$from>) -> Result<Self, ConvertError> {
+                    duration.try_into()
+                }
+            }
+        )*
+    };
+}
+
+impl_try_from!(Nanos => Micros, Millis, Seconds, Minutes, Hours, Days);
+impl_try_from!(Micros => Nanos, Millis, Seconds, Minutes, Hours, Days);
+impl_try_from!(Millis => Nanos, Micros, Seconds, Minutes, Hours, Days);
+impl_try_from!(Seconds => Nanos, Micros, Millis, Minutes, Hours, Days);
+impl_try_from!(Minutes => Nanos, Micros, Millis, Seconds, Hours, Days);
+impl_try_from!(Hours => Nanos, Micros, Millis, Seconds, Minutes, Days);
+impl_try_from!(Days => Nanos, Micros, Millis, Seconds, Minutes, Hours);
+
+/// Writes `value / scale` with as many decimals as it takes, "1.5" or "230".
+fn decimal(f: &mut String, value: u128, scale: u128) {
+    use std::fmt::Write;
+    let _ = write!(f, "{}", value / scale);
+    let rest = value % scale;
+    if rest != 0 {
+        let digits = scale.ilog10() as usize;
+        let fraction = format!("{rest:0digits$}");
+        let _ = write!(f, ".{}", fraction.trim_end_matches('0'));
+    }
+}
+
+/// Prints the duration the way a person would write it, "1.5s", "230ms" or
+/// "2h 30m" once it reaches a minute.
+impl<T: TimeUnit> fmt::Display for Duration<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let nanos_per_day = NANOS_PER_SECOND * SECONDS_PER_DAY;
+        // Split off whole days first, so coarse units cannot overflow
+        let per_day = nanos_per_day / T::NANOS;
+        let days = self.0.ticks() / per_day;
+        let nanos = self.0.ticks() % per_day * T::NANOS;
+
+        let mut out = String::new();
+        if days == 0 && nanos < NANOS_PER_SECOND * SECONDS_PER_MINUTE {
+            let (scale, symbol) = match nanos {
+                0 => (1, "s"),
+                1..NANOS_PER_MICRO => (1, "ns"),
+                NANOS_PER_MICRO..NANOS_PER_MILLI => (NANOS_PER_MICRO, "µs"),
+                NANOS_PER_MILLI..NANOS_PER_SECOND => (NANOS_PER_MILLI, "ms"),
+                _ => (NANOS_PER_SECOND, "s"),
+            };
+            decimal(&mut out, nanos, scale);
+            out.push_str(symbol);
+            return f.pad(&out);
+        }
+
+        let secs = nanos / NANOS_PER_SECOND;
+        let parts = [
+            (days, "d"),
+            (secs / SECONDS_PER_HOUR, "h"),
+            (secs % SECONDS_PER_HOUR / SECONDS_PER_MINUTE, "m"),
+        ];
+        for (value, symbol) in parts.into_iter().filter(|&(value, _)| value != 0) {
+            if !out.is_empty() {
+                out.push(' ');
+            }
+            out.push_str(&format!("{value}{symbol}"));
+        }
+        let secs = nanos % (NANOS_PER_SECOND * SECONDS_PER_MINUTE);
+        if secs != 0 {
+            out.push(' ');
+            decimal(&mut out, secs, NANOS_PER_SECOND);
+            out.push('s');
+        }
+        f.pad(&out)
+    }
+}
+
+impl<T: TimeUnit> fmt::Debug for Duration<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// A point in time, in nanoseconds since the UNIX epoch.
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
 pub struct Instant(u128);
 
+/// The system time at the first call to [`Instant::now`], and the monotonic
+/// clock reading it was taken with.
+static ANCHOR: LazyLock<(std::time::Instant, u128)> = LazyLock::new(|| {
+    let monotonic = std::time::Instant::now();
+    match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(duration) => (monotonic, duration.as_nanos()),
+        Err(_) => panic!("System time is before UNIX epoch, not good"),
+    }
+});
+
 impl Instant {
     pub const fn epoch() -> Self {
         Self(0)
     }
+
+    /// The current time. It never goes backwards, on any thread: readings
+    /// follow the monotonic clock from the system time of the first reading,
+    /// so they do not jump when the system clock is adjusted.
     pub fn now() -> Self {
-        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-            Ok(duration) => Self(duration.as_nanos()),
-            Err(_) => panic!("System time is before UNIX epoch, not good"),
-        }
+        let (monotonic, since_epoch) = *ANCHOR;
+        Self(since_epoch + monotonic.elapsed().as_nanos())
     }
+
     pub fn as_u128(&self) -> u128 {
         self.0
     }
 
-    pub(crate) fn elapsed(&self) -> Duration<Nanos> {
-        Self::now() - *self
+    pub fn elapsed(&self) -> Duration<Nanos> {
+        Self::now().duration_since(*self)
+    }
+
+    /// Time passed from `earlier` to `self`, zero if `earlier` is later.
+    pub fn duration_since(&self, earlier: Instant) -> Duration<Nanos> {
+        *self - earlier
+    }
+
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration<Nanos>> {
+        self.0.checked_sub(earlier.0).map(Duration::from)
+    }
+
+    pub fn checked_add<T: TimeUnit>(&self, duration: Duration<T>) -> Option<Instant> {
+        self.0.checked_add(duration.0.checked_nanos()?).map(Instant)
+    }
+
+    pub fn checked_sub<T: TimeUnit>(&self, duration: Duration<T>) -> Option<Instant> {
+        self.0.checked_sub(duration.0.checked_nanos()?).map(Instant)
     }
 }
 
-// Duration arithmetic
+// Duration arithmetic, in ticks of the unit
 impl<T: TimeUnit> Add for Duration<T> {
     type Output = Self;
     fn add(self, other: Self) -> Self {
-        Duration(T::from_nanos(self.0.into_nanos() + other.0.into_nanos()))
+        self.checked_add(other)
+            .expect("overflow when adding durations")
     }
 }
 impl<T: TimeUnit> Sub for Duration<T> {
     type Output = Self;
     fn sub(self, other: Self) -> Self {
-        Duration(T::from_nanos(self.0.into_nanos() - other.0.into_nanos()))
+        self.checked_sub(other)
+            .expect("overflow when subtracting durations")
     }
 }
 
-impl<T: TimeUnit, U> Mul<U> for Duration<T>
-where
-    U: Num + Copy + AsPrimitive<f64>,
-{
+/// Numbers a duration can be multiplied or divided by. Integers scale
+/// exactly, floats round towards zero.
+pub trait Scalar: Copy {
+    /// `ticks * self`, unless that is negative or does not fit.
+    fn checked_mul(self, ticks: u128) -> Option<u128>;
+    /// `ticks / self`, unless that is negative, infinite or does not fit.
+    fn checked_div(self, ticks: u128) -> Option<u128>;
+    fn is_negative(self) -> bool;
+}
+
+macro_rules! impl_scalar {
+    (int: $($type:ty),*) => {
+        $(
+            impl Scalar for $type {
+                fn checked_mul(self, ticks: u128) -> Option<u128> {
+                    ticks.checked_mul(u128::try_from(self).ok()?)
+                }
+
+                fn checked_div(self, ticks: u128) -> Option<u128> {
+                    ticks.checked_div(u128::try_from(self).ok()?)
+                }
+
+                #[allow(unused_comparisons)]
+                fn is_negative(self) -> bool {
+                    self < 0
+                }
+            }
+        )*
+    };
+    (float: $($type:ty),*) => {
+        $(
+            impl Scalar for $type {
+                fn checked_mul(self, ticks: u128) -> Option<u128> {
+                    let ticks = ticks as f64 * self as f64;
+                    (0.0..u128::MAX as f64).contains(&ticks).then_some(ticks as u128)
+                }
+
+                fn checked_div(self, ticks: u128) -> Option<u128> {
+                    let ticks = ticks as f64 / self as f64;
+                    (0.0..u128::MAX as f64).contains(&ticks).then_some(ticks as u128)
+                }
+
+                fn is_negative(self) -> bool {
+                    self < 0.0
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar!(int: u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+impl_scalar!(float: f32, f64);
+
+impl<T: TimeUnit, S: Scalar> Mul<S> for Duration<T> {
     type Output = Self;
-    fn mul(self, scalar: U) -> Self {
-        let nanos = self.0.into_nanos();
-        let scalar_f64: f64 = scalar.as_();
-        Duration(T::from_nanos((nanos as f64 * scalar_f64) as u128))
+    fn mul(self, scalar: S) -> Self {
+        self.checked_mul(scalar)
+            .expect("overflow when multiplying duration by scalar")
     }
 }
 
-impl<T: TimeUnit, U> Div<U> for Duration<T>
-where
-    U: Num + Copy + AsPrimitive<f64>,
-{
+impl<T: TimeUnit> Mul<Duration<T>> for u32 {
+    type Output = Duration<T>;
+    fn mul(self, duration: Duration<T>) -> Duration<T> {
+        duration * self
+    }
+}
+
+impl<T: TimeUnit, S: Scalar> Div<S> for Duration<T> {
     type Output = Self;
-    fn div(self, scalar: U) -> Self {
-        let nanos = self.0.into_nanos();
-        let scalar_f64: f64 = scalar.as_();
-        Duration(T::from_nanos((nanos as f64 / scalar_f64) as u128))
+    fn div(self, scalar: S) -> Self {
+        self.checked_div(scalar)
+            .expect("divide by zero error when dividing duration by scalar")
     }
 }
 
 impl<T: TimeUnit> AddAssign for Duration<T> {
     fn add_assign(&mut self, other: Self) {
-        *self = Duration(T::from_nanos(self.0.into_nanos() + other.0.into_nanos()));
+        *self = *self + other;
     }
 }
 
 impl<T: TimeUnit> SubAssign for Duration<T> {
     fn sub_assign(&mut self, other: Self) {
-        *self = Duration(T::from_nanos(self.0.into_nanos() - other.0.into_nanos()));
+        *self = *self - other;
+    }
+}
+
+impl<T: TimeUnit, S: Scalar> MulAssign<S> for Duration<T> {
+    fn mul_assign(&mut self, scalar: S) {
+        *self = *self * scalar;
     }
 }
 
-impl<T: TimeUnit, U> MulAssign<U> for Duration<T>
-where
-    U: Num + Copy + AsPrimitive<f64>,
-{
-    fn mul_assign(&mut self, scalar: U) {
-        let nanos = self.0.into_nanos();
-        let scalar_f64: f64 = scalar.as_();
-        *self = Duration(T::from_nanos((nanos as f64 * scalar_f64) as u128));
+impl<T: TimeUnit, S: Scalar> DivAssign<S> for Duration<T> {
+    fn div_assign(&mut self, scalar: S) {
+        *self = *self / scalar;
     }
 }
 
-impl<T: TimeUnit, U> DivAssign<U> for Duration<T>
-where
-    U: Num + Copy + AsPrimitive<f64>,
-{
-    fn div_assign(&mut self, scalar: U) {
-        let nanos = self.0.into_nanos();
-        let scalar_f64: f64 = scalar.as_();
-        *self = Duration(T::from_nanos((nanos as f64 / scalar_f64) as u128));
+impl<T: TimeUnit> Sum for Duration<T> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::from(0), Add::add)
+    }
+}
+
+impl<'a, T: TimeUnit> Sum<&'a Duration<T>> for Duration<T> {
+    fn sum<I: Iterator<Item = &'a Self>>(iter: I) -> Self {
+        iter.copied().sum()
     }
 }
 
@@ -243,14 +537,28 @@ impl Sub for Instant {
 impl<T: TimeUnit> Add<Duration<T>> for Instant {
     type Output = Self;
     fn add(self, duration: Duration<T>) -> Self {
-        Instant(self.0 + duration.0.into_nanos())
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
     }
 }
 
 impl<T: TimeUnit> Sub<Duration<T>> for Instant {
     type Output = Self;
     fn sub(self, duration: Duration<T>) -> Self {
-        Instant(self.0 - duration.0.into_nanos())
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl<T: TimeUnit> AddAssign<Duration<T>> for Instant {
+    fn add_assign(&mut self, duration: Duration<T>) {
+        *self = *self + duration;
+    }
+}
+
+impl<T: TimeUnit> SubAssign<Duration<T>> for Instant {
+    fn sub_assign(&mut self, duration: Duration<T>) {
+        *self = *self - duration;
     }
 }
 
@@ -308,34 +616,125 @@ pub async fn wait_until(expires: Instant, action: impl FnOnce() + Send + Sync +
 }
 
 pub async fn wait_for(duration: Duration<Millis>, action: impl FnOnce() + Send + Sync + 'static) {
-    loop {
-        let Some(worker) = current_worker().await else {
-            yield_now().await;
-            continue;
-        };
-        worker.timers.add(duration, Box::new(action)).await;
+    let Some(worker) = current_worker() else {
+        // No wheel outside of the runtime, and no other task to run meanwhile
+        let duration = std::time::Duration::try_from(duration);
+        std::thread::sleep(duration.unwrap_or(std::time::Duration::MAX));
+        (action)();
         return;
+    };
+    worker.timers.add(duration, Box::new(action)).await;
+}
+
+/// Bits of the deadline, in milliseconds, each level of the wheel resolves.
+const SLOT_BITS: u32 = 6;
+const SLOTS: usize = 1 << SLOT_BITS;
+/// Four levels of 64 slots reach about four and a half hours ahead, timers
+/// further out wait in an overflow list.
+const LEVELS: usize = 4;
+
+type Action = Box<dyn FnOnce() + Send + Sync>;
+
+struct Timer {
+    /// Deadline in milliseconds since the epoch, rounded up.
+    at: u64,
+    action: Action,
+}
+
+/// Hierarchical timing wheel with a resolution of one millisecond. A timer
+/// sits on the level of the highest bit group in which its deadline differs
+/// from the current time, and moves down a level whenever the wheel reaches
+/// the start of its slot, until it is due.
+struct Wheel {
+    now: u64,
+    levels: [[Vec<Timer>; SLOTS]; LEVELS],
+    overflow: Vec<Timer>,
+    len: usize,
+}
+
+impl Wheel {
+    fn new(now: u64) -> Self {
+        Self {
+            now,
+            levels: std::array::from_fn(|_| std::array::from_fn(|_| Vec::new())),
+            overflow: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Files `timer` away, or hands it back if it is already due.
+    fn insert(&mut self, timer: Timer) -> Result<(), Timer> {
+        if timer.at <= self.now {
+            return Err(timer);
+        }
+        let level = ((u64::BITS - 1 - (timer.at ^ self.now).leading_zeros()) / SLOT_BITS) as usize;
+        if level < LEVELS {
+            let slot = (timer.at >> (level as u32 * SLOT_BITS)) as usize & (SLOTS - 1);
+            self.levels[level][slot].push(timer);
+        } else {
+            self.overflow.push(timer);
+        }
+        self.len += 1;
+        Ok(())
+    }
+
+    /// The next time a timer is due or has to move down a level.
+    fn next(&self) -> Option<u64> {
+        let slots = self.levels.iter().enumerate().filter_map(|(level, slots)| {
+            let shift = level as u32 * SLOT_BITS;
+            let current = (self.now >> shift) as usize & (SLOTS - 1);
+            let slot = (current + 1..SLOTS).find(|&slot| !slots[slot].is_empty())?;
+            let block = self.now >> (shift + SLOT_BITS) << (shift + SLOT_BITS);
+            Some(block + ((slot as u64) << shift))
+        });
+        let top = LEVELS as u32 * SLOT_BITS;
+        let overflow = self.overflow.iter().map(|timer| timer.at >> top << top);
+        slots.chain(overflow).min()
+    }
+
+    /// Moves the wheel to `to`, returning the timers that came due in
+    /// deadline order.
+    fn advance(&mut self, to: u64) -> Vec<Action> {
+        let mut due = vec![];
+        while let Some(at) = self.next().filter(|&at| at <= to) {
+            self.now = at;
+            let mut moving = mem::take(&mut self.overflow);
+            for (level, slots) in self.levels.iter_mut().enumerate() {
+                let slot = (at >> (level as u32 * SLOT_BITS)) as usize & (SLOTS - 1);
+                moving.append(&mut slots[slot]);
+            }
+            self.len -= moving.len();
+            for timer in moving {
+                if let Err(timer) = self.insert(timer) {
+                    due.push(timer.action);
+                }
+            }
+        }
+        self.now = self.now.max(to);
+        due
     }
 }
 
-type TimerKey = (Instant, u64);
+fn millis(instant: Instant) -> u64 {
+    instant.0.div_ceil(NANOS_PER_MILLI) as u64
+}
 
-pub struct TimerWheel {
-    // Single map for all timers
-    timers: Arc<Map<TimerKey, Timer>>,
-    current_tick: Arc<AtomicU64>,
-    backoff: Backoff,
+/// The current time in milliseconds since the epoch, rounded down unlike
+/// deadlines, so a timer never fires before its deadline.
+fn now() -> u64 {
+    (Instant::now().0 / NANOS_PER_MILLI) as u64
 }
 
-struct Timer {
-    action: Box<dyn FnOnce() + Send + Sync>,
+/// Timers of one worker, fired from its loop by [`TimerWheel::tick`].
+pub struct TimerWheel {
+    wheel: Mutex<Wheel>,
+    backoff: Backoff,
 }
 
 impl TimerWheel {
     pub fn new() -> Self {
         Self {
-            timers: Arc::new(Map::default()),
-            current_tick: Arc::new(AtomicU64::new(Instant::now().as_u128() as u64)),
+            wheel: Mutex::new(Wheel::new(now())),
             backoff: Backoff::with_step(Duration::from(1)),
         }
     }
@@ -345,50 +744,44 @@ impl TimerWheel {
         expires_in: Duration<Millis>,
         action: impl FnOnce() + Send + Sync + 'static,
     ) {
-        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
-        let expires_at = Instant::now() + expires_in;
-        self.timers
-            .insert(
-                (expires_at, NEXT_ID.fetch_add(1, Ordering::Relaxed)),
-                Timer {
-                    action: Box::new(action),
-                },
-            )
-            .await;
+        self.add_at(Instant::now() + expires_in, action).await;
     }
 
-    pub async fn tick(&self) {
-        let now = Instant::now();
-        self.current_tick.store(now.as_u128() as u64, Release);
-
-        loop {
-            match self.timers.first() {
-                Some(((expires_at, _), _)) if expires_at > &now => {
-                    break;
-                }
-                Some((key, _)) => {
-                    // Remove and execute expired timer
-                    if let Some(timer) = self.timers.remove(&key).await {
-                        dbg!("yo");
-                        (timer.action)();
-                    }
-                }
-                None => break, // No timers remaining
-            }
+    /// Runs `action` on the first tick at or after `expires_at`, right away
+    /// if that already passed.
+    pub async fn add_at(&self, expires_at: Instant, action: impl FnOnce() + Send + Sync + 'static) {
+        let timer = Timer {
+            at: millis(expires_at),
+            action: Box::new(action),
+        };
+        let result = self.wheel.lock().unwrap().insert(timer);
+        if let Err(timer) = result {
+            (timer.action)();
         }
     }
 
+    /// Fires every timer that is due.
+    pub async fn tick(&self) {
+        self.fire(now());
+    }
+
+    /// Fires the timers due within `duration` of the current tick, as if that
+    /// much time had passed.
     pub async fn advance(&self, duration: Duration<Millis>) {
-        let current = self.current_tick.load(Acquire) as u128;
-        let new_tick = current + duration.get().into_nanos();
-        self.current_tick.store(new_tick as u64, Release);
+        let current = self.wheel.lock().unwrap().now;
+        self.fire(current + duration.get().into_inner() as u64);
+    }
 
-        // Process any timers that would have expired during this advance
-        self.tick().await;
+    fn fire(&self, to: u64) {
+        // Actions may add timers, so they run without the lock
+        let due = self.wheel.lock().unwrap().advance(to);
+        for action in due {
+            action();
+        }
     }
 
     pub fn get_current_tick(&self) -> Instant {
-        Instant(self.current_tick.load(Acquire) as u128)
+        Instant(self.wheel.lock().unwrap().now as u128 * NANOS_PER_MILLI)
     }
 
     pub async fn wait_until(&self, target: Instant) {
@@ -398,7 +791,7 @@ impl TimerWheel {
     }
 
     pub fn len(&self) -> usize {
-        self.timers.len()
+        self.wheel.lock().unwrap().len
     }
 
     pub fn is_empty(&self) -> bool {
@@ -406,13 +799,15 @@ impl TimerWheel {
     }
 
     pub async fn clear(&self) {
-        while let Some((_, _)) = self.timers.first() {
-            self.timers.remove_first();
-        }
+        let mut wheel = self.wheel.lock().unwrap();
+        *wheel = Wheel::new(wheel.now);
     }
 
+    /// When the next timer is due, or rather the next time the wheel needs a
+    /// tick, which may come before any timer is due.
     pub async fn next_expiration(&self) -> Option<Instant> {
-        self.timers.first().map(|(instant, _)| instant.0)
+        let next = self.wheel.lock().unwrap().next();
+        next.map(|at| Instant(at as u128 * NANOS_PER_MILLI))
     }
 }
 
@@ -523,7 +918,7 @@ mod tests {
         let millis = Duration::new(Millis(500));
 
         // Convert to same unit for comparison
-        let total: Duration<Millis> = (seconds + millis.into::<Seconds>()).into();
+        let total = seconds.into::<Millis>() + millis;
         assert_eq!(total.get().into_inner(), 1500);
     }
 
@@ -546,7 +941,16 @@ mod tests {
     #[test]
     fn test_duration_display() {
         let duration = Duration::new(Seconds(1));
-        assert_eq!(format!("{:?}", duration), "Duration(Seconds(1))");
+        assert_eq!(format!("{:?}", duration), "1s");
+
+        assert_eq!(Duration::new(Millis(1_500)).to_string(), "1.5s");
+        assert_eq!(Duration::new(Millis(230)).to_string(), "230ms");
+        assert_eq!(Duration::new(Nanos(1_001)).to_string(), "1.001µs");
+        assert_eq!(Duration::new(Nanos(999)).to_string(), "999ns");
+        assert_eq!(Duration::new(Days(0)).to_string(), "0s");
+        assert_eq!(Duration::new(Minutes(150)).to_string(), "2h 30m");
+        assert_eq!(Duration::new(Millis(86_461_500)).to_string(), "1d 1m 1.5s");
+        assert_eq!(format!("{:>6}", Duration::new(Micros(5))), "   5µs");
     }
 
     #[test]
@@ -556,6 +960,150 @@ mod tests {
         let _ = large * 2;
     }
 
+    #[test]
+    fn test_duration_overflow_edges() {
+        let max = Duration::<Nanos>::from(u128::MAX);
+        let one = Duration::<Nanos>::from(1);
+        assert_eq!(max.checked_add(one), None);
+        assert_eq!(max.saturating_add(one), max);
+        assert_eq!(max.checked_sub(max), Some(Duration::INSTANT));
+        assert_eq!(one.checked_sub(max), None);
+        assert_eq!(one.saturating_sub(max), Duration::INSTANT);
+        assert_eq!(max.checked_mul(2u32), None);
+        assert_eq!(max.checked_mul(-1), None);
+        assert_eq!(max.saturating_mul(2u32), max);
+        assert_eq!(max.saturating_mul(-1), Duration::INSTANT);
+        assert_eq!(max.checked_div(0), None);
+        assert_eq!(max.checked_div(u128::MAX), Some(one));
+
+        // Coarse ticks near the maximum do not fit in nanoseconds
+        let days = Duration::new(Days(u128::MAX));
+        assert_eq!(days.try_into::<Nanos>(), Err(ConvertError::Overflow));
+        assert_eq!(
+            Duration::<Nanos>::try_from(days),
+            Err(ConvertError::Overflow)
+        );
+        assert_eq!(Instant::epoch().checked_add(days), None);
+        assert!(std::time::Duration::try_from(days).is_err());
+    }
+
+    #[test]
+    fn test_duration_lossless_conversion() {
+        let seconds = Duration::new(Seconds(3));
+        assert_eq!(
+            Duration::<Nanos>::try_from(seconds),
+            Ok(Duration::new(Nanos(3_000_000_000)))
+        );
+        assert_eq!(
+            Duration::<Minutes>::try_from(seconds),
+            Err(ConvertError::Inexact)
+        );
+        assert_eq!(seconds.into::<Minutes>(), Duration::new(Minutes(0)));
+        let hours: Duration<Hours> = Duration::new(Minutes(120)).try_into().unwrap();
+        assert_eq!(hours.get(), Hours(2));
+
+        let std = StdDuration::new(5, 250);
+        let nanos: Duration<Nanos> = std.into();
+        assert_eq!(nanos, Duration::new(Nanos(5_000_000_250)));
+        assert_eq!(StdDuration::try_from(nanos), Ok(std));
+        assert_eq!(
+            StdDuration::try_from(Duration::new(Hours(1))),
+            Ok(StdDuration::from_secs(3_600))
+        );
+        let too_long = Duration::new(Seconds(u64::MAX as u128 + 1));
+        assert_eq!(StdDuration::try_from(too_long), Err(ConvertError::Overflow));
+    }
+
+    #[test]
+    fn test_cross_unit_ordering() {
+        assert!(Duration::new(Millis(999)) < Duration::new(Seconds(1)));
+        assert!(Duration::new(Millis(1_001)) > Duration::new(Seconds(1)));
+        assert!(Duration::new(Seconds(1)) < Duration::new(Millis(1_001)));
+        assert_eq!(Duration::new(Minutes(60)), Duration::new(Hours(1)));
+        assert_ne!(Duration::new(Nanos(1)), Duration::new(Days(0)));
+
+        // Neither side fits in nanoseconds, but they still compare
+        let hours = Duration::new(Hours(u128::MAX));
+        let days = Duration::new(Days(u128::MAX / 24));
+        assert!(hours > days);
+        assert!(Duration::new(Days(u128::MAX / 24)) == Duration::new(Hours(u128::MAX / 24 * 24)));
+        assert!(Duration::new(Nanos(u128::MAX)) < Duration::new(Days(u128::MAX)));
+    }
+
+    #[test]
+    fn test_duration_scalars() {
+        let duration = Duration::new(Millis(300));
+        assert_eq!(duration * 3u32, Duration::new(Millis(900)));
+        assert_eq!(3 * duration, Duration::new(Millis(900)));
+        assert_eq!(duration * 0.5, Duration::new(Millis(150)));
+        assert_eq!(duration / 4u64, Duration::new(Millis(75)));
+        assert_eq!(
+            Duration::new(Millis(u128::MAX)) * 1u8,
+            Duration::new(Millis(u128::MAX))
+        );
+
+        let total: Duration<Millis> = [duration, duration].iter().sum();
+        assert_eq!(total, Duration::new(Millis(600)));
+    }
+
+    #[test]
+    fn test_instant_monotonic() {
+        let earlier = Instant::now();
+        let last = std::sync::Arc::new(Mutex::new(earlier));
+        let threads = (0..8)
+            .map(|_| {
+                let last = last.clone();
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        // Readings taken in turn never go backwards
+                        let mut last = last.lock().unwrap();
+                        let now = Instant::now();
+                        assert!(now >= *last);
+                        *last = now;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let later = Instant::now();
+        assert_eq!(earlier.duration_since(later), Duration::INSTANT);
+        assert_eq!(earlier.checked_duration_since(later), None);
+        assert_eq!(later.checked_duration_since(earlier), Some(later - earlier));
+        assert!(earlier.elapsed() >= later - earlier);
+    }
+
+    #[test]
+    fn test_wheel_levels() {
+        let fired = std::sync::Arc::new(Mutex::new(vec![]));
+        let mut wheel = Wheel::new(1_000);
+        // Level 0, the edges of levels 1 to 3, and the overflow list
+        let deadlines = [1_001, 1_063, 1_064, 5_000, 300_000, 17_000_000, 40_000_000];
+        for at in deadlines.into_iter().rev() {
+            let fired = fired.clone();
+            let action = Box::new(move || fired.lock().unwrap().push(at));
+            assert!(wheel.insert(Timer { at, action }).is_ok());
+        }
+        assert!(wheel.insert(Timer { at: 1_000, action: Box::new(|| {}) }).is_err());
+        assert_eq!(wheel.len, deadlines.len());
+
+        let mut to = 1_000;
+        for step in [1, 62, 1, 3_936, 295_000, 16_700_000, 23_000_000] {
+            to += step;
+            for action in wheel.advance(to) {
+                action();
+            }
+            let fired = fired.lock().unwrap();
+            assert!(fired.iter().all(|&at| at <= to), "fired early at {to}");
+            let expected = deadlines.iter().filter(|&&at| at <= to).copied();
+            assert_eq!(*fired, expected.collect::<Vec<_>>());
+        }
+        assert_eq!(wheel.len, 0);
+        assert_eq!(wheel.next(), None);
+    }
+
     #[test]
     fn test_duration_with_timer_wheel() {
         block_on(async {
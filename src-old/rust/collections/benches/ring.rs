@@ -0,0 +1,71 @@
+//! Four producers and one consumer moving values through `ring::Mpmc`
+//! against the skip list backed `queue::Queue`.
+
+use std::{hint, thread};
+
+use base::{
+    collections::{queue::Queue, ring::Mpmc},
+    rt::block_on,
+};
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+
+const PRODUCERS: usize = 4;
+const VALUES: usize = 10_000;
+
+fn four_producers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("4 producers");
+    group.throughput(Throughput::Elements((PRODUCERS * VALUES) as u64));
+
+    group.bench_function("ring::Mpmc", |b| {
+        b.iter(|| {
+            let ring = Mpmc::<usize, 1024>::new();
+            thread::scope(|scope| {
+                for _ in 0..PRODUCERS {
+                    scope.spawn(|| {
+                        for value in 0..VALUES {
+                            while ring.push(value).is_err() {
+                                hint::spin_loop();
+                            }
+                        }
+                    });
+                }
+                let mut received = 0;
+                while received < PRODUCERS * VALUES {
+                    if ring.pop().is_some() {
+                        received += 1;
+                    } else {
+                        hint::spin_loop();
+                    }
+                }
+            });
+        })
+    });
+
+    group.bench_function("queue::Queue", |b| {
+        b.iter(|| {
+            let queue = Queue::default();
+            thread::scope(|scope| {
+                for _ in 0..PRODUCERS {
+                    scope.spawn(|| {
+                        for value in 0..VALUES {
+                            block_on(queue.enqueue(value));
+                        }
+                    });
+                }
+                let mut received = 0;
+                while received < PRODUCERS * VALUES {
+                    if block_on(queue.dequeue()).is_some() {
+                        received += 1;
+                    } else {
+                        hint::spin_loop();
+                    }
+                }
+            });
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, four_producers);
+criterion_main!(benches);
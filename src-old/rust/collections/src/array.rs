@@ -4,6 +4,24 @@ use std::ops::{Deref, DerefMut, Index, IndexMut};
 use std::ptr::{self, NonNull};
 use std::slice;
 
+/// The element that did not fit into a full [`Array`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapacityError<T>(pub T);
+
+impl<T> CapacityError<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::fmt::Display for CapacityError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "insufficient capacity")
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for CapacityError<T> {}
+
 pub struct Array<T, const L: usize> {
     pub(crate) data: [MaybeUninit<T>; L],
     len: usize,
@@ -84,10 +102,18 @@ impl<T, const L: usize> Array<T, L> {
         self.len == L
     }
 
+    /// Appends `value`, panicking if the array is full.
+    #[inline]
+    pub fn push(&mut self, value: T) {
+        if self.try_push(value).is_err() {
+            panic!("Array capacity of {L} exceeded");
+        }
+    }
+
     #[inline]
-    pub fn push(&mut self, value: T) -> Result<(), T> {
+    pub fn try_push(&mut self, value: T) -> Result<(), CapacityError<T>> {
         if self.len >= L {
-            return Err(value);
+            return Err(CapacityError(value));
         }
         unsafe {
             self.data[self.len].write(value);
@@ -96,12 +122,33 @@ impl<T, const L: usize> Array<T, L> {
         Ok(())
     }
 
-    #[inline]
-    pub fn try_push(&mut self, value: T) -> bool {
-        match self.push(value) {
-            Ok(_) => true,
-            Err(_) => false,
+    /// Inserts `value` at `index`, shifting everything after it to the
+    /// right. Panics if the array is full or `index > len`.
+    pub fn insert(&mut self, index: usize, value: T) {
+        if self.try_insert(index, value).is_err() {
+            panic!("Array capacity of {L} exceeded");
+        }
+    }
+
+    /// Like [`insert`](Self::insert), but hands `value` back if the array is
+    /// full. Still panics if `index > len`.
+    pub fn try_insert(&mut self, index: usize, value: T) -> Result<(), CapacityError<T>> {
+        if index > self.len {
+            panic!(
+                "insertion index (is {index}) should be <= len (is {})",
+                self.len
+            );
+        }
+        if self.len >= L {
+            return Err(CapacityError(value));
+        }
+        unsafe {
+            let at = self.data.as_mut_ptr().add(index);
+            ptr::copy(at, at.add(1), self.len - index);
+            (*at).write(value);
         }
+        self.len += 1;
+        Ok(())
     }
 
     #[inline]
@@ -114,8 +161,76 @@ impl<T, const L: usize> Array<T, L> {
         }
     }
 
+    /// Drops every element from `len` on, if there are any.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let tail = ptr::slice_from_raw_parts_mut(
+            unsafe { self.data.as_mut_ptr().add(len) } as *mut T,
+            self.len - len,
+        );
+        // Shortened first, so a panicking drop cannot drop anything twice
+        self.len = len;
+        unsafe { ptr::drop_in_place(tail) };
+    }
+
     pub fn clear(&mut self) {
-        while self.pop().is_some() {}
+        self.truncate(0);
+    }
+
+    /// Keeps only the elements `keep` returns true for, in order.
+    pub fn retain(&mut self, mut keep: impl FnMut(&T) -> bool) {
+        /// Closes the gap left by removed elements even if `keep` panics.
+        struct Guard<'a, T, const L: usize> {
+            array: &'a mut Array<T, L>,
+            len: usize,
+            processed: usize,
+            removed: usize,
+        }
+
+        impl<T, const L: usize> Drop for Guard<'_, T, L> {
+            fn drop(&mut self) {
+                unsafe {
+                    let base = self.array.data.as_mut_ptr();
+                    ptr::copy(
+                        base.add(self.processed),
+                        base.add(self.processed - self.removed),
+                        self.len - self.processed,
+                    );
+                }
+                self.array.len = self.len - self.removed;
+            }
+        }
+
+        let len = self.len;
+        // Until the guard is done the array owns nothing, so elements being
+        // moved or dropped are never dropped again
+        self.len = 0;
+        let mut guard = Guard {
+            array: self,
+            len,
+            processed: 0,
+            removed: 0,
+        };
+        while guard.processed < len {
+            let slot = &mut guard.array.data[guard.processed];
+            let kept = keep(unsafe { slot.assume_init_ref() });
+            guard.processed += 1;
+            if !kept {
+                guard.removed += 1;
+                unsafe { slot.assume_init_drop() };
+            } else if guard.removed > 0 {
+                let base = guard.array.data.as_mut_ptr();
+                unsafe {
+                    ptr::copy_nonoverlapping(
+                        base.add(guard.processed - 1),
+                        base.add(guard.processed - 1 - guard.removed),
+                        1,
+                    );
+                }
+            }
+        }
     }
 
     pub fn as_slice(&self) -> &[T] {
@@ -339,7 +454,10 @@ impl<T, const L: usize> Iterator for IntoIter<T, L> {
 
 impl<T, const L: usize> Drop for IntoIter<T, L> {
     fn drop(&mut self) {
-        for i in self.index..self.array.len {
+        let (start, end) = (self.index, self.array.len);
+        // Everything before `index` was moved out already
+        self.array.len = 0;
+        for i in start..end {
             unsafe {
                 ptr::drop_in_place(self.array.data[i].as_mut_ptr());
             }
@@ -349,12 +467,10 @@ impl<T, const L: usize> Drop for IntoIter<T, L> {
 
 impl<T: Clone, const L: usize> Clone for Array<T, L> {
     fn clone(&self) -> Self {
+        // Pushed one by one, so a panicking clone drops only what was cloned
         let mut new = Self::new();
-        new.len = self.len;
-        for i in 0..self.len {
-            unsafe {
-                new.data[i] = MaybeUninit::new((*self.data[i].as_ptr()).clone());
-            }
+        for item in self.iter() {
+            new.push(item.clone());
         }
         new
     }
@@ -425,20 +541,28 @@ impl<T, const L: usize> Default for Array<T, L> {
     }
 }
 
+/// Panics once the array is full.
 impl<T, const L: usize> Extend<T> for Array<T, L> {
     fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
         for item in iter {
-            if self.len >= L {
-                break;
-            }
-            unsafe {
-                self.data[self.len].write(item);
-            }
-            self.len += 1;
+            self.push(item);
+        }
+    }
+}
+
+impl<T, const L: usize> Array<T, L> {
+    /// Collects `iter`, handing back the first element that did not fit.
+    pub fn try_from_iter<I: IntoIterator<Item = T>>(iter: I) -> Result<Self, CapacityError<T>> {
+        let mut array = Self::new();
+        for item in iter {
+            array.try_push(item)?;
         }
+        Ok(array)
     }
 }
 
+/// Panics if `iter` yields more than `L` elements, see
+/// [`Array::try_from_iter`] for the fallible version.
 impl<T, const L: usize> FromIterator<T> for Array<T, L> {
     fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
         let mut array = Self::new();
@@ -458,7 +582,7 @@ macro_rules! array {
     });
     ($elem:expr) => ({
         let mut a = base::collections::array::Array::<_, 1>::new();
-        a.push($elem).unwrap();
+        a.push($elem);
         a
     });
     ($($x:expr),+ $(,)?) => ({
@@ -485,10 +609,10 @@ mod tests {
     #[test]
     fn test_push_pop() {
         let mut array = Array::<i32, 3>::new();
-        assert!(array.push(1).is_ok());
-        assert!(array.push(2).is_ok());
-        assert!(array.push(3).is_ok());
-        assert!(array.push(4).is_err());
+        assert!(array.try_push(1).is_ok());
+        assert!(array.try_push(2).is_ok());
+        assert!(array.try_push(3).is_ok());
+        assert_eq!(array.try_push(4), Err(CapacityError(4)));
         assert_eq!(array.pop(), Some(3));
         assert_eq!(array.pop(), Some(2));
         assert_eq!(array.pop(), Some(1));
@@ -498,9 +622,9 @@ mod tests {
     #[test]
     fn test_iter() {
         let mut array = Array::<i32, 3>::new();
-        array.push(1).unwrap();
-        array.push(2).unwrap();
-        array.push(3).unwrap();
+        array.push(1);
+        array.push(2);
+        array.push(3);
         let mut iter = array.iter();
         assert_eq!(iter.next(), Some(&1));
         assert_eq!(iter.next(), Some(&2));
@@ -511,9 +635,9 @@ mod tests {
     #[test]
     fn test_into_iter() {
         let mut array = Array::<i32, 3>::new();
-        array.push(1).unwrap();
-        array.push(2).unwrap();
-        array.push(3).unwrap();
+        array.push(1);
+        array.push(2);
+        array.push(3);
         let mut iter = array.into_iter();
         assert_eq!(iter.next(), Some(1));
         assert_eq!(iter.next(), Some(2));
@@ -521,6 +645,130 @@ mod tests {
         assert_eq!(iter.next(), None);
     }
 
+    #[test]
+    #[should_panic(expected = "capacity of 2 exceeded")]
+    fn test_push_full() {
+        let mut array = Array::<i32, 2>::new();
+        array.extend([1, 2, 3]);
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut array = Array::<i32, 4>::new();
+        array.insert(0, 2);
+        array.insert(0, 0);
+        array.insert(1, 1);
+        array.insert(3, 3);
+        assert_eq!(array.as_slice(), [0, 1, 2, 3]);
+        assert_eq!(array.try_insert(2, 9), Err(CapacityError(9)));
+        assert_eq!(array.swap_remove(0), Some(0));
+        assert_eq!(array.as_slice(), [3, 1, 2]);
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut array = (0..10).collect::<Array<i32, 16>>();
+        array.retain(|&x| x % 3 != 0);
+        assert_eq!(array.as_slice(), [1, 2, 4, 5, 7, 8]);
+        array.truncate(2);
+        assert_eq!(array.as_slice(), [1, 2]);
+    }
+
+    #[test]
+    fn test_try_from_iter() {
+        let array = Array::<i32, 3>::try_from_iter(0..3).unwrap();
+        assert_eq!(array.as_slice(), [0, 1, 2]);
+        assert_eq!(Array::<i32, 3>::try_from_iter(0..5), Err(CapacityError(3)));
+    }
+
+    use std::{cell::Cell, rc::Rc};
+
+    /// Counts its drops in a shared counter.
+    #[derive(Clone)]
+    struct Counted(Rc<Cell<usize>>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    fn counted<const L: usize>(len: usize) -> (Array<Counted, L>, Rc<Cell<usize>>) {
+        let drops = Rc::new(Cell::new(0));
+        let array = (0..len).map(|_| Counted(drops.clone())).collect();
+        (array, drops)
+    }
+
+    #[test]
+    fn test_drops_each_element_once() {
+        let (array, drops) = counted::<8>(5);
+        drop(array);
+        assert_eq!(drops.get(), 5);
+
+        let (mut array, drops) = counted::<8>(5);
+        drop(array.pop());
+        drop(array.swap_remove(0));
+        array.truncate(2);
+        assert_eq!(drops.get(), 3);
+        array.clear();
+        assert_eq!(drops.get(), 5);
+        drop(array);
+        assert_eq!(drops.get(), 5);
+
+        // Half consumed, the rest dropped with the iterator
+        let (array, drops) = counted::<8>(6);
+        let mut iter = array.into_iter();
+        drop(iter.next());
+        drop(iter.next());
+        drop(iter);
+        assert_eq!(drops.get(), 6);
+
+        let (mut array, drops) = counted::<8>(6);
+        let mut index = 0;
+        array.retain(|_| {
+            index += 1;
+            index % 2 == 0
+        });
+        assert_eq!((array.len(), drops.get()), (3, 3));
+        let copy = array.clone();
+        drop((array, copy));
+        assert_eq!(drops.get(), 9);
+
+        let (array, drops) = counted::<2>(0);
+        let extra = [
+            Counted(drops.clone()),
+            Counted(drops.clone()),
+            Counted(drops.clone()),
+        ];
+        let Err(error) = Array::<_, 2>::try_from_iter(extra) else {
+            panic!("three elements fit in an Array of two");
+        };
+        // The two that fit, and the one that did not
+        drop(error);
+        assert_eq!(drops.get(), 3);
+        drop(array);
+    }
+
+    #[test]
+    fn test_retain_panic() {
+        let (mut array, drops) = counted::<8>(6);
+        let mut index = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            array.retain(|_| {
+                index += 1;
+                if index == 4 {
+                    panic!("in retain");
+                }
+                index % 2 == 0
+            })
+        }));
+        assert!(result.is_err());
+        // Two removed, one kept before the panic and three never looked at
+        assert_eq!((array.len(), drops.get()), (4, 2));
+        drop(array);
+        assert_eq!(drops.get(), 6);
+    }
+
     #[test]
     fn test_macro() {
         let array = array![1, 2, 3];
@@ -38,9 +38,7 @@ impl<T, const N: usize> ArrayVec<T, N> {
                 } else {
                     // Array is full, convert to Vec
                     let mut vec = Vec::with_capacity(N * 2);
-                    for i in 0..N {
-                        vec.push(unsafe { arr.data[i].assume_init_read() });
-                    }
+                    vec.extend(mem::take(arr));
                     vec.push(value);
                     self.0 = State::Vec(vec);
                 }
@@ -167,9 +165,7 @@ impl<T, const N: usize> FromIterator<T> for ArrayVec<T, N> {
 
             // Array full, need to convert to vec
             let mut vec = Vec::with_capacity(N * 2);
-            for i in 0..N {
-                vec.push(unsafe { arr.data[i].assume_init_read() });
-            }
+            vec.extend(arr);
 
             // Continue with remaining items
             vec.extend(iter);
@@ -0,0 +1,430 @@
+//! Chase-Lev work-stealing deque, after "Dynamic Circular Work-Stealing
+//! Deque" with the orderings of "Correct and Efficient Work-Stealing for Weak
+//! Memory Models".
+//!
+//! The [`Owner`] pushes and pops at the bottom, last in first out, and any
+//! number of [`Stealer`]s take from the top, oldest first. The buffer grows
+//! when full; buffers it outgrew are kept until the deque is dropped, as a
+//! stealer may still be reading them.
+//!
+//! The memory orderings are model checked with loom:
+//! `RUSTFLAGS="--cfg loom" cargo test --release deque::loom_tests`.
+
+use std::{mem::MaybeUninit, ops::Deref, ptr};
+
+#[cfg(loom)]
+use loom::{
+    cell::UnsafeCell,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicIsize, AtomicPtr, Ordering::*, fence},
+    },
+};
+#[cfg(not(loom))]
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicIsize, AtomicPtr, Ordering::*, fence},
+};
+
+/// `std::cell::UnsafeCell` with the closure API of loom's, so the deque
+/// reads the same under both.
+#[cfg(not(loom))]
+struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    fn new(value: T) -> Self {
+        Self(std::cell::UnsafeCell::new(value))
+    }
+
+    fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
+/// Keeps the top and bottom indices on cache lines of their own.
+#[repr(align(128))]
+struct CachePadded<T>(T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+const MIN_CAPACITY: usize = 64;
+
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+}
+
+impl<T> Buffer<T> {
+    fn alloc(capacity: usize) -> *mut Self {
+        debug_assert!(capacity.is_power_of_two());
+        let slots = (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect();
+        Box::into_raw(Box::new(Self { slots }))
+    }
+
+    fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn slot(&self, index: isize) -> &UnsafeCell<MaybeUninit<T>> {
+        &self.slots[index as usize & (self.capacity() - 1)]
+    }
+
+    /// # Safety
+    /// Nobody may read the slot of `index` until the write is published.
+    unsafe fn write(&self, index: isize, value: T) {
+        self.slot(index)
+            .with_mut(|slot| unsafe { (*slot).write(value) });
+    }
+
+    /// Copies the value out of the slot of `index`, which is only owned once
+    /// the index was claimed. Stealers may race a write here that their claim
+    /// then fails on, hence the volatile read.
+    ///
+    /// # Safety
+    /// The slot must have been written.
+    unsafe fn read(&self, index: isize) -> MaybeUninit<T> {
+        self.slot(index)
+            .with(|slot| unsafe { ptr::read_volatile(slot) })
+    }
+}
+
+struct Inner<T> {
+    /// Index of the oldest value, advanced by stealers, and by the owner
+    /// taking the last value.
+    top: CachePadded<AtomicIsize>,
+    /// Index the next value is pushed to, only written by the owner.
+    bottom: CachePadded<AtomicIsize>,
+    buffer: AtomicPtr<Buffer<T>>,
+    /// Buffers the deque outgrew, freed on drop.
+    retired: Mutex<Vec<*mut Buffer<T>>>,
+}
+
+impl<T> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let (top, bottom) = (self.top.load(Relaxed), self.bottom.load(Relaxed));
+        let buffer = self.buffer.load(Relaxed);
+        unsafe {
+            for index in top..bottom {
+                (*buffer).read(index).assume_init_drop();
+            }
+            drop(Box::from_raw(buffer));
+            for retired in self.retired.lock().unwrap().drain(..) {
+                drop(Box::from_raw(retired));
+            }
+        }
+    }
+}
+
+/// Creates an empty deque, returning its only [`Owner`] and a [`Stealer`]
+/// that can be cloned for everyone else.
+pub fn deque<T>() -> (Owner<T>, Stealer<T>) {
+    let inner = Arc::new(Inner {
+        top: CachePadded(AtomicIsize::new(0)),
+        bottom: CachePadded(AtomicIsize::new(0)),
+        buffer: AtomicPtr::new(Buffer::alloc(MIN_CAPACITY)),
+        retired: Mutex::new(Vec::new()),
+    });
+    (
+        Owner {
+            inner: inner.clone(),
+        },
+        Stealer { inner },
+    )
+}
+
+/// The end of a deque that pushes and pops, there is only one.
+pub struct Owner<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Owner<T> {}
+
+impl<T> Owner<T> {
+    pub fn push(&mut self, value: T) {
+        let inner = &*self.inner;
+        let bottom = inner.bottom.load(Relaxed);
+        let top = inner.top.load(Acquire);
+        let mut buffer = inner.buffer.load(Relaxed);
+        if bottom - top >= unsafe { (*buffer).capacity() } as isize {
+            buffer = self.grow(top, bottom);
+        }
+        unsafe { (*buffer).write(bottom, value) };
+        // Publishes the value before the stealers can see the new bottom
+        fence(Release);
+        inner.bottom.store(bottom + 1, Relaxed);
+    }
+
+    /// Takes the newest value, if there is one.
+    pub fn pop(&mut self) -> Option<T> {
+        let inner = &*self.inner;
+        let bottom = inner.bottom.load(Relaxed) - 1;
+        let buffer = inner.buffer.load(Relaxed);
+        inner.bottom.store(bottom, Relaxed);
+        // Either the stealers see the lower bottom, or we see their top
+        fence(SeqCst);
+        let top = inner.top.load(Relaxed);
+        if bottom < top {
+            inner.bottom.store(bottom + 1, Relaxed);
+            return None;
+        }
+        let value = unsafe { (*buffer).read(bottom) };
+        if bottom > top {
+            return Some(unsafe { value.assume_init() });
+        }
+        // The last value, which a stealer may be claiming too
+        let won = inner
+            .top
+            .compare_exchange(top, top + 1, SeqCst, Relaxed)
+            .is_ok();
+        inner.bottom.store(bottom + 1, Relaxed);
+        won.then(|| unsafe { value.assume_init() })
+    }
+
+    pub fn len(&self) -> usize {
+        let bottom = self.inner.bottom.load(Relaxed);
+        (bottom - self.inner.top.load(Acquire)).max(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Moves the values to a buffer twice the size.
+    fn grow(&self, top: isize, bottom: isize) -> *mut Buffer<T> {
+        let inner = &*self.inner;
+        let old = inner.buffer.load(Relaxed);
+        let new = Buffer::alloc(unsafe { (*old).capacity() } * 2);
+        for index in top..bottom {
+            unsafe {
+                let value = (*old).read(index);
+                (*new).slot(index).with_mut(|slot| *slot = value);
+            }
+        }
+        inner.retired.lock().unwrap().push(old);
+        inner.buffer.store(new, Release);
+        new
+    }
+}
+
+/// The end of a deque that takes the oldest values.
+pub struct Stealer<T> {
+    inner: Arc<Inner<T>>,
+}
+
+unsafe impl<T: Send> Send for Stealer<T> {}
+unsafe impl<T: Send> Sync for Stealer<T> {}
+
+impl<T> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Stealer<T> {
+    /// Takes the oldest value, if there is one.
+    pub fn steal(&self) -> Option<T> {
+        loop {
+            match self.try_steal() {
+                Steal::Empty => return None,
+                Steal::Taken(value) => return Some(value),
+                Steal::Lost => {}
+            }
+        }
+    }
+
+    /// Takes about half the values, oldest first. The first is returned along
+    /// with how many more were pushed to `into`.
+    pub fn steal_half(&self, into: &mut Owner<T>) -> Option<(T, usize)> {
+        let first = self.steal()?;
+        let mut moved = 0;
+        // One at a time, as the owner pops without claiming anything until
+        // only one value is left
+        for _ in 0..self.len().div_ceil(2) {
+            match self.try_steal() {
+                Steal::Taken(value) => into.push(value),
+                Steal::Empty | Steal::Lost => break,
+            }
+            moved += 1;
+        }
+        Some((first, moved))
+    }
+
+    pub fn len(&self) -> usize {
+        let top = self.inner.top.load(Acquire);
+        fence(SeqCst);
+        (self.inner.bottom.load(Acquire) - top).max(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn try_steal(&self) -> Steal<T> {
+        let inner = &*self.inner;
+        let top = inner.top.load(Acquire);
+        // Either the owner sees our top, or we see its lowered bottom
+        fence(SeqCst);
+        let bottom = inner.bottom.load(Acquire);
+        if bottom <= top {
+            return Steal::Empty;
+        }
+        let buffer = inner.buffer.load(Acquire);
+        let value = unsafe { (*buffer).read(top) };
+        match inner.top.compare_exchange(top, top + 1, SeqCst, Relaxed) {
+            Ok(_) => Steal::Taken(unsafe { value.assume_init() }),
+            Err(_) => Steal::Lost,
+        }
+    }
+}
+
+enum Steal<T> {
+    Empty,
+    Taken(T),
+    /// Another stealer or the owner claimed the value first.
+    Lost,
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use std::{
+        collections::HashSet,
+        sync::atomic::{AtomicBool, AtomicUsize},
+        thread,
+    };
+
+    #[test]
+    fn test_owner_lifo_stealer_fifo() {
+        let (mut owner, stealer) = deque();
+        for i in 0..4 {
+            owner.push(i);
+        }
+        assert_eq!(owner.pop(), Some(3));
+        assert_eq!(stealer.steal(), Some(0));
+        assert_eq!(owner.len(), 2);
+        assert_eq!(owner.pop(), Some(2));
+        assert_eq!(stealer.steal(), Some(1));
+        assert_eq!((owner.pop(), stealer.steal()), (None, None));
+        assert!(owner.is_empty() && stealer.is_empty());
+    }
+
+    #[test]
+    fn test_grows_and_drops_the_rest() {
+        let dropped = Arc::new(AtomicUsize::new(0));
+        struct Counted(Arc<AtomicUsize>);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, SeqCst);
+            }
+        }
+
+        let (mut owner, stealer) = deque();
+        for _ in 0..MIN_CAPACITY * 5 {
+            owner.push(Counted(dropped.clone()));
+        }
+        assert_eq!(stealer.len(), MIN_CAPACITY * 5);
+        drop(stealer.steal());
+        drop(owner.pop());
+        assert_eq!(dropped.load(SeqCst), 2);
+        drop((owner, stealer));
+        assert_eq!(dropped.load(SeqCst), MIN_CAPACITY * 5);
+    }
+
+    #[test]
+    fn test_steal_half() {
+        let (mut victim, stealer) = deque();
+        let (mut thief, _) = deque();
+        for i in 0..10 {
+            victim.push(i);
+        }
+        assert_eq!(stealer.steal_half(&mut thief), Some((0, 5)));
+        assert_eq!((victim.len(), thief.len()), (4, 5));
+        assert_eq!(thief.pop(), Some(5));
+        assert_eq!(victim.pop(), Some(9));
+    }
+
+    #[test]
+    fn test_each_value_taken_once() {
+        const VALUES: usize = 100_000;
+        let (mut owner, stealer) = deque();
+        let done = Arc::new(AtomicBool::new(false));
+        let stealers = (0..3)
+            .map(|_| {
+                let stealer = stealer.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    let (mut own, _) = deque();
+                    let mut taken = vec![];
+                    while !done.load(SeqCst) || !stealer.is_empty() {
+                        match stealer.steal_half(&mut own) {
+                            Some((value, _)) => taken.push(value),
+                            None => thread::yield_now(),
+                        }
+                        while let Some(value) = own.pop() {
+                            taken.push(value);
+                        }
+                    }
+                    taken
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut taken = vec![];
+        for i in 0..VALUES {
+            owner.push(i);
+            if i % 3 == 0
+                && let Some(value) = owner.pop()
+            {
+                taken.push(value);
+            }
+        }
+        done.store(true, SeqCst);
+        while let Some(value) = owner.pop() {
+            taken.push(value);
+        }
+        for stealer in stealers {
+            taken.extend(stealer.join().unwrap());
+        }
+        assert_eq!(taken.len(), VALUES);
+        assert_eq!(taken.into_iter().collect::<HashSet<_>>().len(), VALUES);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use loom::thread;
+
+    #[test]
+    fn last_value_taken_once() {
+        loom::model(|| {
+            let (mut owner, stealer) = deque();
+            owner.push(1);
+            owner.push(2);
+            let thief = thread::spawn(move || stealer.steal());
+            let popped = [owner.pop(), owner.pop()];
+            let stolen = thief.join().unwrap();
+            let mut taken = popped
+                .into_iter()
+                .chain([stolen])
+                .flatten()
+                .collect::<Vec<_>>();
+            taken.sort();
+            assert_eq!(taken, [1, 2]);
+        });
+    }
+}
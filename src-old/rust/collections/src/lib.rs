@@ -1,5 +1,7 @@
 pub mod array;
 pub mod arrayvec;
 pub mod bi;
+pub mod deque;
 pub mod queue;
+pub mod ring;
 pub mod skip;
@@ -0,0 +1,620 @@
+//! Bounded lock-free ring buffers: [`Spsc`] for one producer and one
+//! consumer, and the Vyukov-style [`Mpmc`] for any number of either.
+//!
+//! Both hand values back instead of blocking when full or empty, and have
+//! async adapters that park the task on the runtime until there is room or
+//! a value. Capacities must be powers of two, which is checked when the
+//! ring type is instantiated.
+//!
+//! The memory orderings are model checked with loom:
+//! `RUSTFLAGS="--cfg loom" cargo test --release ring::loom_tests`.
+
+use std::{
+    future::poll_fn,
+    mem::MaybeUninit,
+    ops::Deref,
+    task::{Poll, Waker},
+};
+
+#[cfg(loom)]
+use loom::{
+    cell::UnsafeCell,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering::*, fence},
+    },
+};
+#[cfg(not(loom))]
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, AtomicUsize, Ordering::*, fence},
+};
+
+/// `std::cell::UnsafeCell` with the closure API of loom's, so the rings
+/// read the same under both.
+#[cfg(not(loom))]
+struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    fn new(value: T) -> Self {
+        Self(std::cell::UnsafeCell::new(value))
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
+/// Keeps the producer and consumer indices on cache lines of their own.
+#[repr(align(128))]
+struct CachePadded<T>(T);
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Tasks waiting for the other side of a ring.
+struct Wakers {
+    /// Set while `wakers` is not empty, so the fast path skips the lock.
+    waiting: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl Wakers {
+    fn new() -> Self {
+        Self {
+            waiting: AtomicBool::new(false),
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Registers `waker`. The caller has to retry its operation afterwards,
+    /// the other side may have made progress before seeing the registration.
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|registered| registered.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+        self.waiting.store(true, Relaxed);
+        drop(wakers);
+        // Pairs with the fence in `wake`: either the retry sees the other
+        // side's progress, or the other side sees `waiting`
+        fence(SeqCst);
+    }
+
+    fn wake(&self) {
+        fence(SeqCst);
+        if !self.waiting.load(Relaxed) {
+            return;
+        }
+        let wakers = {
+            let mut wakers = self.wakers.lock().unwrap();
+            self.waiting.store(false, Relaxed);
+            std::mem::take(&mut *wakers)
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// Single-producer, single-consumer ring of `N` values. Split it into its
+/// [`Producer`] and [`Consumer`] to use it.
+pub struct Spsc<T, const N: usize> {
+    /// Position of the next value to pop, only written by the consumer.
+    head: CachePadded<AtomicUsize>,
+    /// Position of the next value to push, only written by the producer.
+    tail: CachePadded<AtomicUsize>,
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    senders: Wakers,
+    receivers: Wakers,
+}
+
+unsafe impl<T: Send, const N: usize> Send for Spsc<T, N> {}
+unsafe impl<T: Send, const N: usize> Sync for Spsc<T, N> {}
+
+impl<T, const N: usize> Spsc<T, N> {
+    const MASK: usize = {
+        assert!(N.is_power_of_two(), "ring capacity must be a power of two");
+        N - 1
+    };
+
+    pub fn new() -> Self {
+        let _ = Self::MASK;
+        Self {
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+            slots: std::array::from_fn(|_| UnsafeCell::new(MaybeUninit::uninit())),
+            senders: Wakers::new(),
+            receivers: Wakers::new(),
+        }
+    }
+
+    pub fn split(self) -> (Producer<T, N>, Consumer<T, N>) {
+        let ring = Arc::new(self);
+        (Producer { ring: ring.clone() }, Consumer { ring })
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<T, const N: usize> Default for Spsc<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Spsc<T, N> {
+    fn drop(&mut self) {
+        let (head, tail) = (self.head.load(Relaxed), self.tail.load(Relaxed));
+        for offset in 0..tail.wrapping_sub(head) {
+            self.slots[head.wrapping_add(offset) & Self::MASK].with_mut(|slot| unsafe {
+                (*slot).assume_init_drop();
+            });
+        }
+    }
+}
+
+pub struct Producer<T, const N: usize> {
+    ring: Arc<Spsc<T, N>>,
+}
+
+impl<T, const N: usize> Producer<T, N> {
+    /// Appends `value`, or hands it back if the ring is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        let ring = &*self.ring;
+        let tail = ring.tail.load(Relaxed);
+        if tail.wrapping_sub(ring.head.load(Acquire)) == N {
+            return Err(value);
+        }
+        ring.slots[tail & Spsc::<T, N>::MASK].with_mut(|slot| unsafe {
+            (*slot).write(value);
+        });
+        ring.tail.store(tail.wrapping_add(1), Release);
+        ring.receivers.wake();
+        Ok(())
+    }
+
+    /// Appends `value`, waiting for room while the ring is full.
+    pub async fn send(&mut self, value: T) {
+        let mut value = Some(value);
+        poll_fn(|cx| {
+            let Err(rejected) = self.push(value.take().unwrap()) else {
+                return Poll::Ready(());
+            };
+            self.ring.senders.register(cx.waker());
+            match self.push(rejected) {
+                Ok(()) => Poll::Ready(()),
+                Err(rejected) => {
+                    value = Some(rejected);
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.ring.tail.load(Relaxed);
+        tail.wrapping_sub(self.ring.head.load(Acquire))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub struct Consumer<T, const N: usize> {
+    ring: Arc<Spsc<T, N>>,
+}
+
+impl<T, const N: usize> Consumer<T, N> {
+    /// Takes the oldest value, if there is one.
+    pub fn pop(&mut self) -> Option<T> {
+        let ring = &*self.ring;
+        let head = ring.head.load(Relaxed);
+        if head == ring.tail.load(Acquire) {
+            return None;
+        }
+        let value = ring.slots[head & Spsc::<T, N>::MASK]
+            .with_mut(|slot| unsafe { (*slot).assume_init_read() });
+        ring.head.store(head.wrapping_add(1), Release);
+        ring.senders.wake();
+        Some(value)
+    }
+
+    /// Takes the oldest value, waiting for one while the ring is empty.
+    pub async fn recv(&mut self) -> T {
+        poll_fn(|cx| {
+            if let Some(value) = self.pop() {
+                return Poll::Ready(value);
+            }
+            self.ring.receivers.register(cx.waker());
+            self.pop().map_or(Poll::Pending, Poll::Ready)
+        })
+        .await
+    }
+
+    pub fn len(&self) -> usize {
+        let head = self.ring.head.load(Relaxed);
+        self.ring.tail.load(Acquire).wrapping_sub(head)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+struct Slot<T> {
+    /// Equals the position of the slot once it may be pushed to, and the
+    /// position plus one once it holds the value pushed there.
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// Multi-producer, multi-consumer ring of `N` values, after Dmitry Vyukov's
+/// bounded queue. Producers and consumers only contend on their own index
+/// and never wait for each other, except on a full or empty ring.
+pub struct Mpmc<T, const N: usize> {
+    /// Position of the next value to pop.
+    head: CachePadded<AtomicUsize>,
+    /// Position of the next value to push.
+    tail: CachePadded<AtomicUsize>,
+    slots: [Slot<T>; N],
+    senders: Wakers,
+    receivers: Wakers,
+}
+
+unsafe impl<T: Send, const N: usize> Send for Mpmc<T, N> {}
+unsafe impl<T: Send, const N: usize> Sync for Mpmc<T, N> {}
+
+impl<T, const N: usize> Mpmc<T, N> {
+    const MASK: usize = {
+        assert!(N.is_power_of_two(), "ring capacity must be a power of two");
+        N - 1
+    };
+
+    pub fn new() -> Self {
+        let _ = Self::MASK;
+        Self {
+            head: CachePadded(AtomicUsize::new(0)),
+            tail: CachePadded(AtomicUsize::new(0)),
+            slots: std::array::from_fn(|position| Slot {
+                sequence: AtomicUsize::new(position),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            }),
+            senders: Wakers::new(),
+            receivers: Wakers::new(),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Appends `value`, or hands it back if the ring is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let mut tail = self.tail.load(Relaxed);
+        loop {
+            let slot = &self.slots[tail & Self::MASK];
+            let lag = slot.sequence.load(Acquire).wrapping_sub(tail) as isize;
+            if lag < 0 {
+                // Still holds the value pushed a lap ago
+                return Err(value);
+            }
+            if lag > 0 {
+                // Another producer claimed this position already
+                tail = self.tail.load(Relaxed);
+                continue;
+            }
+            match self
+                .tail
+                .compare_exchange_weak(tail, tail.wrapping_add(1), Relaxed, Relaxed)
+            {
+                Ok(_) => {
+                    slot.value.with_mut(|value_slot| unsafe {
+                        (*value_slot).write(value);
+                    });
+                    slot.sequence.store(tail.wrapping_add(1), Release);
+                    self.receivers.wake();
+                    return Ok(());
+                }
+                Err(current) => tail = current,
+            }
+        }
+    }
+
+    /// Takes the oldest value, if there is one.
+    pub fn pop(&self) -> Option<T> {
+        let mut head = self.head.load(Relaxed);
+        loop {
+            let slot = &self.slots[head & Self::MASK];
+            let lag = slot
+                .sequence
+                .load(Acquire)
+                .wrapping_sub(head.wrapping_add(1)) as isize;
+            if lag < 0 {
+                // Not pushed to yet
+                return None;
+            }
+            if lag > 0 {
+                // Another consumer took this position already
+                head = self.head.load(Relaxed);
+                continue;
+            }
+            match self
+                .head
+                .compare_exchange_weak(head, head.wrapping_add(1), Relaxed, Relaxed)
+            {
+                Ok(_) => {
+                    let value = slot
+                        .value
+                        .with_mut(|value_slot| unsafe { (*value_slot).assume_init_read() });
+                    // Free for the push one lap ahead
+                    slot.sequence.store(head.wrapping_add(N), Release);
+                    self.senders.wake();
+                    return Some(value);
+                }
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Appends `value`, waiting for room while the ring is full.
+    pub async fn send(&self, value: T) {
+        let mut value = Some(value);
+        poll_fn(|cx| {
+            let Err(rejected) = self.push(value.take().unwrap()) else {
+                return Poll::Ready(());
+            };
+            self.senders.register(cx.waker());
+            match self.push(rejected) {
+                Ok(()) => Poll::Ready(()),
+                Err(rejected) => {
+                    value = Some(rejected);
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Takes the oldest value, waiting for one while the ring is empty.
+    pub async fn recv(&self) -> T {
+        poll_fn(|cx| {
+            if let Some(value) = self.pop() {
+                return Poll::Ready(value);
+            }
+            self.receivers.register(cx.waker());
+            self.pop().map_or(Poll::Pending, Poll::Ready)
+        })
+        .await
+    }
+}
+
+impl<T, const N: usize> Default for Mpmc<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for Mpmc<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use std::{collections::HashSet, thread};
+
+    #[test]
+    fn test_spsc_fifo() {
+        let (mut producer, mut consumer) = Spsc::<usize, 8>::new().split();
+        let sender = thread::spawn(move || {
+            for i in 0..100_000 {
+                while producer.push(i).is_err() {
+                    thread::yield_now();
+                }
+            }
+        });
+        for i in 0..100_000 {
+            let value = loop {
+                if let Some(value) = consumer.pop() {
+                    break value;
+                }
+                thread::yield_now();
+            };
+            assert_eq!(value, i);
+        }
+        sender.join().unwrap();
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn test_full_and_empty() {
+        let (mut producer, mut consumer) = Spsc::<u8, 2>::new().split();
+        assert_eq!(consumer.pop(), None);
+        producer.push(1).unwrap();
+        producer.push(2).unwrap();
+        assert_eq!(producer.push(3), Err(3));
+        assert_eq!(consumer.pop(), Some(1));
+        producer.push(3).unwrap();
+        assert_eq!(producer.len(), 2);
+
+        let ring = Mpmc::<u8, 2>::new();
+        assert_eq!(ring.pop(), None);
+        ring.push(1).unwrap();
+        ring.push(2).unwrap();
+        assert_eq!(ring.push(3), Err(3));
+        assert_eq!(ring.pop(), Some(1));
+        ring.push(3).unwrap();
+        assert_eq!(
+            (ring.pop(), ring.pop(), ring.pop()),
+            (Some(2), Some(3), None)
+        );
+    }
+
+    #[test]
+    fn test_mpmc_delivers_each_value_once() {
+        let ring = Arc::new(Mpmc::<usize, 16>::new());
+        let producers = (0..4)
+            .map(|producer| {
+                let ring = ring.clone();
+                thread::spawn(move || {
+                    for i in 0..10_000 {
+                        block_on(ring.send(producer * 10_000 + i));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let consumers = (0..4)
+            .map(|_| {
+                let ring = ring.clone();
+                thread::spawn(move || {
+                    (0..10_000)
+                        .map(|_| block_on(ring.recv()))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        let mut seen = HashSet::new();
+        for consumer in consumers {
+            let received = consumer.join().unwrap();
+            // Each producer's values arrive in order at any one consumer
+            for producer in 0..4 {
+                let mine = received.iter().filter(|&&value| value / 10_000 == producer);
+                assert!(mine.clone().zip(mine.skip(1)).all(|(a, b)| a < b));
+            }
+            for value in received {
+                assert!(seen.insert(value), "{value} received twice");
+            }
+        }
+        assert_eq!(seen.len(), 40_000);
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn test_async_adapters() {
+        let (mut producer, mut consumer) = Spsc::<String, 4>::new().split();
+        let sender = thread::spawn(move || {
+            block_on(async {
+                for i in 0..1_000 {
+                    producer.send(i.to_string()).await;
+                }
+            })
+        });
+        block_on(async {
+            for i in 0..1_000 {
+                assert_eq!(consumer.recv().await, i.to_string());
+            }
+        });
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_drops_values_left_behind() {
+        let value = Arc::new(());
+        let (mut producer, consumer) = Spsc::<Arc<()>, 4>::new().split();
+        producer.push(value.clone()).unwrap();
+        producer.push(value.clone()).unwrap();
+        drop((producer, consumer));
+
+        let ring = Mpmc::<Arc<()>, 4>::new();
+        ring.push(value.clone()).unwrap();
+        ring.push(value.clone()).unwrap();
+        drop(ring.pop());
+        drop(ring);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use loom::thread;
+
+    #[test]
+    fn spsc_hands_over_values() {
+        loom::model(|| {
+            let (mut producer, mut consumer) = Spsc::<usize, 2>::new().split();
+            let sender = thread::spawn(move || {
+                for i in 0..3 {
+                    while producer.push(i).is_err() {
+                        thread::yield_now();
+                    }
+                }
+            });
+            for i in 0..3 {
+                let value = loop {
+                    if let Some(value) = consumer.pop() {
+                        break value;
+                    }
+                    thread::yield_now();
+                };
+                assert_eq!(value, i);
+            }
+            sender.join().unwrap();
+        });
+    }
+
+    #[test]
+    fn mpmc_hands_over_values() {
+        loom::model(|| {
+            let ring = Arc::new(Mpmc::<usize, 2>::new());
+            let producers = (1..=2)
+                .map(|value| {
+                    let ring = ring.clone();
+                    thread::spawn(move || {
+                        while ring.push(value).is_err() {
+                            thread::yield_now();
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            let consumer = {
+                let ring = ring.clone();
+                thread::spawn(move || {
+                    loop {
+                        if let Some(value) = ring.pop() {
+                            break value;
+                        }
+                        thread::yield_now();
+                    }
+                })
+            };
+            let first = consumer.join().unwrap();
+            for producer in producers {
+                producer.join().unwrap();
+            }
+            let second = ring.pop().unwrap();
+            assert_eq!(first + second, 3);
+            assert_eq!(ring.pop(), None);
+        });
+    }
+
+    #[test]
+    fn waiting_receiver_is_woken() {
+        loom::model(|| {
+            let ring = Arc::new(Mpmc::<usize, 2>::new());
+            let receiver = {
+                let ring = ring.clone();
+                thread::spawn(move || loom::future::block_on(ring.recv()))
+            };
+            ring.push(7).unwrap();
+            assert_eq!(receiver.join().unwrap(), 7);
+        });
+    }
+}
@@ -1,8 +1,8 @@
-#![feature(trait_alias)]
 pub use list::List;
 pub mod list {
-    use chrono as time;
-    use rng::{self, Random, Range};
+    use crate::rng::{self, Random, Range};
+    use crate::sync::backoff::Backoff;
+    use crate::time::{Duration, Millis};
     use std::{
         marker::PhantomData,
         ptr::{self, NonNull},
@@ -43,7 +43,7 @@ pub mod list {
 
     #[test]
     fn test_basic_operations() {
-        rt::block_on(async {
+        crate::rt::block_on(async {
             let list = List::new();
 
             // Test insert
@@ -0,0 +1,446 @@
+use std::{
+    fs, io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::DockerError;
+
+/// A build context assembled from part of a directory, so that a build only
+/// uploads what it needs instead of the whole tree
+///
+/// Paths and patterns are relative to the root, with `/` separators. Patterns
+/// follow `.dockerignore`: `*` and `?` match within one path component, `**`
+/// matches any number of them, and a pattern matching a directory matches
+/// everything in it. The rules of a `.dockerignore` in the root come first,
+/// then the ones added here, and the last rule matching a path decides.
+#[derive(Debug, Clone)]
+pub struct BuildContext {
+    root: PathBuf,
+    rules: Vec<Rule>,
+    renames: Vec<(String, String)>,
+    files: Vec<(String, Vec<u8>)>,
+    dockerignore: bool,
+}
+
+/// The size of an assembled context
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContextStats {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// A context written out to a temporary directory, removed on drop
+pub struct AssembledContext {
+    dir: PathBuf,
+    stats: ContextStats,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: String,
+    exclude: bool,
+}
+
+impl BuildContext {
+    /// Start a context of everything under `root`
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            rules: Vec::new(),
+            renames: Vec::new(),
+            files: Vec::new(),
+            dockerignore: true,
+        }
+    }
+
+    /// Leave out the paths matching `pattern`
+    pub fn exclude(mut self, pattern: impl AsRef<str>) -> Self {
+        self.rules.push(Rule::new(pattern.as_ref(), true));
+        self
+    }
+
+    /// Take back the paths matching `pattern`, like `!pattern` in a
+    /// `.dockerignore`
+    pub fn include(mut self, pattern: impl AsRef<str>) -> Self {
+        self.rules.push(Rule::new(pattern.as_ref(), false));
+        self
+    }
+
+    /// Place the file or directory at `from` at `to` in the context
+    pub fn rename(mut self, from: impl AsRef<str>, to: impl AsRef<str>) -> Self {
+        self.renames
+            .push((normalize(from.as_ref()), normalize(to.as_ref())));
+        self
+    }
+
+    /// Add a file that is not in the tree, replacing any at `path`
+    pub fn file(mut self, path: impl AsRef<str>, contents: impl Into<Vec<u8>>) -> Self {
+        self.files.push((normalize(path.as_ref()), contents.into()));
+        self
+    }
+
+    /// Add a generated Dockerfile, which `docker build` picks up by default
+    pub fn dockerfile(self, contents: impl Into<String>) -> Self {
+        self.file("Dockerfile", contents.into())
+    }
+
+    /// Ignore the `.dockerignore` of the root, if there is one
+    pub fn ignore_dockerignore(mut self) -> Self {
+        self.dockerignore = false;
+        self
+    }
+
+    /// Copy the context into a new temporary directory
+    pub fn assemble(&self) -> Result<AssembledContext, DockerError> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let mut rules = Vec::new();
+        if self.dockerignore {
+            match fs::read_to_string(self.root.join(".dockerignore")) {
+                Ok(text) => rules.extend(parse_dockerignore(&text)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        rules.extend(self.rules.iter().cloned());
+
+        let mut paths = Vec::new();
+        walk(&self.root, Path::new(""), &rules, &mut paths)?;
+
+        let dir = std::env::temp_dir().join(format!(
+            "docker-context-{}-{}",
+            process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)?;
+        let mut context = AssembledContext {
+            dir,
+            stats: ContextStats::default(),
+        };
+
+        for path in paths {
+            let name = self.renamed(&path.to_string_lossy());
+            if self.files.iter().any(|(file, _)| *file == name) {
+                continue;
+            }
+            let src = self.root.join(&path);
+            let dest = context.dir.join(&name);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let metadata = fs::symlink_metadata(&src)?;
+            if metadata.file_type().is_symlink() {
+                symlink(fs::read_link(&src)?, &dest)?;
+            } else {
+                context.stats.bytes += fs::copy(&src, &dest)?;
+            }
+            context.stats.files += 1;
+        }
+
+        for (i, (name, contents)) in self.files.iter().enumerate() {
+            // A later file at the same path wins
+            if self.files[i + 1..].iter().any(|(later, _)| later == name) {
+                continue;
+            }
+            let dest = context.dir.join(name);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&dest, contents)?;
+            context.stats.files += 1;
+            context.stats.bytes += contents.len() as u64;
+        }
+
+        Ok(context)
+    }
+
+    /// Where `path` of the tree ends up in the context
+    fn renamed(&self, path: &str) -> String {
+        // The most specific rename applies
+        let rename = self
+            .renames
+            .iter()
+            .filter(|(from, _)| {
+                path == from
+                    || (path.starts_with(from.as_str()) && path[from.len()..].starts_with('/'))
+            })
+            .max_by_key(|(from, _)| from.len());
+        match rename {
+            Some((from, to)) => format!("{}{}", to, &path[from.len()..]),
+            None => path.to_string(),
+        }
+    }
+}
+
+impl AssembledContext {
+    pub fn path(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn stats(&self) -> ContextStats {
+        self.stats
+    }
+}
+
+impl Drop for AssembledContext {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl Rule {
+    fn new(pattern: &str, exclude: bool) -> Self {
+        Self {
+            pattern: normalize(pattern),
+            exclude,
+        }
+    }
+
+    /// Whether the rule matches `path` or a directory it is in
+    fn matches(&self, path: &[&str]) -> bool {
+        let pattern = self.pattern.split('/').collect::<Vec<_>>();
+        (1..=path.len()).any(|len| glob(&pattern, &path[..len]))
+    }
+}
+
+/// Whether the last rule matching `path` leaves it out
+fn excluded(rules: &[Rule], path: &str) -> bool {
+    let path = path.split('/').collect::<Vec<_>>();
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(&path))
+        .is_some_and(|rule| rule.exclude)
+}
+
+/// Collects the files and symlinks under `root.join(dir)` that are not
+/// excluded, relative to `root`
+fn walk(
+    root: &Path,
+    dir: &Path,
+    rules: &[Rule],
+    out: &mut Vec<PathBuf>,
+) -> Result<(), DockerError> {
+    // An excluded directory can only be skipped if no rule takes part of it
+    // back
+    let can_prune = rules.iter().all(|rule| rule.exclude);
+
+    let mut entries = fs::read_dir(root.join(dir))?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = dir.join(entry.file_name());
+        let name = path.to_string_lossy();
+        if name == ".dockerignore" {
+            continue;
+        }
+        let excluded = excluded(rules, &name);
+        if entry.file_type()?.is_dir() {
+            if !(excluded && can_prune) {
+                walk(root, &path, rules, out)?;
+            }
+        } else if !excluded {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The rules of a `.dockerignore`, skipping blank lines and comments
+fn parse_dockerignore(text: &str) -> Vec<Rule> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.strip_prefix('!') {
+            Some(pattern) => Rule::new(pattern.trim(), false),
+            None => Rule::new(line, true),
+        })
+        .collect()
+}
+
+/// `path` without a leading `/` or `./`, a trailing `/` or empty components
+fn normalize(path: &str) -> String {
+    path.split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether the components of `pattern` match those of `path`
+fn glob(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (Some(&"**"), _) => {
+            glob(&pattern[1..], path) || (!path.is_empty() && glob(pattern, &path[1..]))
+        }
+        (Some(part), Some(name)) => {
+            component(part.as_bytes(), name.as_bytes()) && glob(&pattern[1..], &path[1..])
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+/// Whether `pattern` matches one path component, `*` and `?` included
+fn component(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (Some(b'*'), _) => {
+            component(&pattern[1..], name) || (!name.is_empty() && component(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => component(&pattern[1..], &name[1..]),
+        (Some(a), Some(b)) => a == b && component(&pattern[1..], &name[1..]),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Docker;
+    use std::process::Command;
+
+    /// A source tree in a fresh temporary directory
+    fn tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("docker-tree-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    /// The files of an assembled context, relative to it
+    fn listing(dir: &Path) -> Vec<String> {
+        let mut paths = Vec::new();
+        walk(dir, Path::new(""), &[], &mut paths).unwrap();
+        paths
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_glob() {
+        let matches = |pattern: &str, path: &str| {
+            Rule::new(pattern, true).matches(&path.split('/').collect::<Vec<_>>())
+        };
+        assert!(matches("target", "target/debug/app"));
+        assert!(matches("*.log", "build.log"));
+        assert!(!matches("*.log", "logs/build.log"));
+        assert!(matches("**/*.log", "logs/build.log"));
+        assert!(matches("**/*.log", "build.log"));
+        assert!(matches("src/**/test?.rs", "src/a/b/test1.rs"));
+        assert!(!matches("src/**/test?.rs", "src/a/b/test10.rs"));
+        assert!(matches("/docs/", "docs/index.md"));
+        assert!(!matches("docs", "src/docs"));
+    }
+
+    #[test]
+    fn test_dockerignore_rules() {
+        let rules = parse_dockerignore("# build output\n\ntarget\n*.md\n!README.md\n");
+        assert_eq!(rules.len(), 3);
+        assert!(excluded(&rules, "target/debug/app"));
+        assert!(excluded(&rules, "CHANGES.md"));
+        assert!(!excluded(&rules, "README.md"));
+        assert!(!excluded(&rules, "src/lib.rs"));
+    }
+
+    #[test]
+    fn test_assemble() {
+        let root = tree(
+            "assemble",
+            &[
+                (".dockerignore", "target\n**/*.log\n"),
+                ("Cargo.toml", "[package]"),
+                ("src/lib.rs", "pub fn f() {}"),
+                ("src/notes.log", "noise"),
+                ("target/debug/app", "binary"),
+                ("assets/big.bin", "0123456789"),
+                ("assets/keep.txt", "keep"),
+                ("scripts/install.sh", "echo hi"),
+                ("Dockerfile", "FROM scratch"),
+            ],
+        );
+        let context = BuildContext::new(&root)
+            .exclude("assets")
+            .include("assets/keep.txt")
+            .rename("scripts", "bin")
+            .dockerfile("FROM busybox\n");
+        let assembled = context.assemble().unwrap();
+        assert_eq!(
+            listing(assembled.path()),
+            [
+                "Cargo.toml",
+                "Dockerfile",
+                "assets/keep.txt",
+                "bin/install.sh",
+                "src/lib.rs"
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(assembled.path().join("Dockerfile")).unwrap(),
+            "FROM busybox\n"
+        );
+        let bytes = [
+            "[package]",
+            "FROM busybox\n",
+            "keep",
+            "echo hi",
+            "pub fn f() {}",
+        ]
+        .iter()
+        .map(|contents| contents.len() as u64)
+        .sum();
+        assert_eq!(assembled.stats(), ContextStats { files: 5, bytes });
+
+        // Without the .dockerignore, only the rules added here apply
+        let assembled = context.ignore_dockerignore().assemble().unwrap();
+        assert!(listing(assembled.path()).contains(&"target/debug/app".to_string()));
+        let dir = assembled.path().to_owned();
+        drop(assembled);
+        assert!(!dir.exists());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_docker_build_context() {
+        if !Command::new("docker")
+            .arg("info")
+            .output()
+            .is_ok_and(|output| output.status.success())
+        {
+            return;
+        }
+        let root = tree(
+            "build",
+            &[
+                (".dockerignore", "secrets\n"),
+                ("secrets/key", "hunter2"),
+                ("src/main.c", "int main() {}"),
+                ("target/obj.o", "object"),
+            ],
+        );
+        let context = BuildContext::new(&root)
+            .exclude("target")
+            .dockerfile("FROM busybox\nCOPY . /ctx\n");
+        let tag = format!("docker-context-test:{}", process::id());
+        let (result, stats) =
+            Docker::build_image_from_context(&context, &tag, &[] as &[(&str, &str)]).unwrap();
+        assert!(result.success, "{}", result.stderr);
+        assert_eq!(stats.files, 2);
+
+        let listing = Docker::command(["run", "--rm", &tag, "ls", "-R", "/ctx"]).unwrap();
+        assert!(listing.contains("main.c"));
+        assert!(listing.contains("Dockerfile"));
+        assert!(!listing.contains("key"));
+        assert!(!listing.contains("obj.o"));
+
+        Docker::remove_image(&tag).unwrap();
+        fs::remove_dir_all(root).unwrap();
+    }
+}
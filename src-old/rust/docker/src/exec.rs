@@ -0,0 +1,382 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    os::{fd::FromRawFd, fd::OwnedFd, unix::process::CommandExt},
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use crate::{CommandResult, DockerError};
+
+/// Options for an interactive `docker exec` session
+#[derive(Debug, Clone)]
+pub struct ExecOptions {
+    pub tty: bool,
+    /// Initial terminal size as (cols, rows), only used with a TTY
+    pub size: (u16, u16),
+    pub env_vars: Vec<(String, String)>,
+    pub working_dir: Option<String>,
+    pub user: Option<String>,
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        Self {
+            tty: false,
+            size: (80, 24),
+            env_vars: Vec::new(),
+            working_dir: None,
+            user: None,
+        }
+    }
+}
+
+impl ExecOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate a pseudo terminal for the command, like `docker exec -it`
+    pub fn tty(mut self, tty: bool) -> Self {
+        self.tty = tty;
+        self
+    }
+
+    pub fn size(mut self, cols: u16, rows: u16) -> Self {
+        self.size = (cols, rows);
+        self
+    }
+
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env_vars.push((key.into(), value.into()));
+        self
+    }
+
+    pub fn working_dir(mut self, dir: impl Into<String>) -> Self {
+        self.working_dir = Some(dir.into());
+        self
+    }
+
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    /// Arguments for `docker` running `cmd` in `container` with these options
+    pub(crate) fn args<S: AsRef<str>>(&self, container: &str, cmd: &[S]) -> Vec<String> {
+        let mut args = vec!["exec".to_string(), "-i".to_string()];
+        if self.tty {
+            args.push("-t".to_string());
+        }
+        for (key, value) in &self.env_vars {
+            args.push("-e".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+        if let Some(dir) = &self.working_dir {
+            args.push("-w".to_string());
+            args.push(dir.clone());
+        }
+        if let Some(user) = &self.user {
+            args.push("-u".to_string());
+            args.push(user.clone());
+        }
+        args.push(container.to_string());
+        args.extend(cmd.iter().map(|arg| arg.as_ref().to_string()));
+        args
+    }
+}
+
+enum SessionIo {
+    Pipes {
+        stdin: Option<ChildStdin>,
+        stdout: ChildStdout,
+        stderr: Option<ChildStderr>,
+    },
+    /// Host side of the PTY; stdout and stderr both arrive here
+    Pty { master: File },
+}
+
+/// A running command with its stdio attached. Bytes are passed through as-is:
+/// with a TTY the terminal is in raw mode, so there is no line buffering or
+/// echo and control sequences reach the reader untouched.
+pub struct AttachedSession {
+    child: Option<Child>,
+    io: SessionIo,
+}
+
+impl AttachedSession {
+    /// Spawns `command` with pipes, or with a fresh PTY as its controlling
+    /// terminal when `options.tty` is set.
+    pub(crate) fn spawn(mut command: Command, options: &ExecOptions) -> Result<Self, DockerError> {
+        if !options.tty {
+            let mut child = command
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            let io = SessionIo::Pipes {
+                stdin: child.stdin.take(),
+                stdout: child.stdout.take().unwrap(),
+                stderr: child.stderr.take(),
+            };
+            return Ok(Self {
+                child: Some(child),
+                io,
+            });
+        }
+
+        let (master, slave) = open_pty(options.size)?;
+        command
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        // SAFETY: only async-signal-safe calls between fork and exec
+        unsafe {
+            command.pre_exec(|| {
+                // New session with the PTY as controlling terminal, so resizes
+                // deliver SIGWINCH to the child (the docker CLI forwards them)
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        // The slave copies held by `command` must close here, otherwise reads
+        // on the master never see the child hang up
+        drop(command);
+
+        Ok(Self {
+            child: Some(child),
+            io: SessionIo::Pty { master },
+        })
+    }
+
+    pub fn is_tty(&self) -> bool {
+        matches!(self.io, SessionIo::Pty { .. })
+    }
+
+    /// Resizes the terminal to `cols` x `rows`
+    pub fn resize(&self, cols: u16, rows: u16) -> Result<(), DockerError> {
+        let SessionIo::Pty { master } = &self.io else {
+            return Err(DockerError {
+                message: "Cannot resize a session without a TTY".to_string(),
+            });
+        };
+        set_size(master, (cols, rows))
+    }
+
+    /// Takes the stderr pipe. Sessions with a TTY have none, their error
+    /// output is part of the terminal stream.
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        match &mut self.io {
+            SessionIo::Pipes { stderr, .. } => stderr.take(),
+            SessionIo::Pty { .. } => None,
+        }
+    }
+
+    /// Signals end of input to a piped session. With a TTY input stays open
+    /// until the session is closed.
+    pub fn close_stdin(&mut self) {
+        if let SessionIo::Pipes { stdin, .. } = &mut self.io {
+            stdin.take();
+        }
+    }
+
+    /// Waits for the command to exit and collects whatever output was not read yet
+    pub fn close(mut self) -> Result<CommandResult, DockerError> {
+        self.close_stdin();
+
+        let mut stdout = Vec::new();
+        self.read_to_end(&mut stdout)?;
+        let mut stderr = Vec::new();
+        if let Some(mut pipe) = self.take_stderr() {
+            pipe.read_to_end(&mut stderr)?;
+        }
+
+        let status = self.child.take().unwrap().wait()?;
+        Ok(CommandResult {
+            success: status.success(),
+            stdout: String::from_utf8_lossy(&stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&stderr).into_owned(),
+            exit_code: status.code().unwrap_or(-1),
+        })
+    }
+}
+
+impl Read for AttachedSession {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.io {
+            SessionIo::Pipes { stdout, .. } => stdout.read(buf),
+            // Linux reports a hung up PTY as EIO rather than end of file
+            SessionIo::Pty { master } => match master.read(buf) {
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+                result => result,
+            },
+        }
+    }
+}
+
+impl Write for AttachedSession {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.io {
+            SessionIo::Pipes { stdin: Some(stdin), .. } => stdin.write(buf),
+            SessionIo::Pipes { stdin: None, .. } => Err(io::ErrorKind::BrokenPipe.into()),
+            SessionIo::Pty { master } => master.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.io {
+            SessionIo::Pipes { stdin: Some(stdin), .. } => stdin.flush(),
+            SessionIo::Pipes { stdin: None, .. } => Ok(()),
+            SessionIo::Pty { master } => master.flush(),
+        }
+    }
+}
+
+impl Drop for AttachedSession {
+    fn drop(&mut self) {
+        // Reap a session that was never closed so it does not linger as a zombie
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Opens a PTY pair of the given size with the slave side in raw mode
+fn open_pty(size: (u16, u16)) -> Result<(File, OwnedFd), DockerError> {
+    let mut master = -1;
+    let mut slave = -1;
+    let winsize = winsize(size);
+    // SAFETY: openpty fills both descriptors on success, which we then own
+    let (master, slave) = unsafe {
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        libc::cfmakeraw(&mut termios);
+        termios.c_cflag |= libc::CREAD;
+        if libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), &termios, &winsize) < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        (File::from_raw_fd(master), OwnedFd::from_raw_fd(slave))
+    };
+    Ok((master, slave))
+}
+
+fn set_size(master: &File, size: (u16, u16)) -> Result<(), DockerError> {
+    use std::os::fd::AsRawFd;
+
+    let winsize = winsize(size);
+    // SAFETY: TIOCSWINSZ only reads the winsize we pass
+    if unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &winsize) } < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+fn winsize((cols, rows): (u16, u16)) -> libc::winsize {
+    libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    /// Reads until `needle` shows up or the session ends
+    fn read_until(session: &mut AttachedSession, needle: &str) -> String {
+        let mut out = Vec::new();
+        let mut buf = [0; 256];
+        while !String::from_utf8_lossy(&out).contains(needle) {
+            match session.read(&mut buf).unwrap() {
+                0 => break,
+                n => out.extend_from_slice(&buf[..n]),
+            }
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+
+    #[test]
+    fn test_exec_args() {
+        let options = ExecOptions::new().tty(true).env("TERM", "xterm").working_dir("/srv");
+        assert_eq!(
+            options.args("box", &["stty", "size"]),
+            ["exec", "-i", "-t", "-e", "TERM=xterm", "-w", "/srv", "box", "stty", "size"]
+        );
+        assert_eq!(ExecOptions::new().args("box", &["ls"]), ["exec", "-i", "box", "ls"]);
+    }
+
+    #[test]
+    fn test_tty_resize() {
+        let options = ExecOptions::new().tty(true).size(100, 30);
+        let mut session = AttachedSession::spawn(sh("stty size; read _; stty size"), &options).unwrap();
+        assert!(session.is_tty());
+        assert!(read_until(&mut session, "30 100").contains("30 100"));
+
+        session.resize(132, 43).unwrap();
+        session.write_all(b"\n").unwrap();
+        let result = session.close().unwrap();
+        assert!(result.success);
+        // Raw mode: no echo of the newline and no CRLF translation
+        assert_eq!(result.stdout, "43 132\n");
+    }
+
+    #[test]
+    fn test_tty_control_sequences() {
+        let options = ExecOptions::new().tty(true);
+        let mut session = AttachedSession::spawn(sh("head -c 4"), &options).unwrap();
+        session.write_all(b"\x1b[A\x03").unwrap();
+        let result = session.close().unwrap();
+        assert!(result.success);
+        assert_eq!(result.stdout, "\x1b[A\x03");
+    }
+
+    #[test]
+    fn test_pipes_unchanged() {
+        let mut session = AttachedSession::spawn(sh("cat; echo oops >&2; exit 3"), &ExecOptions::new()).unwrap();
+        assert!(!session.is_tty());
+        assert!(session.resize(80, 24).is_err());
+        session.write_all(b"line one\nline two\n").unwrap();
+        let result = session.close().unwrap();
+        assert_eq!(result.stdout, "line one\nline two\n");
+        assert_eq!(result.stderr, "oops\n");
+        assert_eq!(result.exit_code, 3);
+        assert!(!result.success);
+    }
+
+    #[test]
+    fn test_drop_reaps() {
+        let session = AttachedSession::spawn(sh("sleep 30"), &ExecOptions::new().tty(true)).unwrap();
+        let pid = session.child.as_ref().unwrap().id();
+        drop(session);
+        // SAFETY: signal 0 only checks whether the pid still exists
+        assert_eq!(unsafe { libc::kill(pid as libc::pid_t, 0) }, -1);
+    }
+
+    #[test]
+    fn test_docker_stty_size() {
+        if !Command::new("docker")
+            .arg("info")
+            .output()
+            .is_ok_and(|output| output.status.success())
+        {
+            return;
+        }
+        let options = ExecOptions::new().tty(true).size(90, 20);
+        let mut command = Command::new("docker");
+        command.args(["run", "--rm", "-i", "-t", "busybox", "sh", "-c", "read _; stty size"]);
+        let mut session = AttachedSession::spawn(command, &options).unwrap();
+        session.resize(120, 40).unwrap();
+        session.write_all(b"\r").unwrap();
+        assert!(read_until(&mut session, "40 120").contains("40 120"));
+    }
+}
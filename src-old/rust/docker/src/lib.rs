@@ -1,5 +1,19 @@
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error, fmt, path::Path, process::Command};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    path::Path,
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
+
+mod context;
+mod exec;
+
+pub use context::{AssembledContext, BuildContext, ContextStats};
+pub use exec::{AttachedSession, ExecOptions};
 
 /// Error type for Docker operations
 #[derive(Debug)]
@@ -52,6 +66,61 @@ pub struct ContainerConfig {
     pub restart_policy: Option<String>,
     pub working_dir: Option<String>,
     pub platform: Option<String>, // New field for platform specification
+    pub healthcheck: Option<Healthcheck>,
+}
+
+/// A probe Docker runs in the container to tell whether it is ready, in place
+/// of any HEALTHCHECK of the image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Healthcheck {
+    /// The probe and its arguments, healthy if it exits with 0
+    pub cmd: Vec<String>,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Failures in a row before the container is unhealthy
+    pub retries: u32,
+    /// Time to start up, during which failures are not counted
+    pub start_period: Duration,
+}
+
+impl Healthcheck {
+    /// The options of `docker container create` that set up the probe
+    pub(crate) fn args(&self) -> Vec<String> {
+        // Docker runs the command with a shell, so every argument is quoted
+        let cmd = self
+            .cmd
+            .iter()
+            .map(|arg| format!("'{}'", arg.replace('\'', r"'\''")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        vec![
+            "--health-cmd".to_string(),
+            cmd,
+            "--health-interval".to_string(),
+            go_duration(self.interval),
+            "--health-timeout".to_string(),
+            go_duration(self.timeout),
+            "--health-retries".to_string(),
+            self.retries.to_string(),
+            "--health-start-period".to_string(),
+            go_duration(self.start_period),
+        ]
+    }
+}
+
+/// `duration` in the syntax of Go's `time.ParseDuration`, which Docker reads
+/// durations with, like "500ms" or "3s"
+fn go_duration(duration: Duration) -> String {
+    let nanos = duration.subsec_nanos();
+    if nanos == 0 {
+        format!("{}s", duration.as_secs())
+    } else if nanos.is_multiple_of(1_000_000) {
+        format!("{}ms", duration.as_millis())
+    } else if nanos.is_multiple_of(1_000) {
+        format!("{}us", duration.as_micros())
+    } else {
+        format!("{}ns", duration.as_nanos())
+    }
 }
 /// Docker's container configuration structure used when parsing API responses
 #[derive(Debug, Clone, Deserialize)]
@@ -182,6 +251,33 @@ pub struct ContainerState {
     pub restarting: bool,
     #[serde(rename = "ExitCode")]
     pub exit_code: i32,
+    /// Only present for containers with a healthcheck
+    #[serde(rename = "Health", default)]
+    pub health: Option<Health>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Health {
+    /// "starting", "healthy" or "unhealthy"
+    #[serde(rename = "Status")]
+    pub status: String,
+    #[serde(rename = "FailingStreak", default)]
+    pub failing_streak: u32,
+    /// The most recent probes, oldest first
+    #[serde(rename = "Log", default)]
+    pub log: Vec<HealthProbe>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthProbe {
+    #[serde(rename = "Start")]
+    pub start: String,
+    #[serde(rename = "End")]
+    pub end: String,
+    #[serde(rename = "ExitCode")]
+    pub exit_code: i32,
+    #[serde(rename = "Output")]
+    pub output: String,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -268,6 +364,9 @@ pub struct GraphDriver {
 }
 
 /// Container represents a Docker container
+///
+/// Methods shell out to the `docker` CLI and block until it exits; from a task
+/// on the runtime, call them through `rt::spawn_blocking`.
 pub struct Container {
     name: String,
     id: Option<String>,
@@ -433,6 +532,39 @@ impl Container {
         self.info.as_ref().map(|info| info.state.status.as_str())
     }
 
+    /// Get the state of the healthcheck, if the container has one
+    pub fn health(&self) -> Option<&Health> {
+        self.info.as_ref().and_then(|info| info.state.health.as_ref())
+    }
+
+    /// Wait until the container is healthy, or just running if it has no
+    /// healthcheck. Fails if it stops or `timeout` runs out first.
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<(), DockerError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            self.refresh()?;
+            if !self.running() {
+                return Err(DockerError {
+                    message: format!("Container {} is not running", self.name),
+                });
+            }
+            match self.health() {
+                None => return Ok(()),
+                Some(health) if health.status == "healthy" => return Ok(()),
+                Some(health) if Instant::now() >= deadline => {
+                    let output = health.log.last().map_or("", |probe| probe.output.trim());
+                    return Err(DockerError {
+                        message: format!(
+                            "Container {} is still {} after {:?}: {}",
+                            self.name, health.status, timeout, output
+                        ),
+                    });
+                }
+                Some(_) => thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+
     /// Get container IP address
     pub fn ip_address(&self) -> Option<&str> {
         self.info
@@ -534,6 +666,27 @@ impl Container {
         Docker::exec_container(&self.name, cmd)
     }
 
+    /// Start a command in the container with its stdio attached
+    pub fn attach<S: AsRef<str>>(
+        &self,
+        cmd: &[S],
+        options: &ExecOptions,
+    ) -> Result<AttachedSession, DockerError> {
+        if !self.exists() {
+            return Err(DockerError {
+                message: format!("Container {} does not exist", self.name),
+            });
+        }
+
+        if !self.running() {
+            return Err(DockerError {
+                message: format!("Container {} is not running", self.name),
+            });
+        }
+
+        Docker::attach_container(&self.name, cmd, options)
+    }
+
     /// Get container logs
     pub fn logs(&self, tail: Option<usize>) -> Result<String, DockerError> {
         if !self.exists() {
@@ -808,6 +961,10 @@ impl Docker {
             args_owned.push(volume_mapping);
         }
 
+        if let Some(healthcheck) = &config.healthcheck {
+            args_owned.extend(healthcheck.args());
+        }
+
         if config.labels.get("privileged") == Some(&"true".to_string()) {
             args_owned.push("--privileged".to_string());
         }
@@ -892,6 +1049,17 @@ impl Docker {
         Docker::command_with_result(&["exec", name.as_ref(), "bash", "-c", &args_owned.join(" ")])
     }
 
+    /// Start a command in a running container with its stdio attached
+    pub fn attach_container<S: AsRef<str>>(
+        name: impl AsRef<str>,
+        cmd: &[S],
+        options: &ExecOptions,
+    ) -> Result<AttachedSession, DockerError> {
+        let mut command = Command::new("docker");
+        command.args(options.args(name.as_ref(), cmd));
+        AttachedSession::spawn(command, options)
+    }
+
     /// Get container logs
     pub fn container_logs(
         name: impl AsRef<str>,
@@ -940,6 +1108,29 @@ impl Docker {
         Ok(names)
     }
 
+    /// List all containers, running or not, carrying `label`, given as
+    /// `key` or `key=value`
+    pub fn list_containers_with_label(
+        label: impl AsRef<str>,
+    ) -> Result<Vec<Container>, DockerError> {
+        let filter = format!("--filter=label={}", label.as_ref());
+        let output = Docker::command(["container", "ls", "-a", "--format={{.Names}}", &filter])?;
+        let names = output
+            .lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty())
+            .map(Container::new)
+            .collect();
+
+        Ok(names)
+    }
+
+    /// Remove a container, stopping it first if it is running
+    pub fn force_remove_container(name: impl AsRef<str>) -> Result<(), DockerError> {
+        Docker::command(["container", "rm", "-f", name.as_ref()])?;
+        Ok(())
+    }
+
     /// List all images
     pub fn list_images() -> Result<Vec<Image>, DockerError> {
         let args_owned = vec![
@@ -1004,6 +1195,18 @@ impl Docker {
         Docker::command_with_result(&args_ref)
     }
 
+    /// Build an image from an assembled context instead of a whole
+    /// directory, returning the size of the context with the result
+    pub fn build_image_from_context(
+        context: &BuildContext,
+        tag: impl AsRef<str>,
+        build_args: &[(impl AsRef<str>, impl AsRef<str>)],
+    ) -> Result<(CommandResult, ContextStats), DockerError> {
+        let assembled = context.assemble()?;
+        let result = Docker::build_image(assembled.path(), tag, None::<&Path>, build_args)?;
+        Ok((result, assembled.stats()))
+    }
+
     /// Check Docker daemon status
     pub fn is_available() -> bool {
         let result = Command::new("docker").args(["info"]).output();
@@ -1106,8 +1309,156 @@ impl ContainerConfigBuilder {
         self
     }
 
+    /// Probe the container with `cmd` every `interval`, see [`Healthcheck`]
+    pub fn healthcheck(
+        mut self,
+        cmd: Vec<impl Into<String>>,
+        interval: Duration,
+        timeout: Duration,
+        retries: u32,
+        start_period: Duration,
+    ) -> Self {
+        self.config.healthcheck = Some(Healthcheck {
+            cmd: cmd.into_iter().map(|s| s.into()).collect(),
+            interval,
+            timeout,
+            retries,
+            start_period,
+        });
+        self
+    }
+
     /// Build the ContainerConfig
     pub fn build(self) -> ContainerConfig {
         self.config
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn docker_available() -> bool {
+        Command::new("docker")
+            .arg("info")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    #[test]
+    fn test_go_duration() {
+        assert_eq!(go_duration(Duration::from_millis(500)), "500ms");
+        assert_eq!(go_duration(Duration::from_secs(3)), "3s");
+        assert_eq!(go_duration(Duration::from_millis(1500)), "1500ms");
+        assert_eq!(go_duration(Duration::from_micros(1)), "1us");
+        assert_eq!(go_duration(Duration::from_nanos(7)), "7ns");
+        assert_eq!(go_duration(Duration::ZERO), "0s");
+    }
+
+    #[test]
+    fn test_healthcheck_args() {
+        let config = container_config()
+            .healthcheck(
+                vec!["test", "-f", "it's ready"],
+                Duration::from_millis(500),
+                Duration::from_secs(3),
+                2,
+                Duration::ZERO,
+            )
+            .build();
+        assert_eq!(
+            config.healthcheck.unwrap().args(),
+            [
+                "--health-cmd",
+                r"'test' '-f' 'it'\''s ready'",
+                "--health-interval",
+                "500ms",
+                "--health-timeout",
+                "3s",
+                "--health-retries",
+                "2",
+                "--health-start-period",
+                "0s",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_health() {
+        let state: ContainerState = serde_json::from_str(
+            r#"{
+                "Status": "running", "Running": true, "Paused": false,
+                "Restarting": false, "ExitCode": 0,
+                "Health": {
+                    "Status": "unhealthy",
+                    "FailingStreak": 2,
+                    "Log": [{
+                        "Start": "2024-05-01T12:30:00.1Z",
+                        "End": "2024-05-01T12:30:00.2Z",
+                        "ExitCode": 1,
+                        "Output": "missing\n"
+                    }]
+                }
+            }"#,
+        )
+        .unwrap();
+        let health = state.health.unwrap();
+        assert_eq!(health.status, "unhealthy");
+        assert_eq!(health.failing_streak, 2);
+        assert_eq!(health.log[0].exit_code, 1);
+        assert_eq!(health.log[0].output, "missing\n");
+
+        let state: ContainerState = serde_json::from_str(
+            r#"{"Status": "exited", "Running": false, "Paused": false, "Restarting": false, "ExitCode": 0}"#,
+        )
+        .unwrap();
+        assert!(state.health.is_none());
+    }
+
+    /// Waits until the healthcheck of `container` reports `status`
+    fn wait_for_health(container: &mut Container, status: &str) {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        loop {
+            container.refresh().unwrap();
+            let health = container.health().unwrap();
+            if health.status == status {
+                return;
+            }
+            assert!(Instant::now() < deadline, "still {} waiting for {status}", health.status);
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn test_docker_healthcheck() {
+        if !docker_available() {
+            return;
+        }
+        Docker::pull_image("busybox", "latest").unwrap();
+        let name = format!("docker-healthcheck-{}", std::process::id());
+        let config = container_config()
+            .cmd(vec!["sleep", "60"])
+            .healthcheck(
+                vec!["test", "-f", "/tmp/ready"],
+                Duration::from_millis(500),
+                Duration::from_secs(1),
+                1,
+                Duration::ZERO,
+            )
+            .build();
+        let mut container = Docker::create_container("busybox:latest", &name, &config).unwrap();
+        container.start().unwrap();
+
+        assert_eq!(container.health().unwrap().status, "starting");
+        wait_for_health(&mut container, "unhealthy");
+        assert!(container.wait_ready(Duration::from_millis(200)).is_err());
+        assert_ne!(container.health().unwrap().log.last().unwrap().exit_code, 0);
+
+        Docker::command(["exec", &name, "touch", "/tmp/ready"]).unwrap();
+        wait_for_health(&mut container, "healthy");
+        container.wait_ready(Duration::from_secs(5)).unwrap();
+        assert_eq!(container.health().unwrap().log.last().unwrap().exit_code, 0);
+
+        Docker::force_remove_container(&name).unwrap();
+    }
+}
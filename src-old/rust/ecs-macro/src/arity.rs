@@ -0,0 +1,327 @@
+//! The generators of the tuple impls of `params!`, `func!`, `query!`,
+//! `sink!`, `source!` and `set!`, one impl per arity.
+//!
+//! Every macro takes an optional maximum arity, `params!(16)`, and expands to
+//! the impls for each arity from its minimum up to it, 8 when left out. The
+//! impl of arity `n` names `n` type parameters, so a maximum of `m` costs
+//! about `m * m / 2` of them to expand and type check, every build of the
+//! calling crate. Raise it only where systems need the width.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::LitInt;
+
+/// The maximum arity when a macro is given none.
+pub(crate) const DEFAULT_MAX_ARITY: usize = 8;
+
+/// Expands to `generator` for every arity from `min` to the maximum in
+/// `input`.
+pub(crate) fn expand(
+    input: TokenStream,
+    min: usize,
+    generator: fn(usize) -> proc_macro2::TokenStream,
+) -> TokenStream {
+    let max = match max_arity(input, min) {
+        Ok(max) => max,
+        Err(error) => return error.to_compile_error().into(),
+    };
+
+    let mut output = proc_macro2::TokenStream::new();
+    for arity in min..=max {
+        output.extend(generator(arity));
+    }
+
+    output.into()
+}
+
+fn max_arity(input: TokenStream, min: usize) -> syn::Result<usize> {
+    if input.is_empty() {
+        return Ok(DEFAULT_MAX_ARITY);
+    }
+    let lit = syn::parse::<LitInt>(input)?;
+    let max = lit.base10_parse::<usize>()?;
+    if max < min {
+        return Err(syn::Error::new(
+            lit.span(),
+            format!("the maximum arity must be at least {min}"),
+        ));
+    }
+
+    Ok(max)
+}
+
+pub(crate) fn params_arity(arity: usize) -> proc_macro2::TokenStream {
+    let input_types = (0..arity)
+        .map(|i| quote::format_ident!("T{}", i))
+        .collect::<Vec<_>>();
+
+    let input_tuple = quote! { (#(#input_types,)*) };
+
+    quote! {
+        impl<'a, #(#input_types: Param<'a> + 'static),*> Params<'a> for #input_tuple {
+
+            fn bind(registry: &mut Registry) -> Shard {
+                    let mut shard = Shard::Linear { tables: vec![].into() };
+                    // Every parameter gets the tables its filter admits
+                    #({
+                        let mut archetypes = Vec::<Archetype>::default();
+                        #input_types::inject(&mut archetypes);
+                        for archetype in archetypes {
+                            shard += registry.shard(archetype, #input_types::admits);
+                        }
+                    })*
+                    shard
+            }
+            fn borrows(borrows: &mut Vec<Borrow>) {
+                #(#input_types::borrows(borrows);)*
+            }
+            fn create(archetype: &'a [Archetype], mut table: &'a mut [&'a mut Table], context: &'a Context) -> Self where Self: Sized {
+                let table = UnsafeCell::new(table);
+                (#(#input_types::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),)*)
+            }
+        }
+    }
+}
+
+pub(crate) fn func_arity(arity: usize) -> proc_macro2::TokenStream {
+    let input_types = (0..arity)
+        .map(|i| quote::format_ident!("T{}", i))
+        .collect::<Vec<_>>();
+    let input_params = (0..arity)
+        .map(|i| quote::format_ident!("t{}", i))
+        .collect::<Vec<_>>();
+
+    let input_tuple = quote! { (#(#input_types,)*) };
+
+    let execute_params = quote! { (#(#input_params,)*) };
+
+    quote! {
+        impl<F, R: Send, #(#input_types: Send),*> Func<#input_tuple, Blocking<R>> for F
+        where
+            F: FnOnce(#(#input_types),*) -> R + 'static + Send + Clone,
+            R: Outcome
+        {
+            fn derive(&self) -> Self where Self: Sized {
+                self.clone()
+            }
+            fn execute(self, input: #input_tuple) -> impl Future<Output = R> + Send {
+                let #execute_params = input;
+                use std::future::ready;
+                ready(self(#(#input_params),*))
+            }
+        }
+        impl<F, Fut: Future<Output = R> + Send, R: Send, #(#input_types: Send),*> Func<#input_tuple, Concurrent<Fut>> for F
+        where
+            F: AsyncFnOnce<(#(#input_types,)*), CallOnceFuture = Fut, Output = R> + 'static + Send + Clone,
+        {
+            fn derive(&self) -> Self where Self: Sized {
+                self.clone()
+            }
+            fn execute(self, input: #input_tuple) -> impl Future<Output = R> + Send
+                where
+                    Self: Sized
+            {
+                self.async_call_once(input)
+            }
+        }
+    }
+}
+
+pub(crate) fn query_arity(arity: usize) -> proc_macro2::TokenStream {
+    let types = (0..arity)
+        .map(|i| quote::format_ident!("T{}", i))
+        .collect::<Vec<_>>();
+
+    // Generate implementations for both immutable and mutable reference tuples
+    quote! {
+        impl<'a, #(#types: Sink + 'a),*> Query for (#(#types),*) {
+            type Ref = (#(<#types as Sink>::Ref,)*);
+            type Mut = (#(<#types as Sink>::Mut,)*);
+
+            fn metas(index: usize) -> Option<Array<Meta, 256>> {
+                Some([#(*<#types as Sink>::meta().get(index)?,)*].into_iter().collect())
+            }
+
+            fn elements() -> Array<(bool, Option<TypeId>), 256> {
+                [#((<#types as Sink>::OPTIONAL, <#types as Sink>::trait_id()),)*].into_iter().collect()
+            }
+
+            fn archetype(index: usize) -> Option<Archetype> {
+                let optional = [#(<#types as Sink>::OPTIONAL,)*];
+                Some(Self::metas(index)?.into_iter().zip(optional).filter(|(_, optional)| !optional).map(|(meta, _)| meta).collect())
+            }
+
+            fn borrows(borrows: &mut Vec<Borrow>) {
+                #(for meta in <#types as Sink>::meta() {
+                    borrows.push(Borrow::component(meta, std::any::type_name::<#types>(), <#types as Sink>::MUTABLE));
+                })*
+            }
+
+             fn deduce<F: Filter>(state: &mut State, fetcher: &Fetch<Self, F>) -> Option<Self::Ref> {
+                if state.check(fetcher) {
+                    None?
+                }
+
+                let row = state.cursor.row();
+                let table = &fetcher.tables[state.cursor.table()];
+                let entity = table.entity(row)?;
+
+                let mut index = 0;
+                Some((#(unsafe {
+                    let column = state.columns[index].map(|(column, vtable)| (table.archetype().offset_of(column), vtable.unwrap_or_else(|| table.handle(row, column).vtable())));
+                    let item = <#types as Sink>::try_coerce_component_data(entity, column, state.metas[index])?;
+                    index += 1;
+                    item
+                },)*))
+             }
+
+             fn deduce_mut<F: Filter>(state: &mut State, fetcher: &mut Fetch<Self, F>) -> Option<Self::Mut> {
+                 if state.check(fetcher) {
+                    None?
+                }
+
+                let row = state.cursor.row();
+                let table = &fetcher.tables[state.cursor.table()];
+                let entity = table.entity(row)?;
+
+                let mut index = 0;
+                Some((#(unsafe {
+                    let column = state.columns[index].map(|(column, vtable)| (table.archetype().offset_of(column), vtable.unwrap_or_else(|| table.handle(row, column).vtable())));
+                    if <#types as Sink>::MUTABLE && column.is_some() {
+                        table.touch(row, state.metas[index].id);
+                    }
+                    let item = <#types as Sink>::try_coerce_component_data_mut(entity, column, state.metas[index])?;
+                    index += 1;
+                    item
+                },)*))
+             }
+        }
+    }
+}
+
+pub(crate) fn sink_arity(arity: usize) -> proc_macro2::TokenStream {
+    let input_types = (0..arity)
+        .map(|i| quote::format_ident!("T{}", i))
+        .collect::<Vec<_>>();
+    let input_params = (0..arity)
+        .map(|i| quote::format_ident!("t{}", i))
+        .collect::<Vec<_>>();
+
+    let input_tuple = quote! { (#(#input_types,)*) };
+
+    quote! {
+        impl<#(#input_types: Sink + 'static),*> Sink for #input_tuple {
+            unsafe fn erase_component_data<'a>(self) -> Array<(ecs::component::Handle<'a>, Data), 256>  where Self: 'a {
+                let (#(#input_params,)*) = self;
+                let mut raw_data = array![#(#input_params.erase_component_data(),)*].into_iter().flatten().collect::<Array<_, 256>>();
+                raw_data.sort_by_key(|(_, data)| data.meta.id);
+                raw_data
+            }
+            unsafe fn archetype(&self) -> Archetype {
+                let (#(#input_params,)*) = self;
+                let mut raw_data = array![#(#input_params.archetype(),)*].into_iter().flatten().collect::<Archetype>();
+                raw_data
+            }
+        }
+    }
+}
+pub(crate) fn source_arity(arity: usize) -> proc_macro2::TokenStream {
+    let input_types = (0..arity)
+        .map(|i| quote::format_ident!("T{}", i))
+        .collect::<Vec<_>>();
+    let input_params = (0..arity)
+        .map(|i| quote::format_ident!("t{}", i))
+        .collect::<Vec<_>>();
+
+    let input_tuple = quote! { (#(#input_types,)*) };
+
+    quote! {
+        impl<#(#input_types: Source + 'static),*> Source for #input_tuple {
+            type Table = #input_tuple;
+            unsafe fn erase_component_data<'a>(self) -> Vec<(Handle, Data)>  where Self: 'a {
+                let (#(#input_params,)*) = self;
+                let mut raw_data = vec![#(#input_params.erase_component_data(),)*].into_iter().flatten().collect::<Vec<_>>();
+                raw_data.sort_by_key(|(_, data)| data.meta.id);
+                raw_data
+            }
+            unsafe fn archetype(&self) -> Archetype {
+                let (#(#input_params,)*) = self;
+                let mut raw_data = vec![#(#input_params.archetype(),)*].into_iter().flatten().collect::<Archetype>();
+                // Columns follow the components, which are sorted by id
+                raw_data.sort_by_key(|meta| meta.id);
+                raw_data
+            }
+        }
+    }
+}
+
+pub(crate) fn set_arity(arity: usize) -> proc_macro2::TokenStream {
+    let input_types = (0..arity)
+        .map(|i| quote::format_ident!("T{}", i))
+        .collect::<Vec<_>>();
+    let input_params = (0..arity)
+        .map(|i| quote::format_ident!("t{}", i))
+        .collect::<Vec<_>>();
+    let marker_types = (0..arity)
+        .map(|i| quote::format_ident!("M{}", i))
+        .collect::<Vec<_>>();
+
+    let input_decl = quote! { #(#input_types: Sequence<#marker_types>,)* };
+
+    let input_tuple = quote! { (#(#input_types,)*) };
+    let execute_params = quote! { (#(#input_params,)*) };
+
+    quote! {
+        impl<#input_decl #(#marker_types: Provider),*> Sequence<Set<(#(#marker_types,)*)>> for #input_tuple {
+            type Input = ();
+            type Output = ();
+            type Return = ();
+
+            fn transform(self, graph: &mut Graph) {
+                let #execute_params = self;
+                #(#input_params.transform(graph);)*
+            }
+            fn iter(&self) -> impl Iterator<Item = Id> where Self: Sized {
+                let #execute_params = self;
+                let iter = iter::empty();
+                #(let iter = iter.chain(#input_params.iter());)*
+                iter
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use super::*;
+
+    /// Compares the impls of a few arities with
+    /// `tests/expand/{name}_{arity}.expanded.rs`, or writes the files when run
+    /// with `EXPAND=overwrite`.
+    fn snapshot(name: &str, generator: fn(usize) -> proc_macro2::TokenStream) {
+        for arity in [0, 1, 8, 12] {
+            let expanded = prettyplease::unparse(&syn::parse2(generator(arity)).unwrap());
+            let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                .join(format!("tests/expand/{name}_{arity}.expanded.rs"));
+            if env::var("EXPAND").is_ok_and(|mode| mode == "overwrite") {
+                fs::write(&path, &expanded).unwrap();
+                continue;
+            }
+            let expected = fs::read_to_string(&path)
+                .unwrap_or_else(|error| panic!("{}: {error}", path.display()));
+            assert_eq!(expanded, expected.replace("\r\n", "\n"), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn params_expansion() {
+        snapshot("params", params_arity);
+    }
+
+    #[test]
+    fn func_expansion() {
+        snapshot("func", func_arity);
+    }
+}
@@ -5,6 +5,9 @@ use parse::{Parse, ParseStream};
 use proc_macro::{Span, TokenStream};
 use quote::{format_ident, quote};
 use syn::*;
+
+mod arity;
+
 #[proc_macro_attribute]
 pub fn component(attr: TokenStream, input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as Item);
@@ -16,57 +19,67 @@ pub fn component(attr: TokenStream, input: TokenStream) -> TokenStream {
     }
 }
 
-fn component_struct(input: ItemStruct, mut attr: TokenStream) -> TokenStream {
-    if attr.is_empty() {
-        attr = quote! { dyn ecs::component::Component }.into();
-    }
+fn component_struct(input: ItemStruct, attr: TokenStream) -> TokenStream {
     let name = &input.ident;
-    let mut attr_args = parse_macro_input!(attr as AttrArgs);
-    let set = attr_args.types.remove(0);
-
+    let attr_args = parse_macro_input!(attr as AttrArgs);
+    let krate = &attr_args.krate;
+    let set = attr_args
+        .types
+        .first()
+        .cloned()
+        .unwrap_or_else(|| parse_quote! { dyn #krate::component::Component });
+
+    // Paths are absolute and the impls sit in an anonymous const, so nothing
+    // leaks into or is picked up from the caller's scope
     let expanded = quote! {
         #input // Keep the original struct definition
 
-        impl ecs::component::Component for #name {
-            fn meta() -> ecs::component::Meta {
-                ecs::component::Meta {
-                    id: ecs::component::Id(std::any::TypeId::of::<Self>()),
-                    size: std::mem::size_of::<Self>(),
+        const _: () = {
+            impl #krate::component::Component for #name {
+                fn meta() -> #krate::component::Meta {
+                    #krate::component::Meta {
+                        id: #krate::component::Id(::core::any::TypeId::of::<Self>()),
+                        size: ::core::mem::size_of::<Self>(),
+                        align: ::core::mem::align_of::<Self>(),
+                    }
                 }
             }
-        }
 
-        impl ecs::component::source::Source for #name {
-            type Table = #set;
-            unsafe fn erase_component_data<'a>(mut self) -> ecs::component::table::Components<'a>
-            where
-                Self: 'a + Sized
-            {
-                let mut original = Box::new(self);
-                use ecs::component::table::Erase;
-                let (original, data) = original.erase();
-                let original = Box::into_raw(original as Box<Self::Table>);
-                let (_, vtable) = original.to_raw_parts();
-                let original = Box::from_raw(original);
-                let mut arr = vec![];
-                arr.push((ecs::component::Handle(original, std::mem::transmute(vtable)), data));
-                arr
-            }
-            unsafe fn archetype(&self) -> ecs::component::archetype::Archetype {
-                ecs::component::archetype::Archetype::from_iter([<#name as Component>::meta()])
+            impl #krate::component::source::Source for #name {
+                type Table = #set;
+                unsafe fn erase_component_data<'a>(mut self) -> #krate::component::table::Components<'a>
+                where
+                    Self: 'a + Sized
+                {
+                    let mut original = ::std::boxed::Box::new(self);
+                    let (original, data) = <#name as #krate::component::table::Erase>::erase(original);
+                    let original = ::std::boxed::Box::into_raw(original as ::std::boxed::Box<Self::Table>);
+                    let (_, vtable) = original.to_raw_parts();
+                    let original = ::std::boxed::Box::from_raw(original);
+                    let mut arr = ::std::vec![];
+                    arr.push((#krate::component::Handle(original, ::core::mem::transmute(vtable)), data));
+                    arr
+                }
+                unsafe fn archetype(&self) -> #krate::component::archetype::Archetype {
+                    ::core::iter::FromIterator::from_iter([
+                        <#name as #krate::component::Component>::meta(),
+                    ])
+                }
             }
-        }
-
-        impl ecs::component::access::Access for #name {
-            fn access<'a>(ptr: *mut u8, vtable: std::ptr::DynMetadata<dyn Component>) -> &'a mut Self {
 
-                unsafe { &mut *(ptr as *mut #name) }
-            }
+            impl #krate::component::access::Access for #name {
+                fn access<'a>(
+                    ptr: *mut u8,
+                    vtable: ::core::ptr::DynMetadata<dyn #krate::component::Component>,
+                ) -> &'a mut Self {
+                    unsafe { &mut *(ptr as *mut #name) }
+                }
 
-            fn meta() -> Vec<ecs::component::Meta> {
-                vec![<#name as ecs::component::Component>::meta()]
+                fn meta() -> ::std::vec::Vec<#krate::component::Meta> {
+                    ::std::vec![<#name as #krate::component::Component>::meta()]
+                }
             }
-        }
+        };
     };
 
     expanded.into()
@@ -75,336 +88,108 @@ fn component_struct(input: ItemStruct, mut attr: TokenStream) -> TokenStream {
 fn component_trait(input: ItemTrait, attr: TokenStream) -> TokenStream {
     let name = &input.ident;
     let attr_args = parse_macro_input!(attr as AttrArgs);
+    let krate = &attr_args.krate;
 
     let meta_variants = attr_args.types.iter().map(|ty| {
         quote! {
-            <#ty as ecs::component::Component>::meta()
+            <#ty as #krate::component::Component>::meta()
         }
     });
 
     let expanded = quote! {
         #input // Keep the original trait definition
 
-        impl ecs::component::access::Access for dyn #name {
+        const _: () = {
+            impl #krate::component::access::Access for dyn #name {
+                fn access<'a>(
+                    ptr: *mut u8,
+                    vtable: ::core::ptr::DynMetadata<dyn #krate::component::Component>,
+                ) -> &'a mut Self {
+                    unsafe {
+                        (::core::ptr::from_raw_parts_mut(ptr, ::core::mem::transmute(vtable)) as *mut dyn #name).as_mut().unwrap()
+                    }
+                }
 
-            fn access<'a>(ptr: *mut u8, vtable: std::ptr::DynMetadata<dyn Component>) -> &'a mut Self {
-                unsafe {
-                    (std::ptr::from_raw_parts_mut(ptr, std::mem::transmute(vtable)) as *mut dyn #name).as_mut().unwrap()
+                fn meta() -> ::std::vec::Vec<#krate::component::Meta> {
+                    ::std::vec![#(#meta_variants,)*]
                 }
-            }
 
-            fn meta() -> Vec<ecs::component::Meta> {
-                vec![#(#meta_variants,)*]
+                fn trait_id() -> ::core::option::Option<::core::any::TypeId> {
+                    ::core::option::Option::Some(::core::any::TypeId::of::<dyn #name>())
+                }
             }
-
-        }
+        };
     };
     expanded.into()
 }
 
+/// The arguments of `#[component(...)]`: the types a struct is stored as, or
+/// a trait's implementors, and `crate = "path"` where the `ecs` crate is
+/// renamed.
 struct AttrArgs {
     types: Vec<Type>,
+    krate: Path,
 }
 
 impl Parse for AttrArgs {
     fn parse(input: ParseStream) -> Result<Self> {
-        let types = syn::punctuated::Punctuated::<Type, Token![,]>::parse_terminated(input)?
-            .into_iter()
-            .collect();
-
-        Ok(AttrArgs { types })
-    }
-}
-
-#[proc_macro]
-pub fn params(_input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let mut output = proc_macro2::TokenStream::new();
-
-    for arity in 0..=8 {
-        let impl_block = params_arity(arity);
-        output.extend(impl_block);
-    }
-
-    output.into()
-}
-
-fn params_arity(arity: usize) -> proc_macro2::TokenStream {
-    let input_types = (0..arity)
-        .map(|i| quote::format_ident!("T{}", i))
-        .collect::<Vec<_>>();
-
-    let input_tuple = quote! { (#(#input_types,)*) };
-
-    quote! {
-        impl<'a, #(#input_types: Param<'a> + 'static),*> Params<'a> for #input_tuple {
-
-            fn bind(registry: &mut Registry) -> Shard {
-                    let mut archetypes = Vec::<Archetype>::default();
-                    #(#input_types::inject(&mut archetypes);)*
-                        let mut shard = Shard::Linear { tables: vec![].into() };
-                    for archetype in archetypes {
-                        shard += registry.shard(archetype);
-                    }
-                    shard
-            }
-            fn create(archetype: &'a [Archetype], mut table: &'a mut [&'a mut Table]) -> Self where Self: Sized {
-                let table = UnsafeCell::new(table);
-                (#(#input_types::create(archetype, unsafe { table.get().as_mut().unwrap() }),)*)
+        let mut types = vec![];
+        let mut krate = parse_quote! { ::ecs };
+        while !input.is_empty() {
+            if input.peek(Token![crate]) {
+                input.parse::<Token![crate]>()?;
+                input.parse::<Token![=]>()?;
+                krate = input.parse::<LitStr>()?.parse()?;
+            } else {
+                types.push(input.parse()?);
+            }
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
             }
         }
-    }
-}
 
-#[proc_macro]
-pub fn func(_input: TokenStream) -> TokenStream {
-    let mut output = proc_macro2::TokenStream::new();
-
-    for arity in 0..=8 {
-        let impl_block = func_arity(arity);
-        output.extend(impl_block);
-    }
-
-    output.into()
-}
-
-fn func_arity(arity: usize) -> proc_macro2::TokenStream {
-    let input_types = (0..arity)
-        .map(|i| quote::format_ident!("T{}", i))
-        .collect::<Vec<_>>();
-    let input_params = (0..arity)
-        .map(|i| quote::format_ident!("t{}", i))
-        .collect::<Vec<_>>();
-
-    let input_tuple = quote! { (#(#input_types,)*) };
-
-    let execute_params = quote! { (#(#input_params,)*) };
-
-    quote! {
-        impl<F, R: Send, #(#input_types: Send),*> Func<#input_tuple, Blocking<R>> for F
-        where
-            F: FnOnce(#(#input_types),*) -> R + 'static + Send + Clone,
-            R: Outcome
-        {
-            fn derive(&self) -> Self where Self: Sized {
-                self.clone()
-            }
-            fn execute(self, input: #input_tuple) -> impl Future<Output = R> + Send {
-                let #execute_params = input;
-                use std::future::ready;
-                ready(self(#(#input_params),*))
-            }
-        }
-        impl<F, Fut: Future<Output = R> + Send, R: Send, #(#input_types: Send),*> Func<#input_tuple, Concurrent<Fut>> for F
-        where
-            F: AsyncFnOnce<(#(#input_types,)*), CallOnceFuture = Fut, Output = R> + 'static + Send + Clone,
-        {
-            fn derive(&self) -> Self where Self: Sized {
-                self.clone()
-            }
-            fn execute(self, input: #input_tuple) -> impl Future<Output = R> + Send
-                where
-                    Self: Sized
-            {
-                self.async_call_once(input)
-            }
-        }
+        Ok(AttrArgs { types, krate })
     }
 }
 
+/// Implements `Params` for tuples of `Param`s, from `()` up to the maximum
+/// arity given, `params!(16)`, or 8. See [`arity`] for what each arity costs.
 #[proc_macro]
-pub fn query(_input: TokenStream) -> TokenStream {
-    let mut output = proc_macro2::TokenStream::new();
-
-    // Generate implementations for tuples of references up to 8 elements
-    for arity in 1..=8 {
-        let impl_block = query_arity(arity);
-        output.extend(impl_block);
-    }
-
-    output.into()
-}
-
-fn query_arity(arity: usize) -> proc_macro2::TokenStream {
-    let types = (0..arity)
-        .map(|i| quote::format_ident!("T{}", i))
-        .collect::<Vec<_>>();
-
-    // Generate implementations for both immutable and mutable reference tuples
-    quote! {
-        impl<'a, #(#types: Sink + 'a),*> Query for (#(#types),*) {
-            type Ref = (#(<#types as Sink>::Ref,)*);
-            type Mut = (#(<#types as Sink>::Mut,)*);
-
-            fn offsets(index: usize) -> Option<Array<usize, 256>> {
-                let mut meta = [#(*<#types as Sink>::meta().get(index)?,)*];
-                let mut offset = (0..meta.len()).map(|i| meta.iter().take(i).copied().map(|meta| meta.size).sum::<usize>()).collect::<Array<_, 256>>();
-                let mut meta_offset = meta.into_iter().zip(offset).collect::<Array<_, 256>>();
-                meta_offset.sort_by_key(|(meta, _)| meta.id);
-                Some(meta_offset.into_iter().map(|(_, offset)| offset).collect())
-            }
-
-            fn archetype(index: usize) -> Option<Archetype> {
-                Some([#(*<#types as Sink>::meta().get(index)?,)*].into_iter().collect())
-            }
-
-             fn deduce(state: &mut State, fetcher: &Fetch<Self>) -> Option<Self::Ref> {
-                let cursor = state.cursor;
-                if state.check(fetcher) {
-                    None?
-                }
-
-                let row = cursor.row();
-                let table = cursor.table();
-
-                let mut index = 0;
-                Some((#(unsafe {
-                    let item = <#types as Sink>::coerce_component_data(fetcher.tables[table].entity(row)?, state.offsets[index], state.supertype[index], fetcher.tables[table].handle(row, index));
-                    index += 1;
-                    item
-                },)*))
-             }
-
-             fn deduce_mut(state: &mut State, fetcher: &mut Fetch<Self>) -> Option<Self::Mut> {
-                 if state.check(fetcher) {
-                    None?
-                }
-
-                let row = state.cursor.row();
-                let table = state.cursor.table();
-
-                let mut index = 0;
-                Some((#(unsafe {
-                    let item = <#types as Sink>::coerce_component_data_mut(fetcher.tables[table].entity(row)?, state.offsets[index], state.supertype[index], fetcher.tables[table].handle(row, index));
-                    index += 1;
-                    item
-                },)*))
-             }
-        }
-    }
+pub fn params(input: TokenStream) -> TokenStream {
+    arity::expand(input, 0, arity::params_arity)
 }
 
+/// Implements `Func` for blocking and async functions of up to the maximum
+/// arity given, or 8.
 #[proc_macro]
-pub fn sink(_input: TokenStream) -> TokenStream {
-    let mut output = proc_macro2::TokenStream::new();
-
-    for arity in 1..=8 {
-        let impl_block = sink_arity(arity);
-        output.extend(impl_block);
-    }
-
-    output.into()
+pub fn func(input: TokenStream) -> TokenStream {
+    arity::expand(input, 0, arity::func_arity)
 }
 
-fn sink_arity(arity: usize) -> proc_macro2::TokenStream {
-    let input_types = (0..arity)
-        .map(|i| quote::format_ident!("T{}", i))
-        .collect::<Vec<_>>();
-    let input_params = (0..arity)
-        .map(|i| quote::format_ident!("t{}", i))
-        .collect::<Vec<_>>();
-
-    let input_tuple = quote! { (#(#input_types,)*) };
-
-    quote! {
-        impl<#(#input_types: Sink + 'static),*> Sink for #input_tuple {
-            unsafe fn erase_component_data<'a>(self) -> Array<(ecs::component::Handle<'a>, Data), 256>  where Self: 'a {
-                let (#(#input_params,)*) = self;
-                let mut raw_data = array![#(#input_params.erase_component_data(),)*].into_iter().flatten().collect::<Array<_, 256>>();
-                raw_data.sort_by_key(|(_, data)| data.meta.id);
-                raw_data
-            }
-            unsafe fn archetype(&self) -> Archetype {
-                let (#(#input_params,)*) = self;
-                let mut raw_data = array![#(#input_params.archetype(),)*].into_iter().flatten().collect::<Archetype>();
-                raw_data
-            }
-        }
-    }
-}
+/// Implements `Query` for tuples of `Sink`s, from one element up to the
+/// maximum arity given, or 8.
 #[proc_macro]
-pub fn source(_input: TokenStream) -> TokenStream {
-    let mut output = proc_macro2::TokenStream::new();
-
-    for arity in 1..=8 {
-        let impl_block = source_arity(arity);
-        output.extend(impl_block);
-    }
-
-    output.into()
+pub fn query(input: TokenStream) -> TokenStream {
+    arity::expand(input, 1, arity::query_arity)
 }
 
-fn source_arity(arity: usize) -> proc_macro2::TokenStream {
-    let input_types = (0..arity)
-        .map(|i| quote::format_ident!("T{}", i))
-        .collect::<Vec<_>>();
-    let input_params = (0..arity)
-        .map(|i| quote::format_ident!("t{}", i))
-        .collect::<Vec<_>>();
-
-    let input_tuple = quote! { (#(#input_types,)*) };
-
-    quote! {
-        impl<#(#input_types: Source + 'static),*> Source for #input_tuple {
-            type Table = #input_tuple;
-            unsafe fn erase_component_data<'a>(self) -> Vec<(Handle, Data)>  where Self: 'a {
-                let (#(#input_params,)*) = self;
-                let mut raw_data = vec![#(#input_params.erase_component_data(),)*].into_iter().flatten().collect::<Vec<_>>();
-                raw_data.sort_by_key(|(_, data)| data.meta.id);
-                raw_data
-            }
-            unsafe fn archetype(&self) -> Archetype {
-                let (#(#input_params,)*) = self;
-                let mut raw_data = vec![#(#input_params.archetype(),)*].into_iter().flatten().collect::<Archetype>();
-                raw_data
-            }
-        }
-    }
+/// Implements `Sink` for tuples of `Sink`s, from one element up to the
+/// maximum arity given, or 8.
+#[proc_macro]
+pub fn sink(input: TokenStream) -> TokenStream {
+    arity::expand(input, 1, arity::sink_arity)
 }
 
+/// Implements `Source` for tuples of `Source`s, from one element up to the
+/// maximum arity given, or 8.
 #[proc_macro]
-pub fn set(_input: TokenStream) -> TokenStream {
-    let mut output = proc_macro2::TokenStream::new();
-
-    for arity in 1..=8 {
-        let impl_block = set_arity(arity);
-        output.extend(impl_block);
-    }
-
-    output.into()
+pub fn source(input: TokenStream) -> TokenStream {
+    arity::expand(input, 1, arity::source_arity)
 }
 
-fn set_arity(arity: usize) -> proc_macro2::TokenStream {
-    let input_types = (0..arity)
-        .map(|i| quote::format_ident!("T{}", i))
-        .collect::<Vec<_>>();
-    let input_params = (0..arity)
-        .map(|i| quote::format_ident!("t{}", i))
-        .collect::<Vec<_>>();
-    let marker_types = (0..arity)
-        .map(|i| quote::format_ident!("M{}", i))
-        .collect::<Vec<_>>();
-
-    let input_decl = quote! { #(#input_types: Sequence<#marker_types>,)* };
-
-    let input_tuple = quote! { (#(#input_types,)*) };
-    let execute_params = quote! { (#(#input_params,)*) };
-
-    quote! {
-        impl<#input_decl #(#marker_types: Provider),*> Sequence<Set<(#(#marker_types,)*)>> for #input_tuple {
-            type Input = ();
-            type Output = ();
-            type Return = ();
-
-            fn transform(self, graph: &mut Graph) {
-                let #execute_params = self;
-                #(#input_params.transform(graph);)*
-            }
-            fn iter(&self) -> impl Iterator<Item = Id> where Self: Sized {
-                let #execute_params = self;
-                let iter = iter::empty();
-                #(let iter = iter.chain(#input_params.iter());)*
-                iter
-            }
-        }
-    }
+/// Implements `Sequence` over `Set` for tuples of sequences, from one element
+/// up to the maximum arity given, or 8.
+#[proc_macro]
+pub fn set(input: TokenStream) -> TokenStream {
+    arity::expand(input, 1, arity::set_arity)
 }
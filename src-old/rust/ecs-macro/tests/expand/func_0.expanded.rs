@@ -0,0 +1,34 @@
+impl<F, R: Send> Func<(), Blocking<R>> for F
+where
+    F: FnOnce() -> R + 'static + Send + Clone,
+    R: Outcome,
+{
+    fn derive(&self) -> Self
+    where
+        Self: Sized,
+    {
+        self.clone()
+    }
+    fn execute(self, input: ()) -> impl Future<Output = R> + Send {
+        let () = input;
+        use std::future::ready;
+        ready(self())
+    }
+}
+impl<F, Fut: Future<Output = R> + Send, R: Send> Func<(), Concurrent<Fut>> for F
+where
+    F: AsyncFnOnce<(), CallOnceFuture = Fut, Output = R> + 'static + Send + Clone,
+{
+    fn derive(&self) -> Self
+    where
+        Self: Sized,
+    {
+        self.clone()
+    }
+    fn execute(self, input: ()) -> impl Future<Output = R> + Send
+    where
+        Self: Sized,
+    {
+        self.async_call_once(input)
+    }
+}
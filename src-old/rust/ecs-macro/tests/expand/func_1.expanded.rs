@@ -0,0 +1,35 @@
+impl<F, R: Send, T0: Send> Func<(T0,), Blocking<R>> for F
+where
+    F: FnOnce(T0) -> R + 'static + Send + Clone,
+    R: Outcome,
+{
+    fn derive(&self) -> Self
+    where
+        Self: Sized,
+    {
+        self.clone()
+    }
+    fn execute(self, input: (T0,)) -> impl Future<Output = R> + Send {
+        let (t0,) = input;
+        use std::future::ready;
+        ready(self(t0))
+    }
+}
+impl<F, Fut: Future<Output = R> + Send, R: Send, T0: Send> Func<(T0,), Concurrent<Fut>>
+for F
+where
+    F: AsyncFnOnce<(T0,), CallOnceFuture = Fut, Output = R> + 'static + Send + Clone,
+{
+    fn derive(&self) -> Self
+    where
+        Self: Sized,
+    {
+        self.clone()
+    }
+    fn execute(self, input: (T0,)) -> impl Future<Output = R> + Send
+    where
+        Self: Sized,
+    {
+        self.async_call_once(input)
+    }
+}
@@ -0,0 +1,76 @@
+impl<
+    F,
+    R: Send,
+    T0: Send,
+    T1: Send,
+    T2: Send,
+    T3: Send,
+    T4: Send,
+    T5: Send,
+    T6: Send,
+    T7: Send,
+    T8: Send,
+    T9: Send,
+    T10: Send,
+    T11: Send,
+> Func<(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11), Blocking<R>> for F
+where
+    F: FnOnce(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11) -> R + 'static + Send
+        + Clone,
+    R: Outcome,
+{
+    fn derive(&self) -> Self
+    where
+        Self: Sized,
+    {
+        self.clone()
+    }
+    fn execute(
+        self,
+        input: (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11),
+    ) -> impl Future<Output = R> + Send {
+        let (t0, t1, t2, t3, t4, t5, t6, t7, t8, t9, t10, t11) = input;
+        use std::future::ready;
+        ready(self(t0, t1, t2, t3, t4, t5, t6, t7, t8, t9, t10, t11))
+    }
+}
+impl<
+    F,
+    Fut: Future<Output = R> + Send,
+    R: Send,
+    T0: Send,
+    T1: Send,
+    T2: Send,
+    T3: Send,
+    T4: Send,
+    T5: Send,
+    T6: Send,
+    T7: Send,
+    T8: Send,
+    T9: Send,
+    T10: Send,
+    T11: Send,
+> Func<(T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11), Concurrent<Fut>> for F
+where
+    F: AsyncFnOnce<
+            (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11),
+            CallOnceFuture = Fut,
+            Output = R,
+        > + 'static + Send + Clone,
+{
+    fn derive(&self) -> Self
+    where
+        Self: Sized,
+    {
+        self.clone()
+    }
+    fn execute(
+        self,
+        input: (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11),
+    ) -> impl Future<Output = R> + Send
+    where
+        Self: Sized,
+    {
+        self.async_call_once(input)
+    }
+}
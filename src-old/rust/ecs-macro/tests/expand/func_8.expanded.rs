@@ -0,0 +1,64 @@
+impl<
+    F,
+    R: Send,
+    T0: Send,
+    T1: Send,
+    T2: Send,
+    T3: Send,
+    T4: Send,
+    T5: Send,
+    T6: Send,
+    T7: Send,
+> Func<(T0, T1, T2, T3, T4, T5, T6, T7), Blocking<R>> for F
+where
+    F: FnOnce(T0, T1, T2, T3, T4, T5, T6, T7) -> R + 'static + Send + Clone,
+    R: Outcome,
+{
+    fn derive(&self) -> Self
+    where
+        Self: Sized,
+    {
+        self.clone()
+    }
+    fn execute(
+        self,
+        input: (T0, T1, T2, T3, T4, T5, T6, T7),
+    ) -> impl Future<Output = R> + Send {
+        let (t0, t1, t2, t3, t4, t5, t6, t7) = input;
+        use std::future::ready;
+        ready(self(t0, t1, t2, t3, t4, t5, t6, t7))
+    }
+}
+impl<
+    F,
+    Fut: Future<Output = R> + Send,
+    R: Send,
+    T0: Send,
+    T1: Send,
+    T2: Send,
+    T3: Send,
+    T4: Send,
+    T5: Send,
+    T6: Send,
+    T7: Send,
+> Func<(T0, T1, T2, T3, T4, T5, T6, T7), Concurrent<Fut>> for F
+where
+    F: AsyncFnOnce<(T0, T1, T2, T3, T4, T5, T6, T7), CallOnceFuture = Fut, Output = R>
+        + 'static + Send + Clone,
+{
+    fn derive(&self) -> Self
+    where
+        Self: Sized,
+    {
+        self.clone()
+    }
+    fn execute(
+        self,
+        input: (T0, T1, T2, T3, T4, T5, T6, T7),
+    ) -> impl Future<Output = R> + Send
+    where
+        Self: Sized,
+    {
+        self.async_call_once(input)
+    }
+}
@@ -0,0 +1,20 @@
+impl<'a> Params<'a> for () {
+    fn bind(registry: &mut Registry) -> Shard {
+        let mut shard = Shard::Linear {
+            tables: vec![].into(),
+        };
+        shard
+    }
+    fn borrows(borrows: &mut Vec<Borrow>) {}
+    fn create(
+        archetype: &'a [Archetype],
+        mut table: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
+    where
+        Self: Sized,
+    {
+        let table = UnsafeCell::new(table);
+        ()
+    }
+}
@@ -0,0 +1,29 @@
+impl<'a, T0: Param<'a> + 'static> Params<'a> for (T0,) {
+    fn bind(registry: &mut Registry) -> Shard {
+        let mut shard = Shard::Linear {
+            tables: vec![].into(),
+        };
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T0::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T0::admits);
+            }
+        }
+        shard
+    }
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        T0::borrows(borrows);
+    }
+    fn create(
+        archetype: &'a [Archetype],
+        mut table: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
+    where
+        Self: Sized,
+    {
+        let table = UnsafeCell::new(table);
+        (T0::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),)
+    }
+}
@@ -0,0 +1,144 @@
+impl<
+    'a,
+    T0: Param<'a> + 'static,
+    T1: Param<'a> + 'static,
+    T2: Param<'a> + 'static,
+    T3: Param<'a> + 'static,
+    T4: Param<'a> + 'static,
+    T5: Param<'a> + 'static,
+    T6: Param<'a> + 'static,
+    T7: Param<'a> + 'static,
+    T8: Param<'a> + 'static,
+    T9: Param<'a> + 'static,
+    T10: Param<'a> + 'static,
+    T11: Param<'a> + 'static,
+> Params<'a> for (T0, T1, T2, T3, T4, T5, T6, T7, T8, T9, T10, T11) {
+    fn bind(registry: &mut Registry) -> Shard {
+        let mut shard = Shard::Linear {
+            tables: vec![].into(),
+        };
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T0::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T0::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T1::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T1::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T2::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T2::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T3::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T3::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T4::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T4::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T5::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T5::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T6::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T6::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T7::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T7::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T8::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T8::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T9::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T9::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T10::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T10::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T11::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T11::admits);
+            }
+        }
+        shard
+    }
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        T0::borrows(borrows);
+        T1::borrows(borrows);
+        T2::borrows(borrows);
+        T3::borrows(borrows);
+        T4::borrows(borrows);
+        T5::borrows(borrows);
+        T6::borrows(borrows);
+        T7::borrows(borrows);
+        T8::borrows(borrows);
+        T9::borrows(borrows);
+        T10::borrows(borrows);
+        T11::borrows(borrows);
+    }
+    fn create(
+        archetype: &'a [Archetype],
+        mut table: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
+    where
+        Self: Sized,
+    {
+        let table = UnsafeCell::new(table);
+        (
+            T0::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T1::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T2::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T3::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T4::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T5::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T6::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T7::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T8::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T9::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T10::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T11::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+        )
+    }
+}
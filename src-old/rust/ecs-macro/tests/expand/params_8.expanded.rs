@@ -0,0 +1,104 @@
+impl<
+    'a,
+    T0: Param<'a> + 'static,
+    T1: Param<'a> + 'static,
+    T2: Param<'a> + 'static,
+    T3: Param<'a> + 'static,
+    T4: Param<'a> + 'static,
+    T5: Param<'a> + 'static,
+    T6: Param<'a> + 'static,
+    T7: Param<'a> + 'static,
+> Params<'a> for (T0, T1, T2, T3, T4, T5, T6, T7) {
+    fn bind(registry: &mut Registry) -> Shard {
+        let mut shard = Shard::Linear {
+            tables: vec![].into(),
+        };
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T0::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T0::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T1::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T1::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T2::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T2::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T3::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T3::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T4::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T4::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T5::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T5::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T6::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T6::admits);
+            }
+        }
+        {
+            let mut archetypes = Vec::<Archetype>::default();
+            T7::inject(&mut archetypes);
+            for archetype in archetypes {
+                shard += registry.shard(archetype, T7::admits);
+            }
+        }
+        shard
+    }
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        T0::borrows(borrows);
+        T1::borrows(borrows);
+        T2::borrows(borrows);
+        T3::borrows(borrows);
+        T4::borrows(borrows);
+        T5::borrows(borrows);
+        T6::borrows(borrows);
+        T7::borrows(borrows);
+    }
+    fn create(
+        archetype: &'a [Archetype],
+        mut table: &'a mut [&'a mut Table],
+        context: &'a Context,
+    ) -> Self
+    where
+        Self: Sized,
+    {
+        let table = UnsafeCell::new(table);
+        (
+            T0::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T1::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T2::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T3::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T4::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T5::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T6::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+            T7::create(archetype, unsafe { table.get().as_mut().unwrap() }, context),
+        )
+    }
+}
@@ -0,0 +1,16 @@
+/// Expansions of `#[component]` that must compile in the caller's crate.
+#[test]
+fn ui() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/two_components.rs");
+    cases.pass("tests/ui/meta_in_scope.rs");
+    cases.pass("tests/ui/renamed_crate.rs");
+}
+
+/// Expansions of the tuple impl macros past the default arity.
+#[test]
+fn arity() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/wide_params.rs");
+    cases.compile_fail("tests/ui/arity_too_small.rs");
+}
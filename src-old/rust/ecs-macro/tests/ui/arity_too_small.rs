@@ -0,0 +1,3 @@
+ecs_macro::query!(0);
+
+fn main() {}
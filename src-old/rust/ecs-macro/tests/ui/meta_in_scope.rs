@@ -0,0 +1,19 @@
+#![feature(ptr_metadata)]
+
+use ecs::component::component;
+
+// Named like the items of `ecs::component` the expansion uses
+struct Meta;
+struct Id;
+trait Component {}
+
+#[component]
+struct Position(f32, f32);
+
+impl Component for Position {}
+
+fn main() {
+    let _ = (Meta, Id);
+    let meta = <Position as ecs::component::Component>::meta();
+    assert_eq!(meta.size, std::mem::size_of::<Position>());
+}
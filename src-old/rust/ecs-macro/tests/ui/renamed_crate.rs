@@ -0,0 +1,22 @@
+#![feature(ptr_metadata)]
+
+extern crate ecs as engine;
+
+use engine::component::component;
+
+#[component(crate = "engine")]
+struct Position(f32, f32);
+
+#[component(crate = "engine")]
+struct Velocity(f32, f32);
+
+#[component(crate = "engine", Position, Velocity)]
+trait Moving {}
+
+impl Moving for Position {}
+impl Moving for Velocity {}
+
+fn main() {
+    let metas = <dyn Moving as engine::component::access::Access>::meta();
+    assert_eq!(metas.len(), 2);
+}
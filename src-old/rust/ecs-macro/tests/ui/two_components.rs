@@ -0,0 +1,15 @@
+#![feature(ptr_metadata)]
+
+use ecs::component::component;
+
+#[component]
+struct Position(f32, f32);
+
+#[component]
+struct Velocity(f32, f32);
+
+fn main() {
+    fn is_component<C: ecs::component::Component>() {}
+    is_component::<Position>();
+    is_component::<Velocity>();
+}
@@ -0,0 +1,72 @@
+// Stand-ins for the items of `ecs::system::param` the expansion names
+use std::{any::TypeId, cell::UnsafeCell, ops::AddAssign};
+
+struct Archetype;
+struct Table;
+struct Context;
+struct Borrow(TypeId);
+
+enum Shard {
+    Linear { tables: Box<[usize]> },
+}
+
+impl AddAssign for Shard {
+    fn add_assign(&mut self, other: Shard) {
+        let (Shard::Linear { tables }, Shard::Linear { tables: more }) = (self, other);
+        *tables = tables.iter().chain(more.iter()).copied().collect();
+    }
+}
+
+struct Registry;
+
+impl Registry {
+    fn shard(&mut self, _: Archetype, _: fn(&Archetype) -> bool) -> Shard {
+        Shard::Linear { tables: vec![0].into() }
+    }
+}
+
+trait Param<'a>: Send {
+    fn inject(archetype: &mut Vec<Archetype>);
+    fn admits(_: &Archetype) -> bool {
+        true
+    }
+    fn borrows(_: &mut Vec<Borrow>) {}
+    fn create(archetype: &'a [Archetype], table: &'a mut [&'a mut Table], context: &'a Context) -> Self
+    where
+        Self: Sized + 'a;
+}
+
+trait Params<'a>: Send + 'static {
+    fn bind(registry: &mut Registry) -> Shard;
+    fn borrows(borrows: &mut Vec<Borrow>);
+    fn create(archetype: &'a [Archetype], table: &'a mut [&'a mut Table], context: &'a Context) -> Self
+    where
+        Self: Sized + 'a;
+}
+
+struct Res;
+
+impl<'a> Param<'a> for Res {
+    fn inject(archetype: &mut Vec<Archetype>) {
+        archetype.push(Archetype);
+    }
+    fn borrows(borrows: &mut Vec<Borrow>) {
+        borrows.push(Borrow(TypeId::of::<Self>()));
+    }
+    fn create(_: &'a [Archetype], _: &'a mut [&'a mut Table], _: &'a Context) -> Self {
+        Res
+    }
+}
+
+ecs_macro::params!(16);
+
+type Wide = (Res, Res, Res, Res, Res, Res, Res, Res, Res, Res, Res, Res, Res, Res, Res, Res);
+
+fn main() {
+    let Shard::Linear { tables } = <Wide as Params>::bind(&mut Registry);
+    assert_eq!(tables.len(), 16);
+    let mut borrows = vec![];
+    <Wide as Params>::borrows(&mut borrows);
+    assert_eq!(borrows.len(), 16);
+    let _ = (borrows[0].0, <() as Params>::create(&[], &mut [], &Context));
+}
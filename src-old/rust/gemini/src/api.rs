@@ -0,0 +1,297 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Deserialize;
+
+use crate::GeminiError;
+
+/// Status line and headers of a response, as dumped by `curl -D -`.
+#[derive(Debug)]
+pub(crate) struct ResponseHead {
+    pub(crate) status: u16,
+    headers: Vec<(String, String)>,
+}
+
+impl ResponseHead {
+    /// Parses the final header block at the start of `buf`, skipping interim
+    /// `1xx` responses. Returns the head and the offset where the body starts,
+    /// or `None` if the block is not complete yet.
+    pub(crate) fn parse(buf: &[u8]) -> Option<Result<(ResponseHead, usize), GeminiError>> {
+        let mut offset = 0;
+        loop {
+            let rest = &buf[offset..];
+            let (len, sep) = match find(rest, b"\r\n\r\n") {
+                Some(len) => (len, 4),
+                None => (find(rest, b"\n\n")?, 2),
+            };
+            let block = String::from_utf8_lossy(&rest[..len]);
+            offset += len + sep;
+
+            let mut lines = block.lines();
+            let status = lines
+                .next()
+                .and_then(|line| line.split_whitespace().nth(1))
+                .and_then(|code| code.parse::<u16>().ok());
+            let Some(status) = status else {
+                return Some(Err(GeminiError::HttpError(format!(
+                    "Malformed response head: {}",
+                    block
+                ))));
+            };
+            if (100..200).contains(&status) {
+                continue;
+            }
+
+            let headers = lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+                .collect();
+            return Some(Ok((ResponseHead { status, headers }, offset)));
+        }
+    }
+
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    /// Maps a non-2xx response body to a typed API error.
+    pub(crate) fn error(&self, body: &[u8]) -> GeminiError {
+        api_error(self.status, self.header("retry-after"), body)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[derive(Deserialize)]
+struct ErrorEnvelope {
+    error: ErrorBody,
+}
+
+#[derive(Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    code: Option<u16>,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    status: String,
+    #[serde(default)]
+    details: Vec<serde_json::Value>,
+}
+
+/// Builds `GeminiError::Api` from the HTTP status, an optional `Retry-After`
+/// header and the `{"error": {...}}` body, which the streaming endpoint wraps
+/// in an array. A `google.rpc.RetryInfo` detail is used when the header is
+/// missing.
+pub(crate) fn api_error(code: u16, retry_after: Option<&str>, body: &[u8]) -> GeminiError {
+    let value: Option<serde_json::Value> = serde_json::from_slice(body).ok();
+    let value = match value {
+        Some(serde_json::Value::Array(mut items)) if !items.is_empty() => Some(items.remove(0)),
+        other => other,
+    };
+    let envelope = value.and_then(|value| serde_json::from_value::<ErrorEnvelope>(value).ok());
+
+    let Some(ErrorEnvelope { error }) = envelope else {
+        return GeminiError::Api {
+            code,
+            status: String::new(),
+            message: String::from_utf8_lossy(body).trim().to_string(),
+            retry_after: retry_after.and_then(parse_retry_after),
+        };
+    };
+
+    let retry_after = retry_after.and_then(parse_retry_after).or_else(|| {
+        error
+            .details
+            .iter()
+            .filter(|detail| {
+                detail["@type"]
+                    .as_str()
+                    .is_some_and(|kind| kind.ends_with("google.rpc.RetryInfo"))
+            })
+            .find_map(|detail| detail["retryDelay"].as_str())
+            .and_then(parse_proto_duration)
+    });
+
+    GeminiError::Api {
+        code: error.code.unwrap_or(code),
+        status: error.status,
+        message: error.message,
+        retry_after,
+    }
+}
+
+/// Parses a `Retry-After` value, either delta-seconds or an IMF-fixdate.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = parse_http_date(value)?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Parses protobuf JSON durations such as `"37s"` or `"1.5s"`.
+fn parse_proto_duration(value: &str) -> Option<Duration> {
+    let seconds = value.strip_suffix('s')?.parse::<f64>().ok()?;
+    (seconds >= 0.0).then(|| Duration::from_secs_f64(seconds))
+}
+
+/// Parses `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let mut parts = value.split_whitespace().skip(1);
+    let day = parts.next()?.parse::<i64>().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year = parts.next()?.parse::<i64>().ok()?;
+    let mut time = parts.next()?.split(':').map(|x| x.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" {
+        return None;
+    }
+
+    // Days since the epoch for the proleptic Gregorian calendar.
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUOTA: &str = r#"{
+  "error": {
+    "code": 429,
+    "message": "Resource has been exhausted (e.g. check quota).",
+    "status": "RESOURCE_EXHAUSTED",
+    "details": [
+      { "@type": "type.googleapis.com/google.rpc.RetryInfo", "retryDelay": "37s" }
+    ]
+  }
+}"#;
+
+    const INVALID: &str = r#"{
+  "error": {
+    "code": 400,
+    "message": "Invalid JSON payload received.",
+    "status": "INVALID_ARGUMENT"
+  }
+}"#;
+
+    const INTERNAL: &str = r#"[{
+  "error": {
+    "code": 500,
+    "message": "An internal error has occurred.",
+    "status": "INTERNAL"
+  }
+}]"#;
+
+    #[test]
+    fn test_quota_exhausted() {
+        let head = b"HTTP/2 429 \r\ncontent-type: application/json\r\nRetry-After: 12\r\n\r\n";
+        let mut raw = head.to_vec();
+        raw.extend_from_slice(QUOTA.as_bytes());
+        let (head, start) = ResponseHead::parse(&raw).unwrap().unwrap();
+        assert!(!head.is_success());
+
+        let err = head.error(&raw[start..]);
+        assert!(err.is_retryable());
+        match err {
+            GeminiError::Api {
+                code,
+                status,
+                retry_after,
+                ..
+            } => {
+                assert_eq!(code, 429);
+                assert_eq!(status, "RESOURCE_EXHAUSTED");
+                assert_eq!(retry_after, Some(Duration::from_secs(12)));
+            }
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_retry_info_without_header() {
+        match api_error(429, None, QUOTA.as_bytes()) {
+            GeminiError::Api { retry_after, .. } => {
+                assert_eq!(retry_after, Some(Duration::from_secs(37)))
+            }
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_argument() {
+        let err = api_error(400, None, INVALID.as_bytes());
+        assert!(!err.is_retryable());
+        match err {
+            GeminiError::Api {
+                code,
+                status,
+                message,
+                retry_after,
+            } => {
+                assert_eq!(code, 400);
+                assert_eq!(status, "INVALID_ARGUMENT");
+                assert_eq!(message, "Invalid JSON payload received.");
+                assert_eq!(retry_after, None);
+            }
+            other => panic!("unexpected error {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_internal_error_in_stream_array() {
+        let err = api_error(500, None, INTERNAL.as_bytes());
+        assert!(err.is_retryable());
+        assert!(matches!(err, GeminiError::Api { code: 500, ref status, .. } if status == "INTERNAL"));
+    }
+
+    #[test]
+    fn test_unstructured_body() {
+        let err = api_error(502, None, b"<html>Bad Gateway</html>");
+        assert!(err.is_retryable());
+        assert!(matches!(err, GeminiError::Api { code: 502, ref message, .. } if message.contains("Bad Gateway")));
+    }
+
+    #[test]
+    fn test_interim_head_skipped() {
+        let raw = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nbody";
+        let (head, start) = ResponseHead::parse(raw).unwrap().unwrap();
+        assert!(head.is_success());
+        assert_eq!(head.header("content-type"), Some("text/plain"));
+        assert_eq!(&raw[start..], b"body");
+        assert!(ResponseHead::parse(b"HTTP/1.1 200 OK\r\nContent-").is_none());
+    }
+
+    #[test]
+    fn test_retry_after_http_date() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(784111777))
+        );
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(Duration::ZERO)
+        );
+    }
+}
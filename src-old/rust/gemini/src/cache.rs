@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use crate::{Candidate, GeminiPart};
+
+/// Where and for how long responses are kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub dir: PathBuf,
+    /// Entries older than this are misses and removed by
+    /// [`Cache::purge_expired`].
+    pub ttl: Duration,
+    /// Oldest entries are evicted past this many.
+    pub max_entries: usize,
+}
+
+/// Responses on disk, one JSON file per request body.
+#[derive(Debug)]
+pub struct Cache {
+    config: CacheConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    model: String,
+    candidates: Vec<CachedCandidate>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedCandidate {
+    index: usize,
+    /// Replies in the API's part format.
+    parts: Vec<Value>,
+    finish_reason: Option<String>,
+    token_count: Option<u32>,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// Key of a rendered request. The body already carries contents, tools
+    /// and generation config with sorted keys, so equal requests hash equal.
+    pub(crate) fn key(model: &str, body: &str) -> u64 {
+        let mut hasher = hash::Xxh64::new(0);
+        hasher.update(model.as_bytes());
+        hasher.update(&[0]);
+        hasher.update(body.as_bytes());
+        hasher.finalize()
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.config.dir.join(format!("{key:016x}.json"))
+    }
+
+    /// The candidates stored under `key`, unless missing, unreadable or
+    /// expired.
+    pub(crate) fn get(&self, key: u64) -> Option<Vec<Candidate>> {
+        let path = self.path(key);
+        if self.expired(&fs::metadata(&path).ok()?) {
+            return None;
+        }
+        let entry: Entry = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+        entry
+            .candidates
+            .into_iter()
+            .map(|candidate| {
+                Some(Candidate {
+                    index: candidate.index,
+                    replies: candidate
+                        .parts
+                        .into_iter()
+                        .map(|part| serde_json::from_value::<GeminiPart>(part).ok()?.into_reply())
+                        .collect::<Option<_>>()?,
+                    finish_reason: candidate.finish_reason,
+                    token_count: candidate.token_count,
+                })
+            })
+            .collect()
+    }
+
+    /// Stores `candidates` under `key`. The entry is written to a temporary
+    /// file first and renamed, so readers never see a partial entry.
+    pub(crate) fn put(&self, key: u64, model: &str, candidates: &[Candidate]) -> io::Result<()> {
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+
+        fs::create_dir_all(&self.config.dir)?;
+        let entry = Entry {
+            model: model.to_string(),
+            candidates: candidates
+                .iter()
+                .map(|candidate| CachedCandidate {
+                    index: candidate.index,
+                    parts: candidate.replies.iter().map(|reply| reply.to_part().to_json()).collect(),
+                    finish_reason: candidate.finish_reason.clone(),
+                    token_count: candidate.token_count,
+                })
+                .collect(),
+        };
+        let temp = self.config.dir.join(format!(
+            ".{key:016x}.{}.{}.tmp",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temp, serde_json::to_vec(&entry)?)?;
+        if let Err(e) = fs::rename(&temp, self.path(key)) {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        self.evict()
+    }
+
+    /// Removes expired entries and returns how many there were.
+    pub fn purge_expired(&self) -> io::Result<usize> {
+        let mut purged = 0;
+        for (path, modified) in self.entries()? {
+            if self.expired_at(modified) {
+                fs::remove_file(path)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    fn evict(&self) -> io::Result<()> {
+        let mut entries = self.entries()?;
+        if entries.len() <= self.config.max_entries {
+            return Ok(());
+        }
+        entries.sort_by_key(|(_, modified)| *modified);
+        let excess = entries.len() - self.config.max_entries;
+        for (path, _) in entries.into_iter().take(excess) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Entry files with their write time. Temporary files are skipped.
+    fn entries(&self) -> io::Result<Vec<(PathBuf, SystemTime)>> {
+        let dir = match fs::read_dir(&self.config.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+        let mut entries = vec![];
+        for entry in dir {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with('.') || !name.ends_with(".json") {
+                continue;
+            }
+            entries.push((entry.path(), entry.metadata()?.modified()?));
+        }
+        Ok(entries)
+    }
+
+    fn expired(&self, metadata: &fs::Metadata) -> bool {
+        metadata.modified().map_or(true, |modified| self.expired_at(modified))
+    }
+
+    fn expired_at(&self, modified: SystemTime) -> bool {
+        // Clock skew making an entry look newer than now counts as fresh
+        SystemTime::now()
+            .duration_since(modified)
+            .is_ok_and(|age| age >= self.config.ttl)
+    }
+}
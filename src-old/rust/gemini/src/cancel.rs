@@ -0,0 +1,96 @@
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::{Exchange, GeminiError};
+
+/// How often a blocked read checks its [`CancellationToken`].
+const CANCEL_CHECK: Duration = Duration::from_millis(50);
+
+/// Stops requests of the clients it was given to, from any thread. Streams
+/// end with [`GeminiError::Cancelled`] and the transport is torn down.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), GeminiError> {
+        match self.is_cancelled() {
+            true => Err(GeminiError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Deadline, idle timeout and cancellation of one call, retries included.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Limits {
+    pub(crate) deadline: Option<(Instant, Duration)>,
+    pub(crate) idle: Option<Duration>,
+    pub(crate) cancel: Option<CancellationToken>,
+}
+
+impl Limits {
+    pub(crate) fn check(&self) -> Result<(), GeminiError> {
+        if let Some(cancel) = &self.cancel {
+            cancel.check()?;
+        }
+        match self.deadline {
+            Some((deadline, after)) if Instant::now() >= deadline => Err(GeminiError::Timeout { after }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads from `exchange` within the limits. When one is hit the exchange
+    /// is cancelled and the outer error says which; the inner result is
+    /// that of the read itself.
+    pub(crate) fn read(
+        &self,
+        exchange: &mut dyn Exchange,
+        buf: &mut [u8],
+    ) -> Result<io::Result<usize>, GeminiError> {
+        let idle_since = Instant::now();
+        loop {
+            let now = Instant::now();
+            let idle_left = self.idle.map(|idle| (idle_since + idle).saturating_duration_since(now));
+            let limit = match (self.idle, idle_left) {
+                (Some(after), Some(Duration::ZERO)) => Err(GeminiError::IdleTimeout { after }),
+                _ => Ok(()),
+            };
+            if let Err(e) = self.check().and(limit) {
+                exchange.cancel();
+                return Err(e);
+            }
+
+            let wait = [
+                self.deadline.map(|(deadline, _)| deadline.saturating_duration_since(now)),
+                idle_left,
+                self.cancel.as_ref().map(|_| CANCEL_CHECK),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+            let Some(wait) = wait else {
+                return Ok(exchange.read(buf));
+            };
+            match exchange.read_timeout(buf, wait) {
+                Ok(Some(read)) => return Ok(Ok(read)),
+                Ok(None) => {}
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Ok(Err(e)),
+            }
+        }
+    }
+}
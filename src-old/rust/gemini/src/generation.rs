@@ -0,0 +1,92 @@
+use std::fmt;
+use std::ops::Deref;
+
+use crate::ModelReply;
+
+/// One of the alternative answers requested with
+/// [`GeminiClient::with_candidate_count`](crate::GeminiClient::with_candidate_count).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub index: usize,
+    pub replies: Vec<ModelReply>,
+    /// Why generation stopped, e.g. `STOP` or `MAX_TOKENS`.
+    pub finish_reason: Option<String>,
+    pub token_count: Option<u32>,
+}
+
+impl Candidate {
+    /// The text parts of this candidate, joined.
+    pub fn text(&self) -> String {
+        self.replies
+            .iter()
+            .filter_map(|reply| match reply {
+                ModelReply::Text(text) => Some(text.as_str()),
+                ModelReply::FunctionCall { .. } => None,
+            })
+            .collect()
+    }
+}
+
+/// Result of [`GeminiClient::generate_content`](crate::GeminiClient::generate_content).
+/// Reads as the text of the first candidate; the others are in
+/// [`candidates`](Generation::candidates).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Generation {
+    text: String,
+    candidates: Vec<Candidate>,
+}
+
+impl Generation {
+    /// `None` unless the first candidate has text.
+    pub(crate) fn new(candidates: Vec<Candidate>) -> Option<Self> {
+        let first = candidates.first()?;
+        if !first.replies.iter().any(|reply| matches!(reply, ModelReply::Text(_))) {
+            return None;
+        }
+        Some(Self {
+            text: first.text(),
+            candidates,
+        })
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Every candidate, ordered by index.
+    pub fn candidates(&self) -> &[Candidate] {
+        &self.candidates
+    }
+}
+
+impl Deref for Generation {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl From<Generation> for String {
+    fn from(generation: Generation) -> Self {
+        generation.text
+    }
+}
+
+impl fmt::Display for Generation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl PartialEq<str> for Generation {
+    fn eq(&self, other: &str) -> bool {
+        self.text == other
+    }
+}
+
+impl PartialEq<&str> for Generation {
+    fn eq(&self, other: &&str) -> bool {
+        self.text == *other
+    }
+}
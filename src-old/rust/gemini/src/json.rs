@@ -0,0 +1,73 @@
+use serde::de::DeserializeOwned;
+
+use crate::GeminiError;
+
+pub(crate) const MIME_TYPE: &str = "application/json";
+
+/// The JSON payload of a reply. JSON mode should never wrap its output in a
+/// code fence, but models do it anyway, sometimes with prose around it.
+pub(crate) fn payload(text: &str) -> &str {
+    let trimmed = text.trim();
+    if trimmed.starts_with(['{', '[']) {
+        return trimmed;
+    }
+    let Some(start) = trimmed.find("```") else {
+        return trimmed;
+    };
+    // Skip the info string, e.g. ```json
+    let Some(line_end) = trimmed[start..].find('\n') else {
+        return trimmed;
+    };
+    let body = &trimmed[start + line_end + 1..];
+    match body.find("```") {
+        Some(end) => body[..end].trim(),
+        None => body.trim(),
+    }
+}
+
+pub(crate) fn parse<T: DeserializeOwned>(text: &str) -> Result<T, GeminiError> {
+    let payload = payload(text);
+    serde_json::from_str(payload).map_err(|e| {
+        GeminiError::JsonParseError(format!(
+            "Reply does not match {}: {}. Payload: {}",
+            std::any::type_name::<T>(),
+            e,
+            payload
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Missing {
+        path: String,
+    }
+
+    #[test]
+    fn test_payload() {
+        let bare = r#"[{"path": "a.h"}]"#;
+        assert_eq!(payload(bare), bare);
+        assert_eq!(payload(&format!("\n  {bare}\n")), bare);
+        assert_eq!(payload(&format!("```json\n{bare}\n```")), bare);
+        assert_eq!(payload(&format!("```\n{bare}\n```\n")), bare);
+        assert_eq!(payload(&format!("Here you go:\n```json\n{bare}\n```\nDone.")), bare);
+        assert_eq!(payload(&format!("```json\n{bare}")), bare);
+        // Fences inside string values of bare JSON are left alone
+        let quoted = r#"{"path": "```json"}"#;
+        assert_eq!(payload(quoted), quoted);
+    }
+
+    #[test]
+    fn test_parse_error_has_payload() {
+        let err = parse::<Vec<Missing>>("```json\n[{\"file\": \"a.h\"}]\n```").unwrap_err();
+        let GeminiError::JsonParseError(msg) = err else {
+            panic!("unexpected error {err:?}");
+        };
+        assert!(msg.contains("missing field `path`"), "{msg}");
+        assert!(msg.ends_with(r#"Payload: [{"file": "a.h"}]"#), "{msg}");
+    }
+}
@@ -6,16 +6,47 @@
     trait_alias
 )]
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader};
-use std::ops::{ControlFlow, Coroutine};
-use std::pin::Pin;
-use std::process::{Command, Stdio};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Coroutine, CoroutineState};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod api;
+mod cache;
+mod cancel;
+mod generation;
+mod json;
+mod models;
+mod part;
+mod retry;
+mod stream;
+mod tool;
+mod transport;
+mod usage;
+
+use api::ResponseHead;
+use cancel::Limits;
+use models::ModelPage;
+use retry::{Dedup, Retrier};
+use stream::StreamParser;
+
+pub use cache::{Cache, CacheConfig};
+pub use cancel::CancellationToken;
+pub use generation::{Candidate, Generation};
+pub use models::ModelInfo;
+pub use part::{Content, INLINE_LIMIT, Part, Prompt, Role};
+pub use retry::{Clock, RetryEvent, RetryPolicy, SystemClock};
+pub use tool::{FunctionDeclaration, ModelReply};
+pub use transport::{CurlTransport, Exchange, PoolStats, PooledTransport, Transport};
+pub use usage::{CostEstimate, ModelUsage, Price, PriceTable, UsageTracker};
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiResponse {
+    #[serde(default)]
     candidates: Vec<GeminiCandidate>,
     #[serde(default)]
     usage_metadata: Option<UsageMetadata>,
@@ -24,7 +55,9 @@ struct GeminiResponse {
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiCandidate {
+    #[serde(default)]
     content: GeminiContent,
     #[serde(default)]
     finish_reason: Option<String>,
@@ -32,19 +65,25 @@ struct GeminiCandidate {
     safety_ratings: Option<Vec<SafetyRating>>,
     #[serde(default)]
     index: Option<i32>,
+    #[serde(default)]
+    token_count: Option<u32>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct GeminiContent {
+    #[serde(default)]
     parts: Vec<GeminiPart>,
     #[serde(default)]
     role: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiPart {
     #[serde(default)]
     text: Option<String>,
+    #[serde(default)]
+    function_call: Option<tool::FunctionCall>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,14 +94,63 @@ struct SafetyRating {
     blocked: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct UsageMetadata {
+/// Token counts the API reports alongside a response.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageMetadata {
     #[serde(default)]
-    prompt_token_count: Option<i32>,
+    pub prompt_token_count: Option<i32>,
     #[serde(default)]
-    candidates_token_count: Option<i32>,
+    pub candidates_token_count: Option<i32>,
     #[serde(default)]
-    total_token_count: Option<i32>,
+    pub total_token_count: Option<i32>,
+}
+
+/// Result of the `countTokens` endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenCount {
+    pub total_tokens: u32,
+    #[serde(default)]
+    pub cached_content_token_count: Option<u32>,
+}
+
+impl GeminiResponse {
+    /// Every candidate with its text and function calls, ordered by index.
+    fn candidates(self) -> Vec<Candidate> {
+        let mut candidates = self
+            .candidates
+            .into_iter()
+            .enumerate()
+            .map(|(position, candidate)| Candidate {
+                // A lone candidate may leave its index out
+                index: candidate.index.map_or(position, |index| index as usize),
+                replies: candidate
+                    .content
+                    .parts
+                    .into_iter()
+                    .filter_map(GeminiPart::into_reply)
+                    .collect(),
+                finish_reason: candidate.finish_reason,
+                token_count: candidate.token_count,
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|candidate| candidate.index);
+        candidates
+    }
+}
+
+impl GeminiPart {
+    fn into_reply(self) -> Option<ModelReply> {
+        match self {
+            GeminiPart {
+                function_call: Some(call),
+                ..
+            } => Some(call.into()),
+            GeminiPart { text: Some(text), .. } => Some(ModelReply::Text(text)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -72,6 +160,74 @@ pub enum GeminiError {
     CurlError(String),
     IoError(String),
     StreamError(String),
+    /// curl itself failed, e.g. exit code 56 for a connection reset mid-stream.
+    Transport {
+        exit_code: i32,
+        stderr: String,
+    },
+    /// The API answered with a non-2xx status.
+    Api {
+        code: u16,
+        status: String,
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// Rejected before sending because the prompt exceeds the input budget.
+    InputTooLarge {
+        tokens: u32,
+        limit: u32,
+    },
+    /// Inline data over [`INLINE_LIMIT`] bytes.
+    InlineDataTooLarge {
+        size: usize,
+        limit: usize,
+    },
+    /// The call, retries included, outlived [`GeminiClient::with_timeout`].
+    Timeout {
+        after: Duration,
+    },
+    /// No bytes arrived for [`GeminiClient::with_idle_timeout`].
+    IdleTimeout {
+        after: Duration,
+    },
+    /// Stopped through a [`CancellationToken`].
+    Cancelled,
+    /// The model does not exist, or cannot `generateContent` in which case
+    /// the methods it does support are listed.
+    UnknownModel {
+        model: String,
+        supported_methods: Option<Vec<String>>,
+    },
+}
+
+impl GeminiError {
+    /// Whether repeating the same request may succeed: rate limiting, server
+    /// side failures and dropped connections.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            GeminiError::Api { code, status, .. } => {
+                matches!(code, 408 | 429 | 500 | 502 | 503 | 504)
+                    || matches!(
+                        status.as_str(),
+                        "RESOURCE_EXHAUSTED" | "UNAVAILABLE" | "INTERNAL" | "DEADLINE_EXCEEDED"
+                    )
+            }
+            // Could not resolve/connect, timeout, SSL connect, empty reply, send/recv failure.
+            GeminiError::Transport { exit_code, .. } => {
+                matches!(exit_code, 6 | 7 | 18 | 28 | 35 | 52 | 55 | 56)
+            }
+            // A stalled connection, unlike a spent deadline
+            GeminiError::IdleTimeout { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            GeminiError::Api { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for GeminiError {
@@ -82,6 +238,45 @@ impl std::fmt::Display for GeminiError {
             GeminiError::CurlError(msg) => write!(f, "Curl Error: {}", msg),
             GeminiError::IoError(msg) => write!(f, "IO Error: {}", msg),
             GeminiError::StreamError(msg) => write!(f, "Stream Error: {}", msg),
+            GeminiError::Transport { exit_code, stderr } => {
+                write!(f, "Curl command failed with exit code: {}: {}", exit_code, stderr)
+            }
+            GeminiError::Api {
+                code,
+                status,
+                message,
+                retry_after,
+            } => {
+                write!(f, "API Error {} {}: {}", code, status, message)?;
+                if let Some(retry_after) = retry_after {
+                    write!(f, " (retry after {:?})", retry_after)?;
+                }
+                Ok(())
+            }
+            GeminiError::InputTooLarge { tokens, limit } => {
+                write!(f, "Input of {} tokens exceeds the limit of {}", tokens, limit)
+            }
+            GeminiError::InlineDataTooLarge { size, limit } => write!(
+                f,
+                "Inline data of {} bytes exceeds the limit of {}; upload it with the Files API and send a Part::FileData instead",
+                size, limit
+            ),
+            GeminiError::Timeout { after } => write!(f, "Request timed out after {:?}", after),
+            GeminiError::IdleTimeout { after } => write!(f, "No data received for {:?}", after),
+            GeminiError::Cancelled => write!(f, "Request cancelled"),
+            GeminiError::UnknownModel {
+                model,
+                supported_methods: None,
+            } => write!(f, "Unknown model {}", model),
+            GeminiError::UnknownModel {
+                model,
+                supported_methods: Some(methods),
+            } => write!(
+                f,
+                "Model {} does not support generateContent, only: {}",
+                model,
+                methods.join(", ")
+            ),
         }
     }
 }
@@ -92,10 +287,46 @@ impl std::error::Error for GeminiError {}
 pub trait StreamingCoroutine =
     std::ops::Coroutine<(), Yield = Result<String, GeminiError>, Return = Result<(), GeminiError>>;
 
+/// Streams text and function calls as they arrive.
+pub trait ReplyStreamingCoroutine =
+    std::ops::Coroutine<(), Yield = Result<ModelReply, GeminiError>, Return = Result<(), GeminiError>>;
+
+/// Streams text like [`StreamingCoroutine`] and returns the parsed JSON reply.
+pub trait JsonStreamingCoroutine<T> =
+    std::ops::Coroutine<(), Yield = Result<String, GeminiError>, Return = Result<T, GeminiError>>;
+
+/// Streams text deltas of every candidate, tagged with the candidate's index.
+pub trait CandidateStreamingCoroutine = std::ops::Coroutine<
+        (),
+        Yield = Result<(usize, String), GeminiError>,
+        Return = Result<(), GeminiError>,
+    >;
+
+/// Text and function calls of every candidate, tagged with its index.
+trait IndexedReplyCoroutine = std::ops::Coroutine<
+        (),
+        Yield = Result<(usize, ModelReply), GeminiError>,
+        Return = Result<(), GeminiError>,
+    >;
+
+/// A Gemini model endpoint. The client is `Send + Sync`, so one instance
+/// can serve several threads, and clones share their generation config.
+#[derive(Clone)]
 pub struct GeminiClient {
     model_id: String,
     api_key: Option<String>,
-    generation_config: HashMap<String, Value>,
+    /// Copied on write by the builder methods.
+    generation_config: Arc<HashMap<String, Value>>,
+    retrier: Retrier,
+    transport: Arc<dyn Transport>,
+    max_input_tokens: Option<u32>,
+    usage: UsageTracker,
+    tools: Vec<FunctionDeclaration>,
+    cache: Option<Arc<Cache>>,
+    bypass_cache: bool,
+    timeout: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    cancel: Option<CancellationToken>,
 }
 
 impl GeminiClient {
@@ -103,7 +334,21 @@ impl GeminiClient {
         GeminiClient {
             model_id: model_id.to_string(),
             api_key: None,
-            generation_config: HashMap::new(),
+            generation_config: Arc::default(),
+            retrier: Retrier {
+                policy: RetryPolicy::none(),
+                on_retry: None,
+                clock: Arc::new(SystemClock),
+            },
+            transport: Arc::new(CurlTransport::default()),
+            max_input_tokens: None,
+            usage: UsageTracker::new(),
+            tools: Vec::new(),
+            cache: None,
+            bypass_cache: false,
+            timeout: None,
+            idle_timeout: None,
+            cancel: None,
         }
     }
 
@@ -113,510 +358,1888 @@ impl GeminiClient {
     }
 
     pub fn with_temperature(mut self, temperature: f32) -> Self {
-        self.generation_config
+        Arc::make_mut(&mut self.generation_config)
             .insert("temperature".to_string(), json!(temperature));
         self
     }
 
     pub fn with_max_output_tokens(mut self, max_tokens: i32) -> Self {
-        self.generation_config
+        Arc::make_mut(&mut self.generation_config)
             .insert("maxOutputTokens".to_string(), json!(max_tokens));
         self
     }
 
     pub fn with_top_p(mut self, top_p: f32) -> Self {
-        self.generation_config
+        Arc::make_mut(&mut self.generation_config)
             .insert("topP".to_string(), json!(top_p));
         self
     }
 
     pub fn with_top_k(mut self, top_k: i32) -> Self {
-        self.generation_config
+        Arc::make_mut(&mut self.generation_config)
             .insert("topK".to_string(), json!(top_k));
         self
     }
 
-    // Non-streaming version (kept for compatibility)
-    pub fn generate_content(&self, text: &str) -> Result<String, GeminiError> {
-        // Get API key - either from the client or fail
-        let api_key = match &self.api_key {
-            Some(key) => key,
-            None => {
-                return Err(GeminiError::HttpError(
-                    "API key is required for Gemini API".to_string(),
-                ));
+    /// Asks for `count` alternative answers in one call. They are read
+    /// through [`Generation::candidates`] or
+    /// [`generate_candidates_streaming`](GeminiClient::generate_candidates_streaming).
+    pub fn with_candidate_count(mut self, count: u32) -> Self {
+        Arc::make_mut(&mut self.generation_config)
+            .insert("candidateCount".to_string(), json!(count));
+        self
+    }
+
+    pub fn with_response_mime_type(mut self, mime_type: &str) -> Self {
+        Arc::make_mut(&mut self.generation_config)
+            .insert("responseMimeType".to_string(), json!(mime_type));
+        self
+    }
+
+    /// Constrains JSON replies to an OpenAPI style schema.
+    pub fn with_response_schema(mut self, schema: Value) -> Self {
+        Arc::make_mut(&mut self.generation_config)
+            .insert("responseSchema".to_string(), schema);
+        self
+    }
+
+    /// Functions the model may call. Calls come back as
+    /// [`ModelReply::FunctionCall`] from the reply based methods.
+    pub fn with_tools(mut self, tools: Vec<FunctionDeclaration>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// Retries failed calls, streaming or not, according to `policy`.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retrier.policy = policy;
+        self
+    }
+
+    /// Called before every backoff sleep, e.g. for logging.
+    pub fn with_retry_callback(mut self, callback: impl Fn(&RetryEvent) + Send + Sync + 'static) -> Self {
+        self.retrier.on_retry = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.retrier.clock = clock;
+        self
+    }
+
+    /// Fails calls, retries and backoff included, that take longer than
+    /// `timeout` with [`GeminiError::Timeout`].
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fails an attempt that receives nothing for `timeout` with
+    /// [`GeminiError::IdleTimeout`], which is retried like a dropped
+    /// connection.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Cancels in-flight and future calls once `token` is triggered.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    /// Counts prompt tokens with the API before every request and fails with
+    /// `InputTooLarge` instead of sending a prompt over `limit`.
+    pub fn with_max_input_tokens(mut self, limit: u32) -> Self {
+        self.max_input_tokens = Some(limit);
+        self
+    }
+
+    /// Serves repeated requests from disk instead of the API. Requests are
+    /// keyed by model and request body, so changing the prompt, tools or
+    /// generation config misses.
+    pub fn with_cache(mut self, config: CacheConfig) -> Self {
+        self.cache = Some(Arc::new(Cache::new(config)));
+        self
+    }
+
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_deref()
+    }
+
+    /// A client that always calls the API, for a single request that must be
+    /// fresh. The response still replaces the cached one.
+    pub fn bypass_cache(&self) -> Self {
+        Self {
+            bypass_cache: true,
+            ..self.clone()
+        }
+    }
+
+    /// Usage reported by the most recent successful response. Cache hits
+    /// leave it unchanged.
+    pub fn last_usage(&self) -> Option<UsageMetadata> {
+        self.usage.last()
+    }
+
+    /// Tokens spent by this client and its clones, per model.
+    pub fn usage(&self) -> &UsageTracker {
+        &self.usage
+    }
+
+    /// Requests sent and connections opened by the transport, to report
+    /// alongside [`usage`](GeminiClient::usage).
+    pub fn pool_stats(&self) -> PoolStats {
+        self.transport.stats()
+    }
+
+    /// Records usage into `tracker` instead, e.g. to account for a whole
+    /// session across clients.
+    pub fn with_usage_tracker(mut self, tracker: UsageTracker) -> Self {
+        self.usage = tracker;
+        self
+    }
+
+    pub fn count_tokens(&self, text: &str) -> Result<TokenCount, GeminiError> {
+        self.count_contents(&user(&[Part::text(text)]))
+    }
+
+    fn count_contents(&self, contents: &[Content]) -> Result<TokenCount, GeminiError> {
+        let url = self.url("countTokens")?;
+        let body = json!({ "contents": to_json(contents) }).to_string();
+        let limits = self.limits();
+        self.retrier.run(|| {
+            parse_body(&post_once(&*self.transport, &url, &body, &limits)?, "token count")
+        })
+    }
+
+    /// Every model available to the API key.
+    pub fn list_models(&self) -> Result<Vec<ModelInfo>, GeminiError> {
+        let limits = self.limits();
+        let mut models = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut url = format!("{}/models?pageSize={}&key={}", API_BASE, MODEL_PAGE_SIZE, self.api_key()?);
+            if let Some(token) = &page_token {
+                url.push_str("&pageToken=");
+                url.push_str(&query_escape(token));
             }
-        };
+            let page: ModelPage = self.retrier.run(|| {
+                parse_body(&get_once(&*self.transport, &url, &limits)?, "model list")
+            })?;
+            models.extend(page.models);
+            match page.next_page_token {
+                Some(token) if !token.is_empty() => page_token = Some(token),
+                _ => return Ok(models),
+            }
+        }
+    }
 
-        // Use the correct URL format for non-streaming
-        let url = format!(
-            "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent?key={}",
-            self.model_id, api_key
-        );
+    /// Details of the configured model, such as its token limits.
+    pub fn model_info(&self) -> Result<ModelInfo, GeminiError> {
+        let url = format!("{}/models/{}?key={}", API_BASE, self.model_id, self.api_key()?);
+        let limits = self.limits();
+        self.retrier
+            .run(|| parse_body(&get_once(&*self.transport, &url, &limits)?, "model"))
+            .map_err(|e| match e {
+                GeminiError::Api { code: 404, .. } => GeminiError::UnknownModel {
+                    model: self.model_id.clone(),
+                    supported_methods: None,
+                },
+                e => e,
+            })
+    }
 
-        // Prepare the request body
-        let mut request_body = json!({
-            "contents": [
-                {
-                    "role": "user",
-                    "parts": [
-                        {
-                            "text": text
-                        }
-                    ]
-                }
-            ]
-        });
+    /// Checks that the configured model exists and can generate content, so
+    /// a typo in the model id fails here rather than at the first request.
+    /// Returns the model's details for budgeting prompts against its limits.
+    pub fn validate(&self) -> Result<ModelInfo, GeminiError> {
+        let info = self.model_info()?;
+        if !info.supports("generateContent") {
+            return Err(GeminiError::UnknownModel {
+                model: self.model_id.clone(),
+                supported_methods: Some(info.supported_generation_methods),
+            });
+        }
+        Ok(info)
+    }
 
-        if !self.generation_config.is_empty() {
-            let config = self
-                .generation_config
-                .iter()
-                .map(|(k, v)| (k.clone(), v.clone()))
-                .collect::<HashMap<_, _>>();
-            request_body["generationConfig"] = json!(config);
+    fn preflight(&self, contents: &[Content]) -> Result<(), GeminiError> {
+        let Some(limit) = self.max_input_tokens else {
+            return Ok(());
+        };
+        let tokens = self.count_contents(contents)?.total_tokens;
+        if tokens > limit {
+            return Err(GeminiError::InputTooLarge { tokens, limit });
         }
+        Ok(())
+    }
 
-        let json_body = serde_json::to_string(&request_body)
-            .map_err(|e| GeminiError::JsonParseError(e.to_string()))?;
+    /// Limits of a call starting now.
+    fn limits(&self) -> Limits {
+        Limits {
+            deadline: self.timeout.map(|timeout| (Instant::now() + timeout, timeout)),
+            idle: self.idle_timeout,
+            cancel: self.cancel.clone(),
+        }
+    }
 
-        // Pass JSON data directly to curl
-        let mut curl_cmd = Command::new("curl");
+    fn url(&self, method: &str) -> Result<String, GeminiError> {
+        Ok(format!(
+            "{}/models/{}:{}?key={}",
+            API_BASE,
+            self.model_id,
+            method,
+            self.api_key()?
+        ))
+    }
+
+    fn api_key(&self) -> Result<&str, GeminiError> {
+        self.api_key.as_deref().ok_or_else(|| {
+            GeminiError::HttpError("API key is required for Gemini API".to_string())
+        })
+    }
 
-        curl_cmd
-            .arg("-X")
-            .arg("POST")
-            .arg("-H")
-            .arg("Content-Type: application/json; charset=utf-8")
-            .arg("-d")
-            .arg(json_body)
-            .arg(url);
+    fn request_body(&self, contents: &[Content], json_mode: bool) -> Result<String, GeminiError> {
+        part::check_inline(contents.iter().flat_map(|content| &content.parts))?;
 
-        let output = curl_cmd
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .map_err(|e| GeminiError::CurlError(e.to_string()))?;
+        // Prepare the request body
+        let mut request_body = json!({ "contents": to_json(contents) });
+        if !self.tools.is_empty() {
+            request_body["tools"] = tool::tools(&self.tools);
+        }
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(GeminiError::HttpError(format!(
-                "Curl command failed: {}",
-                stderr
-            )));
+        let mut config = self
+            .generation_config
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect::<HashMap<_, _>>();
+        if json_mode {
+            config.insert("responseMimeType".to_string(), json!(json::MIME_TYPE));
+        }
+        if !config.is_empty() {
+            request_body["generationConfig"] = json!(config);
         }
 
-        let response_str = String::from_utf8_lossy(&output.stdout).to_string();
+        serde_json::to_string(&request_body).map_err(|e| GeminiError::JsonParseError(e.to_string()))
+    }
 
-        let response: GeminiResponse = serde_json::from_str(&response_str).map_err(|e| {
-            GeminiError::JsonParseError(format!(
-                "Failed to parse response: {}. Response: {}",
-                e, response_str
-            ))
-        })?;
+    // Non-streaming version (kept for compatibility)
+    pub fn generate_content(&self, text: &str) -> Result<Generation, GeminiError> {
+        self.generate(&user(&[Part::text(text)]), false)
+    }
 
-        if response.candidates.is_empty() {
-            return Err(GeminiError::HttpError("No candidates returned".to_string()));
+    /// Sends text, images and files together as one prompt.
+    pub fn generate_content_parts(&self, parts: &[Part]) -> Result<Generation, GeminiError> {
+        self.generate(&user(parts), false)
+    }
+
+    /// Continues the conversation in `contents`, returning the model's text
+    /// and function calls. To answer a call, append the model's turn and a
+    /// user turn with a [`Part::FunctionResponse`] and call this again.
+    pub fn generate_replies(&self, contents: &[Content]) -> Result<Vec<ModelReply>, GeminiError> {
+        let candidates = self.candidates(contents, false)?;
+        Ok(candidates.into_iter().next().map(|first| first.replies).unwrap_or_default())
+    }
+
+    /// Requests a JSON reply and deserializes it into `T`. A code fence around
+    /// the payload is tolerated.
+    pub fn generate_json<T: DeserializeOwned>(&self, text: &str) -> Result<T, GeminiError> {
+        json::parse(self.generate(&user(&[Part::text(text)]), true)?.text())
+    }
+
+    /// Generates the text of every prompt, sent one after another over a
+    /// single connection where the transport keeps one open, as
+    /// [`CurlTransport`] does. Each request after the first is spared the TCP
+    /// and TLS handshake. Cached prompts are answered without a request.
+    ///
+    /// The results are in the order of `prompts`. Failed requests are not
+    /// retried, since the rest of the batch has already gone out behind them.
+    pub fn batch(&self, prompts: Vec<Prompt>) -> Vec<Result<String, GeminiError>> {
+        let mut results = Vec::with_capacity(prompts.len());
+        // Index into results and cache key of each prompt to send
+        let mut pending = Vec::new();
+        let mut bodies = Vec::new();
+        for Prompt(parts) in &prompts {
+            let contents = user(parts);
+            let body = match self.request_body(&contents, false) {
+                Ok(body) => body,
+                Err(e) => {
+                    results.push(Some(Err(e)));
+                    continue;
+                }
+            };
+            let key = Cache::key(&self.model_id, &body);
+            if let Some(candidates) = self.cached(key) {
+                results.push(Some(generation(candidates).map(String::from)));
+                continue;
+            }
+            if let Err(e) = self.preflight(&contents) {
+                results.push(Some(Err(e)));
+                continue;
+            }
+            pending.push((results.len(), key));
+            bodies.push(body);
+            results.push(None);
         }
 
-        if let Some(text) = &response.candidates[0].content.parts[0].text {
-            Ok(text.clone())
-        } else {
-            Err(GeminiError::HttpError(
-                "No text found in response".to_string(),
-            ))
+        if !bodies.is_empty() {
+            let limits = self.limits();
+            let sent = self.url("generateContent").and_then(|url| {
+                limits.check()?;
+                self.transport.post_batch(&url, &bodies)
+            });
+            match sent {
+                Ok(exchanges) => {
+                    for ((index, key), exchange) in pending.into_iter().zip(exchanges) {
+                        let generated = read_body(exchange, &limits)
+                            .and_then(|body| parse_generation(&body))
+                            .and_then(|(candidates, usage)| {
+                                self.store(key, &candidates, usage);
+                                generation(candidates)
+                            });
+                        results[index] = Some(generated.map(String::from));
+                    }
+                }
+                Err(e) => {
+                    for (index, _) in pending {
+                        results[index] = Some(Err(e.clone()));
+                    }
+                }
+            }
         }
+        results.into_iter().map(|result| result.expect("every prompt is answered")).collect()
     }
 
-    // New streaming version that returns a coroutine the caller can drive
+    // Streaming version that returns a coroutine the caller can drive. Failed
+    // attempts are restarted per the retry policy without repeating text.
     pub fn generate_content_streaming<'a>(
         &self,
         text: &'a str,
     ) -> Box<dyn StreamingCoroutine + 'a> {
-        // Clone the necessary data so the coroutine can own it
-        let model_id = self.model_id.clone();
-        let api_key = self.api_key.clone();
-        let generation_config = self.generation_config.clone();
+        self.stream(&user(&[Part::text(text)]), false)
+    }
 
-        // Create and return a coroutine
+    /// Streams the reply to `text` into `callback`, one delta at a time, for
+    /// callers that do not want to drive a coroutine.
+    pub fn generate_content_streaming_with_callback(
+        &self,
+        text: &str,
+        mut callback: impl FnMut(String),
+    ) -> Result<(), GeminiError> {
+        let mut stream = Box::into_pin(self.generate_content_streaming(text));
+        loop {
+            match stream.as_mut().resume(()) {
+                CoroutineState::Yielded(Ok(text)) => callback(text),
+                // The stream ends with the same error
+                CoroutineState::Yielded(Err(_)) => {}
+                CoroutineState::Complete(result) => return result,
+            }
+        }
+    }
+
+    /// Streaming counterpart of [`generate_content_parts`].
+    ///
+    /// [`generate_content_parts`]: GeminiClient::generate_content_parts
+    pub fn generate_content_parts_streaming(&self, parts: &[Part]) -> Box<dyn StreamingCoroutine> {
+        self.stream(&user(parts), false)
+    }
+
+    /// Streaming counterpart of [`generate_replies`]. Function calls are
+    /// yielded as soon as they arrive, in between text.
+    ///
+    /// [`generate_replies`]: GeminiClient::generate_replies
+    pub fn generate_replies_streaming(&self, contents: &[Content]) -> Box<dyn ReplyStreamingCoroutine> {
+        self.stream_replies(contents, false)
+    }
+
+    /// Streams the text of all [`with_candidate_count`] candidates at once,
+    /// as `(index, delta)` pairs in the order the deltas arrive.
+    ///
+    /// [`with_candidate_count`]: GeminiClient::with_candidate_count
+    pub fn generate_candidates_streaming(&self, text: &str) -> Box<dyn CandidateStreamingCoroutine> {
+        let mut replies = Box::into_pin(self.stream_candidates(&user(&[Part::text(text)]), false));
         Box::new(
             #[coroutine]
             move || {
-                // Validate API key first
-                let api_key = match api_key {
-                    Some(key) => key,
-                    None => {
-                        yield Result::Err(GeminiError::HttpError(
-                            "API key is required for Gemini API".to_string(),
-                        ));
-                        return Result::Err(GeminiError::HttpError(
-                            "API key is required for Gemini API".to_string(),
-                        ));
+                loop {
+                    let state = replies.as_mut().resume(());
+                    match state {
+                        CoroutineState::Yielded(Ok((index, ModelReply::Text(text)))) => yield Ok((index, text)),
+                        CoroutineState::Yielded(Ok((_, ModelReply::FunctionCall { .. }))) => {}
+                        CoroutineState::Yielded(Err(e)) => yield Err(e),
+                        CoroutineState::Complete(result) => return result,
                     }
-                };
+                }
+            },
+        )
+    }
 
-                // Use the correct URL format for streaming
-                let url = format!(
-                    "https://generativelanguage.googleapis.com/v1beta/models/{}:streamGenerateContent?key={}",
-                    model_id, api_key
-                );
-
-                // Prepare the request body
-                let mut request_body = json!({
-                    "contents": [
-                        {
-                            "role": "user",
-                            "parts": [
-                                {
-                                    "text": text
-                                }
-                            ]
+    /// Streaming JSON mode. Text is yielded as it arrives and buffered, and the
+    /// parsed reply is returned once the stream completes.
+    pub fn generate_json_streaming<'a, T: DeserializeOwned + 'a>(
+        &self,
+        text: &'a str,
+    ) -> Box<dyn JsonStreamingCoroutine<T> + 'a> {
+        let mut stream = Box::into_pin(self.stream(&user(&[Part::text(text)]), true));
+        Box::new(
+            #[coroutine]
+            move || {
+                let mut buffer = String::new();
+                loop {
+                    let state = stream.as_mut().resume(());
+                    match state {
+                        CoroutineState::Yielded(Ok(text)) => {
+                            buffer.push_str(&text);
+                            yield Ok(text);
+                        }
+                        CoroutineState::Yielded(Err(e)) => yield Err(e),
+                        CoroutineState::Complete(result) => {
+                            result?;
+                            return json::parse(&buffer);
                         }
-                    ]
-                });
-
-                if !generation_config.is_empty() {
-                    let config = generation_config
-                        .iter()
-                        .map(|(k, v)| (k.clone(), v.clone()))
-                        .collect::<HashMap<_, _>>();
-                    request_body["generationConfig"] = json!(config);
+                    }
                 }
+            },
+        )
+    }
 
-                let json_body = match serde_json::to_string(&request_body) {
-                    Ok(body) => body,
-                    Err(e) => {
-                        yield Result::Err(GeminiError::JsonParseError(e.to_string()));
-                        return Result::Err(GeminiError::JsonParseError(e.to_string()));
-                    }
-                };
+    fn generate(&self, contents: &[Content], json_mode: bool) -> Result<Generation, GeminiError> {
+        generation(self.candidates(contents, json_mode)?)
+    }
 
-                // Set up curl command for streaming
-                let mut curl_cmd = Command::new("curl");
-
-                curl_cmd
-                    .arg("-X")
-                    .arg("POST")
-                    .arg("-H")
-                    .arg("Content-Type: application/json; charset=utf-8")
-                    .arg("-H")
-                    .arg("Accept: text/event-stream") // Tell the API we want server-sent events
-                    .arg("-N") // Important: disable buffering for streaming
-                    .arg("-d")
-                    .arg(json_body)
-                    .arg(url);
-
-                let mut child = match curl_cmd
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .spawn()
-                {
-                    Ok(child) => child,
-                    Err(e) => {
-                        yield Result::Err(GeminiError::CurlError(e.to_string()));
-                        return Result::Err(GeminiError::CurlError(e.to_string()));
+    fn candidates(&self, contents: &[Content], json_mode: bool) -> Result<Vec<Candidate>, GeminiError> {
+        let body = self.request_body(contents, json_mode)?;
+        let key = Cache::key(&self.model_id, &body);
+        if let Some(candidates) = self.cached(key) {
+            return Ok(candidates);
+        }
+
+        let limits = self.limits();
+        self.preflight(contents)?;
+        let url = self.url("generateContent")?;
+        let (candidates, usage) = self
+            .retrier
+            .run(|| generate_once(&*self.transport, &url, &body, &limits))?;
+        self.store(key, &candidates, usage);
+        Ok(candidates)
+    }
+
+    /// Records the usage of a generation and caches its candidates.
+    fn store(&self, key: u64, candidates: &[Candidate], usage: Option<UsageMetadata>) {
+        if let Some(usage) = usage {
+            self.usage.record(&self.model_id, usage);
+        }
+        if let Some(cache) = &self.cache {
+            // Best effort, a failed write only costs a later request
+            let _ = cache.put(key, &self.model_id, candidates);
+        }
+    }
+
+    fn cached(&self, key: u64) -> Option<Vec<Candidate>> {
+        if self.bypass_cache {
+            return None;
+        }
+        self.cache.as_ref()?.get(key)
+    }
+
+    /// Text only view of [`stream_replies`](GeminiClient::stream_replies).
+    fn stream(&self, contents: &[Content], json_mode: bool) -> Box<dyn StreamingCoroutine> {
+        let mut replies = Box::into_pin(self.stream_replies(contents, json_mode));
+        Box::new(
+            #[coroutine]
+            move || {
+                loop {
+                    let state = replies.as_mut().resume(());
+                    match state {
+                        CoroutineState::Yielded(Ok(ModelReply::Text(text))) => yield Ok(text),
+                        // Only surfaced through generate_replies_streaming
+                        CoroutineState::Yielded(Ok(ModelReply::FunctionCall { .. })) => {}
+                        CoroutineState::Yielded(Err(e)) => yield Err(e),
+                        CoroutineState::Complete(result) => return result,
                     }
-                };
+                }
+            },
+        )
+    }
 
-                let stdout = match child.stdout.take() {
-                    Some(stdout) => stdout,
-                    None => {
-                        yield Result::Err(GeminiError::StreamError(
-                            "Failed to capture stdout".to_string(),
-                        ));
-                        return Result::Err(GeminiError::StreamError(
-                            "Failed to capture stdout".to_string(),
-                        ));
+    /// The first candidate of [`stream_candidates`](GeminiClient::stream_candidates).
+    fn stream_replies(&self, contents: &[Content], json_mode: bool) -> Box<dyn ReplyStreamingCoroutine> {
+        let mut replies = Box::into_pin(self.stream_candidates(contents, json_mode));
+        Box::new(
+            #[coroutine]
+            move || {
+                loop {
+                    let state = replies.as_mut().resume(());
+                    match state {
+                        CoroutineState::Yielded(Ok((0, reply))) => yield Ok(reply),
+                        CoroutineState::Yielded(Ok(_)) => {}
+                        CoroutineState::Yielded(Err(e)) => yield Err(e),
+                        CoroutineState::Complete(result) => return result,
                     }
-                };
+                }
+            },
+        )
+    }
 
-                // Process the stream line by line
-                let reader = BufReader::new(stdout);
-                let mut in_text_field = false;
-                let mut current_text = String::new();
-                let mut textbuf = String::new();
+    fn stream_candidates(&self, contents: &[Content], json_mode: bool) -> Box<dyn IndexedReplyCoroutine> {
+        let body = match self.request_body(contents, json_mode) {
+            Ok(body) => body,
+            Err(e) => return fail(e),
+        };
+        let key = Cache::key(&self.model_id, &body);
+        if let Some(candidates) = self.cached(key) {
+            return replay(candidates);
+        }
 
-                for line_result in reader.lines() {
-                    let line = match line_result {
-                        Ok(line) => line,
-                        Err(e) => {
-                            yield Result::Err(GeminiError::StreamError(e.to_string()));
-                            return Result::Err(GeminiError::StreamError(e.to_string()));
-                        }
-                    };
+        // Build the request up front so the coroutine only owns its inputs.
+        // This includes the token pre-flight, if any.
+        let limits = self.limits();
+        let request = self
+            .preflight(contents)
+            .and_then(|()| self.url("streamGenerateContent"))
+            .map(|url| (url, body));
+        let cache = self.cache.clone().map(|cache| (cache, key, self.model_id.clone()));
+        let transport = self.transport.clone();
+        let retrier = self.retrier.clone();
+        let usage = self.usage.clone();
+        let model = self.model_id.clone();
 
-                    // Skip empty lines
-                    if line.trim().is_empty() {
-                        continue;
+        // Create and return a coroutine
+        Box::new(
+            #[coroutine]
+            move || {
+                let (url, body) = match request {
+                    Ok(request) => request,
+                    Err(e) => {
+                        yield Result::Err(e.clone());
+                        return Result::Err(e);
                     }
+                };
 
-                    // Skip lone commas between objects
-                    if line.trim() == "," {
-                        continue;
-                    }
+                // Restarted attempts repeat every candidate from the start
+                let mut dedup = BTreeMap::<usize, Dedup>::new();
+                let mut candidates = BTreeMap::<usize, Candidate>::new();
+                let mut attempt = 0;
+                loop {
+                    attempt += 1;
+                    dedup.values_mut().for_each(Dedup::restart);
+                    let mut stream = Box::pin(stream_once(
+                        transport.clone(),
+                        url.clone(),
+                        body.clone(),
+                        limits.clone(),
+                    ));
+
+                    let result = loop {
+                        // Checked between yields too, for a caller cancelling
+                        // from inside its loop
+                        if let Err(e) = limits.check() {
+                            break Err(e);
+                        }
+                        let state = stream.as_mut().resume(());
+                        let chunk = match state {
+                            CoroutineState::Yielded(chunk) => chunk,
+                            CoroutineState::Complete(result) => break result,
+                        };
+                        let index = chunk.index;
+                        let dedup = dedup.entry(index).or_insert_with(Dedup::new);
+                        let candidate = candidates.entry(index).or_insert_with(|| Candidate {
+                            index,
+                            replies: vec![],
+                            finish_reason: None,
+                            token_count: None,
+                        });
+                        candidate.finish_reason = chunk.finish_reason.or(candidate.finish_reason.take());
+                        candidate.token_count = chunk.token_count.or(candidate.token_count);
+
+                        let fresh = chunk
+                            .replies
+                            .into_iter()
+                            .filter_map(|reply| match reply {
+                                ModelReply::Text(text) => dedup.filter(text).map(ModelReply::Text),
+                                call => dedup.filter_call().then_some(call),
+                            })
+                            .collect::<Vec<_>>();
+                        candidate.replies.extend(fresh.iter().cloned());
+                        for reply in fresh {
+                            yield Result::Ok((index, reply));
+                        }
+                    };
 
-                    // If we're already inside a text field from previous lines
-                    if in_text_field {
-                        // Find the end quote that isn't escaped
-                        let mut i = 0;
-                        let chars: Vec<char> = line.chars().collect();
-                        let mut found_end = false;
-
-                        while i < chars.len() {
-                            if chars[i] == '"' {
-                                // Check if this quote is escaped (preceded by odd number of backslashes)
-                                let mut backslash_count = 0;
-                                let mut j = i;
-                                while j > 0 && chars[j - 1] == '\\' {
-                                    backslash_count += 1;
-                                    j -= 1;
-                                }
-
-                                if backslash_count % 2 == 0 {
-                                    // This is a real end quote (not escaped)
-                                    current_text.push_str(&line[..i]);
-
-                                    // Yield the text
-                                    yield Result::Ok(current_text.clone());
-
-                                    // Reset state
-                                    in_text_field = false;
-                                    current_text.clear();
-                                    found_end = true;
-
-                                    // Process the rest of the line starting after this quote
-                                    textbuf = line[i + 1..].to_string();
-                                    break;
-                                }
+                    match result {
+                        Ok(reported) => {
+                            if let Some(reported) = reported {
+                                usage.record(&model, reported);
                             }
-                            i += 1;
+                            if let Some((cache, key, model)) = &cache {
+                                let candidates = candidates.into_values().collect::<Vec<_>>();
+                                let _ = cache.put(*key, model, &candidates);
+                            }
+                            return Result::Ok(());
                         }
-
-                        if !found_end {
-                            // No end quote found, continue accumulating
-                            current_text.push_str(&line);
-                            current_text.push('\n');
-                            continue;
+                        Err(e) if retrier.should_retry(attempt, &e) => retrier.wait(attempt, e),
+                        Err(e) => {
+                            yield Result::Err(e.clone());
+                            return Result::Err(e);
                         }
                     }
+                }
+            },
+        )
+    }
+}
 
-                    // Look for new text fields
-                    textbuf.push_str(&line);
-                    let mut search_pos = 0;
+const API_BASE: &str = "https://generativelanguage.googleapis.com/v1beta";
 
-                    while search_pos < textbuf.len() {
-                        let start_marker = r#""text": ""#;
-                        if let Some(start_idx) = textbuf[search_pos..].find(start_marker) {
-                            let absolute_start = search_pos + start_idx;
-                            let content_start = absolute_start + start_marker.len();
+/// Models per page of [`GeminiClient::list_models`], the API's maximum.
+const MODEL_PAGE_SIZE: u32 = 1000;
 
-                            if content_start >= textbuf.len() {
-                                // The start marker is at the end of the buffer, wait for more data
-                                break;
-                            }
+/// Characters per yield when replaying cached text.
+const REPLAY_CHUNK: usize = 4096;
 
-                            // Find the closing quote that isn't escaped
-                            let mut i = 0;
-                            let chars: Vec<char> = textbuf[content_start..].chars().collect();
-                            let mut found_end = false;
-
-                            while i < chars.len() {
-                                if chars[i] == '"' {
-                                    // Check if this quote is escaped
-                                    let mut backslash_count = 0;
-                                    let mut j = i;
-                                    while j > 0 && chars[j - 1] == '\\' {
-                                        backslash_count += 1;
-                                        j -= 1;
-                                    }
-
-                                    if backslash_count % 2 == 0 {
-                                        // This is a real end quote
-                                        let mut i = content_start;
-                                        let mut escape = false;
-                                        while i < textbuf.len() {
-                                            let c = textbuf.as_bytes()[i];
-
-                                            if c == b'\\' && !escape {
-                                                escape = true;
-                                            } else if c == b'"' && !escape {
-                                                // Found unescaped quote
-                                                break;
-                                            } else {
-                                                escape = false;
-                                            }
-
-                                            // Move to next character safely
-                                            i += 1;
-                                            while i < textbuf.len() && !textbuf.is_char_boundary(i)
-                                            {
-                                                i += 1;
-                                            }
-                                        }
-
-                                        if i < textbuf.len() {
-                                            let text = &textbuf[content_start..i];
-
-                                            // Unescape the text
-                                            let unescaped = unescape_string(&text).unwrap();
-
-                                            // Yield the text
-                                            yield Result::Ok(unescaped);
-
-                                            // Update search position - make sure i is a valid boundary
-                                            search_pos = i + 1;
-                                            while search_pos < textbuf.len()
-                                                && !textbuf.is_char_boundary(search_pos)
-                                            {
-                                                search_pos += 1;
-                                            }
-
-                                            found_end = true;
-                                            break;
-                                        }
-                                    }
-                                }
-                                i += 1;
-                            }
+/// A stream that yields `e` and fails with it.
+fn fail(e: GeminiError) -> Box<dyn IndexedReplyCoroutine> {
+    Box::new(
+        #[coroutine]
+        move || {
+            yield Result::Err(e.clone());
+            Result::Err(e)
+        },
+    )
+}
 
-                            if !found_end {
-                                // Text continues beyond this line
-                                in_text_field = true;
-                                current_text = textbuf[content_start..].to_string();
-                                textbuf.clear();
-                                break;
+/// A stream of cached candidates, one after another. Adjacent text is
+/// merged and yielded in large chunks since there is no latency to hide.
+fn replay(candidates: Vec<Candidate>) -> Box<dyn IndexedReplyCoroutine> {
+    Box::new(
+        #[coroutine]
+        move || {
+            for Candidate { index, replies, .. } in candidates {
+                let mut text = String::new();
+                for reply in replies {
+                    match reply {
+                        ModelReply::Text(next) => text.push_str(&next),
+                        call => {
+                            for chunk in chunks(&std::mem::take(&mut text)) {
+                                yield Result::Ok((index, ModelReply::Text(chunk)));
                             }
-                        } else {
-                            // No text field start found
-                            break;
+                            yield Result::Ok((index, call));
                         }
                     }
-
-                    // Clear buffer if we're not in a text field and processed the line
-                    if !in_text_field {
-                        textbuf.clear();
-                    }
                 }
+                for chunk in chunks(&text) {
+                    yield Result::Ok((index, ModelReply::Text(chunk)));
+                }
+            }
+            Result::Ok(())
+        },
+    )
+}
 
-                // Wait for the child process to complete
-                let status = match child.wait() {
-                    Ok(status) => status,
-                    Err(e) => {
-                        yield Result::Err(GeminiError::CurlError(format!(
-                            "Error waiting for curl process: {}",
-                            e
-                        )));
-                        return Result::Err(GeminiError::CurlError(format!(
-                            "Error waiting for curl process: {}",
-                            e
-                        )));
-                    }
-                };
+fn generation(candidates: Vec<Candidate>) -> Result<Generation, GeminiError> {
+    Generation::new(candidates).ok_or_else(|| GeminiError::HttpError("No text found in response".to_string()))
+}
 
-                // Check if curl exited successfully
-                if !status.success() {
-                    let exit_code = status.code().unwrap_or(-1);
-                    yield Result::Err(GeminiError::HttpError(format!(
-                        "Curl command failed with exit code: {}",
-                        exit_code
-                    )));
-                    return Result::Err(GeminiError::HttpError(format!(
-                        "Curl command failed with exit code: {}",
-                        exit_code
-                    )));
-                }
+fn chunks(text: &str) -> Vec<String> {
+    let chars = text.chars().collect::<Vec<_>>();
+    chars
+        .chunks(REPLAY_CHUNK)
+        .map(|chunk| chunk.iter().collect())
+        .collect()
+}
 
-                Result::Ok(())
-            },
-        )
+/// A conversation of a single user turn.
+fn user(parts: &[Part]) -> [Content; 1] {
+    [Content::user(parts.to_vec())]
+}
+
+fn to_json(contents: &[Content]) -> Value {
+    Value::Array(contents.iter().map(Content::to_json).collect())
+}
+
+/// Percent-encodes everything but unreserved characters.
+fn query_escape(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn parse_body<T: DeserializeOwned>(body: &[u8], what: &str) -> Result<T, GeminiError> {
+    serde_json::from_slice(body).map_err(|e| {
+        GeminiError::JsonParseError(format!(
+            "Failed to parse {}: {}. Response: {}",
+            what,
+            e,
+            String::from_utf8_lossy(body)
+        ))
+    })
+}
+
+/// Single non-streaming POST without retries, returning the body of a
+/// successful response.
+fn post_once(
+    transport: &dyn Transport,
+    url: &str,
+    body: &str,
+    limits: &Limits,
+) -> Result<Vec<u8>, GeminiError> {
+    limits.check()?;
+    read_body(transport.post(url, body, false)?, limits)
+}
+
+/// Single GET without retries, like [`post_once`].
+fn get_once(transport: &dyn Transport, url: &str, limits: &Limits) -> Result<Vec<u8>, GeminiError> {
+    limits.check()?;
+    read_body(transport.get(url)?, limits)
+}
+
+fn read_body(mut exchange: Box<dyn Exchange>, limits: &Limits) -> Result<Vec<u8>, GeminiError> {
+    let mut raw = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let read = loop {
+        match limits.read(&mut *exchange, &mut chunk)? {
+            Ok(0) => break Ok(()),
+            Ok(read) => raw.extend_from_slice(&chunk[..read]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => break Err(e),
+        }
+    };
+    exchange.finish()?;
+    read.map_err(|e| GeminiError::IoError(e.to_string()))?;
+
+    let (head, body_start) = ResponseHead::parse(&raw).unwrap_or_else(|| {
+        Err(GeminiError::HttpError(format!(
+            "Incomplete response head: {}",
+            String::from_utf8_lossy(&raw)
+        )))
+    })?;
+    let body = raw.split_off(body_start);
+    if !head.is_success() {
+        return Err(head.error(&body));
     }
+    Ok(body)
 }
 
-// Helper function to extract text from response JSON
-fn extract_text_from_response(json: &Value) -> Option<&str> {
-    json.get("candidates")?
-        .as_array()?
-        .first()?
-        .get("content")?
-        .get("parts")?
-        .as_array()?
-        .first()?
-        .get("text")?
-        .as_str()
+/// Single non-streaming generation without retries.
+fn generate_once(
+    transport: &dyn Transport,
+    url: &str,
+    body: &str,
+    limits: &Limits,
+) -> Result<(Vec<Candidate>, Option<UsageMetadata>), GeminiError> {
+    parse_generation(&post_once(transport, url, body, limits)?)
 }
 
-fn unescape_string(input: &str) -> Result<String, String> {
-    let mut result = String::new();
-    let mut chars = input.chars().peekable();
-
-    while let Some(c) = chars.next() {
-        if c == '\\' {
-            match chars.next() {
-                Some('u') => {
-                    // Handle Unicode escape: \uXXXX
-                    let mut code_point = 0u32;
-                    let mut digit_count = 0;
-
-                    // Check for the opening brace in \u{XXXX} format
-                    let has_braces = chars.peek() == Some(&'{');
-                    if has_braces {
-                        chars.next(); // Consume the '{'
-                    }
+/// The candidates and usage of a successful `generateContent` response.
+fn parse_generation(body: &[u8]) -> Result<(Vec<Candidate>, Option<UsageMetadata>), GeminiError> {
+    let response_str = String::from_utf8_lossy(body).to_string();
 
-                    // Read 4 hex digits (or up to 6 if using braces)
-                    let max_digits = if has_braces { 6 } else { 4 };
+    let response: GeminiResponse = serde_json::from_str(&response_str).map_err(|e| {
+        GeminiError::JsonParseError(format!(
+            "Failed to parse response: {}. Response: {}",
+            e, response_str
+        ))
+    })?;
 
-                    while digit_count < max_digits {
-                        match chars.peek() {
-                            Some(&c) if c.is_digit(16) => {
-                                chars.next(); // Consume the digit
-                                code_point = code_point * 16 + c.to_digit(16).unwrap();
-                                digit_count += 1;
-                            }
-                            Some(&'}') if has_braces => {
-                                chars.next(); // Consume the closing '}'
-                                break;
-                            }
-                            _ if has_braces => {
-                                return Err(format!(
-                                    "Invalid Unicode escape sequence: missing closing brace"
-                                ));
-                            }
-                            _ => break,
-                        }
-                    }
+    if response.candidates.is_empty() {
+        return Err(GeminiError::HttpError("No candidates returned".to_string()));
+    }
 
-                    if digit_count == 0 {
-                        return Err(format!("Invalid Unicode escape sequence: no digits"));
-                    }
+    let usage = response.usage_metadata;
+    let candidates = response.candidates();
+    if candidates.iter().all(|candidate| candidate.replies.is_empty()) {
+        return Err(GeminiError::HttpError(
+            "No text or function call found in response".to_string(),
+        ));
+    }
+    Ok((candidates, usage))
+}
 
-                    if has_braces && chars.peek() != Some(&'}') && digit_count > 0 {
-                        return Err(format!(
-                            "Invalid Unicode escape sequence: missing closing brace"
-                        ));
+/// Single streaming request without retries, yielding the text deltas and
+/// function calls of each candidate as they arrive.
+fn stream_once(
+    transport: Arc<dyn Transport>,
+    url: String,
+    body: String,
+    limits: Limits,
+) -> impl Coroutine<(), Yield = Candidate, Return = Result<Option<UsageMetadata>, GeminiError>> {
+    #[coroutine]
+    move || {
+        limits.check()?;
+        let mut exchange = transport.post(&url, &body, true)?;
+
+        // Read the response head first, then feed the body to the parser and
+        // yield the text of every complete response
+        let mut head: Option<ResponseHead> = None;
+        let mut pending = Vec::new();
+        let mut parser = StreamParser::new();
+        let mut chunk = [0u8; 4096];
+        let mut usage = None;
+
+        loop {
+            let read = match limits.read(&mut *exchange, &mut chunk)? {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    exchange.finish()?;
+                    return Err(GeminiError::StreamError(e.to_string()));
+                }
+            };
+
+            let body = match &head {
+                Some(head) if head.is_success() => &chunk[..read],
+                Some(_) => {
+                    // Error bodies are collected whole and parsed at the end
+                    pending.extend_from_slice(&chunk[..read]);
+                    continue;
+                }
+                None => {
+                    pending.extend_from_slice(&chunk[..read]);
+                    let Some(parsed) = ResponseHead::parse(&pending) else {
+                        continue;
+                    };
+                    let (parsed, body_start) = parsed?;
+                    pending.drain(..body_start);
+                    let success = parsed.is_success();
+                    head = Some(parsed);
+                    if !success {
+                        continue;
                     }
+                    &std::mem::take(&mut pending)[..]
+                }
+            };
 
-                    match char::from_u32(code_point) {
-                        Some(unicode_char) => result.push(unicode_char),
-                        None => {
-                            return Err(format!("Invalid Unicode code point: U+{:X}", code_point));
-                        }
-                    }
+            for response in parser.push(body) {
+                let response = response?;
+                // Totals so far; the final chunk's cover the whole response
+                if response.usage_metadata.is_some() {
+                    usage = response.usage_metadata;
+                }
+                for candidate in response.candidates() {
+                    yield candidate;
+                }
+            }
+        }
+
+        // Transport failures take precedence over a truncated body
+        exchange.finish()?;
+
+        match head {
+            Some(head) if head.is_success() => parser.finish().map(|()| usage),
+            Some(head) => Err(head.error(&pending)),
+            None => Err(GeminiError::HttpError(format!(
+                "Incomplete response head: {}",
+                String::from_utf8_lossy(&pending)
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::io::{Cursor, Read};
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Raw response, how the exchange ends, and whether it stalls forever
+    /// after the raw bytes instead of ending.
+    type Reply = Result<(Vec<u8>, Result<(), GeminiError>, bool), GeminiError>;
+
+    /// Replays canned exchanges in order and counts requests.
+    #[derive(Default)]
+    struct MockTransport {
+        replies: Mutex<VecDeque<Reply>>,
+        posts: AtomicUsize,
+        urls: Mutex<Vec<String>>,
+        bodies: Mutex<Vec<String>>,
+        cancelled: Arc<AtomicUsize>,
+    }
+
+    impl MockTransport {
+        fn reply(self, head: &str, body: &str, finish: Result<(), GeminiError>) -> Self {
+            let raw = format!("{head}\r\n\r\n{body}").into_bytes();
+            self.replies.lock().unwrap().push_back(Ok((raw, finish, false)));
+            self
+        }
+
+        fn stall(self, head: &str, body: &str) -> Self {
+            let raw = format!("{head}\r\n\r\n{body}").into_bytes();
+            self.replies.lock().unwrap().push_back(Ok((raw, Ok(()), true)));
+            self
+        }
+
+        fn refuse(self, error: GeminiError) -> Self {
+            self.replies.lock().unwrap().push_back(Err(error));
+            self
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn get(&self, url: &str) -> Result<Box<dyn Exchange>, GeminiError> {
+            self.post(url, "", false)
+        }
+
+        fn post(&self, url: &str, body: &str, _: bool) -> Result<Box<dyn Exchange>, GeminiError> {
+            self.posts.fetch_add(1, Ordering::SeqCst);
+            self.urls.lock().unwrap().push(url.to_owned());
+            self.bodies.lock().unwrap().push(body.to_owned());
+            let (raw, finish, stalls) = self.replies.lock().unwrap().pop_front().unwrap()?;
+            Ok(Box::new(MockExchange {
+                raw: Cursor::new(raw),
+                finish,
+                stalls,
+                cancelled: self.cancelled.clone(),
+            }))
+        }
+    }
+
+    struct MockExchange {
+        raw: Cursor<Vec<u8>>,
+        finish: Result<(), GeminiError>,
+        stalls: bool,
+        cancelled: Arc<AtomicUsize>,
+    }
+
+    impl Read for MockExchange {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            // Deliver in small pieces to exercise chunk boundaries
+            let len = buf.len().min(16);
+            self.raw.read(&mut buf[..len])
+        }
+    }
+
+    impl Exchange for MockExchange {
+        fn finish(&mut self) -> Result<(), GeminiError> {
+            self.finish.clone()
+        }
+
+        fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> std::io::Result<Option<usize>> {
+            if self.stalls && self.raw.position() == self.raw.get_ref().len() as u64 {
+                std::thread::sleep(timeout);
+                return Ok(None);
+            }
+            self.read(buf).map(Some)
+        }
+
+        fn cancel(&mut self) {
+            self.cancelled.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[derive(Default)]
+    struct FakeClock {
+        sleeps: Mutex<Vec<Duration>>,
+    }
+
+    impl Clock for FakeClock {
+        fn sleep(&self, duration: Duration) {
+            self.sleeps.lock().unwrap().push(duration);
+        }
+    }
+
+    fn chunk(text: &str) -> String {
+        json!({ "candidates": [{ "content": { "parts": [{ "text": text }] } }] }).to_string()
+    }
+
+    fn reset() -> GeminiError {
+        GeminiError::Transport {
+            exit_code: 56,
+            stderr: "Recv failure: Connection reset by peer".into(),
+        }
+    }
+
+    const UNAVAILABLE: &str =
+        r#"{"error": {"code": 503, "message": "The model is overloaded.", "status": "UNAVAILABLE"}}"#;
+
+    fn client(transport: Arc<MockTransport>, clock: Arc<FakeClock>, events: Arc<Mutex<Vec<RetryEvent>>>) -> GeminiClient {
+        GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport)
+            .with_clock(clock)
+            .with_retry(RetryPolicy {
+                max_attempts: 5,
+                base_delay: Duration::from_millis(100),
+                max_delay: Duration::from_secs(10),
+                ..RetryPolicy::default()
+            })
+            .with_retry_callback(move |event| events.lock().unwrap().push(event.clone()))
+    }
+
+    fn assert_jittered(delay: Duration, base_ms: f64) {
+        let ms = delay.as_secs_f64() * 1000.0;
+        assert!(ms >= base_ms * 0.8 - 1e-6 && ms <= base_ms * 1.2 + 1e-6, "{ms}ms");
+    }
+
+    #[test]
+    fn test_streaming_retry_without_duplicates() {
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &format!("[{},\n{}", chunk("Hello, "), &chunk("wor")[..20]), Err(reset()))
+                .reply("HTTP/2 503", UNAVAILABLE, Ok(()))
+                .reply("HTTP/2 200", &format!("[{},\n{}]", chunk("Hello, "), chunk("world")), Ok(())),
+        );
+        let clock = Arc::new(FakeClock::default());
+        let events = Arc::new(Mutex::new(vec![]));
+        let client = client(transport.clone(), clock.clone(), events.clone());
+
+        let mut stream = client.generate_content_streaming("hi");
+        let mut stream = unsafe { Pin::new_unchecked(&mut *stream) };
+        let mut output = vec![];
+        let result = loop {
+            match stream.as_mut().resume(()) {
+                CoroutineState::Yielded(text) => output.push(text.unwrap()),
+                CoroutineState::Complete(result) => break result,
+            }
+        };
+
+        result.unwrap();
+        assert_eq!(output, vec!["Hello, ", "world"]);
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 3);
+
+        let sleeps = clock.sleeps.lock().unwrap();
+        assert_eq!(sleeps.len(), 2);
+        assert_jittered(sleeps[0], 100.0);
+        assert_jittered(sleeps[1], 200.0);
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.iter().map(|e| e.attempt).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(matches!(events[0].error, GeminiError::Transport { exit_code: 56, .. }));
+        assert!(matches!(events[1].error, GeminiError::Api { code: 503, .. }));
+        assert_eq!(events[1].delay, sleeps[1]);
+    }
+
+    #[test]
+    fn test_generate_content_retry() {
+        let transport = Arc::new(
+            MockTransport::default()
+                .refuse(GeminiError::Transport {
+                    exit_code: 7,
+                    stderr: "Failed to connect".into(),
+                })
+                .reply("HTTP/2 429\r\nretry-after: 3", UNAVAILABLE, Ok(()))
+                .reply("HTTP/2 200", &chunk("done"), Ok(())),
+        );
+        let clock = Arc::new(FakeClock::default());
+        let events = Arc::new(Mutex::new(vec![]));
+        let client = client(transport.clone(), clock.clone(), events);
+
+        assert_eq!(client.generate_content("hi").unwrap(), "done");
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 3);
+        let sleeps = clock.sleeps.lock().unwrap();
+        assert_jittered(sleeps[0], 100.0);
+        assert_eq!(sleeps[1], Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_non_retryable_is_not_repeated() {
+        let invalid = r#"{"error": {"code": 400, "message": "bad", "status": "INVALID_ARGUMENT"}}"#;
+        let transport = Arc::new(MockTransport::default().reply("HTTP/2 400", invalid, Ok(())));
+        let clock = Arc::new(FakeClock::default());
+        let client = client(transport.clone(), clock.clone(), Arc::new(Mutex::new(vec![])));
+
+        assert!(matches!(client.generate_content("hi"), Err(GeminiError::Api { code: 400, .. })));
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 1);
+        assert!(clock.sleeps.lock().unwrap().is_empty());
+    }
+
+    fn method(url: &str) -> &str {
+        url.rsplit_once(':').unwrap().1.split('?').next().unwrap()
+    }
+
+    #[test]
+    fn test_count_tokens() {
+        let transport = Arc::new(MockTransport::default().reply(
+            "HTTP/2 200",
+            r#"{"totalTokens": 31, "cachedContentTokenCount": 4}"#,
+            Ok(()),
+        ));
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone());
+
+        let count = client.count_tokens("How many tokens is this?").unwrap();
+        assert_eq!(count.total_tokens, 31);
+        assert_eq!(count.cached_content_token_count, Some(4));
+        assert_eq!(method(&transport.urls.lock().unwrap()[0]), "countTokens");
+    }
+
+    #[test]
+    fn test_preflight_rejects_large_input() {
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", r#"{"totalTokens": 5000}"#, Ok(()))
+                .reply("HTTP/2 200", r#"{"totalTokens": 5000}"#, Ok(())),
+        );
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone())
+            .with_max_input_tokens(1024);
+
+        assert!(matches!(
+            client.generate_content("a whole source tree"),
+            Err(GeminiError::InputTooLarge {
+                tokens: 5000,
+                limit: 1024
+            })
+        ));
+
+        let mut stream = client.generate_content_streaming("a whole source tree");
+        let mut stream = unsafe { Pin::new_unchecked(&mut *stream) };
+        assert!(matches!(
+            stream.as_mut().resume(()),
+            CoroutineState::Yielded(Err(GeminiError::InputTooLarge { .. }))
+        ));
+
+        // Nothing but the two pre-flights was sent
+        let urls = transport.urls.lock().unwrap();
+        assert_eq!(urls.iter().map(|url| method(url)).collect::<Vec<_>>(), ["countTokens"; 2]);
+    }
+
+    #[test]
+    fn test_preflight_within_budget() {
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", r#"{"totalTokens": 12}"#, Ok(()))
+                .reply("HTTP/2 200", &chunk("ok"), Ok(())),
+        );
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone())
+            .with_max_input_tokens(1024);
+
+        assert_eq!(client.generate_content("short").unwrap(), "ok");
+        let urls = transport.urls.lock().unwrap();
+        assert_eq!(urls.iter().map(|url| method(url)).collect::<Vec<_>>(), ["countTokens", "generateContent"]);
+    }
+
+    fn model(name: &str, methods: &[&str]) -> Value {
+        json!({
+            "name": format!("models/{name}"),
+            "displayName": name,
+            "inputTokenLimit": 1048576,
+            "outputTokenLimit": 8192,
+            "supportedGenerationMethods": methods,
+        })
+    }
+
+    #[test]
+    fn test_list_models() {
+        let first = json!({
+            "models": [model("gemini-2.0-flash", &["generateContent", "countTokens"])],
+            "nextPageToken": "page/2=",
+        });
+        let second = json!({ "models": [model("text-embedding-004", &["embedContent"])] });
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &first.to_string(), Ok(()))
+                .reply("HTTP/2 200", &second.to_string(), Ok(())),
+        );
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone());
+
+        let models = client.list_models().unwrap();
+        assert_eq!(models.iter().map(ModelInfo::id).collect::<Vec<_>>(), ["gemini-2.0-flash", "text-embedding-004"]);
+        assert_eq!(models[0].display_name.as_deref(), Some("gemini-2.0-flash"));
+        assert_eq!((models[0].input_token_limit, models[0].output_token_limit), (Some(1048576), Some(8192)));
+        assert!(models[0].supports("generateContent"));
+        assert!(!models[1].supports("generateContent"));
+
+        let urls = transport.urls.lock().unwrap();
+        assert!(!urls[0].contains("pageToken"));
+        assert!(urls[1].ends_with("&pageToken=page%2F2%3D"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let transport = Arc::new(MockTransport::default().reply(
+            "HTTP/2 200",
+            &model("test-model", &["generateContent"]).to_string(),
+            Ok(()),
+        ));
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone());
+
+        assert_eq!(client.validate().unwrap().input_token_limit, Some(1048576));
+        assert!(transport.urls.lock().unwrap()[0].contains("/models/test-model?key="));
+    }
+
+    #[test]
+    fn test_validate_unknown_model() {
+        let not_found = r#"{"error": {"code": 404, "message": "models/gemini-2.0-flash-thinkng is not found", "status": "NOT_FOUND"}}"#;
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 404", not_found, Ok(()))
+                .reply("HTTP/2 200", &model("embedder", &["embedContent"]).to_string(), Ok(())),
+        );
+        let client = GeminiClient::new("gemini-2.0-flash-thinkng")
+            .with_api_key("key")
+            .with_transport(transport.clone());
+
+        assert!(matches!(
+            client.validate(),
+            Err(GeminiError::UnknownModel {
+                supported_methods: None,
+                ..
+            })
+        ));
+        match client.validate() {
+            Err(GeminiError::UnknownModel {
+                supported_methods: Some(methods),
+                ..
+            }) => assert_eq!(methods, ["embedContent"]),
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_usage_captured() {
+        let usage = r#""usageMetadata": {"promptTokenCount": 7, "candidatesTokenCount": 3, "totalTokenCount": 10}"#;
+        let final_chunk = format!(r#"{{"candidates": [{{"content": {{"parts": [{{"text": "!"}}]}}}}], {usage}}}"#);
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &final_chunk, Ok(()))
+                .reply("HTTP/2 200", &format!("[{},\n{}]", chunk("hi"), final_chunk.replace("10}", "12}")), Ok(())),
+        );
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport);
+        assert_eq!(client.last_usage(), None);
+
+        client.generate_content("hello").unwrap();
+        let expected = UsageMetadata {
+            prompt_token_count: Some(7),
+            candidates_token_count: Some(3),
+            total_token_count: Some(10),
+        };
+        assert_eq!(client.last_usage(), Some(expected));
+
+        let mut stream = client.generate_content_streaming("hello");
+        let mut stream = unsafe { Pin::new_unchecked(&mut *stream) };
+        while let CoroutineState::Yielded(text) = stream.as_mut().resume(()) {
+            text.unwrap();
+        }
+        assert_eq!(client.last_usage().unwrap().total_token_count, Some(12));
+    }
+
+    fn with_usage(text: &str, prompt: i32, candidates: i32) -> String {
+        json!({
+            "candidates": [{ "content": { "parts": [{ "text": text }] } }],
+            "usageMetadata": {
+                "promptTokenCount": prompt,
+                "candidatesTokenCount": candidates,
+                "totalTokenCount": prompt + candidates,
+            },
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_usage_tracker() {
+        let streamed = format!("[{},\n{}]", with_usage("a", 100, 1), with_usage("b", 100, 2));
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &with_usage("one", 1000, 200), Ok(()))
+                .reply("HTTP/2 200", &with_usage("two", 3000, 400), Ok(()))
+                .reply("HTTP/2 200", &streamed, Ok(()))
+                .reply("HTTP/2 200", &with_usage("cheap", 500_000, 0), Ok(())),
+        );
+        let tracker = UsageTracker::new();
+        let pro = GeminiClient::new("pro")
+            .with_api_key("key")
+            .with_transport(transport.clone())
+            .with_usage_tracker(tracker.clone());
+        let flash = GeminiClient::new("flash")
+            .with_api_key("key")
+            .with_transport(transport.clone())
+            .with_usage_tracker(tracker.clone());
+
+        pro.generate_content("1").unwrap();
+        pro.clone().with_temperature(0.5).generate_content("2").unwrap();
+        // Only the final chunk's totals of a stream count
+        assert_eq!(collect(pro.generate_content_streaming("3")), ["a", "b"]);
+        flash.generate_content("4").unwrap();
+
+        let per_model = pro.usage().per_model();
+        assert_eq!(
+            per_model["pro"],
+            ModelUsage {
+                calls: 3,
+                prompt_tokens: 4100,
+                candidate_tokens: 602,
+                total_tokens: 4702,
+            }
+        );
+        assert_eq!(per_model["flash"].calls, 1);
+        assert_eq!(tracker.total().prompt_tokens, 504_100);
+
+        let prices = PriceTable::new().with_price("pro", Price { input: 1.25, output: 10.0 });
+        let estimate = tracker.estimate_cost(&prices);
+        assert!((estimate.total - (4100.0 * 1.25 + 602.0 * 10.0) / 1e6).abs() < 1e-12);
+        assert_eq!(estimate.unpriced, ["flash"]);
+
+        let prices = prices.with_fallback(Price { input: 0.1, output: 0.4 });
+        let estimate = tracker.estimate_cost(&prices);
+        assert!((estimate.total - (4100.0 * 1.25 + 602.0 * 10.0 + 500_000.0 * 0.1) / 1e6).abs() < 1e-12);
+        assert!(estimate.unpriced.is_empty());
+
+        tracker.reset();
+        assert_eq!(flash.usage().total(), ModelUsage::default());
+        assert_eq!(flash.last_usage(), None);
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(tag = "type")]
+    enum Diagnostic {
+        Missing { path: String },
+    }
+
+    #[test]
+    fn test_generate_json() {
+        let fixture = r#"[{"type": "Missing", "path": "include/io.h"}]"#;
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &chunk(fixture), Ok(()))
+                .reply("HTTP/2 200", &chunk(&format!("```json\n{fixture}\n```")), Ok(())),
+        );
+        let schema = json!({ "type": "ARRAY", "items": { "type": "OBJECT" } });
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone())
+            .with_response_schema(schema.clone());
+
+        let expected = vec![Diagnostic::Missing {
+            path: "include/io.h".to_owned(),
+        }];
+        assert_eq!(client.generate_json::<Vec<Diagnostic>>("bare").unwrap(), expected);
+        assert_eq!(client.generate_json::<Vec<Diagnostic>>("fenced").unwrap(), expected);
+
+        let body: Value = serde_json::from_str(&transport.bodies.lock().unwrap()[0]).unwrap();
+        assert_eq!(body["generationConfig"]["responseMimeType"], "application/json");
+        assert_eq!(body["generationConfig"]["responseSchema"], schema);
+    }
+
+    #[test]
+    fn test_generate_json_streaming() {
+        let transport = Arc::new(MockTransport::default().reply(
+            "HTTP/2 200",
+            &format!(
+                "[{},\n{},\n{}]",
+                chunk("```json\n[{\"type\": \"Missing\", "),
+                chunk("\"path\": \"a.h\"}]"),
+                chunk("\n```")
+            ),
+            Ok(()),
+        ));
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone());
+
+        let mut stream = client.generate_json_streaming::<Vec<Diagnostic>>("stream");
+        let mut stream = unsafe { Pin::new_unchecked(&mut *stream) };
+        let mut yielded = 0;
+        let parsed = loop {
+            match stream.as_mut().resume(()) {
+                CoroutineState::Yielded(text) => {
+                    text.unwrap();
+                    yielded += 1;
                 }
-                Some('n') => result.push('\n'),
-                Some('r') => result.push('\r'),
-                Some('t') => result.push('\t'),
-                Some('\\') => result.push('\\'),
-                Some('0') => result.push('\0'),
-                Some('\'') => result.push('\''),
-                Some('\"') => result.push('\"'),
-                Some(c) => result.push(c), // Pass through unrecognized escapes
-                None => return Err("Incomplete escape sequence".to_string()),
+                CoroutineState::Complete(result) => break result.unwrap(),
             }
-        } else {
-            result.push(c);
+        };
+        assert_eq!(yielded, 3);
+        assert_eq!(parsed, vec![Diagnostic::Missing { path: "a.h".to_owned() }]);
+        assert!(transport.bodies.lock().unwrap()[0].contains(r#""responseMimeType":"application/json""#));
+    }
+
+    fn fixture(name: &str) -> std::path::PathBuf {
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    #[test]
+    fn test_multimodal_request_body() {
+        const PIXEL: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAADUlEQVR4nGP4z8DwHwAFAAH/iZk9HQAAAABJRU5ErkJggg==";
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &chunk("A red pixel."), Ok(()))
+                .reply("HTTP/2 200", &format!("[{}]", chunk("Red.")), Ok(())),
+        );
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone());
+
+        let parts = [
+            Part::text("What is wrong in this screenshot?"),
+            Part::from_image_path(fixture("pixel.png")).unwrap(),
+            Part::file("https://generativelanguage.googleapis.com/v1beta/files/abc", "application/pdf"),
+        ];
+        assert_eq!(client.generate_content_parts(&parts).unwrap(), "A red pixel.");
+
+        let mut stream = client.generate_content_parts_streaming(&parts);
+        let mut stream = unsafe { Pin::new_unchecked(&mut *stream) };
+        assert!(matches!(stream.as_mut().resume(()), CoroutineState::Yielded(Ok(text)) if text == "Red."));
+
+        let bodies = transport.bodies.lock().unwrap();
+        assert_eq!(bodies[0], bodies[1]);
+        let body: Value = serde_json::from_str(&bodies[0]).unwrap();
+        let sent = &body["contents"][0]["parts"];
+        assert_eq!(sent[0]["text"], "What is wrong in this screenshot?");
+        assert_eq!(sent[1]["inlineData"]["mimeType"], "image/png");
+        assert_eq!(sent[1]["inlineData"]["data"], PIXEL);
+        assert_eq!(sent[2]["fileData"]["fileUri"], "https://generativelanguage.googleapis.com/v1beta/files/abc");
+        assert_eq!(sent[2]["fileData"]["mimeType"], "application/pdf");
+    }
+
+    #[test]
+    fn test_inline_too_large_is_not_sent() {
+        let transport = Arc::new(MockTransport::default());
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone());
+
+        let parts = [Part::inline("image/png", &vec![0; INLINE_LIMIT + 1])];
+        assert!(matches!(
+            client.generate_content_parts(&parts),
+            Err(GeminiError::InlineDataTooLarge { .. })
+        ));
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 0);
+    }
+
+    fn call_chunk(name: &str, args: Value) -> String {
+        json!({ "candidates": [{ "content": { "role": "model", "parts": [{ "functionCall": { "name": name, "args": args } }] } }] })
+            .to_string()
+    }
+
+    fn lookup_symbol() -> FunctionDeclaration {
+        FunctionDeclaration::new(
+            "lookup_symbol",
+            "Finds where a symbol is declared.",
+            json!({ "type": "OBJECT", "properties": { "name": { "type": "STRING" } }, "required": ["name"] }),
+        )
+    }
+
+    #[test]
+    fn test_function_call_round_trip() {
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &call_chunk("lookup_symbol", json!({ "name": "io_open" })), Ok(()))
+                .reply("HTTP/2 200", &chunk("io_open is declared in include/io.h."), Ok(())),
+        );
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone())
+            .with_tools(vec![lookup_symbol()]);
+
+        let mut conversation = vec![Content::user(vec![Part::text("Where is io_open declared?")])];
+        let replies = client.generate_replies(&conversation).unwrap();
+        let [ModelReply::FunctionCall { name, args }] = &replies[..] else {
+            panic!("expected a function call, got {replies:?}");
+        };
+        assert_eq!((name.as_str(), &args["name"]), ("lookup_symbol", &json!("io_open")));
+
+        conversation.push(Content::model(replies.iter().map(ModelReply::to_part).collect()));
+        conversation.push(Content::user(vec![Part::function_response(
+            name,
+            json!({ "path": "include/io.h", "line": 12 }),
+        )]));
+        let replies = client.generate_replies(&conversation).unwrap();
+        assert_eq!(replies, [ModelReply::Text("io_open is declared in include/io.h.".to_owned())]);
+
+        let bodies = transport.bodies.lock().unwrap();
+        let first: Value = serde_json::from_str(&bodies[0]).unwrap();
+        let declaration = &first["tools"][0]["functionDeclarations"][0];
+        assert_eq!(declaration["name"], "lookup_symbol");
+        assert_eq!(declaration["parameters"]["required"], json!(["name"]));
+
+        let second: Value = serde_json::from_str(&bodies[1]).unwrap();
+        let turns = second["contents"].as_array().unwrap();
+        assert_eq!(turns.iter().map(|turn| turn["role"].as_str().unwrap()).collect::<Vec<_>>(), ["user", "model", "user"]);
+        assert_eq!(turns[1]["parts"][0]["functionCall"]["args"]["name"], "io_open");
+        assert_eq!(turns[2]["parts"][0]["functionResponse"]["name"], "lookup_symbol");
+        assert_eq!(turns[2]["parts"][0]["functionResponse"]["response"]["line"], 12);
+    }
+
+    #[test]
+    fn test_streaming_function_call() {
+        let call = call_chunk("lookup_symbol", json!({ "name": "io_open" }));
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &format!("[{},\n{}", chunk("Let me check. "), call), Err(reset()))
+                .reply("HTTP/2 200", &format!("[{},\n{}]", chunk("Let me check. "), call), Ok(())),
+        );
+        let client = client(transport, Arc::new(FakeClock::default()), Arc::new(Mutex::new(vec![])))
+            .with_tools(vec![lookup_symbol()]);
+
+        let conversation = [Content::user(vec![Part::text("Where is io_open declared?")])];
+        let mut stream = client.generate_replies_streaming(&conversation);
+        let mut stream = unsafe { Pin::new_unchecked(&mut *stream) };
+        let mut replies = vec![];
+        while let CoroutineState::Yielded(reply) = stream.as_mut().resume(()) {
+            replies.push(reply.unwrap());
         }
+        // The retried attempt repeats neither the text nor the call
+        assert_eq!(
+            replies,
+            [
+                ModelReply::Text("Let me check. ".to_owned()),
+                ModelReply::FunctionCall {
+                    name: "lookup_symbol".to_owned(),
+                    args: json!({ "name": "io_open" }),
+                },
+            ]
+        );
     }
 
-    Ok(result)
+    #[test]
+    fn test_generate_json_invalid() {
+        let transport = Arc::new(MockTransport::default().reply("HTTP/2 200", &chunk("I cannot do that."), Ok(())));
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport);
+        let err = client.generate_json::<Vec<Diagnostic>>("bare").unwrap_err();
+        assert!(matches!(&err, GeminiError::JsonParseError(msg) if msg.contains("Payload: I cannot do that.")));
+    }
+
+    /// A fresh cache directory for one test.
+    #[test]
+    fn test_concurrent_streams() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<GeminiClient>();
+
+        let body = format!("[{},\n{}]", chunk("Hello, "), chunk("world"));
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &body, Ok(()))
+                .reply("HTTP/2 200", &body, Ok(())),
+        );
+        let client = Arc::new(
+            GeminiClient::new("test-model")
+                .with_api_key("key")
+                .with_transport(transport.clone()),
+        );
+
+        let threads = (0..2)
+            .map(|_| {
+                let client = client.clone();
+                std::thread::spawn(move || {
+                    let mut text = String::new();
+                    client
+                        .generate_content_streaming_with_callback("hi", |delta| text.push_str(&delta))
+                        .unwrap();
+                    text
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), "Hello, world");
+        }
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 2);
+
+        // Clones share the config until one of them changes it
+        let warm = (*client).clone().with_temperature(0.9);
+        assert!(Arc::ptr_eq(&client.generation_config, &(*client).clone().generation_config));
+        assert!(!Arc::ptr_eq(&client.generation_config, &warm.generation_config));
+        assert!(client.generation_config.is_empty());
+    }
+
+    fn cache_config(name: &str, ttl: Duration) -> CacheConfig {
+        let dir = std::env::temp_dir().join(format!("gemini-cache-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        CacheConfig {
+            dir,
+            ttl,
+            max_entries: 16,
+        }
+    }
+
+    fn cached_client(transport: Arc<MockTransport>, config: CacheConfig) -> GeminiClient {
+        GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport)
+            .with_cache(config)
+    }
+
+    fn collect(stream: Box<dyn StreamingCoroutine>) -> Vec<String> {
+        let mut stream = Box::into_pin(stream);
+        let mut output = vec![];
+        while let CoroutineState::Yielded(text) = stream.as_mut().resume(()) {
+            output.push(text.unwrap());
+        }
+        output
+    }
+
+    #[test]
+    fn test_cache_hit_and_miss() {
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &chunk("cached"), Ok(()))
+                .reply("HTTP/2 200", &chunk("other"), Ok(()))
+                .reply("HTTP/2 200", &chunk("fresh"), Ok(())),
+        );
+        let client = cached_client(transport.clone(), cache_config("hit", Duration::from_secs(3600)));
+
+        assert_eq!(client.generate_content("hi").unwrap(), "cached");
+        assert_eq!(client.generate_content("hi").unwrap(), "cached");
+        // Streaming sends the same body, so it is served from the same entry
+        assert_eq!(collect(client.generate_content_streaming("hi")), ["cached"]);
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 1);
+
+        assert_eq!(client.generate_content("bye").unwrap(), "other");
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 2);
+
+        // Bypassing asks the API and refreshes the entry
+        assert_eq!(client.bypass_cache().generate_content("hi").unwrap(), "fresh");
+        assert_eq!(client.generate_content("hi").unwrap(), "fresh");
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_cache_ttl_expiry() {
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &chunk("first"), Ok(()))
+                .reply("HTTP/2 200", &chunk("second"), Ok(())),
+        );
+        // Everything is expired as soon as it is written
+        let client = cached_client(transport.clone(), cache_config("ttl", Duration::ZERO));
+
+        assert_eq!(client.generate_content("hi").unwrap(), "first");
+        assert_eq!(client.generate_content("hi").unwrap(), "second");
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 2);
+        assert_eq!(client.cache().unwrap().purge_expired().unwrap(), 1);
+        assert_eq!(client.cache().unwrap().purge_expired().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_cache_key_includes_config() {
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &chunk("cold"), Ok(()))
+                .reply("HTTP/2 200", &chunk("warm"), Ok(())),
+        );
+        let config = cache_config("temperature", Duration::from_secs(3600));
+        let cold = cached_client(transport.clone(), config.clone()).with_temperature(0.2);
+        let warm = cached_client(transport.clone(), config.clone()).with_temperature(0.9);
+
+        assert_eq!(cold.generate_content("hi").unwrap(), "cold");
+        assert_eq!(warm.generate_content("hi").unwrap(), "warm");
+        assert_eq!(cold.generate_content("hi").unwrap(), "cold");
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 2);
+        assert_eq!(std::fs::read_dir(&config.dir).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_cache_replays_stream_in_large_chunks() {
+        let words = (0..2000).map(|i| format!("w{i} ")).collect::<Vec<_>>();
+        let body = format!("[{}]", words.iter().map(|word| chunk(word)).collect::<Vec<_>>().join(",\n"));
+        let transport = Arc::new(MockTransport::default().reply("HTTP/2 200", &body, Ok(())));
+        let client = cached_client(transport.clone(), cache_config("replay", Duration::from_secs(3600)));
+
+        let live = collect(client.generate_content_streaming("count"));
+        assert_eq!(live.len(), 2000);
+        let replayed = collect(client.generate_content_streaming("count"));
+        assert_eq!(replayed.concat(), live.concat());
+        assert_eq!(replayed.len(), live.concat().len().div_ceil(REPLAY_CHUNK));
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 1);
+    }
+
+    fn candidates_chunk(texts: &[(usize, &str)]) -> String {
+        let candidates = texts
+            .iter()
+            .map(|(index, text)| json!({ "index": index, "content": { "parts": [{ "text": text }] } }))
+            .collect::<Vec<_>>();
+        json!({ "candidates": candidates }).to_string()
+    }
+
+    #[test]
+    fn test_candidates() {
+        let response = json!({
+            "candidates": [
+                { "index": 2, "content": { "parts": [{ "text": "third" }] }, "finishReason": "MAX_TOKENS", "tokenCount": 9 },
+                { "index": 0, "content": { "parts": [{ "text": "fir" }, { "text": "st" }] }, "finishReason": "STOP", "tokenCount": 4 },
+                { "index": 1, "content": { "parts": [{ "text": "second" }] }, "finishReason": "STOP", "tokenCount": 6 },
+            ]
+        });
+        let transport = Arc::new(MockTransport::default().reply("HTTP/2 200", &response.to_string(), Ok(())));
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone())
+            .with_candidate_count(3);
+
+        let generation = client.generate_content("hi").unwrap();
+        assert!(transport.bodies.lock().unwrap()[0].contains(r#""candidateCount":3"#));
+        assert_eq!(generation, "first");
+        assert_eq!(generation.len(), 5);
+        assert_eq!(
+            generation.candidates().iter().map(Candidate::text).collect::<Vec<_>>(),
+            ["first", "second", "third"]
+        );
+        let third = &generation.candidates()[2];
+        assert_eq!((third.index, third.finish_reason.as_deref(), third.token_count), (2, Some("MAX_TOKENS"), Some(9)));
+        assert_eq!(String::from(generation), "first");
+    }
+
+    #[test]
+    fn test_candidates_streaming() {
+        let body = format!(
+            "[{},\n{},\n{}]",
+            candidates_chunk(&[(0, "a"), (1, "x")]),
+            candidates_chunk(&[(1, "y"), (2, "m")]),
+            candidates_chunk(&[(0, "b"), (2, "n")]),
+        );
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &format!("[{},\n", candidates_chunk(&[(0, "a"), (1, "x")])), Err(reset()))
+                .reply("HTTP/2 200", &body, Ok(())),
+        );
+        let client = client(transport.clone(), Arc::new(FakeClock::default()), Arc::new(Mutex::new(vec![])))
+            .with_candidate_count(3)
+            .with_cache(cache_config("candidates", Duration::from_secs(3600)));
+
+        let pairs = |stream: Box<dyn CandidateStreamingCoroutine>| {
+            let mut stream = Box::into_pin(stream);
+            let mut pairs = vec![];
+            while let CoroutineState::Yielded(pair) = stream.as_mut().resume(()) {
+                pairs.push(pair.unwrap());
+            }
+            pairs
+        };
+        let live = pairs(client.generate_candidates_streaming("hi"));
+        let expected = [(0, "a"), (1, "x"), (1, "y"), (2, "m"), (0, "b"), (2, "n")];
+        assert_eq!(live, expected.map(|(index, text)| (index, text.to_owned())));
+
+        // The text stream shows the first candidate only
+        assert_eq!(collect(client.generate_content_streaming("hi")).concat(), "ab");
+        let replayed = pairs(client.generate_candidates_streaming("hi"));
+        assert_eq!(replayed, [(0, "ab"), (1, "xy"), (2, "mn")].map(|(index, text)| (index, text.to_owned())));
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 2);
+    }
+
+    fn drive(stream: Box<dyn StreamingCoroutine>) -> (Vec<String>, Result<(), GeminiError>) {
+        let mut stream = Box::into_pin(stream);
+        let mut output = vec![];
+        loop {
+            match stream.as_mut().resume(()) {
+                CoroutineState::Yielded(Ok(text)) => output.push(text),
+                CoroutineState::Yielded(Err(_)) => {}
+                CoroutineState::Complete(result) => return (output, result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_idle_timeout_mid_stream() {
+        let transport = Arc::new(
+            MockTransport::default()
+                .stall("HTTP/2 200", &format!("[{},\n", chunk("Hello, ")))
+                .reply("HTTP/2 200", &format!("[{},\n{}]", chunk("Hello, "), chunk("world")), Ok(())),
+        );
+        let clock = Arc::new(FakeClock::default());
+        let events = Arc::new(Mutex::new(vec![]));
+        let client = client(transport.clone(), clock, events.clone()).with_idle_timeout(Duration::from_millis(50));
+
+        let started = Instant::now();
+        let (output, result) = drive(client.generate_content_streaming("hi"));
+        result.unwrap();
+        assert_eq!(output, ["Hello, ", "world"]);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        // The stalled attempt was torn down and retried
+        assert_eq!(transport.cancelled.load(Ordering::SeqCst), 1);
+        let events = events.lock().unwrap();
+        assert!(matches!(events[0].error, GeminiError::IdleTimeout { .. }));
+    }
+
+    #[test]
+    fn test_overall_timeout() {
+        let transport = Arc::new(MockTransport::default().stall("HTTP/2 200", &format!("[{},\n", chunk("Hel"))));
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone())
+            .with_timeout(Duration::from_millis(80));
+
+        let (output, result) = drive(client.generate_content_streaming("hi"));
+        assert_eq!(output, ["Hel"]);
+        assert!(matches!(result, Err(GeminiError::Timeout { after }) if after == Duration::from_millis(80)));
+        assert_eq!(transport.cancelled.load(Ordering::SeqCst), 1);
+
+        let transport = Arc::new(MockTransport::default().stall("HTTP/2 200", "{"));
+        let client = client.with_transport(transport.clone());
+        assert!(matches!(client.generate_content("hi"), Err(GeminiError::Timeout { .. })));
+        assert_eq!(transport.cancelled.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let transport = Arc::new(MockTransport::default().stall("HTTP/2 200", &format!("[{},\n", chunk("Hel"))));
+        let token = CancellationToken::new();
+        let client = GeminiClient::new("test-model")
+            .with_api_key("key")
+            .with_transport(transport.clone())
+            .with_cancellation(token.clone());
+
+        let canceller = std::thread::spawn({
+            let token = token.clone();
+            move || {
+                std::thread::sleep(Duration::from_millis(100));
+                token.cancel();
+            }
+        });
+        let (output, result) = drive(client.generate_content_streaming("hi"));
+        canceller.join().unwrap();
+        assert_eq!(output, ["Hel"]);
+        assert!(matches!(result, Err(GeminiError::Cancelled)));
+        assert_eq!(transport.cancelled.load(Ordering::SeqCst), 1);
+
+        // Later calls on the same token fail without a request
+        assert!(matches!(client.generate_content("hi"), Err(GeminiError::Cancelled)));
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_batch() {
+        let transport = Arc::new(
+            MockTransport::default()
+                .reply("HTTP/2 200", &with_usage("one", 10, 1), Ok(()))
+                .reply("HTTP/2 503", UNAVAILABLE, Ok(()))
+                .reply("HTTP/2 200", &with_usage("three", 30, 3), Ok(()))
+                .reply("HTTP/2 200", &chunk("four"), Ok(())),
+        );
+        let client = cached_client(transport.clone(), cache_config("batch", Duration::from_secs(3600)))
+            .with_retry(RetryPolicy {
+                max_attempts: 3,
+                ..RetryPolicy::default()
+            });
+
+        let results = client.batch(vec!["1".into(), "2".into(), "3".into()]);
+        assert_eq!(results[0].as_deref().unwrap(), "one");
+        // A failure is not retried and does not affect the rest
+        assert!(matches!(results[1], Err(GeminiError::Api { code: 503, .. })));
+        assert_eq!(results[2].as_deref().unwrap(), "three");
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 3);
+        assert_eq!(client.usage().per_model()["test-model"].prompt_tokens, 40);
+
+        // Cached prompts are not sent
+        let results = client.batch(vec![Prompt(vec![Part::text("4")]), "1".into()]);
+        assert_eq!(results.iter().map(|result| result.as_deref().unwrap()).collect::<Vec<_>>(), ["four", "one"]);
+        assert_eq!(transport.posts.load(Ordering::SeqCst), 4);
+        assert!(transport.bodies.lock().unwrap()[3].contains(r#""text":"4""#));
+
+        assert!(client.batch(Vec::new()).is_empty());
+    }
 }
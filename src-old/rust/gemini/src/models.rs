@@ -0,0 +1,38 @@
+use serde::Deserialize;
+
+/// What the `models` endpoint reports about a model.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelInfo {
+    /// Resource name, e.g. `models/gemini-2.0-flash`.
+    pub name: String,
+    #[serde(default)]
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub input_token_limit: Option<u32>,
+    #[serde(default)]
+    pub output_token_limit: Option<u32>,
+    #[serde(default)]
+    pub supported_generation_methods: Vec<String>,
+}
+
+impl ModelInfo {
+    /// The id to pass to [`GeminiClient::new`](crate::GeminiClient::new).
+    pub fn id(&self) -> &str {
+        self.name.strip_prefix("models/").unwrap_or(&self.name)
+    }
+
+    pub fn supports(&self, method: &str) -> bool {
+        self.supported_generation_methods.iter().any(|m| m == method)
+    }
+}
+
+/// One page of the `models` listing.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ModelPage {
+    #[serde(default)]
+    pub(crate) models: Vec<ModelInfo>,
+    #[serde(default)]
+    pub(crate) next_page_token: Option<String>,
+}
@@ -0,0 +1,256 @@
+use std::path::Path;
+
+use serde_json::{Value, json};
+
+use crate::GeminiError;
+
+/// Largest request the API accepts with data sent inline. Anything bigger
+/// has to go through the Files API and be referenced with [`Part::FileData`].
+pub const INLINE_LIMIT: usize = 20 * 1024 * 1024;
+
+/// One piece of a multimodal prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Part {
+    Text(String),
+    /// Data sent along with the request, already base64 encoded.
+    InlineData { mime_type: String, base64: String },
+    /// A file previously uploaded through the Files API.
+    FileData { uri: String, mime_type: String },
+    /// A call the model made, replayed as part of its turn.
+    FunctionCall { name: String, args: Value },
+    /// The result of a function call, sent back to the model.
+    FunctionResponse { name: String, response: Value },
+}
+
+impl Part {
+    pub fn text(text: impl Into<String>) -> Self {
+        Part::Text(text.into())
+    }
+
+    /// Inline data from raw bytes.
+    pub fn inline(mime_type: &str, bytes: &[u8]) -> Self {
+        Part::InlineData {
+            mime_type: mime_type.to_string(),
+            base64: encode(bytes),
+        }
+    }
+
+    pub fn file(uri: &str, mime_type: &str) -> Self {
+        Part::FileData {
+            uri: uri.to_string(),
+            mime_type: mime_type.to_string(),
+        }
+    }
+
+    pub fn function_response(name: &str, response: Value) -> Self {
+        Part::FunctionResponse {
+            name: name.to_string(),
+            response,
+        }
+    }
+
+    /// Reads an image to send inline, with the mime type taken from its extension.
+    pub fn from_image_path(path: impl AsRef<Path>) -> Result<Self, GeminiError> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        let mime_type = match extension.as_deref() {
+            Some("png") => "image/png",
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("webp") => "image/webp",
+            Some("gif") => "image/gif",
+            Some("heic") => "image/heic",
+            Some("heif") => "image/heif",
+            _ => {
+                return Err(GeminiError::IoError(format!(
+                    "Unsupported image type: {}",
+                    path.display()
+                )));
+            }
+        };
+
+        let io_error = |e: std::io::Error| GeminiError::IoError(format!("{}: {}", path.display(), e));
+        let size = std::fs::metadata(path).map_err(io_error)?.len() as usize;
+        check_inline_size(size)?;
+        let bytes = std::fs::read(path).map_err(io_error)?;
+        Ok(Part::inline(mime_type, &bytes))
+    }
+
+    /// Size of the inline payload once decoded.
+    fn inline_size(&self) -> usize {
+        match self {
+            Part::InlineData { base64, .. } => {
+                let padding = base64.bytes().rev().take_while(|&b| b == b'=').count();
+                base64.len() / 4 * 3 - padding
+            }
+            _ => 0,
+        }
+    }
+
+    /// The part as it appears in a request's `parts` array.
+    pub(crate) fn to_json(&self) -> Value {
+        match self {
+            Part::Text(text) => json!({ "text": text }),
+            Part::InlineData { mime_type, base64 } => json!({
+                "inlineData": { "mimeType": mime_type, "data": base64 }
+            }),
+            Part::FileData { uri, mime_type } => json!({
+                "fileData": { "mimeType": mime_type, "fileUri": uri }
+            }),
+            Part::FunctionCall { name, args } => json!({
+                "functionCall": { "name": name, "args": args }
+            }),
+            Part::FunctionResponse { name, response } => json!({
+                "functionResponse": { "name": name, "response": response }
+            }),
+        }
+    }
+}
+
+impl From<&str> for Part {
+    fn from(text: &str) -> Self {
+        Part::text(text)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Model,
+}
+
+/// One turn of a conversation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Content {
+    pub role: Role,
+    pub parts: Vec<Part>,
+}
+
+impl Content {
+    pub fn user(parts: Vec<Part>) -> Self {
+        Self {
+            role: Role::User,
+            parts,
+        }
+    }
+
+    pub fn model(parts: Vec<Part>) -> Self {
+        Self {
+            role: Role::Model,
+            parts,
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        let role = match self.role {
+            Role::User => "user",
+            Role::Model => "model",
+        };
+        json!({
+            "role": role,
+            "parts": self.parts.iter().map(Part::to_json).collect::<Vec<_>>()
+        })
+    }
+}
+
+/// A single turn prompt, one of the requests of [`GeminiClient::batch`].
+///
+/// [`GeminiClient::batch`]: crate::GeminiClient::batch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt(pub Vec<Part>);
+
+impl From<&str> for Prompt {
+    fn from(text: &str) -> Self {
+        Prompt(vec![Part::text(text)])
+    }
+}
+
+impl From<String> for Prompt {
+    fn from(text: String) -> Self {
+        Prompt(vec![Part::Text(text)])
+    }
+}
+
+impl From<Vec<Part>> for Prompt {
+    fn from(parts: Vec<Part>) -> Self {
+        Prompt(parts)
+    }
+}
+
+/// Fails if the inline data of `parts` together is over [`INLINE_LIMIT`].
+pub(crate) fn check_inline<'a>(parts: impl IntoIterator<Item = &'a Part>) -> Result<(), GeminiError> {
+    check_inline_size(parts.into_iter().map(Part::inline_size).sum())
+}
+
+fn check_inline_size(size: usize) -> Result<(), GeminiError> {
+    if size > INLINE_LIMIT {
+        return Err(GeminiError::InlineDataTooLarge {
+            size,
+            limit: INLINE_LIMIT,
+        });
+    }
+    Ok(())
+}
+
+/// Standard base64 with padding.
+fn encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let n = group.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        for (input, expected) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(encode(input.as_bytes()), expected);
+            assert_eq!(Part::inline("text/plain", input.as_bytes()).inline_size(), input.len());
+        }
+    }
+
+    #[test]
+    fn test_unsupported_extension() {
+        assert!(matches!(
+            Part::from_image_path("diagram.svg"),
+            Err(GeminiError::IoError(msg)) if msg.contains("diagram.svg")
+        ));
+    }
+
+    #[test]
+    fn test_inline_limit() {
+        let parts = [
+            Part::inline("image/png", &vec![0; INLINE_LIMIT / 2]),
+            Part::file("https://example.com/files/big", "video/mp4"),
+        ];
+        assert!(check_inline(&parts).is_ok());
+
+        let parts = [parts[0].clone(), parts[0].clone(), Part::inline("image/png", b"!")];
+        let err = check_inline(&parts).unwrap_err();
+        assert!(matches!(err, GeminiError::InlineDataTooLarge { size, .. } if size == INLINE_LIMIT + 1));
+        assert!(err.to_string().contains("Files API"));
+    }
+}
@@ -0,0 +1,237 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::GeminiError;
+
+/// How failed requests are repeated by the client.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub retry_on: fn(&GeminiError) -> bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            retry_on: GeminiError::is_retryable,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before the attempt following failed attempt `attempt` (1-based).
+    /// A server provided Retry-After wins, otherwise exponential backoff with
+    /// ±20% jitter, capped at `max_delay`.
+    pub fn delay(&self, attempt: u32, error: &GeminiError) -> Duration {
+        if let Some(retry_after) = error.retry_after() {
+            return retry_after;
+        }
+        let exp = self
+            .base_delay
+            .saturating_mul(1u32 << (attempt - 1).min(31))
+            .min(self.max_delay);
+        exp.mul_f64(0.8 + 0.4 * unit_random())
+    }
+}
+
+/// Reported through the client's retry callback before sleeping.
+#[derive(Debug, Clone)]
+pub struct RetryEvent {
+    /// The attempt that failed (1-based).
+    pub attempt: u32,
+    pub delay: Duration,
+    pub error: GeminiError,
+}
+
+/// Source of sleeping so tests can observe backoff without waiting.
+pub trait Clock: Send + Sync {
+    fn sleep(&self, duration: Duration);
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+pub(crate) type RetryCallback = Arc<dyn Fn(&RetryEvent) + Send + Sync>;
+
+/// Retry state shared by the client and its streaming coroutines.
+#[derive(Clone)]
+pub(crate) struct Retrier {
+    pub(crate) policy: RetryPolicy,
+    pub(crate) on_retry: Option<RetryCallback>,
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl Retrier {
+    pub(crate) fn should_retry(&self, attempt: u32, error: &GeminiError) -> bool {
+        attempt < self.policy.max_attempts && (self.policy.retry_on)(error)
+    }
+
+    /// Repeats `call` until it succeeds, fails permanently or runs out of attempts.
+    pub(crate) fn run<T>(&self, mut call: impl FnMut() -> Result<T, GeminiError>) -> Result<T, GeminiError> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match call() {
+                Err(e) if self.should_retry(attempt, &e) => self.wait(attempt, e),
+                result => return result,
+            }
+        }
+    }
+
+    /// Reports the failure and sleeps for the backoff delay.
+    pub(crate) fn wait(&self, attempt: u32, error: GeminiError) {
+        let delay = self.policy.delay(attempt, &error);
+        if let Some(on_retry) = &self.on_retry {
+            on_retry(&RetryEvent {
+                attempt,
+                delay,
+                error,
+            });
+        }
+        self.clock.sleep(delay);
+    }
+}
+
+/// Drops text and function calls a restarted stream already delivered
+/// before the failure.
+pub(crate) struct Dedup {
+    emitted: usize,
+    seen: usize,
+    calls_emitted: usize,
+    calls_seen: usize,
+}
+
+impl Dedup {
+    pub(crate) fn new() -> Self {
+        Self {
+            emitted: 0,
+            seen: 0,
+            calls_emitted: 0,
+            calls_seen: 0,
+        }
+    }
+
+    /// Called when a new attempt starts streaming from the beginning.
+    pub(crate) fn restart(&mut self) {
+        self.seen = 0;
+        self.calls_seen = 0;
+    }
+
+    /// Whether the next function call of this attempt was not yielded yet.
+    pub(crate) fn filter_call(&mut self) -> bool {
+        self.calls_seen += 1;
+        if self.calls_seen <= self.calls_emitted {
+            return false;
+        }
+        self.calls_emitted = self.calls_seen;
+        true
+    }
+
+    /// Returns the part of `text` that was not yielded yet.
+    pub(crate) fn filter(&mut self, text: String) -> Option<String> {
+        let len = text.chars().count();
+        let skip = self.emitted.saturating_sub(self.seen).min(len);
+        self.seen += len;
+        if skip == len {
+            return None;
+        }
+        self.emitted = self.seen;
+        Some(text.chars().skip(skip).collect())
+    }
+}
+
+/// Uniform value in [0, 1) from a time seeded xorshift.
+fn unit_random() -> f64 {
+    static STATE: AtomicU64 = AtomicU64::new(0);
+    let mut x = STATE.load(Ordering::Relaxed);
+    if x == 0 {
+        x = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0x9E3779B97F4A7C15, |d| d.as_nanos() as u64)
+            | 1;
+    }
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    STATE.store(x, Ordering::Relaxed);
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transient() -> GeminiError {
+        GeminiError::Transport {
+            exit_code: 56,
+            stderr: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_backoff_bounds() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(1000),
+            ..RetryPolicy::default()
+        };
+        for (attempt, base) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (40, 1000)] {
+            let delay = policy.delay(attempt, &transient()).as_secs_f64() * 1000.0;
+            let base = base as f64;
+            assert!(delay >= base * 0.8 - 1e-6 && delay <= base * 1.2 + 1e-6, "{attempt}: {delay}");
+        }
+    }
+
+    #[test]
+    fn test_retry_after_wins() {
+        let error = GeminiError::Api {
+            code: 429,
+            status: "RESOURCE_EXHAUSTED".into(),
+            message: String::new(),
+            retry_after: Some(Duration::from_secs(7)),
+        };
+        assert_eq!(RetryPolicy::default().delay(1, &error), Duration::from_secs(7));
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut dedup = Dedup::new();
+        assert_eq!(dedup.filter("héllo ".into()).as_deref(), Some("héllo "));
+        assert_eq!(dedup.filter("wo".into()).as_deref(), Some("wo"));
+        dedup.restart();
+        assert_eq!(dedup.filter("héllo".into()), None);
+        assert_eq!(dedup.filter(" world".into()).as_deref(), Some("rld"));
+        assert_eq!(dedup.filter("!".into()).as_deref(), Some("!"));
+    }
+
+    #[test]
+    fn test_dedup_calls() {
+        let mut dedup = Dedup::new();
+        assert!(dedup.filter_call());
+        dedup.restart();
+        assert!(!dedup.filter_call());
+        assert!(dedup.filter_call());
+        assert!(dedup.filter_call());
+    }
+}
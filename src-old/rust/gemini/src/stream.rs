@@ -0,0 +1,176 @@
+use crate::{GeminiError, GeminiResponse};
+
+/// Incremental parser over the bytes of a `streamGenerateContent` response.
+///
+/// The endpoint answers with a JSON array of `GeminiResponse` objects (or SSE
+/// `data:` lines when `alt=sse` is requested) that arrive in arbitrary chunks.
+/// Bytes are buffered until a complete top level object has been seen, which
+/// is found by tracking brace depth outside of string literals, so chunk
+/// boundaries may fall anywhere: inside strings, escapes or multi-byte chars.
+pub(crate) struct StreamParser {
+    buf: Vec<u8>,
+    pos: usize,
+    start: Option<usize>,
+    depth: usize,
+    in_string: bool,
+    escape: bool,
+}
+
+impl StreamParser {
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            pos: 0,
+            start: None,
+            depth: 0,
+            in_string: false,
+            escape: false,
+        }
+    }
+
+    /// Feeds a chunk and returns every response completed by it, in order.
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<Result<GeminiResponse, GeminiError>> {
+        self.buf.extend_from_slice(chunk);
+        let mut complete = vec![];
+
+        while self.pos < self.buf.len() {
+            let byte = self.buf[self.pos];
+            let Some(start) = self.start else {
+                // Between objects: array brackets, commas, whitespace and SSE framing.
+                if byte == b'{' {
+                    self.start = Some(self.pos);
+                    self.depth = 1;
+                }
+                self.pos += 1;
+                continue;
+            };
+
+            if self.in_string {
+                if self.escape {
+                    self.escape = false;
+                } else if byte == b'\\' {
+                    self.escape = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                }
+            } else {
+                match byte {
+                    b'"' => self.in_string = true,
+                    b'{' => self.depth += 1,
+                    b'}' => {
+                        self.depth -= 1;
+                        if self.depth == 0 {
+                            let object = &self.buf[start..=self.pos];
+                            complete.push(serde_json::from_slice(object).map_err(|e| {
+                                GeminiError::JsonParseError(format!(
+                                    "Failed to parse stream chunk: {}. Chunk: {}",
+                                    e,
+                                    String::from_utf8_lossy(object)
+                                ))
+                            }));
+                            self.start = None;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            self.pos += 1;
+        }
+
+        // Drop everything that can no longer be part of an object.
+        let consumed = self.start.unwrap_or(self.pos);
+        self.buf.drain(..consumed);
+        self.pos -= consumed;
+        if let Some(start) = &mut self.start {
+            *start -= consumed;
+        }
+
+        complete
+    }
+
+    /// Checks that the stream did not end in the middle of an object.
+    pub(crate) fn finish(&self) -> Result<(), GeminiError> {
+        match self.start {
+            Some(start) => Err(GeminiError::StreamError(format!(
+                "Stream ended inside a response object: {}",
+                String::from_utf8_lossy(&self.buf[start..])
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(chunks: &[&[u8]]) -> Vec<String> {
+        let mut parser = StreamParser::new();
+        let mut texts = vec![];
+        for chunk in chunks {
+            for response in parser.push(chunk) {
+                for candidate in response.unwrap().candidates() {
+                    texts.push(candidate.text());
+                }
+            }
+        }
+        parser.finish().unwrap();
+        texts
+    }
+
+    fn response(text: &str) -> String {
+        serde_json::json!({
+            "candidates": [{ "content": { "parts": [{ "text": text }], "role": "model" } }]
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_array_stream() {
+        let stream = format!("[{}\n,\r\n{}]", response("Hello"), response(", world"));
+        assert_eq!(collect(&[stream.as_bytes()]), vec!["Hello", ", world"]);
+    }
+
+    #[test]
+    fn test_escaped_quotes_and_backslashes() {
+        let text = r#"say "hi" \ then {braces} \"done\""#;
+        let stream = format!("[{}]", response(text));
+        let bytes = stream.as_bytes();
+        let chunks: Vec<&[u8]> = bytes.chunks(3).collect();
+        assert_eq!(collect(&chunks), vec![text]);
+    }
+
+    #[test]
+    fn test_emoji_split_across_chunks() {
+        let stream = format!("[{}]", response("rocket 🚀 launch"));
+        let bytes = stream.as_bytes();
+        let split = stream.find('🚀').unwrap() + 2;
+        assert_eq!(
+            collect(&[&bytes[..split], &bytes[split..]]),
+            vec!["rocket 🚀 launch"]
+        );
+    }
+
+    #[test]
+    fn test_code_embedding_text_marker() {
+        let code = "```json\n{\"text\": \"not a delta\"}\n```";
+        let stream = format!("[{},\n{}]", response(code), response("tail"));
+        let bytes = stream.as_bytes();
+        let chunks: Vec<&[u8]> = bytes.chunks(7).collect();
+        assert_eq!(collect(&chunks), vec![code, "tail"]);
+    }
+
+    #[test]
+    fn test_sse_framing() {
+        let stream = format!("data: {}\r\n\r\ndata: {}\r\n\r\n", response("a"), response("b"));
+        assert_eq!(collect(&[stream.as_bytes()]), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let stream = response("cut");
+        let mut parser = StreamParser::new();
+        assert!(parser.push(&stream.as_bytes()[..10]).is_empty());
+        assert!(parser.finish().is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::Part;
+
+/// A function the model may call instead of answering directly.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    /// OpenAPI style schema of the arguments object.
+    pub parameters_schema: Value,
+}
+
+impl FunctionDeclaration {
+    pub fn new(name: &str, description: &str, parameters_schema: Value) -> Self {
+        Self {
+            name: name.to_string(),
+            description: description.to_string(),
+            parameters_schema,
+        }
+    }
+}
+
+/// One piece of what the model answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelReply {
+    Text(String),
+    /// The model wants `name` called with `args`. Answer it by sending a
+    /// [`Part::FunctionResponse`] in the next turn.
+    FunctionCall { name: String, args: Value },
+}
+
+impl ModelReply {
+    /// The reply as a part of the model's turn, for replaying the conversation.
+    pub fn to_part(&self) -> Part {
+        match self {
+            ModelReply::Text(text) => Part::Text(text.clone()),
+            ModelReply::FunctionCall { name, args } => Part::FunctionCall {
+                name: name.clone(),
+                args: args.clone(),
+            },
+        }
+    }
+}
+
+/// `functionCall` part of a response.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct FunctionCall {
+    name: String,
+    #[serde(default)]
+    args: Value,
+}
+
+impl From<FunctionCall> for ModelReply {
+    fn from(call: FunctionCall) -> Self {
+        ModelReply::FunctionCall {
+            name: call.name,
+            args: call.args,
+        }
+    }
+}
+
+/// The `tools` field of a request declaring `functions`.
+pub(crate) fn tools(functions: &[FunctionDeclaration]) -> Value {
+    let declarations = functions
+        .iter()
+        .map(|function| {
+            json!({
+                "name": function.name,
+                "description": function.description,
+                "parameters": function.parameters_schema,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!([{ "functionDeclarations": declarations }])
+}
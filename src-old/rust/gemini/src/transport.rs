@@ -0,0 +1,1422 @@
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::fs::{self, DirBuilder};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::PathBuf;
+use std::process::{self, Child, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use rustls::pki_types::ServerName;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::GeminiError;
+
+/// Carries a JSON request to the API. The response is read as the raw head
+/// and body, the way `curl -D -` prints it.
+pub trait Transport: Send + Sync {
+    fn post(&self, url: &str, body: &str, streaming: bool) -> Result<Box<dyn Exchange>, GeminiError>;
+
+    fn get(&self, url: &str) -> Result<Box<dyn Exchange>, GeminiError>;
+
+    /// POSTs every body to `url`, one after another, over a single connection
+    /// where the transport can keep one open. The exchanges are read in
+    /// order; one dropped unread is skipped.
+    fn post_batch(&self, url: &str, bodies: &[String]) -> Result<Vec<Box<dyn Exchange>>, GeminiError> {
+        bodies.iter().map(|body| self.post(url, body, false)).collect()
+    }
+
+    /// Requests sent and connections opened so far.
+    fn stats(&self) -> PoolStats {
+        PoolStats::default()
+    }
+}
+
+/// Connection reuse of a transport, for usage reports.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    pub requests: u64,
+    /// Connections opened, each paying its own TCP and TLS handshake.
+    pub connections: u64,
+}
+
+impl PoolStats {
+    /// Requests sent over a connection opened for an earlier one.
+    pub fn reused(&self) -> u64 {
+        self.requests.saturating_sub(self.connections)
+    }
+}
+
+/// An in-flight response.
+pub trait Exchange: Read + Send {
+    /// Waits for the exchange to end and reports transport level failures.
+    fn finish(&mut self) -> Result<(), GeminiError>;
+
+    /// Like `read`, but gives up after `timeout` and returns `None`.
+    /// Transports that cannot wait with a timeout block instead.
+    fn read_timeout(&mut self, buf: &mut [u8], _timeout: Duration) -> io::Result<Option<usize>> {
+        self.read(buf).map(Some)
+    }
+
+    /// Abandons the exchange, releasing whatever carries it.
+    fn cancel(&mut self) {}
+}
+
+/// Sends requests by spawning `curl`. Every call blocks on the child process,
+/// so tasks on the runtime make them through `rt::spawn_blocking`.
+///
+/// A request on its own gets a process, and so a connection, of its own. A
+/// batch is sent by one process chaining the requests with `--next`, which
+/// keeps the connection to the host open between them. [`PooledTransport`]
+/// keeps them open between calls as well. Clones share their [`PoolStats`].
+///
+/// Bodies reach curl through stdin, or files for a batch, rather than its
+/// arguments, which other processes can read and the kernel limits to 128 KiB
+/// each.
+#[derive(Debug, Default, Clone)]
+pub struct CurlTransport {
+    keepalive: Option<Duration>,
+    stats: Arc<Mutex<PoolStats>>,
+}
+
+impl CurlTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Probes connections idle for `idle` with TCP keep-alives, so that a
+    /// batch waiting on a slow reply does not lose its connection to a proxy
+    /// or NAT dropping quiet ones. curl's default is 60 seconds.
+    pub fn with_keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Spawns `curl` for one request, a POST of `body` if there is one.
+    fn curl(&self, url: &str, body: Option<&str>, streaming: bool) -> Result<CurlExchange, GeminiError> {
+        let mut curl_cmd = Command::new("curl");
+        self.request(&mut curl_cmd, url, body.map(|_| "@-"), streaming);
+        let (child, stdout) = spawn(curl_cmd, body)?;
+
+        let mut stats = self.stats.lock().unwrap();
+        stats.requests += 1;
+        stats.connections += 1;
+        Ok(CurlExchange { child, stdout })
+    }
+
+    /// Appends the arguments of one request to `curl_cmd`, a POST of the body
+    /// curl reads from `data` if there is one: `@-` for stdin, or `@` and a
+    /// file.
+    fn request(&self, curl_cmd: &mut Command, url: &str, data: Option<&str>, streaming: bool) {
+        curl_cmd
+            .arg("-s")
+            .arg("-D")
+            .arg("-"); // Dump the response head to stdout ahead of the body
+
+        if let Some(idle) = self.keepalive {
+            curl_cmd.arg("--keepalive-time").arg(idle.as_secs().max(1).to_string());
+        }
+
+        if let Some(data) = data {
+            curl_cmd
+                .arg("-X")
+                .arg("POST")
+                .arg("-H")
+                .arg("Content-Type: application/json; charset=utf-8")
+                .arg("--data-binary") // Sent as is, unlike -d which strips newlines
+                .arg(data);
+        }
+
+        if streaming {
+            curl_cmd
+                .arg("-H")
+                .arg("Accept: text/event-stream") // Tell the API we want server-sent events
+                .arg("-N"); // Important: disable buffering for streaming
+        }
+
+        curl_cmd.arg(url);
+    }
+}
+
+impl Transport for CurlTransport {
+    fn post(&self, url: &str, body: &str, streaming: bool) -> Result<Box<dyn Exchange>, GeminiError> {
+        Ok(Box::new(self.curl(url, Some(body), streaming)?))
+    }
+
+    fn get(&self, url: &str) -> Result<Box<dyn Exchange>, GeminiError> {
+        Ok(Box::new(self.curl(url, None, false)?))
+    }
+
+    fn post_batch(&self, url: &str, bodies: &[String]) -> Result<Vec<Box<dyn Exchange>>, GeminiError> {
+        if bodies.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Responses are told apart by a trailer after each, unlikely to be
+        // part of a body
+        let mut nonce = RandomState::new().build_hasher();
+        nonce.write_usize(bodies.len());
+        let nonce = nonce.finish();
+        let marker = format!("\n--curl-batch-{nonce:016x}");
+        let files = BodyFiles::write(bodies, nonce)?;
+        let mut curl_cmd = Command::new("curl");
+        for i in 0..bodies.len() {
+            if i > 0 {
+                curl_cmd.arg("--next");
+            }
+            let data = format!("@{}", files.path(i).display());
+            self.request(&mut curl_cmd, url, Some(&data), false);
+            curl_cmd
+                .arg("-w")
+                .arg(format!("{marker} %{{exitcode}} %{{num_connects}}\\n"));
+        }
+        let (child, stdout) = spawn(curl_cmd, None)?;
+
+        let batch = Arc::new(Mutex::new(Batch {
+            child,
+            stdout,
+            marker: marker.into_bytes(),
+            pending: Vec::new(),
+            ended: Vec::new(),
+            eof: false,
+            stats: self.stats.clone(),
+            _files: files,
+        }));
+        Ok((0..bodies.len())
+            .map(|index| {
+                Box::new(BatchExchange {
+                    batch: batch.clone(),
+                    index,
+                }) as Box<dyn Exchange>
+            })
+            .collect())
+    }
+
+    fn stats(&self) -> PoolStats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+/// Spawns curl and writes `input` to its stdin. curl reads `@-` in full while
+/// parsing its arguments, before any output that could fill the pipe back.
+fn spawn(mut curl_cmd: Command, input: Option<&str>) -> Result<(Child, ChildStdout), GeminiError> {
+    let mut child = curl_cmd
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| GeminiError::CurlError(e.to_string()))?;
+
+    if let Some(input) = input
+        && let Some(mut stdin) = child.stdin.take()
+    {
+        // curl failing before it read everything says why by its exit code
+        let _ = stdin.write_all(input.as_bytes());
+    }
+
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| GeminiError::StreamError("Failed to capture stdout".to_string()))?;
+
+    Ok((child, stdout))
+}
+
+/// Waits up to `timeout` for `fd` to be readable, closed or failed.
+fn poll(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let timeout = timeout.as_millis().min(i32::MAX as u128) as libc::c_int;
+    match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+        0 => Ok(false),
+        ready if ready < 0 => Err(io::Error::last_os_error()),
+        _ => Ok(true),
+    }
+}
+
+struct CurlExchange {
+    child: Child,
+    stdout: ChildStdout,
+}
+
+impl Read for CurlExchange {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.stdout.read(buf)
+    }
+}
+
+impl Exchange for CurlExchange {
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        // Readable, closed or failed; read tells which
+        match poll(self.stdout.as_raw_fd(), timeout)? {
+            true => self.stdout.read(buf).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    fn cancel(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    fn finish(&mut self) -> Result<(), GeminiError> {
+        let status = self.child.wait().map_err(|e| {
+            GeminiError::CurlError(format!("Error waiting for curl process: {}", e))
+        })?;
+
+        if status.success() {
+            return Ok(());
+        }
+
+        let mut stderr = String::new();
+        if let Some(mut pipe) = self.child.stderr.take() {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        Err(GeminiError::Transport {
+            exit_code: status.code().unwrap_or(-1),
+            stderr,
+        })
+    }
+}
+
+impl Drop for CurlExchange {
+    fn drop(&mut self) {
+        // Exchanges dropped mid-stream must not leave curl running
+        if let Ok(None) = self.child.try_wait() {
+            self.cancel();
+        }
+    }
+}
+
+/// The bodies of a batch, in a directory of their own removed with it. curl
+/// reads stdin for one `@-` only.
+struct BodyFiles {
+    dir: PathBuf,
+}
+
+impl BodyFiles {
+    fn write(bodies: &[String], nonce: u64) -> Result<Self, GeminiError> {
+        let dir = std::env::temp_dir().join(format!("curl-batch-{}-{nonce:016x}", process::id()));
+        let io_error = |e: io::Error| GeminiError::IoError(format!("Failed to write batch bodies: {e}"));
+        DirBuilder::new().mode(0o700).create(&dir).map_err(io_error)?;
+        let files = Self { dir };
+        for (i, body) in bodies.iter().enumerate() {
+            fs::write(files.path(i), body).map_err(io_error)?;
+        }
+        Ok(files)
+    }
+
+    fn path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("{index}.json"))
+    }
+}
+
+impl Drop for BodyFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The output of one curl process sending a batch. Each response is followed
+/// by a trailer of the marker, the exit code of the transfer and the
+/// connections it opened.
+struct Batch {
+    child: Child,
+    stdout: ChildStdout,
+    marker: Vec<u8>,
+    /// Read from curl but not yet handed out.
+    pending: Vec<u8>,
+    /// Exit codes of the responses whose trailer was read, -1 for those cut
+    /// short by curl exiting.
+    ended: Vec<i32>,
+    eof: bool,
+    stats: Arc<Mutex<PoolStats>>,
+    /// Dropped after the fields above, once curl has exited.
+    _files: BodyFiles,
+}
+
+impl Batch {
+    /// Reads the response at `index`, waiting for curl up to `timeout` if
+    /// there is one. Responses before it that were left unread are skipped.
+    fn read(&mut self, index: usize, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<Option<usize>> {
+        let mut skipped = [0; 4096];
+        loop {
+            if self.ended.len() > index {
+                return Ok(Some(0));
+            }
+            let out = match self.ended.len() == index {
+                true => &mut *buf,
+                false => &mut skipped[..],
+            };
+            match self.take(out) {
+                Some(read) if read > 0 && self.ended.len() == index => return Ok(Some(read)),
+                Some(_) => continue,
+                None if self.eof => {
+                    self.ended.push(-1);
+                    continue;
+                }
+                None => {}
+            }
+
+            if let Some(timeout) = timeout
+                && !poll(self.stdout.as_raw_fd(), timeout)?
+            {
+                return Ok(None);
+            }
+            let mut chunk = [0; 4096];
+            match self.stdout.read(&mut chunk)? {
+                0 => self.eof = true,
+                read => self.pending.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+
+    /// Moves pending bytes of the current response to `out`, or ends it at its
+    /// trailer. `None` when more output is needed first.
+    fn take(&mut self, out: &mut [u8]) -> Option<usize> {
+        let at = self
+            .pending
+            .windows(self.marker.len())
+            .position(|window| window == self.marker.as_slice());
+        let available = match at {
+            Some(0) => {
+                let end = self.pending[self.marker.len()..]
+                    .iter()
+                    .position(|&b| b == b'\n')
+                    .map(|end| end + self.marker.len())?;
+                let trailer = String::from_utf8_lossy(&self.pending[self.marker.len()..end]).into_owned();
+                self.pending.drain(..=end);
+                let mut fields = trailer.split_whitespace().map(|field| field.parse::<i64>().ok());
+                let exit_code = fields.next().flatten().unwrap_or(-1);
+                let connections = fields.next().flatten().unwrap_or(0);
+
+                self.ended.push(exit_code as i32);
+                let mut stats = self.stats.lock().unwrap();
+                stats.requests += 1;
+                stats.connections += connections.max(0) as u64;
+                return Some(0);
+            }
+            Some(at) => at,
+            // The tail could be the start of a marker, unless curl is done
+            None if self.eof => self.pending.len(),
+            None => self.pending.len().saturating_sub(self.marker.len() - 1),
+        };
+        if available == 0 {
+            return None;
+        }
+        let read = available.min(out.len());
+        out[..read].copy_from_slice(&self.pending[..read]);
+        self.pending.drain(..read);
+        Some(read)
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// One response of a [`Batch`].
+struct BatchExchange {
+    batch: Arc<Mutex<Batch>>,
+    index: usize,
+}
+
+impl Read for BatchExchange {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.batch.lock().unwrap().read(self.index, buf, None)?;
+        Ok(read.unwrap_or(0))
+    }
+}
+
+impl Exchange for BatchExchange {
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        self.batch.lock().unwrap().read(self.index, buf, Some(timeout))
+    }
+
+    fn cancel(&mut self) {
+        // The rest of the batch goes with it
+        let mut batch = self.batch.lock().unwrap();
+        let _ = batch.child.kill();
+    }
+
+    fn finish(&mut self) -> Result<(), GeminiError> {
+        // Skip whatever of the response is left
+        let mut rest = [0; 4096];
+        while self.read(&mut rest).map_err(|e| GeminiError::IoError(e.to_string()))? > 0 {}
+
+        match self.batch.lock().unwrap().ended[self.index] {
+            0 => Ok(()),
+            exit_code => Err(GeminiError::Transport {
+                exit_code,
+                stderr: String::new(),
+            }),
+        }
+    }
+}
+
+// Failures of a `PooledTransport` are reported with the exit codes curl gives
+// them, which `GeminiError::is_retryable` knows
+const RESOLVE: i32 = 6;
+const CONNECT: i32 = 7;
+const PARTIAL: i32 = 18;
+const TLS: i32 = 35;
+const EMPTY: i32 = 52;
+const SEND: i32 = 55;
+const RECV: i32 = 56;
+
+/// How long a connection may sit in the pool before it is closed rather than
+/// reused.
+const IDLE: Duration = Duration::from_secs(60);
+
+/// Sends requests over HTTP/1.1 connections of its own, kept open between
+/// calls. A connection whose response was read to the end goes back to a pool
+/// per host, and the next request to that host takes it instead of paying for
+/// a TCP and TLS handshake. Clones share the pool and its [`PoolStats`].
+///
+/// Calls block like those of [`CurlTransport`], and failures are reported with
+/// the same exit codes.
+#[derive(Debug, Clone)]
+pub struct PooledTransport {
+    pool: Arc<Pool>,
+    idle: Duration,
+}
+
+impl Default for PooledTransport {
+    fn default() -> Self {
+        Self {
+            pool: Arc::default(),
+            idle: IDLE,
+        }
+    }
+}
+
+impl PooledTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Closes connections idle for longer than `idle` instead of reusing them.
+    /// Servers drop idle connections on a schedule of their own, and a request
+    /// sent over one they dropped is sent again on a new one.
+    pub fn with_idle(mut self, idle: Duration) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    /// Sends one request over a pooled connection, or a new one.
+    fn send(&self, method: &str, url: &str, body: Option<&str>, streaming: bool) -> Result<PooledExchange, GeminiError> {
+        let target = Target::parse(url)?;
+        let mut request = format!("{method} {} HTTP/1.1\r\nHost: {}\r\n", target.path, target.authority);
+        if let Some(body) = body {
+            request.push_str("Content-Type: application/json; charset=utf-8\r\n");
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        if streaming {
+            // Tell the API we want server-sent events
+            request.push_str("Accept: text/event-stream\r\n");
+        }
+        request.push_str("\r\n");
+        let mut request = request.into_bytes();
+        request.extend_from_slice(body.unwrap_or_default().as_bytes());
+
+        let (connection, reused) = match self.pool.take(&target.origin(), self.idle) {
+            Some(connection) => (connection, true),
+            None => (self.pool.connect(&target)?, false),
+        };
+        self.pool.stats.lock().unwrap().requests += 1;
+
+        let mut exchange = PooledExchange {
+            pool: self.pool.clone(),
+            target,
+            request,
+            connection: Some(connection),
+            reused,
+            received: false,
+            raw: Vec::new(),
+            out: Vec::new(),
+            state: State::Head,
+            keep: false,
+            error: None,
+        };
+        exchange.write()?;
+        Ok(exchange)
+    }
+}
+
+impl Transport for PooledTransport {
+    fn post(&self, url: &str, body: &str, streaming: bool) -> Result<Box<dyn Exchange>, GeminiError> {
+        Ok(Box::new(self.send("POST", url, Some(body), streaming)?))
+    }
+
+    fn get(&self, url: &str) -> Result<Box<dyn Exchange>, GeminiError> {
+        Ok(Box::new(self.send("GET", url, None, false)?))
+    }
+
+    fn post_batch(&self, url: &str, bodies: &[String]) -> Result<Vec<Box<dyn Exchange>>, GeminiError> {
+        Ok(bodies
+            .iter()
+            .map(|body| {
+                Box::new(Deferred {
+                    transport: self.clone(),
+                    url: url.to_owned(),
+                    body: body.clone(),
+                    sent: None,
+                }) as Box<dyn Exchange>
+            })
+            .collect())
+    }
+
+    fn stats(&self) -> PoolStats {
+        *self.pool.stats.lock().unwrap()
+    }
+}
+
+/// Idle connections of a [`PooledTransport`] and its clones.
+#[derive(Debug, Default)]
+struct Pool {
+    /// Per origin, the one put back last at the end.
+    connections: Mutex<HashMap<String, Vec<(Connection, Instant)>>>,
+    stats: Mutex<PoolStats>,
+}
+
+impl Pool {
+    /// The connection to `origin` used last, of those idle for less than
+    /// `idle`.
+    fn take(&self, origin: &str, idle: Duration) -> Option<Connection> {
+        let mut connections = self.connections.lock().unwrap();
+        let pooled = connections.get_mut(origin)?;
+        pooled.retain(|(_, since)| since.elapsed() < idle);
+        pooled.pop().map(|(connection, _)| connection)
+    }
+
+    fn put(&self, origin: String, connection: Connection) {
+        let mut connections = self.connections.lock().unwrap();
+        connections.entry(origin).or_default().push((connection, Instant::now()));
+    }
+
+    fn connect(&self, target: &Target) -> Result<Connection, GeminiError> {
+        let addrs: Vec<_> = (target.host.as_str(), target.port)
+            .to_socket_addrs()
+            .map_err(|e| failure(RESOLVE, e))?
+            .collect();
+        let socket = TcpStream::connect(&addrs[..]).map_err(|e| failure(CONNECT, e))?;
+        // Requests are written whole, there is nothing to gain from delaying
+        // their segments
+        let _ = socket.set_nodelay(true);
+
+        let connection = match target.tls {
+            false => Connection::Plain(socket),
+            true => {
+                let name = ServerName::try_from(target.host.clone()).map_err(|e| failure(TLS, e))?;
+                let session = ClientConnection::new(tls_config(), name).map_err(|e| failure(TLS, e))?;
+                let mut stream = StreamOwned::new(session, socket);
+                while stream.conn.is_handshaking() {
+                    stream.conn.complete_io(&mut stream.sock).map_err(|e| failure(TLS, e))?;
+                }
+                Connection::Tls(Box::new(stream))
+            }
+        };
+        self.stats.lock().unwrap().connections += 1;
+        Ok(connection)
+    }
+}
+
+/// Trusts the Mozilla roots compiled into `webpki-roots`, so hosts need no
+/// certificate store of their own.
+fn tls_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let config = ClientConfig::builder_with_provider(provider)
+                .with_safe_default_protocol_versions()
+                .expect("ring supports the default protocol versions")
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            Arc::new(config)
+        })
+        .clone()
+}
+
+fn failure(exit_code: i32, error: impl std::fmt::Display) -> GeminiError {
+    GeminiError::Transport {
+        exit_code,
+        stderr: error.to_string(),
+    }
+}
+
+/// Where a request goes, taken apart from its URL.
+#[derive(Debug)]
+struct Target {
+    tls: bool,
+    /// Host and port as the URL has them, for the `Host` header.
+    authority: String,
+    host: String,
+    port: u16,
+    /// Path and query.
+    path: String,
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Self, GeminiError> {
+        let invalid = || GeminiError::HttpError(format!("Unsupported URL: {url}"));
+        let (tls, rest) = match url.split_once("://") {
+            Some(("https", rest)) => (true, rest),
+            Some(("http", rest)) => (false, rest),
+            _ => return Err(invalid()),
+        };
+        let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+        let (host, port) = match authority.rsplit_once(':') {
+            // Not the inside of an IPv6 address
+            Some((host, port)) if !port.ends_with(']') => (host, port.parse().map_err(|_| invalid())?),
+            _ => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            tls,
+            authority: authority.to_owned(),
+            host: host.trim_start_matches('[').trim_end_matches(']').to_owned(),
+            port,
+            path: match path.starts_with('/') {
+                true => path.to_owned(),
+                false => format!("/{path}"),
+            },
+        })
+    }
+
+    /// What connections are pooled by.
+    fn origin(&self) -> String {
+        let scheme = if self.tls { "https" } else { "http" };
+        format!("{scheme}://{}:{}", self.host, self.port)
+    }
+}
+
+#[derive(Debug)]
+enum Connection {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Connection {
+    fn socket(&self) -> &TcpStream {
+        match self {
+            Connection::Plain(socket) => socket,
+            Connection::Tls(stream) => &stream.sock,
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(socket) => socket.read(buf),
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(socket) => socket.write(buf),
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(socket) => socket.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// How far a response has been decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Head,
+    /// A body of so many more bytes.
+    Length(u64),
+    /// A body ending with the connection.
+    Close,
+    /// A chunked body, ahead of the size line of a chunk.
+    ChunkSize,
+    /// A chunked body, within a chunk of so many more bytes and its CRLF.
+    Chunk(u64),
+    /// A chunked body, after its last chunk.
+    Trailers,
+    Done,
+}
+
+/// A response read off a pooled connection. The head is handed out as is and
+/// the body decoded, the way `curl -D -` prints them. Read to the end, the
+/// connection goes back to the pool; dropped before, it is closed.
+#[derive(Debug)]
+struct PooledExchange {
+    pool: Arc<Pool>,
+    target: Target,
+    /// Kept to be sent again should a pooled connection turn out closed.
+    request: Vec<u8>,
+    /// `None` once the exchange ended.
+    connection: Option<Connection>,
+    /// Whether the connection came from the pool.
+    reused: bool,
+    /// Whether anything was read off the connection.
+    received: bool,
+    /// Read off the connection but not yet decoded.
+    raw: Vec<u8>,
+    /// Decoded but not yet handed out.
+    out: Vec<u8>,
+    state: State,
+    /// Whether the server lets the connection be reused.
+    keep: bool,
+    error: Option<GeminiError>,
+}
+
+impl PooledExchange {
+    fn write(&mut self) -> Result<(), GeminiError> {
+        let connection = self.connection.as_mut().expect("a request is written on a connection");
+        match connection.write_all(&self.request).and_then(|()| connection.flush()) {
+            Ok(()) => Ok(()),
+            Err(_) if self.reused => self.resend(),
+            Err(e) => Err(failure(SEND, e)),
+        }
+    }
+
+    /// Sends the request again on a new connection, the pooled one having
+    /// been closed by the server before it answered. Requests are not sent a
+    /// third time.
+    fn resend(&mut self) -> Result<(), GeminiError> {
+        self.reused = false;
+        self.connection = Some(self.pool.connect(&self.target)?);
+        self.write()
+    }
+
+    /// Reads more of the response, waiting up to `timeout` if there is one.
+    /// `false` when that ran out first.
+    fn receive(&mut self, timeout: Option<Duration>) -> Result<bool, GeminiError> {
+        let connection = self.connection.as_mut().expect("a response is read off a connection");
+        // A zero timeout is refused rather than taken as none
+        let timeout = timeout.map(|timeout| timeout.max(Duration::from_millis(1)));
+        connection.socket().set_read_timeout(timeout).map_err(|e| failure(RECV, e))?;
+
+        let mut chunk = [0; 8192];
+        let read = match connection.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => return Ok(false),
+            Err(e) if e.kind() == ErrorKind::Interrupted => return Ok(true),
+            // Closed without a TLS close_notify, as many servers do
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => 0,
+            Err(_) if self.reused && !self.received => return self.resend().map(|()| true),
+            Err(e) => return Err(failure(RECV, e)),
+        };
+        if read == 0 {
+            return match self.state {
+                State::Close => {
+                    self.state = State::Done;
+                    Ok(true)
+                }
+                _ if self.reused && !self.received => self.resend().map(|()| true),
+                _ if !self.received => Err(failure(EMPTY, "Empty reply from server")),
+                _ => Err(failure(PARTIAL, "Connection closed before the end of the response")),
+            };
+        }
+
+        self.received = true;
+        self.raw.extend_from_slice(&chunk[..read]);
+        self.decode()?;
+        Ok(true)
+    }
+
+    /// Moves as much of `raw` to `out` as the framing of the response allows.
+    fn decode(&mut self) -> Result<(), GeminiError> {
+        loop {
+            match self.state {
+                State::Head => {
+                    let Some(end) = find(&self.raw, b"\r\n\r\n") else {
+                        return Ok(());
+                    };
+                    let head: Vec<u8> = self.raw.drain(..end + 4).collect();
+                    let (status, state, keep) = framing(&head)?;
+                    // Interim responses come ahead of the one to the request
+                    if (100..200).contains(&status) {
+                        continue;
+                    }
+                    self.out.extend_from_slice(&head);
+                    self.state = state;
+                    self.keep = keep;
+                }
+                State::Length(0) => self.state = State::Done,
+                State::Length(left) => {
+                    let left = left - self.take(left);
+                    self.state = State::Length(left);
+                    if left > 0 {
+                        return Ok(());
+                    }
+                }
+                State::Close => {
+                    self.out.append(&mut self.raw);
+                    return Ok(());
+                }
+                State::ChunkSize => {
+                    let Some(end) = find(&self.raw, b"\r\n") else {
+                        return Ok(());
+                    };
+                    let line = String::from_utf8_lossy(&self.raw[..end]).into_owned();
+                    // Chunk extensions after a `;` are of no use here
+                    let size = line
+                        .split(';')
+                        .next()
+                        .and_then(|size| u64::from_str_radix(size.trim(), 16).ok())
+                        .ok_or_else(|| GeminiError::HttpError(format!("Malformed chunk size: {line}")))?;
+                    self.raw.drain(..end + 2);
+                    self.state = match size {
+                        0 => State::Trailers,
+                        size => State::Chunk(size),
+                    };
+                }
+                State::Chunk(0) => {
+                    if self.raw.len() < 2 {
+                        return Ok(());
+                    }
+                    self.raw.drain(..2);
+                    self.state = State::ChunkSize;
+                }
+                State::Chunk(left) => {
+                    let left = left - self.take(left);
+                    self.state = State::Chunk(left);
+                    if left > 0 {
+                        return Ok(());
+                    }
+                }
+                State::Trailers => {
+                    let Some(end) = find(&self.raw, b"\r\n") else {
+                        return Ok(());
+                    };
+                    self.raw.drain(..end + 2);
+                    if end == 0 {
+                        self.state = State::Done;
+                    }
+                }
+                State::Done => return Ok(()),
+            }
+        }
+    }
+
+    /// Moves up to `left` bytes of the body from `raw` to `out`.
+    fn take(&mut self, left: u64) -> u64 {
+        let taken = left.min(self.raw.len() as u64);
+        self.out.extend(self.raw.drain(..taken as usize));
+        taken
+    }
+
+    /// Like [`Exchange::read_timeout`], waiting as long as it takes without a
+    /// timeout. Failures end the response early, for `finish` to report.
+    fn read_within(&mut self, buf: &mut [u8], timeout: Option<Duration>) -> Option<usize> {
+        loop {
+            if !self.out.is_empty() {
+                let read = buf.len().min(self.out.len());
+                buf[..read].copy_from_slice(&self.out[..read]);
+                self.out.drain(..read);
+                return Some(read);
+            }
+            if self.state == State::Done
+                && let Some(connection) = self.connection.take()
+                && self.keep
+                && self.raw.is_empty()
+            {
+                self.pool.put(self.target.origin(), connection);
+            }
+            if self.connection.is_none() {
+                return Some(0);
+            }
+            match self.receive(timeout) {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    self.error = Some(e);
+                    self.connection = None;
+                }
+            }
+        }
+    }
+}
+
+impl Read for PooledExchange {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(self.read_within(buf, None).unwrap_or(0))
+    }
+}
+
+impl Exchange for PooledExchange {
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        Ok(self.read_within(buf, Some(timeout)))
+    }
+
+    fn cancel(&mut self) {
+        self.connection = None;
+    }
+
+    fn finish(&mut self) -> Result<(), GeminiError> {
+        // Skip whatever of the response is left, handing the connection back
+        let mut rest = [0; 4096];
+        while self.read_within(&mut rest, None).unwrap_or(0) > 0 {}
+        self.error.clone().map_or(Ok(()), Err)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// The status of a response head, the framing of the body after it, and
+/// whether the connection can be reused after that.
+fn framing(head: &[u8]) -> Result<(u16, State, bool), GeminiError> {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or_default();
+    let mut parts = status_line.split(' ');
+    let version = parts.next().unwrap_or_default();
+    let status: u16 = parts
+        .next()
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| GeminiError::HttpError(format!("Malformed status line: {status_line}")))?;
+
+    let mut state = State::Close;
+    let mut keep = version == "HTTP/1.1";
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "transfer-encoding" if value.to_ascii_lowercase().contains("chunked") => state = State::ChunkSize,
+            // The chunks tell the length when both are given
+            "content-length" if state != State::ChunkSize => {
+                let length = value
+                    .parse()
+                    .map_err(|_| GeminiError::HttpError(format!("Malformed Content-Length: {value}")))?;
+                state = State::Length(length);
+            }
+            "connection" if value.eq_ignore_ascii_case("close") => keep = false,
+            _ => {}
+        }
+    }
+    if matches!(status, 100..200 | 204 | 304) {
+        state = State::Length(0);
+    }
+    Ok((status, state, keep && state != State::Close))
+}
+
+/// A request of a batch from a [`PooledTransport`], sent once its response is
+/// first read. By then the responses before it were read, handing their
+/// connection back to the pool for it to take. One dropped unread is never
+/// sent.
+struct Deferred {
+    transport: PooledTransport,
+    url: String,
+    body: String,
+    sent: Option<Result<PooledExchange, GeminiError>>,
+}
+
+impl Deferred {
+    fn exchange(&mut self) -> Result<&mut PooledExchange, GeminiError> {
+        let Self {
+            transport,
+            url,
+            body,
+            sent,
+        } = self;
+        sent.get_or_insert_with(|| transport.send("POST", url, Some(body), false))
+            .as_mut()
+            .map_err(|e| e.clone())
+    }
+}
+
+impl Read for Deferred {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.exchange() {
+            Ok(exchange) => exchange.read(buf),
+            // For `finish` to report
+            Err(_) => Ok(0),
+        }
+    }
+}
+
+impl Exchange for Deferred {
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        match self.exchange() {
+            Ok(exchange) => exchange.read_timeout(buf, timeout),
+            Err(_) => Ok(Some(0)),
+        }
+    }
+
+    fn cancel(&mut self) {
+        if let Some(Ok(exchange)) = &mut self.sent {
+            exchange.cancel();
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), GeminiError> {
+        self.exchange()?.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn test_stalled_stream_is_killed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n";
+            socket.write_all(head.as_bytes()).unwrap();
+            socket.write_all(b"[{\"candidates\": [").unwrap();
+            // Stall until the client hangs up
+            let _ = io::copy(&mut socket, &mut io::sink());
+        });
+
+        let mut exchange = CurlTransport::new().curl(&url, Some("{}"), true).unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0; 256];
+        while !received.ends_with(b"[") {
+            let read = exchange.read_timeout(&mut buf, Duration::from_secs(5)).unwrap().unwrap();
+            received.extend_from_slice(&buf[..read]);
+        }
+
+        let started = Instant::now();
+        assert_eq!(exchange.read_timeout(&mut buf, Duration::from_millis(100)).unwrap(), None);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        exchange.cancel();
+        assert!(exchange.child.try_wait().unwrap().is_some());
+        server.join().unwrap();
+    }
+
+    /// Serves HTTP/1.1 with keep-alive, echoing the request bodies, and counts
+    /// the connections it accepts.
+    fn echo_server() -> (String, Arc<AtomicUsize>) {
+        echo_server_closing_after(usize::MAX)
+    }
+
+    /// Like [`echo_server`], but hangs up after `answers` responses on a
+    /// connection without saying so, like a server dropping idle ones.
+    fn echo_server_closing_after(answers: usize) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/echo", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        thread::spawn(move || {
+            for socket in listener.incoming() {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                let mut reader = BufReader::new(socket.unwrap());
+                thread::spawn(move || {
+                    for _ in 0..answers {
+                        let mut len = None;
+                        let mut line = String::new();
+                        while line != "\r\n" {
+                            line.clear();
+                            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                                return;
+                            }
+                            let lower = line.to_ascii_lowercase();
+                            if let Some(value) = lower.strip_prefix("content-length:") {
+                                len = value.trim().parse().ok();
+                            }
+                        }
+                        let mut body = vec![0; len.unwrap_or(0)];
+                        reader.read_exact(&mut body).unwrap();
+                        let head = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", body.len());
+                        let socket = reader.get_mut();
+                        socket.write_all(head.as_bytes()).unwrap();
+                        socket.write_all(&body).unwrap();
+                    }
+                });
+            }
+        });
+        (url, connections)
+    }
+
+    fn bodies(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("{{\"request\": {i}}}")).collect()
+    }
+
+    #[test]
+    fn test_batch_reuses_connection() {
+        let (url, connections) = echo_server();
+        let transport = CurlTransport::new().with_keepalive(Duration::from_secs(5));
+        let bodies = bodies(5);
+
+        let exchanges = transport.post_batch(&url, &bodies).unwrap();
+        for (body, mut exchange) in bodies.iter().zip(exchanges) {
+            let mut raw = String::new();
+            exchange.read_to_string(&mut raw).unwrap();
+            exchange.finish().unwrap();
+            assert!(raw.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(raw.ends_with(&format!("\r\n\r\n{body}")));
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            transport.stats(),
+            PoolStats {
+                requests: 5,
+                connections: 1
+            }
+        );
+        assert_eq!(transport.stats().reused(), 4);
+    }
+
+    #[test]
+    fn test_batch_skips_unread() {
+        let (url, _) = echo_server();
+        let bodies = bodies(3);
+        let mut exchanges = CurlTransport::new().post_batch(&url, &bodies).unwrap();
+        let mut last = exchanges.pop().unwrap();
+        drop(exchanges);
+
+        let mut raw = String::new();
+        last.read_to_string(&mut raw).unwrap();
+        last.finish().unwrap();
+        assert!(raw.ends_with(&bodies[2]));
+        assert!(!raw.contains(&bodies[1]));
+    }
+
+    #[test]
+    fn test_batch_failure() {
+        // Nothing listens on the port once the listener is gone
+        let url = format!("http://{}/", TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap());
+        let transport = CurlTransport::new();
+        for mut exchange in transport.post_batch(&url, &bodies(2)).unwrap() {
+            let mut raw = Vec::new();
+            exchange.read_to_end(&mut raw).unwrap();
+            assert!(raw.is_empty());
+            // Couldn't connect
+            assert!(matches!(exchange.finish(), Err(GeminiError::Transport { exit_code: 7, .. })));
+        }
+        assert_eq!(transport.stats().connections, 0);
+    }
+
+    #[test]
+    fn test_large_body_through_stdin() {
+        let (url, _) = echo_server();
+        // Over the kernel limit on one argument, and read as a file name by -d
+        let body = format!("@{}", "x".repeat(200 * 1024));
+        let mut exchange = CurlTransport::new().post(&url, &body, false).unwrap();
+        let mut raw = String::new();
+        exchange.read_to_string(&mut raw).unwrap();
+        exchange.finish().unwrap();
+        assert!(raw.ends_with(&format!("\r\n\r\n{body}")));
+    }
+
+    #[test]
+    fn test_batch_bodies_are_removed() {
+        let bodies = bodies(2);
+        let files = BodyFiles::write(&bodies, 0).unwrap();
+        assert_eq!(fs::read_to_string(files.path(1)).unwrap(), bodies[1]);
+
+        let dir = files.dir.clone();
+        drop(files);
+        assert!(!dir.exists());
+    }
+
+    /// Reads a response to the end and checks it echoed `body`.
+    fn echoed(mut exchange: Box<dyn Exchange>, body: &str) {
+        let mut raw = String::new();
+        exchange.read_to_string(&mut raw).unwrap();
+        exchange.finish().unwrap();
+        assert!(raw.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(raw.ends_with(&format!("\r\n\r\n{body}")), "{raw}");
+    }
+
+    #[test]
+    fn test_pool_reuses_connection() {
+        let (url, connections) = echo_server();
+        let transport = PooledTransport::new();
+        let clone = transport.clone();
+
+        // Separate calls, on separate handles sharing the pool
+        for (i, body) in bodies(5).iter().enumerate() {
+            let transport = if i % 2 == 0 { &transport } else { &clone };
+            echoed(transport.post(&url, body, false).unwrap(), body);
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            transport.stats(),
+            PoolStats {
+                requests: 5,
+                connections: 1
+            }
+        );
+        assert_eq!(clone.stats().reused(), 4);
+    }
+
+    #[test]
+    fn test_pool_batch_reuses_connection() {
+        let (url, connections) = echo_server();
+        let transport = PooledTransport::new();
+        let bodies = bodies(3);
+        for (body, exchange) in bodies.iter().zip(transport.post_batch(&url, &bodies).unwrap()) {
+            echoed(exchange, body);
+        }
+        echoed(transport.post(&url, "{}", false).unwrap(), "{}");
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+        assert_eq!(transport.stats().reused(), 3);
+    }
+
+    #[test]
+    fn test_pool_unread_response_closes_connection() {
+        let (url, connections) = echo_server();
+        let transport = PooledTransport::new();
+        let mut exchange = transport.post(&url, "{}", false).unwrap();
+        exchange.read_exact(&mut [0; 4]).unwrap();
+        drop(exchange);
+
+        echoed(transport.post(&url, "{}", false).unwrap(), "{}");
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_pool_resends_on_closed_connection() {
+        let (url, connections) = echo_server_closing_after(1);
+        let transport = PooledTransport::new();
+        for body in bodies(3) {
+            echoed(transport.post(&url, &body, false).unwrap(), &body);
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 3);
+        assert_eq!(transport.stats().reused(), 0);
+    }
+
+    #[test]
+    fn test_pool_expires_idle_connections() {
+        let (url, connections) = echo_server();
+        let transport = PooledTransport::new().with_idle(Duration::ZERO);
+        for body in bodies(2) {
+            echoed(transport.post(&url, &body, false).unwrap(), &body);
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_pool_decodes_chunked_body() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket);
+            for _ in 0..2 {
+                let mut line = String::new();
+                while line != "\r\n" {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                }
+                let response = concat!(
+                    "HTTP/1.1 100 Continue\r\n\r\n",
+                    "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n",
+                    "7;ext=1\r\nHello, \r\n5\r\nworld\r\n0\r\nTrailer: 1\r\n\r\n",
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let transport = PooledTransport::new();
+        for _ in 0..2 {
+            let mut raw = String::new();
+            let mut exchange = transport.get(&url).unwrap();
+            exchange.read_to_string(&mut raw).unwrap();
+            exchange.finish().unwrap();
+            assert_eq!(raw, "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nHello, world");
+        }
+        assert_eq!(transport.stats().reused(), 1);
+    }
+
+    #[test]
+    fn test_pool_stalled_stream() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let head = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n";
+            socket.write_all(head.as_bytes()).unwrap();
+            socket.write_all(b"[{\"candidates\": [").unwrap();
+            let _ = io::copy(&mut socket, &mut io::sink());
+        });
+
+        let mut exchange = PooledTransport::new().post(&url, "{}", true).unwrap();
+        let mut received = Vec::new();
+        let mut buf = [0; 256];
+        while !received.ends_with(b"[") {
+            let read = exchange.read_timeout(&mut buf, Duration::from_secs(5)).unwrap().unwrap();
+            received.extend_from_slice(&buf[..read]);
+        }
+
+        let started = Instant::now();
+        assert_eq!(exchange.read_timeout(&mut buf, Duration::from_millis(100)).unwrap(), None);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        // Hanging up ends the server
+        exchange.cancel();
+        server.join().unwrap();
+        assert_eq!(exchange.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_pool_failures() {
+        let url = format!("http://{}/", TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap());
+        let transport = PooledTransport::new();
+        assert!(matches!(
+            transport.post(&url, "{}", false),
+            Err(GeminiError::Transport { exit_code: 7, .. })
+        ));
+        for mut exchange in transport.post_batch(&url, &bodies(2)).unwrap() {
+            assert_eq!(exchange.read(&mut [0; 16]).unwrap(), 0);
+            assert!(matches!(exchange.finish(), Err(GeminiError::Transport { exit_code: 7, .. })));
+        }
+        assert_eq!(transport.stats().connections, 0);
+
+        // Cut off mid-body
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket);
+            let mut line = String::new();
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+            let response = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhalf";
+            reader.get_mut().write_all(response).unwrap();
+        });
+        let mut exchange = transport.get(&url).unwrap();
+        let mut raw = Vec::new();
+        exchange.read_to_end(&mut raw).unwrap();
+        assert!(raw.ends_with(b"half"));
+        assert!(matches!(exchange.finish(), Err(GeminiError::Transport { exit_code: 18, .. })));
+
+        assert!(matches!(transport.get("ftp://host/"), Err(GeminiError::HttpError(_))));
+    }
+}
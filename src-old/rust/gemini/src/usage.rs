@@ -0,0 +1,153 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use crate::UsageMetadata;
+
+/// Tokens spent on one model, summed over calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModelUsage {
+    pub calls: u64,
+    pub prompt_tokens: u64,
+    pub candidate_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl ModelUsage {
+    fn add(&mut self, other: &ModelUsage) {
+        self.calls += other.calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.candidate_tokens += other.candidate_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+impl From<UsageMetadata> for ModelUsage {
+    fn from(usage: UsageMetadata) -> Self {
+        let count = |tokens: Option<i32>| tokens.unwrap_or(0).max(0) as u64;
+        Self {
+            calls: 1,
+            prompt_tokens: count(usage.prompt_token_count),
+            candidate_tokens: count(usage.candidates_token_count),
+            total_tokens: count(usage.total_token_count),
+        }
+    }
+}
+
+impl fmt::Display for ModelUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} calls, {} prompt + {} candidate tokens ({} total)",
+            self.calls, self.prompt_tokens, self.candidate_tokens, self.total_tokens
+        )
+    }
+}
+
+/// Rates per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// Prices by model id, supplied by the user since they change over time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PriceTable {
+    prices: HashMap<String, Price>,
+    fallback: Option<Price>,
+}
+
+impl PriceTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_price(mut self, model: &str, price: Price) -> Self {
+        self.prices.insert(model.to_string(), price);
+        self
+    }
+
+    /// Price of models missing from the table.
+    pub fn with_fallback(mut self, price: Price) -> Self {
+        self.fallback = Some(price);
+        self
+    }
+
+    pub fn price(&self, model: &str) -> Option<Price> {
+        self.prices.get(model).copied().or(self.fallback)
+    }
+}
+
+/// Result of [`UsageTracker::estimate_cost`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CostEstimate {
+    pub total: f64,
+    /// Models with usage but no price and no fallback, left out of the total.
+    pub unpriced: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct Tally {
+    last: Option<UsageMetadata>,
+    models: BTreeMap<String, ModelUsage>,
+}
+
+/// Token usage of every call made by the clients it is shared with. Clones
+/// share the same tally.
+#[derive(Debug, Clone, Default)]
+pub struct UsageTracker(Arc<Mutex<Tally>>);
+
+impl UsageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn record(&self, model: &str, usage: UsageMetadata) {
+        let mut tally = self.0.lock().unwrap();
+        tally.last = Some(usage);
+        tally
+            .models
+            .entry(model.to_string())
+            .or_default()
+            .add(&usage.into());
+    }
+
+    /// Usage reported by the most recent recorded response.
+    pub fn last(&self) -> Option<UsageMetadata> {
+        self.0.lock().unwrap().last
+    }
+
+    pub fn per_model(&self) -> BTreeMap<String, ModelUsage> {
+        self.0.lock().unwrap().models.clone()
+    }
+
+    /// Usage summed over all models.
+    pub fn total(&self) -> ModelUsage {
+        let mut total = ModelUsage::default();
+        for usage in self.0.lock().unwrap().models.values() {
+            total.add(usage);
+        }
+        total
+    }
+
+    pub fn reset(&self) {
+        *self.0.lock().unwrap() = Tally::default();
+    }
+
+    /// Cost of the usage so far: prompt tokens at the input rate, candidate
+    /// tokens at the output rate.
+    pub fn estimate_cost(&self, prices: &PriceTable) -> CostEstimate {
+        let mut estimate = CostEstimate::default();
+        for (model, usage) in self.per_model() {
+            let Some(price) = prices.price(&model) else {
+                estimate.unpriced.push(model);
+                continue;
+            };
+            estimate.total += (usage.prompt_tokens as f64 * price.input
+                + usage.candidate_tokens as f64 * price.output)
+                / 1_000_000.0;
+        }
+        estimate
+    }
+}
@@ -0,0 +1,187 @@
+use core::hash::Hasher;
+
+/// Castagnoli polynomial, reflected.
+const POLY: u32 = 0x82F63B78;
+
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Incremental CRC-32C. Uses the SSE4.2 or ARMv8 CRC instructions when the
+/// cpu supports them and a table driven fallback otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32c {
+    state: u32,
+}
+
+impl Crc32c {
+    pub fn new() -> Self {
+        Self { state: !0 }
+    }
+
+    pub fn update(&mut self, input: &[u8]) {
+        self.state = match hardware::update(self.state, input) {
+            Some(state) => state,
+            None => software(self.state, input),
+        };
+    }
+
+    pub fn finalize(&self) -> u32 {
+        !self.state
+    }
+}
+
+impl Default for Crc32c {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher for Crc32c {
+    fn write(&mut self, bytes: &[u8]) {
+        self.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.finalize() as u64
+    }
+}
+
+/// One-shot CRC-32C of `input`.
+pub fn crc32c(input: &[u8]) -> u32 {
+    let mut crc = Crc32c::new();
+    crc.update(input);
+    crc.finalize()
+}
+
+fn software(mut state: u32, input: &[u8]) -> u32 {
+    for &byte in input {
+        state = TABLE[((state ^ byte as u32) & 0xFF) as usize] ^ (state >> 8);
+    }
+    state
+}
+
+#[cfg(target_arch = "x86_64")]
+mod hardware {
+    use core::arch::x86_64::{_mm_crc32_u8, _mm_crc32_u64};
+
+    pub(super) fn update(state: u32, input: &[u8]) -> Option<u32> {
+        // SAFETY: only called once the cpu reported SSE4.2
+        supported().then(|| unsafe { crc(state, input) })
+    }
+
+    fn supported() -> bool {
+        #[cfg(feature = "std")]
+        return std::is_x86_feature_detected!("sse4.2");
+        #[cfg(not(feature = "std"))]
+        return cfg!(target_feature = "sse4.2");
+    }
+
+    #[target_feature(enable = "sse4.2")]
+    unsafe fn crc(state: u32, input: &[u8]) -> u32 {
+        let mut state = state as u64;
+        let mut words = input.chunks_exact(8);
+        for word in &mut words {
+            state = _mm_crc32_u64(state, u64::from_le_bytes(word.try_into().unwrap()));
+        }
+        let mut state = state as u32;
+        for &byte in words.remainder() {
+            state = _mm_crc32_u8(state, byte);
+        }
+        state
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod hardware {
+    use core::arch::aarch64::{__crc32cb, __crc32cd};
+
+    pub(super) fn update(state: u32, input: &[u8]) -> Option<u32> {
+        // SAFETY: only called once the cpu reported the CRC extension
+        supported().then(|| unsafe { crc(state, input) })
+    }
+
+    fn supported() -> bool {
+        #[cfg(feature = "std")]
+        return std::arch::is_aarch64_feature_detected!("crc");
+        #[cfg(not(feature = "std"))]
+        return cfg!(target_feature = "crc");
+    }
+
+    #[target_feature(enable = "crc")]
+    unsafe fn crc(mut state: u32, input: &[u8]) -> u32 {
+        let mut words = input.chunks_exact(8);
+        for word in &mut words {
+            state = __crc32cd(state, u64::from_le_bytes(word.try_into().unwrap()));
+        }
+        for &byte in words.remainder() {
+            state = __crc32cb(state, byte);
+        }
+        state
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod hardware {
+    pub(super) fn update(_: u32, _: &[u8]) -> Option<u32> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_vectors() {
+        // RFC 3720 appendix B.4
+        assert_eq!(crc32c(b"123456789"), 0xE3069283);
+        assert_eq!(crc32c(&[0; 32]), 0x8A9136AA);
+        assert_eq!(crc32c(&[0xFF; 32]), 0x62A8AB43);
+        let ascending: Vec<u8> = (0..32).collect();
+        assert_eq!(crc32c(&ascending), 0x46DD794E);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn test_hardware_matches_software() {
+        let mut x = 0x2545F4914F6CDD1Du64;
+        for len in 0..300 {
+            let input: Vec<u8> = (0..len)
+                .map(|_| {
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    x as u8
+                })
+                .collect();
+            let soft = !software(!0, &input);
+            if let Some(hard) = hardware::update(!0, &input) {
+                assert_eq!(!hard, soft, "length {len}");
+            }
+            assert_eq!(crc32c(&input), soft);
+        }
+    }
+
+    #[test]
+    fn test_incremental() {
+        let mut crc = Crc32c::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finalize(), 0xE3069283);
+        assert_eq!(crc.finish(), 0xE3069283);
+    }
+}
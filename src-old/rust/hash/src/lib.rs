@@ -0,0 +1,30 @@
+//! Checksums and hashes shared across crates.
+//!
+//! - [`Xxh64`]: fast non-cryptographic hashing for cache keys and ETags.
+//! - [`Crc32c`]: integrity checks, hardware accelerated where available.
+//! - [`Sha256`]: integrity and signing where collisions must be infeasible.
+#![no_std]
+
+#[cfg(any(feature = "std", test))]
+extern crate std;
+
+mod crc32c;
+mod sha256;
+mod xxhash;
+
+pub use crc32c::{Crc32c, crc32c};
+pub use sha256::{Sha256, sha256};
+pub use xxhash::{BuildXxh64, Xxh64, xxhash64};
+
+/// Lowercase hex encoding of a digest.
+pub fn hex(bytes: &[u8]) -> impl core::fmt::Display + '_ {
+    struct Hex<'a>(&'a [u8]);
+
+    impl core::fmt::Display for Hex<'_> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+        }
+    }
+
+    Hex(bytes)
+}
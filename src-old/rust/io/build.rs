@@ -22,6 +22,8 @@ fn main() {
         source: source_dir,
         target:  PathBuf::from(&out_dir),
         external_prompt: None,
+        post_processors: vec![],
+        ..Default::default()
     };
 
     let out = Output {
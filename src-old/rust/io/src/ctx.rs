@@ -79,7 +79,7 @@ impl Context {
                         handle: unsafe {
                             match comp.op.handle.cast::<io::HandleType>().read() {
                                 io::HandleType::File => crate::Handle::File(
-                                    crate::file::File::from_raw(comp.op.handle as *mut _),
+                                    crate::file::RawFile::from_raw(comp.op.handle as *mut _),
                                 ),
                                 io::HandleType::Socket => crate::Handle::Socket(
                                     crate::net::Socket::from_raw(comp.op.handle as *mut _),
@@ -1,20 +1,267 @@
-use std::ptr;
+//! Files that are read and written without blocking the worker.
+//!
+//! Regular files are always ready as far as epoll is concerned, so every
+//! operation runs on the runtime's blocking pool, using positional reads
+//! where it can so concurrent reads of one file do not fight over a cursor.
+//! This is the backend on every platform until the io_uring context in
+//! [`RawFile`] can complete operations on its own.
 
-use crate::bindings::file as ffi;
+use std::{
+    fs::{self, Metadata, OpenOptions},
+    io::{self, Write},
+    panic,
+    path::Path,
+    ptr,
+    sync::Arc,
+};
+
+use io_sys::types as ffi;
 use crate::raw;
+use crate::rt::{JoinError, spawn_blocking};
+
+/// Runs `f` on the blocking pool, passing its panics on to the caller.
+async fn blocking<T, F>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    match spawn_blocking(f).await {
+        Ok(result) => result,
+        Err(JoinError::Panicked(payload)) => panic::resume_unwind(payload),
+        Err(error @ JoinError::Cancelled) => Err(io::Error::other(error.to_string())),
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
 
 pub struct File {
+    file: Arc<fs::File>,
+}
+
+impl File {
+    /// Opens the file at `path` for reading.
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<File> {
+        Self::open_with(path, OpenOptions::new().read(true)).await
+    }
+
+    /// Creates the file at `path` for writing, truncating it if it exists.
+    pub async fn create(path: impl AsRef<Path>) -> io::Result<File> {
+        Self::open_with(
+            path,
+            OpenOptions::new().write(true).create(true).truncate(true),
+        )
+        .await
+    }
+
+    /// Opens the file at `path` with `options`. Errors keep their kind, and
+    /// name the path.
+    pub async fn open_with(path: impl AsRef<Path>, options: &OpenOptions) -> io::Result<File> {
+        let path = path.as_ref().to_owned();
+        let options = options.clone();
+        let file = blocking(move || {
+            options.open(&path).map_err(|error| {
+                io::Error::new(error.kind(), format!("{}: {error}", path.display()))
+            })
+        })
+        .await?;
+        Ok(File {
+            file: Arc::new(file),
+        })
+    }
+
+    /// Reads the whole file, from the start.
+    pub async fn read_to_vec(&self) -> io::Result<Vec<u8>> {
+        let file = self.file.clone();
+        blocking(move || {
+            let mut buf = Vec::with_capacity(file.metadata()?.len() as usize);
+            let mut chunk = vec![0; 64 * 1024];
+            loop {
+                match read_at(&file, &mut chunk, buf.len() as u64) {
+                    Ok(0) => return Ok(buf),
+                    Ok(read) => buf.extend_from_slice(&chunk[..read]),
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                    Err(error) => return Err(error),
+                }
+            }
+        })
+        .await
+    }
+
+    /// Reads into `buf` from `offset`, returning how many bytes were read,
+    /// which is less than `buf.len()` only at the end of the file.
+    pub async fn read_at(&self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let file = self.file.clone();
+        let len = buf.len();
+        let chunk = blocking(move || {
+            let mut chunk = vec![0; len];
+            let mut read = 0;
+            while read < len {
+                match read_at(&file, &mut chunk[read..], offset + read as u64) {
+                    Ok(0) => break,
+                    Ok(n) => read += n,
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                    Err(error) => return Err(error),
+                }
+            }
+            chunk.truncate(read);
+            Ok(chunk)
+        })
+        .await?;
+        buf[..chunk.len()].copy_from_slice(&chunk);
+        Ok(chunk.len())
+    }
+
+    /// Writes all of `buf` at the cursor, which starts at the beginning of
+    /// the file, or its end when opened for appending.
+    pub async fn write_all(&self, buf: &[u8]) -> io::Result<()> {
+        let file = self.file.clone();
+        let buf = buf.to_vec();
+        blocking(move || (&*file).write_all(&buf)).await
+    }
+
+    /// Flushes written data and metadata to the disk.
+    pub async fn sync(&self) -> io::Result<()> {
+        let file = self.file.clone();
+        blocking(move || file.sync_all()).await
+    }
+
+    pub async fn metadata(&self) -> io::Result<Metadata> {
+        let file = self.file.clone();
+        blocking(move || file.metadata()).await
+    }
+}
+
+/// File handle of the native io context.
+pub struct RawFile {
     handle: *mut ffi::File,
 }
 
-impl Default for File {
+impl Default for RawFile {
     fn default() -> Self {
-        File {
+        RawFile {
             handle: ptr::null_mut(),
         }
     }
 }
 
-raw!(File, *mut ffi::File);
+raw!(RawFile, *mut ffi::File);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rt::{block_on, interval, spawn, tests::runtime},
+        time::{Duration, Instant, Millis},
+    };
+    use std::{
+        env,
+        sync::atomic::{AtomicBool, Ordering::*},
+    };
+
+    const SIZE: usize = 10 * 1024 * 1024;
+    const CHUNK: usize = 1024 * 1024;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        env::temp_dir().join(format!("io-file-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn test_read_back_while_timers_tick() {
+        runtime();
+        let path = temp_path("read-back");
+        let data = (0..SIZE).map(|i| (i * 31 % 251) as u8).collect::<Vec<_>>();
+
+        let written = data.clone();
+        let write_path = path.clone();
+        block_on(spawn(async move {
+            let file = File::create(&write_path).await?;
+            for chunk in written.chunks(CHUNK) {
+                file.write_all(chunk).await?;
+            }
+            file.sync().await
+        }))
+        .unwrap()
+        .unwrap();
 
-impl File {}
+        let done = Arc::new(AtomicBool::new(false));
+        let ticker = {
+            let done = done.clone();
+            spawn(async move {
+                let mut interval = interval(Duration::new(Millis(5)));
+                let mut last = Instant::now();
+                let mut longest = Duration::INSTANT;
+                while !done.load(SeqCst) {
+                    interval.tick().await;
+                    longest = longest.max(last.elapsed());
+                    last = Instant::now();
+                }
+                longest
+            })
+        };
+
+        let file = Arc::new(block_on(File::open(&path)).unwrap());
+        let readers = (0..SIZE / CHUNK)
+            .map(|index| {
+                let file = file.clone();
+                spawn(async move {
+                    let mut chunk = vec![0; CHUNK];
+                    let read = file.read_at((index * CHUNK) as u64, &mut chunk).await?;
+                    assert_eq!(read, CHUNK);
+                    io::Result::Ok((index, chunk))
+                })
+            })
+            .collect::<Vec<_>>();
+        for reader in readers {
+            let (index, chunk) = block_on(reader).unwrap().unwrap();
+            assert!(
+                chunk == data[index * CHUNK..][..CHUNK],
+                "chunk {index} differs"
+            );
+        }
+        assert!(block_on(file.read_to_vec()).unwrap() == data);
+        assert_eq!(block_on(file.metadata()).unwrap().len(), SIZE as u64);
+
+        done.store(true, SeqCst);
+        let longest = block_on(ticker).unwrap();
+        assert!(
+            longest < Duration::new(Millis(100)),
+            "timer stalled for {longest}"
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_file() {
+        runtime();
+        let path = temp_path("missing");
+        let error = block_on(File::open(&path)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains(&*path.to_string_lossy()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_permission_denied() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // Permissions do not apply to root
+        if unsafe { libc::geteuid() } == 0 {
+            return;
+        }
+        runtime();
+        let path = temp_path("denied");
+        fs::write(&path, b"secret").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o200)).unwrap();
+        let error = block_on(File::open(&path)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -5,7 +5,7 @@ pub mod net;
 pub mod time;
 
 pub enum Handle {
-    File(file::File),
+    File(file::RawFile),
     Socket(net::Socket),
     Listener(net::Listener),
     Connection(net::Connection),
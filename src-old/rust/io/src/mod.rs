@@ -2,7 +2,7 @@ pub mod file;
 pub mod net;
 
 pub enum Handle {
-    File(file::File),
+    File(file::RawFile),
     Socket(net::Socket),
     Listener(net::Listener),
     Connection(net::Connection),
@@ -69,8 +69,8 @@ mod future {
     macro_rules! stall {
         ($handle:ident, $op:ident, $ffi:expr) => {{
             *$op = 0u64;
-            let ret = unsafe { $ffi };
-            if let Err(err) = ret.check_operation() {
+            let ret: bool = unsafe { $ffi };
+            if !ret {
                 panic!("error");
             }
             (crate::io::future::Stall {
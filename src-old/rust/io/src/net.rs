@@ -1,6 +1,12 @@
 use crate::raw;
+use crate::rt::reactor::{Interest, Registration};
+use crate::rt::timeout;
+use crate::time::{Duration, TimeUnit};
 use std::{
-    net::{Ipv6Addr, SocketAddr, ToSocketAddrs},
+    io::{self, Read, Write},
+    mem,
+    net::{self as std_net, Ipv6Addr, Shutdown, SocketAddr, ToSocketAddrs},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
     pin::Pin,
     ptr,
 };
@@ -13,7 +19,7 @@ use super::OperationId;
 macro_rules! socket_struct {
     ($name:ident) => {
         pub struct $name {
-            handle: *mut Socket,
+            handle: *mut ty::Socket,
             indicator: Pin<Box<u64>>,
         }
 
@@ -71,3 +77,313 @@ impl Socket {
         OperationId(*self.indicator)
     }
 }
+
+/// TCP socket listening for connections, accepted without blocking the
+/// worker.
+pub struct TcpListener {
+    // Deregisters before the socket is closed
+    registration: Registration,
+    listener: std_net::TcpListener,
+    nodelay: bool,
+}
+
+impl TcpListener {
+    /// Binds to the first of `addrs` that works. Names are resolved on the
+    /// calling thread.
+    pub async fn bind(addrs: impl ToSocketAddrs) -> io::Result<TcpListener> {
+        let listener = std_net::TcpListener::bind(addrs)?;
+        listener.set_nonblocking(true)?;
+        Ok(TcpListener {
+            registration: Registration::new(listener.as_raw_fd())?,
+            listener,
+            nodelay: false,
+        })
+    }
+
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        let (stream, addr) = self
+            .registration
+            .io(Interest::Read, || self.listener.accept())
+            .await?;
+        let stream = TcpStream::from_std(stream)?;
+        stream.set_nodelay(self.nodelay)?;
+        Ok((stream, addr))
+    }
+
+    /// Sets `TCP_NODELAY` on the streams accepted from now on.
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.nodelay = nodelay;
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+}
+
+/// Connected TCP socket. Reads and writes take `&self`, so one task can
+/// read while another writes.
+pub struct TcpStream {
+    // Deregisters before the socket is closed
+    registration: Registration,
+    stream: std_net::TcpStream,
+}
+
+impl TcpStream {
+    fn from_std(stream: std_net::TcpStream) -> io::Result<TcpStream> {
+        stream.set_nonblocking(true)?;
+        Ok(TcpStream {
+            registration: Registration::new(stream.as_raw_fd())?,
+            stream,
+        })
+    }
+
+    /// Connects to the first of `addrs` that accepts. Names are resolved on
+    /// the calling thread.
+    pub async fn connect(addrs: impl ToSocketAddrs) -> io::Result<TcpStream> {
+        let mut last = None;
+        for addr in addrs.to_socket_addrs()? {
+            match Self::connect_addr(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(error) => last = Some(error),
+            }
+        }
+        Err(last.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no addresses to connect to")
+        }))
+    }
+
+    /// [`connect`](Self::connect), giving up after `limit`.
+    pub async fn connect_timeout<U: TimeUnit>(
+        addrs: impl ToSocketAddrs,
+        limit: Duration<U>,
+    ) -> io::Result<TcpStream> {
+        timeout(limit, Self::connect(addrs))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))?
+    }
+
+    async fn connect_addr(addr: SocketAddr) -> io::Result<TcpStream> {
+        let domain = match addr {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        let socket = cvt(unsafe { libc::socket(domain, libc::SOCK_STREAM, 0) })?;
+        let socket = unsafe { OwnedFd::from_raw_fd(socket) };
+        let fd = socket.as_raw_fd();
+        cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+        let stream = std_net::TcpStream::from(socket);
+        stream.set_nonblocking(true)?;
+        let stream = TcpStream {
+            registration: Registration::new(fd)?,
+            stream,
+        };
+
+        let (storage, len) = sockaddr(addr);
+        match cvt(unsafe { libc::connect(fd, (&raw const storage).cast(), len) }) {
+            Ok(_) => return Ok(stream),
+            Err(error) if error.raw_os_error() == Some(libc::EINPROGRESS) => {}
+            Err(error) => return Err(error),
+        }
+        // Writable once the handshake is done, either way
+        stream
+            .registration
+            .io(Interest::Write, || {
+                if let Some(error) = stream.stream.take_error()? {
+                    return Err(error);
+                }
+                match stream.stream.peer_addr() {
+                    Ok(_) => Ok(()),
+                    Err(error) if error.kind() == io::ErrorKind::NotConnected => {
+                        Err(io::ErrorKind::WouldBlock.into())
+                    }
+                    Err(error) => Err(error),
+                }
+            })
+            .await?;
+        Ok(stream)
+    }
+
+    /// Reads into `buf`, returning 0 once the peer shut down writing.
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.registration
+            .io(Interest::Read, || (&self.stream).read(buf))
+            .await
+    }
+
+    pub async fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.registration
+            .io(Interest::Write, || (&self.stream).write(buf))
+            .await
+    }
+
+    pub async fn write_all(&self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf).await? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                written => buf = &buf[written..],
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.stream.shutdown(how)
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.stream.set_nodelay(nodelay)
+    }
+
+    pub fn nodelay(&self) -> io::Result<bool> {
+        self.stream.nodelay()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+fn sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage = unsafe { mem::zeroed::<libc::sockaddr_storage>() };
+    let len = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe { &mut *(&raw mut storage).cast::<libc::sockaddr_in>() };
+            sin.sin_family = libc::AF_INET as _;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe { &mut *(&raw mut storage).cast::<libc::sockaddr_in6>() };
+            sin6.sin6_family = libc::AF_INET6 as _;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    (storage, len as libc::socklen_t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::{block_on, spawn, tests::runtime};
+    use crate::time::Millis;
+
+    const CONNECTIONS: usize = 1000;
+    const CLIENTS: usize = 50;
+
+    /// Two sockets per connection, on both ends.
+    fn raise_fd_limit() {
+        let mut limit = unsafe { mem::zeroed::<libc::rlimit>() };
+        unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+        let wanted = (4 * CONNECTIONS as libc::rlim_t).min(limit.rlim_max);
+        if limit.rlim_cur < wanted {
+            limit.rlim_cur = wanted;
+            unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) };
+        }
+    }
+
+    async fn echo(stream: TcpStream) -> io::Result<()> {
+        let mut buf = vec![0; 4096];
+        loop {
+            match stream.read(&mut buf).await? {
+                0 => return stream.shutdown(Shutdown::Write).await,
+                read => stream.write_all(&buf[..read]).await?,
+            }
+        }
+    }
+
+    #[test]
+    fn test_echo_thousand_connections() {
+        runtime();
+        raise_fd_limit();
+        let mut listener = block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+        listener.set_nodelay(true);
+        let addr = listener.local_addr().unwrap();
+        let server = spawn(async move {
+            let mut echoes = vec![];
+            for _ in 0..CONNECTIONS {
+                let (stream, _) = listener.accept().await?;
+                assert!(stream.nodelay()?);
+                echoes.push(spawn(echo(stream)));
+            }
+            for echo in echoes {
+                echo.await.unwrap()?;
+            }
+            io::Result::Ok(())
+        });
+
+        // Every client opens its share of connections before using any, so
+        // all of them are open at once
+        let clients = (0..CLIENTS)
+            .map(|client| {
+                spawn(async move {
+                    let mut streams = vec![];
+                    for _ in 0..CONNECTIONS / CLIENTS {
+                        streams.push(TcpStream::connect(addr).await?);
+                    }
+                    for (index, stream) in streams.iter().enumerate() {
+                        let message = format!("client {client} stream {index} ").repeat(100);
+                        stream.write_all(message.as_bytes()).await?;
+                        stream.shutdown(Shutdown::Write).await?;
+                        let mut echoed = vec![];
+                        let mut buf = [0; 1024];
+                        loop {
+                            match stream.read(&mut buf).await? {
+                                0 => break,
+                                read => echoed.extend_from_slice(&buf[..read]),
+                            }
+                        }
+                        assert!(echoed == message.as_bytes(), "stream {index} of {client}");
+                    }
+                    io::Result::Ok(streams.len())
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut connected = 0;
+        for client in clients {
+            connected += block_on(client).unwrap().unwrap();
+        }
+        assert_eq!(connected, CONNECTIONS);
+        block_on(server).unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_connect_errors() {
+        runtime();
+        // Nothing listens on a port that was just released
+        let addr = std_net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let error = block_on(spawn(TcpStream::connect(addr)))
+            .unwrap()
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+
+        let error = block_on(spawn(TcpStream::connect_timeout(
+            addr,
+            Duration::new(Millis(1000)),
+        )))
+        .unwrap()
+        .err()
+        .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -78,28 +78,43 @@ pub fn vector_constants(input: TokenStream) -> TokenStream {
     expanded.into()
 }
 
+/// Swizzle components of a vector, each alphabet naming lanes 0 to 3.
+const ALPHABETS: [[char; 4]; 2] = [['x', 'y', 'z', 'w'], ['r', 'g', 'b', 'a']];
+
+/// Every pattern of 1 to 4 components over `alphabet`, with the lanes they
+/// name.
+fn patterns(alphabet: [char; 4]) -> impl Iterator<Item = (String, Vec<usize>)> {
+    (1..=4).flat_map(move |len| {
+        std::iter::repeat_n(alphabet.into_iter().zip(0..4), len)
+            .multi_cartesian_product()
+            .map(|perm| perm.into_iter().unzip())
+    })
+}
+
+/// `Pattern` markers for every swizzle, e.g. `Zyx` with the mask `[2, 1, 0]`.
+///
+/// Given the vector type, e.g. `swizzle!(Vector)`, also generates accessors
+/// on `Vector<N, T>(Simd([T; N]))`: getters like `v.zyx()` and, for patterns
+/// naming each lane once, setters like `v.set_xz(other)`, under both xyzw and
+/// rgba names. Lanes past `N` are ruled out by `Lanes<N>: Lane<I>` bounds, so
+/// `.w()` on a two-lane vector fails to compile.
 #[proc_macro]
-pub fn swizzle(_: TokenStream) -> TokenStream {
-    let pattern = |swizzle: [char; 4]| {
-        (1..=4).flat_map(move |len| {
-            let components = swizzle.iter().enumerate().map(|(i, &c)| (c, i as u32));
-
-            std::iter::repeat_n(components, len)
-                .multi_cartesian_product()
-                .collect::<Vec<_>>()
-        })
+pub fn swizzle(input: TokenStream) -> TokenStream {
+    let vec_type = match input.is_empty() {
+        true => None,
+        false => Some(parse_macro_input!(input as Type)),
     };
 
-    let patterns = (pattern)(['x', 'y', 'z', 'w'])
-        .chain((pattern)(['r', 'g', 'b', 'a']))
-        .map(|perm| {
-            let (chars, indices): (Vec<_>, Vec<_>) = perm.into_iter().unzip();
-            let pattern: String = chars.into_iter().collect();
+    let markers = ALPHABETS
+        .into_iter()
+        .flat_map(patterns)
+        .map(|(pattern, indices)| {
             let name = Ident::new(
                 &(pattern[..1].to_uppercase() + &pattern[1..]),
                 Span::call_site().into(),
             );
             let len = indices.len();
+            let indices = indices.iter().map(|&index| index as u32);
 
             quote! {
                 pub struct #name;
@@ -110,8 +125,66 @@ pub fn swizzle(_: TokenStream) -> TokenStream {
             }
         });
 
+    let accessors = vec_type.map(|vec_type| {
+        let lanes = (1..=4usize)
+            .flat_map(|n| (0..n).map(move |lane| quote! { impl Lane<#lane> for Lanes<#n> {} }));
+        let methods = ALPHABETS
+            .into_iter()
+            .flat_map(patterns)
+            .map(|(pattern, indices)| {
+                let getter = format_ident!("{}", pattern);
+                let setter = format_ident!("set_{}", pattern);
+                let len = indices.len();
+                let bounds = indices.iter().unique().map(|lane| quote! { Lane<#lane> });
+                let where_clause = quote! { where Lanes<N>: #(#bounds)+* };
+
+                let get = match len {
+                    1 => quote! {
+                        pub fn #getter(self) -> T #where_clause {
+                            self.0.0[#(#indices)*]
+                        }
+                    },
+                    _ => quote! {
+                        pub fn #getter(self) -> #vec_type<#len, T> #where_clause {
+                            #vec_type(Simd([#(self.0.0[#indices]),*]))
+                        }
+                    },
+                };
+                let set = match len {
+                    _ if !indices.iter().all_unique() => quote! {},
+                    1 => quote! {
+                        pub fn #setter(&mut self, value: T) #where_clause {
+                            self.0.0[#(#indices)*] = value;
+                        }
+                    },
+                    _ => {
+                        let from = 0..len;
+                        quote! {
+                            pub fn #setter(&mut self, other: #vec_type<#len, T>) #where_clause {
+                                #(self.0.0[#indices] = other.0.0[#from];)*
+                            }
+                        }
+                    }
+                };
+                quote! { #get #set }
+            });
+
+        quote! {
+            /// The lane count of a vector, implementing `Lane<I>` for every
+            /// lane `I` it has.
+            pub struct Lanes<const N: usize>;
+            pub trait Lane<const I: usize> {}
+            #(#lanes)*
+
+            impl<const N: usize, T: Copy> #vec_type<N, T> {
+                #(#methods)*
+            }
+        }
+    });
+
     quote! {
-        #(#patterns)*
+        #(#markers)*
+        #accessors
     }
     .into()
 }
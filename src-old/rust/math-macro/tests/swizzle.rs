@@ -0,0 +1,86 @@
+use math_macro::swizzle;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Simd<T, const N: usize>(pub [T; N]);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vector<const N: usize, T>(pub Simd<T, N>);
+
+pub trait Pattern {
+    type Indices;
+    const MASK: Self::Indices;
+}
+
+swizzle!(Vector);
+
+fn vector<const N: usize>(lanes: [u32; N]) -> Vector<N, u32> {
+    Vector(Simd(lanes))
+}
+
+#[test]
+fn test_getters() {
+    let v2 = vector([1, 2]);
+    assert_eq!((v2.x(), v2.y()), (1, 2));
+    assert_eq!(v2.yx(), vector([2, 1]));
+    assert_eq!(v2.xxyy(), vector([1, 1, 2, 2]));
+
+    let v3 = vector([1, 2, 3]);
+    assert_eq!(v3.z(), 3);
+    assert_eq!(v3.xy(), vector([1, 2]));
+    assert_eq!(v3.zyx(), vector([3, 2, 1]));
+    assert_eq!(v3.zzzx(), vector([3, 3, 3, 1]));
+
+    let v4 = vector([1, 2, 3, 4]);
+    assert_eq!(v4.w(), 4);
+    assert_eq!(v4.wzyx(), vector([4, 3, 2, 1]));
+    assert_eq!(v4.xw(), vector([1, 4]));
+    assert_eq!(v4.ywy(), vector([2, 4, 2]));
+}
+
+#[test]
+fn test_every_lane_of_every_length() {
+    let v4 = vector([1, 2, 3, 4]);
+    assert_eq!([v4.x(), v4.y(), v4.z(), v4.w()], [1, 2, 3, 4]);
+    assert_eq!(
+        [v4.xx(), v4.yy(), v4.zz(), v4.ww()],
+        [1, 2, 3, 4].map(|lane| vector([lane; 2]))
+    );
+    assert_eq!(
+        [v4.xxx(), v4.yyy(), v4.zzz(), v4.www()],
+        [1, 2, 3, 4].map(|lane| vector([lane; 3]))
+    );
+    assert_eq!(
+        [v4.xxxx(), v4.yyyy(), v4.zzzz(), v4.wwww()],
+        [1, 2, 3, 4].map(|lane| vector([lane; 4]))
+    );
+}
+
+#[test]
+fn test_rgba_aliases() {
+    let color = vector([10, 20, 30, 40]);
+    assert_eq!(color.a(), color.w());
+    assert_eq!(color.bgra(), color.zyxw());
+    assert_eq!(vector([10, 20, 30]).rgb(), vector([10, 20, 30]));
+}
+
+#[test]
+fn test_setters() {
+    let mut v = vector([1, 2, 3, 4]);
+    v.set_xz(vector([10, 30]));
+    assert_eq!(v, vector([10, 2, 30, 4]));
+    v.set_wzyx(vector([1, 2, 3, 4]));
+    assert_eq!(v, vector([4, 3, 2, 1]));
+    v.set_y(7);
+    assert_eq!(v, vector([4, 7, 2, 1]));
+
+    let mut color = vector([0, 0, 0]);
+    color.set_bg(vector([1, 2]));
+    assert_eq!(color, vector([0, 2, 1]));
+}
+
+#[test]
+fn test_pattern_masks() {
+    assert_eq!(Zyx::MASK, [2, 1, 0]);
+    assert_eq!(Rgba::MASK, [0, 1, 2, 3]);
+    assert_eq!(W::MASK, [3]);
+}
@@ -0,0 +1,6 @@
+/// Swizzles of lanes a vector does not have.
+#[test]
+fn ui() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/lane_out_of_range.rs");
+}
@@ -0,0 +1,15 @@
+use math_macro::swizzle;
+
+pub struct Simd<T, const N: usize>(pub [T; N]);
+pub struct Vector<const N: usize, T>(pub Simd<T, N>);
+
+pub trait Pattern {
+    type Indices;
+    const MASK: Self::Indices;
+}
+
+swizzle!(Vector);
+
+fn main() {
+    Vector(Simd([1.0, 2.0])).w();
+}
@@ -1 +1,7 @@
+pub mod matrix;
+pub mod quaternion;
+pub mod vector;
 
+pub use matrix::Matrix;
+pub use quaternion::Quaternion;
+pub use vector::{Mask, Simd, Vector};
@@ -0,0 +1,323 @@
+//! Matrices of [`Vector`] columns.
+
+use std::ops::{Add, AddAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign};
+
+use num_traits::{Float, One, Zero};
+
+use super::vector::{Element, Vector};
+
+/// `R` rows by `C` columns, stored column-major as the GPU takes them. Each
+/// column is a [`Vector`], so products go through its SIMD lanes a column at
+/// a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Matrix<const R: usize, const C: usize, T>(pub [Vector<R, T>; C]);
+
+impl<const R: usize, const C: usize, T: Copy> Matrix<R, C, T> {
+    /// From the lanes of each column.
+    pub fn new(cols: [[T; R]; C]) -> Self {
+        Self(cols.map(Vector::new))
+    }
+
+    pub const fn from_cols(cols: [Vector<R, T>; C]) -> Self {
+        Self(cols)
+    }
+
+    pub fn from_rows(rows: [[T; C]; R]) -> Self {
+        Matrix::<C, R, T>::new(rows).transpose()
+    }
+
+    pub fn col(&self, col: usize) -> Vector<R, T> {
+        self.0[col]
+    }
+
+    pub fn row(&self, row: usize) -> Vector<C, T> {
+        Vector::new(self.0.map(|col| col[row]))
+    }
+
+    pub fn transpose(self) -> Matrix<C, R, T> {
+        Matrix(std::array::from_fn(|row| self.row(row)))
+    }
+}
+
+impl<const N: usize, T: Element + Zero + One> Matrix<N, N, T> {
+    pub fn identity() -> Self {
+        Self(std::array::from_fn(|col| {
+            let mut lanes = Vector::splat(T::zero());
+            lanes[col] = T::one();
+            lanes
+        }))
+    }
+}
+
+impl<T: Element + Float> Matrix<2, 2, T> {
+    pub fn determinant(&self) -> T {
+        self[(0, 0)] * self[(1, 1)] - self[(0, 1)] * self[(1, 0)]
+    }
+
+    /// `None` for a singular matrix.
+    pub fn inverse(&self) -> Option<Self> {
+        let det = self.determinant();
+        let adjugate = Self::from_rows([
+            [self[(1, 1)], -self[(0, 1)]],
+            [-self[(1, 0)], self[(0, 0)]],
+        ]);
+        scaled(adjugate, det)
+    }
+}
+
+impl<T: Element + Float> Matrix<3, 3, T> {
+    pub fn determinant(&self) -> T {
+        let [a, b, c] = self.0;
+        a.dot(b.cross(c))
+    }
+
+    /// `None` for a singular matrix.
+    pub fn inverse(&self) -> Option<Self> {
+        let [a, b, c] = self.0;
+        // The rows of the adjugate are perpendicular to two of the columns
+        let adjugate = Self([b.cross(c), c.cross(a), a.cross(b)]).transpose();
+        scaled(adjugate, a.dot(b.cross(c)))
+    }
+}
+
+impl<T: Element + Float> Matrix<4, 4, T> {
+    /// The columns split into their first three lanes and their last, for
+    /// the products of [`determinant`](Self::determinant) and
+    /// [`inverse`](Self::inverse), after Lengyel's "Foundations of Game
+    /// Engine Development".
+    fn split(&self) -> ([Vector<3, T>; 4], Vector<4, T>) {
+        (self.0.map(|col| col.xyz()), self.row(3))
+    }
+
+    pub fn determinant(&self) -> T {
+        let ([a, b, c, d], bottom) = self.split();
+        let [x, y, z, w] = bottom.to_array();
+        let s = a.cross(b);
+        let t = c.cross(d);
+        let u = a * y - b * x;
+        let v = c * w - d * z;
+        s.dot(v) + t.dot(u)
+    }
+
+    /// `None` for a singular matrix.
+    pub fn inverse(&self) -> Option<Self> {
+        let ([a, b, c, d], bottom) = self.split();
+        let [x, y, z, w] = bottom.to_array();
+        let s = a.cross(b);
+        let t = c.cross(d);
+        let u = a * y - b * x;
+        let v = c * w - d * z;
+
+        let r0 = b.cross(v) + t * y;
+        let r1 = v.cross(a) - t * x;
+        let r2 = d.cross(u) + s * w;
+        let r3 = u.cross(c) - s * z;
+        let adjugate = Self::from_rows([
+            [r0[0], r0[1], r0[2], -b.dot(t)],
+            [r1[0], r1[1], r1[2], a.dot(t)],
+            [r2[0], r2[1], r2[2], -d.dot(s)],
+            [r3[0], r3[1], r3[2], c.dot(s)],
+        ]);
+        scaled(adjugate, s.dot(v) + t.dot(u))
+    }
+
+    /// Transforms a position, which translations move.
+    pub fn transform_point(&self, point: Vector<3, T>) -> Vector<3, T> {
+        (*self * Vector::new([point[0], point[1], point[2], T::one()])).xyz()
+    }
+
+    /// Transforms a direction, which translations leave as is.
+    pub fn transform_vector(&self, vector: Vector<3, T>) -> Vector<3, T> {
+        (*self * Vector::new([vector[0], vector[1], vector[2], T::zero()])).xyz()
+    }
+}
+
+/// The inverse from the adjugate and the determinant, if there is one.
+fn scaled<const N: usize, T: Element + Float>(adjugate: Matrix<N, N, T>, det: T) -> Option<Matrix<N, N, T>> {
+    (det != T::zero() && det.is_finite()).then(|| adjugate * det.recip())
+}
+
+impl<const R: usize, const C: usize, T> Index<(usize, usize)> for Matrix<R, C, T> {
+    type Output = T;
+
+    /// The lane at `(row, col)`.
+    fn index(&self, (row, col): (usize, usize)) -> &T {
+        &self.0[col][row]
+    }
+}
+
+impl<const R: usize, const C: usize, T> IndexMut<(usize, usize)> for Matrix<R, C, T> {
+    fn index_mut(&mut self, (row, col): (usize, usize)) -> &mut T {
+        &mut self.0[col][row]
+    }
+}
+
+impl<const R: usize, const C: usize, T: Element + Add<Output = T>> Add for Matrix<R, C, T> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|col| self.0[col] + rhs.0[col]))
+    }
+}
+
+impl<const R: usize, const C: usize, T: Element + Sub<Output = T>> Sub for Matrix<R, C, T> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|col| self.0[col] - rhs.0[col]))
+    }
+}
+
+impl<const R: usize, const C: usize, T: Element + Add<Output = T>> AddAssign for Matrix<R, C, T> {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl<const R: usize, const C: usize, T: Element + Sub<Output = T>> SubAssign for Matrix<R, C, T> {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl<const R: usize, const C: usize, T: Element + Neg<Output = T>> Neg for Matrix<R, C, T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(self.0.map(Neg::neg))
+    }
+}
+
+impl<const R: usize, const C: usize, T: Element + Mul<Output = T>> Mul<T> for Matrix<R, C, T> {
+    type Output = Self;
+
+    fn mul(self, rhs: T) -> Self {
+        Self(self.0.map(|col| col * rhs))
+    }
+}
+
+impl<const R: usize, const C: usize, T> Mul<Vector<C, T>> for Matrix<R, C, T>
+where
+    T: Element + Add<Output = T> + Mul<Output = T>,
+{
+    type Output = Vector<R, T>;
+
+    /// The columns weighted by the lanes of `rhs`, summed.
+    fn mul(self, rhs: Vector<C, T>) -> Vector<R, T> {
+        (1..C).map(|col| self.0[col] * rhs[col]).fold(self.0[0] * rhs[0], Add::add)
+    }
+}
+
+impl<const R: usize, const N: usize, const C: usize, T> Mul<Matrix<N, C, T>> for Matrix<R, N, T>
+where
+    T: Element + Add<Output = T> + Mul<Output = T>,
+{
+    type Output = Matrix<R, C, T>;
+
+    fn mul(self, rhs: Matrix<N, C, T>) -> Matrix<R, C, T> {
+        Matrix(rhs.0.map(|col| self * col))
+    }
+}
+
+impl<const N: usize, T> MulAssign for Matrix<N, N, T>
+where
+    T: Element + Add<Output = T> + Mul<Output = T>,
+{
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::{Pcg, Random, Standard};
+
+    /// Whether every lane of `a` is within `epsilon` of that of `b`.
+    fn close<const R: usize, const C: usize>(a: Matrix<R, C, f64>, b: Matrix<R, C, f64>, epsilon: f64) -> bool {
+        (0..C).all(|col| (0..R).all(|row| (a[(row, col)] - b[(row, col)]).abs() <= epsilon))
+    }
+
+    #[test]
+    fn test_layout() {
+        let m = Matrix::from_rows([[1, 2, 3], [4, 5, 6]]);
+        assert_eq!(m, Matrix::new([[1, 4], [2, 5], [3, 6]]));
+        assert_eq!(m[(0, 2)], 3);
+        assert_eq!(m.row(1), Vector::new([4, 5, 6]));
+        assert_eq!(m.col(1), Vector::new([2, 5]));
+        assert_eq!(m.transpose(), Matrix::from_rows([[1, 4], [2, 5], [3, 6]]));
+        assert_eq!(m.transpose().transpose(), m);
+
+        let mut i = Matrix::<3, 3, i32>::identity();
+        assert_eq!(i, Matrix::from_rows([[1, 0, 0], [0, 1, 0], [0, 0, 1]]));
+        i[(2, 0)] = 7;
+        assert_eq!(i.col(0), Vector::new([1, 0, 7]));
+    }
+
+    #[test]
+    fn test_products() {
+        let a = Matrix::from_rows([[1, 2, 3], [4, 5, 6]]);
+        let b = Matrix::from_rows([[7, 8], [9, 10], [11, 12]]);
+        assert_eq!(a * b, Matrix::from_rows([[58, 64], [139, 154]]));
+        assert_eq!(a * Vector::new([1, 0, -1]), Vector::new([-2, -2]));
+        assert_eq!(a * Matrix::identity(), a);
+        assert_eq!(Matrix::identity() * a, a);
+        assert_eq!((a * 2 - a) + a, a * 2);
+        assert_eq!(-a, a * -1);
+
+        let mut c = Matrix::<2, 2, i32>::identity();
+        c *= a * b;
+        assert_eq!(c, a * b);
+    }
+
+    #[test]
+    fn test_transforms() {
+        let translate = Matrix::from_rows([
+            [1.0f32, 0.0, 0.0, 1.0],
+            [0.0, 1.0, 0.0, 2.0],
+            [0.0, 0.0, 1.0, 3.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let v = Vector::new([1.0, 1.0, 1.0]);
+        assert_eq!(translate.transform_point(v), Vector::new([2.0, 3.0, 4.0]));
+        assert_eq!(translate.transform_vector(v), v);
+        assert_eq!(translate * Vector::new([1.0, 1.0, 1.0, 1.0]), Vector::new([2.0, 3.0, 4.0, 1.0]));
+    }
+
+    #[test]
+    fn test_determinants() {
+        assert_eq!(Matrix::from_rows([[3.0f64, 8.0], [4.0, 6.0]]).determinant(), -14.0);
+        let m = Matrix::from_rows([[6.0f64, 1.0, 1.0], [4.0, -2.0, 5.0], [2.0, 8.0, 7.0]]);
+        assert_eq!(m.determinant(), -306.0);
+        let m = Matrix::from_rows([
+            [1.0f64, 0.0, 2.0, -1.0],
+            [3.0, 0.0, 0.0, 5.0],
+            [2.0, 1.0, 4.0, -3.0],
+            [1.0, 0.0, 5.0, 0.0],
+        ]);
+        assert_eq!(m.determinant(), 30.0);
+    }
+
+    #[test]
+    fn test_inverses() {
+        let mut rng = Pcg::<4>::new(Vector::splat(11));
+        for _ in 0..200 {
+            // Random lanes in -1..1 on top of a dominant diagonal keep the
+            // matrices well away from singular
+            let cols = [(); 4].map(|()| [(); 4].map(|()| rng.sample::<f64>(&Standard) * 2.0 - 1.0));
+            let m4 = Matrix::<4, 4, f64>::new(cols) + Matrix::identity() * 4.0;
+            assert!(close(m4.inverse().unwrap() * m4, Matrix::identity(), 1e-12), "{m4:?}");
+            assert!(close(m4 * m4.inverse().unwrap(), Matrix::identity(), 1e-12), "{m4:?}");
+
+            let m3 = Matrix::<3, 3, f64>::from_cols([m4.0[0].xyz(), m4.0[1].xyz(), m4.0[2].xyz()]);
+            assert!(close(m3.inverse().unwrap() * m3, Matrix::identity(), 1e-12), "{m3:?}");
+            let m2 = Matrix::<2, 2, f64>::from_cols([m4.0[0].xy(), m4.0[1].xy()]);
+            assert!(close(m2.inverse().unwrap() * m2, Matrix::identity(), 1e-12), "{m2:?}");
+        }
+
+        assert_eq!(Matrix::<2, 2, f32>::new([[1.0, 2.0], [2.0, 4.0]]).inverse(), None);
+        assert_eq!(Matrix::<3, 3, f32>::new([[1.0, 2.0, 3.0]; 3]).inverse(), None);
+        assert_eq!(Matrix::<4, 4, f32>::new([[0.0; 4]; 4]).inverse(), None);
+        assert_eq!(Matrix::<4, 4, f32>::identity().inverse(), Some(Matrix::identity()));
+    }
+}
@@ -0,0 +1,308 @@
+//! Rotations as unit quaternions.
+
+use std::ops::{Mul, MulAssign, Neg};
+
+use num_traits::Float;
+
+use super::{
+    matrix::Matrix,
+    vector::{Element, Vector},
+};
+
+/// `xi + yj + zk + w`, its lanes in that order. Rotations are the ones of
+/// length 1, which the constructors here return.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion<T>(pub Vector<4, T>);
+
+impl<T: Element + Float> Quaternion<T> {
+    pub fn new(x: T, y: T, z: T, w: T) -> Self {
+        Self(Vector::new([x, y, z, w]))
+    }
+
+    /// No rotation.
+    pub fn identity() -> Self {
+        Self::new(T::zero(), T::zero(), T::zero(), T::one())
+    }
+
+    /// Rotates by `angle` radians counterclockwise around the unit length
+    /// `axis`, looking down on it.
+    pub fn from_axis_angle(axis: Vector<3, T>, angle: T) -> Self {
+        let (sin, cos) = (angle / two::<T>()).sin_cos();
+        let [x, y, z] = (axis * sin).to_array();
+        Self::new(x, y, z, cos)
+    }
+
+    /// Rotates by `x` radians around the X axis, then `y` around Y, then `z`
+    /// around Z, the axes staying put.
+    pub fn from_euler(x: T, y: T, z: T) -> Self {
+        let axis = |lane: usize| {
+            let mut axis = Vector::splat(T::zero());
+            axis[lane] = T::one();
+            axis
+        };
+        Self::from_axis_angle(axis(2), z) * Self::from_axis_angle(axis(1), y) * Self::from_axis_angle(axis(0), x)
+    }
+
+    pub fn x(self) -> T {
+        self.0[0]
+    }
+
+    pub fn y(self) -> T {
+        self.0[1]
+    }
+
+    pub fn z(self) -> T {
+        self.0[2]
+    }
+
+    pub fn w(self) -> T {
+        self.0[3]
+    }
+
+    /// The vector part, `x`, `y` and `z`.
+    fn axis(self) -> Vector<3, T> {
+        self.0.xyz()
+    }
+
+    /// The opposite rotation, for a unit quaternion.
+    pub fn conjugate(self) -> Self {
+        Self::new(-self.x(), -self.y(), -self.z(), self.w())
+    }
+
+    pub fn dot(self, other: Self) -> T {
+        self.0.dot(other.0)
+    }
+
+    pub fn length(self) -> T {
+        self.0.length()
+    }
+
+    /// Scaled back to a length of 1, which products drift away from.
+    pub fn normalize(self) -> Self {
+        Self(self.0.normalize())
+    }
+
+    /// Rotates `v`.
+    pub fn rotate(self, v: Vector<3, T>) -> Vector<3, T> {
+        // v + 2w(u × v) + 2u × (u × v), u being the vector part
+        let u = self.axis();
+        let t = u.cross(v) * two::<T>();
+        v + t * self.w() + u.cross(t)
+    }
+
+    /// Rotates from `self` at `t` = 0 to `other` at `t` = 1 at a constant
+    /// angular speed, the short way around.
+    pub fn slerp(self, other: Self, t: T) -> Self {
+        let mut dot = self.dot(other);
+        // `other` and `-other` are the same rotation, one of them nearer
+        let other = match dot < T::zero() {
+            true => {
+                dot = -dot;
+                -other
+            }
+            false => other,
+        };
+        // Nearly the same, where the sine below would vanish
+        if dot > T::one() - T::epsilon() * T::from(16).unwrap() {
+            return Self(self.0.lerp(other.0, t)).normalize();
+        }
+
+        let theta = dot.acos();
+        let sin = theta.sin();
+        let from = ((T::one() - t) * theta).sin() / sin;
+        let to = (t * theta).sin() / sin;
+        Self(self.0 * from + other.0 * to)
+    }
+}
+
+fn two<T: Float>() -> T {
+    T::one() + T::one()
+}
+
+impl<T: Element + Float> Neg for Quaternion<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
+impl<T: Element + Float> Mul for Quaternion<T> {
+    type Output = Self;
+
+    /// Rotates by `rhs`, then by `self`.
+    fn mul(self, rhs: Self) -> Self {
+        let (a, b) = (self.axis(), rhs.axis());
+        let [x, y, z] = (b * self.w() + a * rhs.w() + a.cross(b)).to_array();
+        Self::new(x, y, z, self.w() * rhs.w() - a.dot(b))
+    }
+}
+
+impl<T: Element + Float> MulAssign for Quaternion<T> {
+    fn mul_assign(&mut self, rhs: Self) {
+        *self = *self * rhs;
+    }
+}
+
+impl<T: Element + Float> Mul<Vector<3, T>> for Quaternion<T> {
+    type Output = Vector<3, T>;
+
+    fn mul(self, rhs: Vector<3, T>) -> Vector<3, T> {
+        self.rotate(rhs)
+    }
+}
+
+impl<T: Element + Float> From<Quaternion<T>> for Matrix<3, 3, T> {
+    fn from(q: Quaternion<T>) -> Self {
+        let [x, y, z, w] = q.0.to_array();
+        let (one, two) = (T::one(), two::<T>());
+        Matrix::new([
+            [one - two * (y * y + z * z), two * (x * y + w * z), two * (x * z - w * y)],
+            [two * (x * y - w * z), one - two * (x * x + z * z), two * (y * z + w * x)],
+            [two * (x * z + w * y), two * (y * z - w * x), one - two * (x * x + y * y)],
+        ])
+    }
+}
+
+impl<T: Element + Float> From<Quaternion<T>> for Matrix<4, 4, T> {
+    fn from(q: Quaternion<T>) -> Self {
+        let rotation = Matrix::<3, 3, T>::from(q);
+        let mut m = Matrix::identity();
+        for col in 0..3 {
+            for row in 0..3 {
+                m[(row, col)] = rotation[(row, col)];
+            }
+        }
+        m
+    }
+}
+
+impl<T: Element + Float> From<Matrix<3, 3, T>> for Quaternion<T> {
+    /// The rotation of a matrix without scale or shear.
+    fn from(m: Matrix<3, 3, T>) -> Self {
+        // Divides by the largest of the four lanes, which can not be near 0
+        let quarter = (two::<T>() * two()).recip();
+        let trace = m[(0, 0)] + m[(1, 1)] + m[(2, 2)];
+        if trace > T::zero() {
+            let s = (trace + T::one()).sqrt() * two();
+            Self::new(
+                (m[(2, 1)] - m[(1, 2)]) / s,
+                (m[(0, 2)] - m[(2, 0)]) / s,
+                (m[(1, 0)] - m[(0, 1)]) / s,
+                s * quarter,
+            )
+        } else if m[(0, 0)] > m[(1, 1)] && m[(0, 0)] > m[(2, 2)] {
+            let s = (T::one() + m[(0, 0)] - m[(1, 1)] - m[(2, 2)]).sqrt() * two();
+            Self::new(
+                s * quarter,
+                (m[(0, 1)] + m[(1, 0)]) / s,
+                (m[(0, 2)] + m[(2, 0)]) / s,
+                (m[(2, 1)] - m[(1, 2)]) / s,
+            )
+        } else if m[(1, 1)] > m[(2, 2)] {
+            let s = (T::one() + m[(1, 1)] - m[(0, 0)] - m[(2, 2)]).sqrt() * two();
+            Self::new(
+                (m[(0, 1)] + m[(1, 0)]) / s,
+                s * quarter,
+                (m[(1, 2)] + m[(2, 1)]) / s,
+                (m[(0, 2)] - m[(2, 0)]) / s,
+            )
+        } else {
+            let s = (T::one() + m[(2, 2)] - m[(0, 0)] - m[(1, 1)]).sqrt() * two();
+            Self::new(
+                (m[(0, 2)] + m[(2, 0)]) / s,
+                (m[(1, 2)] + m[(2, 1)]) / s,
+                s * quarter,
+                (m[(1, 0)] - m[(0, 1)]) / s,
+            )
+        }
+    }
+}
+
+impl<T: Element + Float> From<Matrix<4, 4, T>> for Quaternion<T> {
+    /// The rotation of the upper left 3x3 of a matrix, which must have no
+    /// scale or shear.
+    fn from(m: Matrix<4, 4, T>) -> Self {
+        Matrix::from_cols([m.0[0].xyz(), m.0[1].xyz(), m.0[2].xyz()]).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+    use super::*;
+    use crate::rng::{Pcg, Random, Standard};
+
+    type Vec3 = Vector<3, f64>;
+
+    fn close<const N: usize>(a: Vector<N, f64>, b: Vector<N, f64>) -> bool {
+        (a - b).length() < 1e-12
+    }
+
+    /// Whether `a` and `b` are the same rotation, given one may be the
+    /// negation of the other.
+    fn same(a: Quaternion<f64>, b: Quaternion<f64>) -> bool {
+        close(a.0, b.0) || close(a.0, -b.0)
+    }
+
+    #[test]
+    fn test_rotations() {
+        let quarter = Quaternion::from_axis_angle(Vec3::Z, FRAC_PI_2);
+        assert!(close(quarter.rotate(Vec3::X), Vec3::Y));
+        assert!(close(quarter * Vec3::Y, -Vec3::X));
+        assert!(close(quarter.conjugate() * Vec3::Y, Vec3::X));
+        assert!(same(quarter * quarter, Quaternion::from_axis_angle(Vec3::Z, PI)));
+        assert!(same(quarter * quarter.conjugate(), Quaternion::identity()));
+        assert_eq!(Quaternion::<f64>::identity() * Vec3::X, Vec3::X);
+
+        // X first, then Y, then Z
+        let euler = Quaternion::from_euler(FRAC_PI_2, FRAC_PI_2, 0.0);
+        assert!(close(euler * Vec3::Y, Vec3::X));
+        let steps = Quaternion::from_axis_angle(Vec3::Y, FRAC_PI_2) * Quaternion::from_axis_angle(Vec3::X, FRAC_PI_2);
+        assert!(same(euler, steps));
+
+        let mut drifted = Quaternion(quarter.0 * 3.0);
+        assert!((drifted.length() - 3.0).abs() < 1e-12);
+        drifted = drifted.normalize();
+        drifted *= Quaternion::identity();
+        assert!(same(drifted, quarter));
+    }
+
+    #[test]
+    fn test_slerp() {
+        let from = Quaternion::<f64>::identity();
+        let to = Quaternion::from_axis_angle(Vec3::Z, FRAC_PI_2);
+        assert!(same(from.slerp(to, 0.0), from));
+        assert!(same(from.slerp(to, 1.0), to));
+        assert!(same(from.slerp(to, 0.5), Quaternion::from_axis_angle(Vec3::Z, FRAC_PI_4)));
+        // The short way, though `-to` is the far side of the sphere
+        assert!(same(from.slerp(-to, 0.5), Quaternion::from_axis_angle(Vec3::Z, FRAC_PI_4)));
+        assert!(same(to.slerp(to, 0.5), to));
+    }
+
+    #[test]
+    fn test_matrix_round_trips() {
+        let mut rng = Pcg::<4>::new(Vector::splat(5));
+        let mut angle = || (rng.sample::<f64>(&Standard) * 2.0 - 1.0) * PI;
+        for _ in 0..500 {
+            let q = Quaternion::from_euler(angle(), angle(), angle());
+
+            let m3 = Matrix::<3, 3, f64>::from(q);
+            let v = Vector::new([1.0, -2.0, 3.0]);
+            assert!(close(m3 * v, q * v), "{q:?}");
+            assert!(same(Quaternion::from(m3), q), "{q:?}");
+
+            let m4 = Matrix::<4, 4, f64>::from(q);
+            assert!(close(m4.transform_vector(v), q * v), "{q:?}");
+            assert_eq!(m4.row(3), Vector::new([0.0, 0.0, 0.0, 1.0]));
+            assert!(same(Quaternion::from(m4), q), "{q:?}");
+        }
+
+        // Half turns, where the trace is -1 and the other branches are taken
+        for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+            let q = Quaternion::from_axis_angle(axis, PI);
+            assert!(same(Quaternion::from(Matrix::<3, 3, f64>::from(q)), q));
+        }
+    }
+}
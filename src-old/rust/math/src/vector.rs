@@ -0,0 +1,937 @@
+//! Fixed size vectors of numbers, their arithmetic going through the SIMD
+//! intrinsics lane by lane.
+
+use std::{
+    array,
+    intrinsics::simd::*,
+    ops::{
+        Add, AddAssign, BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Div,
+        DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Not, Rem, RemAssign, Shl, ShlAssign, Shr,
+        ShrAssign, Sub, SubAssign,
+    },
+    ptr,
+};
+
+use math_macro::vector_constants;
+use num_traits::{Float, PrimInt, Signed};
+
+use shuffle::Pattern;
+use swizzle::{Yzx, Zxy};
+
+/// Lanes of a vector, stored as a plain array so each can be borrowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Simd<T, const N: usize>(pub [T; N]);
+
+/// [`Simd`] as the intrinsics take it. Its lanes can not be borrowed, so it
+/// only lives for the length of an operation.
+#[repr(simd)]
+struct Raw<T, const N: usize>([T; N]);
+
+// Derived, this would clone the array in place
+impl<T: Copy, const N: usize> Clone for Raw<T, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Copy, const N: usize> Copy for Raw<T, N> {}
+
+#[repr(simd)]
+struct Indices<const N: usize>([u32; N]);
+
+mod sealed {
+    use super::Vector;
+
+    /// Operations whose intrinsics differ between integer and float lanes.
+    pub trait Sealed: Sized {
+        fn abs<const N: usize>(v: Vector<N, Self>) -> Vector<N, Self>;
+        fn reduce_add<const N: usize>(v: Vector<N, Self>) -> Self;
+        fn reduce_min<const N: usize>(v: Vector<N, Self>) -> Self;
+        fn reduce_max<const N: usize>(v: Vector<N, Self>) -> Self;
+
+        // Whether the operators overflow in some lane, which floats never do
+        fn add_overflows<const N: usize>(a: Vector<N, Self>, b: Vector<N, Self>) -> bool;
+        fn sub_overflows<const N: usize>(a: Vector<N, Self>, b: Vector<N, Self>) -> bool;
+        fn mul_overflows<const N: usize>(a: Vector<N, Self>, b: Vector<N, Self>) -> bool;
+        fn neg_overflows<const N: usize>(v: Vector<N, Self>) -> bool;
+    }
+}
+
+/// Number the intrinsics can hold in a lane.
+pub trait Element: Copy + sealed::Sealed {}
+
+macro_rules! element {
+    ($lanes:ident => $($ty:ty),*) => {
+        $(
+            impl sealed::Sealed for $ty {
+                element!(@$lanes);
+            }
+            impl Element for $ty {}
+        )*
+    };
+    (@int) => {
+        fn abs<const N: usize>(v: Vector<N, Self>) -> Vector<N, Self> {
+            // `MIN` stays `MIN`, as with `wrapping_abs`
+            let negated = Vector::from_raw(unsafe { simd_sub(Vector::splat(0).raw(), v.raw()) });
+            Vector::select(v.lanes_lt(Vector::splat(0)), negated, v)
+        }
+
+        fn reduce_add<const N: usize>(v: Vector<N, Self>) -> Self {
+            unsafe { simd_reduce_add_ordered(v.raw(), 0) }
+        }
+
+        fn reduce_min<const N: usize>(v: Vector<N, Self>) -> Self {
+            unsafe { simd_reduce_min(v.raw()) }
+        }
+
+        fn reduce_max<const N: usize>(v: Vector<N, Self>) -> Self {
+            unsafe { simd_reduce_max(v.raw()) }
+        }
+
+        fn add_overflows<const N: usize>(a: Vector<N, Self>, b: Vector<N, Self>) -> bool {
+            a.checked_add(b).is_none()
+        }
+
+        fn sub_overflows<const N: usize>(a: Vector<N, Self>, b: Vector<N, Self>) -> bool {
+            a.checked_sub(b).is_none()
+        }
+
+        fn mul_overflows<const N: usize>(a: Vector<N, Self>, b: Vector<N, Self>) -> bool {
+            a.checked_mul(b).is_none()
+        }
+
+        fn neg_overflows<const N: usize>(v: Vector<N, Self>) -> bool {
+            Vector::splat(0).checked_sub(v).is_none()
+        }
+    };
+    (@float) => {
+        fn abs<const N: usize>(v: Vector<N, Self>) -> Vector<N, Self> {
+            Vector::from_raw(unsafe { simd_fabs(v.raw()) })
+        }
+
+        fn reduce_add<const N: usize>(v: Vector<N, Self>) -> Self {
+            // -0.0 so a sum of -0.0 lanes keeps its sign
+            unsafe { simd_reduce_add_ordered(v.raw(), -0.0) }
+        }
+
+        // The intrinsics only take integers here. Folding from NaN, which
+        // `min` and `max` pass over, leaves NaN only if every lane is NaN.
+        fn reduce_min<const N: usize>(v: Vector<N, Self>) -> Self {
+            v.to_array().into_iter().fold(Self::NAN, Self::min)
+        }
+
+        fn reduce_max<const N: usize>(v: Vector<N, Self>) -> Self {
+            v.to_array().into_iter().fold(Self::NAN, Self::max)
+        }
+
+        fn add_overflows<const N: usize>(_: Vector<N, Self>, _: Vector<N, Self>) -> bool {
+            false
+        }
+
+        fn sub_overflows<const N: usize>(_: Vector<N, Self>, _: Vector<N, Self>) -> bool {
+            false
+        }
+
+        fn mul_overflows<const N: usize>(_: Vector<N, Self>, _: Vector<N, Self>) -> bool {
+            false
+        }
+
+        fn neg_overflows<const N: usize>(_: Vector<N, Self>) -> bool {
+            false
+        }
+    };
+}
+
+element!(int => u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+element!(float => f32, f64);
+
+impl<T: Element, const N: usize> Simd<T, N> {
+    fn raw(self) -> Raw<T, N> {
+        Raw(self.0)
+    }
+
+    fn from_raw(raw: Raw<T, N>) -> Self {
+        // The lanes are laid out as the array, with padding after them
+        Self(unsafe { ptr::from_ref(&raw).cast::<[T; N]>().read() })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Vector<const N: usize, T>(pub Simd<T, N>);
+
+/// One flag per lane, as the comparisons on [`Vector`] return them. Each lane
+/// is all ones or all zeros, the form the intrinsics take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Mask<const N: usize>(Simd<i8, N>);
+
+impl<const N: usize> Mask<N> {
+    pub const fn splat(value: bool) -> Self {
+        Self(Simd([-(value as i8); N]))
+    }
+
+    pub fn from_array(lanes: [bool; N]) -> Self {
+        Self(Simd(lanes.map(|lane| -(lane as i8))))
+    }
+
+    pub fn to_array(self) -> [bool; N] {
+        self.0.0.map(|lane| lane != 0)
+    }
+
+    pub fn test(self, lane: usize) -> bool {
+        self.0.0[lane] != 0
+    }
+
+    pub fn set(&mut self, lane: usize, value: bool) {
+        self.0.0[lane] = -(value as i8);
+    }
+
+    /// Lane `i` from bit `i`, the bits above `N` being ignored.
+    pub fn from_bitmask(bits: u64) -> Self {
+        const { assert!(N <= 64, "a bitmask holds at most 64 lanes") };
+        Self::from_array(array::from_fn(|lane| bits >> lane & 1 != 0))
+    }
+
+    /// Bit `i` set for lane `i`.
+    pub fn to_bitmask(self) -> u64 {
+        const { assert!(N <= 64, "a bitmask holds at most 64 lanes") };
+        (0..N).fold(0, |bits, lane| bits | (self.test(lane) as u64) << lane)
+    }
+
+    pub fn any(self) -> bool {
+        unsafe { simd_reduce_any(self.0.raw()) }
+    }
+
+    pub fn all(self) -> bool {
+        unsafe { simd_reduce_all(self.0.raw()) }
+    }
+}
+
+impl<const N: usize> Not for Mask<N> {
+    type Output = Self;
+
+    fn not(self) -> Self {
+        self ^ Self::splat(true)
+    }
+}
+
+/// `Mask op Mask` and its assigning form.
+macro_rules! mask {
+    ($($op:ident $method:ident, $assign:ident $assign_method:ident => $intrinsic:ident;)*) => {
+        $(
+            impl<const N: usize> $op for Mask<N> {
+                type Output = Self;
+
+                fn $method(self, rhs: Self) -> Self {
+                    Self(Simd::from_raw(unsafe { $intrinsic(self.0.raw(), rhs.0.raw()) }))
+                }
+            }
+
+            impl<const N: usize> $assign for Mask<N> {
+                fn $assign_method(&mut self, rhs: Self) {
+                    *self = (*self).$method(rhs);
+                }
+            }
+        )*
+    };
+}
+
+mask! {
+    BitAnd bitand, BitAndAssign bitand_assign => simd_and;
+    BitOr bitor, BitOrAssign bitor_assign => simd_or;
+    BitXor bitxor, BitXorAssign bitxor_assign => simd_xor;
+}
+
+impl<const N: usize, T: Copy> Vector<N, T> {
+    pub const fn new(lanes: [T; N]) -> Self {
+        Self(Simd(lanes))
+    }
+
+    pub const fn splat(value: T) -> Self {
+        Self(Simd([value; N]))
+    }
+
+    pub const fn to_array(self) -> [T; N] {
+        self.0.0
+    }
+
+    pub fn map<U: Copy>(self, f: impl FnMut(T) -> U) -> Vector<N, U> {
+        Vector(Simd(self.0.0.map(f)))
+    }
+}
+
+impl<const N: usize, T: Element> Vector<N, T> {
+    fn raw(self) -> Raw<T, N> {
+        self.0.raw()
+    }
+
+    fn from_raw(raw: Raw<T, N>) -> Self {
+        Self(Simd::from_raw(raw))
+    }
+
+    /// Sum of the lanes, wrapping for integers.
+    pub fn reduce(self) -> T
+    where
+        T: Add<Output = T>,
+    {
+        unsafe { simd_reduce_add_unordered(self.raw()) }
+    }
+
+    /// Sum of the lanes added first to last, where [`reduce`](Self::reduce)
+    /// leaves the order to the target, so float sums round the same
+    /// everywhere. Integers wrap.
+    pub fn reduce_add(self) -> T {
+        <T as sealed::Sealed>::reduce_add(self)
+    }
+
+    /// Lane `i` of `a` where lane `i` of `mask` is set, else of `b`.
+    pub fn select(mask: Mask<N>, a: Self, b: Self) -> Self {
+        Self::from_raw(unsafe { simd_select(mask.0.raw(), a.raw(), b.raw()) })
+    }
+
+    /// Picks lane `MASK[i]` for lane `i`, e.g. `v.shuffle::<Zyx, 3>()`.
+    pub fn shuffle<P, const M: usize>(self) -> Vector<M, T>
+    where
+        P: Pattern<Indices = [u32; M]>,
+    {
+        let raw = self.raw();
+        Vector::from_raw(unsafe { simd_shuffle(raw, raw, const { Indices(P::MASK) }) })
+    }
+
+    /// [`shuffle`](Self::shuffle) for patterns as long as the vector.
+    pub fn same_shuffle<P>(self) -> Self
+    where
+        P: Pattern<Indices = [u32; N]>,
+    {
+        self.shuffle::<P, N>()
+    }
+}
+
+/// Lane by lane comparisons. Any comparison with a NaN lane is false but
+/// [`lanes_ne`](Vector::lanes_ne), as with the scalars.
+macro_rules! compare {
+    ($($method:ident => $intrinsic:ident;)*) => {
+        impl<const N: usize, T: Element + PartialOrd> Vector<N, T> {
+            $(
+                pub fn $method(self, other: Self) -> Mask<N> {
+                    Mask(Simd::from_raw(unsafe { $intrinsic(self.raw(), other.raw()) }))
+                }
+            )*
+        }
+    };
+}
+
+compare! {
+    lanes_eq => simd_eq;
+    lanes_ne => simd_ne;
+    lanes_lt => simd_lt;
+    lanes_le => simd_le;
+    lanes_gt => simd_gt;
+    lanes_ge => simd_ge;
+}
+
+impl<const N: usize, T: Element + PartialOrd> Vector<N, T> {
+    /// The lesser of each pair of lanes. A NaN lane gives way to a number, as
+    /// with [`f32::min`].
+    pub fn min(self, other: Self) -> Self {
+        Self::select(self.lanes_lt(other) | other.lanes_ne(other), self, other)
+    }
+
+    /// The greater of each pair of lanes, a NaN lane giving way to a number.
+    pub fn max(self, other: Self) -> Self {
+        Self::select(self.lanes_gt(other) | other.lanes_ne(other), self, other)
+    }
+
+    /// Each lane held between those of `min` and `max`. NaN lanes stay NaN,
+    /// as with [`f32::clamp`].
+    ///
+    /// # Panics
+    ///
+    /// If a lane of `min` is above, or not comparable with, that of `max`.
+    pub fn clamp(self, min: Self, max: Self) -> Self {
+        assert!(min.lanes_le(max).all(), "min > max, or either was NaN");
+        let clamped = Self::select(self.lanes_gt(max), max, self);
+        Self::select(self.lanes_lt(min), min, clamped)
+    }
+
+    /// The least lane. NaN lanes are passed over unless all of them are.
+    pub fn reduce_min(self) -> T {
+        <T as sealed::Sealed>::reduce_min(self)
+    }
+
+    /// The greatest lane. NaN lanes are passed over unless all of them are.
+    pub fn reduce_max(self) -> T {
+        <T as sealed::Sealed>::reduce_max(self)
+    }
+}
+
+/// Integer arithmetic that says what happens on overflow, where the
+/// operators panic in debug builds as the scalar ones do.
+impl<const N: usize, T: Element + PrimInt> Vector<N, T> {
+    pub fn wrapping_add(self, other: Self) -> Self {
+        Self::from_raw(unsafe { simd_add(self.raw(), other.raw()) })
+    }
+
+    pub fn wrapping_sub(self, other: Self) -> Self {
+        Self::from_raw(unsafe { simd_sub(self.raw(), other.raw()) })
+    }
+
+    pub fn wrapping_mul(self, other: Self) -> Self {
+        Self::from_raw(unsafe { simd_mul(self.raw(), other.raw()) })
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        Self::from_raw(unsafe { simd_saturating_add(self.raw(), other.raw()) })
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        Self::from_raw(unsafe { simd_saturating_sub(self.raw(), other.raw()) })
+    }
+
+    /// The sum, or `None` if any lane overflows.
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        // Saturating only differs from wrapping where a lane overflows
+        let sum = self.wrapping_add(other);
+        sum.lanes_eq(self.saturating_add(other)).all().then_some(sum)
+    }
+
+    /// The difference, or `None` if any lane overflows.
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        let difference = self.wrapping_sub(other);
+        difference.lanes_eq(self.saturating_sub(other)).all().then_some(difference)
+    }
+
+    /// The product, or `None` if any lane overflows.
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        // No intrinsic saturates products, so this goes lane by lane
+        let fits = (0..N).all(|lane| self[lane].checked_mul(&other[lane]).is_some());
+        fits.then(|| self.wrapping_mul(other))
+    }
+}
+
+impl<const N: usize, T: Element + Signed> Vector<N, T> {
+    /// Each lane without its sign. `MIN` lanes of signed integers stay
+    /// `MIN`, as with `wrapping_abs`.
+    pub fn abs(self) -> Self {
+        <T as sealed::Sealed>::abs(self)
+    }
+}
+
+impl<const N: usize, T: Element + Add<Output = T> + Mul<Output = T>> Vector<N, T> {
+    /// Sum of the products of the lanes.
+    pub fn dot(self, other: Self) -> T {
+        (self * other).reduce()
+    }
+
+    pub fn length_squared(self) -> T {
+        self.dot(self)
+    }
+}
+
+impl<const N: usize, T: Element + Float> Vector<N, T> {
+    pub fn length(self) -> T {
+        self.length_squared().sqrt()
+    }
+
+    pub fn distance(self, other: Self) -> T {
+        (other - self).length()
+    }
+
+    /// The vector scaled to a length of 1. A zero vector has no direction and
+    /// gives NaN lanes, which [`try_normalize`](Self::try_normalize) avoids.
+    pub fn normalize(self) -> Self {
+        self * self.length().recip()
+    }
+
+    /// [`normalize`](Self::normalize), or `None` for a vector too short, or
+    /// too long, to scale reliably.
+    pub fn try_normalize(self) -> Option<Self> {
+        let length = self.length();
+        (length > T::epsilon() && length.is_finite()).then(|| self * length.recip())
+    }
+
+    /// `self` at `t` = 0 to `other` at `t` = 1, extrapolating beyond.
+    pub fn lerp(self, other: Self, t: T) -> Self {
+        self + (other - self) * t
+    }
+
+    /// The vector mirrored off a surface with the unit length `normal`.
+    pub fn reflect(self, normal: Self) -> Self {
+        self - normal * (self.dot(normal) * (T::one() + T::one()))
+    }
+}
+
+impl<T: Element + Mul<Output = T> + Sub<Output = T>> Vector<3, T> {
+    /// Perpendicular to both, its length the area of the parallelogram they
+    /// span, following the right hand rule.
+    pub fn cross(self, other: Self) -> Self {
+        self.same_shuffle::<Yzx>() * other.same_shuffle::<Zxy>()
+            - self.same_shuffle::<Zxy>() * other.same_shuffle::<Yzx>()
+    }
+}
+
+impl<const N: usize, T: Copy + Default> Default for Vector<N, T> {
+    fn default() -> Self {
+        Self::splat(T::default())
+    }
+}
+
+impl<const N: usize, T> From<[T; N]> for Vector<N, T> {
+    fn from(lanes: [T; N]) -> Self {
+        Self(Simd(lanes))
+    }
+}
+
+impl<const N: usize, T> From<Vector<N, T>> for [T; N] {
+    fn from(vector: Vector<N, T>) -> Self {
+        vector.0.0
+    }
+}
+
+impl<const N: usize, T> Index<usize> for Vector<N, T> {
+    type Output = T;
+
+    fn index(&self, lane: usize) -> &T {
+        &self.0.0[lane]
+    }
+}
+
+impl<const N: usize, T> IndexMut<usize> for Vector<N, T> {
+    fn index_mut(&mut self, lane: usize) -> &mut T {
+        &mut self.0.0[lane]
+    }
+}
+
+/// `Vector op Vector`, `Vector op T` and their assigning forms, the scalar
+/// being splat over every lane.
+macro_rules! binary {
+    ($($op:ident $method:ident, $assign:ident $assign_method:ident => $body:expr;)*) => {
+        $(
+            impl<const N: usize, T: Element + $op<Output = T>> $op for Vector<N, T> {
+                type Output = Self;
+
+                fn $method(self, rhs: Self) -> Self {
+                    let body: fn(Self, Self) -> Self = $body;
+                    body(self, rhs)
+                }
+            }
+
+            impl<const N: usize, T: Element + $op<Output = T>> $op<T> for Vector<N, T> {
+                type Output = Self;
+
+                fn $method(self, rhs: T) -> Self {
+                    self.$method(Self::splat(rhs))
+                }
+            }
+
+            impl<const N: usize, T: Element + $op<Output = T>> $assign for Vector<N, T> {
+                fn $assign_method(&mut self, rhs: Self) {
+                    *self = (*self).$method(rhs);
+                }
+            }
+
+            impl<const N: usize, T: Element + $op<Output = T>> $assign<T> for Vector<N, T> {
+                fn $assign_method(&mut self, rhs: T) {
+                    *self = (*self).$method(rhs);
+                }
+            }
+        )*
+    };
+}
+
+binary! {
+    // The intrinsics wrap, so integer overflow is checked for beforehand as
+    // the scalars do in debug builds
+    Add add, AddAssign add_assign => |a, b| {
+        debug_assert!(!<T as sealed::Sealed>::add_overflows(a, b), "attempt to add with overflow");
+        Vector::from_raw(unsafe { simd_add(a.raw(), b.raw()) })
+    };
+    Sub sub, SubAssign sub_assign => |a, b| {
+        debug_assert!(!<T as sealed::Sealed>::sub_overflows(a, b), "attempt to subtract with overflow");
+        Vector::from_raw(unsafe { simd_sub(a.raw(), b.raw()) })
+    };
+    Mul mul, MulAssign mul_assign => |a, b| {
+        debug_assert!(!<T as sealed::Sealed>::mul_overflows(a, b), "attempt to multiply with overflow");
+        Vector::from_raw(unsafe { simd_mul(a.raw(), b.raw()) })
+    };
+    // Dividing by zero or `MIN / -1` is undefined for the intrinsics, so
+    // these go lane by lane to panic like the scalars do
+    Div div, DivAssign div_assign => |a, b| Vector(Simd(array::from_fn(|i| a[i] / b[i])));
+    Rem rem, RemAssign rem_assign => |a, b| Vector(Simd(array::from_fn(|i| a[i] % b[i])));
+    BitAnd bitand, BitAndAssign bitand_assign => |a, b| Vector::from_raw(unsafe { simd_and(a.raw(), b.raw()) });
+    BitOr bitor, BitOrAssign bitor_assign => |a, b| Vector::from_raw(unsafe { simd_or(a.raw(), b.raw()) });
+    BitXor bitxor, BitXorAssign bitxor_assign => |a, b| Vector::from_raw(unsafe { simd_xor(a.raw(), b.raw()) });
+}
+
+/// Shifts by `Vector` and by `T`. Shifting by the lane width or more is
+/// undefined for the intrinsics, so the amount is taken modulo the width as
+/// `wrapping_shl` does.
+macro_rules! shift {
+    ($($op:ident $method:ident, $assign:ident $assign_method:ident => $intrinsic:ident;)*) => {
+        $(
+            impl<const N: usize, T: Element + PrimInt> $op for Vector<N, T> {
+                type Output = Self;
+
+                fn $method(self, rhs: Self) -> Self {
+                    let width = T::from(size_of::<T>() * 8 - 1).unwrap();
+                    let rhs = rhs & Self::splat(width);
+                    Self::from_raw(unsafe { $intrinsic(self.raw(), rhs.raw()) })
+                }
+            }
+
+            impl<const N: usize, T: Element + PrimInt> $op<T> for Vector<N, T> {
+                type Output = Self;
+
+                fn $method(self, rhs: T) -> Self {
+                    self.$method(Self::splat(rhs))
+                }
+            }
+
+            impl<const N: usize, T: Element + PrimInt> $assign for Vector<N, T> {
+                fn $assign_method(&mut self, rhs: Self) {
+                    *self = (*self).$method(rhs);
+                }
+            }
+
+            impl<const N: usize, T: Element + PrimInt> $assign<T> for Vector<N, T> {
+                fn $assign_method(&mut self, rhs: T) {
+                    *self = (*self).$method(rhs);
+                }
+            }
+        )*
+    };
+}
+
+shift! {
+    Shl shl, ShlAssign shl_assign => simd_shl;
+    Shr shr, ShrAssign shr_assign => simd_shr;
+}
+
+impl<const N: usize, T: Element + Neg<Output = T>> Neg for Vector<N, T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        debug_assert!(!<T as sealed::Sealed>::neg_overflows(self), "attempt to negate with overflow");
+        Self::from_raw(unsafe { simd_neg(self.raw()) })
+    }
+}
+
+vector_constants!(Vector, f32, 0.0, 1.0);
+vector_constants!(Vector, f64, 0.0, 1.0);
+vector_constants!(Vector, i32, 0, 1);
+vector_constants!(Vector, u32, 0, 1);
+vector_constants!(Vector, usize, 0, 1);
+
+pub mod shuffle {
+    /// Lanes picked by a shuffle, `MASK[i]` going to lane `i`.
+    pub trait Pattern {
+        type Indices;
+        const MASK: Self::Indices;
+    }
+
+    /// Interleaves the two halves of `N` lanes, the first half going to the
+    /// even lanes.
+    pub struct Perfect<const N: usize>;
+
+    impl<const N: usize> Pattern for Perfect<N> {
+        type Indices = [u32; N];
+        const MASK: [u32; N] = {
+            let mut mask = [0; N];
+            let half = N.div_ceil(2);
+            let mut lane = 0;
+            while lane < N {
+                mask[lane] = match lane % 2 {
+                    0 => lane / 2,
+                    _ => half + lane / 2,
+                } as u32;
+                lane += 1;
+            }
+            mask
+        };
+    }
+}
+
+/// Swizzle markers for [`Vector::shuffle`] and accessors like `v.zyx()`.
+pub mod swizzle {
+    use math_macro::swizzle;
+
+    use super::{Simd, Vector, shuffle::Pattern};
+
+    swizzle!(Vector);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shuffle::Perfect, swizzle::Zyx, *};
+
+    #[test]
+    fn test_arithmetic() {
+        let a = Vector::new([1u32, 2, 3, 4]);
+        let b = Vector::splat(2u32);
+        assert_eq!(a + b, Vector::new([3, 4, 5, 6]));
+        assert_eq!(a * 3, Vector::new([3, 6, 9, 12]));
+        assert_eq!(a - 1, Vector::new([0, 1, 2, 3]));
+        assert_eq!(a / b, Vector::new([0, 1, 1, 2]));
+        assert_eq!(a % b, Vector::new([1, 0, 1, 0]));
+        assert_eq!(a << 1, Vector::new([2, 4, 6, 8]));
+        assert_eq!(a ^ a, Vector::<4, u32>::ZERO);
+        assert_eq!(-Vector::new([1.0f32, -2.0]), Vector::new([-1.0, 2.0]));
+        assert_eq!(a.reduce(), 10);
+
+        let mut c = a;
+        c += b;
+        c[0] = 7;
+        assert_eq!(c.to_array(), [7, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_shifts_wrap_amounts() {
+        let value = Vector::splat(0x80u128);
+        // A rotation by 0 shifts the other half by the full width
+        let rot = Vector::new([0u128, 4]);
+        let rotated = (value >> rot) | (value << (Vector::splat(128) - rot));
+        assert_eq!(rotated, Vector::new([0x80, 0x08]));
+        assert_eq!(Vector::<2, u8>::splat(1) << 9, Vector::splat(2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_division_by_zero_panics() {
+        let _ = Vector::new([1i32, 2]) / Vector::new([1, 0]);
+    }
+
+    #[test]
+    fn test_shuffles() {
+        let v = Vector::new([0u128, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(
+            v.same_shuffle::<Perfect<8>>(),
+            Vector::new([0, 4, 1, 5, 2, 6, 3, 7])
+        );
+        let odd = Vector::new([0u8, 1, 2, 3, 4]);
+        assert_eq!(odd.same_shuffle::<Perfect<5>>(), Vector::new([0, 3, 1, 4, 2]));
+
+        let v = Vector::new([1.0f32, 2.0, 3.0]);
+        assert_eq!(v.shuffle::<Zyx, 3>(), v.zyx());
+        assert_eq!(v.zyx(), Vector::new([3.0, 2.0, 1.0]));
+        assert_eq!(Vector::<3, f32>::Y.y(), 1.0);
+    }
+
+    #[test]
+    fn test_geometry() {
+        let a = Vector::new([1.0f32, 2.0, 3.0]);
+        let b = Vector::new([4.0f32, -5.0, 6.0]);
+        assert_eq!(a.dot(b), 12.0);
+        assert_eq!(Vector::new([1, 2, 3]).dot(Vector::new([4, 5, 6])), 32);
+        assert_eq!(Vector::new([3.0f64, 4.0]).length_squared(), 25.0);
+        assert_eq!(Vector::new([3.0f64, 4.0]).length(), 5.0);
+        assert_eq!(Vector::new([1.0f32, 1.0]).distance(Vector::new([4.0, 5.0])), 5.0);
+        assert_eq!(Vector::new([0.0f32, 3.0, 4.0]).normalize(), Vector::new([0.0, 0.6, 0.8]));
+
+        type Vec3 = Vector<3, f32>;
+        assert_eq!(Vec3::X.cross(Vec3::Y), Vec3::Z);
+        assert_eq!(Vec3::Y.cross(Vec3::X), -Vec3::Z);
+        assert_eq!(a.cross(b), Vector::new([27.0, 6.0, -13.0]));
+        assert_eq!(Vector::new([1, 0, 0]).cross(Vector::new([0, 1, 0])), Vector::new([0, 0, 1]));
+        assert_eq!(a.cross(b).dot(a), 0.0);
+
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 1.0), b);
+        assert_eq!(a.lerp(b, 0.5), Vector::new([2.5, -1.5, 4.5]));
+        assert_eq!(
+            Vector::new([1.0f32, -1.0]).reflect(Vector::<2, f32>::Y),
+            Vector::new([1.0, 1.0])
+        );
+    }
+
+    #[test]
+    fn test_try_normalize() {
+        assert_eq!(Vector::<3, f32>::ZERO.try_normalize(), None);
+        assert_eq!(Vector::splat(f32::EPSILON / 2.0).try_normalize(), None::<Vector<2, f32>>);
+        assert_eq!(Vector::splat(f32::INFINITY).try_normalize(), None::<Vector<2, f32>>);
+        assert_eq!(Vector::new([0.0f64, -2.0]).try_normalize(), Some(Vector::new([0.0, -1.0])));
+        assert!(Vector::<3, f32>::ZERO.normalize().to_array().iter().all(|lane| lane.is_nan()));
+    }
+
+    #[test]
+    fn test_masks() {
+        let a = Vector::new([1i32, 5, 3, 7]);
+        let b = Vector::new([2, 5, 1, 8]);
+        let lt = a.lanes_lt(b);
+        assert_eq!(lt.to_array(), [true, false, false, true]);
+        assert_eq!(lt.to_bitmask(), 0b1001);
+        assert_eq!(Mask::<4>::from_bitmask(0b1001 | 1 << 40), lt);
+        assert_eq!(a.lanes_le(b).to_bitmask(), 0b1011);
+        assert_eq!((a.lanes_eq(b) | a.lanes_gt(b)).to_bitmask(), a.lanes_ge(b).to_bitmask());
+        assert_eq!(!lt & a.lanes_ne(b), a.lanes_gt(b));
+        assert_eq!(Vector::select(lt, a, b), Vector::new([1, 5, 1, 7]));
+        assert!(lt.any() && !lt.all());
+        assert!(Mask::<3>::splat(true).all() && !Mask::<3>::splat(false).any());
+
+        let mut m = Mask::<2>::splat(false);
+        m.set(1, true);
+        m ^= Mask::from_array([true, true]);
+        assert!(m.test(0) && !m.test(1));
+
+        let nan = Vector::new([f32::NAN, 1.0]);
+        assert_eq!(nan.lanes_eq(nan).to_array(), [false, true]);
+        assert_eq!(nan.lanes_ne(nan).to_array(), [true, false]);
+        // Masks pick lanes of any width
+        assert_eq!(
+            Vector::select(nan.lanes_eq(nan), Vector::splat(1u128), Vector::splat(2)),
+            Vector::new([2, 1])
+        );
+    }
+
+    /// Whether `a` and `b` are equal, or both NaN.
+    fn same<T: PartialOrd>(a: T, b: T) -> bool {
+        a == b || (a.partial_cmp(&a).is_none() && b.partial_cmp(&b).is_none())
+    }
+
+    /// Checks the lane wise operations against the scalar ones for vectors
+    /// made of `lane` applied to generated values.
+    macro_rules! against_scalars {
+        ($($ty:ty => $lane:expr, $min:expr, $max:expr;)*) => {
+            $(
+                for ((a0, a1, a2, a3), (b0, b1, b2, b3)) in crate::rng::fuzz::cases::<(($ty, $ty, $ty, $ty), ($ty, $ty, $ty, $ty))>(3, 500) {
+                    let lane: fn($ty) -> $ty = $lane;
+                    let (min, max): (fn($ty, $ty) -> $ty, fn($ty, $ty) -> $ty) = ($min, $max);
+                    let a = Vector::new([a0, a1, a2, a3].map(lane));
+                    let b = Vector::new([b0, b1, b2, b3].map(lane));
+                    let (lo, hi) = (a.min(b), a.max(b));
+                    for i in 0..4 {
+                        assert!(same(lo[i], min(a[i], b[i])), "{a:?} {b:?}");
+                        assert!(same(hi[i], max(a[i], b[i])), "{a:?} {b:?}");
+                        assert_eq!(a.lanes_lt(b).test(i), a[i] < b[i], "{a:?} {b:?}");
+                        assert_eq!(a.lanes_ge(b).test(i), a[i] >= b[i], "{a:?} {b:?}");
+                        assert_eq!(a.lanes_ne(b).test(i), a[i] != b[i], "{a:?} {b:?}");
+                    }
+                    let scalars = a.to_array();
+                    assert!(same(a.reduce_min(), scalars.into_iter().reduce(min).unwrap()), "{a:?}");
+                    assert!(same(a.reduce_max(), scalars.into_iter().reduce(max).unwrap()), "{a:?}");
+
+                    // Bounds from lanes that are not NaN, which `clamp` rejects
+                    let (lo, hi) = (lo.min(hi), hi.max(lo));
+                    if lo.lanes_le(hi).all() {
+                        let clamped = a.clamp(lo, hi);
+                        for i in 0..4 {
+                            let scalar = match a[i] {
+                                x if x < lo[i] => lo[i],
+                                x if x > hi[i] => hi[i],
+                                x => x,
+                            };
+                            assert!(same(clamped[i], scalar), "{a:?} {lo:?} {hi:?}");
+                        }
+                    }
+                }
+            )*
+        };
+    }
+
+    #[test]
+    fn test_lanes_against_scalars() {
+        against_scalars! {
+            u8 => |x| x, Ord::min, Ord::max;
+            i16 => |x| x, Ord::min, Ord::max;
+            i32 => |x| x % 8, Ord::min, Ord::max;
+            u64 => |x| x, Ord::min, Ord::max;
+            i128 => |x| x, Ord::min, Ord::max;
+            // A fifth of the lanes NaN, the rest in -1..1
+            f32 => |x| if x < 0.2 { f32::NAN } else { x * 2.0 - 1.0 }, f32::min, f32::max;
+            f64 => |x| if x < 0.2 { f64::NAN } else { x * 2.0 - 1.0 }, f64::min, f64::max;
+        }
+    }
+
+    #[test]
+    fn test_abs_and_sums() {
+        assert_eq!(Vector::new([-3i8, 3, 0, i8::MIN]).abs(), Vector::new([3, 3, 0, i8::MIN]));
+        assert_eq!(Vector::new([-1.5f64, 2.0]).abs(), Vector::new([1.5, 2.0]));
+        assert!(Vector::new([-0.0f32]).abs()[0].is_sign_positive());
+        assert!(Vector::new([-f32::NAN]).abs()[0].is_sign_positive());
+
+        assert_eq!(Vector::new([1u8, 2, 255]).reduce_add(), 2);
+        assert_eq!(Vector::new([1.0f32, 1e8, -1e8]).reduce_add(), 0.0);
+        assert!(Vector::new([-0.0f32, -0.0]).reduce_add().is_sign_negative());
+        assert!(Vector::new([f64::NAN, f64::NAN]).reduce_min().is_nan());
+        assert_eq!(Vector::new([f64::NAN, -1.0, f64::NAN]).reduce_max(), -1.0);
+        assert_eq!(Vector::new([3i64, -9, 4]).reduce_min(), -9);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_clamp_rejects_crossed_bounds() {
+        let _ = Vector::splat(1u32).clamp(Vector::new([0, 2]), Vector::new([1, 1]));
+    }
+
+    #[test]
+    fn test_overflow() {
+        let a = Vector::new([250u8, 5, 0, 128]);
+        let b = Vector::new([10u8, 5, 1, 2]);
+        assert_eq!(a.wrapping_add(b), Vector::new([4, 10, 1, 130]));
+        assert_eq!(a.wrapping_sub(b), Vector::new([240, 0, 255, 126]));
+        assert_eq!(a.wrapping_mul(b), Vector::new([196, 25, 0, 0]));
+        assert_eq!(a.saturating_add(b), Vector::new([255, 10, 1, 130]));
+        assert_eq!(a.saturating_sub(b), Vector::new([240, 0, 0, 126]));
+        assert_eq!(a.checked_add(b), None);
+        assert_eq!(a.checked_sub(b), None);
+        assert_eq!(a.checked_mul(b), None);
+        assert_eq!(b.checked_add(b), Some(Vector::new([20, 10, 2, 4])));
+        assert_eq!(a.checked_sub(Vector::splat(0)), Some(a));
+        assert_eq!(b.checked_mul(b), Some(Vector::new([100, 25, 1, 4])));
+
+        let a = Vector::new([i32::MAX, i32::MIN, -7, 1 << 16]);
+        let b = Vector::new([1, 1, 3, 1 << 15]);
+        assert_eq!(a.wrapping_add(b), Vector::new([i32::MIN, i32::MIN + 1, -4, 3 << 15]));
+        assert_eq!(a.wrapping_sub(b), Vector::new([i32::MAX - 1, i32::MAX, -10, 1 << 15]));
+        assert_eq!(a.wrapping_mul(b), Vector::new([i32::MAX, i32::MIN, -21, i32::MIN]));
+        assert_eq!(a.saturating_add(b), Vector::new([i32::MAX, i32::MIN + 1, -4, 3 << 15]));
+        assert_eq!(a.saturating_sub(b), Vector::new([i32::MAX - 1, i32::MIN, -10, 1 << 15]));
+        assert_eq!(a.checked_add(b), None);
+        assert_eq!(a.checked_sub(b), None);
+        assert_eq!(a.checked_mul(b), None);
+        assert_eq!(a.checked_mul(Vector::new([1, 1, -3, 2])), Some(Vector::new([i32::MAX, i32::MIN, 21, 1 << 17])));
+        assert_eq!(-Vector::new([i32::MAX, 0]), Vector::new([-i32::MAX, 0]));
+
+        // Lanes that overflow on their own are caught alongside ones that
+        // do not
+        for (x, y) in crate::rng::fuzz::cases::<(i32, i32)>(9, 1000) {
+            let (a, b) = (Vector::new([x, 1]), Vector::new([y, 1]));
+            assert_eq!(a.checked_add(b).map(|sum| sum[0]), x.checked_add(y));
+            assert_eq!(a.checked_sub(b).map(|difference| difference[0]), x.checked_sub(y));
+            assert_eq!(a.checked_mul(b).map(|product| product[0]), x.checked_mul(y));
+            assert_eq!(a.saturating_add(b)[0], x.saturating_add(y));
+            assert_eq!(a.wrapping_mul(b)[0], x.wrapping_mul(y));
+        }
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic = "attempt to add with overflow"]
+    fn test_add_overflow_panics() {
+        let _ = Vector::new([1u8, 255]) + 1;
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic = "attempt to multiply with overflow"]
+    fn test_mul_overflow_panics() {
+        let _ = Vector::new([1i32, 1 << 16]) * (1 << 16);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic = "attempt to negate with overflow"]
+    fn test_neg_overflow_panics() {
+        let _ = -Vector::new([i32::MIN, 0]);
+    }
+
+    #[test]
+    fn test_normalized_length() {
+        for (x, y, z, scale) in crate::rng::fuzz::cases::<(f32, f32, f32, f32)>(7, 1000) {
+            // Lanes in -1..1, scaled up to 1000
+            let v = (Vector::new([x, y, z]) * 2.0 - 1.0) * (scale * 1000.0);
+            if let Some(unit) = v.try_normalize() {
+                assert!((unit.length() - 1.0).abs() < 1e-5, "{v:?}");
+                assert!((v.normalize() - unit).length() < 1e-6, "{v:?}");
+            }
+        }
+    }
+}
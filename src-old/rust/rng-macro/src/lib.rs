@@ -0,0 +1,189 @@
+use proc_macro::TokenStream;
+use proc_macro2::{TokenStream as TokenStream2, TokenTree};
+use quote::quote;
+use syn::*;
+
+/// Derives `base::rng::fuzz::Generate`.
+///
+/// Fields accept `#[generate(range = "0..100")]` to sample from a range and
+/// `#[generate(with = "path::to::fn")]` to call `fn(&mut impl Rng, u32) -> T`
+/// with the remaining depth budget. Enum variants accept
+/// `#[generate(weight = 3)]`; variants mentioning the enum itself are only
+/// picked while depth budget is left.
+#[proc_macro_derive(Generate, attributes(generate))]
+pub fn derive_generate(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match generate(input) {
+        Ok(output) => output.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+fn generate(mut input: DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+
+    let body = match &input.data {
+        Data::Struct(data) => construct(quote! { Self }, &data.fields)?,
+        Data::Enum(data) => generate_enum(name, data)?,
+        Data::Union(_) => {
+            return Err(Error::new_spanned(&input, "Generate cannot be derived for unions"));
+        }
+    };
+
+    for param in input.generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::base::rng::fuzz::Generate));
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::base::rng::fuzz::Generate for #name #ty_generics #where_clause {
+            #[allow(unused_variables)]
+            fn generate(rng: &mut impl ::base::rng::Rng, depth_budget: u32) -> Self {
+                let depth = depth_budget.saturating_sub(1);
+                #body
+            }
+        }
+    })
+}
+
+fn generate_enum(name: &Ident, data: &DataEnum) -> Result<TokenStream2> {
+    if data.variants.is_empty() {
+        return Err(Error::new_spanned(name, "Generate cannot be derived for empty enums"));
+    }
+
+    let mut weights = vec![];
+    let mut leaf_weights = vec![];
+    let mut arms = vec![];
+    for (index, variant) in data.variants.iter().enumerate() {
+        let attrs = Attrs::parse(&variant.attrs)?;
+        if attrs.range.is_some() || attrs.with.is_some() {
+            return Err(Error::new_spanned(variant, "variants only accept `weight`"));
+        }
+        let weight = match attrs.weight {
+            Some(weight) => weight.base10_parse::<u128>()?,
+            None => 1,
+        };
+        let recursive = variant
+            .fields
+            .iter()
+            .map(|field| &field.ty)
+            .any(|ty| mentions(quote! { #ty }, name));
+
+        weights.push(weight);
+        leaf_weights.push(if recursive { 0 } else { weight });
+
+        let ident = &variant.ident;
+        let value = construct(quote! { Self::#ident }, &variant.fields)?;
+        arms.push(quote! { #index => #value, });
+    }
+
+    if weights.iter().sum::<u128>() == 0 {
+        return Err(Error::new_spanned(name, "at least one variant needs a non-zero weight"));
+    }
+    // Without a terminating variant recursion has to end in empty containers
+    if leaf_weights.iter().sum::<u128>() == 0 {
+        leaf_weights = weights.clone();
+    }
+
+    Ok(quote! {
+        let weights: &[u128] = if depth_budget == 0 {
+            &[#(#leaf_weights),*]
+        } else {
+            &[#(#weights),*]
+        };
+        let mut pick = ::base::rng::Random::sample::<u128>(rng, &::base::rng::Standard) % weights.iter().sum::<u128>();
+        let index = weights
+            .iter()
+            .position(|&weight| {
+                if pick < weight {
+                    return true;
+                }
+                pick -= weight;
+                false
+            })
+            .unwrap();
+        match index {
+            #(#arms)*
+            _ => unreachable!(),
+        }
+    })
+}
+
+fn construct(path: TokenStream2, fields: &Fields) -> Result<TokenStream2> {
+    Ok(match fields {
+        Fields::Named(named) => {
+            let inits = named
+                .named
+                .iter()
+                .map(|field| {
+                    let ident = &field.ident;
+                    let value = field_value(field)?;
+                    Ok(quote! { #ident: #value })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            quote! { #path { #(#inits),* } }
+        }
+        Fields::Unnamed(unnamed) => {
+            let values = unnamed
+                .unnamed
+                .iter()
+                .map(field_value)
+                .collect::<Result<Vec<_>>>()?;
+            quote! { #path(#(#values),*) }
+        }
+        Fields::Unit => path,
+    })
+}
+
+fn field_value(field: &Field) -> Result<TokenStream2> {
+    let attrs = Attrs::parse(&field.attrs)?;
+    let ty = &field.ty;
+    Ok(match (attrs.range, attrs.with) {
+        (Some(_), Some(_)) => {
+            return Err(Error::new_spanned(field, "`range` and `with` are exclusive"));
+        }
+        (Some(range), None) => quote! {{
+            let value: #ty = ::base::rng::Random::sample(rng, &::base::rng::Range::new(#range));
+            value
+        }},
+        (None, Some(with)) => quote! { #with(rng, depth) },
+        (None, None) => quote! { <#ty as ::base::rng::fuzz::Generate>::generate(rng, depth) },
+    })
+}
+
+/// Whether `tokens` name the type being derived, directly or as `Self`.
+fn mentions(tokens: TokenStream2, name: &Ident) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(ident) => ident == *name || ident == "Self",
+        TokenTree::Group(group) => mentions(group.stream(), name),
+        _ => false,
+    })
+}
+
+#[derive(Default)]
+struct Attrs {
+    range: Option<Expr>,
+    with: Option<ExprPath>,
+    weight: Option<LitInt>,
+}
+
+impl Attrs {
+    fn parse(attrs: &[Attribute]) -> Result<Self> {
+        let mut parsed = Self::default();
+        for attr in attrs.iter().filter(|attr| attr.path().is_ident("generate")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("range") {
+                    parsed.range = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                } else if meta.path.is_ident("with") {
+                    parsed.with = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                } else if meta.path.is_ident("weight") {
+                    parsed.weight = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(meta.error("expected `range`, `with` or `weight`"));
+                }
+                Ok(())
+            })?;
+        }
+        Ok(parsed)
+    }
+}
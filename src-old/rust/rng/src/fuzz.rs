@@ -0,0 +1,195 @@
+use crate::{
+    math::vector::Vector,
+    rng::{Pcg, Random, Range, Rng, Standard},
+};
+
+pub use rng_macro::Generate;
+
+/// Depth budget [`cases`] starts every value with.
+pub const DEPTH: u32 = 6;
+
+/// Longest `String` generated.
+pub const MAX_STRING: usize = 32;
+
+/// Longest `Vec` generated while depth budget is left.
+pub const MAX_VEC: usize = 8;
+
+/// Structured random inputs for property tests.
+pub trait Generate: Sized {
+    /// Builds a random value. `depth_budget` bounds nesting: every container
+    /// or recursive variant hands its contents one less, and at zero they are
+    /// empty, `None` or a non-recursive variant.
+    fn generate(rng: &mut impl Rng, depth_budget: u32) -> Self;
+}
+
+/// `count` cases seeded with `seed`. The same seed always yields the same
+/// sequence, so a failing case can be replayed from the seed alone.
+pub fn cases<T: Generate>(seed: u128, count: usize) -> impl Iterator<Item = T> {
+    let mut rng = Pcg::<4>::new(Vector::splat(seed));
+    (0..count).map(move |_| T::generate(&mut rng, DEPTH))
+}
+
+macro_rules! standard {
+    ($($ty:ty),*) => {
+        $(
+            impl Generate for $ty {
+                fn generate(rng: &mut impl Rng, _: u32) -> Self {
+                    rng.sample(&Standard)
+                }
+            }
+        )*
+    };
+}
+
+standard!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, bool);
+
+macro_rules! tuple {
+    ($($name:ident),+) => {
+        impl<$($name: Generate),+> Generate for ($($name,)+) {
+            fn generate(rng: &mut impl Rng, depth_budget: u32) -> Self {
+                ($($name::generate(rng, depth_budget),)+)
+            }
+        }
+    };
+}
+
+tuple!(A);
+tuple!(A, B);
+tuple!(A, B, C);
+tuple!(A, B, C, D);
+tuple!(A, B, C, D, E);
+tuple!(A, B, C, D, E, F);
+
+impl Generate for char {
+    fn generate(rng: &mut impl Rng, _: u32) -> Self {
+        // Mostly printable ASCII, which is what parsers see most
+        if rng.sample(&Standard) {
+            let ascii: u32 = rng.sample(&Range::new(0x20..0x7f));
+            return char::from_u32(ascii).unwrap();
+        }
+        loop {
+            let scalar: u32 = rng.sample(&Range::new(0..0x110000));
+            if let Some(c) = char::from_u32(scalar) {
+                return c;
+            }
+        }
+    }
+}
+
+impl Generate for String {
+    fn generate(rng: &mut impl Rng, depth_budget: u32) -> Self {
+        let len: usize = rng.sample(&Range::new(0..=MAX_STRING));
+        (0..len).map(|_| char::generate(rng, depth_budget)).collect()
+    }
+}
+
+impl<T: Generate> Generate for Vec<T> {
+    fn generate(rng: &mut impl Rng, depth_budget: u32) -> Self {
+        if depth_budget == 0 {
+            return Vec::new();
+        }
+        let len: usize = rng.sample(&Range::new(0..=MAX_VEC));
+        (0..len).map(|_| T::generate(rng, depth_budget - 1)).collect()
+    }
+}
+
+impl<T: Generate> Generate for Option<T> {
+    fn generate(rng: &mut impl Rng, depth_budget: u32) -> Self {
+        if depth_budget == 0 || !rng.sample::<bool>(&Standard) {
+            return None;
+        }
+        Some(T::generate(rng, depth_budget - 1))
+    }
+}
+
+impl<T: Generate> Generate for Box<T> {
+    fn generate(rng: &mut impl Rng, depth_budget: u32) -> Self {
+        Box::new(T::generate(rng, depth_budget))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Generate, Debug, Clone, PartialEq)]
+    enum Expr {
+        Lit(#[generate(range = "0..100")] i64),
+        Var(String),
+        Neg(Box<Expr>),
+        #[generate(weight = 4)]
+        Add(Box<Expr>, Box<Expr>),
+        Call { name: String, args: Vec<Expr> },
+    }
+
+    impl Expr {
+        fn height(&self) -> u32 {
+            1 + match self {
+                Expr::Lit(_) | Expr::Var(_) => 0,
+                Expr::Neg(e) => e.height(),
+                Expr::Add(a, b) => a.height().max(b.height()),
+                Expr::Call { args, .. } => args.iter().map(Expr::height).max().unwrap_or(0),
+            }
+        }
+    }
+
+    fn even(rng: &mut impl Rng, _: u32) -> u64 {
+        rng.sample::<u64>(&Standard) & !1
+    }
+
+    #[derive(Generate, Debug, PartialEq)]
+    struct Packet {
+        #[generate(range = "10..20")]
+        port: u16,
+        #[generate(range = "-1.0..1.0")]
+        gain: f64,
+        #[generate(with = "even")]
+        sequence: u64,
+        payload: Option<Vec<u8>>,
+    }
+
+    #[derive(Generate, Debug, PartialEq)]
+    struct Pair<T>(T, T);
+
+    #[test]
+    fn test_recursive_enum_respects_depth() {
+        let mut rng = Pcg::<4>::new(Vector::splat(7));
+        for depth in 0..4 {
+            for _ in 0..500 {
+                let expr = Expr::generate(&mut rng, depth);
+                assert!(expr.height() <= depth + 1, "{expr:?} deeper than {depth}");
+            }
+        }
+        // The budget does not just cut everything short
+        let tallest = cases::<Expr>(1, 500).map(|expr| expr.height()).max().unwrap();
+        assert!(tallest > 2 && tallest <= DEPTH + 1, "{tallest}");
+    }
+
+    #[test]
+    fn test_attributes() {
+        for packet in cases::<Packet>(42, 1000) {
+            assert!((10..20).contains(&packet.port), "{packet:?}");
+            assert!((-1.0..=1.0).contains(&packet.gain), "{packet:?}");
+            assert_eq!(packet.sequence % 2, 0);
+            assert!(packet.payload.is_none_or(|payload| payload.len() <= MAX_VEC));
+        }
+    }
+
+    #[test]
+    fn test_weights() {
+        let mut rng = Pcg::<4>::new(Vector::splat(3));
+        let adds = (0..2000)
+            .filter(|_| matches!(Expr::generate(&mut rng, 1), Expr::Add(..)))
+            .count();
+        // 4 of 8 total weight
+        assert!((800..1200).contains(&adds), "{adds}");
+    }
+
+    #[test]
+    fn test_cases_reproducible() {
+        let a = cases::<(Expr, Pair<String>)>(99, 200).collect::<Vec<_>>();
+        let b = cases::<(Expr, Pair<String>)>(99, 200).collect::<Vec<_>>();
+        assert_eq!(a, b);
+        assert_ne!(a, cases::<(Expr, Pair<String>)>(100, 200).collect::<Vec<_>>());
+    }
+}
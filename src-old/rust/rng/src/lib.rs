@@ -1,13 +1,15 @@
-#![feature(trait_alias, core_intrinsics)]
 use std::{
-    intrinsics::type_id,
+    any::TypeId,
     ops::{Bound, RangeBounds},
     time::{SystemTime, UNIX_EPOCH},
 };
+
+pub mod fuzz;
+
 pub trait Rng = Iterator<Item = u128>;
 
 pub async fn rng() -> Option<&'static mut impl Rng> {
-    worker::current_worker().await.map(|x| &mut x.rng)
+    worker::current_worker().map(|x| &mut x.rng)
 }
 
 pub async fn random<T>() -> Option<T>
@@ -137,11 +139,11 @@ mod standard {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
-pub struct Id(u128);
+pub struct Id(TypeId);
 
 impl Id {
     pub const fn of<T: 'static>() -> Self {
-        Id(unsafe { type_id::<T>() })
+        Id(TypeId::of::<T>())
     }
 }
 
@@ -158,7 +160,7 @@ mod range {
         ops::{Bound, Neg, RangeBounds},
     };
 
-    use crate::Id;
+    use crate::rng::Id;
 
     use super::{Distribution, Rng, Standard};
     #[derive(Clone, Copy)]
@@ -190,7 +192,8 @@ mod range {
             let this = Id::of::<T>();
             match this {
                 // Unsigned integers
-                U8 | U16 | U32 | U64 | U128 | USIZE => {
+                // `TypeId` can not be matched on, so these are guards
+                id if [U8, U16, U32, U64, U128, USIZE].contains(&id) => {
                     let low = match range.start_bound() {
                         Bound::Included(&x) => x,
                         Bound::Excluded(&x) => x + T::one(),
@@ -206,7 +209,7 @@ mod range {
                 }
 
                 // Signed integers
-                I8 | I16 | I32 | I64 | I128 | ISIZE => {
+                id if [I8, I16, I32, I64, I128, ISIZE].contains(&id) => {
                     let high = match range.end_bound() {
                         Bound::Included(&x) => x + T::one(),
                         Bound::Excluded(&x) => x,
@@ -243,7 +246,7 @@ mod range {
                 }
 
                 // Floating point
-                F32 | F64 => {
+                id if [F32, F64].contains(&id) => {
                     let mut low = match range.start_bound() {
                         Bound::Included(&x) | Bound::Excluded(&x) => x,
                         Bound::Unbounded => T::min_value(),
@@ -283,7 +286,7 @@ mod normal {
 
     use num_traits::Float;
 
-    use crate::{Distribution, Rng, Standard};
+    use crate::rng::{Distribution, Rng, Standard};
 
     // Normal (Gaussian) Distribution using Box-Muller transform
     #[derive(Clone, Copy)]
@@ -321,7 +324,7 @@ mod normal {
 pub use exponential::Exponential;
 mod exponential {
     use super::Random;
-    use crate::{Distribution, Rng, Standard};
+    use crate::rng::{Distribution, Rng, Standard};
     use num_traits::Float;
 
     #[derive(Clone, Copy)]
@@ -431,7 +434,7 @@ mod temporal {
 
 pub use mix::Mix;
 mod mix {
-    use crate::{Distribution, Random, Rng, Standard};
+    use crate::rng::{Distribution, Random, Rng, Standard};
     use std::marker::PhantomData;
 
     #[derive(Clone, Copy, Debug)]
@@ -485,7 +488,7 @@ mod mix {
 pub use gamma::Gamma;
 mod gamma {
     use super::Random;
-    use crate::{Distribution, Rng, Standard};
+    use crate::rng::{Distribution, Rng, Standard};
     use num_traits::Float;
 
     #[derive(Clone, Copy)]
@@ -550,7 +553,7 @@ mod gamma {
 pub use poisson::Poisson;
 mod poisson {
     use super::Random;
-    use crate::{Distribution, Rng, Standard};
+    use crate::rng::{Distribution, Rng, Standard};
     use num_traits::{Float, PrimInt};
 
     #[derive(Clone, Copy)]
@@ -590,7 +593,7 @@ pub use beta::Beta;
 use crate::{math::vector::Vector, rt::worker};
 mod beta {
     use super::Random;
-    use crate::{Distribution, Gamma, Rng, Standard};
+    use crate::rng::{Distribution, Gamma, Rng, Standard};
     use num_traits::Float;
 
     #[derive(Clone, Copy)]
@@ -622,7 +625,7 @@ mod beta {
 
 pub mod transform {
     use super::Random;
-    use crate::{Distribution, Rng, Standard};
+    use crate::rng::{Distribution, Rng, Standard};
     use num_traits::Float;
     use std::marker::PhantomData;
 
@@ -726,7 +729,7 @@ pub trait Branch: Random {
 }
 
 mod pcg {
-    use crate::math::vector::{Vector, shuffle::Perfect};
+    use crate::math::vector::{Simd, Vector, shuffle::Perfect};
 
     use super::{Branch, Random};
 
@@ -814,10 +817,13 @@ mod pcg {
         fn branch(&mut self) -> Self {
             self.avalanche();
 
-            let new_inc = self.increment * self.state + 0xda3e39cb94b95bdb;
+            let new_inc = self
+                .increment
+                .wrapping_mul(self.state)
+                .wrapping_add(Vector::splat(0xda3e39cb94b95bdb));
 
             // Create perturbed state for new branch
-            let new_state = self.state * new_inc + self.increment;
+            let new_state = self.state.wrapping_mul(new_inc).wrapping_add(self.increment);
 
             Self {
                 state: new_state,
@@ -843,7 +849,7 @@ mod pcg {
             let result = self.next_raw();
 
             // Add extra mixing steps
-            self.weyl += Vector::splat(WEYL);
+            self.weyl = self.weyl.wrapping_add(Vector::splat(WEYL));
             self.state ^= self.weyl;
 
             result
@@ -852,7 +858,10 @@ mod pcg {
         #[inline(always)]
         fn next_raw(&mut self) -> Vector<LANES, u128> {
             let old_state = self.state;
-            self.state = old_state * MULTIPLIER + self.increment;
+            // The state is meant to wrap, modulo 2^128
+            self.state = old_state
+                .wrapping_mul(Vector::splat(MULTIPLIER))
+                .wrapping_add(self.increment);
             // Enhanced mixing function for 128-bit
             let xored = (old_state >> 64) ^ old_state;
             let word = (xored >> 63) ^ (xored >> 31) ^ (xored >> 15);
@@ -928,6 +937,34 @@ fn test_pcg() {
     dbg!(pcg.sample::<f64>(&Standard));
     dbg!(pcg.sample::<f64>(&Standard));
 }
+#[test]
+fn test_pcg_output_is_stable() {
+    // Recorded from before the arithmetic wrapped explicitly; the state
+    // overflows on almost every step, so this pins down that it still wraps
+    let mut pcg = Pcg::<4>::new(Vector::new([1, 2, 3, u128::MAX]));
+    let first: Vec<u128> = pcg.by_ref().take(6).collect();
+    assert_eq!(
+        first,
+        [
+            0xe6472f1ce0000278df9e381bce75a28b,
+            0xe31c0000f1bb3c703798eb443a8cd8ce,
+            0xc74880fa0298c40006d535bc50879e99,
+            0x1dfe5ca1000087229e381bcc8a5c1ef3,
+            0x2ee6000082892f5b1badfcaa75f45b03,
+            0xbe700105788d62580003f3f981fe62b5,
+        ]
+    );
+    let mut branched = pcg.branch();
+    let later: Vec<u128> = branched.by_ref().skip(100).take(3).collect();
+    assert_eq!(
+        later,
+        [
+            0x600003cb0598f96d2acf28f70fe5c29e,
+            0x5390099333000147e2907967693211ef,
+            0xb000025ca2f5435e741fd4b7b2e3642f,
+        ]
+    );
+}
 fn chi_square_test(observed: &[u64]) -> f64 {
     let total: u64 = observed.iter().sum();
     let expected = (total as f64) / (observed.len() as f64);
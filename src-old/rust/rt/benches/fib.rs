@@ -0,0 +1,57 @@
+//! Recursive fork-join fibonacci, every call above the cutoff spawning one
+//! half and computing the other. With stealing the time should fall about
+//! linearly with the workers, up to the number of cores.
+
+use std::{future::Future, pin::Pin};
+
+use base::{
+    prelude::Vector,
+    rt::{Runtime, spawn},
+};
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+
+const N: u64 = 30;
+/// Below this the rest is computed in place, so tasks are not all overhead.
+const CUTOFF: u64 = 16;
+
+fn fib(n: u64) -> Pin<Box<dyn Future<Output = u64> + Send>> {
+    Box::pin(async move {
+        if n < CUTOFF {
+            return fib_in_place(n);
+        }
+        let left = spawn(fib(n - 1));
+        let right = fib(n - 2).await;
+        left.await.unwrap() + right
+    })
+}
+
+fn fib_in_place(n: u64) -> u64 {
+    match n {
+        0 | 1 => n,
+        n => fib_in_place(n - 1) + fib_in_place(n - 2),
+    }
+}
+
+fn fork_join(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("fib({N})"));
+    group.sample_size(20);
+    for workers in [1, 2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::new("workers", workers),
+            &workers,
+            |b, &workers| {
+                b.iter(|| {
+                    // The calling thread works too
+                    Runtime::new(Vector::splat(7))
+                        .workers(workers - 1)
+                        .signals(false)
+                        .run(fib(N))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fork_join);
+criterion_main!(benches);
@@ -0,0 +1,193 @@
+//! Dedicated threads for work that would block a worker, like waiting on a
+//! child process or the file system.
+
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Condvar, Mutex},
+    task::{Context, Waker},
+    thread,
+    time::Duration,
+};
+
+use crate::rt::join::{self, JoinHandle};
+
+pub const DEFAULT_MAX_THREADS: usize = 512;
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct State {
+    jobs: VecDeque<Job>,
+    threads: usize,
+    idle: usize,
+    min: usize,
+    max: usize,
+    keep_alive: Duration,
+}
+
+/// Grows a thread for every job that finds no idle one, up to `max`, and
+/// lets threads beyond `min` exit after `keep_alive` without a job.
+struct Pool {
+    state: Mutex<State>,
+    available: Condvar,
+}
+
+static POOL: Pool = Pool {
+    state: Mutex::new(State {
+        jobs: VecDeque::new(),
+        threads: 0,
+        idle: 0,
+        min: 0,
+        max: DEFAULT_MAX_THREADS,
+        keep_alive: DEFAULT_KEEP_ALIVE,
+    }),
+    available: Condvar::new(),
+};
+
+/// Sizes the pool, see [`Runtime::blocking_threads`](crate::rt::Runtime::blocking_threads).
+pub fn configure(min: usize, max: usize, keep_alive: Duration) {
+    assert!(
+        min <= max && max > 0,
+        "blocking pool of {min} to {max} threads"
+    );
+    let mut state = POOL.state.lock().unwrap();
+    state.min = min;
+    state.max = max;
+    state.keep_alive = keep_alive;
+    while state.threads < min {
+        state.threads += 1;
+        start();
+    }
+}
+
+/// Threads the pool has, busy or idle.
+pub fn threads() -> usize {
+    POOL.state.lock().unwrap().threads
+}
+
+/// Runs `f` on the blocking pool, returning a handle that resolves to its
+/// output. The task awaiting the handle is woken through its worker's queue
+/// once `f` returns, so workers are free in the meantime.
+pub fn spawn_blocking<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (mut task, handle) = join::task(async move { f() });
+    let job = move || {
+        // Done in one poll, or skipped if aborted before it got a thread
+        let _ = Pin::new(&mut task).poll(&mut Context::from_waker(Waker::noop()));
+    };
+    let mut state = POOL.state.lock().unwrap();
+    state.jobs.push_back(Box::new(job));
+    // Idle threads only count while there are enough of them for every job
+    if state.jobs.len() <= state.idle {
+        POOL.available.notify_one();
+    } else if state.threads < state.max {
+        state.threads += 1;
+        start();
+    }
+    handle
+}
+
+fn start() {
+    thread::Builder::new()
+        .name("blocking".into())
+        .spawn(run)
+        .expect("failed to spawn blocking thread");
+}
+
+fn run() {
+    let mut state = POOL.state.lock().unwrap();
+    loop {
+        if let Some(job) = state.jobs.pop_front() {
+            drop(state);
+            job();
+            state = POOL.state.lock().unwrap();
+            continue;
+        }
+        state.idle += 1;
+        let keep_alive = state.keep_alive;
+        let (guard, wait) = POOL.available.wait_timeout(state, keep_alive).unwrap();
+        state = guard;
+        state.idle -= 1;
+        if wait.timed_out() && state.jobs.is_empty() && state.threads > state.min {
+            state.threads -= 1;
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rt::{block_on, sleep, spawn, tests::runtime},
+        time::{self, Instant, Millis},
+    };
+    use std::{
+        sync::{Arc, Barrier},
+        time::Instant as StdInstant,
+    };
+
+    #[test]
+    fn test_timers_fire_while_blocking() {
+        runtime();
+        // More blocking calls than there are workers, all in flight at once
+        let blockers = (0..16)
+            .map(|i| {
+                spawn(async move {
+                    spawn_blocking(move || {
+                        thread::sleep(Duration::from_millis(300));
+                        i
+                    })
+                    .await
+                    .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        let timer = spawn(async {
+            let start = Instant::now();
+            sleep(time::Duration::new(Millis(50))).await;
+            start.elapsed()
+        });
+
+        let slept = block_on(timer).unwrap();
+        assert!(
+            slept < time::Duration::new(Millis(150)),
+            "timer fired after {slept:?}"
+        );
+        let sum = blockers
+            .into_iter()
+            .map(|blocker| block_on(blocker).unwrap())
+            .sum::<i32>();
+        assert_eq!(sum, (0..16).sum());
+    }
+
+    #[test]
+    fn test_idle_threads_exit() {
+        runtime();
+        let barrier = Arc::new(Barrier::new(9));
+        let handles = (0..8)
+            .map(|_| {
+                let barrier = barrier.clone();
+                spawn_blocking(move || {
+                    barrier.wait();
+                })
+            })
+            .collect::<Vec<_>>();
+        // Eight jobs only meet at the barrier on eight threads
+        assert!(threads() >= 8);
+        barrier.wait();
+        for handle in handles {
+            block_on(handle).unwrap();
+        }
+
+        let start = StdInstant::now();
+        while threads() > 0 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(threads(), 0);
+    }
+}
@@ -1,53 +1,263 @@
 use std::{
+    any::Any,
+    fmt,
     future::Future,
+    mem,
+    panic::{AssertUnwindSafe, catch_unwind},
     pin::Pin,
-    task::{Context, Poll},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering::*},
+    },
+    task::{Context, Poll, Waker},
 };
 
+/// Awaits every future pushed, resolving to their outputs in the order they
+/// finished.
 pub struct UnorderedJoin<T> {
     futures: Vec<Option<Pin<Box<dyn Future<Output = T> + Send>>>>,
-    len: usize,
+    /// Outputs of the futures that finished in earlier polls.
+    completed: Vec<T>,
 }
 
 impl<T: Send + 'static> UnorderedJoin<T> {
     pub fn new() -> Self {
         Self {
             futures: Vec::new(),
-            len: 0,
+            completed: Vec::new(),
         }
     }
 
     pub fn push(&mut self, future: impl Future<Output = T> + Send + 'static) {
         self.futures.push(Some(Box::pin(future)));
-        self.len += 1;
     }
 }
 
+// The futures are boxed and the outputs are never pinned
+impl<T> Unpin for UnorderedJoin<T> {}
+
 impl<T: Send + 'static> Future for UnorderedJoin<T> {
     type Output = Vec<T>;
 
-    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let mut completed = Vec::new();
-        let mut remaining = self.len;
-
-        // Poll remaining futures
-        for i in 0..self.futures.len() {
-            if let Some(fut) = self.futures[i].as_mut() {
-                match fut.as_mut().poll(cx) {
-                    Poll::Ready(val) => {
-                        completed.push(val);
-                        self.futures[i] = None;
-                        remaining -= 1;
-                    }
-                    Poll::Pending => {}
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        for slot in &mut this.futures {
+            if let Some(future) = slot
+                && let Poll::Ready(output) = future.as_mut().poll(cx)
+            {
+                this.completed.push(output);
+                *slot = None;
+            }
+        }
+        match this.futures.iter().all(Option::is_none) {
+            true => Poll::Ready(mem::take(&mut this.completed)),
+            false => Poll::Pending,
+        }
+    }
+}
+
+/// Why a spawned task finished without an output.
+#[derive(Debug)]
+pub enum JoinError {
+    /// Aborted through its [`JoinHandle`], or dropped by the runtime before it
+    /// finished.
+    Cancelled,
+    /// The task panicked, with the payload it panicked with.
+    Panicked(Box<dyn Any + Send>),
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(f, "task was cancelled"),
+            JoinError::Panicked(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str));
+                match message {
+                    Some(message) => write!(f, "task panicked: {message}"),
+                    None => write!(f, "task panicked"),
                 }
             }
         }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+enum State<T> {
+    Running,
+    Finished(Result<T, JoinError>),
+    Joined,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    aborted: AtomicBool,
+    /// Wakes the task, so an abort drops its future without waiting on it.
+    task: Mutex<Option<Waker>>,
+    /// Wakes whoever awaits the handle.
+    joiner: Mutex<Option<Waker>>,
+}
+
+impl<T> Shared<T> {
+    /// Settles the output, unless it already was.
+    fn finish(&self, output: Result<T, JoinError>) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, State::Running) {
+            return;
+        }
+        *state = State::Finished(output);
+        drop(state);
+        if let Some(joiner) = self.joiner.lock().unwrap().take() {
+            joiner.wake();
+        }
+    }
+}
+
+/// Wraps a spawned future to hand its output, panic or cancellation to the
+/// [`JoinHandle`] returned alongside.
+pub(crate) fn task<F: Future>(future: F) -> (Guarded<F>, JoinHandle<F::Output>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State::Running),
+        aborted: AtomicBool::new(false),
+        task: Mutex::new(None),
+        joiner: Mutex::new(None),
+    });
+    let guarded = Guarded {
+        future: Some(Box::pin(future)),
+        shared: shared.clone(),
+    };
+    (guarded, JoinHandle { shared })
+}
+
+/// The future a spawned task runs in place of the one it was given.
+pub(crate) struct Guarded<F: Future> {
+    future: Option<Pin<Box<F>>>,
+    shared: Arc<Shared<F::Output>>,
+}
+
+impl<F: Future> Future for Guarded<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Guarded { future, shared } = self.get_mut();
+        if shared.aborted.load(Acquire) {
+            *future = None;
+            return Poll::Ready(());
+        }
+        let Some(pinned) = future.as_mut() else {
+            return Poll::Ready(());
+        };
+        *shared.task.lock().unwrap() = Some(cx.waker().clone());
+        // A panic ends this task only, not the worker polling it
+        let output = match catch_unwind(AssertUnwindSafe(|| pinned.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => return Poll::Pending,
+            Ok(Poll::Ready(output)) => Ok(output),
+            Err(payload) => Err(JoinError::Panicked(payload)),
+        };
+        *future = None;
+        shared.finish(output);
+        Poll::Ready(())
+    }
+}
+
+impl<F: Future> Drop for Guarded<F> {
+    fn drop(&mut self) {
+        if self.future.take().is_some() {
+            self.shared.finish(Err(JoinError::Cancelled));
+        }
+    }
+}
+
+/// Resolves to the output of a task started with [`spawn`](crate::rt::spawn) or
+/// [`spawn_local`](crate::rt::spawn_local). Dropping it detaches the task, which
+/// keeps running.
+pub struct JoinHandle<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Cancels the task: it is dropped the next time it would be polled and
+    /// the handle resolves to [`JoinError::Cancelled`] right away. Does nothing
+    /// once the task finished.
+    pub fn abort(&self) {
+        self.shared.aborted.store(true, Release);
+        self.shared.finish(Err(JoinError::Cancelled));
+        if let Some(task) = self.shared.task.lock().unwrap().take() {
+            task.wake();
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !matches!(*self.shared.state.lock().unwrap(), State::Running)
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.state.lock().unwrap();
+        match mem::replace(&mut *state, State::Joined) {
+            State::Finished(output) => Poll::Ready(output),
+            State::Running => {
+                *state = State::Running;
+                // Registered under the state lock, so `finish` cannot miss it
+                *self.shared.joiner.lock().unwrap() = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            State::Joined => panic!("JoinHandle polled after it resolved"),
+        }
+    }
+}
 
-        if remaining == 0 {
-            Poll::Ready(completed)
-        } else {
-            Poll::Pending
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rt::{block_on, sleep, spawn, tests::runtime},
+        time::{Duration, Hours},
+    };
+    use std::thread;
+
+    #[test]
+    fn test_spawn_outputs() {
+        runtime();
+        let handles = (0..1000)
+            .map(|i| spawn(async move { i }))
+            .collect::<Vec<_>>();
+        let sum = handles
+            .into_iter()
+            .map(|handle| block_on(handle).unwrap())
+            .sum::<usize>();
+        assert_eq!(sum, 999 * 1000 / 2);
+    }
+
+    #[test]
+    fn test_abort() {
+        runtime();
+        let sleeper = spawn(sleep(Duration::new(Hours(1))));
+        assert!(!sleeper.is_finished());
+        sleeper.abort();
+        assert!(sleeper.is_finished());
+        assert!(matches!(block_on(sleeper), Err(JoinError::Cancelled)));
+
+        let done = spawn(async { 1 });
+        while !done.is_finished() {
+            thread::yield_now();
         }
+        done.abort();
+        assert_eq!(block_on(done).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_panic_is_caught() {
+        runtime();
+        let error = block_on(spawn(async { panic!("boom") })).unwrap_err();
+        assert!(matches!(error, JoinError::Panicked(_)));
+        assert_eq!(error.to_string(), "task panicked: boom");
+        assert_eq!(block_on(spawn(async { 2 })).unwrap(), 2);
     }
 }
@@ -1,4 +1,3 @@
-#![feature(async_iterator, gen_blocks, coroutines)]
 use std::{
     async_iter::AsyncIterator,
     cell::{OnceCell, UnsafeCell},
@@ -9,7 +8,7 @@ use std::{
     sync::{
         Arc, OnceLock,
         atomic::{
-            AtomicBool, AtomicPtr, AtomicU8, AtomicUsize,
+            AtomicPtr, AtomicU8, AtomicUsize,
             Ordering::{self, *},
         },
     },
@@ -20,34 +19,127 @@ use std::{
     },
 };
 
+pub use blocking::spawn_blocking;
+pub use join::{JoinError, JoinHandle};
 pub use rt_macro::main;
+pub use shutdown::{ShutdownMode, shutdown};
+pub use timer::{Elapsed, Interval, Sleep, Timeout, interval, sleep, sleep_until, timeout};
 
 use derive_more::derive::{Deref, DerefMut, From};
 use flume::{Receiver, r#async::RecvFut, unbounded};
 use pin_project::pin_project;
-use worker::{WorkerHandle, current_worker, select_worker};
+use worker::{Worker, WorkerHandle, current_worker, select_worker};
 
-use collections::{bi::BiMap, queue::Queue, skip::Map};
-use math::Vector;
-use sync::{backoff::Wait, barrier::Barrier, split::Split};
+use crate::{
+    collections::{bi::BiMap, queue::Queue, skip::Map},
+    math::Vector,
+    sync::{backoff::Wait, barrier::Barrier, split::Split},
+};
 
+pub mod blocking;
 pub mod join;
+pub mod metrics;
+pub mod reactor;
+pub mod shutdown;
+pub mod timer;
 pub mod worker;
 
+/// Configures and starts the worker pool.
+pub struct Runtime {
+    seed: Vector<4, u128>,
+    workers: usize,
+    signals: bool,
+    shutdown_deadline: std::time::Duration,
+    blocking_threads: (usize, usize),
+    blocking_keep_alive: std::time::Duration,
+}
+
+impl Runtime {
+    pub fn new(seed: Vector<4, u128>) -> Self {
+        Self {
+            seed,
+            workers: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            signals: true,
+            shutdown_deadline: shutdown::DEFAULT_DEADLINE,
+            blocking_threads: (0, blocking::DEFAULT_MAX_THREADS),
+            blocking_keep_alive: blocking::DEFAULT_KEEP_ALIVE,
+        }
+    }
+
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Whether SIGINT/SIGTERM handlers are installed, see [`shutdown::on_signal`].
+    pub fn signals(mut self, signals: bool) -> Self {
+        self.signals = signals;
+        self
+    }
+
+    pub fn shutdown_deadline(mut self, deadline: std::time::Duration) -> Self {
+        self.shutdown_deadline = deadline;
+        self
+    }
+
+    /// Bounds the threads [`spawn_blocking`] runs on. The pool keeps `min`
+    /// threads around and grows up to `max` while all of them are busy.
+    pub fn blocking_threads(mut self, min: usize, max: usize) -> Self {
+        self.blocking_threads = (min, max);
+        self
+    }
+
+    /// How long a blocking thread beyond the minimum waits for a job before
+    /// it exits.
+    pub fn blocking_keep_alive(mut self, keep_alive: std::time::Duration) -> Self {
+        self.blocking_keep_alive = keep_alive;
+        self
+    }
+
+    pub async fn start(self) -> Arc<Barrier> {
+        let (min, max) = self.blocking_threads;
+        blocking::configure(min, max, self.blocking_keep_alive);
+        shutdown::reset();
+        if self.signals {
+            shutdown::set_deadline(self.shutdown_deadline);
+            shutdown::on_signal();
+        }
+        worker::start(self.seed, self.workers).await
+    }
+
+    /// Runs `future` on the calling thread, which works for the pool in
+    /// between, and returns its output once the pool shut down. Unless
+    /// [`shutdown`] was called already, the pool is shut down gracefully
+    /// within the shutdown deadline once `future` finished. A shutdown from
+    /// elsewhere does not cancel `future`, it can await [`shutdown::token`].
+    pub fn run<F: Future>(self, future: F) -> F::Output {
+        let mode = ShutdownMode::Graceful(self.shutdown_deadline);
+        let start = block_on(self.start());
+        block_on(worker::run(start, future, mode))
+    }
+}
+
+/// Runs `future` on a pool with one worker per core, see [`Runtime::run`].
+pub fn run<F: Future>(future: F) -> F::Output {
+    let seed = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |time| time.as_nanos());
+    Runtime::new(Vector::splat(seed)).run(future)
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct Local;
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct Remote;
 
-pub trait Kind: Copy {
-    type Call;
-    type Fut;
-    type Coro;
+pub trait Kind: Copy + Send + Sync + 'static {
+    type Call: FnOnce();
+    type Fut: Future<Output = ()> + Unpin;
+    type Coro: AsyncIterator<Item = ()> + Unpin;
 
-    fn waker(id: Key<Self>) -> Waker
-    where
-        Self: Sized;
-    async fn schedule(task: Task<Self>);
+    /// Queues `task` to be run. This only pushes to a queue, so wakers can
+    /// call it from any thread without waiting.
+    fn schedule(task: Task<Self>);
 }
 
 impl Kind for Local {
@@ -55,18 +147,16 @@ impl Kind for Local {
     type Fut = Pin<Box<dyn Future<Output = ()>>>;
     type Coro = Pin<Box<dyn AsyncIterator<Item = ()>>>;
 
-    fn waker(id: Key<Self>) -> Waker
-    where
-        Self: Sized,
-    {
-        // Create notify handle
-        let notify = Arc::new(Notify::new(id));
-
-        Waker::from(Arc::new(NotifyWaker(notify)))
-    }
-
-    async fn schedule(task: Task<Self>) {
-        current_worker().await.unwrap().local.enqueue(task).await;
+    fn schedule(task: Task<Self>) {
+        if !worker::running_tasks() {
+            // Dropping the task cancels it
+            return;
+        }
+        // Wakers run on any thread, the task goes back to where it lives
+        let worker = task.owner.expect("local task without an owner");
+        worker.local.push(task);
+        worker.metrics.enqueued();
+        worker.unpark();
     }
 }
 
@@ -75,18 +165,24 @@ impl Kind for Remote {
     type Fut = Pin<Box<dyn Future<Output = ()> + Send>>;
     type Coro = Pin<Box<dyn AsyncIterator<Item = ()> + Send>>;
 
-    fn waker(id: Key<Self>) -> Waker
-    where
-        Self: Sized,
-    {
-        // Create notify handle
-        let notify = Arc::new(Notify::new(id));
-
-        Waker::from(Arc::new(NotifyWaker(notify)))
-    }
-
-    async fn schedule(task: Task<Self>) {
-        current_worker().await.unwrap().remote.enqueue(task).await;
+    fn schedule(task: Task<Self>) {
+        if !worker::running_tasks() {
+            return;
+        }
+        match current_worker() {
+            // Runs here unless an idle worker steals it first
+            Some(worker) => {
+                worker.deque.push(task);
+                worker.metrics.enqueued();
+                worker::wake_idle(worker);
+            }
+            None => {
+                let worker = select_worker();
+                worker.remote.push(task);
+                worker.metrics.enqueued();
+                worker.unpark();
+            }
+        }
     }
 }
 
@@ -105,6 +201,8 @@ impl<K: Kind> Wake for NotifyWaker<K> {
     }
 }
 
+/// Holds a pending task until the waker of the poll that left it pending is
+/// woken, then schedules it again.
 pub struct Notify<K: Kind> {
     key: Key<K>,
     task: AtomicPtr<Task<K>>,
@@ -124,40 +222,64 @@ impl<K: Kind> Notify<K> {
         }
     }
 
-    pub fn register(&self, task: Box<Task<K>>) -> bool {
+    /// Parks `task` here, or hands it back if it was woken already.
+    pub fn register(&self, task: Box<Task<K>>) -> Result<(), Box<Task<K>>> {
         // Convert task to raw pointer
         let task_ptr = Box::into_raw(task);
 
         // Store task pointer and mark as waiting
         self.task.store(task_ptr, Release);
 
-        self.state
+        match self
+            .state
             .compare_exchange(Self::EMPTY, Self::WAITING, AcqRel, Acquire)
-            .is_ok()
+        {
+            Ok(_) => Ok(()),
+            Err(_) => {
+                let task_ptr = self.task.swap(std::ptr::null_mut(), AcqRel);
+                Err(unsafe { Box::from_raw(task_ptr) })
+            }
+        }
     }
 
     pub fn notify(&self) {
-        if self.state.swap(Self::NOTIFIED, AcqRel) == Self::WAITING {
-            // Get task pointer
-            let task_ptr = self.task.load(Acquire);
-            if !task_ptr.is_null() {
-                // Reconstruct box from raw pointer
-                let task = unsafe { Box::from_raw(task_ptr) };
-                block_on(K::schedule(*task))
-            }
+        if let Some(task) = self.take() {
+            K::schedule(*task)
         }
     }
+
+    /// Takes the task out if it is still waiting to be woken.
+    fn take(&self) -> Option<Box<Task<K>>> {
+        if self.state.swap(Self::NOTIFIED, AcqRel) != Self::WAITING {
+            return None;
+        }
+        let task_ptr = self.task.swap(std::ptr::null_mut(), AcqRel);
+        // Reconstruct box from raw pointer
+        (!task_ptr.is_null()).then(|| unsafe { Box::from_raw(task_ptr) })
+    }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
-pub struct Key<K: Kind>(usize, PhantomData<K>);
+/// A task waiting to be woken, which a shutdown cancels.
+pub trait Parked: Send + Sync {
+    fn cancel(&self);
+}
+
+impl<K: Kind> Parked for Notify<K> {
+    fn cancel(&self) {
+        drop(self.take());
+    }
+}
 
-impl<K: Kind> Key<K> {
-    fn waker(&self) -> Waker {
-        K::waker(*self)
+impl<K: Kind> Drop for Notify<K> {
+    /// Nothing can wake the task anymore, so it would never finish.
+    fn drop(&mut self) {
+        self.cancel();
     }
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Key<K: Kind>(usize, PhantomData<K>);
+
 #[pin_project]
 pub enum Act<K: Kind> {
     Call(ManuallyDrop<UnsafeCell<K::Call>>),
@@ -170,6 +292,67 @@ pub static KEY: AtomicUsize = AtomicUsize::new(0);
 pub struct Task<K: Kind> {
     pub key: Key<K>,
     pub act: Option<Act<K>>,
+    pub timing: metrics::Timing,
+    /// The worker a local task was spawned on, the only one that may run it.
+    pub owner: Option<&'static Worker>,
+}
+
+impl<K: Kind> Task<K> {
+    pub fn new(act: Act<K>) -> Self {
+        worker::TASKS.fetch_add(1, Relaxed);
+        Self {
+            key: Key(KEY.fetch_add(1, Ordering::AcqRel), PhantomData),
+            act: Some(act),
+            timing: metrics::Timing::new(),
+            owner: None,
+        }
+    }
+
+    /// Polls the task once. A task that is not done is left with the waker
+    /// of this poll, which schedules it again once woken.
+    fn run(mut self) {
+        let notify = Arc::new(Notify::new(self.key));
+        let waker = Arc::new(Waker::from(Arc::new(NotifyWaker(notify.clone()))));
+        current_worker().unwrap().waker = Some(waker.clone());
+        let mut cx = Context::from_waker(&waker);
+        let start = metrics::Stamp::now();
+        let poll = match &mut self.act {
+            Some(Act::Call(call)) => {
+                let func = unsafe { call.get().read() };
+                (func)();
+                self.timing.polled(start);
+                return;
+            }
+            Some(Act::Fut(fut)) => Pin::new(fut).poll(&mut cx),
+            Some(Act::Coro(coro)) => match Pin::new(coro).poll_next(&mut cx) {
+                Ready(Some(())) => {
+                    self.timing.polled(start);
+                    // Yielded, let other tasks run before the next item
+                    K::schedule(self);
+                    return;
+                }
+                Ready(None) => Ready(()),
+                Pending => Pending,
+            },
+            None => unreachable!(),
+        };
+        self.timing.polled(start);
+        if poll.is_pending() {
+            match notify.register(Box::new(self)) {
+                Ok(()) => current_worker()
+                    .unwrap()
+                    .track(Arc::downgrade(&notify) as _),
+                // Woken while it was being polled
+                Err(task) => K::schedule(*task),
+            }
+        }
+    }
+}
+
+impl<K: Kind> Drop for Task<K> {
+    fn drop(&mut self) {
+        worker::TASKS.fetch_sub(1, Release);
+    }
 }
 
 enum Work {
@@ -178,73 +361,16 @@ enum Work {
 }
 
 impl Work {
-    fn execute(mut self) -> Option<Self> {
-        let poll = match &mut self {
-            Work::Local(task) => {
-                let Task { key, act } = task;
-                match act {
-                    Some(Act::Call(call)) => {
-                        let func = unsafe { call.get().read() };
-                        (func)();
-                        Ready(())
-                    }
-                    Some(Act::Fut(fut)) => {
-                        block_on(current_worker()).unwrap().waker = Some(key.waker().into());
-                        let pinned = pin!(fut);
-                        pinned.poll(&mut block_on(context()))
-                    }
-                    Some(Act::Coro(coro)) => {
-                        block_on(current_worker()).unwrap().waker = Some(key.waker().into());
-                        let pinned = pin!(coro);
-                        pinned.poll_next(&mut block_on(context())).map(|_| ())
-                    }
-                    None => unreachable!(),
-                }
-            }
-            Work::Remote(task) => {
-                let Task { key, act } = task;
-                match act {
-                    Some(Act::Call(call)) => {
-                        let func = unsafe { call.get().read() };
-                        (func)();
-                        Ready(())
-                    }
-                    Some(Act::Fut(fut)) => {
-                        block_on(current_worker()).unwrap().waker = Some(key.waker().into());
-                        let pinned = pin!(fut);
-                        pinned.poll(&mut block_on(context()))
-                    }
-                    Some(Act::Coro(coro)) => {
-                        block_on(current_worker()).unwrap().waker = Some(key.waker().into());
-                        let pinned = pin!(coro);
-                        pinned.poll_next(&mut block_on(context())).map(|_| ())
-                    }
-                    None => unreachable!(),
-                }
-            }
-        };
-        match (poll, &self) {
-            (
-                Pending,
-                Work::Local(Task {
-                    act: Some(Act::Fut(_)),
-                    ..
-                }),
-            )
-            | (
-                Pending,
-                Work::Remote(Task {
-                    act: Some(Act::Fut(_)),
-                    ..
-                }),
-            ) => Some(self),
-            _ => None,
+    fn execute(self) {
+        match self {
+            Work::Local(task) => task.run(),
+            Work::Remote(task) => task.run(),
         }
     }
 }
 
 pub async fn waker() -> &'static mut Arc<Waker> {
-    current_worker().await.unwrap().waker.as_mut().unwrap()
+    current_worker().unwrap().waker.as_mut().unwrap()
 }
 
 pub async fn context() -> Context<'static> {
@@ -288,41 +414,58 @@ impl<F: Future> Block<F> {
     }
 }
 
-pub fn block_on<F: Future>(mut future: F) -> F::Output {
-    let mut fut = Block::new(future);
+/// How long [`block_on`] parks at most, for futures that go pending without
+/// arranging to be woken.
+const BLOCK_ON_PARK: std::time::Duration = std::time::Duration::from_millis(1);
+
+/// Unparks the thread blocked on a future once it is woken.
+struct Unpark(std::thread::Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Polls `future` on this thread until it is done, parked in between until
+/// it is woken.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(Unpark(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
     loop {
-        let Poll::Ready(x) = fut.poll() else {
-            continue;
-        };
-        break x;
+        if let Ready(x) = future.as_mut().poll(&mut cx) {
+            break x;
+        }
+        std::thread::park_timeout(BLOCK_ON_PARK);
     }
 }
 
 pub struct YieldNow {
-    yielded: Arc<AtomicBool>,
+    yielded: bool,
 }
 
 impl YieldNow {
     pub fn new() -> Self {
-        Self {
-            yielded: Arc::new(AtomicBool::new(false)),
-        }
+        Self { yielded: false }
     }
 }
 
 impl Future for YieldNow {
     type Output = ();
 
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        if self.yielded.load(Ordering::SeqCst) {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.yielded {
             Poll::Ready(())
         } else {
-            let waker = cx.waker().clone();
-            let yielded = self.yielded.clone();
-            spawn_local(async move {
-                yielded.store(true, Ordering::SeqCst);
-                waker.wake();
-            });
+            // Woken while being polled, the task goes to the back of the
+            // queue. Not spawning for it, that stops working once shut down.
+            self.yielded = true;
+            cx.waker().wake_by_ref();
             Poll::Pending
         }
     }
@@ -541,74 +684,229 @@ pub async fn spawn_generator<C: AsyncIterator<Item = ()> + 'static + Send>(coro:
         yield;
     };
 
-    Remote::schedule(Task {
-        key: Key(KEY.fetch_add(1, Ordering::AcqRel), PhantomData),
-        act: Some(Act::Coro(
-            Box::pin(coro) as Pin<Box<dyn AsyncIterator<Item = ()> + Send + 'static>>
-        )),
-    });
+    Remote::schedule(Task::new(Act::Coro(
+        Box::pin(coro) as Pin<Box<dyn AsyncIterator<Item = ()> + Send + 'static>>
+    )));
 }
 
-pub async fn spawn_blocking<F: FnOnce() -> T + Send + 'static + Clone, T: Send + 'static>(
-    f: F,
-) -> Handle<T> {
-    let (tx, rx) = unbounded();
-    let handle = Handle(rx.clone());
-    let f = move || {
-        tx.send(UnsafeLocal((f)()));
-    };
-    Remote::schedule(Task {
-        key: Key(KEY.fetch_add(1, Ordering::AcqRel), PhantomData),
-        act: Some(Act::Call(ManuallyDrop::new(UnsafeCell::new(
-            Box::new(f) as Box<dyn FnOnce() + Send + 'static>
-        )))),
-    })
-    .await;
+/// Runs `future` on the worker pool, returning a handle that resolves to its
+/// output. Outside of a worker the task lands on the queue of any worker.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (task, handle) = join::task(future);
+    // After a shutdown the task is dropped right away, cancelling it
+    if worker::accepting_tasks() {
+        Remote::schedule(Task::new(Act::Fut(
+            Box::pin(task) as Pin<Box<dyn Future<Output = ()> + Send + 'static>>
+        )));
+    }
     handle
 }
 
-pub async fn spawn<
-    F: IntoFuture<Output = T, IntoFuture = Fut>,
-    Fut: Future<Output = T> + Send + 'static,
-    T: Send + 'static,
->(
-    fut: F,
-) -> Handle<T> {
-    let (tx, rx) = unbounded();
-    let handle = Handle(rx.clone());
-    let fut = fut.into_future();
-    let fut = async move || {
-        tx.send(UnsafeLocal(fut.await));
-    };
-    Remote::schedule(Task {
-        key: Key(KEY.fetch_add(1, Ordering::AcqRel), PhantomData),
-        act: Some(Act::Fut(
-            Box::pin((fut)()) as Pin<Box<dyn Future<Output = ()> + Send + 'static>>
-        )),
-    })
-    .await;
+/// Runs a `!Send` `future` on the current worker, which it never leaves.
+///
+/// # Panics
+///
+/// When called outside of a worker.
+pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let owner = current_worker().expect("spawn_local called outside of a worker");
+    let (task, handle) = join::task(future);
+    if worker::accepting_tasks() {
+        let mut task = Task::new(Act::Fut(
+            Box::pin(task) as Pin<Box<dyn Future<Output = ()> + 'static>>
+        ));
+        task.owner = Some(owner);
+        Local::schedule(task);
+    }
     handle
 }
 
-pub async fn spawn_local<
-    F: IntoFuture<Output = T, IntoFuture = Fut>,
-    Fut: Future<Output = T> + 'static,
-    T: 'static,
->(
-    fut: F,
-) -> Handle<T> {
-    let (tx, rx) = unbounded();
-    let handle = Handle(rx.clone());
-    let fut = fut.into_future();
-    let fut = async move || {
-        tx.send(UnsafeLocal(fut.await));
-    };
-    Local::schedule(Task {
-        key: Key(KEY.fetch_add(1, Ordering::AcqRel), PhantomData),
-        act: Some(Act::Fut(
-            Box::pin((fut)()) as Pin<Box<dyn Future<Output = ()> + 'static>>
-        )),
-    })
-    .await;
-    handle
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{sync::Once, thread};
+
+    /// Starts one pool for every test, on a thread that joins it as a worker.
+    pub(crate) fn runtime() {
+        static START: Once = Once::new();
+        START.call_once(|| {
+            let (tx, rx) = std::sync::mpsc::channel();
+            thread::spawn(move || {
+                block_on(async move {
+                    let start = Runtime::new(Vector::splat(7))
+                        .workers(3)
+                        .signals(false)
+                        .blocking_keep_alive(std::time::Duration::from_millis(100))
+                        .start()
+                        .await;
+                    tx.send(()).unwrap();
+                    worker::worker_start_barrier(start).await;
+                })
+            });
+            rx.recv().unwrap();
+        });
+    }
+
+    fn threads() -> usize {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|line| line.starts_with("Threads:"));
+        line.unwrap()["Threads:".len()..].trim().parse().unwrap()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_run_and_shutdown_repeatedly() {
+        use std::{process::Command, time::Duration};
+
+        // The pool of the other tests must not be shut down, so run apart
+        if std::env::var_os("RT_RUN_CHILD").is_none() {
+            let status = Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "rt::tests::test_run_and_shutdown_repeatedly"])
+                .env("RT_RUN_CHILD", "1")
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+
+        let before = threads();
+        for round in 0..3u128 {
+            let runtime = || {
+                Runtime::new(Vector::splat(round))
+                    .workers(2)
+                    .signals(false)
+                    .shutdown_deadline(Duration::from_secs(5))
+            };
+
+            // Graceful lets a task finish after the main future did
+            let (output, slow) = runtime().run(async {
+                let slow = spawn(async {
+                    for _ in 0..1000 {
+                        yield_now().await;
+                    }
+                    7
+                });
+                (spawn(async { 1 + 1 }).await.unwrap(), slow)
+            });
+            assert_eq!(output, 2);
+            assert_eq!(block_on(slow).unwrap(), 7);
+            assert_eq!(threads(), before, "threads leaked in round {round}");
+
+            // Immediate cancels what is still pending, and nothing is accepted
+            let (pending, after) = runtime().run(async {
+                let pending = spawn(std::future::pending::<()>());
+                spawn(async {}).await.unwrap();
+                shutdown(ShutdownMode::Immediate);
+                (pending, spawn(async { 1 }))
+            });
+            assert!(matches!(block_on(pending), Err(JoinError::Cancelled)));
+            assert!(matches!(block_on(after), Err(JoinError::Cancelled)));
+            assert!(shutdown::token().is_triggered());
+            assert_eq!(threads(), before, "threads leaked in round {round}");
+        }
+    }
+
+    #[test]
+    fn test_children_are_stolen() {
+        use std::{collections::HashSet, time::Duration};
+
+        runtime();
+        let threads = block_on(spawn(async {
+            let children = (0..64)
+                .map(|_| {
+                    spawn(async {
+                        // Long enough for idle workers to wake and steal
+                        let start = std::time::Instant::now();
+                        while start.elapsed() < Duration::from_micros(500) {
+                            std::hint::spin_loop();
+                        }
+                        thread::current().id()
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut threads = HashSet::new();
+            for child in children {
+                threads.insert(child.await.unwrap());
+            }
+            threads
+        }))
+        .unwrap();
+        assert!(threads.len() > 1, "all children ran on one worker");
+    }
+
+    #[test]
+    fn test_local_tasks_never_migrate() {
+        runtime();
+        let moved = block_on(spawn(async {
+            // Keeps the other workers busy stealing meanwhile
+            let busy = (0..32)
+                .map(|_| {
+                    spawn(async {
+                        for _ in 0..10 {
+                            yield_now().await;
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            let locals = (0..32)
+                .map(|_| {
+                    let home = thread::current().id();
+                    spawn_local(async move {
+                        let mut moved = 0;
+                        for _ in 0..10 {
+                            moved += (thread::current().id() != home) as usize;
+                            yield_now().await;
+                        }
+                        moved + (thread::current().id() != home) as usize
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut moved = 0;
+            for local in locals {
+                moved += local.await.unwrap();
+            }
+            for busy in busy {
+                busy.await.unwrap();
+            }
+            moved
+        }))
+        .unwrap();
+        assert_eq!(moved, 0);
+
+        // Woken from a blocking thread and from whichever worker finishes the
+        // remote task, both resume on the worker that spawned them
+        let moved = block_on(spawn(async {
+            let home = thread::current().id();
+            let blocked = spawn_local(async move {
+                let woken_by = spawn_blocking(|| {
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                    thread::current().id()
+                })
+                .await
+                .unwrap();
+                assert_ne!(woken_by, home);
+                thread::current().id() != home
+            });
+            let remote = spawn_local(async move {
+                spawn(async {
+                    for _ in 0..100 {
+                        yield_now().await;
+                    }
+                })
+                .await
+                .unwrap();
+                thread::current().id() != home
+            });
+            blocked.await.unwrap() as usize + remote.await.unwrap() as usize
+        }))
+        .unwrap();
+        assert_eq!(moved, 0);
+    }
 }
@@ -0,0 +1,393 @@
+//! Counters of the workers and timings of the tasks, for debugging the
+//! runtime.
+//!
+//! They are only kept with the `rt-metrics` feature. Without it every counter
+//! is an empty struct whose updates compile to nothing, and [`snapshot`]
+//! returns zeros.
+
+use std::{fmt, time::Duration};
+
+#[cfg(feature = "rt-metrics")]
+use std::{
+    sync::atomic::{AtomicU64, Ordering::Relaxed},
+    time::Instant,
+};
+
+use crate::rt::{block_on, worker};
+
+static SPAWNED: Counter = Counter::new();
+static DROPPED: Counter = Counter::new();
+static POLLS: Counter = Counter::new();
+static MAX_POLLS: Counter = Counter::new();
+static POLL_NANOS: Counter = Counter::new();
+static MAX_POLL_NANOS: Counter = Counter::new();
+static FIRST_POLL_NANOS: Counter = Counter::new();
+static MAX_FIRST_POLL_NANOS: Counter = Counter::new();
+
+/// A relaxed atomic counter, or nothing without `rt-metrics`.
+#[derive(Debug, Default)]
+pub struct Counter {
+    #[cfg(feature = "rt-metrics")]
+    value: AtomicU64,
+}
+
+#[cfg(feature = "rt-metrics")]
+impl Counter {
+    pub const fn new() -> Self {
+        Self {
+            value: AtomicU64::new(0),
+        }
+    }
+
+    #[inline(always)]
+    pub fn add(&self, n: u64) {
+        self.value.fetch_add(n, Relaxed);
+    }
+
+    #[inline(always)]
+    pub fn sub(&self, n: u64) {
+        self.value.fetch_sub(n, Relaxed);
+    }
+
+    /// Raises the counter to `n` if it is below, for high-water marks.
+    #[inline(always)]
+    pub fn max(&self, n: u64) {
+        self.value.fetch_max(n, Relaxed);
+    }
+
+    #[inline(always)]
+    pub fn get(&self) -> u64 {
+        self.value.load(Relaxed)
+    }
+}
+
+#[cfg(not(feature = "rt-metrics"))]
+impl Counter {
+    pub const fn new() -> Self {
+        Self {}
+    }
+
+    #[inline(always)]
+    pub fn add(&self, _: u64) {}
+
+    #[inline(always)]
+    pub fn sub(&self, _: u64) {}
+
+    #[inline(always)]
+    pub fn max(&self, _: u64) {}
+
+    #[inline(always)]
+    pub fn get(&self) -> u64 {
+        0
+    }
+}
+
+/// Counters of one worker, updated by whoever touches its queues.
+#[derive(Debug, Default)]
+pub struct WorkerCounters {
+    pub polls: Counter,
+    pub steals: Counter,
+    pub parks: Counter,
+    pub unparks: Counter,
+    /// Tasks in the queues of the worker.
+    pub queued: Counter,
+    pub max_queued: Counter,
+}
+
+impl WorkerCounters {
+    #[inline(always)]
+    pub fn enqueued(&self) {
+        self.queued.add(1);
+        self.max_queued.max(self.queued.get());
+    }
+
+    #[inline(always)]
+    pub fn dequeued(&self) {
+        self.queued.sub(1);
+    }
+
+    fn snapshot(&self) -> WorkerMetrics {
+        WorkerMetrics {
+            polls: self.polls.get(),
+            steals: self.steals.get(),
+            parks: self.parks.get(),
+            unparks: self.unparks.get(),
+            queued: self.queued.get(),
+            max_queued: self.max_queued.get(),
+        }
+    }
+}
+
+/// When a poll started, or nothing without `rt-metrics`.
+#[derive(Clone, Copy)]
+pub struct Stamp {
+    #[cfg(feature = "rt-metrics")]
+    at: Instant,
+}
+
+impl Stamp {
+    #[inline(always)]
+    pub fn now() -> Self {
+        Self {
+            #[cfg(feature = "rt-metrics")]
+            at: Instant::now(),
+        }
+    }
+}
+
+/// Timings of one task, added to the totals once it is dropped.
+pub struct Timing {
+    #[cfg(feature = "rt-metrics")]
+    spawned: Instant,
+    #[cfg(feature = "rt-metrics")]
+    first_poll: Option<Duration>,
+    #[cfg(feature = "rt-metrics")]
+    poll_time: Duration,
+    #[cfg(feature = "rt-metrics")]
+    polls: u64,
+}
+
+impl Timing {
+    #[inline(always)]
+    pub fn new() -> Self {
+        SPAWNED.add(1);
+        Self {
+            #[cfg(feature = "rt-metrics")]
+            spawned: Instant::now(),
+            #[cfg(feature = "rt-metrics")]
+            first_poll: None,
+            #[cfg(feature = "rt-metrics")]
+            poll_time: Duration::ZERO,
+            #[cfg(feature = "rt-metrics")]
+            polls: 0,
+        }
+    }
+
+    /// Counts a poll that started at `start` and just returned.
+    #[inline(always)]
+    pub fn polled(&mut self, start: Stamp) {
+        #[cfg(feature = "rt-metrics")]
+        {
+            self.first_poll
+                .get_or_insert(start.at.duration_since(self.spawned));
+            self.poll_time += start.at.elapsed();
+            self.polls += 1;
+        }
+        #[cfg(not(feature = "rt-metrics"))]
+        let _ = start;
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Timing {
+    fn drop(&mut self) {
+        DROPPED.add(1);
+        #[cfg(feature = "rt-metrics")]
+        {
+            POLLS.add(self.polls);
+            MAX_POLLS.max(self.polls);
+            let poll_time = self.poll_time.as_nanos() as u64;
+            POLL_NANOS.add(poll_time);
+            MAX_POLL_NANOS.max(poll_time);
+            if let Some(first_poll) = self.first_poll {
+                let first_poll = first_poll.as_nanos() as u64;
+                FIRST_POLL_NANOS.add(first_poll);
+                MAX_FIRST_POLL_NANOS.max(first_poll);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkerMetrics {
+    /// Tasks polled.
+    pub polls: u64,
+    /// Tasks taken from the queue of another worker.
+    pub steals: u64,
+    /// Times the worker went to sleep for lack of work.
+    pub parks: u64,
+    /// Times the worker was woken, or kept from sleeping.
+    pub unparks: u64,
+    /// Tasks queued right now.
+    pub queued: u64,
+    /// Most tasks ever queued at once.
+    pub max_queued: u64,
+}
+
+/// Timings of the tasks that were dropped, after finishing or being
+/// cancelled. Tasks still alive are only in `spawned`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskMetrics {
+    pub spawned: u64,
+    pub dropped: u64,
+    pub polls: u64,
+    /// Most polls of a single task.
+    pub max_polls: u64,
+    /// Time spent polling, for all tasks.
+    pub poll_time: Duration,
+    /// Most time spent polling a single task.
+    pub max_poll_time: Duration,
+    /// Time from spawning to the first poll, for all tasks.
+    pub first_poll_delay: Duration,
+    pub max_first_poll_delay: Duration,
+}
+
+impl TaskMetrics {
+    pub fn mean_poll_time(&self) -> Duration {
+        mean(self.poll_time, self.polls)
+    }
+
+    pub fn mean_first_poll_delay(&self) -> Duration {
+        mean(self.first_poll_delay, self.dropped)
+    }
+}
+
+fn mean(total: Duration, count: u64) -> Duration {
+    match count {
+        0 => Duration::ZERO,
+        count => Duration::from_nanos((total.as_nanos() / count as u128) as u64),
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// One per worker of the running pool.
+    pub workers: Vec<WorkerMetrics>,
+    pub tasks: TaskMetrics,
+}
+
+/// Reads every counter. They are read one after another while the pool keeps
+/// running, so they need not add up exactly.
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        workers: worker::all_workers()
+            .map(|worker| worker.metrics.snapshot())
+            .collect(),
+        tasks: TaskMetrics {
+            spawned: SPAWNED.get(),
+            dropped: DROPPED.get(),
+            polls: POLLS.get(),
+            max_polls: MAX_POLLS.get(),
+            poll_time: Duration::from_nanos(POLL_NANOS.get()),
+            max_poll_time: Duration::from_nanos(MAX_POLL_NANOS.get()),
+            first_poll_delay: Duration::from_nanos(FIRST_POLL_NANOS.get()),
+            max_first_poll_delay: Duration::from_nanos(MAX_FIRST_POLL_NANOS.get()),
+        },
+    }
+}
+
+/// Prints a [`snapshot`] to stderr.
+pub fn dump() {
+    eprintln!("{}", snapshot());
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>6} {:>10} {:>8} {:>8} {:>8} {:>8} {:>10}",
+            "worker", "polls", "steals", "parks", "unparks", "queued", "max queued"
+        )?;
+        for (index, worker) in self.workers.iter().enumerate() {
+            writeln!(
+                f,
+                "{index:>6} {:>10} {:>8} {:>8} {:>8} {:>8} {:>10}",
+                worker.polls,
+                worker.steals,
+                worker.parks,
+                worker.unparks,
+                worker.queued,
+                worker.max_queued
+            )?;
+        }
+        let tasks = &self.tasks;
+        writeln!(
+            f,
+            "tasks: {} spawned, {} dropped, {} polls (at most {} per task)",
+            tasks.spawned, tasks.dropped, tasks.polls, tasks.max_polls
+        )?;
+        writeln!(
+            f,
+            "poll time: {:?} in all, {:?} per poll, at most {:?} per task",
+            tasks.poll_time,
+            tasks.mean_poll_time(),
+            tasks.max_poll_time
+        )?;
+        write!(
+            f,
+            "first poll: {:?} after spawning on average, at most {:?}",
+            tasks.mean_first_poll_delay(),
+            tasks.max_first_poll_delay
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::{spawn, tests::runtime, yield_now};
+
+    #[cfg(feature = "rt-metrics")]
+    #[test]
+    fn test_counters_of_known_work() {
+        const TASKS: u64 = 200;
+        const YIELDS: u64 = 4;
+
+        runtime();
+        let before = snapshot();
+        let handles = (0..TASKS)
+            .map(|_| {
+                spawn(async {
+                    for _ in 0..YIELDS {
+                        yield_now().await;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            block_on(handle).unwrap();
+        }
+        let after = snapshot();
+
+        // Other tests share the pool, so only lower bounds hold for the deltas
+        let tasks = &after.tasks;
+        assert!(tasks.spawned - before.tasks.spawned >= TASKS);
+        assert!(tasks.dropped - before.tasks.dropped >= TASKS);
+        assert!(tasks.polls - before.tasks.polls >= TASKS * (YIELDS + 1));
+        assert!(tasks.max_polls > YIELDS);
+        assert!(tasks.poll_time > before.tasks.poll_time);
+        assert!(tasks.max_first_poll_delay < Duration::from_secs(10));
+
+        assert!(!after.workers.is_empty());
+        let polls = |snapshot: &Snapshot| snapshot.workers.iter().map(|w| w.polls).sum::<u64>();
+        assert!(polls(&after) - polls(&before) >= TASKS * (YIELDS + 1));
+        assert!(after.workers.iter().any(|w| w.max_queued > 0));
+        assert!(after.workers.iter().all(|w| w.queued <= w.max_queued));
+        assert!(after.workers.iter().map(|w| w.unparks).sum::<u64>() > 0);
+
+        let dump = after.to_string();
+        assert_eq!(dump.lines().count(), after.workers.len() + 4);
+    }
+
+    #[cfg(not(feature = "rt-metrics"))]
+    #[test]
+    fn test_counters_are_off() {
+        runtime();
+        block_on(spawn(async { yield_now().await })).unwrap();
+        let snapshot = snapshot();
+        assert_eq!(snapshot.tasks, TaskMetrics::default());
+        assert!(
+            snapshot
+                .workers
+                .iter()
+                .all(|w| *w == WorkerMetrics::default())
+        );
+        assert_eq!(size_of::<Timing>(), 0);
+        assert_eq!(size_of::<WorkerCounters>(), 0);
+    }
+}
@@ -0,0 +1,529 @@
+//! Readiness of sockets, on epoll or kqueue, polled by every worker.
+//!
+//! Each worker owns a [`Reactor`] and polls it instead of sleeping while it
+//! has nothing to run, and every
+//! [`POLL_INTERVAL`](crate::rt::worker::POLL_INTERVAL) tasks while it is busy.
+//! Sources are registered edge-triggered for reading and writing at once. A
+//! [`Registration`] remembers whether its source was last seen ready in each
+//! direction: operations are tried while it is, and a `WouldBlock` parks the
+//! task until the next edge.
+
+use std::{
+    collections::HashMap,
+    future::poll_fn,
+    io,
+    os::fd::RawFd,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering::*},
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use crate::rt::{block_on, worker};
+
+/// Token of the event that interrupts a poll.
+const NOTIFY: u64 = 0;
+const EVENTS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interest {
+    Read,
+    Write,
+}
+
+#[derive(Default)]
+struct Readiness {
+    ready: bool,
+    /// Counts the edges seen, so a `WouldBlock` does not clear readiness
+    /// that arrived after the operation was tried.
+    tick: u64,
+    waker: Option<Waker>,
+}
+
+struct Source {
+    directions: Mutex<[Readiness; 2]>,
+}
+
+impl Source {
+    fn poll_ready(&self, interest: Interest, cx: &mut Context<'_>) -> Poll<u64> {
+        let mut directions = self.directions.lock().unwrap();
+        let direction = &mut directions[interest as usize];
+        if direction.ready {
+            return Poll::Ready(direction.tick);
+        }
+        match &direction.waker {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => direction.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+
+    fn clear(&self, interest: Interest, tick: u64) {
+        let mut directions = self.directions.lock().unwrap();
+        let direction = &mut directions[interest as usize];
+        if direction.tick == tick {
+            direction.ready = false;
+        }
+    }
+
+    fn set_ready(&self, readable: bool, writable: bool, wakers: &mut Vec<Waker>) {
+        let mut directions = self.directions.lock().unwrap();
+        for (direction, ready) in directions.iter_mut().zip([readable, writable]) {
+            if ready {
+                direction.ready = true;
+                direction.tick += 1;
+                wakers.extend(direction.waker.take());
+            }
+        }
+    }
+}
+
+pub struct Reactor {
+    poller: sys::Poller,
+    events: Mutex<sys::Events>,
+    sources: Mutex<HashMap<u64, Arc<Source>>>,
+    next_token: AtomicU64,
+    /// Set once the poller was notified, until a poll consumes it.
+    notified: AtomicBool,
+}
+
+impl Reactor {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            poller: sys::Poller::new()?,
+            events: Mutex::new(sys::Events::with_capacity(EVENTS)),
+            sources: Mutex::new(HashMap::new()),
+            next_token: AtomicU64::new(NOTIFY + 1),
+            notified: AtomicBool::new(false),
+        })
+    }
+
+    /// Interrupts the current poll, or makes the next one return right away.
+    pub fn notify(&self) {
+        if !self.notified.swap(true, AcqRel) {
+            self.poller
+                .notify()
+                .expect("failed to notify the io reactor");
+        }
+    }
+
+    /// Waits up to `timeout` for sources to become ready, and wakes the
+    /// tasks waiting for them.
+    pub fn poll(&self, timeout: Duration) {
+        // Somebody else is polling already
+        let Ok(mut events) = self.events.try_lock() else {
+            return;
+        };
+        match self.poller.wait(&mut events, timeout) {
+            Ok(()) => {}
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => return,
+            Err(error) => panic!("failed to poll the io reactor: {error}"),
+        }
+        let mut wakers = vec![];
+        {
+            let sources = self.sources.lock().unwrap();
+            for event in events.iter() {
+                let (token, readable, writable) = sys::readiness(event);
+                if token == NOTIFY {
+                    self.poller.drain();
+                    self.notified.store(false, Release);
+                } else if let Some(source) = sources.get(&token) {
+                    source.set_ready(readable, writable, &mut wakers);
+                }
+            }
+        }
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// A file descriptor registered with the reactor of the worker that
+/// created it. The descriptor has to be non-blocking, and stay open until
+/// the registration is dropped.
+pub struct Registration {
+    reactor: &'static Reactor,
+    token: u64,
+    fd: RawFd,
+    source: Arc<Source>,
+}
+
+impl Registration {
+    pub fn new(fd: RawFd) -> io::Result<Self> {
+        let reactor = match worker::current_worker() {
+            Some(worker) => &worker.reactor,
+            None => &worker::select_worker().reactor,
+        };
+        let token = reactor.next_token.fetch_add(1, Relaxed);
+        // Not known to be blocked yet, so the first attempt goes ahead
+        let source = Arc::new(Source {
+            directions: Mutex::new([true, true].map(|ready| Readiness {
+                ready,
+                ..Default::default()
+            })),
+        });
+        reactor
+            .sources
+            .lock()
+            .unwrap()
+            .insert(token, source.clone());
+        if let Err(error) = reactor.poller.add(fd, token) {
+            reactor.sources.lock().unwrap().remove(&token);
+            return Err(error);
+        }
+        Ok(Self {
+            reactor,
+            token,
+            fd,
+            source,
+        })
+    }
+
+    /// Runs `op` once the source is ready for `interest`, and again after
+    /// every `WouldBlock` once it is ready again.
+    pub async fn io<R>(
+        &self,
+        interest: Interest,
+        mut op: impl FnMut() -> io::Result<R>,
+    ) -> io::Result<R> {
+        loop {
+            let tick = poll_fn(|cx| self.source.poll_ready(interest, cx)).await;
+            match op() {
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
+                    self.source.clear(interest, tick)
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.reactor.sources.lock().unwrap().remove(&self.token);
+        // Closing the descriptor would deregister it as well
+        let _ = self.reactor.poller.delete(self.fd);
+    }
+}
+
+fn cvt(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use std::{
+        io,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        time::Duration,
+    };
+
+    use super::{NOTIFY, cvt};
+
+    pub type Events = Vec<libc::epoll_event>;
+
+    pub struct Poller {
+        epoll: OwnedFd,
+        notifier: OwnedFd,
+    }
+
+    impl Poller {
+        pub fn new() -> io::Result<Self> {
+            let epoll = cvt(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+            let epoll = unsafe { OwnedFd::from_raw_fd(epoll) };
+            let notifier =
+                cvt(unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) })?;
+            let notifier = unsafe { OwnedFd::from_raw_fd(notifier) };
+            let poller = Self { epoll, notifier };
+            poller.ctl(
+                libc::EPOLL_CTL_ADD,
+                poller.notifier.as_raw_fd(),
+                libc::EPOLLIN,
+                NOTIFY,
+            )?;
+            Ok(poller)
+        }
+
+        fn ctl(
+            &self,
+            op: libc::c_int,
+            fd: RawFd,
+            events: libc::c_int,
+            token: u64,
+        ) -> io::Result<()> {
+            let mut event = libc::epoll_event {
+                events: events as u32,
+                u64: token,
+            };
+            cvt(unsafe { libc::epoll_ctl(self.epoll.as_raw_fd(), op, fd, &mut event) })?;
+            Ok(())
+        }
+
+        pub fn add(&self, fd: RawFd, token: u64) -> io::Result<()> {
+            let events = libc::EPOLLIN | libc::EPOLLOUT | libc::EPOLLRDHUP | libc::EPOLLET;
+            self.ctl(libc::EPOLL_CTL_ADD, fd, events, token)
+        }
+
+        pub fn delete(&self, fd: RawFd) -> io::Result<()> {
+            self.ctl(libc::EPOLL_CTL_DEL, fd, 0, 0)
+        }
+
+        pub fn notify(&self) -> io::Result<()> {
+            let one = 1u64.to_ne_bytes();
+            cvt(unsafe { libc::write(self.notifier.as_raw_fd(), one.as_ptr().cast(), 8) } as _)?;
+            Ok(())
+        }
+
+        /// Resets the notifier, which stays readable until then.
+        pub fn drain(&self) {
+            let mut count = [0u8; 8];
+            unsafe { libc::read(self.notifier.as_raw_fd(), count.as_mut_ptr().cast(), 8) };
+        }
+
+        pub fn wait(&self, events: &mut Events, timeout: Duration) -> io::Result<()> {
+            events.clear();
+            let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+            let count = cvt(unsafe {
+                libc::epoll_wait(
+                    self.epoll.as_raw_fd(),
+                    events.as_mut_ptr(),
+                    events.capacity() as libc::c_int,
+                    timeout,
+                )
+            })?;
+            unsafe { events.set_len(count as usize) };
+            Ok(())
+        }
+    }
+
+    /// The token of `event`, and whether it reports the source readable and
+    /// writable. Errors and hang-ups count as both, so the next operation
+    /// runs into them.
+    pub fn readiness(event: &libc::epoll_event) -> (u64, bool, bool) {
+        let events = event.events as libc::c_int;
+        let closed = events & (libc::EPOLLERR | libc::EPOLLHUP) != 0;
+        (
+            event.u64,
+            closed || events & (libc::EPOLLIN | libc::EPOLLRDHUP) != 0,
+            closed || events & libc::EPOLLOUT != 0,
+        )
+    }
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+mod sys {
+    use std::{
+        io, mem,
+        os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        ptr,
+        time::Duration,
+    };
+
+    use super::{NOTIFY, cvt};
+
+    pub type Events = Vec<libc::kevent>;
+
+    pub struct Poller {
+        kqueue: OwnedFd,
+    }
+
+    fn kevent(ident: usize, filter: i16, flags: u16, fflags: u32, token: u64) -> libc::kevent {
+        libc::kevent {
+            ident: ident as _,
+            filter: filter as _,
+            flags: flags as _,
+            fflags: fflags as _,
+            udata: token as _,
+            ..unsafe { mem::zeroed() }
+        }
+    }
+
+    impl Poller {
+        pub fn new() -> io::Result<Self> {
+            let kqueue = cvt(unsafe { libc::kqueue() })?;
+            let kqueue = unsafe { OwnedFd::from_raw_fd(kqueue) };
+            cvt(unsafe { libc::fcntl(kqueue.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) })?;
+            let poller = Self { kqueue };
+            poller.change(&[kevent(
+                NOTIFY as usize,
+                libc::EVFILT_USER,
+                libc::EV_ADD | libc::EV_CLEAR,
+                0,
+                NOTIFY,
+            )])?;
+            Ok(poller)
+        }
+
+        fn change(&self, changes: &[libc::kevent]) -> io::Result<()> {
+            cvt(unsafe {
+                libc::kevent(
+                    self.kqueue.as_raw_fd(),
+                    changes.as_ptr(),
+                    changes.len() as _,
+                    ptr::null_mut(),
+                    0,
+                    ptr::null(),
+                )
+            })?;
+            Ok(())
+        }
+
+        pub fn add(&self, fd: RawFd, token: u64) -> io::Result<()> {
+            let flags = libc::EV_ADD | libc::EV_CLEAR;
+            self.change(&[
+                kevent(fd as usize, libc::EVFILT_READ, flags, 0, token),
+                kevent(fd as usize, libc::EVFILT_WRITE, flags, 0, token),
+            ])
+        }
+
+        pub fn delete(&self, fd: RawFd) -> io::Result<()> {
+            self.change(&[
+                kevent(fd as usize, libc::EVFILT_READ, libc::EV_DELETE, 0, 0),
+                kevent(fd as usize, libc::EVFILT_WRITE, libc::EV_DELETE, 0, 0),
+            ])
+        }
+
+        pub fn notify(&self) -> io::Result<()> {
+            self.change(&[kevent(
+                NOTIFY as usize,
+                libc::EVFILT_USER,
+                0,
+                libc::NOTE_TRIGGER,
+                NOTIFY,
+            )])
+        }
+
+        /// The user event clears itself once delivered.
+        pub fn drain(&self) {}
+
+        pub fn wait(&self, events: &mut Events, timeout: Duration) -> io::Result<()> {
+            events.clear();
+            let timeout = libc::timespec {
+                tv_sec: timeout.as_secs() as _,
+                tv_nsec: timeout.subsec_nanos() as _,
+            };
+            let count = cvt(unsafe {
+                libc::kevent(
+                    self.kqueue.as_raw_fd(),
+                    ptr::null(),
+                    0,
+                    events.as_mut_ptr(),
+                    events.capacity() as _,
+                    &timeout,
+                )
+            })?;
+            unsafe { events.set_len(count as usize) };
+            Ok(())
+        }
+    }
+
+    /// The token of `event`, and whether it reports the source readable and
+    /// writable. Errors count as both, so the next operation runs into them.
+    pub fn readiness(event: &libc::kevent) -> (u64, bool, bool) {
+        let error = event.flags & libc::EV_ERROR != 0;
+        (
+            event.udata as u64,
+            error || event.filter == libc::EVFILT_READ,
+            error || event.filter == libc::EVFILT_WRITE,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::tests::runtime;
+    use std::{
+        io::{Read, Write},
+        os::{fd::AsRawFd, unix::net::UnixStream},
+        pin::pin,
+        thread,
+    };
+
+    #[test]
+    fn test_readiness_wakes_the_task() {
+        let reactor: &'static Reactor = Box::leak(Box::new(Reactor::new().unwrap()));
+        let (mut left, right) = UnixStream::pair().unwrap();
+        right.set_nonblocking(true).unwrap();
+        let token = reactor.next_token.fetch_add(1, Relaxed);
+        let source = Arc::new(Source {
+            directions: Mutex::new(Default::default()),
+        });
+        reactor
+            .sources
+            .lock()
+            .unwrap()
+            .insert(token, source.clone());
+        reactor.poller.add(right.as_raw_fd(), token).unwrap();
+        let registration = Registration {
+            reactor,
+            token,
+            fd: right.as_raw_fd(),
+            source,
+        };
+
+        let mut buf = [0; 5];
+        {
+            let mut read = pin!(registration.io(Interest::Read, || (&right).read(&mut buf)));
+            let cx = &mut Context::from_waker(Waker::noop());
+            assert!(read.as_mut().poll(cx).is_pending());
+            left.write_all(b"hello").unwrap();
+            reactor.poll(Duration::from_secs(1));
+            assert!(matches!(read.as_mut().poll(cx), Poll::Ready(Ok(5))));
+        }
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn test_notify_interrupts_poll() {
+        let reactor = Arc::new(Reactor::new().unwrap());
+        let notifier = {
+            let reactor = reactor.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                reactor.notify();
+            })
+        };
+        let start = std::time::Instant::now();
+        reactor.poll(Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        notifier.join().unwrap();
+
+        // A notification before the poll is not lost either
+        reactor.notify();
+        let start = std::time::Instant::now();
+        reactor.poll(Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_registration_on_a_worker() {
+        runtime();
+        let (mut left, right) = UnixStream::pair().unwrap();
+        right.set_nonblocking(true).unwrap();
+        let reader = crate::rt::spawn(async move {
+            let registration = Registration::new(right.as_raw_fd())?;
+            let mut buf = vec![0; 4];
+            let read = registration
+                .io(Interest::Read, || (&right).read(&mut buf))
+                .await?;
+            buf.truncate(read);
+            io::Result::Ok(buf)
+        });
+        thread::sleep(Duration::from_millis(20));
+        left.write_all(b"ping").unwrap();
+        assert_eq!(block_on(reader).unwrap().unwrap(), b"ping");
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    future::Future,
+    io::Read,
+    os::fd::FromRawFd,
+    pin::Pin,
+    sync::{
+        Arc, Mutex, Once,
+        atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicUsize, Ordering::*},
+    },
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::rt::{block_on, worker};
+
+/// Exit code used when a second signal arrives while hooks are still running.
+pub const FORCE_EXIT_CODE: i32 = 130;
+/// Exit code used when the hooks did not finish within the shutdown deadline.
+pub const DEADLINE_EXIT_CODE: i32 = 124;
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(10);
+
+static TRIGGERED: AtomicBool = AtomicBool::new(false);
+static SIGNALS: AtomicUsize = AtomicUsize::new(0);
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+static DEADLINE_MS: AtomicU64 = AtomicU64::new(DEFAULT_DEADLINE.as_millis() as u64);
+static WAITERS: Mutex<Vec<Waker>> = Mutex::new(Vec::new());
+static HOOKS: Hooks = Hooks::new();
+static INSTALL: Once = Once::new();
+
+type Hook = Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+/// Ordered set of async cleanup hooks, lowest priority value runs first.
+#[derive(Default)]
+pub struct Hooks {
+    hooks: Mutex<Vec<(u64, Hook)>>,
+}
+
+impl Hooks {
+    pub const fn new() -> Self {
+        Self {
+            hooks: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn register<F, Fut>(&self, priority: u64, hook: F)
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let hook: Hook = Box::new(move || Box::pin(hook()));
+        self.hooks.lock().unwrap().push((priority, hook));
+    }
+
+    /// Runs every registered hook in priority order (registration order for
+    /// ties). Returns false if the deadline passed before all hooks finished.
+    pub async fn run(&self, deadline: Duration) -> bool {
+        let mut hooks = std::mem::take(&mut *self.hooks.lock().unwrap());
+        hooks.sort_by_key(|(priority, _)| *priority);
+        let hooks = async move {
+            for (_, hook) in hooks {
+                hook().await;
+            }
+        };
+        Deadline {
+            future: Box::pin(hooks),
+            at: Instant::now() + deadline,
+            alarm: None,
+        }
+        .await
+    }
+}
+
+struct Deadline<F> {
+    future: Pin<Box<F>>,
+    at: Instant,
+    /// Shared with the thread that wakes whoever polled last at `at`.
+    alarm: Option<Arc<Mutex<Option<Waker>>>>,
+}
+
+impl<F: Future<Output = ()>> Future for Deadline<F> {
+    type Output = bool;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.future.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(true);
+        }
+        if Instant::now() >= self.at {
+            return Poll::Ready(false);
+        }
+        if let Some(alarm) = &self.alarm {
+            *alarm.lock().unwrap() = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        // Hooks run on the shutdown thread, away from any timer wheel, so a
+        // thread of its own sleeps until the deadline.
+        let alarm = Arc::new(Mutex::new(Some(cx.waker().clone())));
+        let waker = alarm.clone();
+        let wait = self.at.saturating_duration_since(Instant::now());
+        thread::Builder::new()
+            .name("shutdown-deadline".into())
+            .spawn(move || {
+                thread::sleep(wait);
+                if let Some(waker) = waker.lock().unwrap().take() {
+                    waker.wake();
+                }
+            })
+            .expect("failed to spawn shutdown deadline thread");
+        self.alarm = Some(alarm);
+        Poll::Pending
+    }
+}
+
+impl<F> Drop for Deadline<F> {
+    fn drop(&mut self) {
+        // The thread sleeps on, but has nobody left to wake
+        if let Some(alarm) = &self.alarm {
+            alarm.lock().unwrap().take();
+        }
+    }
+}
+
+/// Resolves once shutdown has been requested, by signal or by [`trigger`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShutdownToken;
+
+impl ShutdownToken {
+    pub fn is_triggered(&self) -> bool {
+        TRIGGERED.load(Acquire)
+    }
+}
+
+impl Future for ShutdownToken {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.is_triggered() {
+            return Poll::Ready(());
+        }
+        WAITERS.lock().unwrap().push(cx.waker().clone());
+        // Re-check to not miss a trigger that raced the registration.
+        if self.is_triggered() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+pub fn token() -> ShutdownToken {
+    ShutdownToken
+}
+
+/// Requests shutdown without a signal and wakes every task awaiting the token.
+pub fn trigger() {
+    TRIGGERED.store(true, Release);
+    for waker in WAITERS.lock().unwrap().drain(..) {
+        waker.wake();
+    }
+}
+
+/// Untriggers the token for a runtime started after an earlier one shut down.
+pub(crate) fn reset() {
+    TRIGGERED.store(false, Release);
+}
+
+/// How [`shutdown`] deals with the tasks still running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// Lets the tasks finish, cancelling the ones left after the timeout.
+    Graceful(Duration),
+    /// Cancels every task the next time a worker gets to it.
+    Immediate,
+}
+
+/// Shuts the worker pool down: no tasks are accepted from now on, spawning
+/// one returns a handle that resolves to
+/// [`JoinError::Cancelled`](crate::rt::JoinError::Cancelled), and the tasks left
+/// are drained or cancelled depending on `mode`. Triggers the token too.
+///
+/// Off the pool, this waits for the workers to stop and joins their threads.
+/// On a worker it returns right away, and [`Runtime::run`](crate::rt::Runtime::run)
+/// returns once the pool stopped.
+pub fn shutdown(mode: ShutdownMode) {
+    worker::request_shutdown(mode);
+    if worker::current_worker().is_some() {
+        return;
+    }
+    while !worker::is_stopped() {
+        block_on(worker::advance_shutdown());
+        thread::sleep(Duration::from_millis(1));
+    }
+    worker::join_threads();
+}
+
+pub fn register_hook<F, Fut>(priority: u64, hook: F)
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    HOOKS.register(priority, hook);
+}
+
+pub fn set_deadline(deadline: Duration) {
+    DEADLINE_MS.store(deadline.as_millis() as u64, Relaxed);
+}
+
+/// Installs SIGINT/SIGTERM handlers once and returns the shutdown token.
+///
+/// The handler only counts the signal and writes a byte to a self-pipe; a
+/// dedicated thread picks it up, triggers the token, runs the hooks within the
+/// deadline and exits the process. A second signal exits immediately with
+/// [`FORCE_EXIT_CODE`].
+pub fn on_signal() -> ShutdownToken {
+    INSTALL.call_once(|| {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            panic!("failed to create shutdown pipe: {}", std::io::Error::last_os_error());
+        }
+        PIPE.store(fds[1], Release);
+
+        let mut reader = unsafe { std::fs::File::from_raw_fd(fds[0]) };
+        thread::Builder::new()
+            .name("shutdown".into())
+            .spawn(move || {
+                let mut byte = [0u8; 1];
+                loop {
+                    match reader.read(&mut byte) {
+                        Ok(1) => break,
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        _ => return,
+                    }
+                }
+                trigger();
+                let deadline = Duration::from_millis(DEADLINE_MS.load(Relaxed));
+                let code = if block_on(HOOKS.run(deadline)) {
+                    0
+                } else {
+                    DEADLINE_EXIT_CODE
+                };
+                std::process::exit(code);
+            })
+            .expect("failed to spawn shutdown thread");
+
+        for signal in [libc::SIGINT, libc::SIGTERM] {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = handle_signal as extern "C" fn(libc::c_int) as usize;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, std::ptr::null_mut());
+            }
+        }
+    });
+    token()
+}
+
+extern "C" fn handle_signal(_: libc::c_int) {
+    // Only async-signal-safe calls are allowed in here.
+    if SIGNALS.fetch_add(1, AcqRel) > 0 {
+        unsafe { libc::_exit(FORCE_EXIT_CODE) };
+    }
+    let fd = PIPE.load(Acquire);
+    if fd >= 0 {
+        unsafe { libc::write(fd, [1u8].as_ptr().cast(), 1) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        process::Command,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn test_token_trigger() {
+        let token = on_signal();
+        let waiter = thread::spawn(move || block_on(token));
+        trigger();
+        waiter.join().unwrap();
+        assert!(token.is_triggered());
+        // Other tests start runtimes, which expect an untriggered token
+        reset();
+        assert!(!token.is_triggered());
+    }
+
+    #[test]
+    fn test_hooks_priority_order() {
+        let hooks = Hooks::new();
+        let order = Arc::new(Mutex::new(vec![]));
+        for priority in [30, 10, 20, 10] {
+            let order = order.clone();
+            hooks.register(priority, move || async move {
+                order.lock().unwrap().push(priority);
+            });
+        }
+        assert!(block_on(hooks.run(Duration::from_secs(1))));
+        assert_eq!(*order.lock().unwrap(), vec![10, 10, 20, 30]);
+    }
+
+    #[test]
+    fn test_hooks_deadline() {
+        let hooks = Hooks::new();
+        let ran = Arc::new(AtomicBool::new(false));
+        hooks.register(0, std::future::pending::<()>);
+        let after = ran.clone();
+        hooks.register(1, move || async move { after.store(true, SeqCst) });
+        let start = Instant::now();
+        assert!(!block_on(hooks.run(Duration::from_millis(50))));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(!ran.load(SeqCst));
+    }
+
+    #[test]
+    fn test_deadline_sleeps() {
+        struct Unpark(thread::Thread);
+        impl std::task::Wake for Unpark {
+            fn wake(self: Arc<Self>) {
+                self.0.unpark();
+            }
+        }
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut deadline = Deadline {
+            future: Box::pin(std::future::pending::<()>()),
+            at: Instant::now() + Duration::from_millis(50),
+            alarm: None,
+        };
+        let start = Instant::now();
+        let mut polls = 0;
+        // Parks between polls, so a waker that fires right away spins
+        let finished = loop {
+            polls += 1;
+            match Pin::new(&mut deadline).poll(&mut cx) {
+                Poll::Ready(finished) => break finished,
+                Poll::Pending => thread::park(),
+            }
+        };
+        assert!(!finished);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(polls < 10, "polled {polls} times");
+    }
+
+    #[test]
+    fn test_double_signal_forces_exit() {
+        if std::env::var_os("RT_SHUTDOWN_CHILD").is_some() {
+            on_signal();
+            set_deadline(Duration::from_secs(60));
+            register_hook(0, std::future::pending::<()>);
+            unsafe { libc::raise(libc::SIGINT) };
+            while !token().is_triggered() {
+                thread::yield_now();
+            }
+            unsafe { libc::raise(libc::SIGTERM) };
+            unreachable!("second signal must exit the process");
+        }
+
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "rt::shutdown::tests::test_double_signal_forces_exit"])
+            .env("RT_SHUTDOWN_CHILD", "1")
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(FORCE_EXIT_CODE));
+    }
+}
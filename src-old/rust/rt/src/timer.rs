@@ -0,0 +1,282 @@
+//! Sleeping, timeouts and intervals on the timer wheel of the current worker.
+
+use std::{
+    async_iter::AsyncIterator,
+    fmt,
+    future::{Future, poll_fn},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use pin_project::pin_project;
+
+use crate::{
+    rt::{block_on, worker::current_worker},
+    time::{Duration, Instant, Nanos, TimeUnit},
+};
+
+/// Resolves once `duration` has passed, without blocking the worker.
+pub fn sleep<U: TimeUnit>(duration: Duration<U>) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        waker: None,
+    }
+}
+
+pub struct Sleep {
+    deadline: Instant,
+    /// Shared with the timer, which wakes whoever polled last.
+    waker: Option<Arc<Mutex<Option<Waker>>>>,
+}
+
+impl Sleep {
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        if let Some(waker) = &self.waker {
+            *waker.lock().unwrap() = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let Some(worker) = current_worker() else {
+            // No wheel outside of the runtime, so keep getting polled
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        };
+        let waker = Arc::new(Mutex::new(Some(cx.waker().clone())));
+        let timer = waker.clone();
+        block_on(worker.timers.add_at(self.deadline, move || {
+            if let Some(waker) = timer.lock().unwrap().take() {
+                waker.wake();
+            }
+        }));
+        self.waker = Some(waker);
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        // The timer stays on the wheel, but has nobody left to wake
+        if let Some(waker) = &self.waker {
+            waker.lock().unwrap().take();
+        }
+    }
+}
+
+/// The error of a [`timeout`] whose future did not finish in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+/// Runs `future` for at most `duration`. It is dropped, and with it all the
+/// work it had pending, once the time is up.
+pub fn timeout<U: TimeUnit, F: IntoFuture>(
+    duration: Duration<U>,
+    future: F,
+) -> Timeout<F::IntoFuture> {
+    Timeout {
+        future: future.into_future(),
+        sleep: sleep(duration),
+    }
+}
+
+#[pin_project]
+pub struct Timeout<F> {
+    #[pin]
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Poll::Ready(output) = this.future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(this.sleep).poll(cx).map(|()| Err(Elapsed))
+    }
+}
+
+/// Ticks every `period`, starting one `period` from now. Ticks missed while
+/// nobody polled are skipped rather than delivered in a burst.
+pub fn interval<U: TimeUnit>(period: Duration<U>) -> Interval {
+    let period = period.into::<Nanos>();
+    assert!(
+        period.get().into_inner() > 0,
+        "interval period must not be zero"
+    );
+    Interval {
+        period,
+        sleep: sleep(period),
+    }
+}
+
+pub struct Interval {
+    period: Duration<Nanos>,
+    sleep: Sleep,
+}
+
+impl Interval {
+    /// Waits for the next tick, returning when it was due.
+    pub async fn tick(&mut self) -> Instant {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx))
+            .await
+            .unwrap()
+    }
+}
+
+impl AsyncIterator for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if Pin::new(&mut self.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        let due = self.sleep.deadline();
+        let mut next = due + self.period;
+        let now = Instant::now();
+        if next <= now {
+            let period = self.period.get().into_inner();
+            let missed = (now - next).get().into_inner() / period + 1;
+            next = next + Duration::<Nanos>::from(period * missed);
+        }
+        self.sleep = sleep_until(next);
+        Poll::Ready(Some(due))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        rt::{spawn, tests::runtime},
+        time::Millis,
+    };
+    use std::{
+        process::Command,
+        sync::atomic::{AtomicBool, Ordering::*},
+        thread,
+    };
+
+    #[test]
+    fn test_concurrent_sleeps() {
+        runtime();
+        let sleepers = (0..500u128)
+            .map(|i| {
+                let duration = Duration::new(Millis(50 + i % 100));
+                spawn(async move {
+                    let start = Instant::now();
+                    sleep(duration).await;
+                    (duration, start.elapsed())
+                })
+            })
+            .collect::<Vec<_>>();
+        for sleeper in sleepers {
+            let (duration, slept) = block_on(sleeper).unwrap();
+            assert!(slept >= duration, "woke early: {slept:?} < {duration:?}");
+            assert!(
+                slept < duration + Duration::new(Millis(100)),
+                "woke late: {slept:?} for {duration:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_timeout_drops_the_future() {
+        runtime();
+        struct Flag(Arc<AtomicBool>);
+        impl Drop for Flag {
+            fn drop(&mut self) {
+                self.0.store(true, SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let flag = Flag(dropped.clone());
+        let pending = async move {
+            let _flag = flag;
+            std::future::pending::<()>().await
+        };
+        let result = block_on(spawn(timeout(Duration::new(Millis(20)), pending))).unwrap();
+        assert_eq!(result, Err(Elapsed));
+        assert!(dropped.load(SeqCst));
+
+        let quick = timeout(Duration::new(Millis(1000)), async { 7 });
+        assert_eq!(block_on(spawn(quick)).unwrap(), Ok(7));
+    }
+
+    #[test]
+    fn test_interval() {
+        runtime();
+        let ticks = block_on(spawn(async {
+            let mut interval = interval(Duration::new(Millis(10)));
+            let mut ticks = vec![];
+            for _ in 0..5 {
+                ticks.push(interval.tick().await);
+            }
+            ticks
+        }))
+        .unwrap();
+        // Ticks are exactly one period apart, unless one was missed
+        for pair in ticks.windows(2) {
+            let gap = (pair[1] - pair[0]).get().into_inner();
+            assert!(gap > 0 && gap % 10_000_000 == 0, "ticks {gap}ns apart");
+        }
+    }
+
+    /// CPU time this process spent, in user and kernel mode.
+    fn cpu_time() -> std::time::Duration {
+        let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+        unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+        let time = |time: libc::timeval| {
+            std::time::Duration::new(time.tv_sec as u64, time.tv_usec as u32 * 1_000)
+        };
+        time(usage.ru_utime) + time(usage.ru_stime)
+    }
+
+    #[test]
+    fn test_idle_workers_park() {
+        if std::env::var_os("RT_IDLE_CHILD").is_some() {
+            runtime();
+            // A timer far out must not keep the workers spinning either
+            let _sleeper = spawn(sleep(Duration::new(Millis(60_000))));
+            thread::sleep(std::time::Duration::from_millis(100));
+            let before = cpu_time();
+            thread::sleep(std::time::Duration::from_millis(500));
+            let used = cpu_time() - before;
+            // Spinning workers would use about two seconds here
+            assert!(used < std::time::Duration::from_millis(50), "used {used:?}");
+            return;
+        }
+
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "timer::tests::test_idle_workers_park"])
+            .env("RT_IDLE_CHILD", "1")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
@@ -1,26 +1,70 @@
 use std::{
-    cell::UnsafeCell,
+    cell::{Cell, UnsafeCell},
+    collections::VecDeque,
+    hint,
     marker::PhantomData,
+    pin::pin,
+    ptr::{self, NonNull},
     sync::{
-        Arc, OnceLock,
-        atomic::{AtomicUsize, Ordering::*},
+        Arc, Mutex, OnceLock, Weak,
+        atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering::*, fence},
     },
-    task::Waker,
+    task::{Context, Poll, Wake, Waker},
     thread::{self, Thread},
 };
 
 use crate::{
-    collections::{bi::BiMap, queue::Queue, skip::Map},
+    collections::{
+        bi::BiMap,
+        deque::{Owner, Stealer, deque},
+        skip::Map,
+    },
     prelude::Vector,
     rng::{Branch, Pcg, Random, Range, rng},
     sync::{barrier::Barrier, thread_local},
-    time::TimerWheel,
+    time::{Instant, TimerWheel},
 };
 
-use super::{JoinExt, Key, Local, Remote, SelectExt, Task, Work, block_on};
+use super::{
+    JoinExt, Key, Local, Parked, Remote, Task, Work, block_on,
+    metrics::WorkerCounters,
+    reactor::Reactor,
+    shutdown::{self, ShutdownMode},
+};
 
 static WORKERS: thread_local::Local<Worker> = thread_local::Local::new();
+thread_local! {
+    /// The worker registered for this thread, once it was looked up.
+    static CURRENT: Cell<Option<NonNull<Worker>>> = const { Cell::new(None) };
+}
 static REMOTE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+/// Bumped by every start, workers of earlier pools are left alone.
+static GENERATION: AtomicUsize = AtomicUsize::new(0);
+/// Tasks spawned and not dropped yet.
+pub(crate) static TASKS: AtomicUsize = AtomicUsize::new(0);
+static STATE: AtomicU8 = AtomicU8::new(RUNNING);
+/// When a graceful shutdown gives up on the tasks left.
+static DEADLINE: Mutex<Option<std::time::Instant>> = Mutex::new(None);
+/// Threads started for the pool, joined once it stopped.
+static THREADS: Mutex<Vec<WorkerHandle>> = Mutex::new(Vec::new());
+/// Workers parked right now, so scheduling only looks for one to wake when
+/// there is any.
+static SLEEPING: AtomicUsize = AtomicUsize::new(0);
+
+const RUNNING: u8 = 0;
+/// Shutting down: no new tasks, the ones there are keep running.
+const DRAINING: u8 = 1;
+/// Dropping the tasks that are left.
+const CANCELLING: u8 = 2;
+const STOPPED: u8 = 3;
+/// Longest an idle worker sleeps before looking for work to steal.
+const MAX_PARK: std::time::Duration = std::time::Duration::from_millis(10);
+/// Tasks a busy worker runs between looking for ready sockets, and between
+/// taking the oldest of its tasks rather than the newest.
+pub const POLL_INTERVAL: usize = 61;
+/// Rounds an idle worker spins, twice as long each time, looking for work to
+/// steal before it parks.
+const SPIN_ROUNDS: u32 = 6;
 
 pub trait Register {
     async fn register(&self, worker: Worker);
@@ -35,135 +79,472 @@ impl Register for Thread {
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, PartialOrd, Ord, Default)]
 pub struct WorkerId(usize);
 
+/// Tasks scheduled on a worker by any thread, run in order. Pushing takes a
+/// lock for as long as a `VecDeque` push, so scheduling never has to wait.
+pub struct Inbox<T>(Mutex<VecDeque<T>>);
+
+impl<T> Default for Inbox<T> {
+    fn default() -> Self {
+        Self(Mutex::new(VecDeque::new()))
+    }
+}
+
+impl<T> Inbox<T> {
+    pub fn push(&self, task: T) {
+        self.0.lock().unwrap().push_back(task);
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        self.0.lock().unwrap().pop_front()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.lock().unwrap().is_empty()
+    }
+}
+
 pub struct Worker {
     pub rng: Pcg<4>,
     pub waker: Option<Arc<Waker>>,
     pub timers: TimerWheel,
-    pub local: Queue<Task<Local>>,
+    /// `!Send` tasks spawned on this worker, which only ever run here. Other
+    /// threads push to it when they wake one.
+    pub local: Inbox<Task<Local>>,
     pub local_counter: AtomicUsize,
-    pub remote: Queue<Task<Remote>>,
+    /// `Send` tasks scheduled on this worker, newest first, which idle
+    /// workers steal from the other end.
+    pub deque: Owner<Task<Remote>>,
+    pub stealer: Stealer<Task<Remote>>,
+    /// `Send` tasks scheduled from outside the pool.
+    pub remote: Inbox<Task<Remote>>,
+    /// Polled for sockets while idle, and notified whenever a task is
+    /// scheduled on this worker.
+    pub reactor: Reactor,
+    /// Tasks run since the reactor was last polled.
+    pub since_poll: usize,
+    /// Rounds without work since a task last ran here.
+    pub idle: u32,
+    /// Set while parked, for new tasks to wake the worker to steal them.
+    pub sleeping: AtomicBool,
+    /// Tasks that went pending here, for a shutdown to cancel.
+    pub parked: Mutex<Vec<Weak<dyn Parked>>>,
+    pub generation: usize,
+    pub metrics: WorkerCounters,
 }
 
 unsafe impl Send for Worker {}
 unsafe impl Sync for Worker {}
 impl Worker {
+    fn new(rng: Pcg<4>, generation: usize) -> Self {
+        let (deque, stealer) = deque();
+        Worker {
+            rng,
+            timers: TimerWheel::new(),
+            waker: None,
+            local_counter: 0.into(),
+            local: Inbox::default(),
+            deque,
+            stealer,
+            remote: Inbox::default(),
+            reactor: Reactor::new().expect("failed to create the io reactor"),
+            since_poll: 0,
+            idle: 0,
+            sleeping: AtomicBool::new(false),
+            parked: Mutex::new(Vec::new()),
+            generation,
+            metrics: WorkerCounters::default(),
+        }
+    }
+
     pub async fn work() {
-        let Some(me) = current_worker().await else {
+        advance_shutdown().await;
+        let Some(me) = current_worker() else {
             thread::yield_now();
             return;
         };
         me.timers.tick().await;
-        let Some(mut work) = next_work().await else {
-            thread::yield_now();
+        let Some(work) = next_work() else {
+            me.wait_for_work().await;
             return;
         };
+        me.idle = 0;
+        me.metrics.polls.add(1);
+        me.since_poll += 1;
+        if me.since_poll >= POLL_INTERVAL {
+            me.since_poll = 0;
+            me.reactor.poll(std::time::Duration::ZERO);
+        }
         work.execute();
     }
-    async fn dequeue(&mut self) -> Option<Work> {
-        if let Some(task) = self.local.dequeue().await {
-            return Some(Work::Local(task));
+
+    /// Wakes the worker if it is parked, or keeps it from parking next time.
+    pub fn unpark(&self) {
+        self.metrics.unparks.add(1);
+        self.reactor.notify();
+    }
+
+    /// Remembers a task that went pending on this worker.
+    pub fn track(&self, task: Weak<dyn Parked>) {
+        let mut parked = self.parked.lock().unwrap();
+        // Forget the tasks that were woken or dropped since, before growing
+        if parked.len() == parked.capacity() {
+            parked.retain(|task| task.strong_count() > 0);
         }
-        if let Some(task) = self.remote.dequeue().await {
-            return Some(Work::Remote(task));
+        parked.push(task);
+    }
+
+    /// Drops the tasks queued or parked here. Run on the worker's own thread,
+    /// the `!Send` ones must not be dropped anywhere else.
+    async fn cancel(&mut self) {
+        while self.local.pop().is_some() {
+            self.metrics.dequeued();
+        }
+        while self.deque.pop().is_some() {
+            self.metrics.dequeued();
+        }
+        while self.remote.pop().is_some() {
+            self.metrics.dequeued();
+        }
+        let parked = std::mem::take(&mut *self.parked.lock().unwrap());
+        for task in parked.iter().filter_map(Weak::upgrade) {
+            task.cancel();
+        }
+    }
+
+    /// Spins a little longer each round that found no work, as tasks to steal
+    /// tend to come in bursts, then parks.
+    async fn wait_for_work(&mut self) {
+        if self.idle < SPIN_ROUNDS {
+            for _ in 0..1 << self.idle {
+                hint::spin_loop();
+            }
+            self.idle += 1;
+            return;
+        }
+        self.park().await;
+    }
+
+    /// Waits for sockets until the next timer is due or a task is scheduled
+    /// here, but no longer than [`MAX_PARK`] so queues of other workers are
+    /// stolen from.
+    async fn park(&mut self) {
+        let timeout = match self.timers.next_expiration().await {
+            Some(at) => {
+                let nanos = at.as_u128().saturating_sub(Instant::now().as_u128());
+                MAX_PARK.min(std::time::Duration::from_nanos(nanos as u64))
+            }
+            None => MAX_PARK,
+        };
+        self.since_poll = 0;
+        self.metrics.parks.add(1);
+        self.sleeping.store(true, SeqCst);
+        SLEEPING.fetch_add(1, SeqCst);
+        // Tasks queued before the flag was seen did not wake anyone
+        if !any_stealable() {
+            self.reactor.poll(timeout);
+        }
+        SLEEPING.fetch_sub(1, SeqCst);
+        self.sleeping.store(false, SeqCst);
+    }
+
+    fn dequeue(&mut self) -> Option<Work> {
+        let work = match self.local.pop() {
+            Some(task) => Work::Local(task),
+            None => match self.pop() {
+                Some(task) => Work::Remote(task),
+                None => Work::Remote(self.remote.pop()?),
+            },
+        };
+        self.metrics.dequeued();
+        Some(work)
+    }
+
+    /// The newest task is likely still in the cache, but the oldest is taken
+    /// now and then so a task that keeps waking itself does not starve the
+    /// others.
+    fn pop(&mut self) -> Option<Task<Remote>> {
+        match self.since_poll {
+            0 => self.stealer.steal(),
+            _ => self.deque.pop(),
         }
-        None
     }
 }
 
+/// Works until the pool is shut down.
 pub async fn worker_start_barrier(start: Arc<Barrier>) {
-    start
-        .wait()
-        .select(async move {
-            loop {
-                Worker::work().await;
-            }
-        })
-        .await;
+    start.wait().await;
+    while STATE.load(Acquire) != STOPPED {
+        Worker::work().await;
+    }
+    if let Some(me) = current_worker() {
+        me.cancel().await;
+    }
+}
+
+/// Works while polling `future` whenever it is woken, then shuts the pool
+/// down with `mode` unless it is already, and joins its threads.
+pub async fn run<F: Future>(start: Arc<Barrier>, future: F, mode: ShutdownMode) -> F::Output {
+    struct Main {
+        woken: AtomicBool,
+        worker: &'static Worker,
+    }
+
+    impl Wake for Main {
+        fn wake(self: Arc<Self>) {
+            self.wake_by_ref();
+        }
+
+        fn wake_by_ref(self: &Arc<Self>) {
+            self.woken.store(true, Release);
+            self.worker.unpark();
+        }
+    }
+
+    // Started without a timeout, so waiting does not fail
+    let _ = start.wait().await;
+    let main = Arc::new(Main {
+        woken: AtomicBool::new(true),
+        worker: current_worker().expect("run outside of a worker"),
+    });
+    let waker = Waker::from(main.clone());
+    let mut future = pin!(future);
+    let output = loop {
+        if main.woken.swap(false, AcqRel)
+            && let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker))
+        {
+            break output;
+        }
+        Worker::work().await;
+    };
+    request_shutdown(mode);
+    while STATE.load(Acquire) != STOPPED {
+        Worker::work().await;
+    }
+    if let Some(me) = current_worker() {
+        me.cancel().await;
+    }
+    join_threads();
+    output
 }
 
 pub struct WorkerHandle(thread::JoinHandle<()>);
 
 pub async fn start(seed: Vector<4, u128>, worker_count: usize) -> Arc<Barrier> {
+    let generation = GENERATION.fetch_add(1, AcqRel) + 1;
+    *DEADLINE.lock().unwrap() = None;
+    STATE.store(RUNNING, Release);
+
     let mut rng = Pcg::<4>::new(seed);
     let start = Arc::new(Barrier::new(worker_count + 1));
-    thread::current()
-        .register(Worker {
-            rng: rng.branch(),
-            timers: TimerWheel::new(),
-            waker: None,
-            local_counter: 0.into(),
-            local: Queue::default(),
-            remote: Queue::default(),
-        })
-        .await;
+    // A thread that was a worker of an earlier pool keeps its worker, others
+    // may still refer to it
+    match WORKERS.get_mut().await {
+        Some(worker) => worker.generation = generation,
+        None => {
+            thread::current()
+                .register(Worker::new(rng.branch(), generation))
+                .await
+        }
+    }
 
     for worker in (0..worker_count).map(|x| x + 1).map(WorkerId) {
         let start = start.clone();
-        let handle = thread::spawn(move || {
-            block_on(async {
-                worker_start_barrier(start).await;
-                Worker::work().await;
-            })
-        });
+        let handle = thread::spawn(move || block_on(worker_start_barrier(start)));
         handle
             .thread()
-            .register(Worker {
-                timers: TimerWheel::new(),
-                rng: rng.branch(),
-                waker: None,
-                local_counter: 0.into(),
-                local: Queue::default(),
-                remote: Queue::default(),
-            })
+            .register(Worker::new(rng.branch(), generation))
             .await;
+        THREADS.lock().unwrap().push(WorkerHandle(handle));
     }
 
     start
 }
 
-async fn next_work() -> Option<Work> {
-    let worker = current_worker().await;
-    match worker.unwrap().dequeue().await {
+/// Whether new tasks are accepted.
+pub fn accepting_tasks() -> bool {
+    STATE.load(Acquire) == RUNNING
+}
+
+/// Whether tasks that were woken are run again.
+pub fn running_tasks() -> bool {
+    STATE.load(Acquire) < CANCELLING
+}
+
+/// Whether the pool shut down and its workers stopped.
+pub fn is_stopped() -> bool {
+    STATE.load(Acquire) == STOPPED
+}
+
+/// Stops accepting tasks, and has the pool cancel the ones left once they
+/// are all done or the deadline of `mode` passed. Does nothing if the pool is
+/// shutting down already.
+pub(crate) fn request_shutdown(mode: ShutdownMode) {
+    let deadline = match mode {
+        ShutdownMode::Graceful(timeout) => std::time::Instant::now() + timeout,
+        ShutdownMode::Immediate => std::time::Instant::now(),
+    };
+    {
+        // Held until the deadline is set, for whoever sees the pool draining
+        let mut at = DEADLINE.lock().unwrap();
+        if STATE
+            .compare_exchange(RUNNING, DRAINING, AcqRel, Acquire)
+            .is_err()
+        {
+            return;
+        }
+        *at = Some(deadline);
+    }
+    shutdown::trigger();
+    for worker in all_workers() {
+        worker.unpark();
+    }
+}
+
+/// Cancels the tasks left once a shutdown is due, whichever thread gets
+/// here first.
+pub(crate) async fn advance_shutdown() {
+    if STATE.load(Acquire) != DRAINING {
+        return;
+    }
+    let deadline = DEADLINE
+        .lock()
+        .unwrap()
+        .expect("draining without a deadline");
+    if TASKS.load(Acquire) > 0 && std::time::Instant::now() < deadline {
+        return;
+    }
+    if STATE
+        .compare_exchange(DRAINING, CANCELLING, AcqRel, Acquire)
+        .is_err()
+    {
+        return;
+    }
+    // Only `Send` tasks may be dropped here, the rest is left to the workers
+    for worker in all_workers() {
+        while worker.stealer.steal().is_some() {
+            worker.metrics.dequeued();
+        }
+        while worker.remote.pop().is_some() {
+            worker.metrics.dequeued();
+        }
+    }
+    STATE.store(STOPPED, Release);
+    for worker in all_workers() {
+        worker.unpark();
+    }
+}
+
+/// Joins the threads of a stopped pool.
+pub(crate) fn join_threads() {
+    let threads = std::mem::take(&mut *THREADS.lock().unwrap());
+    for WorkerHandle(thread) in threads {
+        // A worker that panicked has nothing left to clean up
+        let _ = thread.join();
+    }
+}
+
+fn next_work() -> Option<Work> {
+    let worker = current_worker();
+    match worker.unwrap().dequeue() {
         Some(x) => Some(x),
-        None => steal_work().await,
+        None => steal_work(),
     }
 }
 
-pub async fn select_worker() -> &'static Worker {
-    all_workers().await.next().unwrap()
+pub fn select_worker() -> &'static Worker {
+    all_workers().next().unwrap()
 }
 
-async fn all_workers() -> impl Iterator<Item = &'static Worker> + Clone {
+pub(crate) fn all_workers() -> impl Iterator<Item = &'static Worker> + Clone {
     //SAFETY ?????????????
-    let workers = WORKERS.all_values().collect::<Vec<_>>();
+    let generation = GENERATION.load(Acquire);
+    let workers = WORKERS
+        .all_values()
+        .filter(|worker| worker.generation == generation)
+        .collect::<Vec<_>>();
     workers.into_iter()
 }
 
-async fn steal_work() -> Option<Work> {
-    const RETRIES: usize = 5;
-    for _ in 0..RETRIES {
-        let worker = select_worker().await;
-        if let Some(task) = worker.remote.dequeue().await {
-            return Some(Work::Remote(task));
+/// Takes half the tasks of another worker, trying them all in turn from a
+/// random one on. `Local` tasks are never stolen, they stay on the thread
+/// that spawned them.
+fn steal_work() -> Option<Work> {
+    let me = current_worker()?;
+    let workers = all_workers();
+    let count = workers.clone().count();
+    let first = me.rng.sample(&Range::new(0..count));
+    for victim in workers.cycle().skip(first).take(count) {
+        if ptr::eq(victim, &*me) {
+            continue;
         }
+        let task = match victim.stealer.steal_half(&mut me.deque) {
+            Some((task, moved)) => {
+                for _ in 0..moved {
+                    victim.metrics.dequeued();
+                    me.metrics.enqueued();
+                }
+                me.metrics.steals.add(moved as u64 + 1);
+                task
+            }
+            None => match victim.remote.pop() {
+                Some(task) => {
+                    me.metrics.steals.add(1);
+                    task
+                }
+                None => continue,
+            },
+        };
+        victim.metrics.dequeued();
+        return Some(Work::Remote(task));
     }
     None
 }
 
-pub async fn current_worker() -> Option<&'static mut Worker> {
-    WORKERS.get_mut().await
+/// Whether any worker has tasks in its deque. Tasks from outside the pool
+/// unpark the worker they are queued on instead.
+fn any_stealable() -> bool {
+    all_workers().any(|worker| !worker.stealer.is_empty())
+}
+
+/// Wakes a parked worker other than `me`, to steal a task that was just
+/// queued on `me`.
+pub fn wake_idle(me: &Worker) {
+    // Orders the push before the check, against `park` setting its flag
+    // before checking for tasks
+    fence(SeqCst);
+    if SLEEPING.load(SeqCst) == 0 {
+        return;
+    }
+    let idle = all_workers().find(|worker| !ptr::eq(*worker, me) && worker.sleeping.load(SeqCst));
+    if let Some(worker) = idle {
+        worker.unpark();
+    }
+}
+
+/// The worker of this thread, unless it belongs to a pool that was started
+/// before the current one.
+pub fn current_worker() -> Option<&'static mut Worker> {
+    let generation = GENERATION.load(Acquire);
+    let worker = match CURRENT.get() {
+        Some(worker) => unsafe { &mut *worker.as_ptr() },
+        None => {
+            // Lookups never wait, and a thread keeps the worker it registered
+            let worker = block_on(WORKERS.get_mut())?;
+            CURRENT.set(Some(NonNull::from(&mut *worker)));
+            worker
+        }
+    };
+    (worker.generation == generation).then_some(worker)
 }
 
-pub async fn next_local_key() -> Key<Local> {
+pub fn next_local_key() -> Key<Local> {
     Key(
-        current_worker()
-            .await
-            .map(|x| x.local_counter.fetch_add(1, Relaxed))
+        current_worker().map(|x| x.local_counter.fetch_add(1, Relaxed))
             .unwrap_or_default(),
         PhantomData,
     )
 }
 
-pub async fn next_remote_key() -> Key<Remote> {
+pub fn next_remote_key() -> Key<Remote> {
     Key(REMOTE_COUNTER.fetch_add(1, Relaxed), PhantomData)
 }
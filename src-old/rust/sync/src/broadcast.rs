@@ -0,0 +1,301 @@
+//! Multi-producer, multi-consumer channel where every receiver sees every
+//! value sent after it subscribed.
+//!
+//! Sends never wait: the channel keeps the last `capacity` values, and a
+//! receiver that falls further behind skips what it missed and is told how
+//! many values that were with [`RecvError::Lagged`].
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+struct State<T> {
+    /// The last `capacity` values, the first of which was sent as `first`.
+    values: VecDeque<T>,
+    first: u64,
+    capacity: usize,
+    senders: usize,
+    receivers: usize,
+    wakers: Vec<Waker>,
+}
+
+impl<T> State<T> {
+    /// Position of the next value sent.
+    fn end(&self) -> u64 {
+        self.first + self.values.len() as u64
+    }
+}
+
+type Shared<T> = Arc<Mutex<State<T>>>;
+
+pub fn channel<T: Clone>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be positive");
+    let shared = Arc::new(Mutex::new(State {
+        values: VecDeque::with_capacity(capacity),
+        first: 0,
+        capacity,
+        senders: 1,
+        receivers: 1,
+        wakers: Vec::new(),
+    }));
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared, next: 0 },
+    )
+}
+
+/// The value of a send there were no receivers for.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sending on a channel without receivers")
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for SendError<T> {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvError {
+    /// Every sender is gone and every value was received.
+    Closed,
+    /// This many values were overwritten before they were received. The next
+    /// receive returns the oldest value still kept.
+    Lagged(u64),
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvError::Closed => write!(f, "receiving on a closed channel"),
+            RecvError::Lagged(skipped) => write!(f, "receiver lagged by {skipped} values"),
+        }
+    }
+}
+
+impl std::error::Error for RecvError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    Closed,
+    Lagged(u64),
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "receiving on an empty channel"),
+            TryRecvError::Closed => write!(f, "receiving on a closed channel"),
+            TryRecvError::Lagged(skipped) => write!(f, "receiver lagged by {skipped} values"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+pub struct Sender<T> {
+    shared: Shared<T>,
+}
+
+impl<T: Clone> Sender<T> {
+    /// Sends `value` to every receiver, returning how many there are. Once
+    /// the channel is full the oldest value is dropped to make room.
+    pub fn send(&self, value: T) -> Result<usize, SendError<T>> {
+        let mut state = self.shared.lock().unwrap();
+        if state.receivers == 0 {
+            return Err(SendError(value));
+        }
+        let oldest = if state.values.len() == state.capacity {
+            state.first += 1;
+            state.values.pop_front()
+        } else {
+            None
+        };
+        state.values.push_back(value);
+        let receivers = state.receivers;
+        let wakers = std::mem::take(&mut state.wakers);
+        drop(state);
+        drop(oldest);
+        for waker in wakers {
+            waker.wake();
+        }
+        Ok(receivers)
+    }
+
+    /// A receiver of the values sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut state = self.shared.lock().unwrap();
+        state.receivers += 1;
+        Receiver {
+            shared: self.shared.clone(),
+            next: state.end(),
+        }
+    }
+
+    pub fn receivers(&self) -> usize {
+        self.shared.lock().unwrap().receivers
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            for waker in state.wakers.drain(..) {
+                waker.wake();
+            }
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Shared<T>,
+    /// Position of the next value to receive.
+    next: u64,
+}
+
+impl<T: Clone> Receiver<T> {
+    pub fn recv(&mut self) -> RecvFut<'_, T> {
+        RecvFut { receiver: self }
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let state = self.shared.lock().unwrap();
+        if self.next < state.first {
+            let skipped = state.first - self.next;
+            self.next = state.first;
+            return Err(TryRecvError::Lagged(skipped));
+        }
+        match state.values.get((self.next - state.first) as usize) {
+            Some(value) => {
+                self.next += 1;
+                Ok(value.clone())
+            }
+            None if state.senders == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+/// Another receiver at the same position, which sees the same values.
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().unwrap().receivers += 1;
+        Self {
+            shared: self.shared.clone(),
+            next: self.next,
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.lock().unwrap().receivers -= 1;
+    }
+}
+
+pub struct RecvFut<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T: Clone> Future for RecvFut<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.receiver.try_recv() {
+            Ok(value) => Poll::Ready(Ok(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(Err(RecvError::Closed)),
+            Err(TryRecvError::Lagged(skipped)) => Poll::Ready(Err(RecvError::Lagged(skipped))),
+            Err(TryRecvError::Empty) => {
+                let mut state = self.receiver.shared.lock().unwrap();
+                if self.receiver.next == state.end() && state.senders > 0 {
+                    state.wakers.push(cx.waker().clone());
+                } else {
+                    cx.waker().wake_by_ref();
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_every_receiver_sees_every_value() {
+        let (tx, mut first) = channel(16);
+        let receivers = (0..3)
+            .map(|_| {
+                let mut rx = tx.subscribe();
+                thread::spawn(move || {
+                    block_on(async move {
+                        let mut values = vec![];
+                        while let Ok(value) = rx.recv().await {
+                            values.push(value);
+                        }
+                        values
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for value in 0..10 {
+            assert_eq!(tx.send(value), Ok(4));
+        }
+        drop(tx);
+
+        for receiver in receivers {
+            assert_eq!(receiver.join().unwrap(), (0..10).collect::<Vec<_>>());
+        }
+        assert_eq!(block_on(first.recv()), Ok(0));
+    }
+
+    #[test]
+    fn test_lag() {
+        let (tx, mut rx) = channel(2);
+        for value in 0..5 {
+            tx.send(value).unwrap();
+        }
+        assert_eq!(block_on(rx.recv()), Err(RecvError::Lagged(3)));
+        assert_eq!(block_on(rx.recv()), Ok(3));
+        assert_eq!(rx.try_recv(), Ok(4));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+
+        let mut late = tx.subscribe();
+        assert_eq!(late.try_recv(), Err(TryRecvError::Empty));
+        tx.send(5).unwrap();
+        drop(tx);
+        assert_eq!(late.try_recv(), Ok(5));
+        assert_eq!(block_on(late.recv()), Err(RecvError::Closed));
+    }
+
+    #[test]
+    fn test_send_without_receivers() {
+        let (tx, rx) = channel(1);
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+        let _rx = tx.subscribe();
+        assert_eq!(tx.send(2), Ok(1));
+    }
+}
@@ -3,10 +3,14 @@
 pub mod backoff;
 #[cfg(feature = "system")]
 pub mod barrier;
+pub mod broadcast;
+pub mod mpsc;
 pub mod mutex;
 pub mod oneshot;
 #[cfg(feature = "system")]
 pub mod retry;
+pub mod rwlock;
+pub mod semaphore;
 #[cfg(feature = "system")]
 pub mod split;
 #[cfg(feature = "system")]
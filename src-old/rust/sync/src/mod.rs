@@ -1,8 +1,23 @@
+use std::pin::pin;
+
 pub mod backoff;
 pub mod barrier;
+pub mod broadcast;
+pub mod mpsc;
 pub mod mutex;
 pub mod oneshot;
 pub mod retry;
+pub mod rwlock;
+pub mod semaphore;
 pub mod split;
+#[path = "../../thread/src/lib.rs"]
 pub mod thread_local;
 pub mod r#yield;
+
+pub fn poll(
+    cx: &mut std::task::Context<'_>,
+    fut: impl IntoFuture<Output = ()>,
+) -> std::task::Poll<()> {
+    let fut = fut.into_future();
+    pin!(fut).poll(cx)
+}
@@ -0,0 +1,339 @@
+//! Bounded multi-producer, single-consumer channel.
+//!
+//! Senders wait for room once `capacity` values are queued. Dropping every
+//! sender ends the stream, the receiver sees `None` once it drained what was
+//! sent; dropping the receiver makes every send fail with the value back.
+
+use std::{
+    collections::VecDeque,
+    fmt, mem,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+struct State<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    senders: usize,
+    receiver: bool,
+    recv_waker: Option<Waker>,
+    /// Senders waiting for room, all woken whenever a value is taken.
+    send_wakers: Vec<Waker>,
+}
+
+type Shared<T> = Arc<Mutex<State<T>>>;
+
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be positive");
+    let shared = Arc::new(Mutex::new(State {
+        queue: VecDeque::with_capacity(capacity),
+        capacity,
+        senders: 1,
+        receiver: true,
+        recv_waker: None,
+        send_wakers: Vec::new(),
+    }));
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+/// The value of a send the receiver is gone for.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sending on a closed channel")
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for SendError<T> {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel holds `capacity` values already.
+    Full(T),
+    Closed(T),
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "sending on a full channel"),
+            TrySendError::Closed(_) => write!(f, "sending on a closed channel"),
+        }
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for TrySendError<T> {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,
+    /// Every sender is gone and the queue is drained.
+    Closed,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => write!(f, "receiving on an empty channel"),
+            TryRecvError::Closed => write!(f, "receiving on a closed channel"),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+pub struct Sender<T> {
+    shared: Shared<T>,
+}
+
+impl<T> Sender<T> {
+    /// Queues `value`, waiting for room while the channel is full.
+    pub fn send(&self, value: T) -> SendFut<'_, T> {
+        SendFut {
+            sender: self,
+            value: Some(value),
+        }
+    }
+
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.shared.lock().unwrap();
+        if !state.receiver {
+            return Err(TrySendError::Closed(value));
+        }
+        if state.queue.len() == state.capacity {
+            return Err(TrySendError::Full(value));
+        }
+        state.queue.push_back(value);
+        if let Some(waker) = state.recv_waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        !self.shared.lock().unwrap().receiver
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.lock().unwrap().senders += 1;
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0
+            && let Some(waker) = state.recv_waker.take()
+        {
+            waker.wake();
+        }
+    }
+}
+
+pub struct SendFut<'a, T> {
+    sender: &'a Sender<T>,
+    value: Option<T>,
+}
+
+// The value is only ever moved, never pinned
+impl<T> Unpin for SendFut<'_, T> {}
+
+impl<T> Future for SendFut<'_, T> {
+    type Output = Result<(), SendError<T>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let value = self.value.take().expect("SendFut polled after it resolved");
+        match self.sender.try_send(value) {
+            Ok(()) => Poll::Ready(Ok(())),
+            Err(TrySendError::Closed(value)) => Poll::Ready(Err(SendError(value))),
+            Err(TrySendError::Full(value)) => {
+                let mut state = self.sender.shared.lock().unwrap();
+                // Room may have been made since `try_send` let go of the lock
+                if state.queue.len() < state.capacity {
+                    cx.waker().wake_by_ref();
+                } else {
+                    state.send_wakers.push(cx.waker().clone());
+                }
+                drop(state);
+                self.value = Some(value);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Shared<T>,
+}
+
+impl<T> Receiver<T> {
+    /// The next value, or `None` once every sender is gone and all they sent
+    /// was received.
+    pub fn recv(&mut self) -> RecvFut<'_, T> {
+        RecvFut { receiver: self }
+    }
+
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.lock().unwrap();
+        match state.queue.pop_front() {
+            Some(value) => {
+                for waker in state.send_wakers.drain(..) {
+                    waker.wake();
+                }
+                Ok(value)
+            }
+            None if state.senders == 0 => Err(TryRecvError::Closed),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock().unwrap();
+        state.receiver = false;
+        let queue = mem::take(&mut state.queue);
+        let wakers = mem::take(&mut state.send_wakers);
+        drop(state);
+        // Queued values may run arbitrary code when dropped, so not under the lock
+        drop(queue);
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+pub struct RecvFut<'a, T> {
+    receiver: &'a mut Receiver<T>,
+}
+
+impl<T> Future for RecvFut<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.receiver.try_recv() {
+            Ok(value) => Poll::Ready(Some(value)),
+            Err(TryRecvError::Closed) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => {
+                let mut state = self.receiver.shared.lock().unwrap();
+                if state.queue.is_empty() && state.senders > 0 {
+                    state.recv_waker = Some(cx.waker().clone());
+                } else {
+                    cx.waker().wake_by_ref();
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use std::{
+        pin::pin,
+        sync::atomic::{AtomicUsize, Ordering::*},
+        task::Wake,
+        thread,
+    };
+
+    #[derive(Default)]
+    struct Count(AtomicUsize);
+
+    impl Wake for Count {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_fifo_per_sender() {
+        let (tx, mut rx) = channel(8);
+        let senders = (0..4)
+            .map(|sender| {
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    block_on(async move {
+                        for i in 0..1000 {
+                            tx.send((sender, i)).await.unwrap();
+                        }
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+
+        let mut next = [0; 4];
+        let received = block_on(async {
+            let mut received = 0;
+            while let Some((sender, i)) = rx.recv().await {
+                assert_eq!(i, next[sender]);
+                next[sender] += 1;
+                received += 1;
+            }
+            received
+        });
+        assert_eq!(received, 4000);
+        for sender in senders {
+            sender.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_backpressure() {
+        let (tx, mut rx) = channel(2);
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert_eq!(tx.try_send(3), Err(TrySendError::Full(3)));
+
+        let count = Arc::new(Count::default());
+        let waker = Waker::from(count.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut send = pin!(tx.send(3));
+        assert!(send.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(count.0.load(SeqCst), 1);
+        assert_eq!(send.as_mut().poll(&mut cx), Poll::Ready(Ok(())));
+
+        assert_eq!(block_on(rx.recv()), Some(2));
+        assert_eq!(block_on(rx.recv()), Some(3));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn test_closing() {
+        let (tx, mut rx) = channel(4);
+        let count = Arc::new(Count::default());
+        let waker = Waker::from(count.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut recv = Box::pin(rx.recv());
+        assert!(recv.as_mut().poll(&mut cx).is_pending());
+        tx.try_send("last").unwrap();
+        drop(tx);
+        assert_eq!(count.0.load(SeqCst), 1);
+        assert_eq!(recv.as_mut().poll(&mut cx), Poll::Ready(Some("last")));
+        drop(recv);
+        assert_eq!(block_on(rx.recv()), None);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Closed));
+
+        let (tx, rx) = channel(1);
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(block_on(tx.send(1)), Err(SendError(1)));
+    }
+}
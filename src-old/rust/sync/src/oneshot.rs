@@ -1,10 +1,11 @@
 use std::{
     cell::UnsafeCell,
+    pin::Pin,
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicBool, Ordering},
     },
-    task::Waker,
+    task::{Context, Poll, Waker},
 };
 
 #[derive(Debug)]
@@ -78,3 +79,140 @@ impl<T: Send> Future for Recv<T> {
         std::task::Poll::Pending
     }
 }
+
+struct Slot<T> {
+    value: Option<T>,
+    sent: bool,
+    senders: usize,
+    receiver: bool,
+    waker: Option<Waker>,
+}
+
+/// Hands a single value from whichever [`Sender`] sends first to the
+/// [`Receiver`], which resolves to `None` if every sender is dropped first.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let slot = Arc::new(Mutex::new(Slot {
+        value: None,
+        sent: false,
+        senders: 1,
+        receiver: true,
+        waker: None,
+    }));
+    (Sender { slot: slot.clone() }, Receiver { slot })
+}
+
+pub struct Sender<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Sender<T> {
+    /// Hands `value` over, or back if a value was sent already or the
+    /// receiver is gone.
+    pub fn send(&self, value: T) -> Result<(), T> {
+        let mut slot = self.slot.lock().unwrap();
+        if slot.sent || !slot.receiver {
+            return Err(value);
+        }
+        slot.sent = true;
+        slot.value = Some(value);
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        let slot = self.slot.lock().unwrap();
+        slot.sent || !slot.receiver
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.slot.lock().unwrap().senders += 1;
+        Self {
+            slot: self.slot.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut slot = self.slot.lock().unwrap();
+        slot.senders -= 1;
+        if slot.senders == 0
+            && let Some(waker) = slot.waker.take()
+        {
+            waker.wake();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut slot = self.slot.lock().unwrap();
+        if let Some(value) = slot.value.take() {
+            return Poll::Ready(Some(value));
+        }
+        if slot.sent || slot.senders == 0 {
+            return Poll::Ready(None);
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let value = {
+            let mut slot = self.slot.lock().unwrap();
+            slot.receiver = false;
+            slot.value.take()
+        };
+        drop(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use std::thread;
+
+    #[test]
+    fn test_handoff() {
+        let (tx, rx) = channel();
+        let sender = thread::spawn(move || tx.send(String::from("done")));
+        assert_eq!(block_on(rx), Some(String::from("done")));
+        assert_eq!(sender.join().unwrap(), Ok(()));
+    }
+
+    #[test]
+    fn test_double_send_rejected() {
+        let (tx, rx) = channel();
+        let other = tx.clone();
+        assert_eq!(tx.send(1), Ok(()));
+        assert!(other.is_closed());
+        assert_eq!(other.send(2), Err(2));
+        assert_eq!(block_on(rx), Some(1));
+    }
+
+    #[test]
+    fn test_closing() {
+        let (tx, rx) = channel::<u8>();
+        let other = tx.clone();
+        drop(tx);
+        drop(other);
+        assert_eq!(block_on(rx), None);
+
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(tx.send(1), Err(1));
+    }
+}
@@ -0,0 +1,226 @@
+//! Reader-writer lock on top of the fair [`Semaphore`].
+//!
+//! Readers take one permit and writers all of them, in the order they asked.
+//! Readers arriving after a waiting writer queue behind it, so a writer gets
+//! in under constant read load. A panic while holding a guard just releases
+//! the lock; there is no poisoning.
+
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+};
+
+use crate::sync::semaphore::Semaphore;
+
+const MAX_READERS: usize = u32::MAX as usize >> 3;
+
+pub struct RwLock<T: ?Sized> {
+    semaphore: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    pub const fn new(data: T) -> Self {
+        Self {
+            semaphore: Semaphore::new(MAX_READERS),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    pub async fn read(&self) -> ReadGuard<'_, T> {
+        self.semaphore.acquire(1).await.forget();
+        ReadGuard { lock: self }
+    }
+
+    pub async fn write(&self) -> WriteGuard<'_, T> {
+        self.semaphore.acquire(MAX_READERS).await.forget();
+        WriteGuard { lock: self }
+    }
+
+    /// Locks for reading unless a writer holds or waits for the lock.
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        self.semaphore.try_acquire(1)?.forget();
+        Some(ReadGuard { lock: self })
+    }
+
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T>> {
+        self.semaphore.try_acquire(MAX_READERS)?.forget();
+        Some(WriteGuard { lock: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for RwLock<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+pub struct ReadGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Send for ReadGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for ReadGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Holding a permit keeps writers out
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(1);
+    }
+}
+
+pub struct WriteGuard<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+unsafe impl<T: ?Sized + Send + Sync> Send for WriteGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for WriteGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Holding every permit keeps everyone else out
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(MAX_READERS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use std::{
+        sync::{
+            Arc,
+            atomic::{AtomicBool, AtomicUsize, Ordering::*},
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn test_exclusion() {
+        let lock = Arc::new(RwLock::new(0));
+        let readers = Arc::new(AtomicUsize::new(0));
+        let writers = Arc::new(AtomicUsize::new(0));
+        let tasks = (0..48)
+            .map(|task| {
+                let (lock, readers, writers) = (lock.clone(), readers.clone(), writers.clone());
+                thread::spawn(move || {
+                    block_on(async {
+                        for _ in 0..100 {
+                            if task % 4 == 0 {
+                                let mut value = lock.write().await;
+                                assert_eq!(writers.fetch_add(1, SeqCst), 0);
+                                assert_eq!(readers.load(SeqCst), 0);
+                                *value += 1;
+                                thread::yield_now();
+                                writers.fetch_sub(1, SeqCst);
+                            } else {
+                                let _value = lock.read().await;
+                                readers.fetch_add(1, SeqCst);
+                                assert_eq!(writers.load(SeqCst), 0);
+                                thread::yield_now();
+                                readers.fetch_sub(1, SeqCst);
+                            }
+                        }
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.join().unwrap();
+        }
+        assert_eq!(*block_on(lock.read()), 12 * 100);
+    }
+
+    #[test]
+    fn test_writer_not_starved() {
+        let lock = Arc::new(RwLock::new(()));
+        let stop = Arc::new(AtomicBool::new(false));
+        // Overlapping readers, so the lock is never free of them
+        let readers = (0..16)
+            .map(|_| {
+                let (lock, stop) = (lock.clone(), stop.clone());
+                thread::spawn(move || {
+                    block_on(async {
+                        while !stop.load(SeqCst) {
+                            let _value = lock.read().await;
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        thread::sleep(Duration::from_millis(50));
+
+        let start = Instant::now();
+        drop(block_on(lock.write()));
+        let waited = start.elapsed();
+        stop.store(true, SeqCst);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert!(waited < Duration::from_secs(1), "writer waited {waited:?}");
+    }
+
+    #[test]
+    fn test_try_lock() {
+        let lock = RwLock::new(1);
+        let first = lock.try_read().unwrap();
+        let second = lock.try_read().unwrap();
+        assert!(lock.try_write().is_none());
+        drop((first, second));
+        let mut value = lock.try_write().unwrap();
+        *value = 2;
+        assert!(lock.try_read().is_none());
+        drop(value);
+        assert_eq!(lock.into_inner(), 2);
+    }
+
+    #[test]
+    fn test_panic_releases() {
+        let lock = Arc::new(RwLock::new(0));
+        let writer = lock.clone();
+        let panicked = thread::spawn(move || {
+            let _value = writer.try_write().unwrap();
+            panic!("while holding the lock");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(lock.try_write().is_some());
+    }
+}
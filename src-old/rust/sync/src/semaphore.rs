@@ -0,0 +1,287 @@
+//! Counting semaphore with first-come, first-served waiters.
+
+use std::{
+    collections::VecDeque,
+    mem,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll, Waker},
+};
+
+struct Waiter {
+    id: u64,
+    permits: usize,
+    waker: Waker,
+    granted: bool,
+}
+
+struct State {
+    permits: usize,
+    /// Waiters in arrival order. Permits go to the first one only, so a large
+    /// request is not starved by a stream of smaller ones.
+    waiters: VecDeque<Waiter>,
+    next_id: u64,
+}
+
+impl State {
+    /// Hands out permits to waiters from the front, returning their wakers.
+    fn grant(&mut self) -> Vec<Waker> {
+        let mut wakers = vec![];
+        for waiter in self.waiters.iter_mut().skip_while(|waiter| waiter.granted) {
+            if waiter.permits > self.permits {
+                break;
+            }
+            self.permits -= waiter.permits;
+            waiter.granted = true;
+            wakers.push(waiter.waker.clone());
+        }
+        wakers
+    }
+}
+
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+impl Semaphore {
+    pub const fn new(permits: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                permits,
+                waiters: VecDeque::new(),
+                next_id: 0,
+            }),
+        }
+    }
+
+    /// Waits for `permits` permits, behind everyone that started waiting
+    /// earlier. Dropping the future gives up its place in line.
+    pub fn acquire(&self, permits: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore: self,
+            permits,
+            waiting: None,
+        }
+    }
+
+    /// Takes `permits` permits if they are available and nobody is waiting
+    /// for permits already.
+    pub fn try_acquire(&self, permits: usize) -> Option<Permit<'_>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.waiters.is_empty() || state.permits < permits {
+            return None;
+        }
+        state.permits -= permits;
+        Some(Permit {
+            semaphore: self,
+            permits,
+        })
+    }
+
+    pub fn add_permits(&self, permits: usize) {
+        let wakers = {
+            let mut state = self.state.lock().unwrap();
+            state.permits += permits;
+            state.grant()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        self.state.lock().unwrap().permits
+    }
+}
+
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+    /// Our place in line, once we had to queue.
+    waiting: Option<u64>,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = Permit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let semaphore = self.semaphore;
+        let mut state = semaphore.state.lock().unwrap();
+        let permit = Permit {
+            semaphore,
+            permits: self.permits,
+        };
+        let Some(id) = self.waiting else {
+            if state.waiters.is_empty() && state.permits >= self.permits {
+                state.permits -= self.permits;
+                return Poll::Ready(permit);
+            }
+            mem::forget(permit);
+            let id = state.next_id;
+            state.next_id += 1;
+            state.waiters.push_back(Waiter {
+                id,
+                permits: self.permits,
+                waker: cx.waker().clone(),
+                granted: false,
+            });
+            self.waiting = Some(id);
+            return Poll::Pending;
+        };
+        let index = state.waiters.iter().position(|waiter| waiter.id == id);
+        let waiter = &mut state.waiters[index.expect("waiter left the queue")];
+        if waiter.granted {
+            state.waiters.remove(index.unwrap());
+            self.waiting = None;
+            return Poll::Ready(permit);
+        }
+        mem::forget(permit);
+        if !waiter.waker.will_wake(cx.waker()) {
+            waiter.waker = cx.waker().clone();
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let Some(id) = self.waiting else {
+            return;
+        };
+        let wakers = {
+            let mut state = self.semaphore.state.lock().unwrap();
+            let index = state.waiters.iter().position(|waiter| waiter.id == id);
+            if let Some(waiter) = index.and_then(|index| state.waiters.remove(index))
+                && waiter.granted
+            {
+                state.permits += waiter.permits;
+            }
+            // Whoever queued behind us may fit now
+            state.grant()
+        };
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// Permits held until dropped, even by a panic.
+#[must_use = "permits are released as soon as they are dropped"]
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl Permit<'_> {
+    pub fn permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Keeps the permits taken for good.
+    pub fn forget(self) {
+        mem::forget(self);
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(self.permits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rt::block_on;
+    use std::{
+        pin::pin,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering::*},
+        },
+        thread,
+    };
+
+    #[test]
+    fn test_limits_concurrency() {
+        let semaphore = Arc::new(Semaphore::new(4));
+        let active = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let tasks = (0..32)
+            .map(|_| {
+                let (semaphore, active, most) = (semaphore.clone(), active.clone(), most.clone());
+                thread::spawn(move || {
+                    block_on(async {
+                        for _ in 0..50 {
+                            let _permit = semaphore.acquire(1).await;
+                            let now = active.fetch_add(1, SeqCst) + 1;
+                            most.fetch_max(now, SeqCst);
+                            thread::yield_now();
+                            active.fetch_sub(1, SeqCst);
+                        }
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for task in tasks {
+            task.join().unwrap();
+        }
+        assert!(most.load(SeqCst) <= 4);
+        assert_eq!(semaphore.available_permits(), 4);
+    }
+
+    #[test]
+    fn test_first_come_first_served() {
+        let semaphore = Semaphore::new(2);
+        let held = semaphore.try_acquire(2).unwrap();
+        let cx = &mut Context::from_waker(Waker::noop());
+
+        let mut large = pin!(semaphore.acquire(2));
+        let mut small = pin!(semaphore.acquire(1));
+        assert!(large.as_mut().poll(cx).is_pending());
+        assert!(small.as_mut().poll(cx).is_pending());
+
+        // One permit is not enough for the first in line, so nobody gets it
+        drop(held);
+        let Poll::Ready(large_permit) = large.as_mut().poll(cx) else {
+            panic!("first in line did not get its permits");
+        };
+        assert!(small.as_mut().poll(cx).is_pending());
+        assert!(semaphore.try_acquire(1).is_none());
+        drop(large_permit);
+        assert!(small.as_mut().poll(cx).is_ready());
+    }
+
+    #[test]
+    fn test_cancelled_waiter_leaves_the_line() {
+        let semaphore = Semaphore::new(0);
+        let cx = &mut Context::from_waker(Waker::noop());
+        let mut large = Box::pin(semaphore.acquire(5));
+        let mut small = pin!(semaphore.acquire(1));
+        assert!(large.as_mut().poll(cx).is_pending());
+        assert!(small.as_mut().poll(cx).is_pending());
+
+        semaphore.add_permits(1);
+        assert!(small.as_mut().poll(cx).is_pending());
+        drop(large);
+        let Poll::Ready(permit) = small.as_mut().poll(cx) else {
+            panic!("waiter behind a cancelled one did not get its permit");
+        };
+        assert_eq!(permit.permits(), 1);
+        permit.forget();
+        assert_eq!(semaphore.available_permits(), 0);
+    }
+
+    #[test]
+    fn test_panic_releases() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let holder = semaphore.clone();
+        let panicked = thread::spawn(move || {
+            let _permit = holder.try_acquire(1).unwrap();
+            panic!("while holding a permit");
+        })
+        .join();
+        assert!(panicked.is_err());
+        assert!(semaphore.try_acquire(1).is_some());
+    }
+}
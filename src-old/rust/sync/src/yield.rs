@@ -1,8 +1,8 @@
-use std::pin::pin;
 use std::sync::atomic::AtomicBool;
 use std::task::Poll::*;
 
-use io::time::{Duration, Instant, TimeUnit, wait_until};
+use super::poll;
+use crate::time::{Duration, Instant, TimeUnit, wait_until};
 
 pub struct Yield {
     until: Instant,
@@ -30,7 +30,6 @@ impl Future for Yield {
             if self.until > Instant::now() {
                 let waker = waker.clone();
                 wait_until(self.until, move || {
-                    dbg!("WOAHHHHHH");
                     waker.wake_by_ref();
                 })
                 .await;
@@ -1,11 +1,10 @@
-#![feature(unboxed_closures, async_fn_traits, negative_impls, thread_id_value)]
 use std::{
     cell::UnsafeCell,
     sync::{Arc, OnceLock},
     thread::{self, Thread},
 };
 
-use collections::skip::Map;
+use crate::collections::skip::Map;
 
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq, PartialOrd, Ord, Default)]
 pub struct ThreadId(usize);
@@ -1,15 +1,13 @@
-use std::sync::atomic::*;
 use proc_macro::TokenStream;
 use quote::quote;
-use syn::{parse_macro_input, Item, Ident, Generics};
-
-// Global counter for opcode IDs
-static OPCODE_COUNTER: AtomicU32 = AtomicU32::new(0);
+use syn::{parse_macro_input, punctuated::Punctuated, Item, LitInt, Token, Type};
 
+// IDs are written out rather than counted, so that they do not depend on the
+// order the compiler expands macros in and stay stable across builds
 #[proc_macro_attribute]
-pub fn op(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn op(attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as Item);
-    
+
     // Extract the name and generics based on the item type
     let (name, generics) = match &input {
         Item::Struct(item_struct) => (&item_struct.ident, &item_struct.generics),
@@ -23,9 +21,25 @@ pub fn op(_attr: TokenStream, item: TokenStream) -> TokenStream {
             ).to_compile_error().into();
         }
     };
-    
-    // Get the next available ID
-    let id = OPCODE_COUNTER.fetch_add(1, Ordering::SeqCst);
+
+    // Parse the ID from #[op(id = N)]
+    let mut id = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("id") {
+            let lit: LitInt = meta.value()?.parse()?;
+            id = Some(lit.base10_parse::<u32>()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `id = ...`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    let Some(id) = id else {
+        return syn::Error::new_spanned(
+            name,
+            "op attribute needs an explicit ID: #[op(id = N)]"
+        ).to_compile_error().into();
+    };
 
     let repr = if id < 256 {
         quote! { u8 }
@@ -34,12 +48,12 @@ pub fn op(_attr: TokenStream, item: TokenStream) -> TokenStream {
     } else {
         quote! { u32 }
     };
-    
+
     let name_str = name.to_string();
-    
+
     // Split generics for implementation
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
-    
+
     // Generate the Opcode implementation
     let opcode_impl = quote! {
         impl #impl_generics OpCode for #name #ty_generics #where_clause {
@@ -47,13 +61,47 @@ pub fn op(_attr: TokenStream, item: TokenStream) -> TokenStream {
             const ID: Self::Repr = #id as #repr;
             const NAME: OpName = OpName(#name_str);
         }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            /// The entry of the opcode in `ALL_OPS`
+            pub const OP_INFO: OpInfo = OpInfo { name: OpName(#name_str), id: #id };
+        }
     };
-    
+
     // Combine the original item with the new implementation
     let expanded = quote! {
         #input
         #opcode_impl
     };
-    
+
+    TokenStream::from(expanded)
+}
+
+// Lists the given opcode types as `ALL_OPS`, failing to compile if two of
+// them share an ID. Generic opcodes are listed with any instantiation
+#[proc_macro]
+pub fn op_registry(input: TokenStream) -> TokenStream {
+    let parser = Punctuated::<Type, Token![,]>::parse_terminated;
+    let ops = parse_macro_input!(input with parser);
+    let ops = ops.iter();
+
+    let expanded = quote! {
+        pub const ALL_OPS: &[OpInfo] = &[#(<#ops>::OP_INFO),*];
+
+        const _: () = {
+            let mut i = 0;
+            while i < ALL_OPS.len() {
+                let mut j = i + 1;
+                while j < ALL_OPS.len() {
+                    if ALL_OPS[i].id == ALL_OPS[j].id {
+                        panic!("two opcodes in ALL_OPS have the same ID");
+                    }
+                    j += 1;
+                }
+                i += 1;
+            }
+        };
+    };
+
     TokenStream::from(expanded)
-}
\ No newline at end of file
+}
@@ -0,0 +1,53 @@
+use opcode_macro::{op, op_registry};
+use opcode_registry::{OpInfo, OpName};
+
+trait OpCode {
+    type Repr;
+    const ID: Self::Repr;
+    const NAME: OpName;
+}
+
+// Expanded before the opcodes below, which a counter would have shifted
+mod added {
+    use super::*;
+
+    #[op(id = 300)]
+    pub struct Added;
+}
+
+#[op(id = 0)]
+struct Push(#[allow(dead_code)] u64);
+
+#[op(id = 1)]
+struct Pop;
+
+#[op(id = 2)]
+enum Load<T> {
+    #[allow(dead_code)]
+    Local(T),
+}
+
+op_registry! { Push, Pop, Load<u8>, added::Added }
+
+#[test]
+fn test_ids_are_explicit() {
+    assert_eq!(Push::ID, 0u8);
+    assert_eq!(Pop::ID, 1u8);
+    assert_eq!(<Load<u64>>::ID, 2u8);
+    // IDs past a byte widen the repr
+    assert_eq!(added::Added::ID, 300u16);
+    assert_eq!(Pop::NAME, OpName("Pop"));
+}
+
+#[test]
+fn test_registry() {
+    let ids: Vec<u32> = ALL_OPS.iter().map(|op| op.id).collect();
+    assert_eq!(ids, [0, 1, 2, 300]);
+    assert_eq!(
+        ALL_OPS[2],
+        OpInfo {
+            name: OpName("Load"),
+            id: 2
+        }
+    );
+}
@@ -0,0 +1,18 @@
+use opcode_macro::{op, op_registry};
+use opcode_registry::{OpInfo, OpName};
+
+trait OpCode {
+    type Repr;
+    const ID: Self::Repr;
+    const NAME: OpName;
+}
+
+#[op(id = 4)]
+struct Push;
+
+#[op(id = 4)]
+struct Pop;
+
+op_registry! { Push, Pop }
+
+fn main() {}
@@ -0,0 +1,13 @@
+use opcode_macro::op;
+use opcode_registry::OpName;
+
+trait OpCode {
+    type Repr;
+    const ID: Self::Repr;
+    const NAME: OpName;
+}
+
+#[op]
+struct Push;
+
+fn main() {}
@@ -16,6 +16,13 @@ pub struct OpName(pub &'static str);
 
 pub type OpRepr = u8;
 
+/// An opcode as listed in `ALL_OPS`, its ID fixed by `#[op(id = N)]`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct OpInfo {
+    pub name: OpName,
+    pub id: u32,
+}
+
 pub struct Op {
     pub name: OpName,
     pub id: OpRepr,
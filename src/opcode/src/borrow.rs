@@ -0,0 +1,269 @@
+//! Ownership and lifetime checking.
+//!
+//! Every borrow and move is a [`Loan`] of a range of memory, and every access
+//! to memory a loan covers is checked against it: moved memory cannot be read
+//! or borrowed until it is stored to again, memory borrowed mutably cannot be
+//! accessed except through the borrow, and memory borrowed at all cannot be
+//! stored to or moved. Loans of the locals of a frame end with it, moves are
+//! forgotten and borrows are left dangling for the ref to fail when used.
+
+use crate::{Borrows, ErrorKind, Lifetime, Loan, Ownership, VirtualPtr, op::Op};
+
+impl Borrows {
+    /// Live loans overlapping the `len` bytes at `addr`.
+    fn overlapping(&self, addr: VirtualPtr, len: u64) -> impl Iterator<Item = &Loan> {
+        self.loans.iter().filter(move |loan| {
+            !loan.dangling && loan.addr.0 < addr.0 + len && addr.0 < loan.addr.0 + loan.len
+        })
+    }
+
+    fn check(
+        &self,
+        addr: VirtualPtr,
+        len: u64,
+        op: Op,
+        allowed: &[Ownership],
+    ) -> Result<(), ErrorKind> {
+        match self
+            .overlapping(addr, len)
+            .find(|loan| !allowed.contains(&loan.owner))
+        {
+            Some(loan) if loan.owner == Ownership::Moved => {
+                Err(ErrorKind::UseAfterMove { op, addr })
+            }
+            Some(_) => Err(ErrorKind::Aliased { op, addr }),
+            None => Ok(()),
+        }
+    }
+
+    fn add(&mut self, addr: VirtualPtr, len: u64, owner: Ownership, lifetime: Lifetime) -> u64 {
+        let id = self.next;
+        self.next += 1;
+        self.loans.push(Loan {
+            id,
+            addr,
+            len,
+            owner,
+            lifetime,
+            dangling: false,
+        });
+        id
+    }
+
+    pub(crate) fn read(&self, addr: VirtualPtr, len: u64, op: Op) -> Result<(), ErrorKind> {
+        self.check(addr, len, op, &[Ownership::Ref])
+    }
+
+    /// Checks a store, which makes memory moved out of usable again.
+    pub(crate) fn write(&mut self, addr: VirtualPtr, len: u64, op: Op) -> Result<(), ErrorKind> {
+        self.check(addr, len, op, &[Ownership::Moved])?;
+        self.loans.retain(|loan| {
+            loan.dangling
+                || loan.owner != Ownership::Moved
+                || loan.addr.0 >= addr.0 + len
+                || addr.0 >= loan.addr.0 + loan.len
+        });
+        Ok(())
+    }
+
+    pub(crate) fn move_out(
+        &mut self,
+        addr: VirtualPtr,
+        len: u64,
+        lifetime: Lifetime,
+        op: Op,
+    ) -> Result<(), ErrorKind> {
+        self.check(addr, len, op, &[])?;
+        self.add(addr, len, Ownership::Moved, lifetime);
+        Ok(())
+    }
+
+    /// Borrows the `len` bytes at `addr` as `owner`, `Ref` or `Mut`,
+    /// returning the id of the borrow.
+    pub(crate) fn borrow(
+        &mut self,
+        addr: VirtualPtr,
+        len: u64,
+        owner: Ownership,
+        lifetime: Lifetime,
+        op: Op,
+    ) -> Result<u64, ErrorKind> {
+        let allowed: &[Ownership] = match owner {
+            Ownership::Ref => &[Ownership::Ref],
+            _ => &[],
+        };
+        self.check(addr, len, op, allowed)?;
+        Ok(self.add(addr, len, owner, lifetime))
+    }
+
+    /// The borrow `id`, if it does not dangle.
+    pub(crate) fn get(&self, id: u64, op: Op) -> Result<Loan, ErrorKind> {
+        match self
+            .loans
+            .iter()
+            .find(|loan| loan.id == id && loan.owner != Ownership::Moved)
+        {
+            Some(loan) if loan.dangling => Err(ErrorKind::Dangling {
+                op,
+                addr: loan.addr,
+            }),
+            Some(loan) => Ok(*loan),
+            None => Err(ErrorKind::Corrupted),
+        }
+    }
+
+    pub(crate) fn release(&mut self, id: u64) -> Result<(), ErrorKind> {
+        match self
+            .loans
+            .iter()
+            .position(|loan| loan.id == id && loan.owner != Ownership::Moved)
+        {
+            Some(index) => {
+                self.loans.swap_remove(index);
+                Ok(())
+            }
+            None => Err(ErrorKind::Corrupted),
+        }
+    }
+
+    /// Ends the loans of frames deeper than `depth`, which returned.
+    pub(crate) fn end_frames(&mut self, depth: usize) {
+        let depth = Lifetime(depth as u64);
+        self.loans
+            .retain(|loan| loan.lifetime <= depth || loan.owner != Ownership::Moved);
+        for loan in &mut self.loans {
+            loan.dangling |= loan.lifetime > depth;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+    use crate::{
+        Error, FuncId, VirtualMachine,
+        vm::{
+            SLOT,
+            tests::{Assembler, I64, sig, vm},
+        },
+    };
+
+    fn run(vm: &mut VirtualMachine, main: FuncId) -> Error {
+        let error = vm.run(main, &[]).unwrap_err();
+        assert!(vm.call_stack.is_empty());
+        assert_eq!(vm.stack.active, vm.stack.base);
+        error
+    }
+
+    #[test]
+    fn test_use_after_move() {
+        let (mut vm, _) = vm();
+        // Storing after the move makes the local usable again, until the
+        // second move
+        let mut asm = Assembler::default();
+        asm.op(Op::Const, 5).op(Op::Store, 1);
+        asm.op(Op::Move, 1).op(Op::Store, 0);
+        asm.op(Op::Const, 6).op(Op::Store, 1);
+        asm.op(Op::Move, 1).op(Op::Store, 0);
+        asm.op(Op::Load, 1).op(Op::Ret, 0);
+        let main = vm.load(&asm.finish(), sig(&[], Some(I64)), 2).unwrap();
+
+        let error = run(&mut vm, main);
+        assert_eq!(
+            error.kind,
+            ErrorKind::UseAfterMove {
+                op: Op::Load,
+                addr: VirtualPtr(SLOT),
+            }
+        );
+        assert_eq!(error.at, Some((main.0, 36)));
+        // The frame took its moves with it
+        assert!(vm.borrows.loans.is_empty());
+    }
+
+    #[test]
+    fn test_aliasing_mutable_borrows() {
+        let (mut vm, _) = vm();
+        // A shared borrow and a store through a mutable one after it ends
+        let mut asm = Assembler::default();
+        asm.op(Op::Borrow, 0).op(Op::Dup, 0).op(Op::Deref, 0);
+        asm.op(Op::Store, 1).op(Op::Release, 0);
+        asm.op(Op::BorrowMut, 0).op(Op::Dup, 0);
+        asm.op(Op::Const, 9).op(Op::Assign, 0).op(Op::Release, 0);
+        asm.op(Op::Load, 0).op(Op::Ret, 0);
+        let ok = vm.load(&asm.finish(), sig(&[], Some(I64)), 2).unwrap();
+        let result = vm.run(ok, &[]).unwrap();
+        assert_eq!(vm.read_int(&result), Ok(9));
+        assert!(vm.borrows.loans.is_empty());
+
+        let cases = [
+            (Op::Borrow, Op::BorrowMut),
+            (Op::BorrowMut, Op::Borrow),
+            (Op::BorrowMut, Op::Load),
+            (Op::Borrow, Op::Move),
+        ];
+        for (first, second) in cases {
+            let mut asm = Assembler::default();
+            asm.op(first, 0).op(second, 0).op(Op::Ret, 0);
+            let main = vm.load(&asm.finish(), sig(&[], None), 1).unwrap();
+            let error = run(&mut vm, main);
+            assert_eq!(
+                error.kind,
+                ErrorKind::Aliased {
+                    op: second,
+                    addr: VirtualPtr(0),
+                }
+            );
+            assert_eq!(error.at, Some((main.0, 3)));
+            assert!(vm.borrows.loans.iter().all(|loan| loan.dangling));
+        }
+
+        // Stores through shared borrows are refused too
+        let mut asm = Assembler::default();
+        asm.op(Op::Borrow, 0).op(Op::Const, 1).op(Op::Assign, 0);
+        let main = vm.load(&asm.finish(), sig(&[], None), 1).unwrap();
+        let error = run(&mut vm, main);
+        assert_eq!(
+            error.kind,
+            ErrorKind::Aliased {
+                op: Op::Assign,
+                addr: VirtualPtr(0),
+            }
+        );
+    }
+
+    #[test]
+    fn test_dangling_frame_local() {
+        let (mut vm, _) = vm();
+        // Returns a borrow of its own local
+        let mut asm = Assembler::default();
+        asm.op(Op::Const, 7)
+            .op(Op::Store, 0)
+            .op(Op::Borrow, 0)
+            .op(Op::Ret, 0);
+        let leak = vm.load(&asm.finish(), sig(&[], Some(I64)), 1).unwrap();
+        let mut asm = Assembler::default();
+        asm.op(Op::Const, 1).op(Op::Store, 0);
+        asm.op(Op::Call, leak.0 as u64)
+            .op(Op::Deref, 0)
+            .op(Op::Ret, 0);
+        let main = vm.load(&asm.finish(), sig(&[], Some(I64)), 1).unwrap();
+
+        let error = run(&mut vm, main);
+        // The callee's local was in the slot right above the caller's
+        assert_eq!(
+            error.kind,
+            ErrorKind::Dangling {
+                op: Op::Deref,
+                addr: VirtualPtr(SLOT),
+            }
+        );
+        assert_eq!(error.at, Some((main.0, 17)));
+        assert_eq!(
+            error.to_string(),
+            "dangling ref by Deref of 0x8 at function 1 offset 17"
+        );
+    }
+}
@@ -0,0 +1,376 @@
+//! Source maps from bytecode back to the program it was compiled from.
+//!
+//! A [`DebugInfo`] table maps instruction ranges of each function to a file,
+//! line and column. It travels next to the code section, never inside it, so
+//! stripping it leaves the code and its checksum untouched.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+use core::ops::Range;
+
+use crate::{Error, ErrorKind};
+
+/// Index into the file name table of a [`DebugInfo`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileId(pub u32);
+
+/// Where an instruction range came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub func: u32,
+    /// Byte offsets into the function's code.
+    pub start: u32,
+    pub end: u32,
+    pub file: FileId,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// A resolved [`Span`] with its file name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    files: Vec<String>,
+    /// Sorted by function, then start offset, and never overlapping.
+    spans: Vec<Span>,
+}
+
+impl DebugInfo {
+    pub fn builder() -> DebugInfoBuilder {
+        DebugInfoBuilder::default()
+    }
+
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// The span covering `offset` in function `func`.
+    pub fn lookup(&self, func: u32, offset: u32) -> Option<&Span> {
+        let after = self
+            .spans
+            .partition_point(|span| (span.func, span.start) <= (func, offset));
+        let span = self.spans[..after].last()?;
+        (span.func == func && offset < span.end).then_some(span)
+    }
+
+    pub fn resolve(&self, span: &Span) -> SourceLocation {
+        SourceLocation {
+            file: self.files[span.file.0 as usize].clone(),
+            line: span.line,
+            column: span.column,
+        }
+    }
+
+    /// Encodes the table. Every field is a LEB128 varint relative to the
+    /// previous span where that keeps it small: functions and start offsets
+    /// as deltas, lines zigzag encoded since they move both ways.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, self.files.len() as u64);
+        for file in &self.files {
+            write_varint(&mut out, file.len() as u64);
+            out.extend_from_slice(file.as_bytes());
+        }
+
+        write_varint(&mut out, self.spans.len() as u64);
+        let mut prev = Span {
+            func: 0,
+            start: 0,
+            end: 0,
+            file: FileId(0),
+            line: 0,
+            column: 0,
+        };
+        for span in &self.spans {
+            let func_delta = span.func - prev.func;
+            // Ranges restart at the top of every function
+            let base = if func_delta == 0 { prev.end } else { 0 };
+            write_varint(&mut out, func_delta as u64);
+            write_varint(&mut out, (span.start - base) as u64);
+            write_varint(&mut out, (span.end - span.start) as u64);
+            write_varint(&mut out, span.file.0 as u64);
+            write_varint(&mut out, zigzag(span.line as i64 - prev.line as i64));
+            write_varint(&mut out, span.column as u64);
+            prev = *span;
+        }
+        out
+    }
+
+    pub fn decode(mut bytes: &[u8]) -> Result<Self, Error> {
+        let bytes = &mut bytes;
+        let file_count = read_varint(bytes)?;
+        let mut files = Vec::new();
+        for _ in 0..file_count {
+            let len = read_varint(bytes)? as usize;
+            if bytes.len() < len {
+                return Err(Error::new(ErrorKind::Corrupted));
+            }
+            let (name, rest) = bytes.split_at(len);
+            let name = core::str::from_utf8(name).map_err(|_| Error::new(ErrorKind::Corrupted))?;
+            files.push(String::from(name));
+            *bytes = rest;
+        }
+
+        let span_count = read_varint(bytes)?;
+        let mut spans: Vec<Span> = Vec::new();
+        let mut prev: Option<Span> = None;
+        for _ in 0..span_count {
+            let func_delta = read_u32(bytes)?;
+            let start_delta = read_u32(bytes)?;
+            let len = read_u32(bytes)?;
+            let file = read_u32(bytes)?;
+            let line_delta = unzigzag(read_varint(bytes)?);
+            let column = read_u32(bytes)?;
+
+            let (prev_func, prev_end, prev_line) = prev.map_or((0, 0, 0), |p| (p.func, p.end, p.line));
+            let base = if func_delta == 0 { prev_end } else { 0 };
+            let start = checked(base.checked_add(start_delta))?;
+            let span = Span {
+                func: checked(prev_func.checked_add(func_delta))?,
+                start,
+                end: checked(start.checked_add(len))?,
+                file: FileId(file),
+                line: checked(u32::try_from(prev_line as i64 + line_delta).ok())?,
+                column,
+            };
+            if file as usize >= files.len() {
+                return Err(Error::new(ErrorKind::Corrupted));
+            }
+            spans.push(span);
+            prev = Some(span);
+        }
+
+        if !bytes.is_empty() {
+            return Err(Error::new(ErrorKind::Corrupted));
+        }
+        Ok(Self { files, spans })
+    }
+}
+
+/// Records spans while code is emitted.
+#[derive(Debug, Default)]
+pub struct DebugInfoBuilder {
+    info: DebugInfo,
+}
+
+impl DebugInfoBuilder {
+    /// Interns `name` in the file table.
+    pub fn file(&mut self, name: &str) -> FileId {
+        let index = match self.info.files.iter().position(|file| file == name) {
+            Some(index) => index,
+            None => {
+                self.info.files.push(String::from(name));
+                self.info.files.len() - 1
+            }
+        };
+        FileId(index as u32)
+    }
+
+    /// Maps `range` of function `func` to a source location. A range
+    /// continuing the previous span at the same location extends it.
+    pub fn span(&mut self, func: u32, range: Range<u32>, file: FileId, line: u32, column: u32) -> &mut Self {
+        let span = Span {
+            func,
+            start: range.start,
+            end: range.end,
+            file,
+            line,
+            column,
+        };
+        match self.info.spans.last_mut() {
+            Some(last)
+                if last.func == func
+                    && last.end == span.start
+                    && (last.file, last.line, last.column) == (file, line, column) =>
+            {
+                last.end = span.end;
+            }
+            _ => self.info.spans.push(span),
+        }
+        self
+    }
+
+    /// Sorts the spans recorded so far. Where ranges overlap, a span is cut
+    /// short where the next one starts.
+    pub fn build(mut self) -> DebugInfo {
+        let spans = &mut self.info.spans;
+        // Stable, so of two spans starting together the later recorded wins
+        spans.sort_by_key(|span| (span.func, span.start));
+        let mut kept: Vec<Span> = Vec::with_capacity(spans.len());
+        for span in spans.drain(..) {
+            if span.start >= span.end {
+                continue;
+            }
+            match kept.last_mut() {
+                Some(last) if last.func == span.func && last.end > span.start => last.end = span.start,
+                _ => {}
+            }
+            if kept.last().is_some_and(|last| last.start == last.end) {
+                kept.pop();
+            }
+            kept.push(span);
+        }
+        self.info.spans = kept;
+        self.info
+    }
+}
+
+pub(crate) fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, Error> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or(Error::new(ErrorKind::Corrupted))?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::new(ErrorKind::Corrupted))
+}
+
+fn read_u32(bytes: &mut &[u8]) -> Result<u32, Error> {
+    checked(u32::try_from(read_varint(bytes)?).ok())
+}
+
+fn checked<T>(value: Option<T>) -> Result<T, Error> {
+    value.ok_or(Error::new(ErrorKind::Corrupted))
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Module;
+    use alloc::{format, string::ToString, vec};
+
+    fn sample() -> DebugInfo {
+        let mut builder = DebugInfo::builder();
+        let main = builder.file("src/main.bind");
+        let util = builder.file("src/util.bind");
+        builder
+            .span(0, 0..4, main, 1, 1)
+            .span(0, 4..6, main, 2, 5)
+            .span(0, 6..9, main, 2, 5)
+            .span(1, 0..3, util, 10, 3)
+            .span(1, 3..8, main, 3, 9);
+        builder.build()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let info = sample();
+        // Adjacent ranges at the same location are merged
+        assert_eq!(info.spans().len(), 4);
+        assert_eq!(DebugInfo::decode(&info.encode()).unwrap(), info);
+        assert!(DebugInfo::decode(&info.encode()[..5]).is_err());
+    }
+
+    #[test]
+    fn test_lookup() {
+        let module = Module::new(vec![0; 17]).with_debug(sample());
+        let at = |func, offset| module.lookup_span(func, offset).map(|loc| loc.to_string());
+        assert_eq!(at(0, 0).as_deref(), Some("src/main.bind:1:1"));
+        assert_eq!(at(0, 8).as_deref(), Some("src/main.bind:2:5"));
+        assert_eq!(at(0, 9), None);
+        assert_eq!(at(1, 2).as_deref(), Some("src/util.bind:10:3"));
+        assert_eq!(at(2, 0), None);
+    }
+
+    #[test]
+    fn test_error_names_source() {
+        // fn main() { let x = [1, 2]; x[5] } compiled to two functions, the
+        // index instruction at offset 5 of function 1
+        let mut builder = DebugInfo::builder();
+        let file = builder.file("fixtures/index.bind");
+        builder
+            .span(0, 0..3, file, 1, 1)
+            .span(1, 0..5, file, 1, 13)
+            .span(1, 5..7, file, 1, 29);
+        let module = Module::new(vec![0; 10]).with_debug(builder.build());
+
+        let error = module.locate(Error::at(ErrorKind::IndexOutOfBounds, 1, 6));
+        let message = format!("{error}");
+        assert!(message.contains("fixtures/index.bind:1:29"), "{message}");
+        assert!(message.contains("index out of bounds"), "{message}");
+
+        let stripped = module.strip_debug();
+        let message = format!("{}", stripped.locate(Error::at(ErrorKind::IndexOutOfBounds, 1, 6)));
+        assert!(message.contains("function 1 offset 6"), "{message}");
+    }
+
+    #[test]
+    fn test_strip_keeps_code() {
+        let code = (0..64).collect::<Vec<u8>>();
+        let module = Module::new(code.clone()).with_debug(sample());
+        let checksum = module.checksum();
+        let stripped = module.strip_debug();
+        assert_eq!(stripped.code(), &code[..]);
+        assert_eq!(stripped.checksum(), checksum);
+        assert!(stripped.verify());
+        assert!(stripped.debug_info().is_none());
+    }
+
+    #[test]
+    fn test_encoding_is_compact() {
+        // 10k instructions of 1 to 4 bytes over 100 functions, with lines
+        // advancing every few instructions like real code
+        let mut builder = DebugInfo::builder();
+        let file = builder.file("src/generated.bind");
+        let mut offset = 0;
+        let mut line = 1;
+        for i in 0..10_000u32 {
+            let func = i / 100;
+            if i % 100 == 0 {
+                offset = 0;
+            }
+            if i % 3 == 0 {
+                line += 1;
+            }
+            let len = 1 + i % 4;
+            builder.span(func, offset..offset + len, file, line, 5 + i % 7);
+            offset += len;
+        }
+        let info = builder.build();
+        let encoded = info.encode();
+
+        // Fixed width would need 24 bytes a span; deltas fit in one byte each
+        assert_eq!(info.spans().len(), 10_000);
+        assert!(encoded.len() <= 6 * 10_000 + 64, "{}", encoded.len());
+        assert_eq!(DebugInfo::decode(&encoded).unwrap(), info);
+    }
+}
@@ -0,0 +1,239 @@
+//! Listings of loaded functions.
+//!
+//! [`function`] prints the code of a function one instruction per line, after
+//! its offset. Operands indexing a table of the machine are followed by what
+//! they refer to: the type of arithmetic, comparisons, structs and sequences,
+//! the signature of the function called, and the value of a constant.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+};
+use core::fmt::{self, Write};
+
+use crate::{
+    Error, ErrorKind, FuncId, FuncKind, FuncSig, TypeId, TypeInfo, TypeKind, VirtualMachine,
+    op::{self, Op, Operand},
+};
+
+/// Nesting of array types printed before giving up, so that a type table
+/// with a cycle still prints.
+const MAX_NESTING: usize = 4;
+
+/// The listing of the bytecode function `id` of `vm`.
+pub fn function(vm: &VirtualMachine, id: FuncId) -> Result<String, Error> {
+    let function = vm.function(id)?;
+    let FuncKind::Bytecode { addr } = function.func.kind else {
+        return Err(Error::new(ErrorKind::TypeMismatch));
+    };
+    let code = vm.memory.read(addr, function.len as u64)?;
+
+    let mut out = format!(
+        "fn {}{}, locals {}\n",
+        id.0,
+        signature(&vm.types, &function.func.sig),
+        function.locals
+    );
+    let mut offset = 0;
+    while offset < code.len() {
+        let (op, operand, next) =
+            op::decode(code, offset).ok_or(Error::at(ErrorKind::Corrupted, id.0, offset as u32))?;
+        let text = format!("{:>6}  {}", offset, Instruction(op, operand));
+        match note(vm, op, operand) {
+            Some(note) => writeln!(out, "{text:<28}; {note}"),
+            None => writeln!(out, "{text}"),
+        }
+        .unwrap();
+        offset = next;
+    }
+    Ok(out)
+}
+
+/// An instruction as listed, its operand signed if it is a value.
+pub(crate) struct Instruction(pub Op, pub u64);
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self(op, operand) = *self;
+        match op.operand() {
+            Operand::None => write!(f, "{op}"),
+            Operand::Value => write!(f, "{op} {}", operand as i64),
+            _ => write!(f, "{op} {operand}"),
+        }
+    }
+}
+
+/// What the operand of `op` refers to, if it indexes a table.
+fn note(vm: &VirtualMachine, op: Op, operand: u64) -> Option<String> {
+    Some(match op.operand() {
+        Operand::Type => match vm.types.get(operand as usize) {
+            Some(info) => type_name(&vm.types, info, 0),
+            None => missing(),
+        },
+        Operand::Func => match vm.function_table.get(operand as usize) {
+            Some(callee) => {
+                let sig = signature(&vm.types, &callee.func.sig);
+                match callee.func.kind {
+                    FuncKind::Builtin { name, .. } => format!("{name}{sig}"),
+                    FuncKind::Foreign { .. } => format!("foreign{sig}"),
+                    _ => format!("fn {operand}{sig}"),
+                }
+            }
+            None => missing(),
+        },
+        Operand::Constant => match vm.constants.get(operand as usize) {
+            Some(value) => value.to_string(),
+            None => missing(),
+        },
+        _ => return None,
+    })
+}
+
+/// `sig` as `(params) -> ret`, leaving out the return type if there is none.
+fn signature(types: &[TypeInfo], sig: &FuncSig) -> String {
+    let mut out = String::from("(");
+    for (i, param) in sig.params.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        out.push_str(&type_id_name(types, param));
+    }
+    out.push(')');
+    if let Some(ret) = &sig.ret {
+        write!(out, " -> {}", type_id_name(types, ret)).unwrap();
+    }
+    out
+}
+
+fn type_id_name(types: &[TypeInfo], id: &TypeId) -> String {
+    match types.iter().find(|info| info.internal_id == *id) {
+        Some(info) => type_name(types, info, 0),
+        None => format!("#{}", id.0),
+    }
+}
+
+fn type_name(types: &[TypeInfo], info: &TypeInfo, nesting: usize) -> String {
+    match &info.kind {
+        TypeKind::Integer {
+            width,
+            signed: true,
+        } => format!("i{width}"),
+        TypeKind::Integer {
+            width,
+            signed: false,
+        } => format!("u{width}"),
+        TypeKind::Float { width } => format!("f{width}"),
+        TypeKind::Pointer => String::from("ptr"),
+        TypeKind::Array { element_type, len } => {
+            let element = match types.get(*element_type as usize) {
+                Some(_) if nesting == MAX_NESTING => String::from(".."),
+                Some(element) => type_name(types, element, nesting + 1),
+                None => missing(),
+            };
+            format!("[{element}; {len}]")
+        }
+        TypeKind::Struct { field_count } => format!("struct{{{field_count}}}"),
+        TypeKind::Sum { variants, .. } => {
+            let mut out = String::from("sum{");
+            for (i, variant) in variants.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                out.push_str(&variant.name);
+            }
+            out.push('}');
+            out
+        }
+    }
+}
+
+fn missing() -> String {
+    String::from("?")
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::vm::tests::{Assembler, I64, sig, vm};
+
+    #[test]
+    fn test_listing() {
+        let (mut vm, [i64, .., pair]) = vm();
+        vm.constants = vec![0, 1000];
+        fn print(_: &mut VirtualMachine, _: &[crate::Value]) -> Result<crate::Value, Error> {
+            Ok(crate::Value::Unit)
+        }
+        vm.register_builtin("print", sig(&[I64], None), print);
+        let print = vm.load_builtin("print").unwrap();
+
+        let mut asm = Assembler::default();
+        let done = asm.label();
+        asm.op(Op::Load, 0)
+            .op(Op::Const, -1i64 as u64)
+            .op(Op::Lt, i64);
+        asm.jump(Op::BranchIf, done);
+        asm.op(Op::Load, 0).op(Op::Constant, 1).op(Op::Add, i64);
+        asm.op(Op::Dup, 0).op(Op::Call, print.0 as u64);
+        asm.op(Op::Dup, 0)
+            .op(Op::Construct, pair)
+            .op(Op::Destructure, pair);
+        asm.op(Op::Add, i64);
+        asm.op(Op::Const, 3).op(Op::SeqNew, pair).op(Op::SeqLen, 0);
+        asm.op(Op::Add, i64).op(Op::Ret, 0);
+        asm.bind(done).op(Op::Const, 0).op(Op::Ret, 0);
+        let main = vm.load(&asm.finish(), sig(&[I64], Some(I64)), 1).unwrap();
+        let expected = "\
+fn 1(i64) -> i64, locals 1
+     0  load 0
+     3  const -1
+    12  lt 0                ; i64
+    15  branch_if 64
+    20  load 0
+    23  constant 1          ; 1000
+    28  add 0               ; i64
+    31  dup
+    32  call 0              ; print(i64)
+    37  dup
+    38  construct 4         ; struct{2}
+    41  destructure 4       ; struct{2}
+    44  add 0               ; i64
+    47  const 3
+    56  seq_new 4           ; struct{2}
+    59  seq_len
+    60  add 0               ; i64
+    63  ret
+    64  const 0
+    73  ret
+";
+        assert_eq!(function(&vm, main).unwrap(), expected);
+        let x = vm.int(5).unwrap();
+        let result = vm.run(main, &[x]).unwrap();
+        assert_eq!(vm.read_int(&result), Ok(2010));
+        // Only bytecode has a listing
+        assert_eq!(
+            function(&vm, print).unwrap_err().kind,
+            ErrorKind::TypeMismatch
+        );
+    }
+
+    #[test]
+    fn test_type_names() {
+        let (mut vm, [.., pair]) = vm();
+        let array = |element_type| TypeKind::Array {
+            element_type,
+            len: 3,
+        };
+        let pairs = vm.register_type(TypeInfo::new(TypeId(20), array(pair as u32), 48, 8));
+        // An array of itself
+        let cycle = vm.register_type(TypeInfo::new(TypeId(21), array(pairs as u32 + 1), 0, 8));
+        let name = |index: u16| type_name(&vm.types, &vm.types[index as usize], 0);
+        assert_eq!(name(pairs), "[struct{2}; 3]");
+        assert_eq!(name(cycle), "[[[[[..; 3]; 3]; 3]; 3]; 3]");
+        assert_eq!(
+            signature(&vm.types, &sig(&[TypeId(20), TypeId(99)], None)),
+            "([struct{2}; 3], #99)"
+        );
+    }
+}
@@ -0,0 +1,169 @@
+//! The heap allocator.
+//!
+//! The heap runs from the end of the stack to [`VirtualMachine::heap`], past
+//! which memory was never handed out. Freed blocks go to a free list that is
+//! searched first fit before growing the heap, and are merged with their
+//! neighbours, lowering the top again when they reach it. Blocks carry no
+//! header, so freeing takes the size back.
+
+use crate::{Error, ErrorKind, VirtualMachine, VirtualPtr, trace::TraceEvent};
+
+impl VirtualMachine {
+    /// A block of `size` bytes aligned to `align`, a power of two.
+    pub fn alloc(&mut self, size: u64, align: u64) -> Result<VirtualPtr, Error> {
+        let addr = self.alloc_block(size, align)?;
+        self.trace(|_| TraceEvent::Alloc { addr, size });
+        Ok(addr)
+    }
+
+    /// Returns the `size` bytes at `ptr`, which [`alloc`](Self::alloc) handed
+    /// out, to the heap. Blocks that were never allocated or were already
+    /// freed are refused with [`ErrorKind::Corrupted`].
+    pub fn free(&mut self, ptr: VirtualPtr, size: u64) -> Result<(), Error> {
+        self.free_block(ptr, size)?;
+        self.trace(|_| TraceEvent::Free { addr: ptr, size });
+        Ok(())
+    }
+
+    fn alloc_block(&mut self, size: u64, align: u64) -> Result<VirtualPtr, Error> {
+        debug_assert!(align.is_power_of_two());
+        let fits = self.free.iter().position(|&(addr, len)| {
+            let start = addr.0.next_multiple_of(align);
+            (start - addr.0)
+                .checked_add(size)
+                .is_some_and(|needed| needed <= len)
+        });
+        if let Some(index) = fits {
+            let (addr, len) = self.free.remove(index);
+            let start = addr.0.next_multiple_of(align);
+            let (before, after) = (start - addr.0, len - (start - addr.0) - size);
+            if after > 0 {
+                self.free.insert(index, (VirtualPtr(start + size), after));
+            }
+            if before > 0 {
+                self.free.insert(index, (addr, before));
+            }
+            return Ok(VirtualPtr(start));
+        }
+
+        let top = self.heap.0;
+        let start = top.next_multiple_of(align);
+        match start.checked_add(size) {
+            Some(end) if end <= self.memory.len() => {
+                self.heap = VirtualPtr(end);
+                // The padding was never handed out, so it is not traced
+                if start > top {
+                    self.free_block(VirtualPtr(top), start - top)?;
+                }
+                Ok(VirtualPtr(start))
+            }
+            _ => Err(Error::new(ErrorKind::OutOfMemory)),
+        }
+    }
+
+    fn free_block(&mut self, ptr: VirtualPtr, size: u64) -> Result<(), Error> {
+        if size == 0 {
+            return Ok(());
+        }
+        let end = match ptr.0.checked_add(size) {
+            Some(end) if ptr >= self.stack.end && end <= self.heap.0 => end,
+            _ => return Err(Error::new(ErrorKind::Corrupted)),
+        };
+        let index = self.free.partition_point(|&(addr, _)| addr < ptr);
+        let overlaps_prev = index
+            .checked_sub(1)
+            .is_some_and(|prev| self.free[prev].0.0 + self.free[prev].1 > ptr.0);
+        let overlaps_next = self.free.get(index).is_some_and(|next| next.0.0 < end);
+        if overlaps_prev || overlaps_next {
+            return Err(Error::new(ErrorKind::Corrupted));
+        }
+
+        let (mut start, mut index, mut end) = (ptr.0, index, end);
+        if let Some(&(next, len)) = self.free.get(index)
+            && next.0 == end
+        {
+            end += len;
+            self.free.remove(index);
+        }
+        if let Some(prev) = index.checked_sub(1)
+            && self.free[prev].0.0 + self.free[prev].1 == start
+        {
+            start = self.free.remove(prev).0.0;
+            index = prev;
+        }
+        match end == self.heap.0 {
+            true => self.heap = VirtualPtr(start),
+            false => self.free.insert(index, (VirtualPtr(start), end - start)),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::tests::vm;
+
+    #[test]
+    fn test_reuse_and_merge() {
+        let (mut vm, _) = vm();
+        let bottom = vm.heap;
+        let a = vm.alloc(16, 8).unwrap();
+        let b = vm.alloc(16, 8).unwrap();
+        let c = vm.alloc(16, 8).unwrap();
+        let d = vm.alloc(16, 8).unwrap();
+
+        // A freed block is reused, and what it has left over stays free
+        vm.free(b, 16).unwrap();
+        assert_eq!(vm.alloc(8, 8), Ok(b));
+        assert_eq!(vm.free, [(VirtualPtr(b.0 + 8), 8)]);
+        vm.free(b, 8).unwrap();
+        assert_eq!(vm.free, [(b, 16)]);
+
+        // Neighbours merge on both sides
+        vm.free(a, 16).unwrap();
+        assert_eq!(vm.free, [(a, 32)]);
+        vm.free(c, 16).unwrap();
+        assert_eq!(vm.free, [(a, 48)]);
+        assert_eq!(vm.alloc(40, 8), Ok(a));
+
+        // Reaching the top lowers it
+        vm.free(a, 40).unwrap();
+        vm.free(d, 16).unwrap();
+        assert!(vm.free.is_empty());
+        assert_eq!(vm.heap, bottom);
+    }
+
+    #[test]
+    fn test_alignment() {
+        let (mut vm, _) = vm();
+        let a = vm.alloc(1, 1).unwrap();
+        let b = vm.alloc(8, 8).unwrap();
+        assert_eq!(b.0 % 8, 0);
+        // The padding before `b` is free for smaller blocks
+        assert_eq!(vm.free, [(VirtualPtr(a.0 + 1), b.0 - a.0 - 1)]);
+        assert_eq!(vm.alloc(2, 2), Ok(VirtualPtr(a.0 + 2)));
+    }
+
+    #[test]
+    fn test_rejects_bad_frees() {
+        let (mut vm, _) = vm();
+        let a = vm.alloc(16, 8).unwrap();
+        vm.alloc(16, 8).unwrap();
+        vm.free(a, 16).unwrap();
+        assert_eq!(vm.free(a, 16).unwrap_err().kind, ErrorKind::Corrupted);
+        assert_eq!(
+            vm.free(VirtualPtr(a.0 + 8), 16).unwrap_err().kind,
+            ErrorKind::Corrupted
+        );
+        assert_eq!(
+            vm.free(VirtualPtr(0), 8).unwrap_err().kind,
+            ErrorKind::Corrupted
+        );
+        assert_eq!(vm.free(vm.heap, 8).unwrap_err().kind, ErrorKind::Corrupted);
+        assert_eq!(
+            vm.alloc(vm.memory.len(), 1).unwrap_err().kind,
+            ErrorKind::OutOfMemory
+        );
+    }
+}
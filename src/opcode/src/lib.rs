@@ -1,14 +1,33 @@
 #![no_std]
 
-use alloc::{boxed::Box, vec::Vec};
-use core::{ marker::PhantomData, mem::MaybeUninit};
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::{ fmt, marker::PhantomData, mem::MaybeUninit, ops::Range};
 extern crate core;
 extern crate alloc;
 
+pub mod debug;
+pub mod disasm;
+mod borrow;
+mod heap;
+pub mod module;
+mod native;
+pub mod op;
+pub mod seq;
+mod sum;
+pub mod trace;
+mod verify;
+mod vm;
+
+use debug::{DebugInfo, SourceLocation};
+use op::Op;
+use trace::Tracer;
+
 pub struct VirtualMemory(Vec<u8>);
 
-pub struct VirtualPtr(u64);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VirtualPtr(pub u64);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Ownership {
     Owned,
     Ref,
@@ -16,8 +35,11 @@ pub enum Ownership {
     Moved
 }
 
+/// Depth of the call frame memory belongs to, 1 for the outermost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Lifetime(pub u64);
 
+#[derive(Debug)]
 pub struct Ref {
     addr: VirtualPtr,
     pointee: TypeId,
@@ -28,14 +50,21 @@ pub struct Ref {
 pub struct StackPtr {
     base: VirtualPtr,
     active: VirtualPtr,
+    /// One past the last byte of the stack.
+    end: VirtualPtr,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Variant {
-
+    pub name: String,
+    /// What the variant holds besides its tag, if anything.
+    pub payload_type: Option<TypeId>,
 }
 
-pub struct TypeId(u128);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TypeId(pub u128);
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TypeKind {
     // Primitives
     Integer { width: u8, signed: bool },
@@ -50,13 +79,14 @@ pub enum TypeKind {
     },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SumSafety {
     Untagged,    // C union - no safety
     Tagged,      // Zig union - manual checking
     Safe,        // Rust enum - compiler checking
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TypeInfo {
     internal_id: TypeId,
     kind: TypeKind,
@@ -64,16 +94,269 @@ pub struct TypeInfo {
     alignment: u64,
 }
 
+impl TypeInfo {
+    pub fn new(internal_id: TypeId, kind: TypeKind, size: u64, alignment: u64) -> Self {
+        Self {
+            internal_id,
+            kind,
+            size,
+            alignment,
+        }
+    }
+
+    pub fn id(&self) -> TypeId {
+        self.internal_id
+    }
 
+    pub fn kind(&self) -> &TypeKind {
+        &self.kind
+    }
 
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn alignment(&self) -> u64 {
+        self.alignment
+    }
+}
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Code or debug info that does not match its checksum or format.
+    Corrupted,
+    OutOfFuel,
+    TypeMismatch,
+    IndexOutOfBounds,
+    OutOfMemory,
+    StackOverflow,
+    /// Integer arithmetic past the range of its type, when it traps.
+    Overflow,
+    DivisionByZero,
+    /// A builtin that was never registered.
+    Unresolved,
+    /// A foreign signature the trampoline cannot call.
+    Unsupported,
+    /// A string that is not UTF-8.
+    InvalidUtf8,
+    /// `op` read memory at `addr` after it was moved out of.
+    UseAfterMove { op: Op, addr: VirtualPtr },
+    /// `op` accessed memory at `addr` against a live borrow of it.
+    Aliased { op: Op, addr: VirtualPtr },
+    /// `op` went through a ref to memory at `addr` of a frame that returned.
+    Dangling { op: Op, addr: VirtualPtr },
+    /// The payload of variant `expected` read from a sum holding `found`.
+    WrongVariant { expected: u64, found: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Error {
+    pub kind: ErrorKind,
+    /// Function index and byte offset of the failing instruction.
+    pub at: Option<(u32, u32)>,
+    /// Source of the failing instruction, when the module has debug info.
+    pub location: Option<SourceLocation>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            at: None,
+            location: None,
+        }
+    }
+
+    /// An error raised by the instruction at `offset` of function `func`.
+    pub fn at(kind: ErrorKind, func: u32, offset: u32) -> Self {
+        Self {
+            at: Some((func, offset)),
+            ..Self::new(kind)
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ErrorKind::Corrupted => "corrupted module",
+            ErrorKind::OutOfFuel => "out of fuel",
+            ErrorKind::TypeMismatch => "type mismatch",
+            ErrorKind::IndexOutOfBounds => "index out of bounds",
+            ErrorKind::OutOfMemory => "out of memory",
+            ErrorKind::StackOverflow => "stack overflow",
+            ErrorKind::Overflow => "arithmetic overflow",
+            ErrorKind::DivisionByZero => "division by zero",
+            ErrorKind::Unresolved => "unresolved builtin",
+            ErrorKind::Unsupported => "unsupported foreign signature",
+            ErrorKind::InvalidUtf8 => "invalid UTF-8",
+            ErrorKind::UseAfterMove { .. } => "use after move",
+            ErrorKind::Aliased { .. } => "aliased borrow",
+            ErrorKind::Dangling { .. } => "dangling ref",
+            ErrorKind::WrongVariant { .. } => "wrong variant",
+        };
+        write!(f, "{kind}")?;
+        if let ErrorKind::UseAfterMove { op, addr }
+        | ErrorKind::Aliased { op, addr }
+        | ErrorKind::Dangling { op, addr } = self.kind
+        {
+            write!(f, " by {op:?} of {:#x}", addr.0)?;
+        }
+        if let ErrorKind::WrongVariant { expected, found } = self.kind {
+            write!(f, " {found} instead of {expected}")?;
+        }
+        match (&self.location, self.at) {
+            (Some(location), _) => write!(f, " at {location}"),
+            (None, Some((func, offset))) => write!(f, " at function {func} offset {offset}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// A unit of bytecode together with the CRC-32C it was produced with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Module {
+    /// Types that operands of the code refer to by index.
+    types: Vec<TypeInfo>,
+    /// Functions that calls in the code refer to by index.
+    functions: Vec<ModuleFunction>,
+    /// Values that `Constant` instructions refer to by index.
+    constants: Vec<u64>,
+    code: Vec<u8>,
+    checksum: u32,
+    /// Kept apart from the code, which the checksum covers alone.
+    debug: Option<DebugInfo>,
+}
+
+impl Module {
+    pub fn new(code: Vec<u8>) -> Self {
+        let checksum = hash::crc32c(&code);
+        Self {
+            types: Vec::new(),
+            functions: Vec::new(),
+            constants: Vec::new(),
+            code,
+            checksum,
+            debug: None,
+        }
+    }
+
+    /// Rebuilds a module read from storage or the wire, rejecting corrupted code.
+    pub fn from_parts(code: Vec<u8>, checksum: u32) -> Result<Self, Error> {
+        let module = Self {
+            checksum,
+            ..Self::new(code)
+        };
+        if module.verify() {
+            Ok(module)
+        } else {
+            Err(Error::new(ErrorKind::Corrupted))
+        }
+    }
+
+    pub fn with_types(mut self, types: Vec<TypeInfo>) -> Self {
+        self.types = types;
+        self
+    }
+
+    pub fn with_functions(mut self, functions: Vec<ModuleFunction>) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    pub fn with_constants(mut self, constants: Vec<u64>) -> Self {
+        self.constants = constants;
+        self
+    }
+
+    pub fn with_debug(mut self, debug: DebugInfo) -> Self {
+        self.debug = Some(debug);
+        self
+    }
+
+    pub fn debug_info(&self) -> Option<&DebugInfo> {
+        self.debug.as_ref()
+    }
+
+    /// Drops the debug info. The code section and checksum are unchanged.
+    pub fn strip_debug(mut self) -> Self {
+        self.debug = None;
+        self
+    }
+
+    /// Source location of the instruction at `offset` of function `func`.
+    pub fn lookup_span(&self, func: u32, offset: u32) -> Option<SourceLocation> {
+        let debug = self.debug.as_ref()?;
+        Some(debug.resolve(debug.lookup(func, offset)?))
+    }
+
+    /// Fills in where `error` happened in the source, if known.
+    pub fn locate(&self, mut error: Error) -> Error {
+        if let Some((func, offset)) = error.at {
+            error.location = self.lookup_span(func, offset);
+        }
+        error
+    }
+
+    pub fn types(&self) -> &[TypeInfo] {
+        &self.types
+    }
+
+    pub fn functions(&self) -> &[ModuleFunction] {
+        &self.functions
+    }
+
+    pub fn constants(&self) -> &[u64] {
+        &self.constants
+    }
+
+    pub fn code(&self) -> &[u8] {
+        &self.code
+    }
+
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
 
+    pub fn verify(&self) -> bool {
+        hash::crc32c(&self.code) == self.checksum
+    }
+}
+
+/// An entry of the function table of a [`Module`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleFunction {
+    /// The `code` range of the code section, with `locals` slots including
+    /// the arguments.
+    Bytecode {
+        sig: FuncSig,
+        locals: u16,
+        code: Range<u32>,
+    },
+    /// A builtin the VM must have registered under `name` with `sig`.
+    Builtin { name: String, sig: FuncSig },
 }
 
+impl ModuleFunction {
+    pub fn sig(&self) -> &FuncSig {
+        match self {
+            ModuleFunction::Bytecode { sig, .. } | ModuleFunction::Builtin { sig, .. } => sig,
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct Int(VirtualPtr);
+#[derive(Debug)]
 pub struct Float(VirtualPtr);
 
+#[derive(Debug)]
 pub struct Seq {
+    /// Where bytecode keeps the data, length and capacity, which the other
+    /// fields were read from.
+    header: VirtualPtr,
     data: VirtualPtr,
     len: usize,
     capacity: Option<usize>,
@@ -81,6 +364,7 @@ pub struct Seq {
     owner: Ownership
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum FuncKind {
     Bytecode {
         addr: VirtualPtr,
@@ -92,22 +376,27 @@ pub enum FuncKind {
     },
     Builtin {
         name: &'static str,
+        /// Into the builtins of the VM, resolved from the name when loaded.
+        index: usize,
     },
     Foreign {
         entry_point: *const u8
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FuncSig {
     pub params: Vec<TypeId>,
     pub ret: Option<TypeId>,
 }
 
+#[derive(Debug)]
 pub struct Func {
     pub kind: FuncKind,
     pub sig: FuncSig,
 }
 
+#[derive(Debug)]
 pub enum Value {
     Int(Int),
     Float(Float),
@@ -119,13 +408,78 @@ pub enum Value {
     Never,
 }
 
+/// Index into the function table of a [`VirtualMachine`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FuncId(pub u32);
+
+/// What integer arithmetic does past the range of its type.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    #[default]
+    Wrap,
+    Trap,
+}
+
 pub struct VirtualMachine {
     pub memory: VirtualMemory,
     pub heap: VirtualPtr,
+    /// Freed blocks below `heap` as address and size, sorted and with no two
+    /// adjacent.
+    pub free: Vec<(VirtualPtr, u64)>,
     pub stack: StackPtr,
     pub call_stack: Vec<CallFrame>,
     pub function_table: Vec<Function>,
     pub builtins: Vec<BuiltinFunction>,
+    /// Types the operands of instructions refer to by index.
+    pub types: Vec<TypeInfo>,
+    pub overflow: Overflow,
+    pub borrows: Borrows,
+    /// Values `Constant` instructions refer to by index.
+    pub constants: Vec<u64>,
+    /// Set with [`set_tracer`](Self::set_tracer).
+    tracer: Option<Tracer>,
+}
+
+/// A function of the function table.
+pub struct Function {
+    pub func: Func,
+    /// Bytes of code at the address of a bytecode function.
+    pub len: u32,
+    /// Slots for locals, starting with the arguments.
+    pub locals: u16,
+}
+
+/// A borrow of the `len` bytes at `addr`, or a move out of them.
+#[derive(Debug, Clone, Copy)]
+pub struct Loan {
+    /// What bytecode holds to refer to the borrow.
+    pub id: u64,
+    pub addr: VirtualPtr,
+    pub len: u64,
+    /// `Ref` or `Mut` for borrows, `Moved` for memory moved out of.
+    pub owner: Ownership,
+    pub lifetime: Lifetime,
+    /// Set when the frame of the memory returns while it is borrowed.
+    pub dangling: bool,
+}
+
+/// The borrows and moves of a [`VirtualMachine`], checked against each
+/// access to memory they cover.
+#[derive(Debug, Default)]
+pub struct Borrows {
+    loans: Vec<Loan>,
+    next: u64,
+}
+
+/// An active call of a bytecode function.
+#[derive(Debug, Clone, Copy)]
+pub struct CallFrame {
+    pub func: FuncId,
+    /// Offset of the next instruction into the code of the function.
+    pub pc: u32,
+    pub locals: VirtualPtr,
+    /// Bottom of the operand stack, right above the locals.
+    pub operands: VirtualPtr,
 }
 
 pub struct BuiltinFunction {
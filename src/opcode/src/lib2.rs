@@ -7,7 +7,7 @@ use opcode_registry::Op;
 use ops2::SerializeCopy;
 use ops2::slice::*;
 
-pub use opcode_macro::op;
+pub use opcode_macro::{op, op_registry};
 pub use opcode_registry as registry;
 
 use crate::ops2::OpCode;
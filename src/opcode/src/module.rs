@@ -0,0 +1,649 @@
+//! The binary module format.
+//!
+//! A module starts with [`MAGIC`] and [`VERSION`], followed by the type
+//! table, the function table, the constant pool, the code section with its
+//! checksum and, last, the optional debug info. Counts and lengths are LEB128
+//! varints, type ids and constants are little-endian. Decoding checks every
+//! count against the bytes left before acting on it, so no length field can
+//! make it allocate more than the input could hold.
+
+use alloc::{string::String, vec::Vec};
+use core::fmt;
+
+use crate::{
+    Error, ErrorKind, FuncSig, Module, ModuleFunction, SumSafety, TypeId, TypeInfo, TypeKind,
+    Variant,
+    debug::{DebugInfo, write_varint},
+};
+
+pub const MAGIC: [u8; 4] = *b"OPCM";
+pub const VERSION: u16 = 2;
+
+/// Why bytes are not a module. Offsets are of the field at fault.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes do not start with [`MAGIC`].
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// The bytes end inside the field at the offset.
+    Truncated(usize),
+    /// The field at the offset holds a value out of its range.
+    Invalid(usize),
+    /// The code section does not match its checksum.
+    Checksum,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "not a module"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported module version {version}")
+            }
+            DecodeError::Truncated(offset) => write!(f, "module truncated at offset {offset}"),
+            DecodeError::Invalid(offset) => write!(f, "invalid module field at offset {offset}"),
+            DecodeError::Checksum => write!(f, "module code does not match its checksum"),
+        }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(_: DecodeError) -> Self {
+        Error::new(ErrorKind::Corrupted)
+    }
+}
+
+impl Module {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+
+        write_varint(&mut out, self.types.len() as u64);
+        for info in &self.types {
+            write_type(&mut out, info);
+        }
+
+        write_varint(&mut out, self.functions.len() as u64);
+        for function in &self.functions {
+            match function {
+                ModuleFunction::Bytecode { sig, locals, code } => {
+                    out.push(0);
+                    write_sig(&mut out, sig);
+                    write_varint(&mut out, *locals as u64);
+                    write_varint(&mut out, code.start as u64);
+                    write_varint(&mut out, code.len() as u64);
+                }
+                ModuleFunction::Builtin { name, sig } => {
+                    out.push(1);
+                    write_sig(&mut out, sig);
+                    write_str(&mut out, name);
+                }
+            }
+        }
+
+        write_varint(&mut out, self.constants.len() as u64);
+        for constant in &self.constants {
+            out.extend_from_slice(&constant.to_le_bytes());
+        }
+
+        write_varint(&mut out, self.code.len() as u64);
+        out.extend_from_slice(&self.code);
+        out.extend_from_slice(&self.checksum.to_le_bytes());
+
+        match &self.debug {
+            Some(debug) => {
+                let debug = debug.encode();
+                out.push(1);
+                write_varint(&mut out, debug.len() as u64);
+                out.extend_from_slice(&debug);
+            }
+            None => out.push(0),
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(MAGIC.len() as u64) != Ok(&MAGIC[..]) {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let mut types = Vec::new();
+        for _ in 0..reader.count()? {
+            types.push(reader.type_info()?);
+        }
+
+        let mut functions = Vec::new();
+        // Where each code range was read, to blame once the code is known
+        let mut ranges = Vec::new();
+        for _ in 0..reader.count()? {
+            let at = reader.offset;
+            let tag = reader.byte()?;
+            if tag > 1 {
+                return Err(DecodeError::Invalid(at));
+            }
+            let sig = reader.sig()?;
+            functions.push(match tag {
+                0 => {
+                    let locals = reader.int()?;
+                    let at = reader.offset;
+                    ranges.push(at);
+                    let start: u32 = reader.int()?;
+                    let len: u32 = reader.int()?;
+                    let end = start.checked_add(len).ok_or(DecodeError::Invalid(at))?;
+                    ModuleFunction::Bytecode {
+                        sig,
+                        locals,
+                        code: start..end,
+                    }
+                }
+                _ => ModuleFunction::Builtin {
+                    name: reader.string()?,
+                    sig,
+                },
+            });
+        }
+
+        let mut constants = Vec::new();
+        for _ in 0..reader.count()? {
+            constants.push(u64::from_le_bytes(reader.array()?));
+        }
+
+        let len = reader.varint()?;
+        let code = reader.take(len)?.to_vec();
+        let checksum = u32::from_le_bytes(reader.array()?);
+        if hash::crc32c(&code) != checksum {
+            return Err(DecodeError::Checksum);
+        }
+        let bytecode = functions.iter().filter_map(|function| match function {
+            ModuleFunction::Bytecode { code, .. } => Some(code),
+            ModuleFunction::Builtin { .. } => None,
+        });
+        for (range, at) in bytecode.zip(ranges) {
+            if range.end as usize > code.len() {
+                return Err(DecodeError::Invalid(at));
+            }
+        }
+
+        let at = reader.offset;
+        let debug = match reader.byte()? {
+            0 => None,
+            1 => {
+                let len = reader.varint()?;
+                let at = reader.offset;
+                let debug = DebugInfo::decode(reader.take(len)?);
+                Some(debug.map_err(|_| DecodeError::Invalid(at))?)
+            }
+            _ => return Err(DecodeError::Invalid(at)),
+        };
+        if reader.offset != bytes.len() {
+            return Err(DecodeError::Invalid(reader.offset));
+        }
+
+        Ok(Self {
+            types,
+            functions,
+            constants,
+            code,
+            checksum,
+            debug,
+        })
+    }
+}
+
+fn write_type(out: &mut Vec<u8>, info: &TypeInfo) {
+    out.extend_from_slice(&info.internal_id.0.to_le_bytes());
+    match &info.kind {
+        TypeKind::Integer { width, signed } => out.extend_from_slice(&[0, *width, *signed as u8]),
+        TypeKind::Float { width } => out.extend_from_slice(&[1, *width]),
+        TypeKind::Pointer => out.push(2),
+        TypeKind::Array { element_type, len } => {
+            out.push(3);
+            write_varint(out, *element_type as u64);
+            write_varint(out, *len as u64);
+        }
+        TypeKind::Struct { field_count } => {
+            out.push(4);
+            write_varint(out, *field_count as u64);
+        }
+        TypeKind::Sum {
+            tag_type,
+            variants,
+            safety,
+        } => {
+            out.push(5);
+            match tag_type {
+                Some(id) => {
+                    out.push(1);
+                    out.extend_from_slice(&id.0.to_le_bytes());
+                }
+                None => out.push(0),
+            }
+            out.push(*safety as u8);
+            write_varint(out, variants.len() as u64);
+            for variant in variants {
+                write_str(out, &variant.name);
+                match variant.payload_type {
+                    Some(id) => {
+                        out.push(1);
+                        out.extend_from_slice(&id.0.to_le_bytes());
+                    }
+                    None => out.push(0),
+                }
+            }
+        }
+    }
+    write_varint(out, info.size);
+    write_varint(out, info.alignment);
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_varint(out, s.len() as u64);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_sig(out: &mut Vec<u8>, sig: &FuncSig) {
+    write_varint(out, sig.params.len() as u64);
+    for param in &sig.params {
+        out.extend_from_slice(&param.0.to_le_bytes());
+    }
+    match sig.ret {
+        Some(ret) => {
+            out.push(1);
+            out.extend_from_slice(&ret.0.to_le_bytes());
+        }
+        None => out.push(0),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: u64) -> Result<&'a [u8], DecodeError> {
+        let rest = &self.bytes[self.offset..];
+        if len > rest.len() as u64 {
+            return Err(DecodeError::Truncated(self.offset));
+        }
+        self.offset += len as usize;
+        Ok(&rest[..len as usize])
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N as u64)?.try_into().unwrap())
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, DecodeError> {
+        let at = self.offset;
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte().map_err(|_| DecodeError::Truncated(at))?;
+            let bits = (byte & 0x7f) as u64;
+            if bits << shift >> shift != bits {
+                return Err(DecodeError::Invalid(at));
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::Invalid(at))
+    }
+
+    fn int<T: TryFrom<u64>>(&mut self) -> Result<T, DecodeError> {
+        let at = self.offset;
+        T::try_from(self.varint()?).map_err(|_| DecodeError::Invalid(at))
+    }
+
+    /// A count of items that take at least a byte each.
+    fn count(&mut self) -> Result<u64, DecodeError> {
+        let at = self.offset;
+        let count = self.varint()?;
+        match count <= (self.bytes.len() - self.offset) as u64 {
+            true => Ok(count),
+            false => Err(DecodeError::Truncated(at)),
+        }
+    }
+
+    fn flag(&mut self) -> Result<bool, DecodeError> {
+        let at = self.offset;
+        match self.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(DecodeError::Invalid(at)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let at = self.offset;
+        let len = self.varint()?;
+        let s = core::str::from_utf8(self.take(len)?).map_err(|_| DecodeError::Invalid(at))?;
+        Ok(String::from(s))
+    }
+
+    fn type_id(&mut self) -> Result<TypeId, DecodeError> {
+        Ok(TypeId(u128::from_le_bytes(self.array()?)))
+    }
+
+    fn type_info(&mut self) -> Result<TypeInfo, DecodeError> {
+        let id = self.type_id()?;
+        let at = self.offset;
+        let kind = match self.byte()? {
+            0 => TypeKind::Integer {
+                width: self.byte()?,
+                signed: self.flag()?,
+            },
+            1 => TypeKind::Float {
+                width: self.byte()?,
+            },
+            2 => TypeKind::Pointer,
+            3 => TypeKind::Array {
+                element_type: self.int()?,
+                len: self.int()?,
+            },
+            4 => TypeKind::Struct {
+                field_count: self.int()?,
+            },
+            5 => {
+                let tag_type = match self.flag()? {
+                    true => Some(self.type_id()?),
+                    false => None,
+                };
+                let at = self.offset;
+                let safety = match self.byte()? {
+                    0 => SumSafety::Untagged,
+                    1 => SumSafety::Tagged,
+                    2 => SumSafety::Safe,
+                    _ => return Err(DecodeError::Invalid(at)),
+                };
+                let mut variants = Vec::new();
+                for _ in 0..self.count()? {
+                    let name = self.string()?;
+                    let payload_type = match self.flag()? {
+                        true => Some(self.type_id()?),
+                        false => None,
+                    };
+                    variants.push(Variant { name, payload_type });
+                }
+                TypeKind::Sum {
+                    tag_type,
+                    variants,
+                    safety,
+                }
+            }
+            _ => return Err(DecodeError::Invalid(at)),
+        };
+        Ok(TypeInfo::new(id, kind, self.varint()?, self.varint()?))
+    }
+
+    fn sig(&mut self) -> Result<FuncSig, DecodeError> {
+        let mut params = Vec::new();
+        for _ in 0..self.count()? {
+            params.push(self.type_id()?);
+        }
+        let ret = match self.flag()? {
+            true => Some(self.type_id()?),
+            false => None,
+        };
+        Ok(FuncSig { params, ret })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        Value, VirtualMachine,
+        op::Op,
+        vm::tests::{Assembler, I64, PAIR, sig, vm},
+    };
+
+    fn print(_: &mut VirtualMachine, _: &[Value]) -> Result<Value, Error> {
+        Ok(Value::Unit)
+    }
+
+    /// `add(x) = x + 42`, `twice(x) = add(add(x))` and an imported `print`.
+    fn sample() -> Module {
+        let mut asm = Assembler::default();
+        asm.op(Op::Load, 0)
+            .op(Op::Constant, 0)
+            .op(Op::Add, 0)
+            .op(Op::Ret, 0);
+        let mut code = asm.finish();
+        let add = code.len() as u32;
+        asm.op(Op::Load, 0)
+            .op(Op::Call, 0)
+            .op(Op::Call, 0)
+            .op(Op::Ret, 0);
+        code.extend(asm.finish());
+        let twice = code.len() as u32;
+
+        let mut debug = DebugInfo::builder();
+        let file = debug.file("add.bind");
+        debug.span(0, 0..add, file, 1, 1).span(1, 0..4, file, 2, 1);
+        let sum = TypeKind::Sum {
+            tag_type: Some(I64),
+            variants: vec![
+                Variant {
+                    name: String::from("None"),
+                    payload_type: None,
+                },
+                Variant {
+                    name: String::from("Some"),
+                    payload_type: Some(PAIR),
+                },
+            ],
+            safety: SumSafety::Safe,
+        };
+        Module::new(code)
+            .with_types(vec![
+                TypeInfo::new(
+                    I64,
+                    TypeKind::Integer {
+                        width: 64,
+                        signed: true,
+                    },
+                    8,
+                    8,
+                ),
+                TypeInfo::new(PAIR, TypeKind::Struct { field_count: 2 }, 16, 8),
+                TypeInfo::new(TypeId(20), sum, 16, 8),
+                TypeInfo::new(
+                    TypeId(21),
+                    TypeKind::Array {
+                        element_type: 0,
+                        len: 4,
+                    },
+                    32,
+                    8,
+                ),
+                TypeInfo::new(TypeId(22), TypeKind::Float { width: 32 }, 4, 4),
+                TypeInfo::new(TypeId(23), TypeKind::Pointer, 8, 8),
+            ])
+            .with_functions(vec![
+                ModuleFunction::Bytecode {
+                    sig: sig(&[I64], Some(I64)),
+                    locals: 1,
+                    code: 0..add,
+                },
+                ModuleFunction::Bytecode {
+                    sig: sig(&[I64], Some(I64)),
+                    locals: 1,
+                    code: add..twice,
+                },
+                ModuleFunction::Builtin {
+                    name: String::from("print"),
+                    sig: sig(&[I64], None),
+                },
+            ])
+            .with_constants(vec![42, u64::MAX])
+            .with_debug(debug.build())
+    }
+
+    /// An xorshift generator, for inputs that are random but repeatable.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, bound: usize) -> usize {
+            (self.next() % bound as u64) as usize
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let module = sample();
+        assert_eq!(Module::decode(&module.encode()), Ok(module.clone()));
+        let stripped = module.strip_debug();
+        assert_eq!(Module::decode(&stripped.encode()), Ok(stripped));
+        let empty = Module::new(Vec::new());
+        assert_eq!(Module::decode(&empty.encode()), Ok(empty));
+    }
+
+    #[test]
+    fn test_loads_decoded() {
+        let (mut vm, _) = vm();
+        vm.register_builtin("print", sig(&[I64], None), print);
+        let module = Module::decode(&sample().encode()).unwrap();
+        let [_, twice, _] = vm.load_module(&module).unwrap()[..] else {
+            panic!();
+        };
+        let x = vm.int(1).unwrap();
+        let result = vm.run(twice, &[x]).unwrap();
+        assert_eq!(vm.read_int(&result), Ok(85));
+    }
+
+    #[test]
+    fn test_rejects_truncated() {
+        let bytes = sample().encode();
+        for len in 0..bytes.len() {
+            let error = Module::decode(&bytes[..len]).unwrap_err();
+            match len {
+                ..4 => assert_eq!(error, DecodeError::BadMagic),
+                _ => assert!(matches!(error, DecodeError::Truncated(at) if at <= len)),
+            }
+        }
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Module::decode(&trailing),
+            Err(DecodeError::Invalid(bytes.len()))
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_fields() {
+        let bytes = sample().encode();
+        let mut version = bytes.clone();
+        version[4] = 1;
+        assert_eq!(
+            Module::decode(&version),
+            Err(DecodeError::UnsupportedVersion(1))
+        );
+
+        // The last bytes are the code, its checksum and the debug info
+        let code = Module::new(vec![Op::Ret as u8]).encode();
+        let mut corrupted = code.clone();
+        corrupted[code.len() - 6] = Op::Pop as u8;
+        assert_eq!(Module::decode(&corrupted), Err(DecodeError::Checksum));
+        let mut debug = code.clone();
+        *debug.last_mut().unwrap() = 2;
+        assert_eq!(
+            Module::decode(&debug),
+            Err(DecodeError::Invalid(code.len() - 1))
+        );
+    }
+
+    #[test]
+    fn test_rejects_huge_lengths() {
+        let header = [&MAGIC[..], &VERSION.to_le_bytes()].concat();
+        let mut bytes = header.clone();
+        write_varint(&mut bytes, u64::MAX);
+        assert_eq!(Module::decode(&bytes), Err(DecodeError::Truncated(6)));
+
+        // Empty tables, then a code section claiming far more than follows
+        let mut bytes = header.clone();
+        bytes.extend([0, 0, 0]);
+        write_varint(&mut bytes, 1 << 40);
+        bytes.extend([0; 16]);
+        assert_eq!(Module::decode(&bytes), Err(DecodeError::Truncated(15)));
+
+        // A varint running past 64 bits
+        let mut bytes = header.clone();
+        bytes.extend([0xff; 10]);
+        bytes.push(0);
+        assert_eq!(Module::decode(&bytes), Err(DecodeError::Invalid(6)));
+    }
+
+    #[test]
+    fn test_fuzz_decode() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        let header = [&MAGIC[..], &VERSION.to_le_bytes()].concat();
+        for _ in 0..20_000 {
+            let len = rng.below(64);
+            let mut bytes: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+            if rng.below(2) == 0 {
+                bytes.splice(0..0, header.iter().copied());
+            }
+            let _ = Module::decode(&bytes);
+        }
+
+        // Corrupting a valid module gets past the header
+        let bytes = sample().encode();
+        for _ in 0..20_000 {
+            let mut bytes = bytes.clone();
+            for _ in 0..1 + rng.below(4) {
+                let at = rng.below(bytes.len());
+                bytes[at] = rng.next() as u8;
+            }
+            let _ = Module::decode(&bytes);
+        }
+    }
+
+    #[test]
+    fn test_fuzz_load() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        let module = sample();
+        for _ in 0..20_000 {
+            let mut code = module.code().to_vec();
+            for _ in 0..1 + rng.below(4) {
+                let at = rng.below(code.len());
+                code[at] = match rng.below(2) {
+                    0 => rng.below(Op::COUNT) as u8,
+                    _ => rng.next() as u8,
+                };
+            }
+            let module = Module::new(code)
+                .with_types(module.types().to_vec())
+                .with_functions(module.functions().to_vec())
+                .with_constants(module.constants().to_vec());
+
+            let (mut vm, _) = vm();
+            vm.register_builtin("print", sig(&[I64], None), print);
+            let functions = vm.function_table.len();
+            if vm.load_module(&module).is_err() {
+                assert_eq!(vm.function_table.len(), functions);
+            }
+        }
+    }
+}
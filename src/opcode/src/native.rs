@@ -0,0 +1,473 @@
+//! Functions implemented outside of bytecode.
+//!
+//! Builtins are Rust functions registered by name and resolved to an index
+//! when a function is loaded for them. Foreign functions are C functions
+//! called through a trampoline that puts every integer and pointer in the
+//! integer argument registers and every float in the float ones, which is how
+//! the System V x86-64 and AArch64 conventions pass them. Other signatures
+//! and targets are refused when loading.
+
+use alloc::vec::Vec;
+
+use crate::{
+    BuiltinFunction, Error, ErrorKind, Float, Func, FuncId, FuncKind, FuncSig, Function, Int,
+    Ownership, Ref, TypeKind, Value, VirtualMachine, VirtualPtr,
+    trace::TraceEvent,
+    vm::{SLOT, extend},
+};
+
+impl VirtualMachine {
+    /// Adds a builtin for [`load_builtin`](Self::load_builtin) to find by
+    /// `name`. Registering a name again replaces the builtin for functions
+    /// loaded afterwards.
+    pub fn register_builtin(
+        &mut self,
+        name: &'static str,
+        sig: FuncSig,
+        imp: fn(&mut VirtualMachine, &[Value]) -> Result<Value, Error>,
+    ) {
+        self.builtins.push(BuiltinFunction { name, imp, sig });
+    }
+
+    /// Adds a function calling the builtin registered as `name`.
+    pub fn load_builtin(&mut self, name: &str) -> Result<FuncId, Error> {
+        let index = self
+            .builtins
+            .iter()
+            .rposition(|builtin| builtin.name == name)
+            .ok_or(Error::new(ErrorKind::Unresolved))?;
+        let builtin = &self.builtins[index];
+        let sig = builtin.sig.clone();
+        let kind = FuncKind::Builtin {
+            name: builtin.name,
+            index,
+        };
+        for param in sig.params.iter().chain(&sig.ret) {
+            self.type_of(param)?;
+        }
+        Ok(self.push_native(kind, sig))
+    }
+
+    /// Adds a function calling the C function at `entry_point`. Integers of
+    /// up to 64 bits, 32 and 64 bit floats and pointers can be passed, as many
+    /// as fit in argument registers. Pointers are translated between virtual
+    /// and host addresses, so the function sees the memory of the VM.
+    ///
+    /// # Safety
+    ///
+    /// `entry_point` must be a function with the C signature `sig` describes.
+    pub unsafe fn load_foreign(
+        &mut self,
+        entry_point: *const u8,
+        sig: FuncSig,
+    ) -> Result<FuncId, Error> {
+        let (mut ints, mut floats) = (0, 0);
+        for param in &sig.params {
+            match self.type_of(param)? {
+                TypeKind::Integer { width: 1..=64, .. } | TypeKind::Pointer => ints += 1,
+                TypeKind::Float { width: 32 | 64 } => floats += 1,
+                _ => return Err(Error::new(ErrorKind::Unsupported)),
+            }
+        }
+        if let Some(ret) = &sig.ret {
+            match self.type_of(ret)? {
+                TypeKind::Integer { width: 1..=64, .. }
+                | TypeKind::Pointer
+                | TypeKind::Float { width: 32 | 64 } => {}
+                _ => return Err(Error::new(ErrorKind::Unsupported)),
+            }
+        }
+        if !trampoline::SUPPORTED || ints > trampoline::INTS || floats > trampoline::FLOATS {
+            return Err(Error::new(ErrorKind::Unsupported));
+        }
+        Ok(self.push_native(FuncKind::Foreign { entry_point }, sig))
+    }
+
+    fn push_native(&mut self, kind: FuncKind, sig: FuncSig) -> FuncId {
+        let locals = sig.params.len() as u16;
+        self.function_table.push(Function {
+            func: Func { kind, sig },
+            len: 0,
+            locals,
+        });
+        FuncId(self.function_table.len() as u32 - 1)
+    }
+
+    /// Calls the builtin or foreign function `id` with its arguments popped
+    /// from the stack, returning what it returned, if anything.
+    pub(crate) fn call_native(&mut self, id: FuncId) -> Result<Option<u64>, Error> {
+        let Func { kind, sig } = &self.function(id)?.func;
+        let (kind, sig) = (*kind, sig.clone());
+        let args = self.args(sig.params.len())?;
+        // Native calls take no frame, but count as one deeper
+        let depth = self.call_stack.len() + 1;
+        self.trace(|vm| TraceEvent::Call {
+            depth,
+            func: id,
+            args: vm.read_slots(args, sig.params.len()),
+        });
+        let result = match kind {
+            FuncKind::Builtin { index, .. } => self.call_builtin(index, &sig, args),
+            FuncKind::Foreign { entry_point } => self.call_foreign(entry_point, &sig, args),
+            _ => Err(Error::new(ErrorKind::TypeMismatch)),
+        };
+        self.stack.active = args;
+        if let Ok(value) = result {
+            self.trace(|_| TraceEvent::Return {
+                depth,
+                func: id,
+                value,
+            });
+        }
+        result
+    }
+
+    fn call_builtin(
+        &mut self,
+        index: usize,
+        sig: &FuncSig,
+        args: VirtualPtr,
+    ) -> Result<Option<u64>, Error> {
+        let imp = self
+            .builtins
+            .get(index)
+            .ok_or(Error::new(ErrorKind::Unresolved))?
+            .imp;
+        let mut values = Vec::with_capacity(sig.params.len());
+        for (slot, param) in slots(args).zip(&sig.params) {
+            values.push(match self.type_of(param)? {
+                TypeKind::Integer { .. } => Value::Int(Int(slot)),
+                TypeKind::Float { .. } => Value::Float(Float(slot)),
+                TypeKind::Pointer | TypeKind::Struct { .. } | TypeKind::Sum { .. } => {
+                    Value::Ref(Ref {
+                        addr: VirtualPtr(self.memory.read_u64(slot)?),
+                        pointee: *param,
+                        owner: Ownership::Ref,
+                        lifetime: None,
+                    })
+                }
+                _ => return Err(Error::new(ErrorKind::TypeMismatch)),
+            });
+        }
+
+        let ret = imp(self, &values)?;
+        let ret_type = match (sig.ret, ret) {
+            (_, Value::Err(error)) => return Err(error),
+            (None, Value::Unit) => return Ok(None),
+            (None, _) => return Err(Error::new(ErrorKind::TypeMismatch)),
+            (Some(ret_type), ret) => (self.type_of(&ret_type)?, ret),
+        };
+        match ret_type {
+            (&TypeKind::Integer { width, signed }, Value::Int(Int(ptr))) => {
+                let bits = self.memory.read_u64(ptr)?;
+                Ok(Some(extend(bits, width, signed)? as u64))
+            }
+            (TypeKind::Float { .. }, Value::Float(Float(ptr))) => {
+                Ok(Some(self.memory.read_u64(ptr)?))
+            }
+            (TypeKind::Pointer | TypeKind::Struct { .. }, Value::Ref(ret)) => Ok(Some(ret.addr.0)),
+            (TypeKind::Pointer, Value::Seq(ret)) => Ok(Some(ret.header.0)),
+            _ => Err(Error::new(ErrorKind::TypeMismatch)),
+        }
+    }
+
+    fn call_foreign(
+        &mut self,
+        entry_point: *const u8,
+        sig: &FuncSig,
+        args: VirtualPtr,
+    ) -> Result<Option<u64>, Error> {
+        let mut ints = [0; trampoline::INTS];
+        let mut floats = [0.0; trampoline::FLOATS];
+        let (mut int, mut float) = (0, 0);
+        for (slot, param) in slots(args).zip(&sig.params) {
+            let bits = self.memory.read_u64(slot)?;
+            match *self.type_of(param)? {
+                TypeKind::Integer { width, signed } => {
+                    ints[int] = extend(bits, width, signed)? as u64;
+                    int += 1;
+                }
+                TypeKind::Pointer => {
+                    ints[int] = self.host(bits)? as u64;
+                    int += 1;
+                }
+                // The callee only reads the low half of the register
+                TypeKind::Float { width: 32 } => {
+                    floats[float] = f64::from_bits(bits as u32 as u64);
+                    float += 1;
+                }
+                _ => {
+                    floats[float] = f64::from_bits(bits);
+                    float += 1;
+                }
+            }
+        }
+
+        let ret_type = match &sig.ret {
+            Some(ret) => Some(self.type_of(ret)?),
+            None => None,
+        };
+        let float_ret = matches!(ret_type, Some(TypeKind::Float { .. }));
+        // SAFETY: the signature was checked when loading, and the caller of
+        // `load_foreign` promised it matches the function
+        let bits = unsafe { trampoline::call(entry_point, &ints, &floats, float_ret) };
+        match ret_type {
+            None => Ok(None),
+            Some(&TypeKind::Integer { width, signed }) => {
+                Ok(Some(extend(bits, width, signed)? as u64))
+            }
+            Some(TypeKind::Float { width: 32 }) => Ok(Some(bits as u32 as u64)),
+            Some(TypeKind::Pointer) => Ok(Some(self.virtual_addr(bits as *const u8)?)),
+            Some(_) => Ok(Some(bits)),
+        }
+    }
+
+    /// The host address of `addr`, which may be one past the end of memory.
+    fn host(&mut self, addr: u64) -> Result<*mut u8, ErrorKind> {
+        match addr <= self.memory.len() {
+            true => Ok(self.memory.0.as_mut_ptr().wrapping_add(addr as usize)),
+            false => Err(ErrorKind::IndexOutOfBounds),
+        }
+    }
+
+    fn virtual_addr(&self, ptr: *const u8) -> Result<u64, ErrorKind> {
+        let addr = (ptr as usize).wrapping_sub(self.memory.0.as_ptr() as usize) as u64;
+        match addr <= self.memory.len() {
+            true => Ok(addr),
+            false => Err(ErrorKind::IndexOutOfBounds),
+        }
+    }
+}
+
+/// The stack slots of arguments starting at `args`.
+fn slots(args: VirtualPtr) -> impl Iterator<Item = VirtualPtr> {
+    (0..).map(move |index| VirtualPtr(args.0 + index * SLOT))
+}
+
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), not(windows)))]
+mod trampoline {
+    pub const SUPPORTED: bool = true;
+    pub const INTS: usize = 6;
+    pub const FLOATS: usize = 8;
+
+    #[rustfmt::skip]
+    type Trampoline<R> = unsafe extern "C" fn(
+        u64, u64, u64, u64, u64, u64,
+        f64, f64, f64, f64, f64, f64, f64, f64,
+    ) -> R;
+
+    /// Calls `entry_point` with all argument registers set, returning the
+    /// integer or float return register as bits. The function ignores the
+    /// registers it does not take.
+    pub unsafe fn call(
+        entry_point: *const u8,
+        ints: &[u64; INTS],
+        floats: &[f64; FLOATS],
+        float_ret: bool,
+    ) -> u64 {
+        let [a, b, c, d, e, f] = *ints;
+        let [g, h, i, j, k, l, m, n] = *floats;
+        unsafe {
+            match float_ret {
+                true => {
+                    let function = core::mem::transmute::<*const u8, Trampoline<f64>>(entry_point);
+                    function(a, b, c, d, e, f, g, h, i, j, k, l, m, n).to_bits()
+                }
+                false => {
+                    let function = core::mem::transmute::<*const u8, Trampoline<u64>>(entry_point);
+                    function(a, b, c, d, e, f, g, h, i, j, k, l, m, n)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(all(any(target_arch = "x86_64", target_arch = "aarch64"), not(windows))))]
+mod trampoline {
+    pub const SUPPORTED: bool = false;
+    pub const INTS: usize = 0;
+    pub const FLOATS: usize = 0;
+
+    pub unsafe fn call(_: *const u8, _: &[u64; INTS], _: &[f64; FLOATS], _: bool) -> u64 {
+        unreachable!("foreign functions are refused when loading on this target")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::{sync::Mutex, vec::Vec};
+
+    use super::*;
+    use crate::{
+        TypeId, TypeInfo,
+        op::Op,
+        vm::tests::{Assembler, F64, I64, PAIR, sig, vm},
+    };
+
+    const I32: TypeId = TypeId(6);
+    const PTR: TypeId = TypeId(7);
+    const F32: TypeId = TypeId(8);
+
+    unsafe extern "C" {
+        fn abs(x: i32) -> i32;
+        fn fabsf(x: f32) -> f32;
+        fn ldexp(x: f64, exp: i32) -> f64;
+        fn strlen(s: *const u8) -> usize;
+    }
+
+    static PRINTED: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+    fn print(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Error> {
+        PRINTED.lock().unwrap().push(vm.read_int(&args[0])?);
+        Ok(Value::Unit)
+    }
+
+    /// The number of fields of the struct passed.
+    fn len(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Error> {
+        let Value::Ref(arg) = &args[0] else {
+            return Err(Error::new(ErrorKind::TypeMismatch));
+        };
+        match vm.type_of(&arg.pointee)? {
+            TypeKind::Struct { field_count } => vm.int(*field_count as u64),
+            _ => Err(Error::new(ErrorKind::TypeMismatch)),
+        }
+    }
+
+    fn fail(_: &mut VirtualMachine, _: &[Value]) -> Result<Value, Error> {
+        Err(Error::new(ErrorKind::DivisionByZero))
+    }
+
+    fn foreign_vm() -> VirtualMachine {
+        let (mut vm, _) = vm();
+        let integer = |width| TypeKind::Integer {
+            width,
+            signed: true,
+        };
+        vm.register_type(TypeInfo::new(I32, integer(32), 4, 4));
+        vm.register_type(TypeInfo::new(PTR, TypeKind::Pointer, 8, 8));
+        vm.register_type(TypeInfo::new(F32, TypeKind::Float { width: 32 }, 4, 4));
+        vm
+    }
+
+    #[test]
+    fn test_builtins() {
+        let (mut vm, [i64, .., pair]) = vm();
+        vm.register_builtin("print", sig(&[I64], None), print);
+        vm.register_builtin("len", sig(&[PAIR], Some(I64)), len);
+        let print = vm.load_builtin("print").unwrap();
+        let len = vm.load_builtin("len").unwrap();
+
+        // print(a * 2); print(len(Pair { a, a })); return len(Pair { a, a }) + a
+        let mut asm = Assembler::default();
+        asm.op(Op::Load, 0)
+            .op(Op::Const, 2)
+            .op(Op::Mul, i64)
+            .op(Op::Call, print.0 as u64);
+        asm.op(Op::Load, 0)
+            .op(Op::Dup, 0)
+            .op(Op::Construct, pair)
+            .op(Op::Dup, 0)
+            .op(Op::Call, len.0 as u64)
+            .op(Op::Call, print.0 as u64)
+            .op(Op::Call, len.0 as u64);
+        asm.op(Op::Load, 0).op(Op::Add, i64).op(Op::Ret, 0);
+        let main = vm.load(&asm.finish(), sig(&[I64], Some(I64)), 1).unwrap();
+
+        let a = vm.int(21).unwrap();
+        let result = vm.run(main, &[a]).unwrap();
+        assert_eq!(vm.read_int(&result), Ok(23));
+        assert_eq!(*PRINTED.lock().unwrap(), [42, 2]);
+        assert_eq!(vm.stack.active, vm.stack.base);
+
+        // Builtins may also be the entry
+        let a = vm.int(21).unwrap();
+        let result = vm.run(len, &[a]).unwrap_err();
+        assert_eq!(result.kind, ErrorKind::TypeMismatch);
+        assert_eq!(
+            vm.load_builtin("missing").unwrap_err().kind,
+            ErrorKind::Unresolved
+        );
+    }
+
+    #[test]
+    fn test_builtin_error_unwinds() {
+        let (mut vm, _) = vm();
+        vm.register_builtin("fail", sig(&[], Some(I64)), fail);
+        let fail = vm.load_builtin("fail").unwrap();
+
+        let mut asm = Assembler::default();
+        asm.op(Op::Const, 1)
+            .op(Op::Call, fail.0 as u64)
+            .op(Op::Ret, 0);
+        let inner = vm.load(&asm.finish(), sig(&[], Some(I64)), 0).unwrap();
+        let mut asm = Assembler::default();
+        asm.op(Op::Call, inner.0 as u64).op(Op::Ret, 0);
+        let outer = vm.load(&asm.finish(), sig(&[], Some(I64)), 0).unwrap();
+
+        let error = vm.run(outer, &[]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::DivisionByZero);
+        assert_eq!(error.at, Some((inner.0, 9)));
+        assert!(vm.call_stack.is_empty());
+        assert_eq!(vm.stack.active, vm.stack.base);
+    }
+
+    #[test]
+    fn test_foreign() {
+        let mut vm = foreign_vm();
+        let (abs, fabsf, ldexp, strlen) = unsafe {
+            (
+                vm.load_foreign(abs as *const u8, sig(&[I32], Some(I32))),
+                vm.load_foreign(fabsf as *const u8, sig(&[F32], Some(F32))),
+                vm.load_foreign(ldexp as *const u8, sig(&[F64, I32], Some(F64))),
+                vm.load_foreign(strlen as *const u8, sig(&[PTR], Some(I64))),
+            )
+        };
+
+        let x = vm.int(-7i64 as u64).unwrap();
+        let result = vm.run(abs.unwrap(), &[x]).unwrap();
+        assert_eq!(vm.read_int(&result), Ok(7));
+
+        let x = Value::Float(Float(vm.cell((-2.5f32).to_bits() as u64).unwrap()));
+        let result = vm.run(fabsf.unwrap(), &[x]).unwrap();
+        let Value::Float(Float(ptr)) = result else {
+            panic!("{result:?}");
+        };
+        let bits = vm.memory.read_u64(ptr).unwrap();
+        assert_eq!(f32::from_bits(bits as u32), 2.5);
+
+        let (x, exp) = (vm.float(1.5).unwrap(), vm.int(3).unwrap());
+        let result = vm.run(ldexp.unwrap(), &[x, exp]).unwrap();
+        assert_eq!(vm.read_float(&result), Ok(12.0));
+
+        let addr = vm.alloc(6, 1).unwrap();
+        vm.memory.write(addr, b"hello\0").unwrap();
+        let s = Value::Ref(Ref {
+            addr,
+            pointee: PTR,
+            owner: Ownership::Ref,
+            lifetime: None,
+        });
+        let result = vm.run(strlen.unwrap(), &[s]).unwrap();
+        assert_eq!(vm.read_int(&result), Ok(5));
+    }
+
+    #[test]
+    fn test_unsupported_foreign() {
+        let mut vm = foreign_vm();
+        let entry_point = abs as *const u8;
+        let unsupported = [
+            sig(&[PAIR], None),
+            sig(&[], Some(PAIR)),
+            sig(&[I64; 7], None),
+            sig(&[F64; 9], None),
+        ];
+        for sig in unsupported {
+            let error = unsafe { vm.load_foreign(entry_point, sig) }.unwrap_err();
+            assert_eq!(error.kind, ErrorKind::Unsupported);
+        }
+        let loaded = unsafe { vm.load_foreign(entry_point, sig(&[I64; 6], None)) };
+        assert!(loaded.is_ok());
+    }
+}
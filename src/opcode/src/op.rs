@@ -0,0 +1,289 @@
+//! The instruction encoding.
+//!
+//! An instruction is its [`Op`] byte followed by a little-endian operand of
+//! [`Op::operand_len`] bytes. Jump targets are offsets into the code of the
+//! function, types are indices into the type table of the VM.
+
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use span_macro::bytecode;
+
+bytecode! {
+    pub enum Op {
+        /// Pushes the 8 byte operand.
+        Const,
+        Pop,
+        Dup,
+        /// Pushes the local of the 2 byte operand.
+        Load,
+        /// Pops into the local of the 2 byte operand.
+        Store,
+        /// Pops `b`, then `a`, and pushes `a + b` of the type of the 2 byte
+        /// operand, an integer or a float. So do the others up to `Rem`.
+        Add,
+        Sub,
+        Mul,
+        Div,
+        Rem,
+        /// Pops `b`, then `a`, and pushes 1 if `a == b` for the type of the
+        /// 2 byte operand, else 0. So do the others up to `Ge`.
+        Eq,
+        Ne,
+        Lt,
+        Le,
+        Gt,
+        Ge,
+        /// Continues at the 4 byte offset.
+        Jump,
+        /// Pops a condition and continues at the 4 byte offset if it is not 0.
+        BranchIf,
+        /// Pops a condition and continues at the 4 byte offset if it is 0.
+        BranchIfNot,
+        /// Calls the function of the 4 byte index with its arguments popped,
+        /// the first pushed first.
+        Call,
+        /// Returns the popped value, or nothing if the function returns no
+        /// value.
+        Ret,
+        /// Pops the fields of the struct type of the 2 byte operand, the last
+        /// on top, and pushes a pointer to the struct.
+        Construct,
+        /// Pops a pointer to a struct of the type of the 2 byte operand and
+        /// pushes its fields, the last on top.
+        Destructure,
+        /// Pops a capacity and pushes a new sequence of the element type of
+        /// the 2 byte operand. So do the others up to `SeqSet` take the type.
+        SeqNew,
+        /// Pops a value, then a sequence, and appends the value.
+        SeqPush,
+        /// Pops an index, then a sequence, and pushes the element there.
+        SeqGet,
+        /// Pops a value, an index, then a sequence, and replaces the element
+        /// there.
+        SeqSet,
+        /// Pops a sequence and pushes its length.
+        SeqLen,
+        /// Pushes the local of the 2 byte operand and marks it moved out of,
+        /// so that it cannot be read again until stored to.
+        Move,
+        /// Borrows the local of the 2 byte operand and pushes the borrow.
+        Borrow,
+        /// Borrows the local of the 2 byte operand mutably and pushes the
+        /// borrow.
+        BorrowMut,
+        /// Pops a borrow and pushes the value it refers to.
+        Deref,
+        /// Pops a value, then a mutable borrow, and stores the value through
+        /// the borrow.
+        Assign,
+        /// Pops a borrow and ends it.
+        Release,
+        /// Pushes the value of the 4 byte index into the constant pool.
+        Constant,
+        /// Pops a payload, then a variant index, and pushes a new value of the
+        /// sum type of the 2 byte operand. The payload is dropped for variants
+        /// without one. So do the others up to `SumPayloadUnchecked` take the
+        /// type.
+        SumNew,
+        /// Pops a value of a `Safe` or `Tagged` sum and pushes the index of
+        /// the variant it holds.
+        SumTag,
+        /// Pops a variant index, then a value of a `Safe` or `Tagged` sum, and
+        /// pushes the payload if the value holds that variant.
+        SumPayload,
+        /// As `SumPayload`, but reads the payload of the variant popped
+        /// whichever the value holds. Not allowed for `Safe` sums.
+        SumPayloadUnchecked,
+    }
+}
+
+impl Op {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Self::try_from(byte).ok()
+    }
+
+    pub const fn operand_len(self) -> usize {
+        match self {
+            Self::Pop
+            | Self::Dup
+            | Self::Ret
+            | Self::SeqLen
+            | Self::Deref
+            | Self::Assign
+            | Self::Release => 0,
+            Self::Load
+            | Self::Store
+            | Self::Add
+            | Self::Sub
+            | Self::Mul
+            | Self::Div
+            | Self::Rem
+            | Self::Eq
+            | Self::Ne
+            | Self::Lt
+            | Self::Le
+            | Self::Gt
+            | Self::Ge
+            | Self::Construct
+            | Self::Destructure
+            | Self::SeqNew
+            | Self::SeqPush
+            | Self::SeqGet
+            | Self::SeqSet
+            | Self::Move
+            | Self::Borrow
+            | Self::BorrowMut
+            | Self::SumNew
+            | Self::SumTag
+            | Self::SumPayload
+            | Self::SumPayloadUnchecked => 2,
+            Self::Jump | Self::BranchIf | Self::BranchIfNot | Self::Call | Self::Constant => 4,
+            Self::Const => 8,
+        }
+    }
+
+    /// What the operand refers to.
+    pub const fn operand(self) -> Operand {
+        match self {
+            Self::Pop
+            | Self::Dup
+            | Self::Ret
+            | Self::SeqLen
+            | Self::Deref
+            | Self::Assign
+            | Self::Release => Operand::None,
+            Self::Const => Operand::Value,
+            Self::Load | Self::Store | Self::Move | Self::Borrow | Self::BorrowMut => {
+                Operand::Local
+            }
+            Self::Add
+            | Self::Sub
+            | Self::Mul
+            | Self::Div
+            | Self::Rem
+            | Self::Eq
+            | Self::Ne
+            | Self::Lt
+            | Self::Le
+            | Self::Gt
+            | Self::Ge
+            | Self::Construct
+            | Self::Destructure
+            | Self::SeqNew
+            | Self::SeqPush
+            | Self::SeqGet
+            | Self::SeqSet
+            | Self::SumNew
+            | Self::SumTag
+            | Self::SumPayload
+            | Self::SumPayloadUnchecked => Operand::Type,
+            Self::Jump | Self::BranchIf | Self::BranchIfNot => Operand::Target,
+            Self::Call => Operand::Func,
+            Self::Constant => Operand::Constant,
+        }
+    }
+}
+
+/// The mnemonic of disassembly listings and traces, the name in snake case.
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, c) in self.name().char_indices() {
+            if c.is_ascii_uppercase() && i > 0 {
+                f.write_char('_')?;
+            }
+            f.write_char(c.to_ascii_lowercase())?;
+        }
+        Ok(())
+    }
+}
+
+/// What the operand of an [`Op`] refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    None,
+    /// An immediate value.
+    Value,
+    /// A local of the function.
+    Local,
+    /// An index into the type table.
+    Type,
+    /// An offset into the code of the function.
+    Target,
+    /// An index into the function table.
+    Func,
+    /// An index into the constant pool.
+    Constant,
+}
+
+/// Appends an instruction to `code`. Bytes of the operand past
+/// [`Op::operand_len`] are dropped.
+pub fn encode(code: &mut Vec<u8>, op: Op, operand: u64) {
+    op.encode(code);
+    code.extend_from_slice(&operand.to_le_bytes()[..op.operand_len()]);
+}
+
+/// The instruction at `offset` of `code`, its operand and the offset of the
+/// next one.
+pub fn decode(code: &[u8], offset: usize) -> Option<(Op, u64, usize)> {
+    let mut rest = code.get(offset..)?;
+    let op = Op::decode(&mut rest).ok()?;
+    let len = op.operand_len();
+    let mut operand = [0; 8];
+    operand[..len].copy_from_slice(rest.get(..len)?);
+    Some((op, u64::from_le_bytes(operand), offset + 1 + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut code = Vec::new();
+        encode(&mut code, Op::Const, u64::MAX - 1);
+        encode(&mut code, Op::Load, 3);
+        encode(&mut code, Op::Jump, 0x0102_0304);
+        encode(&mut code, Op::Ret, 0);
+        assert_eq!(code.len(), 9 + 3 + 5 + 1);
+
+        let mut offset = 0;
+        let mut decoded = Vec::new();
+        while let Some((op, operand, next)) = decode(&code, offset) {
+            decoded.push((op, operand));
+            offset = next;
+        }
+        assert_eq!(offset, code.len());
+        assert_eq!(
+            decoded,
+            [
+                (Op::Const, u64::MAX - 1),
+                (Op::Load, 3),
+                (Op::Jump, 0x0102_0304),
+                (Op::Ret, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_truncated_and_unknown() {
+        let last = Op::SumPayloadUnchecked;
+        assert_eq!(Op::from_byte(last as u8), Some(last));
+        assert_eq!(Op::from_byte(Op::COUNT as u8), None);
+        assert_eq!(Op::try_from(Op::COUNT as u8), Err(UnknownOpcode(39)));
+        assert_eq!(decode(&[Op::Const as u8, 1, 2], 0), None);
+        assert_eq!(decode(&[], 0), None);
+    }
+
+    #[test]
+    fn test_mnemonics() {
+        assert_eq!(Op::BranchIfNot.name(), "BranchIfNot");
+        assert_eq!(Op::BranchIfNot.to_string(), "branch_if_not");
+        assert_eq!(Op::SumPayloadUnchecked.to_string(), "sum_payload_unchecked");
+        assert_eq!(OPCODE_TABLE.len(), Op::COUNT);
+        assert!(OPCODE_TABLE.iter().all(|info| info.enum_name == "Op"));
+        assert_eq!(OPCODE_TABLE[Op::Ret as usize].name, "Ret");
+    }
+}
@@ -1,5 +1,5 @@
 use super::OpCode;
-use crate::registry::OpName;
+use crate::registry::{OpInfo, OpName};
 use crate::*;
 use crate::{Decode, Decoder, Encode, Encoder, Error, Serialize};
 use core::mem;
@@ -8,11 +8,11 @@ use super::primitive::Primitive;
 /// Opcode for encoding Copy types by transmuting to bytes
 /// This excludes primitive types which are handled by SerializePrimitive
 #[derive(Debug, Clone)]
-#[op]
+#[op(id = 0)]
 pub struct CopyEncode<T: Copy + 'static>(T);
 
 #[derive(Debug, Clone)]
-#[op]
+#[op(id = 1)]
 pub struct CopyDecode<T: Copy + 'static>(T);
 
 
@@ -1,5 +1,5 @@
 use super::OpCode;
-use crate::registry::OpName;
+use crate::registry::{OpInfo, OpName};
 use crate::*;
 use crate::{Decode, Decoder, Encode, Encoder, Error, Serialize};
 use core::mem;
@@ -77,7 +77,7 @@ impl From<Int> for usize {
 
 /// Opcode for encoding variable-length integers
 #[derive(Debug, Clone)]
-#[op]
+#[op(id = 4)]
 pub struct SerializeInt {
     pub value: Int,
 }
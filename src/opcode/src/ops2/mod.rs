@@ -6,7 +6,7 @@ pub mod slice;
 pub mod int;
 use std::{any::Any, ops};
 
-use crate::{registry, Serialize};
+use crate::{op_registry, registry, registry::OpInfo, Serialize};
 
 pub trait OpCode  {
    type Repr: Serialize;
@@ -16,4 +16,13 @@ pub trait OpCode  {
 }
 
 // Re-export the main opcodes
-pub use copy::SerializeCopy;
\ No newline at end of file
+pub use copy::SerializeCopy;
+
+// Every opcode, by the IDs given in #[op(id = N)]
+op_registry! {
+    copy::CopyEncode<u8>,
+    copy::CopyDecode<u8>,
+    primitive::SerializePrimitive<u8>,
+    slice::SerializeSlice<'static, u8>,
+    int::SerializeInt,
+}
@@ -1,5 +1,5 @@
 use super::OpCode;
-use crate::registry::OpName;
+use crate::registry::{OpInfo, OpName};
 use crate::*;
 use crate::{Decode, Decoder, Encode, Encoder, Error, Serialize};
 use core::mem;
@@ -12,7 +12,7 @@ pub trait NotPrimitive: Copy + 'static {}
 
 /// Opcode for encoding primitive types by transmuting to bytes
 #[derive(Debug, Clone)]
-#[op]
+#[op(id = 2)]
 pub struct SerializePrimitive<T: Primitive> {
     pub value: T,
 }
@@ -6,12 +6,12 @@ use core::mem;
 use core::slice::SlicePattern;
 use std::default;
 use crate::ops2::OpCode;
-use crate::registry::OpName;
+use crate::registry::{OpInfo, OpName};
 use crate::op;
 
 /// Opcode for encoding slices using smart encoding
 #[derive(Debug, Clone)]
-#[op]
+#[op(id = 3)]
 pub enum SerializeSlice<'a, T> {
     Owned(Vec<T>),
     Borrowed(&'a [T])
@@ -0,0 +1,433 @@
+//! Sequences and strings.
+//!
+//! Bytecode refers to a sequence by the address of its header, three slots
+//! holding the address of the data, the length and the capacity. Elements are
+//! stored at the size of their type, structs as pointers to them, and a push
+//! that finds the data full moves it to a block twice as large. Strings are
+//! sequences of bytes holding UTF-8.
+
+use alloc::vec::Vec;
+
+use crate::{
+    Error, ErrorKind, Ownership, Ref, Seq, TypeInfo, TypeKind, Value, VirtualMachine, VirtualPtr,
+    vm::{SLOT, extend},
+};
+
+/// The capacity a sequence grows to from none.
+const MIN_CAPACITY: u64 = 4;
+
+pub(crate) struct Header {
+    pub data: VirtualPtr,
+    pub len: u64,
+    pub capacity: u64,
+}
+
+/// How values of a type are stored, as elements of sequences and payloads of
+/// sums.
+pub(crate) struct Layout {
+    pub size: u64,
+    pub alignment: u64,
+    /// Width and signedness of integers, which are extended again when read.
+    pub integer: Option<(u8, bool)>,
+}
+
+impl VirtualMachine {
+    /// A sequence of bytes holding `s`, with `element` the index of a 1 byte
+    /// integer type.
+    pub fn string(&mut self, element: u16, s: &str) -> Result<Value, Error> {
+        if self.layout(element as u64)?.size != 1 {
+            return Err(Error::new(ErrorKind::TypeMismatch));
+        }
+        let len = s.len() as u64;
+        let header = self.seq_new(element as u64, len)?;
+        let data = self.seq_header(header)?.data;
+        self.memory.write(data, s.as_bytes())?;
+        self.write_header(
+            header,
+            &Header {
+                data,
+                len,
+                capacity: len,
+            },
+        )?;
+        Ok(Value::Seq(Seq {
+            header,
+            data,
+            len: s.len(),
+            capacity: Some(s.len()),
+            element: self.types[element as usize].clone(),
+            owner: Ownership::Owned,
+        }))
+    }
+
+    /// The bytes of the sequence `value` refers to, if they are UTF-8.
+    pub fn read_string(&self, value: &Value) -> Result<&str, Error> {
+        let header = match value {
+            Value::Seq(seq) => seq.header,
+            Value::Ref(seq) => seq.addr,
+            _ => return Err(Error::new(ErrorKind::TypeMismatch)),
+        };
+        let header = self.seq_header(header)?;
+        let bytes = self.memory.read(header.data, header.len)?;
+        core::str::from_utf8(bytes).map_err(|_| Error::new(ErrorKind::InvalidUtf8))
+    }
+
+    pub(crate) fn seq_new(&mut self, element: u64, capacity: u64) -> Result<VirtualPtr, Error> {
+        let size = self.layout(element)?.size;
+        let bytes = capacity
+            .checked_mul(size)
+            .ok_or(Error::new(ErrorKind::OutOfMemory))?;
+        let data = self.alloc(bytes, SLOT)?;
+        let header = match self.alloc(3 * SLOT, SLOT) {
+            Ok(header) => header,
+            Err(error) => {
+                self.free(data, bytes)?;
+                return Err(error);
+            }
+        };
+        self.write_header(
+            header,
+            &Header {
+                data,
+                len: 0,
+                capacity,
+            },
+        )?;
+        Ok(header)
+    }
+
+    pub(crate) fn seq_header(&self, seq: VirtualPtr) -> Result<Header, Error> {
+        let slot = |index| VirtualPtr(seq.0.wrapping_add(index * SLOT));
+        Ok(Header {
+            data: VirtualPtr(self.memory.read_u64(slot(0))?),
+            len: self.memory.read_u64(slot(1))?,
+            capacity: self.memory.read_u64(slot(2))?,
+        })
+    }
+
+    fn write_header(&mut self, seq: VirtualPtr, header: &Header) -> Result<(), Error> {
+        let mut bytes = [0; 3 * SLOT as usize];
+        for (slot, value) in
+            bytes
+                .chunks_exact_mut(SLOT as usize)
+                .zip([header.data.0, header.len, header.capacity])
+        {
+            slot.copy_from_slice(&value.to_le_bytes());
+        }
+        self.memory.write(seq, &bytes)
+    }
+
+    pub(crate) fn seq_push(
+        &mut self,
+        seq: VirtualPtr,
+        element: u64,
+        value: u64,
+    ) -> Result<(), Error> {
+        let layout = self.layout(element)?;
+        let mut header = self.seq_header(seq)?;
+        if header.len >= header.capacity {
+            self.grow(&mut header, layout.size)?;
+        }
+        header.len += 1;
+        let addr = element_at(&header, &layout, header.len - 1)?;
+        self.write_stored(addr, &layout, value)?;
+        self.write_header(seq, &header)
+    }
+
+    pub(crate) fn seq_get(&self, seq: VirtualPtr, element: u64, index: u64) -> Result<u64, Error> {
+        let layout = self.layout(element)?;
+        let header = self.seq_header(seq)?;
+        let addr = element_at(&header, &layout, index)?;
+        self.read_stored(addr, &layout)
+    }
+
+    pub(crate) fn seq_set(
+        &mut self,
+        seq: VirtualPtr,
+        element: u64,
+        index: u64,
+        value: u64,
+    ) -> Result<(), Error> {
+        let layout = self.layout(element)?;
+        let header = self.seq_header(seq)?;
+        let addr = element_at(&header, &layout, index)?;
+        self.write_stored(addr, &layout, value)
+    }
+
+    /// The value stored at `addr` as `layout` says, back in a slot.
+    pub(crate) fn read_stored(&self, addr: VirtualPtr, layout: &Layout) -> Result<u64, Error> {
+        let mut bytes = [0; SLOT as usize];
+        bytes[..layout.size as usize].copy_from_slice(self.memory.read(addr, layout.size)?);
+        let bits = u64::from_le_bytes(bytes);
+        match layout.integer {
+            Some((width, signed)) => Ok(extend(bits, width, signed)? as u64),
+            None => Ok(bits),
+        }
+    }
+
+    pub(crate) fn write_stored(
+        &mut self,
+        addr: VirtualPtr,
+        layout: &Layout,
+        value: u64,
+    ) -> Result<(), Error> {
+        self.memory
+            .write(addr, &value.to_le_bytes()[..layout.size as usize])
+    }
+
+    /// Moves the data of `header` to a block of twice the capacity.
+    fn grow(&mut self, header: &mut Header, size: u64) -> Result<(), Error> {
+        let capacity = header
+            .capacity
+            .checked_mul(2)
+            .ok_or(Error::new(ErrorKind::OutOfMemory))?
+            .max(MIN_CAPACITY);
+        let bytes = capacity
+            .checked_mul(size)
+            .ok_or(Error::new(ErrorKind::OutOfMemory))?;
+        let data = self.alloc(bytes, SLOT)?;
+        let used: Vec<u8> = self.memory.read(header.data, header.len * size)?.to_vec();
+        self.memory.write(data, &used)?;
+        self.free(header.data, header.capacity * size)?;
+        header.data = data;
+        header.capacity = capacity;
+        Ok(())
+    }
+
+    fn layout(&self, element: u64) -> Result<Layout, ErrorKind> {
+        let info = self
+            .types
+            .get(element as usize)
+            .ok_or(ErrorKind::TypeMismatch)?;
+        storage(info)
+    }
+}
+
+/// How values of `info` are stored: scalars at their size, anything else as a
+/// pointer to it.
+pub(crate) fn storage(info: &TypeInfo) -> Result<Layout, ErrorKind> {
+    let scalar = (1..=SLOT).contains(&info.size)
+        && info.alignment.is_power_of_two()
+        && info.alignment <= info.size;
+    match info.kind {
+        TypeKind::Integer { width, signed } if scalar && width as u64 <= info.size * 8 => {
+            Ok(Layout {
+                size: info.size,
+                alignment: info.alignment,
+                integer: Some((width, signed)),
+            })
+        }
+        TypeKind::Float { width } if scalar && width as u64 == info.size * 8 => Ok(Layout {
+            size: info.size,
+            alignment: info.alignment,
+            integer: None,
+        }),
+        TypeKind::Pointer | TypeKind::Struct { .. } | TypeKind::Sum { .. } => Ok(Layout {
+            size: SLOT,
+            alignment: SLOT,
+            integer: None,
+        }),
+        _ => Err(ErrorKind::TypeMismatch),
+    }
+}
+
+fn element_at(header: &Header, layout: &Layout, index: u64) -> Result<VirtualPtr, ErrorKind> {
+    if index >= header.len {
+        return Err(ErrorKind::IndexOutOfBounds);
+    }
+    index
+        .checked_mul(layout.size)
+        .and_then(|offset| header.data.0.checked_add(offset))
+        .map(VirtualPtr)
+        .ok_or(ErrorKind::IndexOutOfBounds)
+}
+
+/// A builtin returning the sequence of bytes it takes if they are UTF-8, for
+/// bytecode to treat it as a string from then on.
+pub fn from_utf8(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Error> {
+    let [Value::Ref(bytes)] = args else {
+        return Err(Error::new(ErrorKind::TypeMismatch));
+    };
+    vm.read_string(&args[0])?;
+    Ok(Value::Ref(Ref {
+        addr: bytes.addr,
+        pointee: bytes.pointee,
+        owner: Ownership::Ref,
+        lifetime: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        TypeId, TypeInfo,
+        op::Op,
+        vm::tests::{Assembler, I64, sig, vm},
+    };
+
+    const PTR: TypeId = TypeId(6);
+
+    /// A machine with the test types and a pointer type, loaded with
+    /// `squares(n) -> seq` and `get(seq, index) -> i64`.
+    fn squares() -> (VirtualMachine, [u64; 6], [crate::FuncId; 2]) {
+        let (mut vm, [i64, i8, u8, f64, pair]) = vm();
+        let ptr = vm.register_type(TypeInfo::new(PTR, TypeKind::Pointer, 8, 8)) as u64;
+
+        // n, seq, i
+        let mut asm = Assembler::default();
+        let (top, end) = (asm.label(), asm.label());
+        asm.op(Op::Const, 0).op(Op::SeqNew, i64).op(Op::Store, 1);
+        asm.bind(top);
+        asm.op(Op::Load, 2).op(Op::Load, 0).op(Op::Lt, i64);
+        asm.jump(Op::BranchIfNot, end);
+        asm.op(Op::Load, 1)
+            .op(Op::Load, 2)
+            .op(Op::Dup, 0)
+            .op(Op::Mul, i64)
+            .op(Op::SeqPush, i64);
+        asm.op(Op::Load, 2)
+            .op(Op::Const, 1)
+            .op(Op::Add, i64)
+            .op(Op::Store, 2);
+        asm.jump(Op::Jump, top);
+        asm.bind(end).op(Op::Load, 1).op(Op::Ret, 0);
+        let squares = vm.load(&asm.finish(), sig(&[I64], Some(PTR)), 3).unwrap();
+
+        let mut asm = Assembler::default();
+        asm.op(Op::Load, 0)
+            .op(Op::Load, 1)
+            .op(Op::SeqGet, i64)
+            .op(Op::Ret, 0);
+        let get = vm
+            .load(&asm.finish(), sig(&[PTR, I64], Some(I64)), 2)
+            .unwrap();
+        (vm, [i64, i8, u8, f64, pair, ptr], [squares, get])
+    }
+
+    fn seq_addr(value: &Value) -> VirtualPtr {
+        match value {
+            Value::Ref(seq) => seq.addr,
+            _ => panic!("{value:?}"),
+        }
+    }
+
+    #[test]
+    fn test_build_and_grow() {
+        let (mut vm, _, [squares, get]) = squares();
+        let n = vm.int(100).unwrap();
+        let seq = vm.run(squares, &[n]).unwrap();
+
+        // 0, 4, 8, .., 128 means five moves
+        let header = vm.seq_header(seq_addr(&seq)).unwrap();
+        assert_eq!((header.len, header.capacity), (100, 128));
+        // The blocks moved out of were merged back into one
+        let moved = (4 + 8 + 16 + 32 + 64) * SLOT;
+        assert!(
+            vm.free.iter().any(|&(_, len)| len == moved),
+            "{:?}",
+            vm.free
+        );
+
+        for index in [0, 1, 57, 99] {
+            let seq = Value::Ref(Ref {
+                addr: seq_addr(&seq),
+                pointee: PTR,
+                owner: Ownership::Ref,
+                lifetime: None,
+            });
+            let i = vm.int(index).unwrap();
+            let result = vm.run(get, &[seq, i]).unwrap();
+            assert_eq!(vm.read_int(&result), Ok(index * index));
+        }
+    }
+
+    #[test]
+    fn test_set_and_len() {
+        let (mut vm, [i64, ..], _) = squares();
+        // Sets [1] to the length, then returns [1] + [0]
+        let mut asm = Assembler::default();
+        asm.op(Op::Const, 2)
+            .op(Op::SeqNew, i64)
+            .op(Op::Store, 0)
+            .op(Op::Load, 0)
+            .op(Op::Const, -5i64 as u64)
+            .op(Op::SeqPush, i64)
+            .op(Op::Load, 0)
+            .op(Op::Const, 0)
+            .op(Op::SeqPush, i64);
+        asm.op(Op::Load, 0)
+            .op(Op::Const, 1)
+            .op(Op::Load, 0)
+            .op(Op::SeqLen, 0)
+            .op(Op::SeqSet, i64);
+        asm.op(Op::Load, 0)
+            .op(Op::Const, 1)
+            .op(Op::SeqGet, i64)
+            .op(Op::Load, 0)
+            .op(Op::Const, 0)
+            .op(Op::SeqGet, i64)
+            .op(Op::Add, i64)
+            .op(Op::Ret, 0);
+        let main = vm.load(&asm.finish(), sig(&[], Some(I64)), 1).unwrap();
+        let result = vm.run(main, &[]).unwrap();
+        assert_eq!(vm.read_int(&result), Ok(-3i64 as u64));
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let (mut vm, _, [squares, get]) = squares();
+        let n = vm.int(3).unwrap();
+        let seq = seq_addr(&vm.run(squares, &[n]).unwrap());
+        let active = vm.stack.active;
+
+        let arg = |vm: &mut VirtualMachine, index| {
+            let seq = Value::Ref(Ref {
+                addr: seq,
+                pointee: PTR,
+                owner: Ownership::Ref,
+                lifetime: None,
+            });
+            (seq, vm.int(index).unwrap())
+        };
+        for index in [3, u64::MAX] {
+            let (seq, i) = arg(&mut vm, index);
+            let error = vm.run(get, &[seq, i]).unwrap_err();
+            assert_eq!(error.kind, ErrorKind::IndexOutOfBounds);
+            assert_eq!(error.at, Some((get.0, 6)));
+            assert_eq!(vm.stack.active, active);
+        }
+        // The machine carries on after the error
+        let (seq, i) = arg(&mut vm, 2);
+        let result = vm.run(get, &[seq, i]).unwrap();
+        assert_eq!(vm.read_int(&result), Ok(4));
+    }
+
+    #[test]
+    fn test_strings() {
+        let (mut vm, [i64, _, u8, ..], _) = squares();
+        vm.register_builtin("from_utf8", sig(&[PTR], Some(PTR)), from_utf8);
+        let from_utf8 = vm.load_builtin("from_utf8").unwrap();
+
+        let s = vm.string(u8 as u16, "héllo").unwrap();
+        assert_eq!(vm.read_string(&s), Ok("héllo"));
+        let s = vm.run(from_utf8, &[s]).unwrap();
+        assert_eq!(vm.read_string(&s), Ok("héllo"));
+
+        // Appends a lone continuation byte
+        let mut asm = Assembler::default();
+        asm.op(Op::Load, 0)
+            .op(Op::Const, 0x80)
+            .op(Op::SeqPush, u8)
+            .op(Op::Load, 0)
+            .op(Op::Call, from_utf8.0 as u64)
+            .op(Op::Ret, 0);
+        let append = vm.load(&asm.finish(), sig(&[PTR], Some(PTR)), 1).unwrap();
+        let error = vm.run(append, &[s]).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::InvalidUtf8);
+        assert_eq!(
+            vm.string(i64 as u16, "").unwrap_err().kind,
+            ErrorKind::TypeMismatch
+        );
+    }
+}
@@ -0,0 +1,499 @@
+//! Sum types.
+//!
+//! Bytecode refers to a value of a sum type by the address of a block holding
+//! its tag, the index of the variant it holds, followed by the payload of the
+//! variant. The tag is an integer of the tag type at offset 0, the smallest
+//! unsigned integer the indices fit in if the sum names none. Payloads are
+//! stored like elements of sequences, scalars at their size and anything else
+//! as a pointer to it, and all of them start at the first offset past the tag
+//! aligned for every payload. `Untagged` sums have no tag, so their payloads
+//! start at offset 0 and which variant a value holds is up to the program.
+//! The block is as large as the largest payload needs, rounded up to the
+//! largest alignment of the tag and the payloads.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    Error, ErrorKind, SumSafety, TypeId, TypeInfo, TypeKind, Variant, VirtualMachine, VirtualPtr,
+    seq::{Layout, storage},
+};
+
+pub(crate) struct SumLayout {
+    /// How the tag is stored, if there is one.
+    pub tag: Option<Layout>,
+    /// Offset of the payload.
+    pub payload: u64,
+    pub size: u64,
+    pub alignment: u64,
+}
+
+impl TypeInfo {
+    /// A sum type of `variants` laid out as described in [`sum`](self), with
+    /// the tag and payload types found in `types`, the type table of the
+    /// machine it is for. The tag type must be an integer holding every
+    /// variant index, and `Untagged` sums cannot have one.
+    pub fn sum(
+        internal_id: TypeId,
+        tag_type: Option<TypeId>,
+        variants: Vec<Variant>,
+        safety: SumSafety,
+        types: &[TypeInfo],
+    ) -> Result<Self, Error> {
+        let layout = sum_layout(types, tag_type, &variants, safety)?;
+        let kind = TypeKind::Sum {
+            tag_type,
+            variants,
+            safety,
+        };
+        Ok(Self::new(internal_id, kind, layout.size, layout.alignment))
+    }
+}
+
+pub(crate) fn sum_layout(
+    types: &[TypeInfo],
+    tag_type: Option<TypeId>,
+    variants: &[Variant],
+    safety: SumSafety,
+) -> Result<SumLayout, ErrorKind> {
+    let find = |id: &TypeId| {
+        types
+            .iter()
+            .find(|info| info.internal_id == *id)
+            .ok_or(ErrorKind::TypeMismatch)
+    };
+    let last = variants.len().saturating_sub(1) as u64;
+    let tag = match (safety, tag_type) {
+        (SumSafety::Untagged, None) => None,
+        (SumSafety::Untagged, Some(_)) => return Err(ErrorKind::TypeMismatch),
+        (_, Some(id)) => {
+            let layout = storage(find(&id)?)?;
+            match layout.integer {
+                Some((width, signed)) if fits(last, width, signed) => Some(layout),
+                _ => return Err(ErrorKind::TypeMismatch),
+            }
+        }
+        (_, None) => {
+            let size = match last {
+                0..=0xff => 1,
+                0x100..=0xffff => 2,
+                _ => 4,
+            };
+            Some(Layout {
+                size,
+                alignment: size,
+                integer: Some((size as u8 * 8, false)),
+            })
+        }
+    };
+
+    let (mut size, mut alignment) = (0, 1);
+    for payload in variants
+        .iter()
+        .filter_map(|variant| variant.payload_type.as_ref())
+    {
+        let layout = storage(find(payload)?)?;
+        size = size.max(layout.size);
+        alignment = alignment.max(layout.alignment);
+    }
+    let payload = tag
+        .as_ref()
+        .map_or(0, |tag| tag.size.next_multiple_of(alignment));
+    let alignment = alignment.max(tag.as_ref().map_or(1, |tag| tag.alignment));
+    Ok(SumLayout {
+        tag,
+        payload,
+        size: (payload + size).next_multiple_of(alignment),
+        alignment,
+    })
+}
+
+/// Whether `value` fits in an integer of `width` bits.
+fn fits(value: u64, width: u8, signed: bool) -> bool {
+    if !(1..=64).contains(&width) {
+        return false;
+    }
+    let bits = width as u32 - signed as u32;
+    u64::MAX.checked_shr(64 - bits).unwrap_or(0) >= value
+}
+
+impl VirtualMachine {
+    /// A new value of the sum type at index `sum` holding `variant`.
+    pub(crate) fn sum_new(
+        &mut self,
+        sum: u64,
+        variant: u64,
+        payload: u64,
+    ) -> Result<VirtualPtr, Error> {
+        let (variants, _, layout) = self.sum_type(sum)?;
+        let payload_type = variants
+            .get(variant as usize)
+            .ok_or(ErrorKind::IndexOutOfBounds)?
+            .payload_type;
+        let payload_layout = payload_type
+            .map(|id| self.payload_layout(&id))
+            .transpose()?;
+
+        let value = self.alloc(layout.size, layout.alignment)?;
+        // Padding and the bytes past smaller payloads read as zero
+        self.memory.write(value, &vec![0; layout.size as usize])?;
+        if let Some(tag) = &layout.tag {
+            self.write_stored(value, tag, variant)?;
+        }
+        if let Some(payload_layout) = &payload_layout {
+            let addr = VirtualPtr(value.0 + layout.payload);
+            self.write_stored(addr, payload_layout, payload)?;
+        }
+        Ok(value)
+    }
+
+    /// The index of the variant the sum at `value` holds.
+    pub(crate) fn sum_tag(&self, sum: u64, value: VirtualPtr) -> Result<u64, Error> {
+        let (variants, _, layout) = self.sum_type(sum)?;
+        let tag = layout.tag.as_ref().ok_or(ErrorKind::TypeMismatch)?;
+        let found = self.read_stored(value, tag)?;
+        match found < variants.len() as u64 {
+            true => Ok(found),
+            false => Err(ErrorKind::Corrupted.into()),
+        }
+    }
+
+    /// The payload of `variant` from the sum at `value`, after checking that
+    /// it holds that variant if `checked`.
+    pub(crate) fn sum_payload(
+        &self,
+        sum: u64,
+        value: VirtualPtr,
+        variant: u64,
+        checked: bool,
+    ) -> Result<u64, Error> {
+        let (variants, safety, layout) = self.sum_type(sum)?;
+        let payload_type = variants
+            .get(variant as usize)
+            .ok_or(ErrorKind::IndexOutOfBounds)?
+            .payload_type;
+        match (safety, checked) {
+            (SumSafety::Safe | SumSafety::Tagged, true) => {
+                let found = self.sum_tag(sum, value)?;
+                if found != variant {
+                    return Err(ErrorKind::WrongVariant {
+                        expected: variant,
+                        found,
+                    }
+                    .into());
+                }
+            }
+            // Untagged sums cannot be checked, and safe ones must be
+            (SumSafety::Untagged, true) | (SumSafety::Safe, false) => {
+                return Err(ErrorKind::TypeMismatch.into());
+            }
+            (SumSafety::Tagged | SumSafety::Untagged, false) => {}
+        }
+        let payload_type = payload_type.ok_or(ErrorKind::TypeMismatch)?;
+        let payload_layout = self.payload_layout(&payload_type)?;
+        self.read_stored(VirtualPtr(value.0 + layout.payload), &payload_layout)
+    }
+
+    fn sum_type(&self, sum: u64) -> Result<(&[Variant], SumSafety, SumLayout), ErrorKind> {
+        let info = self
+            .types
+            .get(sum as usize)
+            .ok_or(ErrorKind::TypeMismatch)?;
+        let TypeKind::Sum {
+            tag_type,
+            variants,
+            safety,
+        } = &info.kind
+        else {
+            return Err(ErrorKind::TypeMismatch);
+        };
+        let layout = sum_layout(&self.types, *tag_type, variants, *safety)?;
+        Ok((variants, *safety, layout))
+    }
+
+    fn payload_layout(&self, id: &TypeId) -> Result<Layout, ErrorKind> {
+        storage(
+            self.types
+                .iter()
+                .find(|info| info.internal_id == *id)
+                .ok_or(ErrorKind::TypeMismatch)?,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::{String, ToString};
+
+    use super::*;
+    use crate::{
+        Module, ModuleFunction,
+        op::Op,
+        vm::tests::{Assembler, F64, I8, I64, PAIR, U8, sig, vm},
+    };
+
+    const I32: TypeId = TypeId(7);
+    const OPTION: TypeId = TypeId(10);
+    const RESULT: TypeId = TypeId(11);
+    const UNION: TypeId = TypeId(12);
+
+    fn variant(name: &str, payload_type: Option<TypeId>) -> Variant {
+        Variant {
+            name: String::from(name),
+            payload_type,
+        }
+    }
+
+    /// The test machine with `I32`, `Option<i64>`, `Result<i32, u8>` and an
+    /// untagged union of `i64` and `f64`, returning the indices of the sums.
+    fn sums() -> (VirtualMachine, [u64; 3]) {
+        let (mut vm, _) = vm();
+        let integer = TypeKind::Integer {
+            width: 32,
+            signed: true,
+        };
+        vm.register_type(TypeInfo::new(I32, integer, 4, 4));
+        let option = [variant("None", None), variant("Some", Some(I64))];
+        let result = [variant("Ok", Some(I32)), variant("Err", Some(U8))];
+        let union = [variant("Int", Some(I64)), variant("Float", Some(F64))];
+        let sums = [
+            (OPTION, None, option, SumSafety::Safe),
+            (RESULT, Some(U8), result, SumSafety::Tagged),
+            (UNION, None, union, SumSafety::Untagged),
+        ]
+        .map(|(id, tag_type, variants, safety)| {
+            let info = TypeInfo::sum(id, tag_type, variants.to_vec(), safety, &vm.types);
+            vm.register_type(info.unwrap()) as u64
+        });
+        (vm, sums)
+    }
+
+    /// Runs `code` as a function of no arguments returning an `i64`.
+    fn eval(vm: &mut VirtualMachine, asm: &mut Assembler) -> Result<u64, Error> {
+        asm.op(Op::Ret, 0);
+        let main = vm.load(&asm.finish(), sig(&[], Some(I64)), 0)?;
+        let result = vm.run(main, &[])?;
+        vm.read_int(&result)
+    }
+
+    #[test]
+    fn test_layouts() {
+        let (vm, [option, result, union]) = sums();
+        let info = |index: u64| &vm.types[index as usize];
+        // The tag takes a byte, the payload starts aligned after it
+        assert_eq!((info(option).size(), info(option).alignment()), (16, 8));
+        assert_eq!((info(result).size(), info(result).alignment()), (8, 4));
+        assert_eq!((info(union).size(), info(union).alignment()), (8, 8));
+        let layout = |index: u64| match &info(index).kind {
+            TypeKind::Sum {
+                tag_type,
+                variants,
+                safety,
+            } => sum_layout(&vm.types, *tag_type, variants, *safety).unwrap(),
+            _ => unreachable!(),
+        };
+        assert_eq!(layout(option).payload, 8);
+        assert_eq!(layout(result).payload, 4);
+        assert_eq!(layout(union).payload, 0);
+        assert!(layout(union).tag.is_none());
+
+        let sum = |tag_type, variants: &[Variant], safety| {
+            TypeInfo::sum(TypeId(20), tag_type, variants.to_vec(), safety, &vm.types)
+                .map(|info| (info.size(), info.alignment()))
+        };
+        // Structs are held by pointer, small payloads pack after the tag
+        let boxed = [variant("None", None), variant("Some", Some(PAIR))];
+        assert_eq!(sum(None, &boxed, SumSafety::Safe), Ok((16, 8)));
+        let small = [variant("A", None), variant("B", Some(I8))];
+        assert_eq!(sum(None, &small, SumSafety::Safe), Ok((2, 1)));
+        assert_eq!(sum(None, &[], SumSafety::Untagged), Ok((0, 1)));
+
+        // Too many variants for the tag type, and tags that are not integers
+        let many: Vec<_> = (0..300).map(|i| variant(&i.to_string(), None)).collect();
+        assert_eq!(sum(None, &many, SumSafety::Tagged), Ok((2, 2)));
+        let mismatch = Err(Error::new(ErrorKind::TypeMismatch));
+        assert_eq!(sum(Some(U8), &many, SumSafety::Tagged), mismatch);
+        assert_eq!(sum(Some(I8), &many[..128], SumSafety::Safe), Ok((1, 1)));
+        assert_eq!(sum(Some(I8), &many[..129], SumSafety::Safe), mismatch);
+        assert_eq!(sum(Some(F64), &small, SumSafety::Safe), mismatch);
+        assert_eq!(sum(Some(U8), &small, SumSafety::Untagged), mismatch);
+        let unknown = [variant("A", Some(TypeId(99)))];
+        assert_eq!(sum(None, &unknown, SumSafety::Safe), mismatch);
+    }
+
+    #[test]
+    fn test_option() {
+        let (mut vm, [option, ..]) = sums();
+        let i64 = vm.types.iter().position(|info| info.id() == I64).unwrap() as u64;
+        let unwrap_or = vm.function_table.len() as u64;
+        let mut asm = Assembler::default();
+        let none = asm.label();
+        asm.op(Op::Load, 0).op(Op::SumTag, option);
+        asm.jump(Op::BranchIfNot, none);
+        asm.op(Op::Load, 0)
+            .op(Op::Const, 1)
+            .op(Op::SumPayload, option);
+        asm.op(Op::Ret, 0);
+        asm.bind(none).op(Op::Load, 1).op(Op::Ret, 0);
+        vm.load(&asm.finish(), sig(&[OPTION, I64], Some(I64)), 2)
+            .unwrap();
+
+        // Some(n) if n > 0, else None, unwrapped with 7 as the default
+        let mut asm = Assembler::default();
+        let (none, call) = (asm.label(), asm.label());
+        asm.op(Op::Load, 0).op(Op::Const, 0).op(Op::Gt, i64);
+        asm.jump(Op::BranchIfNot, none);
+        asm.op(Op::Const, 1).op(Op::Load, 0).op(Op::SumNew, option);
+        asm.op(Op::Store, 1).jump(Op::Jump, call);
+        asm.bind(none);
+        asm.op(Op::Const, 0).op(Op::Const, 0).op(Op::SumNew, option);
+        asm.op(Op::Store, 1);
+        asm.bind(call);
+        asm.op(Op::Load, 1).op(Op::Const, 7).op(Op::Call, unwrap_or);
+        asm.op(Op::Ret, 0);
+        let main = vm.load(&asm.finish(), sig(&[I64], Some(I64)), 2).unwrap();
+        for (n, expected) in [(5, 5), (-3i64 as u64, 7)] {
+            let n = vm.int(n).unwrap();
+            let result = vm.run(main, &[n]).unwrap();
+            assert_eq!(vm.read_int(&result), Ok(expected));
+        }
+
+        // Reading Some out of None
+        let mut asm = Assembler::default();
+        asm.op(Op::Const, 0).op(Op::Const, 0).op(Op::SumNew, option);
+        asm.op(Op::Const, 1).op(Op::SumPayload, option);
+        let error = eval(&mut vm, &mut asm).unwrap_err();
+        assert_eq!(
+            error.kind,
+            ErrorKind::WrongVariant {
+                expected: 1,
+                found: 0
+            }
+        );
+        assert_eq!(error.at.map(|(_, offset)| offset), Some(30));
+        assert!(
+            error
+                .to_string()
+                .starts_with("wrong variant 0 instead of 1 at")
+        );
+        // Safe sums have no unchecked reads, nor variants past the last
+        asm.op(Op::Const, 1).op(Op::Const, 5).op(Op::SumNew, option);
+        asm.op(Op::Const, 1).op(Op::SumPayloadUnchecked, option);
+        let error = eval(&mut vm, &mut asm).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TypeMismatch);
+        asm.op(Op::Const, 2).op(Op::Const, 5).op(Op::SumNew, option);
+        let error = eval(&mut vm, &mut asm).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::IndexOutOfBounds);
+    }
+
+    #[test]
+    fn test_result() {
+        let (mut vm, [_, result, _]) = sums();
+        let mut asm = Assembler::default();
+        let err = |asm: &mut Assembler| {
+            asm.op(Op::Const, 1)
+                .op(Op::Const, 200)
+                .op(Op::SumNew, result);
+        };
+
+        err(&mut asm);
+        asm.op(Op::SumTag, result);
+        assert_eq!(eval(&mut vm, &mut asm), Ok(1));
+        err(&mut asm);
+        asm.op(Op::Const, 1).op(Op::SumPayload, result);
+        assert_eq!(eval(&mut vm, &mut asm), Ok(200));
+        err(&mut asm);
+        asm.op(Op::Const, 0).op(Op::SumPayload, result);
+        assert_eq!(
+            eval(&mut vm, &mut asm).unwrap_err().kind,
+            ErrorKind::WrongVariant {
+                expected: 0,
+                found: 1
+            }
+        );
+        // Read as Ok it is the byte of Err and the zeroed bytes after it
+        err(&mut asm);
+        asm.op(Op::Const, 0).op(Op::SumPayloadUnchecked, result);
+        assert_eq!(eval(&mut vm, &mut asm), Ok(200));
+
+        // Payloads narrower than a slot are extended again
+        asm.op(Op::Const, 0)
+            .op(Op::Const, -5i64 as u64)
+            .op(Op::SumNew, result);
+        asm.op(Op::Const, 0).op(Op::SumPayload, result);
+        assert_eq!(eval(&mut vm, &mut asm), Ok(-5i64 as u64));
+    }
+
+    #[test]
+    fn test_untagged() {
+        let (mut vm, [.., union]) = sums();
+        let mut asm = Assembler::default();
+        let float = |asm: &mut Assembler| {
+            asm.op(Op::Const, 1)
+                .op(Op::Const, 1.5f64.to_bits())
+                .op(Op::SumNew, union);
+        };
+
+        float(&mut asm);
+        asm.op(Op::Const, 0).op(Op::SumPayloadUnchecked, union);
+        assert_eq!(eval(&mut vm, &mut asm), Ok(1.5f64.to_bits()));
+        // There is no tag to read or check
+        float(&mut asm);
+        asm.op(Op::SumTag, union);
+        assert_eq!(
+            eval(&mut vm, &mut asm).unwrap_err().kind,
+            ErrorKind::TypeMismatch
+        );
+        float(&mut asm);
+        asm.op(Op::Const, 1).op(Op::SumPayload, union);
+        assert_eq!(
+            eval(&mut vm, &mut asm).unwrap_err().kind,
+            ErrorKind::TypeMismatch
+        );
+    }
+
+    #[test]
+    fn test_verify_rejects() {
+        let (mut vm, _) = vm();
+        let option = TypeInfo::sum(
+            OPTION,
+            None,
+            vec![variant("None", None), variant("Some", Some(I64))],
+            SumSafety::Safe,
+            &vm.types,
+        )
+        .unwrap();
+        let mut asm = Assembler::default();
+        asm.op(Op::Const, 0).op(Op::Const, 0).op(Op::SumNew, 0);
+        asm.op(Op::Const, 1)
+            .op(Op::SumPayloadUnchecked, 0)
+            .op(Op::Ret, 0);
+        let code = asm.finish();
+        let len = code.len() as u32;
+        let module = Module::new(code)
+            .with_types(vec![option])
+            .with_functions(vec![ModuleFunction::Bytecode {
+                sig: sig(&[], Some(I64)),
+                locals: 0,
+                code: 0..len,
+            }]);
+        let error = vm.load_module(&module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TypeMismatch);
+        assert_eq!(error.at, Some((0, 30)));
+
+        // A payload type the machine does not have
+        let mut bare = VirtualMachine::new(1 << 12, 1 << 10);
+        let module = module.with_types(vec![TypeInfo::new(
+            OPTION,
+            TypeKind::Sum {
+                tag_type: None,
+                variants: vec![variant("Some", Some(I64))],
+                safety: SumSafety::Safe,
+            },
+            16,
+            8,
+        )]);
+        let error = bare.load_module(&module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TypeMismatch);
+        assert!(bare.types.is_empty());
+    }
+}
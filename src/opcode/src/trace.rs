@@ -0,0 +1,266 @@
+//! Execution tracing.
+//!
+//! A tracer set with [`VirtualMachine::set_tracer`] is handed a
+//! [`TraceEvent`] before every instruction, for every call and return, and
+//! for every heap allocation. Without one, the machine checks for it once per
+//! instruction and builds no events.
+
+use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use core::{
+    cell::RefCell,
+    fmt::{self, Write},
+};
+
+use crate::{
+    Error, FuncId, Value, VirtualMachine, VirtualPtr, disasm::Instruction, op::Op, vm::SLOT,
+};
+
+pub type Tracer = Box<dyn FnMut(TraceEvent)>;
+
+/// What the machine did. Depths count frames of the call stack, the callee's
+/// included for calls and returns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// An instruction about to execute.
+    Step {
+        depth: usize,
+        func: FuncId,
+        pc: u32,
+        op: Op,
+        operand: u64,
+    },
+    /// A call of a bytecode or native function, with the bits of its
+    /// arguments.
+    Call {
+        depth: usize,
+        func: FuncId,
+        args: Vec<u64>,
+    },
+    /// A return, with the bits of the value if the function returns one.
+    Return {
+        depth: usize,
+        func: FuncId,
+        value: Option<u64>,
+    },
+    Alloc {
+        addr: VirtualPtr,
+        size: u64,
+    },
+    Free {
+        addr: VirtualPtr,
+        size: u64,
+    },
+}
+
+impl fmt::Display for TraceEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Step {
+                func,
+                pc,
+                op,
+                operand,
+                ..
+            } => write!(f, "{}:{pc} {}", func.0, Instruction(*op, *operand)),
+            Self::Call { func, args, .. } => {
+                write!(f, "call {}(", func.0)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{arg}")?;
+                }
+                f.write_char(')')
+            }
+            Self::Return {
+                func,
+                value: Some(value),
+                ..
+            } => write!(f, "ret {} = {value}", func.0),
+            Self::Return { func, .. } => write!(f, "ret {}", func.0),
+            Self::Alloc { addr, size } => write!(f, "alloc {size} at {:#x}", addr.0),
+            Self::Free { addr, size } => write!(f, "free {size} at {:#x}", addr.0),
+        }
+    }
+}
+
+impl VirtualMachine {
+    /// Hands every event from now on to `tracer`, replacing the tracer set
+    /// before.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    /// Stops tracing, returning the tracer.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Hands the event `event` builds to the tracer, if there is one.
+    #[inline]
+    pub(crate) fn trace(&mut self, event: impl FnOnce(&Self) -> TraceEvent) {
+        if let Some(mut tracer) = self.tracer.take() {
+            tracer(event(self));
+            self.tracer = Some(tracer);
+        }
+    }
+
+    /// The bits of the `count` stack slots from `addr`.
+    pub(crate) fn read_slots(&self, addr: VirtualPtr, count: usize) -> Vec<u64> {
+        (0..count as u64)
+            .map_while(|index| self.memory.read_u64(VirtualPtr(addr.0 + index * SLOT)).ok())
+            .collect()
+    }
+}
+
+/// Runs `entry` with `args` as [`VirtualMachine::run`] does, returning the
+/// result with the trace of the run, one event per line indented by depth.
+pub fn trace_to_string(
+    vm: &mut VirtualMachine,
+    entry: FuncId,
+    args: &[Value],
+) -> (Result<Value, Error>, String) {
+    let out = Rc::new(RefCell::new(String::new()));
+    let mut depth = vm.call_stack.len();
+    let sink = out.clone();
+    let previous = vm.take_tracer();
+    vm.set_tracer(Box::new(move |event| {
+        // Calls and returns, and what natives allocate, line up with the
+        // instructions of the caller
+        let indent = match event {
+            TraceEvent::Step { depth: step, .. } => {
+                depth = step;
+                step
+            }
+            TraceEvent::Call { depth: call, .. } | TraceEvent::Return { depth: call, .. } => {
+                depth = call - 1;
+                depth
+            }
+            _ => depth,
+        };
+        let mut out = sink.borrow_mut();
+        writeln!(out, "{:indent$}{event}", "", indent = indent * 2).unwrap();
+    }));
+
+    let result = vm.run(entry, args);
+    vm.tracer = previous;
+    let out = out.take();
+    (result, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{
+        ErrorKind,
+        vm::tests::{Assembler, I64, sig, vm},
+    };
+
+    fn double(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Error> {
+        let x = vm.read_int(&args[0])?;
+        vm.int(x * 2)
+    }
+
+    #[test]
+    fn test_trace() {
+        let (mut vm, [i64, .., pair]) = vm();
+        vm.register_builtin("double", sig(&[I64], Some(I64)), double);
+        let double = vm.load_builtin("double").unwrap();
+        let mut asm = Assembler::default();
+        asm.op(Op::Load, 0).op(Op::Dup, 0).op(Op::Construct, pair);
+        asm.op(Op::Destructure, pair).op(Op::Add, i64);
+        asm.op(Op::Call, double.0 as u64).op(Op::Ret, 0);
+        let main = vm.load(&asm.finish(), sig(&[I64], Some(I64)), 1).unwrap();
+
+        let x = vm.int(5).unwrap();
+        let (result, trace) = trace_to_string(&mut vm, main, &[x]);
+        assert_eq!(vm.read_int(&result.unwrap()), Ok(20));
+        // The struct, the cell `double` returns and the one `run` does
+        let expected = "\
+call 1(5)
+  1:0 load 0
+  1:3 dup
+  1:4 construct 4
+  alloc 16 at 0x1020
+  1:7 destructure 4
+  1:10 add 0
+  1:13 call 0
+  call 0(10)
+  alloc 8 at 0x1030
+  ret 0 = 20
+  1:18 ret
+ret 1 = 20
+alloc 8 at 0x1038
+";
+        assert_eq!(trace, expected);
+        assert!(vm.tracer.is_none());
+    }
+
+    #[test]
+    fn test_fibonacci_depths() {
+        let (mut vm, [i64, ..]) = vm();
+        let fib = vm.function_table.len() as u64;
+        let mut asm = Assembler::default();
+        let recurse = asm.label();
+        asm.op(Op::Load, 0).op(Op::Const, 2).op(Op::Lt, i64);
+        asm.jump(Op::BranchIfNot, recurse);
+        asm.op(Op::Load, 0).op(Op::Ret, 0);
+        asm.bind(recurse);
+        asm.op(Op::Load, 0)
+            .op(Op::Const, 1)
+            .op(Op::Sub, i64)
+            .op(Op::Call, fib);
+        asm.op(Op::Load, 0)
+            .op(Op::Const, 2)
+            .op(Op::Sub, i64)
+            .op(Op::Call, fib);
+        asm.op(Op::Add, i64).op(Op::Ret, 0);
+        let fib = vm.load(&asm.finish(), sig(&[I64], Some(I64)), 1).unwrap();
+
+        let n = vm.int(5).unwrap();
+        let (result, trace) = trace_to_string(&mut vm, fib, &[n]);
+        assert_eq!(vm.read_int(&result.unwrap()), Ok(5));
+
+        // Calls of fib(5) by depth, from the indentation of the trace
+        let mut calls = vec![0; 5];
+        let mut returns = vec![0; 5];
+        for line in trace.lines() {
+            let event = line.trim_start();
+            let depth = (line.len() - event.len()) / 2;
+            if event.starts_with("call ") {
+                calls[depth] += 1;
+            } else if event.starts_with("ret 0 =") {
+                returns[depth] += 1;
+            } else if !event.starts_with("alloc") {
+                // Instructions are one deeper than the call starting them
+                assert!((1..=5).contains(&depth), "{line}");
+            }
+        }
+        assert_eq!(calls, [1, 2, 4, 6, 2]);
+        assert_eq!(returns, calls);
+        assert!(trace.starts_with("call 0(5)\n  0:0 load 0\n"));
+        assert!(trace.ends_with("  0:67 ret\nret 0 = 5\nalloc 8 at 0x1050\n"));
+    }
+
+    #[test]
+    fn test_tracer_replaced() {
+        let (mut vm, _) = vm();
+        let mut asm = Assembler::default();
+        asm.op(Op::Pop, 0);
+        let main = vm.load(&asm.finish(), sig(&[], None), 0).unwrap();
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        vm.set_tracer(Box::new(move |event| sink.borrow_mut().push(event)));
+        let (result, trace) = trace_to_string(&mut vm, main, &[]);
+        assert_eq!(result.unwrap_err().kind, ErrorKind::Corrupted);
+        assert_eq!(trace, "call 0()\n  0:0 pop\n");
+        // The tracer set before is back, and saw nothing of the run
+        assert!(events.borrow().is_empty());
+        vm.run(main, &[]).unwrap_err();
+        assert_eq!(events.borrow().len(), 2);
+        assert!(vm.take_tracer().is_some());
+    }
+}
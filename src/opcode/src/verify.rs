@@ -0,0 +1,533 @@
+//! Verification and loading of modules.
+//!
+//! Every function of a module is checked before any of it is loaded, for what
+//! the interpreter would otherwise only find while running it: instructions
+//! must decode, jumps must land on instructions, operands must name locals,
+//! types, functions and constants that exist, and every path through the code
+//! must reach an instruction with the same operands on the stack, never pop
+//! more than it pushed and return with nothing but its value left. Indices of
+//! the module are then relocated into the tables of the VM.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    Error, ErrorKind, FuncId, FuncSig, Module, ModuleFunction, SumSafety, TypeKind, VirtualMachine,
+    op::{self, Op, Operand},
+    sum::sum_layout,
+};
+
+struct Instruction {
+    offset: u32,
+    op: Op,
+    operand: u64,
+    next: u32,
+}
+
+impl VirtualMachine {
+    /// Loads the functions of `module` after verifying all of them, returning
+    /// their ids in order. Types already registered under the same id are
+    /// shared, and must then be the same. Nothing is loaded on an error.
+    pub fn load_module(&mut self, module: &Module) -> Result<Vec<FuncId>, Error> {
+        if !module.verify() {
+            return Err(Error::new(ErrorKind::Corrupted));
+        }
+        let base = self.function_table.len() as u32;
+        let mut verified = Vec::with_capacity(module.functions.len());
+        for (index, function) in module.functions.iter().enumerate() {
+            let func = base.saturating_add(index as u32);
+            verified.push(match function {
+                ModuleFunction::Bytecode { sig, locals, code } => {
+                    let code = module
+                        .code
+                        .get(code.start as usize..code.end as usize)
+                        .ok_or(Error::at(ErrorKind::Corrupted, func, 0))?;
+                    if (*locals as usize) < sig.params.len() {
+                        return Err(Error::at(ErrorKind::TypeMismatch, func, 0));
+                    }
+                    verify(module, sig, *locals, code, func)?
+                }
+                ModuleFunction::Builtin { name, sig } => {
+                    let builtin = self
+                        .builtins
+                        .iter()
+                        .rev()
+                        .find(|builtin| builtin.name == name);
+                    match builtin {
+                        Some(builtin) if builtin.sig == *sig => Vec::new(),
+                        Some(_) => return Err(Error::new(ErrorKind::TypeMismatch)),
+                        None => return Err(Error::new(ErrorKind::Unresolved)),
+                    }
+                }
+            });
+        }
+
+        let lens = (
+            self.types.len(),
+            self.constants.len(),
+            self.function_table.len(),
+        );
+        let result = self.install(module, &verified);
+        if result.is_err() {
+            self.types.truncate(lens.0);
+            self.constants.truncate(lens.1);
+            self.function_table.truncate(lens.2);
+        }
+        result
+    }
+
+    fn install(
+        &mut self,
+        module: &Module,
+        verified: &[Vec<Instruction>],
+    ) -> Result<Vec<FuncId>, Error> {
+        let mut types = Vec::with_capacity(module.types.len());
+        for info in &module.types {
+            let known = self
+                .types
+                .iter()
+                .position(|known| known.internal_id == info.internal_id);
+            let index = match known {
+                Some(index) if self.types[index] == *info => index,
+                Some(_) => return Err(Error::new(ErrorKind::TypeMismatch)),
+                None => {
+                    self.types.push(info.clone());
+                    self.types.len() - 1
+                }
+            };
+            types.push(u16::try_from(index).map_err(|_| Error::new(ErrorKind::OutOfMemory))?);
+        }
+        for sig in module.functions.iter().map(ModuleFunction::sig) {
+            for id in sig.params.iter().chain(&sig.ret) {
+                self.type_of(id)?;
+            }
+        }
+        // Sums refer to their tag and payload types by id as well
+        for info in &module.types {
+            if let TypeKind::Sum {
+                tag_type,
+                variants,
+                safety,
+            } = &info.kind
+            {
+                sum_layout(&self.types, *tag_type, variants, *safety)?;
+            }
+        }
+        let constants = self.constants.len() as u64;
+        self.constants.extend_from_slice(&module.constants);
+
+        let base = self.function_table.len() as u64;
+        let mut ids = Vec::with_capacity(module.functions.len());
+        for (function, instructions) in module.functions.iter().zip(verified) {
+            ids.push(match function {
+                ModuleFunction::Bytecode { sig, locals, code } => {
+                    let mut code = module.code[code.start as usize..code.end as usize].to_vec();
+                    for instruction in instructions {
+                        let operand = match instruction.op.operand() {
+                            Operand::Type => types[instruction.operand as usize] as u64,
+                            Operand::Func => base + instruction.operand,
+                            Operand::Constant => constants + instruction.operand,
+                            _ => continue,
+                        };
+                        let len = instruction.op.operand_len();
+                        if operand >> (len * 8) != 0 {
+                            return Err(Error::new(ErrorKind::OutOfMemory));
+                        }
+                        let at = instruction.offset as usize + 1;
+                        code[at..at + len].copy_from_slice(&operand.to_le_bytes()[..len]);
+                    }
+                    self.load(&code, sig.clone(), *locals)?
+                }
+                ModuleFunction::Builtin { name, .. } => self.load_builtin(name)?,
+            });
+        }
+        Ok(ids)
+    }
+}
+
+/// Checks the code of a function of `module` that will be loaded as `func`.
+fn verify(
+    module: &Module,
+    sig: &FuncSig,
+    locals: u16,
+    code: &[u8],
+    func: u32,
+) -> Result<Vec<Instruction>, Error> {
+    let at = |kind, offset| Error::at(kind, func, offset);
+    let mut instructions = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        let (op, operand, next) =
+            op::decode(code, offset).ok_or(at(ErrorKind::Corrupted, offset as u32))?;
+        instructions.push(Instruction {
+            offset: offset as u32,
+            op,
+            operand,
+            next: next as u32,
+        });
+        offset = next;
+    }
+    if instructions.is_empty() {
+        return Err(at(ErrorKind::Corrupted, 0));
+    }
+
+    let index_of = |offset: u64| {
+        instructions
+            .binary_search_by_key(&offset, |instruction| instruction.offset as u64)
+            .ok()
+    };
+    let mut depths = vec![None; instructions.len()];
+    let mut pending = vec![(0, 0u64)];
+    while let Some((index, depth)) = pending.pop() {
+        let instruction = &instructions[index];
+        let corrupted = || at(ErrorKind::Corrupted, instruction.offset);
+        match depths[index] {
+            Some(known) if known == depth => continue,
+            Some(_) => return Err(corrupted()),
+            None => depths[index] = Some(depth),
+        }
+        let (pops, pushes) = effect(module, sig, locals, instruction)
+            .map_err(|kind| at(kind, instruction.offset))?;
+        let depth = depth.checked_sub(pops).ok_or_else(corrupted)? + pushes;
+
+        // Running past the last instruction is corrupted too
+        let next = index_of(instruction.next as u64).ok_or_else(corrupted);
+        let target = index_of(instruction.operand).ok_or_else(corrupted);
+        match instruction.op {
+            Op::Ret if depth != 0 => return Err(corrupted()),
+            Op::Ret => {}
+            Op::Jump => pending.push((target?, depth)),
+            Op::BranchIf | Op::BranchIfNot => {
+                pending.push((target?, depth));
+                pending.push((next?, depth));
+            }
+            _ => pending.push((next?, depth)),
+        }
+    }
+    Ok(instructions)
+}
+
+/// How many operands `instruction` pops and pushes.
+fn effect(
+    module: &Module,
+    sig: &FuncSig,
+    locals: u16,
+    instruction: &Instruction,
+) -> Result<(u64, u64), ErrorKind> {
+    let operand = instruction.operand;
+    let kind = || {
+        module
+            .types
+            .get(operand as usize)
+            .map(|info| &info.kind)
+            .ok_or(ErrorKind::TypeMismatch)
+    };
+    let fields = || match kind()? {
+        TypeKind::Struct { field_count } => Ok(*field_count as u64),
+        _ => Err(ErrorKind::TypeMismatch),
+    };
+    match instruction.op.operand() {
+        Operand::Local if operand >= locals as u64 => return Err(ErrorKind::IndexOutOfBounds),
+        Operand::Constant if operand >= module.constants.len() as u64 => {
+            return Err(ErrorKind::IndexOutOfBounds);
+        }
+        _ => {}
+    }
+
+    Ok(match instruction.op {
+        Op::Const | Op::Load | Op::Move | Op::Borrow | Op::BorrowMut | Op::Constant => (0, 1),
+        Op::Pop | Op::Store | Op::BranchIf | Op::BranchIfNot | Op::Release => (1, 0),
+        Op::Dup => (1, 2),
+        Op::Jump => (0, 0),
+        Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem => match kind()? {
+            TypeKind::Integer { .. } | TypeKind::Float { .. } => (2, 1),
+            _ => return Err(ErrorKind::TypeMismatch),
+        },
+        Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => match kind()? {
+            TypeKind::Integer { .. } | TypeKind::Float { .. } | TypeKind::Pointer => (2, 1),
+            _ => return Err(ErrorKind::TypeMismatch),
+        },
+        Op::Call => {
+            let callee = module
+                .functions
+                .get(operand as usize)
+                .ok_or(ErrorKind::IndexOutOfBounds)?
+                .sig();
+            (callee.params.len() as u64, callee.ret.is_some() as u64)
+        }
+        Op::Ret => (sig.ret.is_some() as u64, 0),
+        Op::Construct => (fields()?, 1),
+        Op::Destructure => (1, fields()?),
+        Op::SeqNew | Op::SeqPush | Op::SeqGet | Op::SeqSet => {
+            match kind()? {
+                TypeKind::Integer { .. }
+                | TypeKind::Float { .. }
+                | TypeKind::Pointer
+                | TypeKind::Struct { .. } => {}
+                _ => return Err(ErrorKind::TypeMismatch),
+            }
+            match instruction.op {
+                Op::SeqNew => (1, 1),
+                Op::SeqPush => (2, 0),
+                Op::SeqGet => (2, 1),
+                _ => (3, 0),
+            }
+        }
+        Op::SeqLen | Op::Deref => (1, 1),
+        Op::Assign => (2, 0),
+        Op::SumNew | Op::SumTag | Op::SumPayload | Op::SumPayloadUnchecked => {
+            let TypeKind::Sum { safety, .. } = kind()? else {
+                return Err(ErrorKind::TypeMismatch);
+            };
+            // What the sum allows is known before it runs
+            match (instruction.op, safety) {
+                (Op::SumNew, _) => return Ok((2, 1)),
+                (Op::SumTag | Op::SumPayload, SumSafety::Untagged)
+                | (Op::SumPayloadUnchecked, SumSafety::Safe) => {
+                    return Err(ErrorKind::TypeMismatch);
+                }
+                (Op::SumTag, _) => (1, 1),
+                _ => (2, 1),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::String, vec};
+
+    use super::*;
+    use crate::{
+        TypeId, TypeInfo, Value,
+        vm::tests::{Assembler, I64, PAIR, sig, vm},
+    };
+
+    fn module(
+        types: Vec<TypeInfo>,
+        functions: &[(FuncSig, u16, Vec<u8>)],
+        constants: Vec<u64>,
+    ) -> Module {
+        let mut code = Vec::new();
+        let mut table = Vec::new();
+        for (sig, locals, body) in functions {
+            let start = code.len() as u32;
+            code.extend_from_slice(body);
+            table.push(ModuleFunction::Bytecode {
+                sig: sig.clone(),
+                locals: *locals,
+                code: start..code.len() as u32,
+            });
+        }
+        Module::new(code)
+            .with_types(types)
+            .with_functions(table)
+            .with_constants(constants)
+    }
+
+    fn int() -> TypeInfo {
+        TypeInfo::new(
+            I64,
+            TypeKind::Integer {
+                width: 64,
+                signed: true,
+            },
+            8,
+            8,
+        )
+    }
+
+    fn double(vm: &mut VirtualMachine, args: &[Value]) -> Result<Value, Error> {
+        let x = vm.read_int(&args[0])?;
+        vm.int(x * 2)
+    }
+
+    #[test]
+    fn test_load_and_run() {
+        let (mut vm, _) = vm();
+        let mut asm = Assembler::default();
+        asm.op(Op::Const, 0).op(Op::Ret, 0);
+        vm.load(&asm.finish(), sig(&[], Some(I64)), 0).unwrap();
+
+        // Types are listed in an order different from the machine's, and the
+        // struct is new to it
+        let point = TypeInfo::new(TypeId(20), TypeKind::Struct { field_count: 2 }, 16, 8);
+        let mut asm = Assembler::default();
+        asm.op(Op::Load, 0)
+            .op(Op::Load, 1)
+            .op(Op::Add, 1)
+            .op(Op::Ret, 0);
+        let sum = asm.finish();
+        // main(x) = 2 * sum(x, 100), through a point
+        asm.op(Op::Load, 0).op(Op::Constant, 0).op(Op::Call, 0);
+        asm.op(Op::Dup, 0)
+            .op(Op::Construct, 0)
+            .op(Op::Destructure, 0);
+        asm.op(Op::Add, 1).op(Op::Ret, 0);
+        let main = asm.finish();
+        let module = module(
+            vec![point, int()],
+            &[
+                (sig(&[I64, I64], Some(I64)), 2, sum),
+                (sig(&[I64], Some(I64)), 1, main),
+            ],
+            vec![100],
+        );
+
+        let types = vm.types.len();
+        for base in [1, 3] {
+            let ids = vm.load_module(&module).unwrap();
+            assert_eq!(ids, [FuncId(base), FuncId(base + 1)]);
+            let x = vm.int(5).unwrap();
+            let result = vm.run(ids[1], &[x]).unwrap();
+            assert_eq!(vm.read_int(&result), Ok(210));
+        }
+        // The second load shared the struct and appended its constants again
+        assert_eq!(vm.types.len(), types + 1);
+        assert_eq!(vm.constants, [100, 100]);
+    }
+
+    #[test]
+    fn test_builtin_imports() {
+        let (mut vm, _) = vm();
+        let mut asm = Assembler::default();
+        asm.op(Op::Load, 0).op(Op::Call, 1).op(Op::Ret, 0);
+        let mut module = module(
+            vec![int()],
+            &[(sig(&[I64], Some(I64)), 1, asm.finish())],
+            Vec::new(),
+        );
+        let mut functions = module.functions().to_vec();
+        functions.push(ModuleFunction::Builtin {
+            name: String::from("double"),
+            sig: sig(&[I64], Some(I64)),
+        });
+        module = module.with_functions(functions);
+
+        let error = vm.load_module(&module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Unresolved);
+        vm.register_builtin("double", sig(&[I64], None), double);
+        let error = vm.load_module(&module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TypeMismatch);
+        assert!(vm.function_table.is_empty());
+
+        vm.register_builtin("double", sig(&[I64], Some(I64)), double);
+        let ids = vm.load_module(&module).unwrap();
+        let x = vm.int(21).unwrap();
+        let result = vm.run(ids[0], &[x]).unwrap();
+        assert_eq!(vm.read_int(&result), Ok(42));
+    }
+
+    #[test]
+    fn test_rejects_conflicting_types() {
+        let (mut vm, _) = vm();
+        let mut asm = Assembler::default();
+        asm.op(Op::Constant, 0).op(Op::Ret, 0);
+        let pair = TypeInfo::new(PAIR, TypeKind::Struct { field_count: 3 }, 24, 8);
+        let module = module(
+            vec![
+                TypeInfo::new(TypeId(20), TypeKind::Pointer, 8, 8),
+                pair,
+                int(),
+            ],
+            &[(sig(&[], Some(I64)), 0, asm.finish())],
+            vec![1],
+        );
+        let types = vm.types.len();
+        let error = vm.load_module(&module).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TypeMismatch);
+        // The pointer type added before the conflict was rolled back
+        assert_eq!(vm.types.len(), types);
+        assert!(vm.constants.is_empty());
+        assert!(vm.function_table.is_empty());
+    }
+
+    #[test]
+    fn test_rejects_unverified() {
+        let unary = sig(&[I64], Some(I64));
+        let nullary = sig(&[], Some(I64));
+        let pair = TypeInfo::new(PAIR, TypeKind::Struct { field_count: 2 }, 16, 8);
+        let mut asm = Assembler::default();
+        let mut cases = Vec::new();
+        let mut case = |asm: &mut Assembler, sig: &FuncSig, kind, offset, types: Vec<TypeInfo>| {
+            cases.push((asm.finish(), sig.clone(), kind, offset, types));
+            *asm = Assembler::default();
+        };
+
+        // Into the middle of an instruction
+        asm.op(Op::Jump, 1).op(Op::Ret, 0);
+        case(&mut asm, &nullary, ErrorKind::Corrupted, 0, vec![int()]);
+        // Off the end of the code
+        asm.op(Op::Const, 1).op(Op::Pop, 0);
+        case(&mut asm, &nullary, ErrorKind::Corrupted, 9, vec![int()]);
+        // Popping an empty stack
+        asm.op(Op::Pop, 0).op(Op::Ret, 0);
+        case(&mut asm, &nullary, ErrorKind::Corrupted, 0, vec![int()]);
+        // Reaching the same instruction with one and with no operands
+        let merge = asm.label();
+        asm.op(Op::Load, 0)
+            .jump(Op::BranchIf, merge)
+            .op(Op::Const, 1);
+        asm.bind(merge).op(Op::Ret, 0);
+        case(&mut asm, &unary, ErrorKind::Corrupted, 17, vec![int()]);
+        // Returning with an operand left over
+        asm.op(Op::Const, 1).op(Op::Const, 2).op(Op::Ret, 0);
+        case(&mut asm, &nullary, ErrorKind::Corrupted, 18, vec![int()]);
+        // A local past those of the function
+        asm.op(Op::Load, 3).op(Op::Ret, 0);
+        case(
+            &mut asm,
+            &unary,
+            ErrorKind::IndexOutOfBounds,
+            0,
+            vec![int()],
+        );
+        // A type the module does not have, and arithmetic on a struct
+        asm.op(Op::Const, 1)
+            .op(Op::Const, 2)
+            .op(Op::Add, 7)
+            .op(Op::Ret, 0);
+        case(&mut asm, &nullary, ErrorKind::TypeMismatch, 18, vec![int()]);
+        asm.op(Op::Const, 1)
+            .op(Op::Const, 2)
+            .op(Op::Add, 0)
+            .op(Op::Ret, 0);
+        case(
+            &mut asm,
+            &nullary,
+            ErrorKind::TypeMismatch,
+            18,
+            vec![pair, int()],
+        );
+        // A constant and a function the module does not have
+        asm.op(Op::Constant, 5).op(Op::Ret, 0);
+        case(
+            &mut asm,
+            &nullary,
+            ErrorKind::IndexOutOfBounds,
+            0,
+            vec![int()],
+        );
+        asm.op(Op::Call, 9).op(Op::Ret, 0);
+        case(
+            &mut asm,
+            &nullary,
+            ErrorKind::IndexOutOfBounds,
+            0,
+            vec![int()],
+        );
+        // No code at all
+        case(&mut asm, &nullary, ErrorKind::Corrupted, 0, vec![int()]);
+
+        let (mut vm, _) = vm();
+        let mut asm = Assembler::default();
+        asm.op(Op::Const, 0).op(Op::Ret, 0);
+        vm.load(&asm.finish(), nullary.clone(), 0).unwrap();
+        for (code, sig, kind, offset, types) in cases {
+            let module = module(types, &[(sig, 1, code)], vec![1]);
+            let error = vm.load_module(&module).unwrap_err();
+            assert_eq!((error.kind, error.at), (kind, Some((1, offset))));
+            assert_eq!(vm.function_table.len(), 1);
+            assert!(vm.constants.is_empty());
+        }
+    }
+}
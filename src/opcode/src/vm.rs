@@ -0,0 +1,907 @@
+//! The interpreter.
+//!
+//! Bytecode runs on a stack of 8 byte slots in [`VirtualMemory`]. A call moves
+//! its arguments into the first locals of the new [`CallFrame`], the other
+//! locals start zeroed, and the operand stack of the call grows above them.
+//! Integers are kept sign or zero extended to the full slot.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    Borrows, CallFrame, Error, ErrorKind, Float, Func, FuncId, FuncKind, FuncSig, Function, Int,
+    Lifetime, Overflow, Ownership, Ref, StackPtr, TypeId, TypeInfo, TypeKind, Value,
+    VirtualMachine, VirtualMemory, VirtualPtr,
+    op::{self, Op},
+    trace::TraceEvent,
+};
+
+pub(crate) const SLOT: u64 = 8;
+/// Calls that may be active at once. Calls without locals take no stack, so
+/// recursing through them overflows here instead.
+const MAX_DEPTH: usize = 1 << 16;
+
+impl VirtualMemory {
+    pub fn new(size: usize) -> Self {
+        Self(vec![0; size])
+    }
+
+    pub fn len(&self) -> u64 {
+        self.0.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn read(&self, ptr: VirtualPtr, len: u64) -> Result<&[u8], Error> {
+        let range = self.range(ptr, len)?;
+        Ok(&self.0[range])
+    }
+
+    pub fn write(&mut self, ptr: VirtualPtr, bytes: &[u8]) -> Result<(), Error> {
+        let range = self.range(ptr, bytes.len() as u64)?;
+        self.0[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn read_u64(&self, ptr: VirtualPtr) -> Result<u64, Error> {
+        let bytes = self.read(ptr, SLOT)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    pub fn write_u64(&mut self, ptr: VirtualPtr, value: u64) -> Result<(), Error> {
+        self.write(ptr, &value.to_le_bytes())
+    }
+
+    fn range(&self, ptr: VirtualPtr, len: u64) -> Result<core::ops::Range<usize>, Error> {
+        match ptr.0.checked_add(len) {
+            Some(end) if end <= self.len() => Ok(ptr.0 as usize..end as usize),
+            _ => Err(Error::new(ErrorKind::IndexOutOfBounds)),
+        }
+    }
+}
+
+impl VirtualMachine {
+    /// A machine with `memory` bytes, the first `stack` of them for the
+    /// stack and the rest for code and the heap.
+    pub fn new(memory: usize, stack: usize) -> Self {
+        assert!(stack <= memory, "the stack does not fit in memory");
+        let stack = VirtualPtr(stack as u64);
+        Self {
+            memory: VirtualMemory::new(memory),
+            heap: stack,
+            free: Vec::new(),
+            stack: StackPtr {
+                base: VirtualPtr(0),
+                active: VirtualPtr(0),
+                end: stack,
+            },
+            call_stack: Vec::new(),
+            function_table: Vec::new(),
+            builtins: Vec::new(),
+            types: Vec::new(),
+            overflow: Overflow::default(),
+            borrows: Borrows::default(),
+            constants: Vec::new(),
+            tracer: None,
+        }
+    }
+
+    /// Adds a type for instructions to refer to by the returned index.
+    pub fn register_type(&mut self, info: TypeInfo) -> u16 {
+        self.types.push(info);
+        (self.types.len() - 1) as u16
+    }
+
+    /// Copies `code` into memory as a function taking and returning what `sig`
+    /// says, with `locals` slots including its arguments. Code that does not
+    /// decode is rejected here rather than when it runs.
+    pub fn load(&mut self, code: &[u8], sig: FuncSig, locals: u16) -> Result<FuncId, Error> {
+        let id = self.function_table.len() as u32;
+        if (locals as usize) < sig.params.len() {
+            return Err(Error::new(ErrorKind::TypeMismatch));
+        }
+        let mut offset = 0;
+        while offset < code.len() {
+            match op::decode(code, offset) {
+                Some((_, _, next)) => offset = next,
+                None => return Err(Error::at(ErrorKind::Corrupted, id, offset as u32)),
+            }
+        }
+
+        let len = u32::try_from(code.len()).map_err(|_| Error::new(ErrorKind::OutOfMemory))?;
+        let addr = self.alloc(code.len() as u64, 1)?;
+        self.memory.write(addr, code)?;
+        self.function_table.push(Function {
+            func: Func {
+                kind: FuncKind::Bytecode { addr },
+                sig,
+            },
+            len,
+            locals,
+        });
+        Ok(FuncId(id))
+    }
+
+    /// An integer on the heap, for passing to [`run`](Self::run).
+    pub fn int(&mut self, value: u64) -> Result<Value, Error> {
+        Ok(Value::Int(Int(self.cell(value)?)))
+    }
+
+    /// A 64 bit float on the heap, for passing to [`run`](Self::run).
+    pub fn float(&mut self, value: f64) -> Result<Value, Error> {
+        Ok(Value::Float(Float(self.cell(value.to_bits())?)))
+    }
+
+    pub fn read_int(&self, value: &Value) -> Result<u64, Error> {
+        match value {
+            Value::Int(Int(ptr)) => self.memory.read_u64(*ptr),
+            _ => Err(Error::new(ErrorKind::TypeMismatch)),
+        }
+    }
+
+    pub fn read_float(&self, value: &Value) -> Result<f64, Error> {
+        match value {
+            Value::Float(Float(ptr)) => Ok(f64::from_bits(self.memory.read_u64(*ptr)?)),
+            _ => Err(Error::new(ErrorKind::TypeMismatch)),
+        }
+    }
+
+    pub(crate) fn cell(&mut self, bits: u64) -> Result<VirtualPtr, Error> {
+        let ptr = self.alloc(SLOT, SLOT)?;
+        self.memory.write_u64(ptr, bits)?;
+        Ok(ptr)
+    }
+
+    /// Calls `entry` with `args` and runs until it returns. On an error the
+    /// calls it made are unwound and the error names the failing instruction.
+    pub fn run(&mut self, entry: FuncId, args: &[Value]) -> Result<Value, Error> {
+        let (depth, active) = (self.call_stack.len(), self.stack.active);
+        let result = self.call_entry(entry, args, depth);
+        if result.is_err() {
+            self.call_stack.truncate(depth);
+            self.borrows.end_frames(depth);
+            self.stack.active = active;
+        }
+        result
+    }
+
+    fn call_entry(&mut self, entry: FuncId, args: &[Value], depth: usize) -> Result<Value, Error> {
+        let function = self.function(entry)?;
+        let params = &function.func.sig.params;
+        if params.len() != args.len() {
+            return Err(Error::new(ErrorKind::TypeMismatch));
+        }
+        let mut bits = Vec::with_capacity(args.len());
+        for (param, arg) in params.iter().zip(args) {
+            let kind = self.type_of(param)?;
+            bits.push(match (kind, arg) {
+                (TypeKind::Integer { .. }, Value::Int(Int(ptr)))
+                | (TypeKind::Float { .. }, Value::Float(Float(ptr))) => {
+                    self.memory.read_u64(*ptr)?
+                }
+                (
+                    TypeKind::Pointer | TypeKind::Struct { .. } | TypeKind::Sum { .. },
+                    Value::Ref(arg),
+                ) => arg.addr.0,
+                (TypeKind::Pointer, Value::Seq(seq)) => seq.header.0,
+                _ => return Err(Error::new(ErrorKind::TypeMismatch)),
+            });
+        }
+        for bits in bits {
+            self.push(bits)?;
+        }
+        let ret = match self.function(entry)?.func.kind {
+            FuncKind::Bytecode { .. } => {
+                self.enter(entry)?;
+                self.execute(depth)?
+            }
+            _ => self.call_native(entry)?.unwrap_or(0),
+        };
+
+        let function = self.function(entry)?;
+        let Some(ret_type) = function.func.sig.ret else {
+            return Ok(Value::Unit);
+        };
+        match self.type_of(&ret_type)? {
+            TypeKind::Integer { .. } => self.int(ret),
+            TypeKind::Float { .. } => Ok(Value::Float(Float(self.cell(ret)?))),
+            _ => Ok(Value::Ref(Ref {
+                addr: VirtualPtr(ret),
+                pointee: ret_type,
+                owner: Ownership::Owned,
+                lifetime: None,
+            })),
+        }
+    }
+
+    pub(crate) fn function(&self, id: FuncId) -> Result<&Function, Error> {
+        self.function_table
+            .get(id.0 as usize)
+            .ok_or(Error::new(ErrorKind::IndexOutOfBounds))
+    }
+
+    pub(crate) fn type_of(&self, id: &TypeId) -> Result<&TypeKind, Error> {
+        self.types
+            .iter()
+            .find(|info| info.internal_id == *id)
+            .map(|info| &info.kind)
+            .ok_or(Error::new(ErrorKind::TypeMismatch))
+    }
+
+    /// Where the `params` arguments of a call start on the stack.
+    pub(crate) fn args(&self, params: usize) -> Result<VirtualPtr, ErrorKind> {
+        let bottom = match self.call_stack.last() {
+            Some(frame) => frame.operands.0,
+            None => self.stack.base.0,
+        };
+        match self.stack.active.0.checked_sub(params as u64 * SLOT) {
+            Some(args) if args >= bottom => Ok(VirtualPtr(args)),
+            _ => Err(ErrorKind::Corrupted),
+        }
+    }
+
+    /// Starts a call of `id`, whose arguments are on top of the stack.
+    fn enter(&mut self, id: FuncId) -> Result<(), ErrorKind> {
+        let function = self.function(id)?;
+        let FuncKind::Bytecode { .. } = function.func.kind else {
+            return Err(ErrorKind::TypeMismatch);
+        };
+        let params = function.func.sig.params.len();
+        let extra = function.locals as usize - params;
+        let locals = self.args(params)?;
+        if self.call_stack.len() == MAX_DEPTH {
+            return Err(ErrorKind::StackOverflow);
+        }
+        for _ in 0..extra {
+            self.push(0)?;
+        }
+        self.call_stack.push(CallFrame {
+            func: id,
+            pc: 0,
+            locals,
+            operands: self.stack.active,
+        });
+        self.trace(|vm| TraceEvent::Call {
+            depth: vm.call_stack.len(),
+            func: id,
+            args: vm.read_slots(locals, params),
+        });
+        Ok(())
+    }
+
+    /// Runs until the call stack is back to `depth` frames, returning what
+    /// the last frame returned.
+    fn execute(&mut self, depth: usize) -> Result<u64, Error> {
+        loop {
+            let frame = *self.call_stack.last().unwrap();
+            let at = |kind| Error::at(kind, frame.func.0, frame.pc);
+            // Errors of builtins are placed at the call
+            let locate = |mut error: Error| {
+                error.at.get_or_insert((frame.func.0, frame.pc));
+                error
+            };
+            let (op, operand, next) = self.fetch(&frame).map_err(at)?;
+            self.trace(|vm| TraceEvent::Step {
+                depth: vm.call_stack.len(),
+                func: frame.func,
+                pc: frame.pc,
+                op,
+                operand,
+            });
+            self.call_stack.last_mut().unwrap().pc = next;
+            match self.step(&frame, op, operand) {
+                Ok(Some(ret)) if self.call_stack.len() == depth => return Ok(ret),
+                Ok(Some(ret)) => {
+                    // Only functions returning a value leave one for the caller
+                    if self.function(frame.func)?.func.sig.ret.is_some() {
+                        self.push(ret).map_err(at)?;
+                    }
+                }
+                Ok(None) => {}
+                Err(error) => return Err(locate(error)),
+            }
+        }
+    }
+
+    fn fetch(&self, frame: &CallFrame) -> Result<(Op, u64, u32), ErrorKind> {
+        let function = self.function(frame.func)?;
+        let FuncKind::Bytecode { addr } = function.func.kind else {
+            return Err(ErrorKind::TypeMismatch);
+        };
+        let code = self.memory.read(addr, function.len as u64)?;
+        // Running off the end or jumping into the middle of an instruction
+        let (op, operand, next) =
+            op::decode(code, frame.pc as usize).ok_or(ErrorKind::Corrupted)?;
+        Ok((op, operand, next as u32))
+    }
+
+    /// Executes one instruction, returning the value a returning frame
+    /// leaves.
+    fn step(&mut self, frame: &CallFrame, op: Op, operand: u64) -> Result<Option<u64>, Error> {
+        match op {
+            Op::Const => self.push(operand)?,
+            Op::Pop => {
+                self.pop(frame)?;
+            }
+            Op::Dup => {
+                let value = self.pop(frame)?;
+                self.push(value)?;
+                self.push(value)?;
+            }
+            Op::Load => {
+                let local = self.local(frame, operand)?;
+                self.borrows.read(local, SLOT, op)?;
+                let value = self.memory.read_u64(local)?;
+                self.push(value)?;
+            }
+            Op::Store => {
+                let local = self.local(frame, operand)?;
+                let value = self.pop(frame)?;
+                self.borrows.write(local, SLOT, op)?;
+                self.memory.write_u64(local, value)?;
+            }
+            Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Rem => {
+                let (b, a) = (self.pop(frame)?, self.pop(frame)?);
+                let value = match *self.type_at(operand)? {
+                    TypeKind::Integer { width, signed } => {
+                        integer(op, a, b, width, signed, self.overflow)?
+                    }
+                    TypeKind::Float { width } => float(op, a, b, width)?,
+                    _ => return Err(ErrorKind::TypeMismatch.into()),
+                };
+                self.push(value)?;
+            }
+            Op::Eq | Op::Ne | Op::Lt | Op::Le | Op::Gt | Op::Ge => {
+                let (b, a) = (self.pop(frame)?, self.pop(frame)?);
+                let ordering = match *self.type_at(operand)? {
+                    TypeKind::Integer { width, signed } => {
+                        let (a, b) = (extend(a, width, signed)?, extend(b, width, signed)?);
+                        Some(a.cmp(&b))
+                    }
+                    TypeKind::Float { width: 32 } => {
+                        f32::from_bits(a as u32).partial_cmp(&f32::from_bits(b as u32))
+                    }
+                    TypeKind::Float { width: 64 } => {
+                        f64::from_bits(a).partial_cmp(&f64::from_bits(b))
+                    }
+                    TypeKind::Pointer => Some(a.cmp(&b)),
+                    _ => return Err(ErrorKind::TypeMismatch.into()),
+                };
+                // Comparisons with NaN are false, except for `Ne`
+                let result = match ordering {
+                    Some(ordering) => match op {
+                        Op::Eq => ordering.is_eq(),
+                        Op::Ne => ordering.is_ne(),
+                        Op::Lt => ordering.is_lt(),
+                        Op::Le => ordering.is_le(),
+                        Op::Gt => ordering.is_gt(),
+                        _ => ordering.is_ge(),
+                    },
+                    None => op == Op::Ne,
+                };
+                self.push(result as u64)?;
+            }
+            Op::Jump => self.jump(operand),
+            Op::BranchIf | Op::BranchIfNot => {
+                let taken = (self.pop(frame)? != 0) == (op == Op::BranchIf);
+                if taken {
+                    self.jump(operand);
+                }
+            }
+            Op::Call => {
+                let id = FuncId(operand as u32);
+                match self.function(id)?.func.kind {
+                    FuncKind::Bytecode { .. } => self.enter(id)?,
+                    _ => {
+                        if let Some(ret) = self.call_native(id)? {
+                            self.push(ret)?;
+                        }
+                    }
+                }
+            }
+            Op::Ret => {
+                let function = self.function(frame.func)?;
+                let ret = match function.func.sig.ret {
+                    Some(_) => Some(self.pop(frame)?),
+                    None => None,
+                };
+                self.trace(|vm| TraceEvent::Return {
+                    depth: vm.call_stack.len(),
+                    func: frame.func,
+                    value: ret,
+                });
+                self.stack.active = frame.locals;
+                self.call_stack.pop();
+                self.borrows.end_frames(self.call_stack.len());
+                return Ok(Some(ret.unwrap_or(0)));
+            }
+            Op::Construct => {
+                let fields = self.fields(operand)?;
+                let ptr = self.alloc(fields * SLOT, SLOT)?;
+                for field in (0..fields).rev() {
+                    let value = self.pop(frame)?;
+                    self.memory
+                        .write_u64(VirtualPtr(ptr.0 + field * SLOT), value)?;
+                }
+                self.push(ptr.0)?;
+            }
+            Op::Destructure => {
+                let fields = self.fields(operand)?;
+                let ptr = self.pop(frame)?;
+                for field in 0..fields {
+                    let addr = ptr
+                        .checked_add(field * SLOT)
+                        .ok_or(ErrorKind::IndexOutOfBounds)?;
+                    let value = self.memory.read_u64(VirtualPtr(addr))?;
+                    self.push(value)?;
+                }
+            }
+            Op::SeqNew => {
+                let capacity = self.pop(frame)?;
+                let seq = self.seq_new(operand, capacity)?;
+                self.push(seq.0)?;
+            }
+            Op::SeqPush => {
+                let value = self.pop(frame)?;
+                let seq = VirtualPtr(self.pop(frame)?);
+                self.seq_push(seq, operand, value)?;
+            }
+            Op::SeqGet => {
+                let index = self.pop(frame)?;
+                let seq = VirtualPtr(self.pop(frame)?);
+                let value = self.seq_get(seq, operand, index)?;
+                self.push(value)?;
+            }
+            Op::SeqSet => {
+                let value = self.pop(frame)?;
+                let index = self.pop(frame)?;
+                let seq = VirtualPtr(self.pop(frame)?);
+                self.seq_set(seq, operand, index, value)?;
+            }
+            Op::SeqLen => {
+                let seq = VirtualPtr(self.pop(frame)?);
+                let len = self.seq_header(seq)?.len;
+                self.push(len)?;
+            }
+            Op::Move => {
+                let local = self.local(frame, operand)?;
+                let lifetime = Lifetime(self.call_stack.len() as u64);
+                self.borrows.move_out(local, SLOT, lifetime, op)?;
+                let value = self.memory.read_u64(local)?;
+                self.push(value)?;
+            }
+            Op::Borrow | Op::BorrowMut => {
+                let local = self.local(frame, operand)?;
+                let owner = match op {
+                    Op::Borrow => Ownership::Ref,
+                    _ => Ownership::Mut,
+                };
+                let lifetime = Lifetime(self.call_stack.len() as u64);
+                let id = self.borrows.borrow(local, SLOT, owner, lifetime, op)?;
+                self.push(id)?;
+            }
+            Op::Deref => {
+                let id = self.pop(frame)?;
+                let loan = self.borrows.get(id, op)?;
+                let value = self.memory.read_u64(loan.addr)?;
+                self.push(value)?;
+            }
+            Op::Assign => {
+                let value = self.pop(frame)?;
+                let id = self.pop(frame)?;
+                let loan = self.borrows.get(id, op)?;
+                if loan.owner != Ownership::Mut {
+                    return Err(ErrorKind::Aliased {
+                        op,
+                        addr: loan.addr,
+                    }
+                    .into());
+                }
+                self.memory.write_u64(loan.addr, value)?;
+            }
+            Op::Constant => {
+                let value = *self
+                    .constants
+                    .get(operand as usize)
+                    .ok_or(ErrorKind::IndexOutOfBounds)?;
+                self.push(value)?;
+            }
+            Op::Release => {
+                let id = self.pop(frame)?;
+                self.borrows.release(id)?;
+            }
+            Op::SumNew => {
+                let payload = self.pop(frame)?;
+                let variant = self.pop(frame)?;
+                let value = self.sum_new(operand, variant, payload)?;
+                self.push(value.0)?;
+            }
+            Op::SumTag => {
+                let value = VirtualPtr(self.pop(frame)?);
+                let tag = self.sum_tag(operand, value)?;
+                self.push(tag)?;
+            }
+            Op::SumPayload | Op::SumPayloadUnchecked => {
+                let variant = self.pop(frame)?;
+                let value = VirtualPtr(self.pop(frame)?);
+                let checked = op == Op::SumPayload;
+                let payload = self.sum_payload(operand, value, variant, checked)?;
+                self.push(payload)?;
+            }
+        }
+        Ok(None)
+    }
+
+    pub(crate) fn push(&mut self, value: u64) -> Result<(), ErrorKind> {
+        let top = self.stack.active.0;
+        if top + SLOT > self.stack.end.0 {
+            return Err(ErrorKind::StackOverflow);
+        }
+        self.memory.write_u64(VirtualPtr(top), value)?;
+        self.stack.active = VirtualPtr(top + SLOT);
+        Ok(())
+    }
+
+    /// Pops a value pushed by the call of `frame`.
+    fn pop(&mut self, frame: &CallFrame) -> Result<u64, ErrorKind> {
+        if self.stack.active.0 < frame.operands.0 + SLOT {
+            return Err(ErrorKind::Corrupted);
+        }
+        self.stack.active = VirtualPtr(self.stack.active.0 - SLOT);
+        Ok(self.memory.read_u64(self.stack.active)?)
+    }
+
+    fn local(&self, frame: &CallFrame, index: u64) -> Result<VirtualPtr, ErrorKind> {
+        let addr = frame.locals.0 + index * SLOT;
+        match addr < frame.operands.0 {
+            true => Ok(VirtualPtr(addr)),
+            false => Err(ErrorKind::IndexOutOfBounds),
+        }
+    }
+
+    fn jump(&mut self, target: u64) {
+        // Targets past the code fail when the next instruction is fetched
+        self.call_stack.last_mut().unwrap().pc = target as u32;
+    }
+
+    fn type_at(&self, index: u64) -> Result<&TypeKind, ErrorKind> {
+        self.types
+            .get(index as usize)
+            .map(|info| &info.kind)
+            .ok_or(ErrorKind::TypeMismatch)
+    }
+
+    fn fields(&self, index: u64) -> Result<u64, ErrorKind> {
+        match *self.type_at(index)? {
+            TypeKind::Struct { field_count } => Ok(field_count as u64),
+            _ => Err(ErrorKind::TypeMismatch),
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Error::new(kind)
+    }
+}
+
+impl From<Error> for ErrorKind {
+    fn from(error: Error) -> Self {
+        error.kind
+    }
+}
+
+/// `value` cut to `width` bits and extended back to 128.
+pub(crate) fn extend(value: u64, width: u8, signed: bool) -> Result<i128, ErrorKind> {
+    if !(1..=64).contains(&width) {
+        return Err(ErrorKind::TypeMismatch);
+    }
+    let shift = 64 - width as u32;
+    Ok(match signed {
+        true => ((value << shift) as i64 >> shift) as i128,
+        false => ((value << shift) >> shift) as i128,
+    })
+}
+
+fn integer(
+    op: Op,
+    a: u64,
+    b: u64,
+    width: u8,
+    signed: bool,
+    overflow: Overflow,
+) -> Result<u64, ErrorKind> {
+    let (a, b) = (extend(a, width, signed)?, extend(b, width, signed)?);
+    // Exact in 128 bits, even for the minimum divided by -1, except for
+    // products of large unsigned 64 bit integers
+    let result = match op {
+        Op::Add => a.checked_add(b),
+        Op::Sub => a.checked_sub(b),
+        Op::Mul => a.checked_mul(b),
+        Op::Div | Op::Rem if b == 0 => return Err(ErrorKind::DivisionByZero),
+        Op::Div => a.checked_div(b),
+        _ => a.checked_rem(b),
+    };
+    let bits = match result {
+        Some(result) => result as u64,
+        None => (a as u64).wrapping_mul(b as u64),
+    };
+    let wrapped = extend(bits, width, signed)?;
+    if result != Some(wrapped) && overflow == Overflow::Trap {
+        return Err(ErrorKind::Overflow);
+    }
+    Ok(wrapped as u64)
+}
+
+fn float(op: Op, a: u64, b: u64, width: u8) -> Result<u64, ErrorKind> {
+    macro_rules! apply {
+        ($a:expr, $b:expr) => {
+            match op {
+                Op::Add => $a + $b,
+                Op::Sub => $a - $b,
+                Op::Mul => $a * $b,
+                Op::Div => $a / $b,
+                _ => $a % $b,
+            }
+        };
+    }
+    match width {
+        32 => Ok(apply!(f32::from_bits(a as u32), f32::from_bits(b as u32)).to_bits() as u64),
+        64 => Ok(apply!(f64::from_bits(a), f64::from_bits(b)).to_bits()),
+        _ => Err(ErrorKind::TypeMismatch),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::op::encode;
+
+    /// Writes code with jumps to labels bound later.
+    #[derive(Default)]
+    pub(crate) struct Assembler {
+        code: Vec<u8>,
+        labels: Vec<Option<u32>>,
+        fixups: Vec<(usize, usize)>,
+    }
+
+    impl Assembler {
+        pub(crate) fn op(&mut self, op: Op, operand: u64) -> &mut Self {
+            encode(&mut self.code, op, operand);
+            self
+        }
+
+        pub(crate) fn label(&mut self) -> usize {
+            self.labels.push(None);
+            self.labels.len() - 1
+        }
+
+        pub(crate) fn bind(&mut self, label: usize) -> &mut Self {
+            self.labels[label] = Some(self.code.len() as u32);
+            self
+        }
+
+        pub(crate) fn jump(&mut self, op: Op, label: usize) -> &mut Self {
+            self.fixups.push((self.code.len() + 1, label));
+            self.op(op, 0)
+        }
+
+        pub(crate) fn finish(&mut self) -> Vec<u8> {
+            for &(at, label) in &self.fixups {
+                let target = self.labels[label].unwrap();
+                self.code[at..at + 4].copy_from_slice(&target.to_le_bytes());
+            }
+            core::mem::take(&mut self.code)
+        }
+    }
+
+    pub(crate) const I64: TypeId = TypeId(1);
+    pub(crate) const I8: TypeId = TypeId(2);
+    pub(crate) const U8: TypeId = TypeId(3);
+    pub(crate) const F64: TypeId = TypeId(4);
+    pub(crate) const PAIR: TypeId = TypeId(5);
+
+    /// A machine with the types above, returning their indices in order.
+    pub(crate) fn vm() -> (VirtualMachine, [u64; 5]) {
+        let mut vm = VirtualMachine::new(1 << 16, 1 << 12);
+        let integer = |width, signed| TypeKind::Integer { width, signed };
+        let indices = [
+            TypeInfo::new(I64, integer(64, true), 8, 8),
+            TypeInfo::new(I8, integer(8, true), 1, 1),
+            TypeInfo::new(U8, integer(8, false), 1, 1),
+            TypeInfo::new(F64, TypeKind::Float { width: 64 }, 8, 8),
+            TypeInfo::new(PAIR, TypeKind::Struct { field_count: 2 }, 16, 8),
+        ]
+        .map(|info| vm.register_type(info) as u64);
+        (vm, indices)
+    }
+
+    pub(crate) fn sig(params: &[TypeId], ret: Option<TypeId>) -> FuncSig {
+        FuncSig {
+            params: params.to_vec(),
+            ret,
+        }
+    }
+
+    #[test]
+    fn test_fibonacci() {
+        let (mut vm, [i64, ..]) = vm();
+        let mut asm = Assembler::default();
+        let (top, end) = (asm.label(), asm.label());
+        // n, a, b, t
+        asm.op(Op::Const, 0).op(Op::Store, 1);
+        asm.op(Op::Const, 1).op(Op::Store, 2);
+        asm.bind(top);
+        asm.op(Op::Load, 0).op(Op::Const, 0).op(Op::Gt, i64);
+        asm.jump(Op::BranchIfNot, end);
+        asm.op(Op::Load, 1)
+            .op(Op::Load, 2)
+            .op(Op::Add, i64)
+            .op(Op::Store, 3);
+        asm.op(Op::Load, 2).op(Op::Store, 1);
+        asm.op(Op::Load, 3).op(Op::Store, 2);
+        asm.op(Op::Load, 0)
+            .op(Op::Const, 1)
+            .op(Op::Sub, i64)
+            .op(Op::Store, 0);
+        asm.jump(Op::Jump, top);
+        asm.bind(end).op(Op::Load, 1).op(Op::Ret, 0);
+        let fib = vm.load(&asm.finish(), sig(&[I64], Some(I64)), 4).unwrap();
+
+        for (n, expected) in [(0, 0), (1, 1), (10, 55), (90, 2880067194370816120)] {
+            let n = vm.int(n).unwrap();
+            let result = vm.run(fib, &[n]).unwrap();
+            assert_eq!(vm.read_int(&result), Ok(expected));
+        }
+        assert!(vm.call_stack.is_empty());
+        assert_eq!(vm.stack.active, vm.stack.base);
+    }
+
+    #[test]
+    fn test_calls() {
+        let (mut vm, [.., f64, _]) = vm();
+        let mut asm = Assembler::default();
+        asm.op(Op::Load, 0)
+            .op(Op::Dup, 0)
+            .op(Op::Mul, f64)
+            .op(Op::Ret, 0);
+        let square = vm.load(&asm.finish(), sig(&[F64], Some(F64)), 1).unwrap();
+
+        // Whether the sum of the squares is below the limit
+        asm.op(Op::Load, 0).op(Op::Call, square.0 as u64);
+        asm.op(Op::Load, 1).op(Op::Call, square.0 as u64);
+        asm.op(Op::Add, f64)
+            .op(Op::Load, 2)
+            .op(Op::Lt, f64)
+            .op(Op::Ret, 0);
+        let below = vm
+            .load(&asm.finish(), sig(&[F64, F64, F64], Some(I64)), 3)
+            .unwrap();
+
+        let x = vm.float(1.5).unwrap();
+        let result = vm.run(square, &[x]).unwrap();
+        assert_eq!(vm.read_float(&result), Ok(2.25));
+        for (limit, expected) in [(10.0, 1), (6.25, 0), (f64::NAN, 0)] {
+            let args = [1.5, 2.0, limit].map(|x| vm.float(x).unwrap());
+            let result = vm.run(below, &args).unwrap();
+            assert_eq!(vm.read_int(&result), Ok(expected));
+        }
+        let args = [vm.float(1.0).unwrap(), vm.int(1).unwrap()];
+        assert_eq!(
+            vm.run(below, &args).unwrap_err().kind,
+            ErrorKind::TypeMismatch
+        );
+    }
+
+    #[test]
+    fn test_integer_widths() {
+        let (mut vm, [_, i8, u8, ..]) = vm();
+        let mut programs = Vec::new();
+        for (ty, op) in [(i8, Op::Add), (u8, Op::Add), (u8, Op::Sub), (i8, Op::Div)] {
+            let mut asm = Assembler::default();
+            asm.op(Op::Load, 0)
+                .op(Op::Load, 1)
+                .op(op, ty)
+                .op(Op::Ret, 0);
+            let id = if ty == i8 { I8 } else { U8 };
+            programs.push(vm.load(&asm.finish(), sig(&[id, id], Some(id)), 2).unwrap());
+        }
+        let run = |vm: &mut VirtualMachine, program: usize, a: i64, b: i64| {
+            let args = [vm.int(a as u64).unwrap(), vm.int(b as u64).unwrap()];
+            let result = vm.run(programs[program], &args)?;
+            Ok::<_, Error>(vm.read_int(&result).unwrap() as i64)
+        };
+
+        assert_eq!(run(&mut vm, 0, 127, 1), Ok(-128));
+        assert_eq!(run(&mut vm, 1, 200, 100), Ok(44));
+        assert_eq!(run(&mut vm, 2, 1, 2), Ok(255));
+        // Only the low 8 bits of the operands count
+        assert_eq!(run(&mut vm, 0, 0x1ff, 2), Ok(1));
+        assert_eq!(run(&mut vm, 3, -128, -1), Ok(-128));
+
+        vm.overflow = Overflow::Trap;
+        assert_eq!(run(&mut vm, 0, 100, 27), Ok(127));
+        for (program, a, b) in [(0, 127, 1), (1, 200, 100), (2, 1, 2), (3, -128, -1)] {
+            let error = run(&mut vm, program, a, b).unwrap_err();
+            assert_eq!(
+                (error.kind, error.at),
+                (ErrorKind::Overflow, Some((program as u32, 6)))
+            );
+        }
+        let error = run(&mut vm, 3, 1, 0).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::DivisionByZero);
+    }
+
+    #[test]
+    fn test_struct_swap() {
+        let (mut vm, [.., pair]) = vm();
+        let mut asm = Assembler::default();
+        asm.op(Op::Load, 0).op(Op::Load, 1).op(Op::Construct, pair);
+        asm.op(Op::Destructure, pair)
+            .op(Op::Store, 0)
+            .op(Op::Store, 1);
+        asm.op(Op::Load, 0).op(Op::Load, 1).op(Op::Construct, pair);
+        asm.op(Op::Ret, 0);
+        let swap = vm
+            .load(&asm.finish(), sig(&[I64, I64], Some(PAIR)), 2)
+            .unwrap();
+
+        let args = [vm.int(3).unwrap(), vm.int(4).unwrap()];
+        let Value::Ref(pair) = vm.run(swap, &args).unwrap() else {
+            panic!("a struct is returned by reference");
+        };
+        assert_eq!(pair.pointee, PAIR);
+        let field = |i| vm.memory.read_u64(VirtualPtr(pair.addr.0 + i * SLOT));
+        assert_eq!((field(0), field(1)), (Ok(4), Ok(3)));
+    }
+
+    #[test]
+    fn test_errors_unwind() {
+        let (mut vm, [i64, ..]) = vm();
+        let mut asm = Assembler::default();
+        asm.op(Op::Load, 0)
+            .op(Op::Const, 0)
+            .op(Op::Div, i64)
+            .op(Op::Ret, 0);
+        let divide = vm.load(&asm.finish(), sig(&[I64], Some(I64)), 1).unwrap();
+        asm.op(Op::Const, 5)
+            .op(Op::Call, divide.0 as u64)
+            .op(Op::Ret, 0);
+        let outer = vm.load(&asm.finish(), sig(&[], Some(I64)), 0).unwrap();
+
+        let error = vm.run(outer, &[]).unwrap_err();
+        assert_eq!(
+            (error.kind, error.at),
+            (ErrorKind::DivisionByZero, Some((0, 12)))
+        );
+        assert!(vm.call_stack.is_empty());
+        assert_eq!(vm.stack.active, vm.stack.base);
+
+        // Unbounded recursion runs out of stack
+        let id = vm.function_table.len() as u64;
+        asm.op(Op::Call, id).op(Op::Ret, 0);
+        let recurse = vm.load(&asm.finish(), sig(&[], None), 0).unwrap();
+        assert_eq!(
+            vm.run(recurse, &[]).unwrap_err().kind,
+            ErrorKind::StackOverflow
+        );
+        assert!(vm.call_stack.is_empty());
+
+        // Popping the locals, an unknown opcode, and running off the end
+        asm.op(Op::Pop, 0).op(Op::Ret, 0);
+        let underflow = vm.load(&asm.finish(), sig(&[I64], None), 1).unwrap();
+        let arg = vm.int(1).unwrap();
+        assert_eq!(
+            vm.run(underflow, &[arg]).unwrap_err().kind,
+            ErrorKind::Corrupted
+        );
+        let error = vm
+            .load(&[Op::Ret as u8, 0xff], sig(&[], None), 0)
+            .unwrap_err();
+        assert_eq!((error.kind, error.at), (ErrorKind::Corrupted, Some((4, 1))));
+        asm.op(Op::Const, 1);
+        let no_ret = vm.load(&asm.finish(), sig(&[], None), 0).unwrap();
+        assert_eq!(vm.run(no_ret, &[]).unwrap_err().at, Some((no_ret.0, 9)));
+    }
+}
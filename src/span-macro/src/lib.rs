@@ -8,7 +8,7 @@
 // proc-macro = true
 use proc_macro::TokenStream;
 use proc_macro2::{Span, TokenStream as TokenStream2};
-use quote::{quote, ToTokens};
+use quote::{format_ident, quote, ToTokens};
 use syn::{
     parse::{Parse, ParseStream},
     parse_macro_input,
@@ -51,6 +51,7 @@ pub fn bytecode(input: TokenStream) -> TokenStream {
 fn process_bytecode(input: BytecodeInput) -> Result<TokenStream2> {
     let mut output_items = Vec::new();
     let mut discriminant_counter = 0u64;
+    let mut enums = Vec::new();
 
     // Check that no enums have repr attributes or manual discriminants - the macro handles this
     for item in &input.items {
@@ -81,14 +82,19 @@ fn process_bytecode(input: BytecodeInput) -> Result<TokenStream2> {
     for item in input.items {
         match item {
             Item::Enum(mut enum_item) => {
+                let operands = take_operands(&mut enum_item)?;
+
                 // Add the repr attribute automatically
                 ensure_repr_attribute(&mut enum_item, &repr_type);
                 
                 // Add common derive traits automatically
                 ensure_derive_traits(&mut enum_item);
                 
+                let first = discriminant_counter;
                 assign_discriminants(&mut enum_item, &mut discriminant_counter, &repr_type)?;
                 output_items.push(enum_item.to_token_stream());
+                output_items.push(generate_enum_impls(&enum_item, &operands, first, &repr_type));
+                enums.push((enum_item, operands));
             }
             other => {
                 // Pass through non-enum items unchanged
@@ -97,11 +103,335 @@ fn process_bytecode(input: BytecodeInput) -> Result<TokenStream2> {
         }
     }
 
+    output_items.push(generate_opcode_table(&enums, &repr_type));
+
     Ok(quote! {
         #(#output_items)*
     })
 }
 
+/// An operand field of a variant and the number of bytes encoding it
+struct Operand {
+    ty: Ident,
+    size: usize,
+    signed: bool,
+    width: usize,
+}
+
+/// Parse the operands of every variant of an enum, removing the
+/// `#[operands(...)]` attributes
+fn take_operands(enum_item: &mut ItemEnum) -> Result<Vec<Vec<Operand>>> {
+    let mut operands = Vec::new();
+    for variant in &mut enum_item.variants {
+        let mut width = None;
+        for attr in variant.attrs.iter().filter(|attr| attr.path().is_ident("operands")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("width") {
+                    let lit: LitInt = meta.value()?.parse()?;
+                    width = Some((lit.base10_parse::<usize>()?, lit.span()));
+                    Ok(())
+                } else {
+                    Err(meta.error("Expected `width = ...` in #[operands(...)]"))
+                }
+            })?;
+        }
+        variant.attrs.retain(|attr| !attr.path().is_ident("operands"));
+
+        let mut fields = match &variant.fields {
+            Fields::Unit => Vec::new(),
+            Fields::Unnamed(fields) => fields
+                .unnamed
+                .iter()
+                .map(|field| operand_type(&field.ty))
+                .collect::<Result<Vec<_>>>()?,
+            Fields::Named(fields) => {
+                return Err(Error::new_spanned(
+                    fields,
+                    "Operands of enum variants inside bytecode! macro must be tuple fields"
+                ));
+            }
+        };
+
+        // The width applies to every operand of the variant, which must fit it
+        if let Some((width, span)) = width {
+            if fields.is_empty() {
+                return Err(Error::new(span, "#[operands(width = ...)] on a variant without operands"));
+            }
+            for operand in &mut fields {
+                if width == 0 || width > operand.size {
+                    return Err(Error::new(
+                        span,
+                        format!("Operand width must be from 1 to {}, the size of {}", operand.size, operand.ty),
+                    ));
+                }
+                operand.width = width;
+            }
+        }
+        operands.push(fields);
+    }
+
+    Ok(operands)
+}
+
+/// The operand a field of the given type is, if it is a primitive integer
+fn operand_type(ty: &Type) -> Result<Operand> {
+    const SUPPORTED: [(&str, usize, bool); 8] = [
+        ("u8", 1, false),
+        ("u16", 2, false),
+        ("u32", 4, false),
+        ("u64", 8, false),
+        ("i8", 1, true),
+        ("i16", 2, true),
+        ("i32", 4, true),
+        ("i64", 8, true),
+    ];
+
+    if let Type::Path(type_path) = ty
+        && type_path.qself.is_none()
+        && let Some(ident) = type_path.path.get_ident()
+        && let Some(&(_, size, signed)) = SUPPORTED.iter().find(|(name, ..)| ident == name)
+    {
+        return Ok(Operand { ty: ident.clone(), size, signed, width: size });
+    }
+
+    Err(Error::new_spanned(
+        ty,
+        "Unsupported operand type inside bytecode! macro - operands must be u8, u16, u32, u64, i8, i16, i32 or i64"
+    ))
+}
+
+/// The pattern matching a variant with its operands bound to `operand0`, `operand1`, ...
+fn variant_pattern(variant: &Ident, operands: &[Operand]) -> TokenStream2 {
+    if operands.is_empty() {
+        return quote! { Self::#variant };
+    }
+    let bindings = (0..operands.len()).map(|index| format_ident!("operand{}", index));
+    quote! { Self::#variant(#(#bindings),*) }
+}
+
+/// Generate the opcode, encoding and decoding, names and variant count of an
+/// enum whose discriminants start at `first`, and the conversion from the
+/// repr type if no variant has operands
+fn generate_enum_impls(
+    enum_item: &ItemEnum,
+    operands: &[Vec<Operand>],
+    first: u64,
+    repr_type: &Type,
+) -> TokenStream2 {
+    let ident = &enum_item.ident;
+    let variants: Vec<&Ident> = enum_item.variants.iter().map(|variant| &variant.ident).collect();
+    let names: Vec<String> = variants.iter().map(|variant| variant.to_string()).collect();
+    let discriminants: Vec<LitInt> = (first..first + variants.len() as u64)
+        .map(|value| LitInt::new(&value.to_string(), Span::call_site()))
+        .collect();
+    let patterns: Vec<TokenStream2> = variants
+        .iter()
+        .zip(operands)
+        .map(|(variant, operands)| variant_pattern(variant, operands))
+        .collect();
+    let count = variants.len();
+
+    // Operands follow the opcode little-endian, each cut to its width
+    let encoders = operands.iter().map(|operands| {
+        let writes = operands.iter().enumerate().map(|(index, operand)| {
+            let binding = format_ident!("operand{}", index);
+            let width = operand.width;
+            quote! { out.extend_from_slice(&#binding.to_le_bytes()[..#width]); }
+        });
+        quote! { #(#writes)* }
+    });
+
+    // Operands narrower than their type are sign extended if it is signed
+    let decoders = operands.iter().map(|operands| {
+        let total: usize = operands.iter().map(|operand| operand.width).sum();
+        let check = (total > 0).then(|| quote! {
+            if bytes.len() < #total {
+                return ::core::result::Result::Err(DecodeError::Truncated);
+            }
+        });
+        let reads = operands.iter().enumerate().map(|(index, operand)| {
+            let binding = format_ident!("operand{}", index);
+            let Operand { ty, size, width, .. } = operand;
+            let extend = (operand.signed && width < size).then(|| quote! {
+                if buf[#width - 1] & 0x80 != 0 {
+                    buf[#width..].fill(0xff);
+                }
+            });
+            quote! {
+                let #binding = {
+                    let mut buf = [0u8; #size];
+                    buf[..#width].copy_from_slice(&bytes[..#width]);
+                    #extend
+                    bytes = &bytes[#width..];
+                    #ty::from_le_bytes(buf)
+                };
+            }
+        });
+        quote! { #check #(#reads)* }
+    });
+
+    let try_from = operands.iter().all(Vec::is_empty).then(|| quote! {
+        impl ::core::convert::TryFrom<#repr_type> for #ident {
+            type Error = UnknownOpcode;
+
+            fn try_from(value: #repr_type) -> ::core::result::Result<Self, UnknownOpcode> {
+                match value {
+                    #(#discriminants => ::core::result::Result::Ok(Self::#variants),)*
+                    _ => ::core::result::Result::Err(UnknownOpcode(value)),
+                }
+            }
+        }
+    });
+
+    quote! {
+        #try_from
+
+        impl #ident {
+            /// The number of variants
+            pub const COUNT: usize = #count;
+
+            /// The name of the variant as written
+            pub const fn name(self) -> &'static str {
+                match self {
+                    #(Self::#variants { .. } => #names,)*
+                }
+            }
+
+            /// The discriminant, which the variant is encoded with
+            pub const fn opcode(&self) -> #repr_type {
+                // SAFETY: the enum has a primitive representation, so it
+                // starts with its discriminant
+                unsafe { *(self as *const Self).cast::<#repr_type>() }
+            }
+
+            /// Append the opcode and operands to `out`. Operand bytes past
+            /// the width of the operand are dropped
+            pub fn encode(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.opcode().to_le_bytes());
+                match *self {
+                    #(#patterns => { #encoders })*
+                }
+            }
+
+            /// Decode the instruction at the start of `input` and advance past
+            /// it. `input` is left as it was if decoding fails
+            pub fn decode(input: &mut &[u8]) -> ::core::result::Result<Self, DecodeError> {
+                const OPCODE: usize = ::core::mem::size_of::<#repr_type>();
+                let mut bytes = *input;
+                if bytes.len() < OPCODE {
+                    return ::core::result::Result::Err(DecodeError::Truncated);
+                }
+                let mut buf = [0u8; OPCODE];
+                buf.copy_from_slice(&bytes[..OPCODE]);
+                bytes = &bytes[OPCODE..];
+
+                let value = match #repr_type::from_le_bytes(buf) {
+                    #(#discriminants => {
+                        #decoders
+                        #patterns
+                    })*
+                    opcode => return ::core::result::Result::Err(DecodeError::UnknownOpcode(opcode)),
+                };
+                *input = bytes;
+                ::core::result::Result::Ok(value)
+            }
+        }
+    }
+}
+
+/// Generate the errors of failed conversions and the table of every variant
+/// of the invocation, checked at compile time to be numbered from 0 without gaps
+fn generate_opcode_table(enums: &[(ItemEnum, Vec<Vec<Operand>>)], repr_type: &Type) -> TokenStream2 {
+    let entries = enums.iter().flat_map(|(enum_item, operands)| {
+        let ident = &enum_item.ident;
+        let enum_name = ident.to_string();
+        enum_item.variants.iter().zip(operands).map(move |(variant, operands)| {
+            let variant_ident = &variant.ident;
+            let name = variant_ident.to_string();
+            // Any operands do for reading the opcode
+            let zeros = (!operands.is_empty()).then(|| {
+                let zeros = operands.iter().map(|_| quote! { 0 });
+                quote! { (#(#zeros),*) }
+            });
+            quote! {
+                OpInfo {
+                    enum_name: #enum_name,
+                    name: #name,
+                    discriminant: #ident::#variant_ident #zeros.opcode(),
+                }
+            }
+        })
+    });
+    let total: usize = enums.iter().map(|(enum_item, _)| enum_item.variants.len()).sum();
+
+    quote! {
+        /// A value of the repr type that no variant has
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct UnknownOpcode(pub #repr_type);
+
+        impl ::core::fmt::Display for UnknownOpcode {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                ::core::write!(f, "unknown opcode {}", self.0)
+            }
+        }
+
+        impl ::core::error::Error for UnknownOpcode {}
+
+        /// Why bytes are not an instruction
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum DecodeError {
+            /// The bytes end inside the instruction
+            Truncated,
+            /// No variant of the enum decoded has the opcode
+            UnknownOpcode(#repr_type),
+        }
+
+        impl ::core::fmt::Display for DecodeError {
+            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
+                match self {
+                    DecodeError::Truncated => f.write_str("instruction truncated"),
+                    DecodeError::UnknownOpcode(opcode) => ::core::write!(f, "unknown opcode {}", opcode),
+                }
+            }
+        }
+
+        impl ::core::error::Error for DecodeError {}
+
+        impl ::core::convert::From<UnknownOpcode> for DecodeError {
+            fn from(error: UnknownOpcode) -> Self {
+                DecodeError::UnknownOpcode(error.0)
+            }
+        }
+
+        /// A variant of an enum declared in `bytecode!`
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct OpInfo {
+            pub enum_name: &'static str,
+            pub name: &'static str,
+            pub discriminant: #repr_type,
+        }
+
+        /// Every variant, indexed by discriminant
+        #[allow(dead_code)]
+        pub(crate) const OPCODE_TABLE: [OpInfo; #total] = [#(#entries),*];
+
+        const _: () = {
+            ::core::assert!(
+                #total as u128 <= #repr_type::MAX as u128,
+                "bytecode! discriminants do not fit the repr type"
+            );
+            let mut index = 0;
+            while index < OPCODE_TABLE.len() {
+                ::core::assert!(
+                    OPCODE_TABLE[index].discriminant as usize == index,
+                    "bytecode! discriminants must be numbered from 0 without gaps"
+                );
+                index += 1;
+            }
+        };
+    }
+}
+
 /// Check if an enum has a repr attribute
 fn has_repr_attribute(attrs: &[Attribute]) -> bool {
     attrs.iter().any(|attr| attr.path().is_ident("repr"))
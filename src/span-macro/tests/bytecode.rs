@@ -0,0 +1,57 @@
+use span_macro::bytecode;
+
+bytecode! {
+    pub enum Arith {
+        Add,
+        Sub,
+        Mul,
+    }
+
+    pub enum Control {
+        Jump,
+        Ret,
+    }
+
+    pub const LAST: u8 = 4;
+}
+
+#[test]
+fn test_try_from_round_trips() {
+    for op in [Arith::Add, Arith::Sub, Arith::Mul] {
+        assert_eq!(Arith::try_from(op as u8), Ok(op));
+    }
+    for op in [Control::Jump, Control::Ret] {
+        assert_eq!(Control::try_from(op as u8), Ok(op));
+    }
+}
+
+#[test]
+fn test_try_from_rejects_others() {
+    // Numbering continues across the enums of an invocation
+    assert_eq!(Control::Jump as u8, 3);
+    assert_eq!(Arith::try_from(3), Err(UnknownOpcode(3)));
+    assert_eq!(Control::try_from(2), Err(UnknownOpcode(2)));
+    assert_eq!(Control::try_from(LAST + 1), Err(UnknownOpcode(5)));
+    assert_eq!(UnknownOpcode(5).to_string(), "unknown opcode 5");
+}
+
+#[test]
+fn test_names_and_table() {
+    assert_eq!(Arith::COUNT, 3);
+    assert_eq!(Control::COUNT, 2);
+    assert_eq!(Arith::Mul.name(), "Mul");
+    assert_eq!(Control::Ret.name(), "Ret");
+
+    assert_eq!(OPCODE_TABLE.len(), Arith::COUNT + Control::COUNT);
+    for (index, info) in OPCODE_TABLE.iter().enumerate() {
+        assert_eq!(info.discriminant as usize, index);
+    }
+    assert_eq!(
+        OPCODE_TABLE[3],
+        OpInfo {
+            enum_name: "Control",
+            name: "Jump",
+            discriminant: 3,
+        }
+    );
+}
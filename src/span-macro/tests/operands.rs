@@ -0,0 +1,117 @@
+use span_macro::bytecode;
+
+bytecode! {
+    pub enum Instr {
+        Nop,
+        LoadLocal(u16),
+        Jump(i32),
+        Push(i64),
+        #[operands(width = 2)]
+        Offset(i32),
+        #[operands(width = 3)]
+        Field(u32, u64),
+        Pair(u8, i8),
+    }
+
+    pub enum Meta {
+        Line(u32),
+        End,
+    }
+}
+
+/// An xorshift generator, for inputs that are random but repeatable.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// An instruction with operands that fit their widths
+    fn instr(&mut self) -> Instr {
+        let bits = self.next();
+        match self.next() % Instr::COUNT as u64 {
+            0 => Instr::Nop,
+            1 => Instr::LoadLocal(bits as u16),
+            2 => Instr::Jump(bits as i32),
+            3 => Instr::Push(bits as i64),
+            4 => Instr::Offset(bits as i16 as i32),
+            5 => Instr::Field(bits as u32 & 0xff_ffff, bits >> 40),
+            _ => Instr::Pair(bits as u8, (bits >> 8) as i8),
+        }
+    }
+}
+
+#[test]
+fn test_encoding() {
+    let mut out = Vec::new();
+    Instr::LoadLocal(0x0102).encode(&mut out);
+    Instr::Offset(-2).encode(&mut out);
+    Instr::Field(0x0a0b0c, 7).encode(&mut out);
+    Meta::End.encode(&mut out);
+    assert_eq!(out, [1, 0x02, 0x01, 4, 0xfe, 0xff, 5, 0x0c, 0x0b, 0x0a, 7, 0, 0, 8]);
+
+    let mut input = &out[..];
+    assert_eq!(Instr::decode(&mut input), Ok(Instr::LoadLocal(0x0102)));
+    // Narrow operands are sign extended
+    assert_eq!(Instr::decode(&mut input), Ok(Instr::Offset(-2)));
+    assert_eq!(Instr::decode(&mut input), Ok(Instr::Field(0x0a0b0c, 7)));
+    assert_eq!(Meta::decode(&mut input), Ok(Meta::End));
+    assert!(input.is_empty());
+
+    // Bytes past the width are dropped
+    out.clear();
+    Instr::Offset(0x12345).encode(&mut out);
+    assert_eq!(Instr::decode(&mut &out[..]), Ok(Instr::Offset(0x2345)));
+}
+
+#[test]
+fn test_round_trip() {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    for _ in 0..100 {
+        let len = rng.next() % 32;
+        let instrs: Vec<Instr> = (0..len).map(|_| rng.instr()).collect();
+        let mut out = Vec::new();
+        for instr in &instrs {
+            instr.encode(&mut out);
+        }
+
+        let mut input = &out[..];
+        let mut decoded = Vec::new();
+        while !input.is_empty() {
+            decoded.push(Instr::decode(&mut input).unwrap());
+        }
+        assert_eq!(decoded, instrs);
+    }
+}
+
+#[test]
+fn test_decode_rejects() {
+    let mut out = Vec::new();
+    Instr::Push(-1).encode(&mut out);
+    for len in 0..out.len() {
+        let mut input = &out[..len];
+        assert_eq!(Instr::decode(&mut input), Err(DecodeError::Truncated));
+        assert_eq!(input.len(), len);
+    }
+
+    // Opcodes of the other enum are unknown
+    let mut input = &[Meta::End.opcode(), 0][..];
+    assert_eq!(Instr::decode(&mut input), Err(DecodeError::UnknownOpcode(8)));
+    assert_eq!(input.len(), 2);
+    assert_eq!(Meta::decode(&mut &[1, 0, 0][..]), Err(DecodeError::UnknownOpcode(1)));
+    assert_eq!(DecodeError::Truncated.to_string(), "instruction truncated");
+}
+
+#[test]
+fn test_names_and_table() {
+    assert_eq!(Instr::COUNT, 7);
+    assert_eq!(Instr::Field(1, 2).name(), "Field");
+    assert_eq!(Meta::Line(3).opcode(), 7);
+    assert_eq!(OPCODE_TABLE.len(), 9);
+    assert_eq!(OPCODE_TABLE[7].enum_name, "Meta");
+    assert_eq!(OPCODE_TABLE[7].name, "Line");
+}
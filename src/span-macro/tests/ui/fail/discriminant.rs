@@ -0,0 +1,10 @@
+use span_macro::bytecode;
+
+bytecode! {
+    pub enum Op {
+        Nop,
+        Halt = 7,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,9 @@
+use span_macro::bytecode;
+
+bytecode! {
+    pub enum Op {
+        Load { local: u16 },
+    }
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use span_macro::bytecode;
+
+bytecode! {
+    pub enum Op {
+        #[operands(width = 3)]
+        Load(u16),
+    }
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use span_macro::bytecode;
+
+bytecode! {
+    #[repr(u16)]
+    pub enum Op {
+        Nop,
+    }
+}
+
+fn main() {}
@@ -0,0 +1,19 @@
+use span_macro::bytecode;
+
+bytecode! {
+    pub enum Op {
+        Nop,
+        Halt,
+    }
+}
+
+// The table and conversions are usable in const contexts
+const HALT: OpInfo = OPCODE_TABLE[Op::Halt as usize];
+const NAME: &str = Op::Nop.name();
+
+fn main() {
+    assert_eq!(HALT.name, "Halt");
+    assert_eq!(NAME, "Nop");
+    assert_eq!(Op::try_from(1u8), Ok(Op::Halt));
+    assert!(Op::try_from(Op::COUNT as u8).is_err());
+}